@@ -3,6 +3,7 @@
  * サーバーAPIとの通信で使用する型定義
  */
 
+import type { SignedIntegrityReport } from './integrity.js';
 import type { RecordingState } from './recording.js';
 
 /**
@@ -13,6 +14,11 @@ export interface CreateRecordingResponse {
   recording_id: string;
   created_at: string;
   state: RecordingState;
+  /**
+   * 終了時の整合性レポートに署名する鍵（hex, 32バイト）
+   * サーバーにINTEGRITY_SECRETが設定されている場合のみ
+   */
+  integrity_key?: string;
 }
 
 /**
@@ -21,6 +27,8 @@ export interface CreateRecordingResponse {
  */
 export interface UpdateStateRequest {
   state: RecordingState;
+  /** syncedへの遷移時に送る整合性レポート（integrity_keyが発行された場合は必須） */
+  integrity_report?: SignedIntegrityReport;
 }
 
 /**
@@ -30,3 +30,10 @@ export interface ChunkMetadata {
   /** Creation timestamp (Unix timestamp ms) */
   createdAt: number;
 }
+
+/**
+ * Extended type (hex) of the `uuid` box the recorder writes after each
+ * fragment when fragment checksums are on; it holds version/flags (4 bytes)
+ * and the BLAKE3 hash (32 bytes) of the moof + mdat before it
+ */
+export const FRAGMENT_CHECKSUM_UUID = 'c7f1a2e45b3d4e8a9f268d4c1b7e3a90';
@@ -99,3 +99,12 @@ export class RoomAccessDeniedError extends DomainError {
     super(message, 'ROOM_ACCESS_DENIED');
   }
 }
+
+/**
+ * 整合性レポートの検証エラー
+ */
+export class IntegrityVerificationError extends DomainError {
+  constructor(message: string) {
+    super(message, 'INTEGRITY_VERIFICATION_FAILED');
+  }
+}
@@ -0,0 +1,180 @@
+/**
+ * Ingest protocol version this build speaks
+ *
+ * Bump it when the upload API changes in a way older peers cannot ignore,
+ * and raise `MIN_INGEST_PROTOCOL_VERSION` once the old behavior is dropped.
+ */
+export const INGEST_PROTOCOL_VERSION = 1;
+
+/** Oldest ingest protocol version this build still speaks */
+export const MIN_INGEST_PROTOCOL_VERSION = 1;
+
+/**
+ * Container of uploaded media
+ *
+ * - `fmp4`: init segment + moof/mdat chunks (the recorder's output)
+ * - `mp4`: a whole progressive file
+ */
+export type IngestContainer = 'fmp4' | 'mp4';
+
+/**
+ * What one side of an upload supports, sent by the client before uploading
+ * and answered by the server
+ *
+ * Lists are in order of preference. Unknown codecs, containers and features
+ * are kept as plain strings so a peer never fails on a value added later.
+ */
+export interface IngestCapabilities {
+  /** Newest protocol version spoken */
+  protocolVersion: number;
+  /** Oldest protocol version spoken; defaults to `protocolVersion` */
+  minProtocolVersion?: number;
+  /** RFC 6381 codec strings, e.g. `avc1.42C01E`, `mp4a.40.2` */
+  codecs: string[];
+  containers: (IngestContainer | string)[];
+  /** Optional protocol features, e.g. `chunk-hash`, `integrity-report` */
+  features?: string[];
+  /** Free-form client or server name and version, for logs */
+  agent?: string;
+}
+
+/**
+ * Feature a client offers when its chunks are encrypted (AES-128-CBC per
+ * chunk): they are not plain fMP4, so the server cannot read their boxes
+ */
+export const ENCRYPTED_CHUNKS_FEATURE = 'encrypted-chunks';
+
+/**
+ * Feature a client offers when it uploads content-defined (CDC) chunks,
+ * which are cut anywhere inside boxes
+ */
+export const CDC_CHUNKS_FEATURE = 'cdc-chunks';
+
+/**
+ * Capabilities assumed for a peer that never sent a handshake: the upload
+ * API as it was before versioning
+ */
+export const LEGACY_INGEST_CAPABILITIES: IngestCapabilities = {
+  protocolVersion: 1,
+  minProtocolVersion: 1,
+  codecs: ['avc1', 'mp4a'],
+  containers: ['fmp4'],
+  features: [],
+};
+
+/**
+ * Outcome of a handshake, the same whichever side computes it
+ */
+export type IngestNegotiation =
+  | {
+      accepted: true;
+      /** Version both sides use from now on */
+      protocolVersion: number;
+      /** Codecs both support, in the client's order */
+      codecs: string[];
+      containers: string[];
+      features: string[];
+    }
+  | {
+      accepted: false;
+      reason: 'protocol-version' | 'codecs' | 'containers';
+      /** Human-readable explanation for logs and error messages */
+      message: string;
+    };
+
+/**
+ * POST /api/ingest/handshake - Request
+ *
+ * With `recordingId`, the server keeps the outcome on the recording
+ * (`RecordingMetadata.ingest`) and holds the recording's uploads to it.
+ */
+export interface IngestHandshakeRequest extends IngestCapabilities {
+  recordingId?: string;
+}
+
+/**
+ * POST /api/ingest/handshake - Response
+ */
+export interface IngestHandshakeResponse {
+  negotiation: IngestNegotiation;
+  /** The server's own capabilities, so a rejected client can tell the user why */
+  server: IngestCapabilities;
+}
+
+/**
+ * Whether codec strings name the same codec
+ *
+ * Either side may give only the sample entry (`avc1`) or add a profile
+ * (`avc1.42C01E`); a bare entry matches every profile of it.
+ */
+function codecMatches(a: string, b: string): boolean {
+  const [entryA, ...profileA] = a.toLowerCase().split('.');
+  const [entryB, ...profileB] = b.toLowerCase().split('.');
+  if (entryA !== entryB) {
+    return false;
+  }
+  return profileA.length === 0 || profileB.length === 0 || profileA.join('.') === profileB.join('.');
+}
+
+/**
+ * Negotiate an upload between `client` and `server`
+ *
+ * Picks the newest protocol version both speak and keeps the client's
+ * codecs, containers and features the server also supports. Rejects the
+ * upload only when the version ranges do not overlap or nothing the client
+ * sends can be stored; an unknown feature is simply left out.
+ */
+export function negotiateIngest(
+  client: IngestCapabilities,
+  server: IngestCapabilities
+): IngestNegotiation {
+  const clientMin = client.minProtocolVersion ?? client.protocolVersion;
+  const serverMin = server.minProtocolVersion ?? server.protocolVersion;
+  const protocolVersion = Math.min(client.protocolVersion, server.protocolVersion);
+  if (protocolVersion < Math.max(clientMin, serverMin)) {
+    return {
+      accepted: false,
+      reason: 'protocol-version',
+      message:
+        `Client speaks ingest protocol ${clientMin}-${client.protocolVersion}, ` +
+        `server ${serverMin}-${server.protocolVersion}`,
+    };
+  }
+
+  const codecs = client.codecs.filter((codec) =>
+    server.codecs.some((supported) => codecMatches(codec, supported))
+  );
+  if (codecs.length === 0) {
+    return {
+      accepted: false,
+      reason: 'codecs',
+      message: `Server supports none of the codecs ${client.codecs.join(', ')}`,
+    };
+  }
+
+  const containers = client.containers.filter((container) => server.containers.includes(container));
+  if (containers.length === 0) {
+    return {
+      accepted: false,
+      reason: 'containers',
+      message: `Server supports none of the containers ${client.containers.join(', ')}`,
+    };
+  }
+
+  const serverFeatures = server.features ?? [];
+  const features = (client.features ?? []).filter((feature) => serverFeatures.includes(feature));
+  return { accepted: true, protocolVersion, codecs, containers, features };
+}
+
+/**
+ * Features the uploads of a recording may use after `negotiation`
+ *
+ * None after a rejected handshake; `null` for a recording that never had
+ * one, whose uploads keep the API as it was before versioning.
+ */
+export function negotiatedIngestFeatures(negotiation: IngestNegotiation | undefined): string[] | null {
+  if (!negotiation) {
+    return null;
+  }
+  return negotiation.accepted ? negotiation.features : [];
+}
@@ -18,9 +18,11 @@ export {
   RoomNotFoundError,
   InvalidRoomStateTransitionError,
   RoomAccessDeniedError,
+  IntegrityVerificationError,
 } from './errors/DomainErrors.js';
 
 // Chunk types
+export { FRAGMENT_CHECKSUM_UUID } from './chunk.js';
 export type { ChunkId, ChunkMetadata } from './chunk.js';
 
 // Recording types
@@ -28,9 +30,40 @@ export type {
   RecordingId,
   RecordingState,
   RecordingMetadata,
+  RetentionPolicy,
   Recording,
 } from './recording.js';
 
+// Search index
+export { SEARCH_INDEX_VERSION, matchesSearchIndex, searchTerms } from './search-index.js';
+export type { SessionIndex, SessionIndexEntry, SessionIndexRange } from './search-index.js';
+
+// Recording presets
+export { RECORDING_PRESETS, findRecordingPreset } from './presets.js';
+export type { RecordingPreset, UploadPolicy } from './presets.js';
+
+// Integrity report
+export { INTEGRITY_REPORT_VERSION } from './integrity.js';
+export type { IntegrityManifestChunk, IntegrityReport, SignedIntegrityReport } from './integrity.js';
+
+// Ingest handshake
+export {
+  INGEST_PROTOCOL_VERSION,
+  MIN_INGEST_PROTOCOL_VERSION,
+  LEGACY_INGEST_CAPABILITIES,
+  ENCRYPTED_CHUNKS_FEATURE,
+  CDC_CHUNKS_FEATURE,
+  negotiateIngest,
+  negotiatedIngestFeatures,
+} from './handshake.js';
+export type {
+  IngestContainer,
+  IngestCapabilities,
+  IngestNegotiation,
+  IngestHandshakeRequest,
+  IngestHandshakeResponse,
+} from './handshake.js';
+
 // API types
 export type {
   CreateRecordingResponse,
@@ -0,0 +1,73 @@
+/**
+ * Version of the integrity report format
+ */
+export const INTEGRITY_REPORT_VERSION = 1;
+
+/**
+ * Chunk entry of the recorder's session manifest
+ */
+export interface IntegrityManifestChunk {
+  chunkId: {
+    sessionId: string;
+    track: 'muxed' | 'video' | 'audio';
+    /** Simulcast rendition name */
+    rendition?: string;
+    sequence: number;
+  };
+  /** Timestamp in microseconds from session start */
+  timestamp: number;
+  size: number;
+  /** BLAKE3 hash of the chunk data (hex) */
+  hash?: string;
+  hasKeyframe?: boolean;
+  createdAt: number;
+}
+
+/**
+ * End-of-session summary produced by the WASM recorder
+ * (`Recorder.integrity_report`)
+ */
+export interface IntegrityReport {
+  version: number;
+  /** Session ID of the recorder, which is the recording ID */
+  sessionId: string;
+  /**
+   * Head of the hash chain over the manifest's chunks (hex): starts with
+   * BLAKE3(sessionId), each chunk in order extends it with
+   * BLAKE3(previous || chunk hash), both as raw 32-byte digests
+   */
+  chainHead: string;
+  chunkCount: number;
+  /** Combined size of the chunks in bytes */
+  totalBytes: number;
+  /** Frames lost or altered and gaps in the media */
+  quality: {
+    droppedFrames: number;
+    rejectedFrames: number;
+    correctedFrames: number;
+    emptyFrames: number;
+    oversizedFrames: number;
+    largestFrame: number;
+    maxFrameSize: number;
+    gaps: { startUs: number; endUs: number }[];
+    totalGapUs: number;
+  };
+  /** MuxerStats counters at the end of the recording */
+  stats: Record<string, number>;
+  /** Session manifest (only the fields checked by the server are typed) */
+  manifest: {
+    sessionId: string;
+    chunks: IntegrityManifestChunk[];
+    [field: string]: unknown;
+  };
+}
+
+/**
+ * Integrity report as sent to the server with the `synced` state
+ */
+export interface SignedIntegrityReport {
+  /** JSON of the IntegrityReport, signed as is */
+  report: string;
+  /** Keyed BLAKE3 hash of `report` under the recording's integrity key (hex) */
+  signature: string;
+}
@@ -0,0 +1,66 @@
+import presets from './presets.json' with { type: 'json' };
+
+/**
+ * Upload concurrency and retry settings of a preset
+ */
+export interface UploadPolicy {
+  maxConcurrentUploads: number;
+  /** Retries per chunk before it is marked failed */
+  maxRetries: number;
+  /** Wait before a retry, multiplied by the retry count */
+  retryDelayMs: number;
+}
+
+/**
+ * Named recording configuration
+ *
+ * The same data is embedded in the WASM core (`recording_preset(id)`), so a
+ * preset is selected by ID on either side.
+ */
+export interface RecordingPreset {
+  id: string;
+  /** Name shown in the UI */
+  name: string;
+  description: string;
+  /** MuxideConfig fields; sps/pps come from the encoder at runtime */
+  muxer: {
+    videoWidth?: number;
+    videoHeight?: number;
+    videoTimescale?: number;
+    fragmentDurationMs: number;
+    audioSampleRate?: number;
+    audioChannels?: number;
+    audioPrimingSamples?: number;
+    maxFrameSize?: number;
+    startAlignment?: 'trim' | 'delay';
+    /**
+     * 'absolute' writes the moof's file position as the tfhd base data
+     * offset, for older decoders that mishandle default-base-is-moof
+     */
+    dataOffsetMode?: 'moof' | 'absolute';
+    /** Track names shown by editors, e.g. "Screen" and "Microphone" */
+    videoTrackName?: string;
+    audioTrackName?: string;
+    /** Nudge audio durations back onto their timestamps when they drift */
+    audioSkewCorrection?: boolean;
+  };
+  /** ChunkSizingConfig for the adaptive chunk size policy */
+  chunking: {
+    minFragmentMs: number;
+    maxFragmentMs: number;
+    targetUploadMs: number;
+    maxBacklogMs: number;
+    hysteresis: number;
+  };
+  upload: UploadPolicy;
+}
+
+/** Built-in recording presets */
+export const RECORDING_PRESETS: readonly RecordingPreset[] = presets as RecordingPreset[];
+
+/**
+ * Look up a built-in preset by ID
+ */
+export function findRecordingPreset(id: string): RecordingPreset | undefined {
+  return RECORDING_PRESETS.find((preset) => preset.id === id);
+}
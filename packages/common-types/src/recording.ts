@@ -1,3 +1,5 @@
+import type { IngestNegotiation } from './handshake.js';
+
 /**
  * Unique identifier for a recording (UUID)
  */
@@ -54,6 +56,61 @@ export interface RecordingMetadata {
     /** 同期に使用したサンプル数 */
     syncSampleCount: number;
   };
+
+  /** Input loudness per ITU-R BS.1770 (measured by the WASM LoudnessMeter) */
+  loudness?: {
+    /** Gated integrated loudness (LUFS) */
+    integratedLufs?: number;
+    /** Highest momentary (400 ms) loudness (LUFS) */
+    maxMomentaryLufs?: number;
+    /** Highest short-term (3 s) loudness (LUFS) */
+    maxShortTermLufs?: number;
+    /** Highest sample peak (dBFS) */
+    peakDbfs: number;
+  };
+
+  /** Frame rate measured from the recorded video timestamps */
+  framerate?: {
+    averageFps: number;
+    medianFps: number;
+    /** Rate of the slowest 5% of frame intervals */
+    p5Fps: number;
+    /** Rate of the fastest 5% of frame intervals */
+    p95Fps: number;
+    /** Whether the frame rate varied noticeably (VFR) */
+    variable: boolean;
+    frameCount: number;
+  };
+
+  /** Gains combining the audio sources (e.g. screen and mic) into one mix */
+  downmix?: {
+    sources: {
+      /** Source name, e.g. "screen" or "mic" */
+      label: string;
+      /** Gain in dB (0 keeps the level) */
+      gainDb: number;
+      /** Track holding the source on its own, if recorded separately */
+      trackId?: number;
+    }[];
+    /** Whether the recorded audio already is the mix */
+    applied: boolean;
+  };
+
+  /** How long the recording is kept once finished (evaluated by wasm-core's `recording_retention_status`) */
+  retention?: RetentionPolicy;
+
+  /** Outcome of the ingest handshake, set by the server; uploads are held to it */
+  ingest?: IngestNegotiation;
+}
+
+/**
+ * Per-recording retention, set by the owner and honored by every purge
+ */
+export interface RetentionPolicy {
+  /** Keep the recording this long (ms) after it finished; the server's default applies if unset */
+  expireAfterMs?: number;
+  /** Never purge while set, whatever the expiry says */
+  legalHold?: boolean;
 }
 
 /**
@@ -0,0 +1,73 @@
+/**
+ * Version of the search index format
+ */
+export const SEARCH_INDEX_VERSION = 1;
+
+/**
+ * Searchable summary of a finished recording, built by the WASM recorder
+ * at stop (`Recorder.get_search_index`) or from a manifest
+ * (`manifest_search_index`), and optionally embedded in the MP4's session
+ * metadata
+ *
+ * Same shape as `SessionIndex` in wasm-core. Times are in microseconds on
+ * the timeline of the assembled file.
+ */
+export interface SessionIndex {
+  version: number;
+  sessionId: string;
+  /** Display name of the recording */
+  title?: string;
+  participant?: string;
+  /** Length of the file, if known */
+  durationUs?: number;
+  /** Labeled bookmarks, each running until the next one */
+  chapters?: SessionIndexEntry[];
+  /** Other labeled markers */
+  captions?: SessionIndexEntry[];
+  /** Ranges silence detection reported */
+  silence?: SessionIndexRange[];
+  /** The rest of the file, when its duration is known */
+  talk?: SessionIndexRange[];
+  /** Sorted, distinct lowercase words of the title, participant, chapters and captions */
+  terms?: string[];
+}
+
+/**
+ * Timed text of a `SessionIndex`
+ */
+export interface SessionIndexEntry {
+  startUs: number;
+  endUs: number;
+  text: string;
+}
+
+/**
+ * Time range of a `SessionIndex`
+ */
+export interface SessionIndexRange {
+  startUs: number;
+  endUs: number;
+}
+
+/**
+ * Lowercase words of a text, split at anything but letters and digits
+ */
+export function searchTerms(text: string): string[] {
+  return text
+    .split(/[^\p{Alphabetic}\p{N}]+/u)
+    .filter((word) => word.length > 0)
+    .map((word) => word.toLowerCase());
+}
+
+/**
+ * Whether every word of `query` is part of one of the index's terms; an
+ * empty query matches everything
+ *
+ * Must give the same answer as `SessionIndex::matches` in wasm-core, so
+ * library search over local recordings works without loading the WASM
+ * module.
+ */
+export function matchesSearchIndex(index: SessionIndex, query: string): boolean {
+  const terms = index.terms ?? [];
+  return searchTerms(query).every((word) => terms.some((term) => term.includes(word)));
+}
@@ -0,0 +1,14 @@
+/**
+ * Fragment Checksum Verifier Interface
+ *
+ * チャンク内の各フラグメント（moof+mdat）の後ろに置かれたチェックサムbox の検証を抽象化
+ */
+export interface IFragmentChecksumVerifier {
+  /**
+   * チャンク内のチェックサムboxをすべて検証する
+   * チェックサムboxのないフラグメントは検証せずに通す
+   * 暗号化チャンク・CDCチャンクは呼び出し側が除外する（ハンドシェイクで判断）
+   * @throws InvalidChunkError チェックサムが一致しない、またはトップレベルのboxとして読めない場合
+   */
+  verify(data: Uint8Array): void;
+}
@@ -0,0 +1,24 @@
+import type { RecordingId, IntegrityReport, SignedIntegrityReport } from '@maycast/common-types';
+
+/**
+ * Integrity Report Verifier Interface
+ *
+ * 録画終了時の整合性レポート（WASMレコーダーが署名）の鍵発行と検証を抽象化
+ */
+export interface IIntegrityReportVerifier {
+  /**
+   * 録画に発行する署名鍵（hex, 32バイト）
+   */
+  issueKey(recordingId: RecordingId): string;
+
+  /**
+   * 署名とハッシュチェーンを検証し、レポートを返す
+   * @throws IntegrityVerificationError 検証に失敗した場合
+   */
+  verify(recordingId: RecordingId, signed: SignedIntegrityReport): IntegrityReport;
+
+  /**
+   * マニフェストのチャンクハッシュと同じ方式で保存済みチャンクをハッシュ（hex）
+   */
+  hashChunk(data: Uint8Array): string;
+}
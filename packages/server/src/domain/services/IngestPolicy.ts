@@ -0,0 +1,28 @@
+import type { RecordingEntity } from '@maycast/common-types';
+import { InvalidOperationError, negotiatedIngestFeatures } from '@maycast/common-types';
+
+/**
+ * Ingestハンドシェイクの結果に基づくアップロード可否
+ *
+ * ハンドシェイクのない録画は従来どおりアップロードできる
+ */
+
+/**
+ * ハンドシェイクが拒否された録画のアップロードを拒否する
+ * @throws InvalidOperationError 交渉が成立しなかった場合
+ */
+export function assertIngestAccepted(recording: RecordingEntity): void {
+  const negotiation = recording.getMetadata()?.ingest;
+  if (negotiation && !negotiation.accepted) {
+    throw new InvalidOperationError(
+      `Ingest handshake of recording ${recording.getId()} was rejected: ${negotiation.message}`
+    );
+  }
+}
+
+/**
+ * 録画のアップロードで使える機能（ハンドシェイクのない録画は null）
+ */
+export function ingestFeatures(recording: RecordingEntity): string[] | null {
+  return negotiatedIngestFeatures(recording.getMetadata()?.ingest);
+}
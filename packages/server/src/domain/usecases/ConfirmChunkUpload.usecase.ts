@@ -1,6 +1,7 @@
 import type { RecordingId, ChunkId } from '@maycast/common-types';
 import { RecordingNotFoundError } from '@maycast/common-types';
 import type { IRecordingRepository } from '../repositories/IRecordingRepository.js';
+import { assertIngestAccepted } from '../services/IngestPolicy.js';
 
 export interface ConfirmChunkUploadRequest {
   recordingId: RecordingId;
@@ -27,6 +28,7 @@ export class ConfirmChunkUploadUseCase {
     if (!recording) {
       throw new RecordingNotFoundError(`Recording not found: ${request.recordingId}`);
     }
+    assertIngestAccepted(recording);
 
     // チャンクの場合はカウントをインクリメント
     if (request.type === 'chunk') {
@@ -3,6 +3,7 @@ import type { RecordingId, Recording, RoomId } from '@maycast/common-types';
 import type { IRecordingRepository } from '../repositories/IRecordingRepository.js';
 import type { IRoomRepository } from '../repositories/IRoomRepository.js';
 import type { IRoomEventPublisher } from '../events/IRoomEventPublisher.js';
+import type { IIntegrityReportVerifier } from '../services/IIntegrityReportVerifier.js';
 import { v4 as uuidv4 } from 'uuid';
 
 /**
@@ -18,6 +19,8 @@ export interface CreateRecordingRequest {
 export interface CreateRecordingResponse {
   recordingId: RecordingId;
   recording: Recording;
+  /** 整合性レポートの署名鍵（Verifierが設定されている場合） */
+  integrityKey?: string;
 }
 
 /**
@@ -29,20 +32,24 @@ export interface CreateRecordingResponse {
  * 3. Recordingを永続化
  * 4. roomIdが指定されている場合、RoomにRecordingを追加
  * 5. WebSocket経由でイベント通知
+ * 6. 整合性レポートの署名鍵を発行（Verifierが設定されている場合）
  */
 export class CreateRecordingUseCase {
   private recordingRepository: IRecordingRepository;
   private roomRepository: IRoomRepository;
   private roomEventPublisher: IRoomEventPublisher | null;
+  private integrityReportVerifier: IIntegrityReportVerifier | null;
 
   constructor(
     recordingRepository: IRecordingRepository,
     roomRepository: IRoomRepository,
-    roomEventPublisher: IRoomEventPublisher | null = null
+    roomEventPublisher: IRoomEventPublisher | null = null,
+    integrityReportVerifier: IIntegrityReportVerifier | null = null
   ) {
     this.recordingRepository = recordingRepository;
     this.roomRepository = roomRepository;
     this.roomEventPublisher = roomEventPublisher;
+    this.integrityReportVerifier = integrityReportVerifier;
   }
 
   async execute(request?: CreateRecordingRequest): Promise<CreateRecordingResponse> {
@@ -76,6 +83,7 @@ export class CreateRecordingUseCase {
     return {
       recordingId,
       recording: recording.toDTO(),
+      integrityKey: this.integrityReportVerifier?.issueKey(recordingId),
     };
   }
 }
@@ -3,6 +3,7 @@ import type { UploadUrlResponse } from '@maycast/common-types';
 import { RecordingNotFoundError } from '@maycast/common-types';
 import type { IRecordingRepository } from '../repositories/IRecordingRepository.js';
 import type { IPresignedUrlService } from '../services/IPresignedUrlService.js';
+import { assertIngestAccepted, ingestFeatures } from '../services/IngestPolicy.js';
 
 export interface GetUploadUrlRequest {
   recordingId: RecordingId;
@@ -15,6 +16,7 @@ export interface GetUploadUrlRequest {
  *
  * S3バックエンドの場合: クライアントがS3に直接アップロードするためのPresigned URLを返す
  * ローカルバックエンドの場合: directUpload=false を返し、従来のプロキシ方式を使用
+ * Ingestハンドシェイクで direct-upload を交渉していない録画もプロキシ方式
  */
 export class GetUploadUrlUseCase {
   private recordingRepository: IRecordingRepository;
@@ -34,12 +36,19 @@ export class GetUploadUrlUseCase {
     if (!recording) {
       throw new RecordingNotFoundError(`Recording not found: ${request.recordingId}`);
     }
+    assertIngestAccepted(recording);
 
     // S3直接アップロードをサポートしていない場合
     if (!this.presignedUrlService.isUploadSupported()) {
       return { directUpload: false };
     }
 
+    // 直接アップロードを交渉していない場合（ハンドシェイクのない録画は従来どおり）
+    const features = ingestFeatures(recording);
+    if (features && !features.includes('direct-upload')) {
+      return { directUpload: false };
+    }
+
     const roomId = recording.getRoomId();
     const expiresIn = 900; // 15分
 
@@ -0,0 +1,109 @@
+import { describe, it, expect, beforeEach } from 'vitest';
+import { blake3 } from '@noble/hashes/blake3.js';
+import { bytesToHex } from '@noble/hashes/utils.js';
+import { RecordingEntity, InvalidChunkError, InvalidOperationError } from '@maycast/common-types';
+import type { IngestCapabilities } from '@maycast/common-types';
+import { InMemoryRecordingRepository } from '../../infrastructure/repositories/InMemoryRecordingRepository.js';
+import { InMemoryChunkRepository } from '../../infrastructure/repositories/InMemoryChunkRepository.js';
+import { SERVER_INGEST_CAPABILITIES } from '../../presentation/routes/ingest.js';
+import type { IPresignedUrlService } from '../services/IPresignedUrlService.js';
+import { NegotiateIngestUseCase } from './NegotiateIngest.usecase.js';
+import { UploadChunkUseCase } from './UploadChunk.usecase.js';
+import { UploadInitSegmentUseCase } from './UploadInitSegment.usecase.js';
+import { GetUploadUrlUseCase } from './GetUploadUrl.usecase.js';
+
+const CLIENT: IngestCapabilities = {
+  protocolVersion: 1,
+  codecs: ['avc1.42C01E', 'mp4a.40.2'],
+  containers: ['fmp4'],
+  features: ['chunk-hash'],
+};
+
+const presignedUrlService: IPresignedUrlService = {
+  isSupported: () => true,
+  getInitSegmentUrl: async () => 'https://storage/init',
+  getChunkUrl: async () => 'https://storage/chunk',
+  isUploadSupported: () => true,
+  getInitSegmentUploadUrl: async () => 'https://storage/init',
+  getChunkUploadUrl: async () => 'https://storage/chunk',
+};
+
+describe('NegotiateIngestUseCase', () => {
+  let recordings: InMemoryRecordingRepository;
+  let chunks: InMemoryChunkRepository;
+  let negotiate: NegotiateIngestUseCase;
+  let uploadChunk: UploadChunkUseCase;
+
+  beforeEach(async () => {
+    recordings = new InMemoryRecordingRepository();
+    chunks = new InMemoryChunkRepository();
+    negotiate = new NegotiateIngestUseCase(recordings, SERVER_INGEST_CAPABILITIES);
+    uploadChunk = new UploadChunkUseCase(recordings, chunks);
+    await recordings.save(RecordingEntity.create('rec-1'));
+  });
+
+  it('録画を指定すると交渉結果をメタデータに保存する', async () => {
+    const response = await negotiate.execute({ capabilities: CLIENT, recordingId: 'rec-1' });
+    expect(response.negotiation.accepted).toBe(true);
+
+    const recording = await recordings.findById('rec-1');
+    expect(recording!.getMetadata()?.ingest).toEqual(response.negotiation);
+  });
+
+  it('交渉が成立しなかった録画のアップロードを拒否する', async () => {
+    const response = await negotiate.execute({
+      capabilities: { ...CLIENT, codecs: ['vp09'] },
+      recordingId: 'rec-1',
+    });
+    expect(response.negotiation.accepted).toBe(false);
+
+    await expect(
+      uploadChunk.execute({ recordingId: 'rec-1', chunkId: 0, data: Buffer.from([1, 2, 3]) })
+    ).rejects.toThrow(InvalidOperationError);
+    await expect(
+      new UploadInitSegmentUseCase(recordings, chunks).execute({
+        recordingId: 'rec-1',
+        data: Buffer.from([1]),
+      })
+    ).rejects.toThrow(InvalidOperationError);
+    expect(await chunks.listChunkIds('rec-1')).toEqual([]);
+  });
+
+  it('chunk-hashを交渉した録画はハッシュを照合する', async () => {
+    await negotiate.execute({ capabilities: CLIENT, recordingId: 'rec-1' });
+    const data = Buffer.from([1, 2, 3]);
+
+    await expect(uploadChunk.execute({ recordingId: 'rec-1', chunkId: 0, data })).rejects.toThrow(
+      InvalidChunkError
+    );
+    await expect(
+      uploadChunk.execute({ recordingId: 'rec-1', chunkId: 0, data, hash: '00'.repeat(32) })
+    ).rejects.toThrow(InvalidChunkError);
+
+    await uploadChunk.execute({ recordingId: 'rec-1', chunkId: 0, data, hash: bytesToHex(blake3(data)) });
+    expect(await chunks.listChunkIds('rec-1')).toEqual([0]);
+  });
+
+  it('ハンドシェイクのない録画は従来どおりアップロードできる', async () => {
+    await uploadChunk.execute({ recordingId: 'rec-1', chunkId: 0, data: Buffer.from([1]) });
+    expect(await chunks.listChunkIds('rec-1')).toEqual([0]);
+
+    const getUploadUrl = new GetUploadUrlUseCase(recordings, presignedUrlService);
+    const result = await getUploadUrl.execute({ recordingId: 'rec-1', type: 'chunk', chunkId: 0 });
+    expect(result.directUpload).toBe(true);
+  });
+
+  it('direct-uploadを交渉していない録画はプロキシ方式になる', async () => {
+    await negotiate.execute({ capabilities: CLIENT, recordingId: 'rec-1' });
+    const getUploadUrl = new GetUploadUrlUseCase(recordings, presignedUrlService);
+    const result = await getUploadUrl.execute({ recordingId: 'rec-1', type: 'chunk', chunkId: 0 });
+    expect(result.directUpload).toBe(false);
+
+    await negotiate.execute({
+      capabilities: { ...CLIENT, features: ['direct-upload'] },
+      recordingId: 'rec-1',
+    });
+    const direct = await getUploadUrl.execute({ recordingId: 'rec-1', type: 'chunk', chunkId: 0 });
+    expect(direct.directUpload).toBe(true);
+  });
+});
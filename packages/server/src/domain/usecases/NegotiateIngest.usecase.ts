@@ -0,0 +1,49 @@
+import type { RecordingId, IngestCapabilities, IngestHandshakeResponse } from '@maycast/common-types';
+import { RecordingNotFoundError, negotiateIngest } from '@maycast/common-types';
+import type { IRecordingRepository } from '../repositories/IRecordingRepository.js';
+
+/**
+ * Ingestハンドシェイクリクエスト
+ */
+export interface NegotiateIngestRequest {
+  capabilities: IngestCapabilities;
+  /** 指定された場合、交渉結果を録画に保存する */
+  recordingId?: RecordingId;
+}
+
+/**
+ * Ingestハンドシェイク Use Case (Server-side)
+ *
+ * ビジネスフロー:
+ * 1. クライアントとサーバーの対応範囲から交渉結果を決める
+ * 2. 録画が指定されていれば、交渉結果をメタデータ（ingest）に保存する
+ *    以降のアップロードはこの結果に従う（IngestPolicy）
+ */
+export class NegotiateIngestUseCase {
+  private recordingRepository: IRecordingRepository;
+  private serverCapabilities: IngestCapabilities;
+
+  constructor(recordingRepository: IRecordingRepository, serverCapabilities: IngestCapabilities) {
+    this.recordingRepository = recordingRepository;
+    this.serverCapabilities = serverCapabilities;
+  }
+
+  async execute(request: NegotiateIngestRequest): Promise<IngestHandshakeResponse> {
+    // 1. 交渉
+    const negotiation = negotiateIngest(request.capabilities, this.serverCapabilities);
+
+    // 2. 録画への保存
+    if (request.recordingId !== undefined) {
+      const recording = await this.recordingRepository.findById(request.recordingId);
+      if (!recording) {
+        throw new RecordingNotFoundError(`Recording not found: ${request.recordingId}`);
+      }
+      const metadata = { ...(recording.getMetadata() ?? {}), ingest: negotiation };
+      // Entityのビジネスルールでメタデータ設定（録画終了後は不可）
+      recording.setMetadata(metadata);
+      await this.recordingRepository.updateMetadata(request.recordingId, metadata);
+    }
+
+    return { negotiation, server: this.serverCapabilities };
+  }
+}
@@ -33,8 +33,10 @@ export class UpdateRecordingMetadataUseCase {
     }
 
     // 既存のメタデータとマージ（participantName等の既存フィールドを保持）
+    // ingestはハンドシェイクでのみ設定するため、クライアントからの値は無視する
+    const { ingest: _ingest, ...metadata } = request.metadata;
     const existingMetadata = recording.getMetadata() || {};
-    const mergedMetadata = { ...existingMetadata, ...request.metadata };
+    const mergedMetadata = { ...existingMetadata, ...metadata };
 
     // Entityのビジネスルールでメタデータ設定
     recording.setMetadata(mergedMetadata);
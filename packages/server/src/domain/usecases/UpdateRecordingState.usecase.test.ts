@@ -0,0 +1,123 @@
+import { describe, it, expect, beforeEach } from 'vitest';
+import { blake3 } from '@noble/hashes/blake3.js';
+import { bytesToHex, hexToBytes, utf8ToBytes } from '@noble/hashes/utils.js';
+import { RecordingEntity, IntegrityVerificationError, INTEGRITY_REPORT_VERSION } from '@maycast/common-types';
+import type { IntegrityManifestChunk, SignedIntegrityReport } from '@maycast/common-types';
+import { InMemoryRecordingRepository } from '../../infrastructure/repositories/InMemoryRecordingRepository.js';
+import { InMemoryChunkRepository } from '../../infrastructure/repositories/InMemoryChunkRepository.js';
+import { Blake3IntegrityReportVerifier } from '../../infrastructure/services/Blake3IntegrityReportVerifier.js';
+import { UpdateRecordingStateUseCase } from './UpdateRecordingState.usecase.js';
+
+const RECORDING_ID = 'rec-1';
+
+function manifestChunk(
+  sequence: number,
+  data: Uint8Array,
+  chunkId: Partial<IntegrityManifestChunk['chunkId']> = {}
+): IntegrityManifestChunk {
+  return {
+    chunkId: { sessionId: RECORDING_ID, track: 'muxed', sequence, ...chunkId },
+    timestamp: sequence * 1_000_000,
+    size: data.length,
+    hash: bytesToHex(blake3(data)),
+    createdAt: 0,
+  };
+}
+
+/** WASM側 integrity.rs と同じ方式で署名したレポート */
+function signedReport(
+  verifier: Blake3IntegrityReportVerifier,
+  chunks: IntegrityManifestChunk[]
+): SignedIntegrityReport {
+  let head = blake3(utf8ToBytes(RECORDING_ID));
+  for (const chunk of chunks) {
+    const link = new Uint8Array(64);
+    link.set(head);
+    link.set(hexToBytes(chunk.hash!), 32);
+    head = blake3(link);
+  }
+  const report = JSON.stringify({
+    version: INTEGRITY_REPORT_VERSION,
+    sessionId: RECORDING_ID,
+    chainHead: bytesToHex(head),
+    chunkCount: chunks.length,
+    totalBytes: chunks.reduce((sum, chunk) => sum + chunk.size, 0),
+    quality: {},
+    stats: {},
+    manifest: { sessionId: RECORDING_ID, chunks },
+  });
+  const key = hexToBytes(verifier.issueKey(RECORDING_ID));
+  return { report, signature: bytesToHex(blake3(utf8ToBytes(report), { key })) };
+}
+
+describe('UpdateRecordingStateUseCase', () => {
+  let recordings: InMemoryRecordingRepository;
+  let chunks: InMemoryChunkRepository;
+  let verifier: Blake3IntegrityReportVerifier;
+  let useCase: UpdateRecordingStateUseCase;
+
+  const first = Buffer.from([1, 2, 3]);
+  const second = Buffer.from([4, 5, 6, 7]);
+
+  beforeEach(async () => {
+    recordings = new InMemoryRecordingRepository();
+    chunks = new InMemoryChunkRepository();
+    verifier = new Blake3IntegrityReportVerifier('secret');
+    useCase = new UpdateRecordingStateUseCase(recordings, chunks, verifier);
+
+    const recording = RecordingEntity.create(RECORDING_ID);
+    recording.startRecording();
+    recording.finalize();
+    await recordings.save(recording);
+  });
+
+  const markAsSynced = (integrityReport: SignedIntegrityReport) =>
+    useCase.execute({ recordingId: RECORDING_ID, state: 'synced', integrityReport });
+
+  it('保存済みチャンクがマニフェストと一致すればsyncedにする', async () => {
+    await chunks.saveChunk(RECORDING_ID, 0, first);
+    await chunks.saveChunk(RECORDING_ID, 1, second);
+
+    // サーバーに送られないトラック・レンディションのチャンクは照合しない
+    const report = signedReport(verifier, [
+      manifestChunk(0, first),
+      manifestChunk(0, Buffer.from([9]), { track: 'muxed', rendition: 'low' }),
+      manifestChunk(1, second),
+    ]);
+    await markAsSynced(report);
+
+    expect((await recordings.findById(RECORDING_ID))!.getState()).toBe('synced');
+  });
+
+  it('保存データが署名済みハッシュと異なれば拒否する', async () => {
+    await chunks.saveChunk(RECORDING_ID, 0, first);
+    await chunks.saveChunk(RECORDING_ID, 1, Buffer.from([4, 5, 6, 8]));
+
+    const report = signedReport(verifier, [manifestChunk(0, first), manifestChunk(1, second)]);
+    await expect(markAsSynced(report)).rejects.toThrow(IntegrityVerificationError);
+    expect((await recordings.findById(RECORDING_ID))!.getState()).toBe('finalizing');
+  });
+
+  it('未アップロードやマニフェスト外のチャンクがあれば拒否する', async () => {
+    await chunks.saveChunk(RECORDING_ID, 0, first);
+
+    const report = signedReport(verifier, [manifestChunk(0, first), manifestChunk(1, second)]);
+    await expect(markAsSynced(report)).rejects.toThrow('Chunks not uploaded: 1');
+
+    await chunks.saveChunk(RECORDING_ID, 1, second);
+    await chunks.saveChunk(RECORDING_ID, 2, second);
+    await expect(markAsSynced(report)).rejects.toThrow('Chunks not in the manifest: 2');
+  });
+
+  it('別トラックのチャンクで保存済みシーケンスを埋められない', async () => {
+    await chunks.saveChunk(RECORDING_ID, 0, first);
+    await chunks.saveChunk(RECORDING_ID, 1, second);
+
+    // 1番はvideoトラックとしてのみ載っている
+    const report = signedReport(verifier, [
+      manifestChunk(0, first),
+      manifestChunk(1, second, { track: 'video' }),
+    ]);
+    await expect(markAsSynced(report)).rejects.toThrow('Chunks not in the manifest: 1');
+  });
+});
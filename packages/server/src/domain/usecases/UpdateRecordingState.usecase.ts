@@ -1,6 +1,8 @@
-import type { RecordingId, RecordingState } from '@maycast/common-types';
-import { RecordingNotFoundError } from '@maycast/common-types';
+import type { RecordingId, RecordingState, RoomId, SignedIntegrityReport } from '@maycast/common-types';
+import { RecordingNotFoundError, IntegrityVerificationError } from '@maycast/common-types';
 import type { IRecordingRepository } from '../repositories/IRecordingRepository.js';
+import type { IChunkRepository } from '../repositories/IChunkRepository.js';
+import type { IIntegrityReportVerifier } from '../services/IIntegrityReportVerifier.js';
 
 /**
  * 録画状態更新リクエスト
@@ -8,6 +10,8 @@ import type { IRecordingRepository } from '../repositories/IRecordingRepository.
 export interface UpdateRecordingStateRequest {
   recordingId: RecordingId;
   state: RecordingState;
+  /** syncedへの遷移時の整合性レポート */
+  integrityReport?: SignedIntegrityReport;
 }
 
 /**
@@ -15,14 +19,23 @@ export interface UpdateRecordingStateRequest {
  *
  * ビジネスフロー:
  * 1. Recordingを取得
- * 2. Entityのビジネスルールで状態遷移
- * 3. 状態を永続化
+ * 2. syncedへの遷移では整合性レポートを検証（Verifierが設定されている場合）
+ * 3. Entityのビジネスルールで状態遷移
+ * 4. 状態を永続化
  */
 export class UpdateRecordingStateUseCase {
   private recordingRepository: IRecordingRepository;
+  private chunkRepository: IChunkRepository | null;
+  private integrityReportVerifier: IIntegrityReportVerifier | null;
 
-  constructor(recordingRepository: IRecordingRepository) {
+  constructor(
+    recordingRepository: IRecordingRepository,
+    chunkRepository: IChunkRepository | null = null,
+    integrityReportVerifier: IIntegrityReportVerifier | null = null
+  ) {
     this.recordingRepository = recordingRepository;
+    this.chunkRepository = chunkRepository;
+    this.integrityReportVerifier = integrityReportVerifier;
   }
 
   async execute(request: UpdateRecordingStateRequest): Promise<void> {
@@ -41,6 +54,7 @@ export class UpdateRecordingStateUseCase {
         recording.finalize();
         break;
       case 'synced':
+        await this.verifyIntegrityReport(request, recording.getRoomId());
         recording.markAsSynced();
         break;
       default:
@@ -50,4 +64,65 @@ export class UpdateRecordingStateUseCase {
 
     await this.recordingRepository.updateState(request.recordingId, recording.getState());
   }
+
+  /**
+   * 整合性レポートを検証し、マニフェストのチャンクがすべて保存済みで
+   * 保存データのハッシュがマニフェスト（＝署名済みハッシュチェーン）と一致するか確認
+   */
+  private async verifyIntegrityReport(
+    request: UpdateRecordingStateRequest,
+    roomId?: RoomId
+  ): Promise<void> {
+    if (!this.integrityReportVerifier) {
+      return;
+    }
+    if (!request.integrityReport) {
+      throw new IntegrityVerificationError('Integrity report is required to mark a recording as synced');
+    }
+
+    const verifier = this.integrityReportVerifier;
+    const report = verifier.verify(request.recordingId, request.integrityReport);
+
+    if (!this.chunkRepository) {
+      return;
+    }
+
+    // サーバーに送られるのはメインストリーム（muxed、レンディションなし）のみ
+    const expected = new Map<number, string | undefined>();
+    for (const chunk of report.manifest.chunks) {
+      const { sessionId, track, rendition, sequence } = chunk.chunkId;
+      if (sessionId !== request.recordingId) {
+        throw new IntegrityVerificationError(`Manifest chunk ${sequence} belongs to session ${sessionId}`);
+      }
+      if (track !== 'muxed' || rendition !== undefined) {
+        continue;
+      }
+      if (expected.has(sequence)) {
+        throw new IntegrityVerificationError(`Manifest lists chunk ${sequence} more than once`);
+      }
+      expected.set(sequence, chunk.hash);
+    }
+
+    const stored = await this.chunkRepository.listChunkIds(request.recordingId, roomId);
+    const storedSet = new Set(stored);
+    const missing = [...expected.keys()].filter((sequence) => !storedSet.has(sequence));
+    if (missing.length > 0) {
+      throw new IntegrityVerificationError(`Chunks not uploaded: ${missing.join(', ')}`);
+    }
+    const unexpected = stored.filter((sequence) => !expected.has(sequence));
+    if (unexpected.length > 0) {
+      throw new IntegrityVerificationError(`Chunks not in the manifest: ${unexpected.join(', ')}`);
+    }
+
+    // 保存済みデータを再ハッシュ
+    for (const [sequence, hash] of expected) {
+      const data = await this.chunkRepository.getChunk(request.recordingId, sequence, roomId);
+      if (!data) {
+        throw new IntegrityVerificationError(`Chunks not uploaded: ${sequence}`);
+      }
+      if (verifier.hashChunk(data) !== hash) {
+        throw new IntegrityVerificationError(`Stored chunk ${sequence} does not match its manifest hash`);
+      }
+    }
+  }
 }
@@ -1,7 +1,16 @@
+import { blake3 } from '@noble/hashes/blake3.js';
+import { bytesToHex } from '@noble/hashes/utils.js';
 import type { RecordingId, ChunkId } from '@maycast/common-types';
-import { RecordingNotFoundError, InvalidChunkError } from '@maycast/common-types';
+import {
+  RecordingNotFoundError,
+  InvalidChunkError,
+  ENCRYPTED_CHUNKS_FEATURE,
+  CDC_CHUNKS_FEATURE,
+} from '@maycast/common-types';
 import type { IRecordingRepository } from '../repositories/IRecordingRepository.js';
 import type { IChunkRepository } from '../repositories/IChunkRepository.js';
+import type { IFragmentChecksumVerifier } from '../services/IFragmentChecksumVerifier.js';
+import { assertIngestAccepted, ingestFeatures } from '../services/IngestPolicy.js';
 
 /**
  * チャンクアップロードリクエスト
@@ -10,6 +19,7 @@ export interface UploadChunkRequest {
   recordingId: RecordingId;
   chunkId: ChunkId;
   data: Buffer;
+  /** クライアントが計算したBLAKE3ハッシュ（X-Chunk-Hash, hex） */
   hash?: string;
 }
 
@@ -17,21 +27,26 @@ export interface UploadChunkRequest {
  * チャンクアップロード Use Case (Server-side)
  *
  * ビジネスフロー:
- * 1. Recordingの存在確認
- * 2. チャンクデータの検証
+ * 1. Recordingの存在確認とIngestハンドシェイク結果の確認
+ * 2. チャンクデータの検証（chunk-hashを交渉済みならハッシュを照合、
+ *    fragment-checksumを交渉済みならフラグメントのチェックサムboxを照合。
+ *    暗号化・CDCチャンクを交渉した録画はboxとして読めないため照合しない）
  * 3. チャンクを保存
  * 4. チャンク数を増加
  */
 export class UploadChunkUseCase {
   private recordingRepository: IRecordingRepository;
   private chunkRepository: IChunkRepository;
+  private fragmentChecksumVerifier: IFragmentChecksumVerifier | null;
 
   constructor(
     recordingRepository: IRecordingRepository,
-    chunkRepository: IChunkRepository
+    chunkRepository: IChunkRepository,
+    fragmentChecksumVerifier: IFragmentChecksumVerifier | null = null
   ) {
     this.recordingRepository = recordingRepository;
     this.chunkRepository = chunkRepository;
+    this.fragmentChecksumVerifier = fragmentChecksumVerifier;
   }
 
   async execute(request: UploadChunkRequest): Promise<void> {
@@ -40,11 +55,25 @@ export class UploadChunkUseCase {
     if (!recording) {
       throw new RecordingNotFoundError(`Recording not found: ${request.recordingId}`);
     }
+    assertIngestAccepted(recording);
 
     // 2. チャンクデータの検証
     if (request.data.byteLength === 0) {
       throw new InvalidChunkError('Chunk data is empty');
     }
+    const features = ingestFeatures(recording);
+    if (features?.includes('chunk-hash')) {
+      if (!request.hash) {
+        throw new InvalidChunkError('Chunk hash is required by the ingest handshake');
+      }
+      if (bytesToHex(blake3(request.data)) !== request.hash.toLowerCase()) {
+        throw new InvalidChunkError(`Chunk hash mismatch: ${request.chunkId}`);
+      }
+    }
+    const plainFmp4 = !features?.includes(ENCRYPTED_CHUNKS_FEATURE) && !features?.includes(CDC_CHUNKS_FEATURE);
+    if (features?.includes('fragment-checksum') && plainFmp4) {
+      this.fragmentChecksumVerifier?.verify(request.data);
+    }
 
     // 3. チャンクを保存 (roomIdがある場合はRoom用ストレージパスを使用)
     const roomId = recording.getRoomId();
@@ -2,6 +2,7 @@ import type { RecordingId } from '@maycast/common-types';
 import { RecordingNotFoundError, InvalidChunkError } from '@maycast/common-types';
 import type { IRecordingRepository } from '../repositories/IRecordingRepository.js';
 import type { IChunkRepository } from '../repositories/IChunkRepository.js';
+import { assertIngestAccepted } from '../services/IngestPolicy.js';
 
 /**
  * Init Segmentアップロードリクエスト
@@ -15,7 +16,7 @@ export interface UploadInitSegmentRequest {
  * Init Segmentアップロード Use Case (Server-side)
  *
  * ビジネスフロー:
- * 1. Recordingの存在確認
+ * 1. Recordingの存在確認とIngestハンドシェイク結果の確認
  * 2. Init Segmentデータの検証
  * 3. Init Segmentを保存
  */
@@ -37,6 +38,7 @@ export class UploadInitSegmentUseCase {
     if (!recording) {
       throw new RecordingNotFoundError(`Recording not found: ${request.recordingId}`);
     }
+    assertIngestAccepted(recording);
 
     // 2. Init Segmentデータの検証
     if (request.data.byteLength === 0) {
@@ -9,6 +9,8 @@ import { getPool } from '../database/PostgresClient.js';
 import { getStorageConfig } from '../config/storageConfig.js';
 import { S3PresignedUrlService } from '../services/S3PresignedUrlService.js';
 import { NoOpPresignedUrlService } from '../services/NoOpPresignedUrlService.js';
+import { Blake3IntegrityReportVerifier } from '../services/Blake3IntegrityReportVerifier.js';
+import { Blake3FragmentChecksumVerifier } from '../services/Blake3FragmentChecksumVerifier.js';
 
 // Use Cases - Recording
 import { CreateRecordingUseCase } from '../../domain/usecases/CreateRecording.usecase.js';
@@ -21,6 +23,7 @@ import { DownloadRecordingUseCase } from '../../domain/usecases/DownloadRecordin
 import { GetDownloadUrlsUseCase } from '../../domain/usecases/GetDownloadUrls.usecase.js';
 import { GetUploadUrlUseCase } from '../../domain/usecases/GetUploadUrl.usecase.js';
 import { ConfirmChunkUploadUseCase } from '../../domain/usecases/ConfirmChunkUpload.usecase.js';
+import { NegotiateIngestUseCase } from '../../domain/usecases/NegotiateIngest.usecase.js';
 
 // Use Cases - Room
 import { CreateRoomUseCase } from '../../domain/usecases/CreateRoom.usecase.js';
@@ -33,12 +36,14 @@ import { ValidateRoomAccessUseCase } from '../../domain/usecases/ValidateRoomAcc
 import { RecordingController } from '../../presentation/controllers/RecordingController.js';
 import { ChunkController } from '../../presentation/controllers/ChunkController.js';
 import { RoomController } from '../../presentation/controllers/RoomController.js';
+import { SERVER_INGEST_CAPABILITIES } from '../../presentation/routes/ingest.js';
 
 import type { IRecordingRepository } from '../../domain/repositories/IRecordingRepository.js';
 import type { IChunkRepository } from '../../domain/repositories/IChunkRepository.js';
 import type { IRoomRepository } from '../../domain/repositories/IRoomRepository.js';
 import type { IRoomEventPublisher } from '../../domain/events/IRoomEventPublisher.js';
 import type { IPresignedUrlService } from '../../domain/services/IPresignedUrlService.js';
+import type { IIntegrityReportVerifier } from '../../domain/services/IIntegrityReportVerifier.js';
 
 /**
  * DIコンテナのセットアップ (Server-side)
@@ -46,6 +51,9 @@ import type { IPresignedUrlService } from '../../domain/services/IPresignedUrlSe
  * ストレージバックエンドは環境変数 STORAGE_BACKEND で切り替え:
  * - 'local' (default): LocalFileSystemChunkRepository
  * - 's3': S3ChunkRepository (Cloudflare R2, MinIO, AWS S3)
+ *
+ * 環境変数 INTEGRITY_SECRET が設定されている場合、録画ごとに整合性レポートの
+ * 署名鍵を発行し、synced への遷移時にレポートを検証する
  */
 export function setupContainer(): DIContainer {
   const container = DIContainer.getInstance();
@@ -77,6 +85,13 @@ export function setupContainer(): DIContainer {
   }
   container.register<IPresignedUrlService>('PresignedUrlService', presignedUrlService);
 
+  // Integrity Report Verifier（INTEGRITY_SECRET設定時のみ）
+  const integritySecret = process.env.INTEGRITY_SECRET;
+  const integrityReportVerifier: IIntegrityReportVerifier | null = integritySecret
+    ? new Blake3IntegrityReportVerifier(integritySecret)
+    : null;
+  console.log(`🔏 Integrity reports: ${integrityReportVerifier ? 'required' : 'disabled'}`);
+
   // Repositories
   const pool = getPool();
   const recordingRepository = new PostgresRecordingRepository(pool);
@@ -95,14 +110,19 @@ export function setupContainer(): DIContainer {
   const createRecordingUseCase = new CreateRecordingUseCase(
     recordingRepository,
     roomRepository,
-    roomEventPublisher
+    roomEventPublisher,
+    integrityReportVerifier
   );
   container.register('CreateRecordingUseCase', createRecordingUseCase);
 
   const getRecordingUseCase = new GetRecordingUseCase(recordingRepository);
   container.register('GetRecordingUseCase', getRecordingUseCase);
 
-  const updateRecordingStateUseCase = new UpdateRecordingStateUseCase(recordingRepository);
+  const updateRecordingStateUseCase = new UpdateRecordingStateUseCase(
+    recordingRepository,
+    chunkRepository,
+    integrityReportVerifier
+  );
   container.register('UpdateRecordingStateUseCase', updateRecordingStateUseCase);
 
   const updateRecordingMetadataUseCase = new UpdateRecordingMetadataUseCase(recordingRepository);
@@ -114,7 +134,11 @@ export function setupContainer(): DIContainer {
   );
   container.register('UploadInitSegmentUseCase', uploadInitSegmentUseCase);
 
-  const uploadChunkUseCase = new UploadChunkUseCase(recordingRepository, chunkRepository);
+  const uploadChunkUseCase = new UploadChunkUseCase(
+    recordingRepository,
+    chunkRepository,
+    new Blake3FragmentChecksumVerifier()
+  );
   container.register('UploadChunkUseCase', uploadChunkUseCase);
 
   const downloadRecordingUseCase = new DownloadRecordingUseCase(
@@ -139,6 +163,12 @@ export function setupContainer(): DIContainer {
   const confirmChunkUploadUseCase = new ConfirmChunkUploadUseCase(recordingRepository);
   container.register('ConfirmChunkUploadUseCase', confirmChunkUploadUseCase);
 
+  const negotiateIngestUseCase = new NegotiateIngestUseCase(
+    recordingRepository,
+    SERVER_INGEST_CAPABILITIES
+  );
+  container.register('NegotiateIngestUseCase', negotiateIngestUseCase);
+
   // Controllers
   const recordingController = new RecordingController(
     createRecordingUseCase,
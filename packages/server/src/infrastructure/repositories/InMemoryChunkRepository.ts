@@ -0,0 +1,49 @@
+import type { RecordingId, ChunkId, RoomId } from '@maycast/common-types';
+import type { IChunkRepository } from '../../domain/repositories/IChunkRepository.js';
+
+/**
+ * In-memory Chunk Repository の実装
+ *
+ * テストおよびストレージなしの動作確認用
+ */
+export class InMemoryChunkRepository implements IChunkRepository {
+  private initSegments: Map<string, Buffer> = new Map();
+  private chunks: Map<string, Map<ChunkId, Buffer>> = new Map();
+
+  private key(recordingId: RecordingId, roomId?: RoomId): string {
+    return roomId ? `rooms/${roomId}/${recordingId}` : recordingId;
+  }
+
+  async saveInitSegment(recordingId: RecordingId, data: Buffer, roomId?: RoomId): Promise<void> {
+    this.initSegments.set(this.key(recordingId, roomId), data);
+  }
+
+  async getInitSegment(recordingId: RecordingId, roomId?: RoomId): Promise<Buffer | null> {
+    return this.initSegments.get(this.key(recordingId, roomId)) ?? null;
+  }
+
+  async saveChunk(recordingId: RecordingId, chunkId: ChunkId, data: Buffer, roomId?: RoomId): Promise<void> {
+    const key = this.key(recordingId, roomId);
+    let chunks = this.chunks.get(key);
+    if (!chunks) {
+      chunks = new Map();
+      this.chunks.set(key, chunks);
+    }
+    chunks.set(chunkId, data);
+  }
+
+  async getChunk(recordingId: RecordingId, chunkId: ChunkId, roomId?: RoomId): Promise<Buffer | null> {
+    return this.chunks.get(this.key(recordingId, roomId))?.get(chunkId) ?? null;
+  }
+
+  async listChunkIds(recordingId: RecordingId, roomId?: RoomId): Promise<ChunkId[]> {
+    const chunks = this.chunks.get(this.key(recordingId, roomId));
+    return chunks ? [...chunks.keys()].sort((a, b) => a - b) : [];
+  }
+
+  async deleteAllChunks(recordingId: RecordingId, roomId?: RoomId): Promise<void> {
+    const key = this.key(recordingId, roomId);
+    this.chunks.delete(key);
+    this.initSegments.delete(key);
+  }
+}
@@ -0,0 +1,153 @@
+import { describe, it, expect, beforeEach } from 'vitest';
+import { blake3 } from '@noble/hashes/blake3.js';
+import {
+  RecordingEntity,
+  InvalidChunkError,
+  FRAGMENT_CHECKSUM_UUID,
+  ENCRYPTED_CHUNKS_FEATURE,
+  CDC_CHUNKS_FEATURE,
+} from '@maycast/common-types';
+import type { IngestCapabilities } from '@maycast/common-types';
+import { InMemoryRecordingRepository } from '../repositories/InMemoryRecordingRepository.js';
+import { InMemoryChunkRepository } from '../repositories/InMemoryChunkRepository.js';
+import { SERVER_INGEST_CAPABILITIES } from '../../presentation/routes/ingest.js';
+import { NegotiateIngestUseCase } from '../../domain/usecases/NegotiateIngest.usecase.js';
+import { UploadChunkUseCase } from '../../domain/usecases/UploadChunk.usecase.js';
+import { Blake3FragmentChecksumVerifier } from './Blake3FragmentChecksumVerifier.js';
+
+function box(type: string, payload: Uint8Array): Uint8Array {
+  const out = new Uint8Array(8 + payload.byteLength);
+  new DataView(out.buffer).setUint32(0, out.byteLength);
+  out.set(Array.from(type, (c) => c.charCodeAt(0)), 4);
+  out.set(payload, 8);
+  return out;
+}
+
+function concat(...parts: Uint8Array[]): Uint8Array {
+  const out = new Uint8Array(parts.reduce((n, p) => n + p.byteLength, 0));
+  let offset = 0;
+  for (const part of parts) {
+    out.set(part, offset);
+    offset += part.byteLength;
+  }
+  return out;
+}
+
+function checksumBox(fragment: Uint8Array): Uint8Array {
+  const uuid = Uint8Array.from(FRAGMENT_CHECKSUM_UUID.match(/../g)!, (h) => parseInt(h, 16));
+  return box('uuid', concat(uuid, new Uint8Array(4), blake3(fragment)));
+}
+
+/** moof + mdat + チェックサムbox */
+function fragment(seed: number, withChecksum = true): Uint8Array {
+  const moofMdat = concat(box('moof', new Uint8Array(24).fill(seed)), box('mdat', new Uint8Array(64).fill(seed + 1)));
+  return withChecksum ? concat(moofMdat, checksumBox(moofMdat)) : moofMdat;
+}
+
+const CLIENT: IngestCapabilities = {
+  protocolVersion: 1,
+  codecs: ['avc1.42C01E', 'mp4a.40.2'],
+  containers: ['fmp4'],
+  features: [],
+};
+
+describe('Blake3FragmentChecksumVerifier', () => {
+  const verifier = new Blake3FragmentChecksumVerifier();
+
+  it('チェックサムが一致するフラグメントを通す', () => {
+    expect(() => verifier.verify(concat(fragment(1), fragment(2)))).not.toThrow();
+  });
+
+  it('チェックサムが一致しないフラグメントを拒否する', () => {
+    const data = concat(fragment(1), fragment(2));
+    data[20] ^= 0xff;
+    expect(() => verifier.verify(data)).toThrow(InvalidChunkError);
+  });
+
+  it('チェックサムboxのないチャンクは検証せずに通す', () => {
+    expect(() => verifier.verify(fragment(1, false))).not.toThrow();
+  });
+
+  it('途中で切れたチャンクを拒否する', () => {
+    const data = concat(fragment(1), fragment(2));
+    expect(() => verifier.verify(data.subarray(0, data.byteLength - 10))).toThrow(InvalidChunkError);
+    expect(() => verifier.verify(data.subarray(0, 4))).toThrow(InvalidChunkError);
+  });
+
+  it('boxのサイズや種類が壊れたチャンクを拒否する', () => {
+    const badSize = concat(fragment(1));
+    new DataView(badSize.buffer).setUint32(0, 4);
+    expect(() => verifier.verify(badSize)).toThrow(InvalidChunkError);
+
+    const badType = concat(fragment(1));
+    badType[4] ^= 0x20;
+    expect(() => verifier.verify(badType)).toThrow(InvalidChunkError);
+  });
+});
+
+describe('UploadChunkUseCase のフラグメントチェックサム検証', () => {
+  let recordings: InMemoryRecordingRepository;
+  let negotiate: NegotiateIngestUseCase;
+  let uploadChunk: UploadChunkUseCase;
+  let tampered: Buffer;
+
+  beforeEach(async () => {
+    recordings = new InMemoryRecordingRepository();
+    negotiate = new NegotiateIngestUseCase(recordings, SERVER_INGEST_CAPABILITIES);
+    uploadChunk = new UploadChunkUseCase(
+      recordings,
+      new InMemoryChunkRepository(),
+      new Blake3FragmentChecksumVerifier()
+    );
+    await recordings.save(RecordingEntity.create('rec-1'));
+    tampered = Buffer.from(fragment(1));
+    tampered[20] ^= 0xff;
+  });
+
+  it('fragment-checksumを交渉した録画ではチェックサムを照合する', async () => {
+    await negotiate.execute({
+      capabilities: { ...CLIENT, features: ['fragment-checksum'] },
+      recordingId: 'rec-1',
+    });
+
+    await expect(
+      uploadChunk.execute({ recordingId: 'rec-1', chunkId: 0, data: Buffer.from(fragment(1)) })
+    ).resolves.toBeUndefined();
+    await expect(
+      uploadChunk.execute({ recordingId: 'rec-1', chunkId: 1, data: tampered })
+    ).rejects.toThrow(InvalidChunkError);
+  });
+
+  it('暗号化・CDCチャンクを交渉した録画では照合しない', async () => {
+    const encrypted = Buffer.from(Uint8Array.from({ length: 256 }, (_, i) => (i * 151 + 17) & 0xff));
+    for (const feature of [ENCRYPTED_CHUNKS_FEATURE, CDC_CHUNKS_FEATURE]) {
+      await recordings.save(RecordingEntity.create(`rec-${feature}`));
+      await negotiate.execute({
+        capabilities: { ...CLIENT, features: ['fragment-checksum', feature] },
+        recordingId: `rec-${feature}`,
+      });
+      await expect(
+        uploadChunk.execute({ recordingId: `rec-${feature}`, chunkId: 0, data: encrypted })
+      ).resolves.toBeUndefined();
+    }
+  });
+
+  it('fragment-checksumを交渉した録画では読めないチャンクを拒否する', async () => {
+    await negotiate.execute({
+      capabilities: { ...CLIENT, features: ['fragment-checksum'] },
+      recordingId: 'rec-1',
+    });
+
+    await expect(
+      uploadChunk.execute({ recordingId: 'rec-1', chunkId: 0, data: Buffer.from(fragment(1)).subarray(0, 40) })
+    ).rejects.toThrow(InvalidChunkError);
+  });
+
+  it('fragment-checksumを交渉していない録画では照合しない', async () => {
+    await negotiate.execute({ capabilities: CLIENT, recordingId: 'rec-1' });
+
+    await expect(
+      uploadChunk.execute({ recordingId: 'rec-1', chunkId: 0, data: tampered })
+    ).resolves.toBeUndefined();
+  });
+});
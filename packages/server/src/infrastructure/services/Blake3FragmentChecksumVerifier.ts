@@ -0,0 +1,88 @@
+import { blake3 } from '@noble/hashes/blake3.js';
+import { bytesToHex } from '@noble/hashes/utils.js';
+import { FRAGMENT_CHECKSUM_UUID, InvalidChunkError } from '@maycast/common-types';
+import type { IFragmentChecksumVerifier } from '../../domain/services/IFragmentChecksumVerifier.js';
+
+/**
+ * Blake3 Fragment Checksum Verifier
+ *
+ * トップレベルのboxを順に読み、チェックサムbox（uuid）ごとに直前のmoofから
+ * そのboxの手前までの BLAKE3 と照合する（WASM側 fragment_checksum.rs と同じ）
+ * box: size(4) + 'uuid'(4) + FRAGMENT_CHECKSUM_UUID(16) + version/flags(4) + hash(32)
+ *
+ * トップレベルのboxとして読めないデータ（途中で切れた、サイズが壊れた、
+ * 未知のboxを含む）は fragment_checksum.rs と同じくエラーにする
+ * 暗号化チャンクやCDCチャンクはハンドシェイクを見て呼び出し側が除外する
+ */
+export class Blake3FragmentChecksumVerifier implements IFragmentChecksumVerifier {
+  verify(data: Uint8Array): void {
+    const boxes = topLevelBoxes(data);
+    let fragmentStart: number | null = null;
+    for (const box of boxes) {
+      if (box.type === 'moof') {
+        fragmentStart = box.offset;
+      } else if (box.type === 'uuid' && isChecksumBox(box.payload)) {
+        if (fragmentStart === null) {
+          throw new InvalidChunkError(`Checksum box at ${box.offset} follows no fragment`);
+        }
+        if (box.payload.byteLength < 52) {
+          throw new InvalidChunkError('Truncated fragment checksum box');
+        }
+        const expected = bytesToHex(box.payload.subarray(20, 52));
+        if (bytesToHex(blake3(data.subarray(fragmentStart, box.offset))) !== expected) {
+          throw new InvalidChunkError(`Fragment checksum mismatch at offset ${fragmentStart}`);
+        }
+        fragmentStart = null;
+      }
+    }
+  }
+}
+
+interface TopLevelBox {
+  offset: number;
+  type: string;
+  payload: Uint8Array;
+}
+
+/** fMP4のトップレベルbox（styp/sidx/moof/mdat/uuid等） */
+const FRAGMENT_BOX_TYPES = new Set(['styp', 'sidx', 'prft', 'emsg', 'moof', 'mdat', 'uuid', 'free', 'skip']);
+
+/**
+ * データ全体をトップレベルのboxに分ける
+ * @throws InvalidChunkError サイズが範囲外、末尾が途中で切れている、または未知のboxがある場合
+ */
+function topLevelBoxes(data: Uint8Array): TopLevelBox[] {
+  const view = new DataView(data.buffer, data.byteOffset, data.byteLength);
+  const boxes: TopLevelBox[] = [];
+  let offset = 0;
+  while (offset < data.byteLength) {
+    if (offset + 8 > data.byteLength) {
+      throw new InvalidChunkError(`Truncated box header at offset ${offset}`);
+    }
+    let size = view.getUint32(offset);
+    let header = 8;
+    if (size === 1) {
+      if (offset + 16 > data.byteLength) {
+        throw new InvalidChunkError(`Truncated box header at offset ${offset}`);
+      }
+      size = Number(view.getBigUint64(offset + 8));
+      header = 16;
+    } else if (size === 0) {
+      size = data.byteLength - offset;
+    }
+    if (size < header || offset + size > data.byteLength) {
+      throw new InvalidChunkError(`Invalid box size ${size} at offset ${offset}`);
+    }
+    const type = String.fromCharCode(...data.subarray(offset + 4, offset + 8));
+    if (!FRAGMENT_BOX_TYPES.has(type)) {
+      throw new InvalidChunkError(`Unexpected box type at offset ${offset}`);
+    }
+    boxes.push({ offset, type, payload: data.subarray(offset + header, offset + size) });
+    offset += size;
+  }
+  return boxes;
+}
+
+function isChecksumBox(payload: Uint8Array): boolean {
+  return payload.byteLength >= 16 && bytesToHex(payload.subarray(0, 16)) === FRAGMENT_CHECKSUM_UUID;
+}
@@ -0,0 +1,100 @@
+import { timingSafeEqual } from 'node:crypto';
+import { blake3 } from '@noble/hashes/blake3.js';
+import { bytesToHex, hexToBytes, utf8ToBytes } from '@noble/hashes/utils.js';
+import { INTEGRITY_REPORT_VERSION, IntegrityVerificationError } from '@maycast/common-types';
+import type { RecordingId, IntegrityReport, SignedIntegrityReport } from '@maycast/common-types';
+import type { IIntegrityReportVerifier } from '../../domain/services/IIntegrityReportVerifier.js';
+
+/**
+ * Blake3 Integrity Report Verifier
+ *
+ * 録画ごとの鍵はサーバーシークレットから導出するため保存不要:
+ *   key = BLAKE3(recordingId, key = BLAKE3(INTEGRITY_SECRET))
+ * 署名はレポートJSON文字列そのもののkeyed BLAKE3（WASM側 integrity.rs と同じ）
+ */
+export class Blake3IntegrityReportVerifier implements IIntegrityReportVerifier {
+  private serverKey: Uint8Array;
+
+  constructor(secret: string) {
+    this.serverKey = blake3(utf8ToBytes(secret));
+  }
+
+  issueKey(recordingId: RecordingId): string {
+    return bytesToHex(this.recordingKey(recordingId));
+  }
+
+  verify(recordingId: RecordingId, signed: SignedIntegrityReport): IntegrityReport {
+    // 1. 署名（パース前のバイト列に対して検証）
+    const expected = blake3(utf8ToBytes(signed.report), { key: this.recordingKey(recordingId) });
+    let signature: Uint8Array;
+    try {
+      signature = hexToBytes(signed.signature);
+    } catch {
+      throw new IntegrityVerificationError('Invalid integrity report signature');
+    }
+    if (signature.length !== expected.length || !timingSafeEqual(signature, expected)) {
+      throw new IntegrityVerificationError('Integrity report signature does not match');
+    }
+
+    // 2. レポート本体
+    let report: IntegrityReport;
+    try {
+      report = JSON.parse(signed.report) as IntegrityReport;
+    } catch {
+      throw new IntegrityVerificationError('Integrity report is not valid JSON');
+    }
+    if (report.version !== INTEGRITY_REPORT_VERSION) {
+      throw new IntegrityVerificationError(`Unsupported integrity report version: ${report.version}`);
+    }
+    if (report.sessionId !== recordingId || report.manifest?.sessionId !== recordingId) {
+      throw new IntegrityVerificationError(`Integrity report is not for recording ${recordingId}`);
+    }
+
+    // 3. マニフェストとの整合性
+    const chunks = report.manifest.chunks ?? [];
+    const totalBytes = chunks.reduce((sum, chunk) => sum + chunk.size, 0);
+    if (report.chunkCount !== chunks.length || report.totalBytes !== totalBytes) {
+      throw new IntegrityVerificationError('Chunk count or size does not match the manifest');
+    }
+    if (chainHead(recordingId, chunks.map((chunk) => chunk.hash)) !== report.chainHead) {
+      throw new IntegrityVerificationError('Hash chain head does not match the manifest');
+    }
+
+    return report;
+  }
+
+  hashChunk(data: Uint8Array): string {
+    return bytesToHex(blake3(data));
+  }
+
+  private recordingKey(recordingId: RecordingId): Uint8Array {
+    return blake3(utf8ToBytes(recordingId), { key: this.serverKey });
+  }
+}
+
+/**
+ * チャンクハッシュのハッシュチェーンの先頭（hex）
+ * BLAKE3(sessionId) から始め、各チャンクで BLAKE3(previous || chunk hash)
+ */
+function chainHead(sessionId: string, hashes: (string | undefined)[]): string {
+  let head = blake3(utf8ToBytes(sessionId));
+  for (const hash of hashes) {
+    if (!hash) {
+      throw new IntegrityVerificationError('Manifest chunk has no hash');
+    }
+    let chunkHash: Uint8Array;
+    try {
+      chunkHash = hexToBytes(hash);
+    } catch {
+      throw new IntegrityVerificationError(`Invalid chunk hash: ${hash}`);
+    }
+    if (chunkHash.length !== 32) {
+      throw new IntegrityVerificationError(`Invalid chunk hash: ${hash}`);
+    }
+    const link = new Uint8Array(head.length + chunkHash.length);
+    link.set(head);
+    link.set(chunkHash, head.length);
+    head = blake3(link);
+  }
+  return bytesToHex(head);
+}
@@ -63,7 +63,8 @@ export class ChunkController {
       return;
     }
 
-    await this.uploadChunkUseCase.execute({ recordingId: id, chunkId, data });
+    const hash = req.get('X-Chunk-Hash') || undefined;
+    await this.uploadChunkUseCase.execute({ recordingId: id, chunkId, data, hash });
 
     res.status(200).json({ success: true });
   }
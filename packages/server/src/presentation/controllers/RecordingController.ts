@@ -47,6 +47,7 @@ export class RecordingController {
       room_id: result.recording.roomId,
       created_at: result.recording.createdAt,
       state: result.recording.state,
+      integrity_key: result.integrityKey,
     });
   }
 
@@ -73,7 +74,7 @@ export class RecordingController {
 
   async updateState(req: Request, res: Response): Promise<void> {
     const { id } = req.params;
-    const { state } = req.body;
+    const { state, integrity_report: integrityReport } = req.body;
 
     console.log('🔍 [RecordingController.updateState] Request received:', {
       id,
@@ -88,7 +89,7 @@ export class RecordingController {
       return;
     }
 
-    await this.updateRecordingStateUseCase.execute({ recordingId: id, state });
+    await this.updateRecordingStateUseCase.execute({ recordingId: id, state, integrityReport });
 
     res.status(200).json({ success: true });
   }
@@ -69,6 +69,7 @@ function getStatusCodeForDomainError(error: DomainError): number {
     case 'INVALID_OPERATION':
     case 'INVALID_CHUNK':
     case 'INVALID_ROOM_STATE_TRANSITION':
+    case 'INTEGRITY_VERIFICATION_FAILED':
       return 400;
 
     // 507 Insufficient Storage
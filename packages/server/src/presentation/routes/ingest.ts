@@ -0,0 +1,82 @@
+import express from 'express';
+import {
+  CDC_CHUNKS_FEATURE,
+  ENCRYPTED_CHUNKS_FEATURE,
+  INGEST_PROTOCOL_VERSION,
+  MIN_INGEST_PROTOCOL_VERSION,
+} from '@maycast/common-types';
+import type { IngestCapabilities, IngestHandshakeRequest } from '@maycast/common-types';
+import type { NegotiateIngestUseCase } from '../../domain/usecases/NegotiateIngest.usecase.js';
+import { asyncHandler } from '../middleware/errorHandler.js';
+
+/**
+ * このサーバーが受け付けるアップロード
+ *
+ * 保存時にコーデックを解釈しないため、レコーダーが出力するコーデックの
+ * サンプルエントリのみを列挙する
+ */
+export const SERVER_INGEST_CAPABILITIES: IngestCapabilities = {
+  protocolVersion: INGEST_PROTOCOL_VERSION,
+  minProtocolVersion: MIN_INGEST_PROTOCOL_VERSION,
+  codecs: ['avc1', 'mp4a'],
+  containers: ['fmp4'],
+  features: [
+    'chunk-hash',
+    'integrity-report',
+    'direct-upload',
+    'fragment-checksum',
+    ENCRYPTED_CHUNKS_FEATURE,
+    CDC_CHUNKS_FEATURE,
+  ],
+  agent: 'maycast-server',
+};
+
+function isStringArray(value: unknown): value is string[] {
+  return Array.isArray(value) && value.every((item) => typeof item === 'string');
+}
+
+function isIngestHandshakeRequest(value: unknown): value is IngestHandshakeRequest {
+  const body = value as Partial<IngestHandshakeRequest> | null;
+  return (
+    typeof body === 'object' &&
+    body !== null &&
+    (body.recordingId === undefined || typeof body.recordingId === 'string') &&
+    Number.isInteger(body.protocolVersion) &&
+    (body.minProtocolVersion === undefined || Number.isInteger(body.minProtocolVersion)) &&
+    isStringArray(body.codecs) &&
+    isStringArray(body.containers) &&
+    (body.features === undefined || isStringArray(body.features))
+  );
+}
+
+/**
+ * Ingest Router
+ *
+ * チャンクアップロード前のハンドシェイク
+ *
+ * NOTE: JSONデータを扱うため、express.json()ミドルウェアを使用
+ */
+export function createIngestRouter(negotiateIngestUseCase: NegotiateIngestUseCase): express.Router {
+  const router = express.Router();
+
+  const jsonParser = express.json();
+
+  /**
+   * POST /api/ingest/handshake
+   * クライアントの対応範囲を受け取り、交渉結果とサーバーの対応範囲を返す
+   * 交渉が成立しない場合も200で返し、クライアントが理由を表示できるようにする
+   * recordingIdがあれば交渉結果を録画に保存し、以降のアップロードをそれに従わせる
+   */
+  router.post('/ingest/handshake', jsonParser, asyncHandler(async (req, res) => {
+    if (!isIngestHandshakeRequest(req.body)) {
+      res.status(400).json({ error: 'Request body must be IngestCapabilities' });
+      return;
+    }
+
+    const { recordingId, ...capabilities } = req.body;
+    const response = await negotiateIngestUseCase.execute({ capabilities, recordingId });
+    res.status(200).json(response);
+  }));
+
+  return router;
+}
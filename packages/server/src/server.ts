@@ -7,6 +7,7 @@ import { setupContainer } from './infrastructure/di/setupContainer.js';
 import { createRecordingsRouter } from './presentation/routes/recordings.js';
 import { createChunksRouter } from './presentation/routes/chunks.js';
 import { createRoomsRouter } from './presentation/routes/rooms.js';
+import { createIngestRouter } from './presentation/routes/ingest.js';
 import { errorHandler } from './presentation/middleware/errorHandler.js';
 import { createRoomAccessMiddleware } from './presentation/middleware/roomAccessMiddleware.js';
 
@@ -17,6 +18,7 @@ import type { RoomController } from './presentation/controllers/RoomController.j
 import type { UpdateRoomStateUseCase } from './domain/usecases/UpdateRoomState.usecase.js';
 import type { GetRoomUseCase } from './domain/usecases/GetRoom.usecase.js';
 import type { ValidateRoomAccessUseCase } from './domain/usecases/ValidateRoomAccess.usecase.js';
+import type { NegotiateIngestUseCase } from './domain/usecases/NegotiateIngest.usecase.js';
 import type { IRecordingRepository } from './domain/repositories/IRecordingRepository.js';
 
 // Load environment variables
@@ -38,6 +40,7 @@ const roomController = container.resolve<RoomController>('RoomController');
 const updateRoomStateUseCase = container.resolve<UpdateRoomStateUseCase>('UpdateRoomStateUseCase');
 const getRoomUseCase = container.resolve<GetRoomUseCase>('GetRoomUseCase');
 const validateRoomAccessUseCase = container.resolve<ValidateRoomAccessUseCase>('ValidateRoomAccessUseCase');
+const negotiateIngestUseCase = container.resolve<NegotiateIngestUseCase>('NegotiateIngestUseCase');
 const recordingRepository = container.resolve<IRecordingRepository>('RecordingRepository');
 
 // Room Access Middleware
@@ -55,6 +58,8 @@ app.use(morgan(LOG_LEVEL === 'debug' ? 'dev' : 'combined'));
 // - チャンクルーター: express.raw()でバイナリデータをパース
 // - レコーディングルーター: express.json()でJSONデータをパース
 // - ルームルーター: express.json()でJSONデータをパース
+// - Ingestルーター: express.json()でJSONデータをパース
+app.use('/api', createIngestRouter(negotiateIngestUseCase));
 app.use('/api', createChunksRouter(chunkController));
 app.use('/api', createRecordingsRouter(recordingController));
 app.use('/api', createRoomsRouter(roomController, roomAccessMiddleware));
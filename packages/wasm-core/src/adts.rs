@@ -0,0 +1,218 @@
+//! ADTS framing of raw AAC frames.
+//!
+//! WebCodecs delivers raw AAC access units, which the fMP4 muxer stores as
+//! is. Legacy endpoints (Icecast, HLS audio renditions in MPEG-TS, plain
+//! `.aac` files) instead expect each frame behind a 7-byte ADTS header that
+//! repeats the profile, sample rate and channel layout, since there is no
+//! container to carry the AudioSpecificConfig. `AdtsWriterState` builds that
+//! header from the muxer's audio settings, so the same encoded frames can be
+//! streamed as ADTS while they are muxed.
+//!
+//! Headers are written without a CRC (`protection_absent` = 1) and hold one
+//! raw data block each.
+
+use crate::muxide_muxer::{sampling_frequency_index, MuxideConfig, SAMPLING_FREQUENCIES};
+
+/// Size of an ADTS header without CRC
+pub const ADTS_HEADER_SIZE: usize = 7;
+
+/// Largest frame an ADTS header can describe (13-bit length, header included)
+pub const ADTS_MAX_FRAME_SIZE: usize = (1 << 13) - 1;
+
+/// AAC Main, LC, SSR and LTP: the object types ADTS has a profile for
+const MAX_ADTS_OBJECT_TYPE: u8 = 4;
+/// SBR and PS object types, signalled explicitly in front of the core type
+const SBR_OBJECT_TYPE: u8 = 5;
+const PS_OBJECT_TYPE: u8 = 29;
+
+/// Wraps raw AAC frames in ADTS headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdtsWriterState {
+    /// Audio object type minus one
+    profile: u8,
+    sampling_frequency_index: u8,
+    channel_configuration: u8,
+}
+
+impl AdtsWriterState {
+    /// Create a writer for AAC-LC at `sample_rate` with `channels` channels
+    pub fn new(sample_rate: u32, channels: u16) -> Result<Self, String> {
+        let sampling_frequency_index = sampling_frequency_index(sample_rate)
+            .ok_or_else(|| format!("ADTS cannot signal a sample rate of {} Hz", sample_rate))?;
+        Self::with_fields(2, sampling_frequency_index, channels as u32)
+    }
+
+    /// Create a writer from an AudioSpecificConfig (WebCodecs
+    /// `decoderConfig.description`)
+    ///
+    /// Explicitly signalled HE-AAC (SBR, PS) is framed as its AAC core, as
+    /// ADTS streams carry SBR implicitly.
+    pub fn from_audio_specific_config(config: &[u8]) -> Result<Self, String> {
+        let mut bits = BitReader::new(config);
+        let mut object_type = read_object_type(&mut bits)?;
+        let frequency_index = bits.read(4)? as u8;
+        if frequency_index as usize >= SAMPLING_FREQUENCIES.len() {
+            return Err(format!(
+                "ADTS cannot signal sampling frequency index {}",
+                frequency_index
+            ));
+        }
+        let channels = bits.read(4)?;
+        if object_type == SBR_OBJECT_TYPE || object_type == PS_OBJECT_TYPE {
+            // extensionSamplingFrequencyIndex, then the core object type
+            if bits.read(4)? == 15 {
+                bits.read(24)?;
+            }
+            object_type = read_object_type(&mut bits)?;
+        }
+        if !(1..=MAX_ADTS_OBJECT_TYPE).contains(&object_type) {
+            return Err(format!(
+                "ADTS cannot signal audio object type {}",
+                object_type
+            ));
+        }
+        Self::with_fields(object_type, frequency_index, channels)
+    }
+
+    /// Create a writer for the audio track of a muxer configuration
+    pub fn from_muxer_config(config: &MuxideConfig) -> Result<Self, String> {
+        if !config.has_audio() {
+            return Err("Audio not configured".to_string());
+        }
+        match &config.audio_specific_config {
+            Some(asc) => Self::from_audio_specific_config(asc),
+            None => Self::new(
+                config.audio_sample_rate.unwrap_or(48000),
+                config.audio_channels.unwrap_or(2),
+            ),
+        }
+    }
+
+    fn with_fields(
+        object_type: u8,
+        sampling_frequency_index: u8,
+        channels: u32,
+    ) -> Result<Self, String> {
+        if !(1..=7).contains(&channels) {
+            return Err(format!(
+                "ADTS cannot signal channel configuration {}",
+                channels
+            ));
+        }
+        Ok(Self {
+            profile: object_type - 1,
+            sampling_frequency_index,
+            channel_configuration: channels as u8,
+        })
+    }
+
+    /// Sample rate announced in the headers
+    pub fn sample_rate(&self) -> u32 {
+        SAMPLING_FREQUENCIES[self.sampling_frequency_index as usize]
+    }
+
+    /// Build the header for a raw frame of `frame_size` bytes
+    pub fn header(&self, frame_size: usize) -> Result<[u8; ADTS_HEADER_SIZE], String> {
+        let length = frame_size + ADTS_HEADER_SIZE;
+        if length > ADTS_MAX_FRAME_SIZE {
+            return Err(format!(
+                "AAC frame of {} bytes is too large for ADTS",
+                frame_size
+            ));
+        }
+        let length = length as u16;
+        let channels = self.channel_configuration;
+        Ok([
+            0xFF,
+            // Syncword end, MPEG-4, layer 0, no CRC
+            0xF1,
+            (self.profile << 6) | (self.sampling_frequency_index << 2) | (channels >> 2),
+            ((channels & 0x3) << 6) | (length >> 11) as u8,
+            (length >> 3) as u8,
+            // Length end, buffer fullness 0x7FF (variable bitrate)
+            ((length & 0x7) << 5) as u8 | 0x1F,
+            // Buffer fullness end, one raw data block
+            0xFC,
+        ])
+    }
+
+    /// Wrap a raw AAC frame in an ADTS header
+    pub fn wrap(&self, frame: &[u8]) -> Result<Vec<u8>, String> {
+        let mut out = Vec::with_capacity(ADTS_HEADER_SIZE + frame.len());
+        self.wrap_into(frame, &mut out)?;
+        Ok(out)
+    }
+
+    /// Append a raw AAC frame with its ADTS header to `out`
+    pub fn wrap_into(&self, frame: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+        out.extend_from_slice(&self.header(frame.len())?);
+        out.extend_from_slice(frame);
+        Ok(())
+    }
+}
+
+/// audioObjectType, with the escape for types above 30
+fn read_object_type(bits: &mut BitReader) -> Result<u8, String> {
+    let object_type = bits.read(5)? as u8;
+    if object_type == 31 {
+        return Ok(32 + bits.read(6)? as u8);
+    }
+    Ok(object_type)
+}
+
+/// Reads big-endian bit fields
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn read(&mut self, count: usize) -> Result<u32, String> {
+        let mut value = 0;
+        for _ in 0..count {
+            let byte = self
+                .bytes
+                .get(self.pos / 8)
+                .ok_or("AudioSpecificConfig is truncated")?;
+            value = (value << 1) | ((byte >> (7 - self.pos % 8)) & 1) as u32;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adts_headers() {
+        // AAC-LC, 48 kHz, stereo: the header ffmpeg writes for a 371-byte frame
+        let writer = AdtsWriterState::new(48000, 2).unwrap();
+        let frame = vec![0x21; 364];
+        let wrapped = writer.wrap(&frame).unwrap();
+        assert_eq!(&wrapped[..7], &[0xFF, 0xF1, 0x4C, 0x80, 0x2E, 0x7F, 0xFC]);
+        assert_eq!(&wrapped[7..], &frame[..]);
+
+        // Same fields from the AudioSpecificConfig WebCodecs reports
+        assert_eq!(
+            AdtsWriterState::from_audio_specific_config(&[0x11, 0x90]).unwrap(),
+            writer
+        );
+        // HE-AAC with explicit SBR (24 kHz core, 48 kHz output) as its LC core
+        let he_aac =
+            AdtsWriterState::from_audio_specific_config(&[0x2B, 0x11, 0x88, 0x00]).unwrap();
+        assert_eq!(he_aac.sample_rate(), 24000);
+        assert_eq!(he_aac.header(0).unwrap()[2] >> 6, 1);
+
+        assert!(AdtsWriterState::new(47999, 2).is_err());
+        assert!(AdtsWriterState::new(48000, 8).is_err());
+        assert!(writer.wrap(&vec![0; ADTS_MAX_FRAME_SIZE]).is_err());
+        // AAC-ELD (object type 39) has no ADTS profile
+        assert!(AdtsWriterState::from_audio_specific_config(&[0xF8, 0xE6, 0x40]).is_err());
+    }
+}
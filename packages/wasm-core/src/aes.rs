@@ -0,0 +1,91 @@
+//! AES-128-CBC with PKCS#7 padding, on the RustCrypto `aes` and `cbc` crates.
+//!
+//! Only what HLS `METHOD=AES-128` segment encryption needs. The `aes` crate
+//! uses hardware instructions where available and a constant-time software
+//! implementation otherwise.
+
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, InnerIvInit, KeyInit};
+
+/// Key and block size in bytes
+pub(crate) const AES_BLOCK_SIZE: usize = 16;
+
+/// AES-128 with its expanded key
+#[derive(Clone)]
+pub(crate) struct Aes128 {
+    cipher: aes::Aes128,
+}
+
+impl std::fmt::Debug for Aes128 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print key material
+        f.write_str("Aes128")
+    }
+}
+
+impl Aes128 {
+    pub(crate) fn new(key: &[u8]) -> Result<Self, String> {
+        let cipher = aes::Aes128::new_from_slice(key)
+            .map_err(|_| format!("AES-128 key must be 16 bytes, got {}", key.len()))?;
+        Ok(Self { cipher })
+    }
+
+    /// Encrypt in CBC mode, padding with PKCS#7
+    pub(crate) fn cbc_encrypt(&self, iv: &[u8; AES_BLOCK_SIZE], data: &[u8]) -> Vec<u8> {
+        cbc::Encryptor::inner_iv_init(self.cipher.clone(), iv.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(data)
+    }
+
+    /// Decrypt in CBC mode and strip the PKCS#7 padding
+    pub(crate) fn cbc_decrypt(
+        &self,
+        iv: &[u8; AES_BLOCK_SIZE],
+        data: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        if data.is_empty() || !data.len().is_multiple_of(AES_BLOCK_SIZE) {
+            return Err(format!(
+                "AES-CBC data of {} bytes is not whole blocks",
+                data.len()
+            ));
+        }
+        cbc::Decryptor::inner_iv_init(self.cipher.clone(), iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(data)
+            .map_err(|_| "Invalid PKCS#7 padding (wrong key or IV?)".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_aes128_vectors() {
+        // SP 800-38A F.2.1, first two blocks, then one block of padding
+        let aes = Aes128::new(&hex("2b7e151628aed2a6abf7158809cf4f3c")).unwrap();
+        let iv: [u8; 16] = hex("000102030405060708090a0b0c0d0e0f").try_into().unwrap();
+        let plaintext = hex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51");
+        let ciphertext = aes.cbc_encrypt(&iv, &plaintext);
+        assert_eq!(
+            ciphertext[..32],
+            hex("7649abac8119b246cee98e9b12e9197d5086cb9b507219ee95db113a917678b2")
+        );
+        assert_eq!(ciphertext.len(), 48);
+        assert_eq!(aes.cbc_decrypt(&iv, &ciphertext).unwrap(), plaintext);
+
+        // Odd sizes round-trip; a wrong key fails the padding check
+        let short = aes.cbc_encrypt(&iv, b"moof");
+        assert_eq!(short.len(), 16);
+        assert_eq!(aes.cbc_decrypt(&iv, &short).unwrap(), b"moof");
+        let other = Aes128::new(&[7; 16]).unwrap();
+        assert!(other.cbc_decrypt(&iv, &ciphertext).is_err());
+        assert!(aes.cbc_decrypt(&iv, &ciphertext[..20]).is_err());
+        assert!(Aes128::new(&[0; 24]).is_err());
+    }
+}
@@ -0,0 +1,724 @@
+//! Server-side assembly of uploaded chunks (`native` feature).
+//!
+//! `ChunkAssembler` collects the chunks uploaded for one session, checks each
+//! against the session manifest (size and BLAKE3 hash), and streams the final
+//! MP4 to any tokio writer: the init segment followed by the chunks in
+//! sequence order. Chunks are fMP4 fragments, so plain concatenation is a
+//! valid file; optionally they are merged into larger fragments on the way
+//! out. Fragments are renumbered on the way out as well, so their mfhd
+//! sequence numbers increase through the file even if chunks come from
+//! several muxer runs (see `continuity`); `assembled_manifest` gives the
+//! manifest matching the output. Encrypted chunks are opaque and copied as
+//! stored, neither renumbered nor merged.
+//!
+//! While a session is still uploading, `prepare_download` and
+//! `write_download` serve its playable prefix over HTTP: the init segment
+//! and the chunks received without a gap from the first one, with single
+//! `Range` requests answered as `206 Partial Content`. The prefix only grows
+//! as chunks arrive, so byte offsets stay valid across requests; its total
+//! length is reported as unknown (`*`) until every chunk is there.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::chunk::{ChunkId, TrackKind};
+use crate::compat::compare_init_segments;
+use crate::continuity::SequenceContinuity;
+use crate::logging::{log_event, LogLevel};
+use crate::manifest::{ChunkManifest, KeyframeLocation};
+use crate::muxide_muxer::Refragmenter;
+use crate::session::SessionId;
+
+/// Which stream to assemble and how
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyOptions {
+    pub track: TrackKind,
+    /// Simulcast rendition, None for single-rendition recordings
+    pub rendition: Option<String>,
+    /// Merge this many chunks into each output fragment; None copies chunks as-is
+    pub chunks_per_fragment: Option<u32>,
+}
+
+impl Default for AssemblyOptions {
+    fn default() -> Self {
+        Self {
+            track: TrackKind::Muxed,
+            rendition: None,
+            chunks_per_fragment: None,
+        }
+    }
+}
+
+/// How to answer a download request for a possibly incomplete recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgressiveDownload {
+    /// HTTP status: 200, 206 or 416 (range not satisfiable)
+    pub status: u16,
+    /// Bytes of the playable prefix
+    pub available: u64,
+    /// Whether the prefix is the whole recording
+    pub complete: bool,
+    /// Bytes of the prefix to send, None for a 416
+    pub range: Option<Range<u64>>,
+}
+
+impl ProgressiveDownload {
+    /// Response headers, names in canonical case
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let total = if self.complete {
+            self.available.to_string()
+        } else {
+            "*".to_string()
+        };
+        let mut headers = vec![
+            ("Accept-Ranges", "bytes".to_string()),
+            ("Content-Type", "video/mp4".to_string()),
+        ];
+        match &self.range {
+            Some(range) => {
+                headers.push(("Content-Length", (range.end - range.start).to_string()));
+                if self.status == 206 {
+                    headers.push((
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", range.start, range.end - 1, total),
+                    ));
+                }
+            }
+            None => {
+                headers.push(("Content-Length", "0".to_string()));
+                headers.push(("Content-Range", format!("bytes */{}", self.available)));
+            }
+        }
+        if !self.complete {
+            // The prefix grows; a cached copy would stop early
+            headers.push(("Cache-Control", "no-store".to_string()));
+        }
+        headers
+    }
+}
+
+/// Collects verified chunks of one session and writes the assembled file
+pub struct ChunkAssembler {
+    manifest: ChunkManifest,
+    init_segments: BTreeMap<Option<String>, Vec<u8>>,
+    chunks: BTreeMap<ChunkId, Vec<u8>>,
+}
+
+impl ChunkAssembler {
+    /// Create an assembler for the session described by `manifest`
+    pub fn new(manifest: ChunkManifest) -> Self {
+        Self {
+            manifest,
+            init_segments: BTreeMap::new(),
+            chunks: BTreeMap::new(),
+        }
+    }
+
+    /// Session being assembled
+    pub fn session_id(&self) -> &SessionId {
+        &self.manifest.session_id
+    }
+
+    /// Set the init segment of the recording, or of one rendition
+    ///
+    /// A replacement (e.g. re-uploaded after a recovery) must describe the
+    /// same tracks and codec configuration, or the chunks muxed for the
+    /// first one would not play behind it.
+    pub fn put_init_segment(
+        &mut self,
+        rendition: Option<&str>,
+        data: Vec<u8>,
+    ) -> Result<(), String> {
+        let rendition = rendition.map(str::to_string);
+        if let Some(existing) = self.init_segments.get(&rendition) {
+            if *existing != data {
+                compare_init_segments(existing, &data)?.ensure()?;
+            }
+        }
+        self.init_segments.insert(rendition, data);
+        Ok(())
+    }
+
+    /// Accept an uploaded chunk after checking it against the manifest
+    ///
+    /// Re-uploading a chunk with the same contents is allowed.
+    pub fn ingest(&mut self, id: ChunkId, data: Vec<u8>) -> Result<(), String> {
+        let metadata = self
+            .manifest
+            .get(&id)
+            .ok_or_else(|| format!("Chunk {} is not in the manifest", id))?;
+        if metadata.size != data.len() as u64 {
+            return Err(format!(
+                "Chunk {} size mismatch: manifest has {} bytes, got {}",
+                id,
+                metadata.size,
+                data.len()
+            ));
+        }
+        if let Some(expected) = &metadata.hash {
+            let actual = blake3::hash(&data).to_hex();
+            if actual.as_str() != expected {
+                return Err(format!(
+                    "Chunk {} hash mismatch: expected {}, got {}",
+                    id, expected, actual
+                ));
+            }
+        }
+        self.chunks.insert(id, data);
+        Ok(())
+    }
+
+    /// Read a chunk to the end from `reader` and ingest it
+    pub async fn ingest_from<R: AsyncRead + Unpin>(
+        &mut self,
+        id: ChunkId,
+        reader: &mut R,
+    ) -> Result<(), String> {
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .await
+            .map_err(|e| format!("Failed to read chunk {}: {}", id, e))?;
+        self.ingest(id, data)
+    }
+
+    /// Chunks of the selected stream listed in the manifest but not ingested yet
+    pub fn missing(&self, options: &AssemblyOptions) -> Vec<ChunkId> {
+        self.stream_chunks(options)
+            .into_iter()
+            .filter(|id| !self.chunks.contains_key(id))
+            .collect()
+    }
+
+    /// Write the assembled file to `out`, returning the number of bytes written
+    ///
+    /// Fails without writing anything if the init segment or any chunk of the
+    /// stream is missing.
+    pub async fn write_to<W: AsyncWrite + Unpin>(
+        &self,
+        options: &AssemblyOptions,
+        out: &mut W,
+    ) -> Result<u64, String> {
+        let init = self
+            .init_segments
+            .get(&options.rendition)
+            .ok_or_else(|| format!("Init segment missing for session {}", self.session_id()))?;
+        let ids = self.stream_chunks(options);
+        if ids.is_empty() {
+            return Err(format!(
+                "No {} chunks in the manifest of session {}",
+                options.track,
+                self.session_id()
+            ));
+        }
+        let missing = self.missing(options);
+        if !missing.is_empty() {
+            let list: Vec<String> = missing.iter().map(ToString::to_string).collect();
+            return Err(format!("Missing chunks: {}", list.join(", ")));
+        }
+
+        if self.encrypted() && options.chunks_per_fragment.is_some() {
+            return Err("Encrypted chunks cannot be merged".to_string());
+        }
+
+        let mut refragmenter = options
+            .chunks_per_fragment
+            .map(|count| Refragmenter::new(init, count))
+            .transpose()?;
+        let mut continuity = (!self.encrypted()).then(SequenceContinuity::default);
+        let mut renumber = |data: &[u8]| match continuity.as_mut() {
+            Some(continuity) => continuity.renumber(data),
+            None => Ok(data.to_vec()),
+        };
+        let mut written = 0u64;
+        write_all(out, init, &mut written).await?;
+        for id in &ids {
+            let data = &self.chunks[id];
+            match refragmenter.as_mut() {
+                Some(refragmenter) => {
+                    if let Some(fragment) = refragmenter.push_segment(data)? {
+                        write_all(out, &renumber(&fragment)?, &mut written).await?;
+                    }
+                }
+                None => write_all(out, &renumber(data)?, &mut written).await?,
+            }
+        }
+        if let Some(fragment) = refragmenter.as_mut().and_then(Refragmenter::finish) {
+            write_all(out, &renumber(&fragment)?, &mut written).await?;
+        }
+        if let Some(continuity) = continuity.filter(|c| !c.is_continuous()) {
+            log_event!(
+                LogLevel::Warn,
+                "Renumbered fragments of assembled recording",
+                session = self.session_id(),
+                breaks = continuity.breaks().len(),
+            );
+        }
+        out.flush()
+            .await
+            .map_err(|e| format!("Failed to flush output: {}", e))?;
+
+        log_event!(
+            LogLevel::Info,
+            "Assembled recording",
+            session = self.session_id(),
+            chunks = ids.len(),
+            bytes = written,
+        );
+        Ok(written)
+    }
+
+    /// The manifest as it describes the file `write_to` writes with
+    /// `options`
+    ///
+    /// Fragment ranges and bookmarked keyframes carry the mfhd sequence
+    /// numbers given on the way out. Merged fragments are not tracked, so
+    /// with `chunks_per_fragment` the manifest has neither. Fragment ranges
+    /// describe the main muxed stream: for other streams, and for encrypted
+    /// chunks, which are written as stored, the manifest is unchanged. Key
+    /// periods count chunks, which are never renumbered.
+    pub fn assembled_manifest(&self, options: &AssemblyOptions) -> ChunkManifest {
+        let mut manifest = self.manifest.clone();
+        if self.encrypted() || options.track != TrackKind::Muxed || options.rendition.is_some() {
+            return manifest;
+        }
+        let keyframes = manifest
+            .markers
+            .iter_mut()
+            .filter_map(|marker| marker.bookmark.as_mut());
+        if options.chunks_per_fragment.is_some() {
+            keyframes.for_each(|bookmark| bookmark.keyframe = None);
+            manifest.fragments.clear();
+            return manifest;
+        }
+        // Fragments are listed in file order, the order `write_to` numbers
+        // them in
+        let mut sequences = BTreeMap::new();
+        let mut next = SequenceContinuity::default().next_sequence();
+        for fragment in &mut manifest.fragments {
+            sequences.insert(fragment.offset, next);
+            fragment.sequence = next;
+            next = next.saturating_add(1);
+        }
+        for keyframe in keyframes.filter_map(|bookmark| bookmark.keyframe.as_mut()) {
+            let KeyframeLocation {
+                fragment_offset,
+                sequence,
+                ..
+            } = keyframe;
+            if let Some(&renumbered) = sequences.get(fragment_offset) {
+                *sequence = renumbered;
+            }
+        }
+        manifest
+    }
+
+    /// Plan the response to a download of the playable prefix
+    ///
+    /// `range` is the request's `Range` header. A single byte range gets a
+    /// 206, a range starting past the prefix a 416; a missing, malformed or
+    /// multi-range header gets the whole prefix with a 200. Fails if the init
+    /// segment is missing or chunks are to be merged, which would move byte
+    /// offsets as the prefix grows.
+    pub fn prepare_download(
+        &self,
+        options: &AssemblyOptions,
+        range: Option<&str>,
+    ) -> Result<ProgressiveDownload, String> {
+        let (parts, complete) = self.playable_prefix(options)?;
+        let available: u64 = parts.iter().map(|part| part.len() as u64).sum();
+        let (status, range) = match range.and_then(parse_range) {
+            None => (200, Some(0..available)),
+            Some(ByteRange::Suffix(0)) => (416, None),
+            Some(ByteRange::Suffix(length)) => {
+                (206, Some(available.saturating_sub(length)..available))
+            }
+            Some(ByteRange::From(start, _)) if start >= available => (416, None),
+            Some(ByteRange::From(start, last)) => {
+                let end = last.map_or(available, |last| last.saturating_add(1).min(available));
+                (206, Some(start..end))
+            }
+        };
+        Ok(ProgressiveDownload {
+            status,
+            available,
+            complete,
+            range,
+        })
+    }
+
+    /// Write the body planned by `prepare_download`, returning the number of
+    /// bytes written
+    pub async fn write_download<W: AsyncWrite + Unpin>(
+        &self,
+        options: &AssemblyOptions,
+        download: &ProgressiveDownload,
+        out: &mut W,
+    ) -> Result<u64, String> {
+        let Some(range) = &download.range else {
+            return Ok(0);
+        };
+        let (parts, _) = self.playable_prefix(options)?;
+        let mut written = 0u64;
+        let mut offset = 0u64;
+        for part in parts {
+            let part_end = offset + part.len() as u64;
+            let start = range.start.max(offset);
+            let end = range.end.min(part_end);
+            if start < end {
+                let slice = &part[(start - offset) as usize..(end - offset) as usize];
+                write_all(out, slice, &mut written).await?;
+            }
+            offset = part_end;
+        }
+        if written != range.end - range.start {
+            return Err(format!(
+                "Playable prefix of session {} is shorter than the planned download",
+                self.session_id()
+            ));
+        }
+        out.flush()
+            .await
+            .map_err(|e| format!("Failed to flush output: {}", e))?;
+        Ok(written)
+    }
+
+    /// Init segment and the chunks received without a gap from the first,
+    /// and whether that is the whole stream
+    fn playable_prefix(&self, options: &AssemblyOptions) -> Result<(Vec<&[u8]>, bool), String> {
+        if options.chunks_per_fragment.is_some() {
+            return Err("Progressive downloads copy chunks as-is".to_string());
+        }
+        let init = self
+            .init_segments
+            .get(&options.rendition)
+            .ok_or_else(|| format!("Init segment missing for session {}", self.session_id()))?;
+        let ids = self.stream_chunks(options);
+        let mut parts = vec![init.as_slice()];
+        parts.extend(
+            ids.iter()
+                .map_while(|id| self.chunks.get(id))
+                .map(Vec::as_slice),
+        );
+        let complete = !ids.is_empty() && parts.len() == ids.len() + 1;
+        Ok((parts, complete))
+    }
+
+    /// Whether the chunks are encrypted as a whole, hence opaque
+    fn encrypted(&self) -> bool {
+        !self.manifest.keys.is_empty()
+    }
+
+    /// IDs of the selected stream in the manifest, in sequence order
+    fn stream_chunks(&self, options: &AssemblyOptions) -> Vec<ChunkId> {
+        let mut ids: Vec<ChunkId> = self
+            .manifest
+            .chunks
+            .iter()
+            .map(|chunk| &chunk.chunk_id)
+            .filter(|id| id.track == options.track && id.rendition == options.rendition)
+            .cloned()
+            .collect();
+        ids.sort();
+        ids
+    }
+}
+
+/// A single range of a `Range` header
+enum ByteRange {
+    /// `bytes=start-` or `bytes=start-last`
+    From(u64, Option<u64>),
+    /// `bytes=-length`
+    Suffix(u64),
+}
+
+/// Parse a single-range `Range` header; None for anything else
+fn parse_range(header: &str) -> Option<ByteRange> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, last) = spec.split_once('-')?;
+    let (start, last) = (start.trim(), last.trim());
+    if start.is_empty() {
+        return last.parse().ok().map(ByteRange::Suffix);
+    }
+    let start = start.parse().ok()?;
+    if last.is_empty() {
+        return Some(ByteRange::From(start, None));
+    }
+    let last = last.parse().ok()?;
+    (last >= start).then_some(ByteRange::From(start, Some(last)))
+}
+
+async fn write_all<W: AsyncWrite + Unpin>(
+    out: &mut W,
+    data: &[u8],
+    written: &mut u64,
+) -> Result<(), String> {
+    out.write_all(data)
+        .await
+        .map_err(|e| format!("Failed to write output: {}", e))?;
+    *written += data.len() as u64;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::RecordedChunk;
+    use crate::continuity::validate_sequences;
+    use crate::manifest::{Bookmark, KeyPeriod, Marker};
+    use crate::muxide_muxer::MuxideConfig;
+    use crate::recorder::{RecorderEvent, RecorderState};
+
+    fn record() -> (ChunkManifest, Vec<u8>, Vec<RecordedChunk>) {
+        let mut recorder = RecorderState::new(
+            SessionId::from("s1"),
+            MuxideConfig {
+                sps: Some(vec![0x67, 0x42, 0xC0, 0x1E, 0xD9, 0x00, 0x50, 0x05]),
+                pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+                fragment_duration_ms: 1000,
+                ..Default::default()
+            },
+        );
+        let init = recorder.start().unwrap();
+        for i in 0..150u64 {
+            recorder
+                .push_video(&[0, 0, 0, 1, 0x65], i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+        recorder.stop().unwrap();
+        let chunks = recorder
+            .take_events()
+            .into_iter()
+            .filter_map(|event| match event {
+                RecorderEvent::ChunkReady(chunk) => Some(chunk),
+                _ => None,
+            })
+            .collect();
+        (recorder.manifest().clone(), init, chunks)
+    }
+
+    #[tokio::test]
+    async fn test_assembles_chunks_in_order() {
+        let (manifest, init, chunks) = record();
+        let mut assembler = ChunkAssembler::new(manifest);
+        assembler.put_init_segment(None, init.clone()).unwrap();
+
+        let options = AssemblyOptions::default();
+        assert_eq!(assembler.missing(&options).len(), chunks.len());
+        for chunk in chunks.iter().rev() {
+            let mut reader = &chunk.data[..];
+            assembler
+                .ingest_from(chunk.metadata.chunk_id.clone(), &mut reader)
+                .await
+                .unwrap();
+        }
+        assert!(assembler.missing(&options).is_empty());
+
+        let mut out = Vec::new();
+        let written = assembler.write_to(&options, &mut out).await.unwrap();
+        let mut expected = init;
+        for chunk in &chunks {
+            expected.extend_from_slice(&chunk.data);
+        }
+        assert_eq!(out, expected);
+        assert_eq!(written, expected.len() as u64);
+
+        // Merging chunks keeps the samples but produces fewer fragments
+        let mut merged = Vec::new();
+        let options = AssemblyOptions {
+            chunks_per_fragment: Some(2),
+            ..Default::default()
+        };
+        assembler.write_to(&options, &mut merged).await.unwrap();
+        let moofs = |file: &[u8]| file.windows(4).filter(|w| w == b"moof").count();
+        assert_eq!(moofs(&out), chunks.len());
+        assert_eq!(moofs(&merged), chunks.len().div_ceil(2));
+    }
+
+    #[tokio::test]
+    async fn test_renumbered_output_and_manifest() {
+        let (mut manifest, init, mut chunks) = record();
+        // Chunks from the third on come from a second muxer run, which
+        // numbered its fragments from 1 again
+        let mut restart = SequenceContinuity::default();
+        for chunk in chunks.iter_mut().skip(2) {
+            chunk.data = restart.renumber(&chunk.data).unwrap();
+            let entry = manifest
+                .chunks
+                .iter_mut()
+                .find(|c| c.chunk_id == chunk.metadata.chunk_id)
+                .unwrap();
+            entry.hash = Some(blake3::hash(&chunk.data).to_hex().to_string());
+        }
+        for (i, fragment) in manifest.fragments.iter_mut().skip(2).enumerate() {
+            fragment.sequence = i as u32 + 1;
+        }
+        let last = manifest.fragments.last().unwrap().clone();
+        manifest.add_marker(Marker {
+            timestamp_us: last.timestamp_us,
+            label: Some("End".to_string()),
+            bookmark: Some(Bookmark {
+                keyframe: Some(KeyframeLocation {
+                    timestamp_us: last.timestamp_us,
+                    sequence: last.sequence,
+                    fragment_offset: last.offset,
+                    sample_offset: last.offset + 8,
+                }),
+            }),
+        });
+        let assemble = |manifest: ChunkManifest| {
+            let mut assembler = ChunkAssembler::new(manifest);
+            assembler.put_init_segment(None, init.clone()).unwrap();
+            for chunk in &chunks {
+                assembler
+                    .ingest(chunk.metadata.chunk_id.clone(), chunk.data.clone())
+                    .unwrap();
+            }
+            assembler
+        };
+        let options = AssemblyOptions::default();
+
+        let assembler = assemble(manifest.clone());
+        let mut out = Vec::new();
+        assembler.write_to(&options, &mut out).await.unwrap();
+        assert!(validate_sequences([&out[..]]).unwrap().is_empty());
+        let assembled = assembler.assembled_manifest(&options);
+        let sequences: Vec<u32> = assembled.fragments.iter().map(|f| f.sequence).collect();
+        assert_eq!(sequences, (1..=chunks.len() as u32).collect::<Vec<_>>());
+        let bookmark = assembled.markers.iter().find_map(|m| m.bookmark.as_ref());
+        assert_eq!(
+            bookmark
+                .and_then(|b| b.keyframe.as_ref())
+                .map(|k| k.sequence),
+            Some(chunks.len() as u32)
+        );
+        let merged = AssemblyOptions {
+            chunks_per_fragment: Some(2),
+            ..Default::default()
+        };
+        assert!(assembler.assembled_manifest(&merged).fragments.is_empty());
+
+        // Encrypted chunks are copied as stored, and cannot be merged
+        manifest.keys.push(KeyPeriod {
+            first_sequence: 0,
+            key_uri: "key-1".to_string(),
+        });
+        let assembler = assemble(manifest.clone());
+        let mut out = Vec::new();
+        assembler.write_to(&options, &mut out).await.unwrap();
+        let mut stored = init.clone();
+        for chunk in &chunks {
+            stored.extend_from_slice(&chunk.data);
+        }
+        assert_eq!(out, stored);
+        assert_eq!(assembler.assembled_manifest(&options), manifest);
+        let err = assembler.write_to(&merged, &mut Vec::new()).await;
+        assert!(err.unwrap_err().contains("cannot be merged"));
+    }
+
+    fn download(assembler: &ChunkAssembler, range: Option<&str>) -> ProgressiveDownload {
+        assembler
+            .prepare_download(&AssemblyOptions::default(), range)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_progressive_download() {
+        let (manifest, init, chunks) = record();
+        let mut assembler = ChunkAssembler::new(manifest);
+        assembler.put_init_segment(None, init.clone()).unwrap();
+        // Chunk 2 has not arrived, so chunk 3 is not playable yet
+        for chunk in chunks.iter().filter(|c| c.metadata.chunk_id.sequence != 2) {
+            assembler
+                .ingest(chunk.metadata.chunk_id.clone(), chunk.data.clone())
+                .unwrap();
+        }
+        let mut prefix = init;
+        prefix.extend_from_slice(&chunks[0].data);
+        prefix.extend_from_slice(&chunks[1].data);
+        let length = prefix.len() as u64;
+
+        let options = AssemblyOptions::default();
+        let whole = download(&assembler, None);
+        assert_eq!((whole.status, whole.available), (200, length));
+        assert!(!whole.complete);
+        assert!(whole
+            .headers()
+            .contains(&("Content-Length", length.to_string())));
+
+        // Reads crossing part boundaries, clamped to what is available
+        let start = prefix.len() - chunks[1].data.len() - 10;
+        let partial = download(&assembler, Some(&format!("bytes={}-", start)));
+        assert_eq!(partial.status, 206);
+        assert!(partial
+            .headers()
+            .contains(&("Content-Range", format!("bytes {}-{}/*", start, length - 1))));
+        let mut out = Vec::new();
+        assembler
+            .write_download(&options, &partial, &mut out)
+            .await
+            .unwrap();
+        assert_eq!(out, &prefix[start..]);
+        assert_eq!(
+            download(&assembler, Some("bytes=-4")).range,
+            Some(length - 4..length)
+        );
+        assert_eq!(
+            download(&assembler, Some("bytes=0-99999999")).range,
+            Some(0..length)
+        );
+        assert_eq!(download(&assembler, Some("bytes=0-1,5-9")).status, 200);
+        let past = download(&assembler, Some(&format!("bytes={}-", length)));
+        assert_eq!((past.status, past.range.clone()), (416, None));
+        assert!(past
+            .headers()
+            .contains(&("Content-Range", format!("bytes */{}", length))));
+
+        // Once everything arrived the total length is known
+        assembler
+            .ingest(chunks[2].metadata.chunk_id.clone(), chunks[2].data.clone())
+            .unwrap();
+        let complete = download(&assembler, Some("bytes=0-9"));
+        assert!(complete.complete);
+        assert!(complete
+            .headers()
+            .contains(&("Content-Range", format!("bytes 0-9/{}", complete.available))));
+        let mut out = Vec::new();
+        assembler.write_to(&options, &mut out).await.unwrap();
+        assert_eq!(complete.available, out.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_and_incomplete_chunks() {
+        let (manifest, init, chunks) = record();
+        let mut assembler = ChunkAssembler::new(manifest);
+        assembler.put_init_segment(None, init).unwrap();
+
+        let first = &chunks[0];
+        let mut corrupted = first.data.clone();
+        *corrupted.last_mut().unwrap() ^= 0xFF;
+        let err = assembler
+            .ingest(first.metadata.chunk_id.clone(), corrupted)
+            .unwrap_err();
+        assert!(err.contains("hash mismatch"), "{}", err);
+
+        let unknown = ChunkId::new(SessionId::from("other"), TrackKind::Muxed, 0);
+        assert!(assembler.ingest(unknown, first.data.clone()).is_err());
+
+        assembler
+            .ingest(first.metadata.chunk_id.clone(), first.data.clone())
+            .unwrap();
+        let mut out = Vec::new();
+        let err = assembler
+            .write_to(&AssemblyOptions::default(), &mut out)
+            .await
+            .unwrap_err();
+        assert!(err.contains("Missing chunks: s1/muxed/1"), "{}", err);
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,443 @@
+//! Content-defined chunking (FastCDC) for deduplicating archival storage.
+//!
+//! Recorder chunks end at fMP4 fragment boundaries, which playback and live
+//! upload need but which shift with every recording, so two files sharing
+//! most of their bytes (a re-export, an imported copy, a session resumed
+//! into a new one) share no chunks. `split_content_defined` cuts a finished
+//! recording's byte stream where its content says instead: a gear rolling
+//! hash over the bytes, with FastCDC's normalized chunking (a stricter mask
+//! before `avg_size`, a looser one after) keeping sizes near the average.
+//! Equal runs of bytes then produce equal chunks whatever precedes them, and
+//! a backend that stores chunks by hash keeps each only once.
+//!
+//! The result is an ordinary `ChunkManifest`: the muxed chunks are replaced
+//! by `CDC_RENDITION` chunks whose sizes, hashes and concatenation behave
+//! like the fragment-aligned ones (init segment + chunks in order is the
+//! recording), so storage, upload and integrity code handle either. Only
+//! consumers that parse chunks as fragments need the fMP4 split.
+//!
+//! The gear table and masks are part of the format: changing them changes
+//! every cut point and defeats deduplication against stored data.
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::chunk::{ChunkId, ChunkMetadata, TrackKind};
+use crate::manifest::ChunkManifest;
+
+/// Rendition of content-defined chunks, which keeps their IDs and file names
+/// apart from the fragment-aligned ones of the same session
+pub const CDC_RENDITION: &str = "cdc";
+
+/// Smallest `min_size` accepted
+const MIN_CHUNK_SIZE: u32 = 64;
+
+/// Mask bits added before and removed after the average size (FastCDC
+/// normalization level 2)
+const NORMALIZATION: u32 = 2;
+
+/// Chunk size bounds, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CdcConfig {
+    pub min_size: u32,
+    /// Expected size; rounded down to a power of two for the masks
+    pub avg_size: u32,
+    pub max_size: u32,
+}
+
+impl Default for CdcConfig {
+    fn default() -> Self {
+        Self {
+            min_size: 16 * 1024,
+            avg_size: 64 * 1024,
+            max_size: 256 * 1024,
+        }
+    }
+}
+
+impl CdcConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.min_size < MIN_CHUNK_SIZE
+            || self.min_size >= self.avg_size
+            || self.avg_size >= self.max_size
+        {
+            return Err(format!(
+                "Chunk sizes must satisfy {} <= min < avg < max, got {}/{}/{}",
+                MIN_CHUNK_SIZE, self.min_size, self.avg_size, self.max_size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Masks over the top bits of the hash, before and after `avg_size`
+    fn masks(&self) -> (u64, u64) {
+        let bits = self.avg_size.ilog2();
+        let mask = |bits: u32| u64::MAX << (64 - bits.clamp(1, 63));
+        (mask(bits + NORMALIZATION), mask(bits - NORMALIZATION))
+    }
+}
+
+/// Random 64-bit values per byte value, from SplitMix64 with a fixed seed
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x6D61_7963_6173_7463; // "maycastc"
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Length of the chunk starting at `data[0]`
+fn cut_point(data: &[u8], config: &CdcConfig, (mask_small, mask_large): (u64, u64)) -> usize {
+    let min = config.min_size as usize;
+    if data.len() <= min {
+        return data.len();
+    }
+    let end = data.len().min(config.max_size as usize);
+    let normal = end.min(config.avg_size as usize);
+    let mut hash = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(end).skip(min) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        let mask = if i < normal { mask_small } else { mask_large };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// Byte ranges of the content-defined chunks of `data`, covering it in order
+pub fn cdc_ranges(data: &[u8], config: &CdcConfig) -> Result<Vec<Range<usize>>, String> {
+    config.validate()?;
+    let masks = config.masks();
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let end = start + cut_point(&data[start..], config, masks);
+        ranges.push(start..end);
+        start = end;
+    }
+    Ok(ranges)
+}
+
+/// A recording cut into content-defined chunks
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentDefinedSplit {
+    /// The source manifest with its muxed chunks replaced and fragments
+    /// pointing at the new chunks
+    pub manifest: ChunkManifest,
+    /// Bytes of the init segment at the start of the recording
+    pub init_segment_len: usize,
+    /// Range of each chunk of `manifest` in the recording, in sequence order
+    pub ranges: Vec<(ChunkId, Range<usize>)>,
+}
+
+/// Cut a finished recording (init segment + muxed chunks, as assembled) into
+/// content-defined chunks
+///
+/// `manifest` is the session's fragment-aligned manifest; the recording must
+/// be exactly its init segment followed by its single-rendition muxed
+/// chunks. Each new chunk takes the timestamp and creation time of the
+/// fragment-aligned chunk its first byte comes from. Cuts do not follow
+/// frames, so a new chunk `has_keyframe` when it holds where a keyframe
+/// chunk starts or the sample of a bookmark's keyframe, the points players
+/// seek to; bookmark keyframe locations are file offsets and stay valid.
+/// Fragments are pointed at the chunk holding their moof. Other chunks
+/// (per-track, renditions) are kept as they are.
+///
+/// Encrypted recordings are refused: their key periods and IVs follow the
+/// fragment-aligned chunk sequences, which content-defined cuts cannot keep.
+pub fn split_content_defined(
+    manifest: &ChunkManifest,
+    recording: &[u8],
+    config: &CdcConfig,
+) -> Result<ContentDefinedSplit, String> {
+    if !manifest.keys.is_empty() {
+        return Err("Encrypted recordings cannot be split by content".to_string());
+    }
+    let source: Vec<&ChunkMetadata> = manifest
+        .chunks_for_track(TrackKind::Muxed)
+        .into_iter()
+        .filter(|chunk| chunk.chunk_id.rendition.is_none())
+        .collect();
+    let muxed_len: u64 = source.iter().map(|chunk| chunk.size).sum();
+    let init_segment_len = (recording.len() as u64)
+        .checked_sub(muxed_len)
+        .ok_or("Recording is shorter than its chunks")? as usize;
+
+    // Start offset in the recording of every source chunk
+    let mut starts = Vec::with_capacity(source.len());
+    let mut offset = init_segment_len as u64;
+    for chunk in &source {
+        starts.push(offset);
+        offset += chunk.size;
+    }
+    let source_at = |offset: u64| source[starts.partition_point(|&start| start <= offset) - 1];
+
+    // Offsets players seek to: keyframe chunk starts and bookmark keyframes
+    let mut keyframes: Vec<u64> = source
+        .iter()
+        .zip(&starts)
+        .filter(|(chunk, _)| chunk.has_keyframe == Some(true))
+        .map(|(_, &start)| start)
+        .chain(
+            manifest
+                .markers
+                .iter()
+                .filter_map(|m| m.bookmark.as_ref()?.keyframe.as_ref())
+                .map(|keyframe| keyframe.sample_offset),
+        )
+        .collect();
+    keyframes.sort_unstable();
+
+    let mut split = manifest.clone();
+    split.chunks.retain(|chunk| {
+        !(chunk.chunk_id.track == TrackKind::Muxed && chunk.chunk_id.rendition.is_none())
+    });
+    let mut ranges = Vec::new();
+    for (sequence, range) in cdc_ranges(&recording[init_segment_len..], config)?
+        .into_iter()
+        .enumerate()
+    {
+        let range = range.start + init_segment_len..range.end + init_segment_len;
+        let from = source_at(range.start as u64);
+        let id = ChunkId::new(
+            manifest.session_id.clone(),
+            TrackKind::Muxed,
+            sequence as u64,
+        )
+        .with_rendition(CDC_RENDITION);
+        let first_keyframe = keyframes.partition_point(|&offset| offset < range.start as u64);
+        let has_keyframe = keyframes
+            .get(first_keyframe)
+            .is_some_and(|&offset| offset < range.end as u64);
+        split.add_chunk(ChunkMetadata {
+            chunk_id: id.clone(),
+            timestamp_us: from.timestamp_us,
+            size: range.len() as u64,
+            hash: Some(blake3::hash(&recording[range.clone()]).to_hex().to_string()),
+            has_keyframe: Some(has_keyframe),
+            created_at: from.created_at,
+        })?;
+        ranges.push((id, range));
+    }
+
+    for fragment in &mut split.fragments {
+        let index = ranges.partition_point(|(_, range)| range.end as u64 <= fragment.offset);
+        fragment.chunk_id = match ranges.get(index) {
+            Some((id, range)) if range.start as u64 <= fragment.offset => id.clone(),
+            _ => {
+                return Err(format!(
+                    "Fragment {} at byte {} is outside the recording's chunks",
+                    fragment.sequence, fragment.offset
+                ))
+            }
+        };
+    }
+    Ok(ContentDefinedSplit {
+        manifest: split,
+        init_segment_len,
+        ranges,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{Bookmark, FragmentRange, KeyPeriod, KeyframeLocation, Marker};
+    use crate::session::SessionId;
+
+    /// Deterministic incompressible bytes
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 33) as u8
+            })
+            .collect()
+    }
+
+    fn config() -> CdcConfig {
+        CdcConfig {
+            min_size: 1024,
+            avg_size: 4096,
+            max_size: 16_384,
+        }
+    }
+
+    #[test]
+    fn test_cut_points_survive_an_insertion() {
+        let data = noise(400_000, 1);
+        let ranges = cdc_ranges(&data, &config()).unwrap();
+        assert_eq!(ranges.first().unwrap().start, 0);
+        assert_eq!(ranges.last().unwrap().end, data.len());
+        assert!(ranges.windows(2).all(|w| w[0].end == w[1].start));
+        assert!(ranges[..ranges.len() - 1]
+            .iter()
+            .all(|r| (1024..=16_384).contains(&r.len())));
+        let average = data.len() / ranges.len();
+        assert!((2_048..8_192).contains(&average), "average {}", average);
+
+        // Bytes inserted near the start only change the chunks around them
+        let mut edited = noise(100, 2);
+        edited.extend_from_slice(&data);
+        let hashes = |data: &[u8]| -> Vec<blake3::Hash> {
+            cdc_ranges(data, &config())
+                .unwrap()
+                .into_iter()
+                .map(|r| blake3::hash(&data[r]))
+                .collect()
+        };
+        let original = hashes(&data);
+        let shared = hashes(&edited)
+            .iter()
+            .filter(|hash| original.contains(hash))
+            .count();
+        assert!(
+            shared + 3 >= original.len(),
+            "{} of {}",
+            shared,
+            original.len()
+        );
+
+        assert!(cdc_ranges(
+            &data,
+            &CdcConfig {
+                min_size: 8,
+                ..config()
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_split_recording() {
+        let session = SessionId::from("s1");
+        let init = b"ftypmoov".to_vec();
+        let mut recording = init.clone();
+        let mut manifest = ChunkManifest::new(session.clone());
+        for sequence in 0..4u64 {
+            let data = noise(30_000, 10 + sequence);
+            manifest
+                .add_chunk(ChunkMetadata {
+                    chunk_id: ChunkId::new(session.clone(), TrackKind::Muxed, sequence),
+                    timestamp_us: sequence * 2_000_000,
+                    size: data.len() as u64,
+                    hash: None,
+                    has_keyframe: Some(sequence != 2),
+                    created_at: 1_000 + sequence,
+                })
+                .unwrap();
+            manifest.fragments.push(FragmentRange {
+                sequence: sequence as u32 + 1,
+                offset: recording.len() as u64,
+                size: data.len() as u64,
+                timestamp_us: sequence * 2_000_000,
+                chunk_id: ChunkId::new(session.clone(), TrackKind::Muxed, sequence),
+            });
+            recording.extend_from_slice(&data);
+        }
+        // A bookmark inside chunk 2, which has no keyframe at its start
+        let bookmarked = (init.len() + 2 * 30_000 + 20_000) as u64;
+        manifest.add_marker(Marker {
+            timestamp_us: 5_000_000,
+            label: None,
+            bookmark: Some(Bookmark {
+                keyframe: Some(KeyframeLocation {
+                    timestamp_us: 5_000_000,
+                    sequence: 3,
+                    fragment_offset: bookmarked - 100,
+                    sample_offset: bookmarked,
+                }),
+            }),
+        });
+        manifest
+            .add_chunk(ChunkMetadata {
+                chunk_id: ChunkId::new(session.clone(), TrackKind::Audio, 0),
+                timestamp_us: 0,
+                size: 10,
+                hash: None,
+                has_keyframe: None,
+                created_at: 1_000,
+            })
+            .unwrap();
+
+        let split = split_content_defined(&manifest, &recording, &config()).unwrap();
+        assert_eq!(split.init_segment_len, init.len());
+        let mut assembled = init.clone();
+        for ((id, range), chunk) in split
+            .ranges
+            .iter()
+            .zip(&split.manifest.chunks_for_track(TrackKind::Muxed))
+        {
+            assert_eq!(&chunk.chunk_id, id);
+            assert_eq!(id.rendition.as_deref(), Some(CDC_RENDITION));
+            let data = &recording[range.clone()];
+            assert_eq!(chunk.hash, Some(blake3::hash(data).to_hex().to_string()));
+            assembled.extend_from_slice(data);
+        }
+        assert_eq!(assembled, recording);
+        assert!(split.ranges.len() > 4);
+        assert!(split
+            .manifest
+            .contains(&ChunkId::new(session.clone(), TrackKind::Audio, 0)));
+        assert!(!split
+            .manifest
+            .contains(&ChunkId::new(session, TrackKind::Muxed, 0)));
+
+        // Timestamps come from the fragment-aligned chunk of the first byte
+        let last = split
+            .manifest
+            .chunks_for_track(TrackKind::Muxed)
+            .pop()
+            .unwrap();
+        assert_eq!((last.timestamp_us, last.created_at), (6_000_000, 1_003));
+
+        // Fragments and keyframes map to the chunks holding them
+        let chunk_holding = |offset: u64| {
+            split
+                .ranges
+                .iter()
+                .find(|(_, range)| range.contains(&(offset as usize)))
+                .and_then(|(id, _)| split.manifest.chunks.iter().find(|c| &c.chunk_id == id))
+                .unwrap()
+        };
+        for fragment in &split.manifest.fragments {
+            assert_eq!(fragment.chunk_id, chunk_holding(fragment.offset).chunk_id);
+        }
+        for (sequence, fragment) in split.manifest.fragments.iter().enumerate() {
+            assert_eq!(
+                chunk_holding(fragment.offset).has_keyframe,
+                Some(sequence != 2)
+            );
+        }
+        assert_eq!(chunk_holding(bookmarked).has_keyframe, Some(true));
+        let keyframe_chunks = split
+            .manifest
+            .chunks_for_track(TrackKind::Muxed)
+            .iter()
+            .filter(|chunk| chunk.has_keyframe == Some(true))
+            .count();
+        assert_eq!(keyframe_chunks, 4);
+
+        assert!(split_content_defined(&manifest, &recording[..100], &config()).is_err());
+        manifest.keys.push(KeyPeriod {
+            first_sequence: 0,
+            key_uri: "key-1".to_string(),
+        });
+        assert!(split_content_defined(&manifest, &recording, &config()).is_err());
+    }
+}
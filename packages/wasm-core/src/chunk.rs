@@ -0,0 +1,427 @@
+//! Chunk addressing for per-track segment streams.
+//!
+//! A chunk is addressed by `(session, track, sequence)` so that video and audio
+//! segments uploaded independently never collide. Simulcast chunks also carry
+//! the name of the rendition they belong to. Older data used a bare sequence
+//! number; those IDs still deserialize and parse as legacy IDs with an empty
+//! session on the muxed track, and serialize back as bare numbers.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::session::SessionId;
+
+/// Which segment stream a chunk belongs to
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Tsify,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackKind {
+    /// Combined video + audio fragments (what `MuxideMuxer` emits today)
+    Muxed,
+    /// Video-only segment stream
+    Video,
+    /// Audio-only segment stream
+    Audio,
+}
+
+impl TrackKind {
+    /// Get the lowercase name used in display strings and storage keys
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrackKind::Muxed => "muxed",
+            TrackKind::Video => "video",
+            TrackKind::Audio => "audio",
+        }
+    }
+}
+
+impl fmt::Display for TrackKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TrackKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "muxed" => Ok(TrackKind::Muxed),
+            "video" => Ok(TrackKind::Video),
+            "audio" => Ok(TrackKind::Audio),
+            _ => Err(format!("Unknown track kind: {}", s)),
+        }
+    }
+}
+
+/// Unique identifier for a chunk within a session
+///
+/// Ordering is by session, then track, then rendition, then sequence number,
+/// so sorting a list of IDs groups each stream's segments in upload order.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(from = "ChunkIdRepr", into = "ChunkIdRepr")]
+pub struct ChunkId {
+    #[serde(rename = "sessionId")]
+    pub session: SessionId,
+    pub track: TrackKind,
+    /// Simulcast rendition name, None for single-rendition recordings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    pub rendition: Option<String>,
+    pub sequence: u64,
+}
+
+impl ChunkId {
+    /// Create a ChunkId for a segment of the given track
+    pub fn new(session: SessionId, track: TrackKind, sequence: u64) -> Self {
+        Self {
+            session,
+            track,
+            rendition: None,
+            sequence,
+        }
+    }
+
+    /// Label the chunk with a simulcast rendition
+    pub fn with_rendition(mut self, rendition: impl Into<String>) -> Self {
+        self.rendition = Some(rendition.into());
+        self
+    }
+
+    /// Create a ChunkId from a bare sequence number (pre-composite format)
+    pub fn legacy(sequence: u64) -> Self {
+        Self::new(SessionId::default(), TrackKind::Muxed, sequence)
+    }
+
+    /// Returns true if this ID carries no session or track information
+    pub fn is_legacy(&self) -> bool {
+        self.session.is_empty() && self.track == TrackKind::Muxed
+    }
+
+    /// Get the ID of the following chunk in the same track
+    pub fn next(&self) -> Self {
+        Self {
+            sequence: self.sequence + 1,
+            ..self.clone()
+        }
+    }
+}
+
+/// Formats as `{session}/{track}/{sequence}`, with the track written as
+/// `{track}:{rendition}` for simulcast chunks, or just `{sequence}` for legacy IDs
+impl fmt::Display for ChunkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.rendition {
+            _ if self.is_legacy() => write!(f, "{}", self.sequence),
+            Some(rendition) => write!(
+                f,
+                "{}/{}:{}/{}",
+                self.session, self.track, rendition, self.sequence
+            ),
+            None => write!(f, "{}/{}/{}", self.session, self.track, self.sequence),
+        }
+    }
+}
+
+impl FromStr for ChunkId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.rsplitn(3, '/');
+        let sequence = parts
+            .next()
+            .unwrap_or_default()
+            .parse::<u64>()
+            .map_err(|e| format!("Invalid chunk sequence in '{}': {}", s, e))?;
+
+        match (parts.next(), parts.next()) {
+            (None, None) => Ok(Self::legacy(sequence)),
+            (Some(track), Some(session)) => {
+                let (track, rendition) = match track.split_once(':') {
+                    Some((track, rendition)) => (track, Some(rendition.to_string())),
+                    None => (track, None),
+                };
+                Ok(Self {
+                    rendition,
+                    ..Self::new(SessionId::from(session), track.parse()?, sequence)
+                })
+            }
+            _ => Err(format!("Invalid chunk ID: {}", s)),
+        }
+    }
+}
+
+/// Serialized forms of a ChunkId; legacy IDs are written back as they were
+/// read, as a bare sequence number
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ChunkIdRepr {
+    /// Bare sequence number written before per-track addressing existed
+    Legacy(u64),
+    Composite {
+        #[serde(rename = "sessionId")]
+        session: SessionId,
+        track: TrackKind,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rendition: Option<String>,
+        sequence: u64,
+    },
+}
+
+impl From<ChunkId> for ChunkIdRepr {
+    fn from(id: ChunkId) -> Self {
+        if id.is_legacy() && id.rendition.is_none() {
+            return ChunkIdRepr::Legacy(id.sequence);
+        }
+        ChunkIdRepr::Composite {
+            session: id.session,
+            track: id.track,
+            rendition: id.rendition,
+            sequence: id.sequence,
+        }
+    }
+}
+
+impl From<ChunkIdRepr> for ChunkId {
+    fn from(repr: ChunkIdRepr) -> Self {
+        match repr {
+            ChunkIdRepr::Legacy(sequence) => ChunkId::legacy(sequence),
+            ChunkIdRepr::Composite {
+                session,
+                track,
+                rendition,
+                sequence,
+            } => ChunkId {
+                session,
+                track,
+                rendition,
+                sequence,
+            },
+        }
+    }
+}
+
+/// Metadata for a single chunk
+///
+/// Mirrors `ChunkMetadata` in `@maycast/common-types`, with the recording ID
+/// carried by the composite `chunkId`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkMetadata {
+    pub chunk_id: ChunkId,
+    /// Timestamp in microseconds from session start
+    #[serde(rename = "timestamp")]
+    pub timestamp_us: u64,
+    /// Size of the chunk in bytes, as stored (encrypted when encryption is
+    /// on)
+    pub size: u64,
+    /// BLAKE3 hash of the chunk data as stored (hex)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Whether this chunk contains a keyframe
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub has_keyframe: Option<bool>,
+    /// Creation timestamp (Unix timestamp ms)
+    pub created_at: u64,
+}
+
+/// `ChunkMetadata` as the web client and server exchange it, with the
+/// recording the chunk belongs to named by `recordingId`
+///
+/// Legacy records carry a bare sequence number as `chunkId`, so their
+/// `recordingId` is the only session ID they have.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkRecord {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    pub recording_id: Option<SessionId>,
+    #[serde(flatten)]
+    pub metadata: ChunkMetadata,
+}
+
+impl ChunkRecord {
+    /// Fill in `recordingId` from a composite chunk ID, rejecting records
+    /// with no session ID at all or two that disagree
+    pub fn normalize(mut self) -> Result<Self, String> {
+        let session = &self.metadata.chunk_id.session;
+        match &self.recording_id {
+            Some(recording) if recording.is_empty() => {
+                return Err("Chunk metadata has an empty recordingId".to_string())
+            }
+            Some(recording) if !session.is_empty() && recording != session => {
+                return Err(format!(
+                    "Chunk {} does not belong to recording {}",
+                    self.metadata.chunk_id, recording
+                ))
+            }
+            Some(_) => {}
+            None if session.is_empty() => {
+                return Err(format!(
+                    "Chunk metadata has no session ID: {}",
+                    self.metadata.chunk_id
+                ))
+            }
+            None => self.recording_id = Some(session.clone()),
+        }
+        Ok(self)
+    }
+}
+
+/// A finished chunk: manifest entry plus the segment bytes (moof + mdat)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedChunk {
+    pub metadata: ChunkMetadata,
+    pub data: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> SessionId {
+        SessionId::from("5f0c7d7e-2a61-4b8e-9d55-0c8f1c1e2b3a")
+    }
+
+    #[test]
+    fn test_chunk_id_ordering() {
+        let mut ids = [
+            ChunkId::new(session(), TrackKind::Audio, 1),
+            ChunkId::new(session(), TrackKind::Video, 2),
+            ChunkId::new(session(), TrackKind::Audio, 0),
+            ChunkId::new(session(), TrackKind::Video, 10),
+        ];
+        ids.sort();
+
+        let order: Vec<(TrackKind, u64)> = ids.iter().map(|id| (id.track, id.sequence)).collect();
+        assert_eq!(
+            order,
+            vec![
+                (TrackKind::Video, 2),
+                (TrackKind::Video, 10),
+                (TrackKind::Audio, 0),
+                (TrackKind::Audio, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_same_sequence_on_different_tracks_is_distinct() {
+        let video = ChunkId::new(session(), TrackKind::Video, 3);
+        let audio = ChunkId::new(session(), TrackKind::Audio, 3);
+        assert_ne!(video, audio);
+        assert_ne!(video.to_string(), audio.to_string());
+    }
+
+    #[test]
+    fn test_display_and_parse_roundtrip() {
+        let id = ChunkId::new(session(), TrackKind::Video, 42);
+        let s = id.to_string();
+        assert_eq!(s, "5f0c7d7e-2a61-4b8e-9d55-0c8f1c1e2b3a/video/42");
+        assert_eq!(s.parse::<ChunkId>().unwrap(), id);
+
+        let legacy = ChunkId::legacy(7);
+        assert_eq!(legacy.to_string(), "7");
+        assert_eq!("7".parse::<ChunkId>().unwrap(), legacy);
+
+        let simulcast = ChunkId::new(session(), TrackKind::Muxed, 3).with_rendition("360p");
+        let s = simulcast.to_string();
+        assert_eq!(s, "5f0c7d7e-2a61-4b8e-9d55-0c8f1c1e2b3a/muxed:360p/3");
+        assert_eq!(s.parse::<ChunkId>().unwrap(), simulcast);
+        assert_eq!(simulcast.next().rendition.as_deref(), Some("360p"));
+
+        assert!("abc/video".parse::<ChunkId>().is_err());
+        assert!("abc/subtitle/1".parse::<ChunkId>().is_err());
+    }
+
+    #[test]
+    fn test_serde_composite_and_legacy() {
+        let id = ChunkId::new(session(), TrackKind::Audio, 5);
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(
+            json,
+            r#"{"sessionId":"5f0c7d7e-2a61-4b8e-9d55-0c8f1c1e2b3a","track":"audio","sequence":5}"#
+        );
+        assert_eq!(serde_json::from_str::<ChunkId>(&json).unwrap(), id);
+
+        let simulcast = id.with_rendition("1080p");
+        let json = serde_json::to_string(&simulcast).unwrap();
+        assert!(json.contains(r#""rendition":"1080p""#));
+        assert_eq!(serde_json::from_str::<ChunkId>(&json).unwrap(), simulcast);
+
+        // Chunk IDs persisted before per-track streams were bare numbers
+        let legacy: ChunkId = serde_json::from_str("12").unwrap();
+        assert!(legacy.is_legacy());
+        assert_eq!(legacy.sequence, 12);
+        assert_eq!(serde_json::to_string(&legacy).unwrap(), "12");
+    }
+
+    #[test]
+    fn test_chunk_metadata_serde() {
+        let metadata = ChunkMetadata {
+            chunk_id: ChunkId::new(session(), TrackKind::Muxed, 1),
+            timestamp_us: 2_000_000,
+            size: 1024,
+            hash: None,
+            has_keyframe: Some(true),
+            created_at: 1_700_000_000_000,
+        };
+        let json = serde_json::to_value(&metadata).unwrap();
+        assert_eq!(json["timestamp"], 2_000_000);
+        assert_eq!(json["hasKeyframe"], true);
+        assert!(json.get("hash").is_none());
+
+        let parsed: ChunkMetadata = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, metadata);
+    }
+
+    #[test]
+    fn test_chunk_record_normalize() {
+        let legacy = serde_json::json!({
+            "recordingId": "rec-1",
+            "chunkId": 3,
+            "timestamp": 0,
+            "size": 10,
+            "createdAt": 1,
+        });
+        let record: ChunkRecord = serde_json::from_value(legacy.clone()).unwrap();
+        let record = record.normalize().unwrap();
+        assert_eq!(record.recording_id, Some(SessionId::from("rec-1")));
+        assert_eq!(
+            serde_json::to_value(&record).unwrap()["recordingId"],
+            "rec-1"
+        );
+
+        let mut anonymous = legacy.clone();
+        anonymous.as_object_mut().unwrap().remove("recordingId");
+        let record: ChunkRecord = serde_json::from_value(anonymous).unwrap();
+        assert!(record.normalize().is_err());
+
+        // A composite ID names the recording itself, which must agree
+        let metadata = ChunkMetadata {
+            chunk_id: ChunkId::new(session(), TrackKind::Muxed, 1),
+            timestamp_us: 0,
+            size: 10,
+            hash: None,
+            has_keyframe: None,
+            created_at: 1,
+        };
+        let record = ChunkRecord {
+            recording_id: None,
+            metadata: metadata.clone(),
+        };
+        assert_eq!(record.normalize().unwrap().recording_id, Some(session()));
+        let record = ChunkRecord {
+            recording_id: Some(SessionId::from("rec-2")),
+            metadata,
+        };
+        assert!(record.normalize().is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//! Wall-clock access that works both in the browser and in native tests.
+//!
+//! Components that read the time on their own (chunk creation times, the
+//! stall watchdog, upload timings) hold a `ClockHandle`, the system clock
+//! unless a test sets a `ManualClock` that only moves when told to. Code that
+//! is handed the time as an argument, such as `collect_garbage` or the
+//! `*_at` methods, needs no clock.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Current Unix time in milliseconds
+#[cfg(target_arch = "wasm32")]
+pub fn now_ms() -> u64 {
+    js_sys::Date::now() as u64
+}
+
+/// Current Unix time in milliseconds
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Source of wall-clock time
+pub trait Clock {
+    /// Current Unix time in milliseconds
+    fn now_ms(&self) -> u64;
+}
+
+/// The real wall clock (`now_ms`)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        now_ms()
+    }
+}
+
+/// A clock that stands still until set or advanced
+///
+/// Clones share the time, so a test keeps one to move the clock of the
+/// component it handed the other to.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now_ms: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Create a clock reading `now_ms`
+    pub fn new(now_ms: u64) -> Self {
+        Self {
+            now_ms: Arc::new(AtomicU64::new(now_ms)),
+        }
+    }
+
+    /// Set the time to `now_ms`
+    pub fn set(&self, now_ms: u64) {
+        self.now_ms.store(now_ms, Ordering::Relaxed);
+    }
+
+    /// Move the time forward by `ms`
+    pub fn advance(&self, ms: u64) {
+        self.now_ms.fetch_add(ms, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_ms(&self) -> u64 {
+        self.now_ms.load(Ordering::Relaxed)
+    }
+}
+
+/// Shared handle to the clock of a component, the system clock by default
+///
+/// Handles are equal when they share the same clock; every default handle
+/// shares one system clock.
+#[derive(Clone)]
+pub struct ClockHandle(Arc<dyn Clock + Send + Sync>);
+
+impl ClockHandle {
+    /// Share `clock`
+    pub fn new(clock: impl Clock + Send + Sync + 'static) -> Self {
+        Self(Arc::new(clock))
+    }
+
+    /// Current Unix time in milliseconds
+    pub fn now_ms(&self) -> u64 {
+        self.0.now_ms()
+    }
+}
+
+impl Default for ClockHandle {
+    fn default() -> Self {
+        static SYSTEM: OnceLock<ClockHandle> = OnceLock::new();
+        SYSTEM.get_or_init(|| Self::new(SystemClock)).clone()
+    }
+}
+
+impl fmt::Debug for ClockHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ClockHandle").field(&self.now_ms()).finish()
+    }
+}
+
+impl PartialEq for ClockHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ClockHandle {}
@@ -0,0 +1,467 @@
+//! QuickTime compatibility checks for media segments.
+//!
+//! Other players forgive fragments that QuickTime and Safari do not: a tfdt
+//! that does not continue where the previous fragment of the track ended
+//! shows up as a gap or a freeze, a trun data offset outside the mdat makes
+//! the fragment unplayable, and a fragment whose first sample claims to be a
+//! sync sample but is not an IDR frame decodes as garbage until the next
+//! real keyframe.
+//!
+//! `CompatChecker` verifies these rules segment by segment. The muxer runs it
+//! in debug builds, and in release builds when `MuxideConfig.compatChecks` is
+//! set.
+//!
+//! Media segments are only playable behind an init segment describing the
+//! same tracks. `compare_init_segments` tells whether segments muxed for one
+//! init segment can be played (or concatenated) behind another: same track
+//! IDs, handlers, timescales and sample entries (codec and its
+//! configuration, e.g. SPS/PPS).
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::muxide_muxer::{
+    find_box, parse_boxes, read_trun, read_u32, read_u64, MuxideConfig, MuxideMuxerState,
+};
+
+/// A compatibility rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompatRule {
+    /// tfdt equals the previous tfdt plus the previous trun durations
+    TfdtContinuity,
+    /// trun data offsets point inside the mdat that follows the moof
+    DataOffset,
+    /// The first sample of a fragment flagged as sync is an IDR frame
+    KeyframeIsIdr,
+}
+
+impl CompatRule {
+    /// Whether breaking the rule is a muxer bug rather than bad input
+    pub fn is_muxer_bug(self) -> bool {
+        !matches!(self, CompatRule::KeyframeIsIdr)
+    }
+}
+
+/// A broken rule, with what to look at to fix it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatViolation {
+    pub rule: CompatRule,
+    pub message: String,
+}
+
+impl fmt::Display for CompatViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Checks media segments of one stream, in order
+#[derive(Debug, Clone, Default)]
+pub struct CompatChecker {
+    video_track_id: Option<u32>,
+    /// Decode time where each track's next fragment must start; the first
+    /// fragment seen of a track sets it
+    next_decode_time: BTreeMap<u32, u64>,
+}
+
+impl CompatChecker {
+    /// Create a checker; `video_track_id` enables the IDR check on that track
+    pub fn new(video_track_id: Option<u32>) -> Self {
+        Self {
+            video_track_id,
+            next_decode_time: BTreeMap::new(),
+        }
+    }
+
+    /// Check the next media segment (one or more moof + mdat pairs)
+    ///
+    /// The IDR check is skipped when the mdat payload is missing, as in
+    /// dry-run segments.
+    pub fn check_segment(&mut self, segment: &[u8]) -> Result<Vec<CompatViolation>, String> {
+        let mut violations = Vec::new();
+        let mut pos = 0;
+        let mut moof: Option<(usize, &[u8])> = None;
+        while pos < segment.len() {
+            let size = read_u32(segment, pos)? as usize;
+            if size < 8 {
+                return Err(format!("Invalid box size {} at offset {}", size, pos));
+            }
+            let start = pos;
+            pos += size;
+            match segment.get(start + 4..start + 8) {
+                Some(b"moof") => {
+                    let payload = segment.get(start + 8..pos).ok_or("Truncated moof box")?;
+                    moof = Some((start, payload));
+                }
+                Some(b"mdat") => {
+                    let (moof_start, payload) = moof
+                        .take()
+                        .ok_or_else(|| format!("mdat at offset {} has no moof", start))?;
+                    self.check_fragment(
+                        segment,
+                        moof_start,
+                        payload,
+                        (start + 8 - moof_start, pos - moof_start),
+                        &mut violations,
+                    )?;
+                }
+                _ => {}
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Check one moof whose mdat payload spans `mdat` (moof-relative)
+    fn check_fragment(
+        &mut self,
+        segment: &[u8],
+        moof_start: usize,
+        moof: &[u8],
+        mdat: (usize, usize),
+        violations: &mut Vec<CompatViolation>,
+    ) -> Result<(), String> {
+        let mfhd = find_box(moof, b"mfhd")?.ok_or("moof has no mfhd box")?;
+        let sequence = read_u32(mfhd.payload, 4)?;
+        for traf in parse_boxes(moof)?.iter().filter(|b| &b.typ == b"traf") {
+            let tfhd = find_box(traf.payload, b"tfhd")?.ok_or("traf has no tfhd box")?;
+            let tfdt = find_box(traf.payload, b"tfdt")?.ok_or("traf has no tfdt box")?;
+            let trun = find_box(traf.payload, b"trun")?.ok_or("traf has no trun box")?;
+            let track_id = read_u32(tfhd.payload, 4)?;
+            let decode_time = if tfdt.payload.first() == Some(&1) {
+                read_u64(tfdt.payload, 4)?
+            } else {
+                read_u32(tfdt.payload, 4)? as u64
+            };
+            let run = read_trun(trun.payload)?;
+            let duration: u64 = run.entries.iter().map(|e| e.duration as u64).sum();
+            let bytes: usize = run.entries.iter().map(|e| e.size as usize).sum();
+
+            if let Some(&expected) = self.next_decode_time.get(&track_id) {
+                if decode_time != expected {
+                    violations.push(CompatViolation {
+                        rule: CompatRule::TfdtContinuity,
+                        message: format!(
+                            "Fragment #{} track {}: tfdt {} should be {} (previous tfdt plus its \
+                             trun durations); QuickTime shows a gap or freeze here. Sample \
+                             durations must add up to the next fragment's base decode time",
+                            sequence, track_id, decode_time, expected
+                        ),
+                    });
+                }
+            }
+            self.next_decode_time
+                .insert(track_id, decode_time + duration);
+
+            let offset = run.data_offset as usize;
+            if offset < mdat.0 || offset + bytes > mdat.1 {
+                violations.push(CompatViolation {
+                    rule: CompatRule::DataOffset,
+                    message: format!(
+                        "Fragment #{} track {}: samples at moof offset {}..{} are outside the \
+                         mdat payload {}..{}; QuickTime rejects the fragment. The trun data \
+                         offset must be patched after the moof size is known",
+                        sequence,
+                        track_id,
+                        offset,
+                        offset + bytes,
+                        mdat.0,
+                        mdat.1
+                    ),
+                });
+                continue;
+            }
+
+            let first = match run.entries.first() {
+                Some(first) if Some(track_id) == self.video_track_id => first,
+                _ => continue,
+            };
+            if first.flags & 0x0001_0000 != 0 {
+                continue;
+            }
+            let start = moof_start + offset;
+            let Some(sample) = segment.get(start..start + first.size as usize) else {
+                continue;
+            };
+            if !has_idr(sample) {
+                violations.push(CompatViolation {
+                    rule: CompatRule::KeyframeIsIdr,
+                    message: format!(
+                        "Fragment #{} track {}: first sample is flagged as a sync sample but \
+                         has no IDR NAL unit; QuickTime shows garbage until the next keyframe. \
+                         Only mark frames the encoder reports as key (EncodedVideoChunk.type \
+                         == \"key\")",
+                        sequence, track_id
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Result of comparing two init segments
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct InitCompatibility {
+    /// Whether media segments of one play behind the other
+    pub compatible: bool,
+    /// What differs, one entry per mismatch
+    pub differences: Vec<String>,
+}
+
+impl InitCompatibility {
+    /// Fail with every difference listed unless compatible
+    pub fn ensure(self) -> Result<(), String> {
+        if self.compatible {
+            return Ok(());
+        }
+        Err(format!(
+            "Incompatible init segments: {}",
+            self.differences.join("; ")
+        ))
+    }
+}
+
+/// What media segments depend on in one trak of an init segment
+pub(crate) struct InitTrack {
+    pub(crate) handler: [u8; 4],
+    pub(crate) timescale: u32,
+    /// First stsd sample entry, codec configuration included
+    sample_entry: Vec<u8>,
+}
+
+/// Compare the tracks two init segments declare
+pub fn compare_init_segments(a: &[u8], b: &[u8]) -> Result<InitCompatibility, String> {
+    let a = read_init_tracks(a)?;
+    let b = read_init_tracks(b)?;
+    let mut differences = Vec::new();
+    for (track_id, track) in &a {
+        let Some(other) = b.get(track_id) else {
+            differences.push(format!("track {} is missing from the second", track_id));
+            continue;
+        };
+        let handler = |h: &[u8; 4]| String::from_utf8_lossy(h).into_owned();
+        if track.handler != other.handler {
+            differences.push(format!(
+                "track {} handler {} vs {}",
+                track_id,
+                handler(&track.handler),
+                handler(&other.handler)
+            ));
+            continue;
+        }
+        if track.timescale != other.timescale {
+            differences.push(format!(
+                "track {} timescale {} vs {}",
+                track_id, track.timescale, other.timescale
+            ));
+        }
+        let codec = |entry: &[u8]| {
+            entry
+                .get(4..8)
+                .map(|c| String::from_utf8_lossy(c).into_owned())
+                .unwrap_or_default()
+        };
+        if codec(&track.sample_entry) != codec(&other.sample_entry) {
+            differences.push(format!(
+                "track {} codec {} vs {}",
+                track_id,
+                codec(&track.sample_entry),
+                codec(&other.sample_entry)
+            ));
+        } else if track.sample_entry != other.sample_entry {
+            differences.push(format!(
+                "track {} {} configuration differs",
+                track_id,
+                codec(&track.sample_entry)
+            ));
+        }
+    }
+    for track_id in b.keys().filter(|id| !a.contains_key(id)) {
+        differences.push(format!("track {} is missing from the first", track_id));
+    }
+    Ok(InitCompatibility {
+        compatible: differences.is_empty(),
+        differences,
+    })
+}
+
+/// Compare the init segment a muxer would write for `config` with `init_segment`
+pub fn check_config_against_init(
+    config: &MuxideConfig,
+    init_segment: &[u8],
+) -> Result<InitCompatibility, String> {
+    let mut muxer = MuxideMuxerState::new(config.clone());
+    muxer.init()?;
+    compare_init_segments(&muxer.get_init_segment()?, init_segment)
+}
+
+pub(crate) fn read_init_tracks(init_segment: &[u8]) -> Result<BTreeMap<u32, InitTrack>, String> {
+    let moov = find_box(init_segment, b"moov")?.ok_or("Init segment has no moov box")?;
+    let mut tracks = BTreeMap::new();
+    for trak in parse_boxes(moov.payload)?
+        .into_iter()
+        .filter(|b| &b.typ == b"trak")
+    {
+        let tkhd = find_box(trak.payload, b"tkhd")?.ok_or("trak has no tkhd box")?;
+        let id_offset = if tkhd.payload.first() == Some(&1) {
+            20
+        } else {
+            12
+        };
+        let mdia = find_box(trak.payload, b"mdia")?.ok_or("trak has no mdia box")?;
+        let mdhd = find_box(mdia.payload, b"mdhd")?.ok_or("mdia has no mdhd box")?;
+        let timescale_offset = if mdhd.payload.first() == Some(&1) {
+            20
+        } else {
+            12
+        };
+        let hdlr = find_box(mdia.payload, b"hdlr")?.ok_or("mdia has no hdlr box")?;
+        let minf = find_box(mdia.payload, b"minf")?.ok_or("mdia has no minf box")?;
+        let stbl = find_box(minf.payload, b"stbl")?.ok_or("minf has no stbl box")?;
+        let stsd = find_box(stbl.payload, b"stsd")?.ok_or("stbl has no stsd box")?;
+        let entry_size = read_u32(stsd.payload, 8)? as usize;
+        tracks.insert(
+            read_u32(tkhd.payload, id_offset)?,
+            InitTrack {
+                handler: hdlr
+                    .payload
+                    .get(8..12)
+                    .and_then(|h| h.try_into().ok())
+                    .ok_or("Truncated hdlr box")?,
+                timescale: read_u32(mdhd.payload, timescale_offset)?,
+                sample_entry: stsd
+                    .payload
+                    .get(8..8 + entry_size)
+                    .ok_or("Truncated stsd box")?
+                    .to_vec(),
+            },
+        );
+    }
+    Ok(tracks)
+}
+
+/// Whether AVCC sample data holds an IDR slice (NAL type 5)
+fn has_idr(sample: &[u8]) -> bool {
+    let mut pos = 0;
+    while let Ok(len) = read_u32(sample, pos) {
+        if sample.get(pos + 4).map(|b| b & 0x1F) == Some(5) {
+            return true;
+        }
+        match (len as usize)
+            .checked_add(4)
+            .and_then(|step| pos.checked_add(step))
+        {
+            Some(next) if next < sample.len() => pos = next,
+            _ => break,
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_broken_fragments() {
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        });
+        muxer.init().unwrap();
+        for i in 0..90u64 {
+            // Flagged as a keyframe every second, but never an IDR frame
+            muxer
+                .push_video_chunk(&[0, 0, 0, 2, 0x41, 1], i * 33_333, i % 30 == 0)
+                .unwrap();
+            muxer.push_audio_chunk(&[0; 8], i * 21_333, 21_333).unwrap();
+        }
+        muxer.force_flush().unwrap();
+        let segments = muxer.get_pending_segments();
+        assert_eq!(segments.len(), 3);
+
+        let mut checker = CompatChecker::new(Some(1));
+        let violations = checker.check_segment(&segments[0]).unwrap();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].rule, CompatRule::KeyframeIsIdr);
+        assert!(!violations[0].rule.is_muxer_bug());
+        // Skipping a segment breaks continuity on both tracks
+        let violations = checker.check_segment(&segments[2]).unwrap();
+        let rules: Vec<CompatRule> = violations.iter().map(|v| v.rule).collect();
+        assert_eq!(
+            rules,
+            vec![CompatRule::TfdtContinuity, CompatRule::TfdtContinuity]
+        );
+        assert!(violations[0]
+            .message
+            .contains("track 1: tfdt 191998 should be 95999"));
+
+        // A data offset past the mdat
+        let mut checker = CompatChecker::new(Some(1));
+        let mut segment = segments[1].clone();
+        let trun = segment.windows(4).position(|w| w == b"trun").unwrap();
+        let data_offset = read_u32(&segment, trun + 12).unwrap() + 1000;
+        segment[trun + 12..trun + 16].copy_from_slice(&data_offset.to_be_bytes());
+        let violations = checker.check_segment(&segment).unwrap();
+        assert_eq!(violations[0].rule, CompatRule::DataOffset);
+        assert!(violations.iter().all(|v| v.rule.is_muxer_bug()));
+
+        // NAL lengths running past the sample end the IDR search
+        assert!(has_idr(&[0, 0, 0, 1, 0x41, 0, 0, 0, 1, 0x65]));
+        assert!(!has_idr(&[0, 0, 0, 9, 0x41, 0, 0, 0, 1, 0x65]));
+        assert!(!has_idr(&[0xFF, 0xFF, 0xFF, 0xFF, 0x41]));
+    }
+
+    #[test]
+    fn test_compare_init_segments() {
+        let config = MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            ..Default::default()
+        };
+        let mut muxer = MuxideMuxerState::new(config.clone());
+        muxer.init().unwrap();
+        let init = muxer.get_init_segment().unwrap();
+        assert!(
+            check_config_against_init(&config, &init)
+                .unwrap()
+                .compatible
+        );
+
+        let other = MuxideConfig {
+            sps: Some(vec![0x67, 0x64, 0x00, 0x28]),
+            audio_timescale: Some(90000),
+            ..config.clone()
+        };
+        let result = check_config_against_init(&other, &init).unwrap();
+        assert_eq!(
+            result.differences,
+            vec![
+                "track 1 avc1 configuration differs",
+                "track 2 timescale 90000 vs 48000"
+            ]
+        );
+        let audio_only = MuxideConfig {
+            sps: None,
+            pps: None,
+            ..config
+        };
+        let error = check_config_against_init(&audio_only, &init)
+            .unwrap()
+            .ensure()
+            .unwrap_err();
+        assert!(error.contains("track 1 handler soun vs vide"));
+        assert!(error.contains("track 2 is missing from the first"));
+    }
+}
@@ -0,0 +1,193 @@
+//! Compression of session metadata.
+//!
+//! Manifests of long sessions list thousands of chunks and are rewritten
+//! with every one of them, and write-ahead logs repeat the same record
+//! headers; both shrink about 10x. They are stored as gzip (RFC 1952): raw
+//! deflate from `miniz_oxide`, which builds for WASM without C code, in a
+//! gzip member. Readers detect the gzip magic bytes, so plain files written
+//! before compression stay readable, and gzip lets the server's JSON body
+//! parser inflate uploads sent with `Content-Encoding: gzip` on its own.
+
+use std::borrow::Cow;
+
+/// First two bytes of every gzip member
+pub const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// Deflate level for files written now and then, such as manifests
+pub const DEFAULT_COMPRESSION_LEVEL: u8 = 6;
+
+/// Deflate level for files written while recording, such as WAL batches
+pub const FAST_COMPRESSION_LEVEL: u8 = 1;
+
+/// Largest output `decompress` produces, against corrupt size fields and
+/// decompression bombs
+pub const MAX_DECOMPRESSED_SIZE: usize = 256 * 1024 * 1024;
+
+const HEADER_LEN: usize = 10;
+const TRAILER_LEN: usize = 8;
+/// Compression method byte for deflate
+const CM_DEFLATE: u8 = 8;
+const FLAG_HCRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+/// Operating system byte: unknown
+const OS_UNKNOWN: u8 = 255;
+
+/// Compress `data` into a gzip member at deflate `level` (0-10)
+pub fn compress(data: &[u8], level: u8) -> Vec<u8> {
+    let deflated = miniz_oxide::deflate::compress_to_vec(data, level);
+    let mut out = Vec::with_capacity(HEADER_LEN + deflated.len() + TRAILER_LEN);
+    out.extend_from_slice(&GZIP_MAGIC);
+    out.push(CM_DEFLATE);
+    out.push(0); // Flags
+    out.extend_from_slice(&0u32.to_le_bytes()); // No modification time
+    out.push(0); // Extra flags
+    out.push(OS_UNKNOWN);
+    out.extend_from_slice(&deflated);
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Compress `data` unless that does not make it smaller
+///
+/// Only for formats whose plain form never starts with `GZIP_MAGIC` (JSON,
+/// WAL records), so readers can still tell the two apart.
+pub fn compress_if_smaller(data: &[u8], level: u8) -> Cow<'_, [u8]> {
+    let compressed = compress(data, level);
+    if compressed.len() < data.len() {
+        Cow::Owned(compressed)
+    } else {
+        Cow::Borrowed(data)
+    }
+}
+
+/// Whether `data` starts like a gzip member
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN + TRAILER_LEN && data[..2] == GZIP_MAGIC && data[2] == CM_DEFLATE
+}
+
+/// Decompress a gzip member, checking its CRC and length
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if !is_compressed(data) {
+        return Err("Not gzip data".to_string());
+    }
+    let flags = data[3];
+    let mut pos = HEADER_LEN;
+    if flags & FLAG_EXTRA != 0 {
+        let len = data
+            .get(pos..pos + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or("Truncated gzip header")?;
+        pos += 2 + len;
+    }
+    for flag in [FLAG_NAME, FLAG_COMMENT] {
+        if flags & flag != 0 {
+            let end = data
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or("Truncated gzip header")?;
+            pos += end + 1;
+        }
+    }
+    if flags & FLAG_HCRC != 0 {
+        pos += 2;
+    }
+    let trailer = data.len() - TRAILER_LEN;
+    if pos > trailer {
+        return Err("Truncated gzip header".to_string());
+    }
+
+    let out = miniz_oxide::inflate::decompress_to_vec_with_limit(
+        &data[pos..trailer],
+        MAX_DECOMPRESSED_SIZE,
+    )
+    .map_err(|e| format!("Corrupt gzip data: {:?}", e.status))?;
+    let crc = u32::from_le_bytes(data[trailer..trailer + 4].try_into().unwrap());
+    let size = u32::from_le_bytes(data[trailer + 4..].try_into().unwrap());
+    if crc32(&out) != crc || out.len() as u32 != size {
+        return Err("gzip data does not match its checksum".to_string());
+    }
+    Ok(out)
+}
+
+/// Decompress gzip data and pass anything else through unchanged
+pub fn decompress_if_compressed(data: &[u8]) -> Result<Cow<'_, [u8]>, String> {
+    if is_compressed(data) {
+        decompress(data).map(Cow::Owned)
+    } else {
+        Ok(Cow::Borrowed(data))
+    }
+}
+
+/// CRC-32 (IEEE, reflected) as used by gzip
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    0xEDB8_8320 ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0u32, |crc, &b| {
+        TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gzip_round_trip() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let json = br#"{"chunkId":"s1/muxed/0","timestamp":0,"size":1024}"#.repeat(200);
+        let compressed = compress(&json, DEFAULT_COMPRESSION_LEVEL);
+        assert!(is_compressed(&compressed));
+        assert!(compressed.len() * 10 < json.len());
+        assert_eq!(decompress(&compressed).unwrap(), json);
+        assert_eq!(decompress_if_compressed(&compressed).unwrap(), json);
+        // Plain data passes through; incompressible data stays plain
+        assert!(matches!(
+            decompress_if_compressed(&json).unwrap(),
+            Cow::Borrowed(_)
+        ));
+        assert!(matches!(
+            compress_if_smaller(b"{}", FAST_COMPRESSION_LEVEL),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(
+            decompress(&compress(&[], FAST_COMPRESSION_LEVEL)).unwrap(),
+            b""
+        );
+
+        // A member with a file name, as written by the gzip tool
+        let mut named = compressed[..3].to_vec();
+        named.push(FLAG_NAME);
+        named.extend_from_slice(&compressed[4..HEADER_LEN]);
+        named.extend_from_slice(b"manifest.json\0");
+        named.extend_from_slice(&compressed[HEADER_LEN..]);
+        assert_eq!(decompress(&named).unwrap(), json);
+
+        let mut corrupt = compressed.clone();
+        let len = corrupt.len();
+        corrupt[len - 5] ^= 0xFF;
+        assert!(decompress(&corrupt).is_err());
+        assert!(decompress(&compressed[..compressed.len() - 20]).is_err());
+        assert!(decompress(b"{}").is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! mfhd sequence continuity of an assembled stream.
+//!
+//! Every moof carries a sequence number in its mfhd, and players expect it
+//! to increase through the file: some treat a number that repeats or goes
+//! back as a discontinuity and flush their buffers or stop. One muxer run
+//! numbers its fragments 1, 2, 3..., but a stream stitched together from
+//! several runs (a session continued by a fresh muxer after its snapshot was
+//! lost, recordings merged after the fact) starts over with each of them.
+//!
+//! `SequenceContinuity` walks the segments of a stream in file order. It
+//! records every fragment whose sequence number does not increase, and
+//! `renumber` rewrites the numbers in place to run on from a first one.
+//! Only the mfhd changes, so byte offsets (and a range map) stay valid.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::muxide_muxer::{parse_boxes, read_u32};
+
+/// A fragment whose mfhd sequence number does not increase
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct SequenceBreak {
+    /// Position of the fragment in the stream, from 0
+    pub fragment_index: u64,
+    /// Sequence number of the fragment before it
+    pub previous: u32,
+    /// Its own sequence number, at most `previous`
+    pub sequence: u32,
+}
+
+/// Validates and renumbers the mfhd sequence numbers of one stream, in order
+#[derive(Debug, Clone)]
+pub struct SequenceContinuity {
+    /// Number `renumber` gives the next fragment
+    next_sequence: u32,
+    fragment_count: u64,
+    /// Original sequence number of the last fragment seen
+    previous: Option<u32>,
+    breaks: Vec<SequenceBreak>,
+}
+
+impl Default for SequenceContinuity {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl SequenceContinuity {
+    /// Start a stream whose first fragment `renumber` numbers `first_sequence`
+    pub fn new(first_sequence: u32) -> Self {
+        Self {
+            next_sequence: first_sequence,
+            fragment_count: 0,
+            previous: None,
+            breaks: Vec::new(),
+        }
+    }
+
+    /// Check the next segment, returning the breaks found in it
+    pub fn check(&mut self, segment: &[u8]) -> Result<Vec<SequenceBreak>, String> {
+        let known = self.breaks.len();
+        for pos in mfhd_sequences(segment)? {
+            self.note(read_u32(segment, pos)?);
+        }
+        Ok(self.breaks[known..].to_vec())
+    }
+
+    /// Check the next segment and rewrite its sequence numbers to continue
+    /// the numbering
+    pub fn renumber(&mut self, segment: &[u8]) -> Result<Vec<u8>, String> {
+        let mut data = segment.to_vec();
+        for pos in mfhd_sequences(segment)? {
+            self.note(read_u32(segment, pos)?);
+            data[pos..pos + 4].copy_from_slice(&self.next_sequence.to_be_bytes());
+            self.next_sequence = self
+                .next_sequence
+                .checked_add(1)
+                .ok_or("Fragment sequence numbers exhausted")?;
+        }
+        Ok(data)
+    }
+
+    /// Sequence number `renumber` gives the next fragment
+    pub fn next_sequence(&self) -> u32 {
+        self.next_sequence
+    }
+
+    /// Fragments seen so far
+    pub fn fragment_count(&self) -> u64 {
+        self.fragment_count
+    }
+
+    /// Every break found so far, in stream order
+    pub fn breaks(&self) -> &[SequenceBreak] {
+        &self.breaks
+    }
+
+    /// Whether the original sequence numbers increased throughout
+    pub fn is_continuous(&self) -> bool {
+        self.breaks.is_empty()
+    }
+
+    fn note(&mut self, sequence: u32) {
+        if let Some(previous) = self.previous.filter(|&previous| sequence <= previous) {
+            self.breaks.push(SequenceBreak {
+                fragment_index: self.fragment_count,
+                previous,
+                sequence,
+            });
+        }
+        self.previous = Some(sequence);
+        self.fragment_count += 1;
+    }
+}
+
+/// Breaks in the mfhd sequence numbers of a stream's segments, in file order
+pub fn validate_sequences<'a>(
+    segments: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<Vec<SequenceBreak>, String> {
+    let mut continuity = SequenceContinuity::default();
+    for segment in segments {
+        continuity.check(segment)?;
+    }
+    Ok(continuity.breaks)
+}
+
+/// Offsets of the sequence number of every mfhd among top-level moofs
+fn mfhd_sequences(segment: &[u8]) -> Result<Vec<usize>, String> {
+    let mut positions = Vec::new();
+    for moof in parse_boxes(segment)?.iter().filter(|b| &b.typ == b"moof") {
+        let mfhd = parse_boxes(moof.payload)?
+            .into_iter()
+            .find(|b| &b.typ == b"mfhd")
+            .ok_or("moof has no mfhd box")?;
+        read_u32(mfhd.payload, 4)?;
+        // Version and flags precede the sequence number
+        positions.push(moof.offset + 8 + mfhd.offset + 8 + 4);
+    }
+    Ok(positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::muxide_muxer::{trace_segment, MuxideConfig, MuxideMuxerState};
+
+    /// Segments of a fresh muxer run: 3 s of 30fps video
+    fn run() -> Vec<Vec<u8>> {
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        });
+        muxer.init().unwrap();
+        for i in 0..90u64 {
+            muxer
+                .push_video_chunk(&[0, 0, 0, 1, 0x65], i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+        muxer.force_flush().unwrap();
+        muxer.get_pending_segments()
+    }
+
+    #[test]
+    fn test_renumbers_restarted_sequences() {
+        let first = run();
+        let restarted = run();
+        let segments: Vec<&[u8]> = first.iter().chain(&restarted).map(Vec::as_slice).collect();
+        assert!(validate_sequences(first.iter().map(Vec::as_slice))
+            .unwrap()
+            .is_empty());
+        let breaks = validate_sequences(segments.iter().copied()).unwrap();
+        assert_eq!(
+            breaks,
+            vec![SequenceBreak {
+                fragment_index: 3,
+                previous: 3,
+                sequence: 1,
+            }]
+        );
+
+        let mut continuity = SequenceContinuity::default();
+        let mut renumbered = Vec::new();
+        for segment in &segments {
+            let data = continuity.renumber(segment).unwrap();
+            assert_eq!(data.len(), segment.len());
+            renumbered.extend(data);
+        }
+        assert!(!continuity.is_continuous());
+        assert_eq!(continuity.fragment_count(), 6);
+        assert_eq!(continuity.next_sequence(), 7);
+        let sequences: Vec<u32> = trace_segment(&renumbered)
+            .unwrap()
+            .iter()
+            .map(|t| t.sequence)
+            .collect();
+        assert_eq!(sequences, vec![1, 2, 3, 4, 5, 6]);
+        assert!(validate_sequences([renumbered.as_slice()])
+            .unwrap()
+            .is_empty());
+
+        // Already continuous input comes out unchanged
+        let mut continuity = SequenceContinuity::default();
+        for segment in &first {
+            assert_eq!(&continuity.renumber(segment).unwrap(), segment);
+        }
+        assert!(continuity.is_continuous());
+        assert!(SequenceContinuity::default().check(&[0, 0, 0, 4]).is_err());
+    }
+}
@@ -0,0 +1,996 @@
+//! Importing external MP4 files into the session pipeline.
+//!
+//! A file the user drags in (a phone recording, an export from another
+//! tool) is a progressive MP4: one moov with sample tables, the media in an
+//! mdat. `import_mp4` reads the first H.264 and AAC tracks out of it and
+//! pushes their samples, interleaved by decode time, through a
+//! `MuxideMuxerState` built from the caller's configuration with the file's
+//! codec parameters. The result is what a live recording of the same media
+//! would have produced: an init segment, hashed chunks and a manifest, which
+//! then go through the same storage, upload and verification code.
+//!
+//! Edit lists are ignored, so a file whose edit list trims its start plays
+//! from the first sample instead. Fragmented MP4s are not supported here;
+//! session archives (`storage::archive`) re-import our own recordings.
+//!
+//! `SampleReader` hands out the samples of a file one at a time instead:
+//! of a finished recording (init segment and media segments) as well as of
+//! a progressive MP4, with their track, timestamps, flags and bytes, so
+//! thumbnailing, analysis or captioning code needs no MP4 parser of its own.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::chunk::{RecordedChunk, TrackKind};
+use crate::manifest::ChunkManifest;
+use crate::muxide_muxer::{
+    extract_sps_pps_from_avcc, find_box, parse_boxes, read_trun, read_u32, read_u64, MuxideConfig,
+    MuxideMuxerState, FRAME_FLAG_KEYFRAME,
+};
+use crate::session::{SessionId, SessionState};
+
+/// Bytes of a VisualSampleEntry before its child boxes
+const VISUAL_SAMPLE_ENTRY_LEN: usize = 78;
+/// Bytes of a version 0 AudioSampleEntry before its child boxes
+const AUDIO_SAMPLE_ENTRY_LEN: usize = 28;
+/// Extra bytes of QuickTime sound sample descriptions, by version
+const QUICKTIME_SOUND_EXTRA_LEN: [usize; 3] = [0, 16, 36];
+/// ES_Descriptor, DecoderConfigDescriptor and DecoderSpecificInfo tags
+const ES_DESCRIPTOR_TAG: u8 = 0x03;
+const DECODER_CONFIG_TAG: u8 = 0x04;
+const DECODER_SPECIFIC_INFO_TAG: u8 = 0x05;
+
+/// One sample of a demuxed track, in track timescale ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sample {
+    /// Byte offset of the sample in the file
+    offset: u64,
+    size: u32,
+    dts: u64,
+    /// Composition offset (pts - dts), from the ctts
+    composition_offset: i64,
+    duration: u32,
+    is_sync: bool,
+}
+
+/// A track of a progressive MP4
+#[derive(Debug, Clone)]
+struct Track {
+    timescale: u32,
+    samples: Vec<Sample>,
+}
+
+impl Track {
+    fn ticks_to_us(&self, ticks: u64) -> u64 {
+        (ticks as u128 * 1_000_000 / self.timescale as u128) as u64
+    }
+}
+
+/// Codec parameters of the H.264 track
+#[derive(Debug, Clone)]
+struct VideoTrack {
+    track: Track,
+    width: u32,
+    height: u32,
+    sps: Vec<u8>,
+    pps: Vec<u8>,
+}
+
+/// Codec parameters of the AAC track
+#[derive(Debug, Clone)]
+struct AudioTrack {
+    track: Track,
+    sample_rate: u32,
+    channels: u16,
+    audio_specific_config: Vec<u8>,
+}
+
+/// An external file turned into session chunks by `import_mp4`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedRecording {
+    pub init_segment: Vec<u8>,
+    /// Manifest of the chunks, `finalizing` like a stopped recording
+    pub manifest: ChunkManifest,
+    pub chunks: Vec<RecordedChunk>,
+}
+
+/// Re-fragment a progressive MP4 into chunks of `session`
+///
+/// `config` supplies everything but the codec parameters (fragment
+/// duration, validation, track names, ...); dimensions, SPS/PPS and the
+/// audio configuration are taken from the file. Fails if the file has
+/// neither an H.264 (`avc1`) nor an AAC (`mp4a`) track.
+pub fn import_mp4(
+    data: &[u8],
+    session: SessionId,
+    config: MuxideConfig,
+) -> Result<ImportedRecording, String> {
+    let moov = top_level_box(data, b"moov")?.ok_or("Not an MP4 file: no moov box")?;
+    if find_box(moov, b"mvex")?.is_some() {
+        return Err("Fragmented MP4 files cannot be imported".to_string());
+    }
+    let mut video = None;
+    let mut audio = None;
+    for trak in parse_boxes(moov)?.into_iter().filter(|b| &b.typ == b"trak") {
+        let mdia = find_box(trak.payload, b"mdia")?.ok_or("trak has no mdia box")?;
+        let hdlr = find_box(mdia.payload, b"hdlr")?.ok_or("mdia has no hdlr box")?;
+        let stbl = find_box(mdia.payload, b"minf")?
+            .map(|minf| find_box(minf.payload, b"stbl"))
+            .transpose()?
+            .flatten()
+            .ok_or("trak has no sample table")?;
+        match hdlr.payload.get(8..12) {
+            Some(b"vide") if video.is_none() => {
+                video = read_video_track(mdia.payload, stbl.payload, data.len())?
+            }
+            Some(b"soun") if audio.is_none() => {
+                audio = read_audio_track(mdia.payload, stbl.payload, data.len())?
+            }
+            _ => {}
+        }
+    }
+    if video.is_none() && audio.is_none() {
+        return Err("MP4 file has no H.264 or AAC track".to_string());
+    }
+
+    let mut config = config;
+    config.video_width = video.as_ref().map(|v| v.width);
+    config.video_height = video.as_ref().map(|v| v.height);
+    config.sps = video.as_ref().map(|v| v.sps.clone());
+    config.pps = video.as_ref().map(|v| v.pps.clone());
+    config.audio_sample_rate = audio.as_ref().map(|a| a.sample_rate);
+    config.audio_channels = audio.as_ref().map(|a| a.channels);
+    config.audio_specific_config = audio.as_ref().map(|a| a.audio_specific_config.clone());
+    config.previous_audio_configs.clear();
+
+    let mut muxer = MuxideMuxerState::new(config);
+    muxer.init()?;
+    let init_segment = muxer.get_init_segment()?;
+
+    // Both tracks interleaved by decode time, video first on ties
+    let mut samples: Vec<(&Track, bool, &Sample)> = Vec::new();
+    if let Some(video) = &video {
+        samples.extend(video.track.samples.iter().map(|s| (&video.track, true, s)));
+    }
+    if let Some(audio) = &audio {
+        samples.extend(audio.track.samples.iter().map(|s| (&audio.track, false, s)));
+    }
+    samples.sort_by_key(|(track, is_video, sample)| (track.ticks_to_us(sample.dts), !is_video));
+    for (track, is_video, sample) in samples {
+        let bytes = sample_data(data, sample)?;
+        let dts = track.ticks_to_us(sample.dts);
+        let duration = track.ticks_to_us(sample.duration as u64) as u32;
+        if is_video {
+            let pts =
+                track.ticks_to_us(sample.dts.saturating_add_signed(sample.composition_offset));
+            let flags = if sample.is_sync {
+                FRAME_FLAG_KEYFRAME
+            } else {
+                0
+            };
+            muxer.push_video_chunk_full(bytes, pts, dts, Some(duration), flags)?;
+        } else {
+            muxer.push_audio_chunk(bytes, dts, duration)?;
+        }
+    }
+    muxer.finish()?;
+
+    let chunks = muxer.take_pending_chunks(&session);
+    let mut manifest = ChunkManifest::new(session);
+    manifest.state.transition_to(SessionState::Recording)?;
+    manifest.state.transition_to(SessionState::Finalizing)?;
+    for chunk in &chunks {
+        manifest.add_chunk(chunk.metadata.clone())?;
+    }
+    Ok(ImportedRecording {
+        init_segment,
+        manifest,
+        chunks,
+    })
+}
+
+/// Payload of the first top-level box of a type
+///
+/// Unlike `find_box`, accepts 64-bit box sizes and a last box extending to
+/// the end of the file, both common for the mdat of large recordings.
+fn top_level_box<'a>(data: &'a [u8], typ: &[u8; 4]) -> Result<Option<&'a [u8]>, String> {
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let (header, end) = top_level_box_at(data, pos)?;
+        if &data[pos + 4..pos + 8] == typ {
+            return Ok(Some(&data[pos + header..end]));
+        }
+        pos = end;
+    }
+    Ok(None)
+}
+
+/// Header length and end of the top-level box at `pos`
+fn top_level_box_at(data: &[u8], pos: usize) -> Result<(usize, usize), String> {
+    let (header, size) = match read_u32(data, pos)? {
+        0 => (8, (data.len() - pos) as u64),
+        1 => (16, read_u64(data, pos + 8)?),
+        size => (8, size as u64),
+    };
+    let end = pos as u64 + size;
+    if size < header || end > data.len() as u64 {
+        return Err("Invalid top-level box size".to_string());
+    }
+    Ok((header as usize, end as usize))
+}
+
+/// Bytes of a sample in the file
+fn sample_data<'a>(data: &'a [u8], sample: &Sample) -> Result<&'a [u8], String> {
+    let start = usize::try_from(sample.offset).map_err(|_| "Sample offset out of range")?;
+    start
+        .checked_add(sample.size as usize)
+        .and_then(|end| data.get(start..end))
+        .ok_or_else(|| "Sample lies outside the file".to_string())
+}
+
+/// First sample entry of an stsd box: its type and payload
+fn sample_entry(stbl: &[u8]) -> Result<([u8; 4], &[u8]), String> {
+    let stsd = find_box(stbl, b"stsd")?.ok_or("stbl has no stsd box")?;
+    let entry = parse_boxes(stsd.payload.get(8..).ok_or("Truncated stsd box")?)?
+        .into_iter()
+        .next()
+        .ok_or("stsd has no sample entry")?;
+    Ok((entry.typ, entry.payload))
+}
+
+fn read_video_track(
+    mdia: &[u8],
+    stbl: &[u8],
+    file_len: usize,
+) -> Result<Option<VideoTrack>, String> {
+    let (typ, entry) = sample_entry(stbl)?;
+    if &typ != b"avc1" {
+        return Ok(None);
+    }
+    let children = entry
+        .get(VISUAL_SAMPLE_ENTRY_LEN..)
+        .ok_or("Truncated avc1 sample entry")?;
+    let width = u16::from_be_bytes([entry[24], entry[25]]) as u32;
+    let height = u16::from_be_bytes([entry[26], entry[27]]) as u32;
+    let avcc = find_box(children, b"avcC")?.ok_or("avc1 has no avcC box")?;
+    let (sps, pps) = extract_sps_pps_from_avcc(avcc.payload)?;
+    Ok(Some(VideoTrack {
+        track: read_track(mdia, stbl, file_len)?,
+        width,
+        height,
+        sps,
+        pps,
+    }))
+}
+
+fn read_audio_track(
+    mdia: &[u8],
+    stbl: &[u8],
+    file_len: usize,
+) -> Result<Option<AudioTrack>, String> {
+    let (typ, entry) = sample_entry(stbl)?;
+    if &typ != b"mp4a" {
+        return Ok(None);
+    }
+    if entry.len() < AUDIO_SAMPLE_ENTRY_LEN {
+        return Err("Truncated mp4a sample entry".to_string());
+    }
+    let version = u16::from_be_bytes([entry[8], entry[9]]) as usize;
+    let extra = *QUICKTIME_SOUND_EXTRA_LEN
+        .get(version)
+        .ok_or_else(|| format!("Unsupported mp4a version {}", version))?;
+    let children = entry
+        .get(AUDIO_SAMPLE_ENTRY_LEN + extra..)
+        .ok_or("Truncated mp4a sample entry")?;
+    let esds = find_box(children, b"esds")?.ok_or("mp4a has no esds box")?;
+    let audio_specific_config = decoder_specific_info(esds.payload.get(4..).unwrap_or(&[]))?
+        .ok_or("esds has no AudioSpecificConfig")?
+        .to_vec();
+    let sample_rate = u32::from_be_bytes([entry[24], entry[25], entry[26], entry[27]]) >> 16;
+    Ok(Some(AudioTrack {
+        track: read_track(mdia, stbl, file_len)?,
+        sample_rate,
+        channels: u16::from_be_bytes([entry[16], entry[17]]),
+        audio_specific_config,
+    }))
+}
+
+/// Split an ISO 14496-1 descriptor into its tag, body and the bytes after it
+fn read_descriptor(data: &[u8]) -> Result<(u8, &[u8], &[u8]), String> {
+    let tag = *data.first().ok_or("Truncated descriptor")?;
+    let mut len = 0usize;
+    let mut pos = 1;
+    loop {
+        let byte = *data.get(pos).ok_or("Truncated descriptor")?;
+        len = (len << 7) | (byte & 0x7F) as usize;
+        pos += 1;
+        if byte & 0x80 == 0 || pos == 5 {
+            break;
+        }
+    }
+    let body = data.get(pos..pos + len).ok_or("Truncated descriptor")?;
+    Ok((tag, body, &data[pos + len..]))
+}
+
+/// AudioSpecificConfig inside the ES_Descriptor of an esds box
+fn decoder_specific_info(es: &[u8]) -> Result<Option<&[u8]>, String> {
+    let (tag, body, _) = read_descriptor(es)?;
+    if tag != ES_DESCRIPTOR_TAG || body.len() < 3 {
+        return Ok(None);
+    }
+    let flags = body[2];
+    let mut pos = 3;
+    if flags & 0x80 != 0 {
+        pos += 2; // dependsOn_ES_ID
+    }
+    if flags & 0x40 != 0 {
+        pos += 1 + *body.get(pos).ok_or("Truncated ES_Descriptor")? as usize; // URL
+    }
+    if flags & 0x20 != 0 {
+        pos += 2; // OCR_ES_Id
+    }
+    let mut rest = body.get(pos..).ok_or("Truncated ES_Descriptor")?;
+    while !rest.is_empty() {
+        let (tag, config, next) = read_descriptor(rest)?;
+        if tag == DECODER_CONFIG_TAG {
+            // objectTypeIndication, streamType, bufferSizeDB, bitrates
+            let mut inner = config
+                .get(13..)
+                .ok_or("Truncated DecoderConfigDescriptor")?;
+            while !inner.is_empty() {
+                let (tag, info, next) = read_descriptor(inner)?;
+                if tag == DECODER_SPECIFIC_INFO_TAG {
+                    return Ok(Some(info));
+                }
+                inner = next;
+            }
+        }
+        rest = next;
+    }
+    Ok(None)
+}
+
+/// Read the sample table of a track in a file of `file_len` bytes
+///
+/// Sample counts are checked against what the tables and the file can hold
+/// before anything is allocated.
+fn read_track(mdia: &[u8], stbl: &[u8], file_len: usize) -> Result<Track, String> {
+    let mdhd = find_box(mdia, b"mdhd")?.ok_or("mdia has no mdhd box")?;
+    let timescale_offset = if mdhd.payload.first() == Some(&1) {
+        20
+    } else {
+        12
+    };
+    let timescale = read_u32(mdhd.payload, timescale_offset)?;
+    if timescale == 0 {
+        return Err("Track timescale is 0".to_string());
+    }
+    let table = |typ: &[u8; 4]| -> Result<Option<&[u8]>, String> {
+        Ok(find_box(stbl, typ)?.map(|b| b.payload))
+    };
+
+    // Sample sizes; a size table holds one entry per sample, samples of a
+    // constant size must fit in the file, and each needs a decode time
+    let stsz = table(b"stsz")?.ok_or("stbl has no stsz box")?;
+    let stts = table(b"stts")?.ok_or("stbl has no stts box")?;
+    let constant_size = read_u32(stsz, 4)?;
+    let count = read_u32(stsz, 8)? as usize;
+    let max_count = match constant_size {
+        0 => stsz.len().saturating_sub(12) / 4,
+        size => file_len / size as usize,
+    };
+    if count > max_count {
+        return Err(format!(
+            "stsz claims {} samples, the file holds at most {}",
+            count, max_count
+        ));
+    }
+    let mut timed = 0usize;
+    for entry in 0..read_u32(stts, 4)? as usize {
+        timed = timed.saturating_add(read_u32(stts, 8 + 8 * entry)? as usize);
+    }
+    if timed < count {
+        return Err("stts covers fewer samples than stsz".to_string());
+    }
+    let mut samples = Vec::with_capacity(count);
+    for i in 0..count {
+        let size = match constant_size {
+            0 => read_u32(stsz, 12 + 4 * i)?,
+            size => size,
+        };
+        samples.push(Sample {
+            offset: 0,
+            size,
+            dts: 0,
+            composition_offset: 0,
+            duration: 0,
+            is_sync: true,
+        });
+    }
+
+    // Decode times
+    let mut index = 0usize;
+    let mut dts = 0u64;
+    for entry in 0..read_u32(stts, 4)? as usize {
+        let run = read_u32(stts, 8 + 8 * entry)? as usize;
+        let delta = read_u32(stts, 12 + 8 * entry)?;
+        for sample in samples.iter_mut().skip(index).take(run) {
+            sample.dts = dts;
+            sample.duration = delta;
+            dts = dts.saturating_add(delta as u64);
+        }
+        index = index.saturating_add(run);
+    }
+
+    // Composition offsets; version 1 makes them signed
+    if let Some(ctts) = table(b"ctts")? {
+        let signed = ctts.first() == Some(&1);
+        let mut index = 0;
+        for entry in 0..read_u32(ctts, 4)? as usize {
+            let run = read_u32(ctts, 8 + 8 * entry)? as usize;
+            let raw = read_u32(ctts, 12 + 8 * entry)?;
+            let offset = if signed {
+                raw as i32 as i64
+            } else {
+                raw as i64
+            };
+            for sample in samples.iter_mut().skip(index).take(run) {
+                sample.composition_offset = offset;
+            }
+            index = index.saturating_add(run);
+        }
+    }
+
+    // Sync samples; without an stss every sample is one
+    if let Some(stss) = table(b"stss")? {
+        samples.iter_mut().for_each(|s| s.is_sync = false);
+        for entry in 0..read_u32(stss, 4)? as usize {
+            let number = read_u32(stss, 8 + 4 * entry)? as usize;
+            if let Some(sample) = number.checked_sub(1).and_then(|i| samples.get_mut(i)) {
+                sample.is_sync = true;
+            }
+        }
+    }
+
+    // Chunk offsets, then samples laid out chunk by chunk
+    let offsets: Vec<u64> = if let Some(stco) = table(b"stco")? {
+        (0..read_u32(stco, 4)? as usize)
+            .map(|i| read_u32(stco, 8 + 4 * i).map(u64::from))
+            .collect::<Result<_, _>>()?
+    } else {
+        let co64 = table(b"co64")?.ok_or("stbl has no chunk offsets")?;
+        (0..read_u32(co64, 4)? as usize)
+            .map(|i| read_u64(co64, 8 + 8 * i))
+            .collect::<Result<_, _>>()?
+    };
+    let stsc = table(b"stsc")?.ok_or("stbl has no stsc box")?;
+    let runs = read_u32(stsc, 4)? as usize;
+    let mut index = 0;
+    for run in 0..runs {
+        let first_chunk = read_u32(stsc, 8 + 12 * run)? as usize;
+        let per_chunk = read_u32(stsc, 12 + 12 * run)? as usize;
+        let last_chunk = if run + 1 < runs {
+            read_u32(stsc, 8 + 12 * (run + 1))? as usize
+        } else {
+            offsets.len() + 1
+        };
+        for chunk in first_chunk..last_chunk {
+            let mut offset = *offsets
+                .get(chunk.wrapping_sub(1))
+                .ok_or("stsc refers to a missing chunk")?;
+            for sample in samples.iter_mut().skip(index).take(per_chunk) {
+                sample.offset = offset;
+                offset = offset.saturating_add(sample.size as u64);
+            }
+            index = index.saturating_add(per_chunk);
+        }
+    }
+    if index < samples.len() {
+        return Err("stsc covers fewer samples than stsz".to_string());
+    }
+    Ok(Track { timescale, samples })
+}
+
+/// Where and when a sample yielded by `SampleReader` plays
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleInfo {
+    pub track_id: u32,
+    /// `video` or `audio`
+    pub kind: TrackKind,
+    /// Presentation and decode timestamps in microseconds
+    pub pts_us: u64,
+    pub dts_us: u64,
+    pub duration_us: u32,
+    /// `FRAME_FLAG_*` bits, as `push_video_chunk_full` takes them
+    pub flags: u32,
+}
+
+/// A sample of a file read by `SampleReader`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaSample {
+    pub info: SampleInfo,
+    /// AVCC video or a raw AAC frame
+    pub data: Vec<u8>,
+}
+
+/// A video or audio track of a file read by `SampleReader`
+#[derive(Debug, Clone)]
+struct ReaderTrack {
+    track_id: u32,
+    kind: TrackKind,
+    timescale: u32,
+}
+
+impl ReaderTrack {
+    fn ticks_to_us(&self, ticks: u64) -> u64 {
+        (ticks as u128 * 1_000_000 / self.timescale as u128) as u64
+    }
+}
+
+/// Yields the video and audio samples of an MP4 file one at a time
+///
+/// Samples come in file order: those of the moov's sample tables by their
+/// position in the file (a progressive MP4), then fragment by fragment,
+/// within a fragment track by track (a recording). A fragment's sample list
+/// is only read once the reader gets to it. Tracks other than video and
+/// audio are skipped. `data` is anything holding the bytes, e.g. a `&[u8]`
+/// or a `Vec<u8>`.
+pub struct SampleReader<D> {
+    data: D,
+    tracks: Vec<ReaderTrack>,
+    /// Samples found and not yet returned, with the index of their track
+    pending: VecDeque<(usize, Sample)>,
+    /// Position of the next top-level box to look for fragments in
+    position: usize,
+    /// An error was returned; the iterator ends
+    failed: bool,
+}
+
+impl<D: AsRef<[u8]>> SampleReader<D> {
+    /// Read the tracks of `data`; fails if it has no moov or neither a
+    /// video nor an audio track
+    pub fn new(data: D) -> Result<Self, String> {
+        let bytes = data.as_ref();
+        let moov = top_level_box(bytes, b"moov")?.ok_or("Not an MP4 file: no moov box")?;
+        let mut tracks = Vec::new();
+        let mut pending = Vec::new();
+        for trak in parse_boxes(moov)?.into_iter().filter(|b| &b.typ == b"trak") {
+            let tkhd = find_box(trak.payload, b"tkhd")?.ok_or("trak has no tkhd box")?;
+            let track_id_offset = if tkhd.payload.first() == Some(&1) {
+                20
+            } else {
+                12
+            };
+            let track_id = read_u32(tkhd.payload, track_id_offset)?;
+            let mdia = find_box(trak.payload, b"mdia")?.ok_or("trak has no mdia box")?;
+            let hdlr = find_box(mdia.payload, b"hdlr")?.ok_or("mdia has no hdlr box")?;
+            let kind = match hdlr.payload.get(8..12) {
+                Some(b"vide") => TrackKind::Video,
+                Some(b"soun") => TrackKind::Audio,
+                _ => continue,
+            };
+            let stbl = find_box(mdia.payload, b"minf")?
+                .map(|minf| find_box(minf.payload, b"stbl"))
+                .transpose()?
+                .flatten()
+                .ok_or("trak has no sample table")?;
+            let track = read_track(mdia.payload, stbl.payload, bytes.len())?;
+            pending.extend(track.samples.into_iter().map(|s| (tracks.len(), s)));
+            tracks.push(ReaderTrack {
+                track_id,
+                kind,
+                timescale: track.timescale,
+            });
+        }
+        if tracks.is_empty() {
+            return Err("MP4 file has no video or audio track".to_string());
+        }
+        pending.sort_by_key(|(_, sample)| sample.offset);
+        Ok(Self {
+            data,
+            tracks,
+            pending: pending.into(),
+            position: 0,
+            failed: false,
+        })
+    }
+
+    /// The next sample, or None after the last one
+    pub fn next_sample(&mut self) -> Result<Option<MediaSample>, String> {
+        while self.pending.is_empty() {
+            if !self.read_next_fragment()? {
+                return Ok(None);
+            }
+        }
+        let Some((index, sample)) = self.pending.pop_front() else {
+            return Ok(None);
+        };
+        let track = &self.tracks[index];
+        let pts = sample.dts.saturating_add_signed(sample.composition_offset);
+        Ok(Some(MediaSample {
+            info: SampleInfo {
+                track_id: track.track_id,
+                kind: track.kind,
+                pts_us: track.ticks_to_us(pts),
+                dts_us: track.ticks_to_us(sample.dts),
+                duration_us: track.ticks_to_us(sample.duration as u64) as u32,
+                flags: if sample.is_sync {
+                    FRAME_FLAG_KEYFRAME
+                } else {
+                    0
+                },
+            },
+            data: sample_data(self.data.as_ref(), &sample)?.to_vec(),
+        }))
+    }
+
+    /// Queue the samples of the next moof; false once there is none left
+    fn read_next_fragment(&mut self) -> Result<bool, String> {
+        let data = self.data.as_ref();
+        while self.position + 8 <= data.len() {
+            let start = self.position;
+            let (header, end) = top_level_box_at(data, start)?;
+            self.position = end;
+            if &data[start + 4..start + 8] == b"moof" {
+                let samples = read_moof(&self.tracks, start, &data[start + header..end])?;
+                self.pending.extend(samples);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<D: AsRef<[u8]>> Iterator for SampleReader<D> {
+    type Item = Result<MediaSample, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let next = self.next_sample().transpose();
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+/// Samples of the known tracks in the moof at `moof_offset`, with the
+/// index of their track
+///
+/// Handles the tfhd and trun fields other muxers use too: an explicit base
+/// data offset, default sample duration, size and flags, and several truns
+/// per traf.
+fn read_moof(
+    tracks: &[ReaderTrack],
+    moof_offset: usize,
+    moof: &[u8],
+) -> Result<Vec<(usize, Sample)>, String> {
+    let mut samples = Vec::new();
+    for traf in parse_boxes(moof)?.iter().filter(|b| &b.typ == b"traf") {
+        let tfhd = find_box(traf.payload, b"tfhd")?.ok_or("traf has no tfhd box")?;
+        let tfhd_flags = read_u32(tfhd.payload, 0)? & 0x00FF_FFFF;
+        let track_id = read_u32(tfhd.payload, 4)?;
+        let Some(index) = tracks.iter().position(|t| t.track_id == track_id) else {
+            continue;
+        };
+        let mut pos = 8;
+        let mut field = |flag: u32, len: usize| -> Result<Option<u64>, String> {
+            if tfhd_flags & flag == 0 {
+                return Ok(None);
+            }
+            let value = match len {
+                8 => read_u64(tfhd.payload, pos)?,
+                _ => read_u32(tfhd.payload, pos)? as u64,
+            };
+            pos += len;
+            Ok(Some(value))
+        };
+        let base_offset = field(0x000001, 8)?.unwrap_or(moof_offset as u64);
+        field(0x000002, 4)?;
+        let default_duration = field(0x000008, 4)?.unwrap_or(0) as u32;
+        let default_size = field(0x000010, 4)?.unwrap_or(0) as u32;
+        let default_flags = field(0x000020, 4)?.unwrap_or(0) as u32;
+
+        let tfdt = find_box(traf.payload, b"tfdt")?.ok_or("traf has no tfdt box")?;
+        let mut dts = if tfdt.payload.first() == Some(&1) {
+            read_u64(tfdt.payload, 4)?
+        } else {
+            read_u32(tfdt.payload, 4)? as u64
+        };
+        for trun in parse_boxes(traf.payload)?
+            .iter()
+            .filter(|b| &b.typ == b"trun")
+        {
+            let trun_flags = read_u32(trun.payload, 0)?;
+            let run = read_trun(trun.payload)?;
+            // The data offset is signed
+            let mut offset = base_offset.saturating_add_signed(run.data_offset as i32 as i64);
+            for (i, entry) in run.entries.into_iter().enumerate() {
+                let has_flags =
+                    trun_flags & 0x000400 != 0 || (i == 0 && trun_flags & 0x000004 != 0);
+                let duration = match trun_flags & 0x000100 {
+                    0 => default_duration,
+                    _ => entry.duration,
+                };
+                let size = match trun_flags & 0x000200 {
+                    0 => default_size,
+                    _ => entry.size,
+                };
+                let flags = if has_flags {
+                    entry.flags
+                } else {
+                    default_flags
+                };
+                samples.push((
+                    index,
+                    Sample {
+                        offset,
+                        size,
+                        dts,
+                        composition_offset: entry.composition_offset as i64,
+                        duration,
+                        // sample_is_non_sync_sample
+                        is_sync: flags & 0x0001_0000 == 0,
+                    },
+                ));
+                offset = offset.saturating_add(size as u64);
+                dts = dts.saturating_add(duration as u64);
+            }
+        }
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::muxide_muxer::build_box;
+
+    fn full_box(typ: &[u8; 4], version: u8, fields: &[u32]) -> Vec<u8> {
+        let mut payload = vec![version, 0, 0, 0];
+        for field in fields {
+            payload.extend_from_slice(&field.to_be_bytes());
+        }
+        build_box(typ, &payload)
+    }
+
+    /// stsd of the `handler` track of an init segment made by our muxer
+    fn stsd(init: &[u8], handler: &[u8; 4]) -> Vec<u8> {
+        let moov = find_box(init, b"moov").unwrap().unwrap();
+        for trak in parse_boxes(moov.payload).unwrap() {
+            if &trak.typ != b"trak" {
+                continue;
+            }
+            let mdia = find_box(trak.payload, b"mdia").unwrap().unwrap();
+            let hdlr = find_box(mdia.payload, b"hdlr").unwrap().unwrap();
+            if &hdlr.payload[8..12] == handler {
+                let minf = find_box(mdia.payload, b"minf").unwrap().unwrap();
+                let stbl = find_box(minf.payload, b"stbl").unwrap().unwrap();
+                let stsd = find_box(stbl.payload, b"stsd").unwrap().unwrap();
+                return build_box(b"stsd", stsd.payload);
+            }
+        }
+        panic!("no {:?} track", handler);
+    }
+
+    fn trak(track_id: u32, handler: &[u8; 4], timescale: u32, stbl: Vec<u8>) -> Vec<u8> {
+        let mut hdlr = vec![0; 8];
+        hdlr.extend_from_slice(handler);
+        hdlr.extend_from_slice(&[0; 13]);
+        let mdia = [
+            full_box(b"mdhd", 0, &[0, 0, timescale, 0, 0]),
+            build_box(b"hdlr", &hdlr),
+            build_box(b"minf", &build_box(b"stbl", &stbl)),
+        ]
+        .concat();
+        let tkhd = full_box(b"tkhd", 0, &[0, 0, track_id]);
+        build_box(b"trak", &[tkhd, build_box(b"mdia", &mdia)].concat())
+    }
+
+    /// A progressive MP4 with three H.264 frames and six AAC frames
+    fn progressive_mp4(source: &MuxideConfig) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let mut muxer = MuxideMuxerState::new(source.clone());
+        muxer.init().unwrap();
+        let init = muxer.get_init_segment().unwrap();
+
+        let video: Vec<Vec<u8>> = (0..3u8)
+            .map(|i| vec![0, 0, 0, 4, if i == 0 { 0x65 } else { 0x41 }, 0xA0, 0xB0, i])
+            .collect();
+        let audio: Vec<Vec<u8>> = (0..6u8).map(|i| vec![0x21, 0xC0 + i, 0x55, i]).collect();
+        let ftyp = build_box(b"ftyp", b"isom\0\0\0\0isom");
+        let video_offset = (ftyp.len() + 8) as u32;
+        let audio_offset = video_offset + video.iter().map(|f| f.len() as u32).sum::<u32>();
+        let mdat = build_box(b"mdat", &[video.concat(), audio.concat()].concat());
+
+        let video_stbl = [
+            stsd(&init, b"vide"),
+            full_box(b"stts", 0, &[1, 3, 3600]),
+            full_box(b"stss", 0, &[1, 1]),
+            full_box(b"stsz", 0, &[0, 3, 8, 8, 8]),
+            full_box(b"stsc", 0, &[1, 1, 3, 1]),
+            full_box(b"stco", 0, &[1, video_offset]),
+        ]
+        .concat();
+        let audio_stbl = [
+            stsd(&init, b"soun"),
+            full_box(b"stts", 0, &[1, 6, 1024]),
+            full_box(b"stsz", 0, &[4, 6]),
+            full_box(b"stsc", 0, &[1, 1, 6, 1]),
+            full_box(b"stco", 0, &[1, audio_offset]),
+        ]
+        .concat();
+        let moov = build_box(
+            b"moov",
+            &[
+                trak(1, b"vide", 90_000, video_stbl),
+                trak(2, b"soun", 48_000, audio_stbl),
+            ]
+            .concat(),
+        );
+        let frames = [video, audio].concat();
+        ([ftyp, mdat, moov].concat(), frames)
+    }
+
+    #[test]
+    fn test_import_mp4() {
+        let source = MuxideConfig {
+            video_width: Some(640),
+            video_height: Some(360),
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E, 0xD9, 0x00, 0x50, 0x05]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            audio_sample_rate: Some(48_000),
+            audio_channels: Some(2),
+            ..Default::default()
+        };
+        let (file, frames) = progressive_mp4(&source);
+
+        let config = MuxideConfig {
+            video_track_name: Some("Imported".to_string()),
+            ..Default::default()
+        };
+        let imported = import_mp4(&file, SessionId::from("s1"), config).unwrap();
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            video_track_name: Some("Imported".to_string()),
+            ..source
+        });
+        muxer.init().unwrap();
+        assert_eq!(imported.init_segment, muxer.get_init_segment().unwrap());
+
+        assert_eq!(imported.manifest.state, SessionState::Finalizing);
+        assert!(!imported.chunks.is_empty());
+        assert_eq!(imported.manifest.chunk_count(), imported.chunks.len());
+        let media: Vec<u8> = imported
+            .chunks
+            .iter()
+            .flat_map(|chunk| chunk.data.iter().copied())
+            .collect();
+        for frame in &frames {
+            assert!(media.windows(frame.len()).any(|w| w == frame.as_slice()));
+        }
+        assert!(imported.chunks[0].metadata.hash.is_some());
+        assert_eq!(imported.chunks[0].metadata.has_keyframe, Some(true));
+
+        // Our own fragmented output is refused
+        let err = import_mp4(
+            &imported.init_segment,
+            SessionId::from("s2"),
+            MuxideConfig::default(),
+        )
+        .unwrap_err();
+        assert!(err.contains("Fragmented"));
+        assert!(import_mp4(
+            b"not an mp4",
+            SessionId::from("s3"),
+            MuxideConfig::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_sample_counts_are_bounded() {
+        let mdhd = full_box(b"mdhd", 0, &[0, 0, 48_000, 0, 0]);
+        let stbl = |stts: &[u32], stsz: &[u32]| {
+            [
+                full_box(b"stts", 0, stts),
+                full_box(b"stsz", 0, stsz),
+                full_box(b"stsc", 0, &[1, 1, 6, 1]),
+                full_box(b"stco", 0, &[1, 0]),
+            ]
+            .concat()
+        };
+        let track = read_track(&mdhd, &stbl(&[1, 6, 1024], &[4, 6]), 24).unwrap();
+        assert_eq!(track.samples.len(), 6);
+
+        // Constant-size samples beyond the file, a size table shorter than
+        // its count, or more samples than stts times
+        for (stts, stsz) in [
+            (&[1, u32::MAX, 1024][..], &[4, u32::MAX][..]),
+            (&[1, 6, 1024], &[4, 7]),
+            (&[1, 6, 1024], &[0, 6, 4, 4]),
+            (&[1, 5, 1024], &[4, 6]),
+        ] {
+            assert!(read_track(&mdhd, &stbl(stts, stsz), 24).is_err());
+        }
+    }
+
+    #[test]
+    fn test_sample_reader() {
+        let source = MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E, 0xD9, 0x00, 0x50, 0x05]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            audio_sample_rate: Some(48_000),
+            audio_channels: Some(2),
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        };
+
+        // A progressive file, in mdat order
+        let (file, frames) = progressive_mp4(&source);
+        let samples: Vec<MediaSample> = SampleReader::new(&file[..])
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let data: Vec<Vec<u8>> = samples.iter().map(|s| s.data.clone()).collect();
+        assert_eq!(data, frames);
+        assert_eq!(
+            samples[1].info,
+            SampleInfo {
+                track_id: 1,
+                kind: TrackKind::Video,
+                pts_us: 40_000,
+                dts_us: 40_000,
+                duration_us: 40_000,
+                flags: 0,
+            }
+        );
+        assert_eq!(samples[0].info.flags, FRAME_FLAG_KEYFRAME);
+        assert_eq!(samples[4].info.kind, TrackKind::Audio);
+        assert_eq!(samples[4].info.dts_us, 21_333);
+
+        // A recording, fragment by fragment
+        let mut muxer = MuxideMuxerState::new(source);
+        muxer.init().unwrap();
+        let mut pushed = Vec::new();
+        for i in 0..60u64 {
+            let frame = vec![0, 0, 0, 2, if i % 30 == 0 { 0x65 } else { 0x41 }, i as u8];
+            muxer
+                .push_video_chunk(&frame, i * 33_333, i % 30 == 0)
+                .unwrap();
+            muxer
+                .push_audio_chunk(&[0x21, i as u8], i * 21_333, 21_333)
+                .unwrap();
+            pushed.push((frame, i * 33_333, i % 30 == 0));
+        }
+        let recording = muxer.get_complete_file().unwrap();
+        let mut reader = SampleReader::new(recording).unwrap();
+        let mut video = Vec::new();
+        let mut audio = 0;
+        while let Some(sample) = reader.next_sample().unwrap() {
+            match sample.info.kind {
+                TrackKind::Video => video.push((
+                    sample.data,
+                    sample.info.pts_us,
+                    sample.info.flags == FRAME_FLAG_KEYFRAME,
+                )),
+                _ => {
+                    assert_eq!(sample.info.track_id, 2);
+                    assert_eq!(sample.data[0], 0x21);
+                    audio += 1;
+                }
+            }
+        }
+        assert_eq!(video.len(), pushed.len());
+        for ((data, pts, key), (frame, ts, keyframe)) in video.iter().zip(&pushed) {
+            assert_eq!((data, key), (frame, keyframe));
+            // Timestamps went through the 90 kHz track timescale
+            assert!(pts.abs_diff(*ts) < 12, "{} {}", pts, ts);
+        }
+        assert_eq!(audio, 60);
+        assert!(reader.next_sample().unwrap().is_none());
+        assert!(SampleReader::new(&b"not an mp4"[..]).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! PCM downmix of several audio sources into one track.
+//!
+//! A screen recording with a microphone has two audio sources. Recorded as
+//! two tracks, simple players take only the first and lose the other, so
+//! the recorder can instead mix them before encoding. `DownmixMixerState`
+//! takes interleaved PCM of each source as its AudioWorklet delivers it,
+//! applies the gain of the source from a `DownmixRecipe`, and hands back the
+//! summed samples for as many frames as every source has delivered. A
+//! source that stalls (a worklet suspended, a device unplugged) would hold
+//! the mix back and let the others pile up, so once another source is
+//! `MAX_LAG_FRAMES` ahead the stalled one is taken as silent for that time,
+//! and what it delivers for it later is dropped. The recipe, marked as
+//! applied, is stored in `RecordingMetadata`.
+
+use crate::metadata::DownmixRecipe;
+
+/// Frames a source may run ahead of a stalled one before the stalled one is
+/// filled with silence (one second at 48 kHz)
+pub const MAX_LAG_FRAMES: usize = 48_000;
+
+/// Convert a gain in dB to a linear factor
+pub fn db_to_gain(gain_db: f64) -> f32 {
+    10f64.powf(gain_db / 20.0) as f32
+}
+
+/// Mixes the interleaved PCM of a recipe's sources, all with the same sample
+/// rate and channel count
+#[derive(Debug, Clone)]
+pub struct DownmixMixerState {
+    recipe: DownmixRecipe,
+    gains: Vec<f32>,
+    channels: usize,
+    /// Samples of each source not mixed yet
+    pending: Vec<Vec<f32>>,
+    /// Samples of each source already mixed as silence, to drop when they
+    /// arrive
+    skipped: Vec<usize>,
+    clipped_samples: u64,
+    filled_frames: u64,
+}
+
+impl DownmixMixerState {
+    /// Create a mixer for the sources of `recipe`
+    pub fn new(recipe: DownmixRecipe, channels: u16) -> Result<Self, String> {
+        if recipe.sources.is_empty() {
+            return Err("Downmix recipe has no sources".to_string());
+        }
+        if channels == 0 {
+            return Err("Downmix needs at least one channel".to_string());
+        }
+        if let Some(source) = recipe.sources.iter().find(|s| !s.gain_db.is_finite()) {
+            return Err(format!(
+                "Invalid gain for downmix source {}: {}",
+                source.label, source.gain_db
+            ));
+        }
+        let gains = recipe
+            .sources
+            .iter()
+            .map(|s| db_to_gain(s.gain_db))
+            .collect();
+        let pending = vec![Vec::new(); recipe.sources.len()];
+        let skipped = vec![0; recipe.sources.len()];
+        Ok(Self {
+            recipe,
+            gains,
+            channels: channels as usize,
+            pending,
+            skipped,
+            clipped_samples: 0,
+            filled_frames: 0,
+        })
+    }
+
+    /// Add interleaved samples in [-1, 1] of the source at `source` (its
+    /// index in the recipe)
+    pub fn push(&mut self, source: usize, samples: &[f32]) -> Result<(), String> {
+        if !samples.len().is_multiple_of(self.channels) {
+            return Err(format!(
+                "Sample count {} is not a multiple of {} channels",
+                samples.len(),
+                self.channels
+            ));
+        }
+        let pending = self
+            .pending
+            .get_mut(source)
+            .ok_or_else(|| format!("Unknown downmix source: {}", source))?;
+        let skipped = &mut self.skipped[source];
+        let dropped = (*skipped).min(samples.len());
+        *skipped -= dropped;
+        pending.extend_from_slice(&samples[dropped..]);
+        Ok(())
+    }
+
+    /// Mix the frames every source has delivered, and those of sources
+    /// `MAX_LAG_FRAMES` ahead of a stalled one
+    pub fn take_mixed(&mut self) -> Vec<f32> {
+        let len = self.pending.iter().map(Vec::len).min().unwrap_or(0);
+        let max_lag = MAX_LAG_FRAMES * self.channels;
+        let ahead = self.pending.iter().map(Vec::len).max().unwrap_or(0);
+        if ahead - len <= max_lag {
+            return self.mix(len);
+        }
+        let len = ahead - max_lag;
+        for (pending, skipped) in self.pending.iter().zip(self.skipped.iter_mut()) {
+            *skipped += len.saturating_sub(pending.len());
+        }
+        let filled = len - self.pending.iter().map(Vec::len).min().unwrap_or(0);
+        self.filled_frames += (filled / self.channels) as u64;
+        self.mix(len)
+    }
+
+    /// Mix everything left at the end of the recording; sources that fell
+    /// behind are silent for the rest
+    pub fn finish(&mut self) -> Vec<f32> {
+        let len = self.pending.iter().map(Vec::len).max().unwrap_or(0);
+        self.mix(len)
+    }
+
+    /// Samples clamped to [-1, 1] after mixing so far
+    pub fn clipped_samples(&self) -> u64 {
+        self.clipped_samples
+    }
+
+    /// Frames mixed with a stalled source filled with silence so far
+    pub fn filled_frames(&self) -> u64 {
+        self.filled_frames
+    }
+
+    /// The recipe to store in the recording metadata, marked as applied
+    pub fn recipe(&self) -> DownmixRecipe {
+        DownmixRecipe {
+            applied: true,
+            ..self.recipe.clone()
+        }
+    }
+
+    /// Mix and remove the first `len` samples of every source
+    fn mix(&mut self, len: usize) -> Vec<f32> {
+        let mut mixed = vec![0f32; len];
+        for (pending, &gain) in self.pending.iter_mut().zip(&self.gains) {
+            let available = pending.len().min(len);
+            for (out, &sample) in mixed.iter_mut().zip(&pending[..available]) {
+                *out += sample * gain;
+            }
+            pending.drain(..available);
+        }
+        for sample in &mut mixed {
+            if sample.abs() > 1.0 {
+                *sample = sample.clamp(-1.0, 1.0);
+                self.clipped_samples += 1;
+            }
+        }
+        mixed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::DownmixSource;
+
+    fn source(label: &str, gain_db: f64) -> DownmixSource {
+        DownmixSource {
+            label: label.to_string(),
+            gain_db,
+            track_id: None,
+        }
+    }
+
+    #[test]
+    fn test_downmix_mixer() {
+        let recipe = DownmixRecipe {
+            sources: vec![source("screen", -6.0), source("mic", 0.0)],
+            applied: false,
+        };
+        let mut mixer = DownmixMixerState::new(recipe, 2).unwrap();
+        let screen_gain = db_to_gain(-6.0);
+        assert!((screen_gain - 0.501).abs() < 0.001);
+
+        // The screen worklet delivers first; nothing is mixed until the
+        // microphone catches up
+        mixer.push(0, &[0.5, -0.5, 0.5, -0.5, 1.0, 1.0]).unwrap();
+        assert!(mixer.take_mixed().is_empty());
+        mixer.push(1, &[0.25, 0.25, 0.9, -0.3]).unwrap();
+        let mixed = mixer.take_mixed();
+        assert_eq!(mixed.len(), 4);
+        assert_eq!(mixed[0], 0.5 * screen_gain + 0.25);
+        assert_eq!(mixed[1], -0.5 * screen_gain + 0.25);
+        // Sums beyond full scale are clamped
+        assert_eq!(mixed[2], 1.0);
+        assert_eq!(mixed[3], -0.5 * screen_gain - 0.3);
+        assert_eq!(mixer.clipped_samples(), 1);
+        assert!(mixer.take_mixed().is_empty());
+
+        // The last screen frame is mixed with silence
+        assert_eq!(mixer.finish(), vec![screen_gain, screen_gain]);
+        assert!(mixer.finish().is_empty());
+        assert!(mixer.recipe().applied);
+
+        assert!(mixer.push(2, &[0.0, 0.0]).is_err());
+        assert!(mixer.push(0, &[0.0]).is_err());
+        assert!(DownmixMixerState::new(DownmixRecipe::default(), 2).is_err());
+        let silent = DownmixRecipe {
+            sources: vec![source("mic", f64::NEG_INFINITY)],
+            applied: false,
+        };
+        assert!(DownmixMixerState::new(silent, 2).is_err());
+    }
+
+    #[test]
+    fn test_stalled_source_is_filled_with_silence() {
+        let recipe = DownmixRecipe {
+            sources: vec![source("screen", 0.0), source("mic", 0.0)],
+            applied: false,
+        };
+        let mut mixer = DownmixMixerState::new(recipe, 1).unwrap();
+        mixer.push(1, &[0.5; 10]).unwrap();
+        // The microphone stalls while the screen keeps delivering
+        mixer.push(0, &vec![0.25; MAX_LAG_FRAMES + 20]).unwrap();
+        let mixed = mixer.take_mixed();
+        assert_eq!(mixed.len(), 20);
+        assert_eq!(mixed[9], 0.75);
+        assert_eq!(mixed[10], 0.25);
+        assert_eq!(mixer.filled_frames(), 10);
+        assert_eq!(
+            mixer.pending.iter().map(Vec::len).max(),
+            Some(MAX_LAG_FRAMES)
+        );
+
+        // Late samples for the silent stretch are dropped, the rest line up
+        mixer.push(1, &[0.0; 8]).unwrap();
+        assert!(mixer.take_mixed().is_empty());
+        mixer.push(1, &[0.0, 0.0, 0.5]).unwrap();
+        assert_eq!(mixer.take_mixed(), vec![0.75]);
+        assert_eq!(mixer.filled_frames(), 10);
+    }
+}
@@ -0,0 +1,51 @@
+//! Structured error object thrown across the WASM boundary.
+//!
+//! Internally the crate keeps using `Result<_, String>`; bindings that return
+//! typed values convert those messages into a `CoreError` so JS callers can
+//! branch on `kind` instead of matching message text.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+use wasm_bindgen::JsValue;
+
+/// Category of a CoreError
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorKind {
+    /// The caller passed a value that could not be parsed or validated
+    InvalidInput,
+    /// The operation is not allowed in the current state
+    InvalidState,
+}
+
+/// Error object thrown to JS by typed bindings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+pub struct CoreError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl CoreError {
+    /// Create an InvalidInput error
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        Self {
+            kind: ErrorKind::InvalidInput,
+            message: message.into(),
+        }
+    }
+
+    /// Create an InvalidState error
+    pub fn invalid_state(message: impl Into<String>) -> Self {
+        Self {
+            kind: ErrorKind::InvalidState,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<CoreError> for JsValue {
+    fn from(error: CoreError) -> Self {
+        serde_wasm_bindgen::to_value(&error).unwrap_or_else(|_| JsValue::from_str(&error.message))
+    }
+}
@@ -0,0 +1,145 @@
+//! Extracting one track of a finished recording.
+//!
+//! `extract_track` turns a muxed recording (init segment followed by its
+//! media segments) into a standalone MP4 holding only its audio or only its
+//! video, so an "audio only" download needs no server-side ffmpeg. The
+//! samples are read back out of every fragment and muxed again by a
+//! `Refragmenter`; the kept track's trak box is copied unchanged apart from
+//! its track ID.
+
+use crate::merge::{read_tracks, renumber_trak, set_alternate_group, set_translation};
+use crate::muxide_muxer::{
+    build_box, build_ftyp, build_mvhd, build_trex, parse_boxes, Refragmenter,
+    DEFAULT_MOVIE_TIMESCALE,
+};
+
+/// Build an MP4 holding only track `track_id` of `recording`
+///
+/// The track becomes track 1 of the output, enabled, outside any
+/// alternate group and at the origin. Fragment boundaries, decode times and sample flags are
+/// preserved.
+pub fn extract_track(recording: &[u8], track_id: u32) -> Result<Vec<u8>, String> {
+    let track = read_tracks(recording)?
+        .into_iter()
+        .find(|t| t.track_id == track_id)
+        .ok_or_else(|| format!("Recording has no track {}", track_id))?;
+    let mut refragmenter = Refragmenter::new(recording, 1)?;
+    refragmenter.select_track(track_id)?;
+
+    let mut moov = build_mvhd(DEFAULT_MOVIE_TIMESCALE, 2);
+    // A track of a merged file may be a disabled alternative; alone it plays
+    let mut trak = renumber_trak(&track.trak, 1)?;
+    set_alternate_group(&mut trak, 0, true)?;
+    // and a display moved to its region of a layout is shown at the origin
+    set_translation(&mut trak, 0, 0)?;
+    moov.extend_from_slice(&trak);
+    moov.extend_from_slice(&build_box(b"mvex", &build_trex(1)));
+    let mut out = build_ftyp();
+    out.extend_from_slice(&build_box(b"moov", &moov));
+
+    // Feed each moof with the mdat following it
+    let boxes = parse_boxes(recording)?;
+    let mut fragment_start = None;
+    for b in &boxes {
+        match &b.typ {
+            b"moof" => fragment_start = Some(b.offset),
+            b"mdat" => {
+                let start = fragment_start
+                    .take()
+                    .ok_or_else(|| format!("mdat at offset {} has no moof", b.offset))?;
+                let end = b.offset + 8 + b.payload.len();
+                if let Some(fragment) = refragmenter.push_segment(&recording[start..end])? {
+                    out.extend_from_slice(&fragment);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compat::compare_init_segments;
+    use crate::muxide_muxer::{trace_segment, FragmentTrace, MuxideConfig, MuxideMuxerState};
+
+    fn split_init(mp4: &[u8]) -> (&[u8], &[u8]) {
+        let moov = parse_boxes(mp4)
+            .unwrap()
+            .into_iter()
+            .find(|b| &b.typ == b"moov")
+            .unwrap();
+        mp4.split_at(moov.offset + 8 + moov.payload.len())
+    }
+
+    fn record(config: MuxideConfig) -> Vec<u8> {
+        let mut muxer = MuxideMuxerState::new(config);
+        muxer.init().unwrap();
+        let mut recording = muxer.get_init_segment().unwrap();
+        for i in 0..90u64 {
+            muxer
+                .push_video_chunk(&[0, 0, 0, 1, 0x65, i as u8], i * 33_333, i % 30 == 0)
+                .unwrap();
+            muxer
+                .push_audio_chunk(&[i as u8; 8], i * 21_333, 21_333)
+                .unwrap();
+        }
+        muxer.force_flush().unwrap();
+        for segment in muxer.get_pending_segments() {
+            recording.extend_from_slice(&segment);
+        }
+        recording
+    }
+
+    #[test]
+    fn test_extract_track() {
+        let video = MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        };
+        let audio = MuxideConfig {
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        };
+        let av = MuxideConfig {
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            ..video.clone()
+        };
+        let recording = record(av);
+
+        let (_, source_media) = split_init(&recording);
+        let source = trace_segment(source_media).unwrap();
+
+        // The init segment is what a single-track muxer would write, the
+        // fragments are the source's fragments of that track
+        for (track_id, config) in [(1, video), (2, audio)] {
+            let extracted = extract_track(&recording, track_id).unwrap();
+            let (init, media) = split_init(&extracted);
+            let mut muxer = MuxideMuxerState::new(config);
+            muxer.init().unwrap();
+            assert!(
+                compare_init_segments(init, &muxer.get_init_segment().unwrap())
+                    .unwrap()
+                    .compatible
+            );
+            let expected: Vec<_> = source
+                .iter()
+                .filter(|t| t.track_id == track_id)
+                .map(|t| FragmentTrace {
+                    track_id: 1,
+                    ..t.clone()
+                })
+                .collect();
+            assert_eq!(trace_segment(media).unwrap(), expected);
+        }
+
+        let error = extract_track(&recording, 3).unwrap_err();
+        assert_eq!(error, "Recording has no track 3");
+    }
+}
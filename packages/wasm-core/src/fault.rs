@@ -0,0 +1,438 @@
+//! Injected failures for resilience tests (`fault-injection` feature).
+//!
+//! Recovery paths (retrying a failed write, rebuilding from the write-ahead
+//! log, resending a chunk whose hash does not match) otherwise only run when
+//! something breaks in production. The wrappers here break things on
+//! purpose, on a deterministic schedule:
+//!
+//! - `FaultySink` fails `SegmentSink` writes, as a full disk or a closed
+//!   stream would;
+//! - `FaultyStore` fails `ChunkStore` writes and returns corrupted chunk
+//!   reads, whose hashes then no longer match the manifest;
+//! - `FaultyTransport` (`native`) fails uploads;
+//! - `TimestampFaults` makes frame timestamps jump, as after a suspended tab
+//!   or a misbehaving encoder.
+//!
+//! Each failure point is a `Fault`. Clones of a `Fault` share its counters,
+//! so a test keeps one to see how often it fired after handing the other to
+//! a wrapper.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::chunk::TrackKind;
+use crate::segment_sink::{MediaSegment, SegmentSink};
+use crate::session::SessionId;
+use crate::storage::{parse_chunk_file_name, ChunkStore};
+
+/// Which calls to a fault point fail, counting calls from 0
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FaultTrigger {
+    #[default]
+    Never,
+    /// Only call `n`
+    Once(u64),
+    /// `count` calls from call `start` on: a transient outage
+    Range { start: u64, count: u64 },
+    /// Every call from call `n` on: a dead component
+    From(u64),
+    /// Every `n`th call (calls `n - 1`, `2n - 1`, ...)
+    Every(u64),
+}
+
+impl FaultTrigger {
+    fn fires(&self, call: u64) -> bool {
+        match *self {
+            FaultTrigger::Never => false,
+            FaultTrigger::Once(n) => call == n,
+            FaultTrigger::Range { start, count } => (start..start + count).contains(&call),
+            FaultTrigger::From(n) => call >= n,
+            FaultTrigger::Every(n) => n > 0 && (call + 1).is_multiple_of(n),
+        }
+    }
+}
+
+/// One failure point and how often it was reached and fired
+#[derive(Debug, Clone, Default)]
+pub struct Fault {
+    trigger: FaultTrigger,
+    calls: Arc<AtomicU64>,
+    fired: Arc<AtomicU64>,
+}
+
+impl Fault {
+    pub fn new(trigger: FaultTrigger) -> Self {
+        Self {
+            trigger,
+            ..Default::default()
+        }
+    }
+
+    /// Count a call, returning whether it fails
+    pub fn check(&self) -> bool {
+        let call = self.calls.fetch_add(1, Ordering::Relaxed);
+        let fires = self.trigger.fires(call);
+        if fires {
+            self.fired.fetch_add(1, Ordering::Relaxed);
+        }
+        fires
+    }
+
+    /// Calls counted so far
+    pub fn calls(&self) -> u64 {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    /// Calls that failed so far
+    pub fn fired(&self) -> u64 {
+        self.fired.load(Ordering::Relaxed)
+    }
+}
+
+/// Error message of an injected failure
+fn injected(what: impl std::fmt::Display) -> String {
+    format!("Injected fault: {}", what)
+}
+
+/// `SegmentSink` whose writes fail when `fault` fires
+///
+/// A failed write is not passed on, like a write the destination refused.
+pub struct FaultySink<S> {
+    inner: S,
+    fault: Fault,
+}
+
+impl<S: SegmentSink> FaultySink<S> {
+    pub fn new(inner: S, fault: Fault) -> Self {
+        Self { inner, fault }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: SegmentSink> SegmentSink for FaultySink<S> {
+    fn write_init(&mut self, init: &[u8]) -> Result<(), String> {
+        if self.fault.check() {
+            return Err(injected("init segment write"));
+        }
+        self.inner.write_init(init)
+    }
+
+    fn write_segment(&mut self, segment: MediaSegment) -> Result<(), String> {
+        if self.fault.check() {
+            return Err(injected(format!("segment {} write", segment.sequence)));
+        }
+        self.inner.write_segment(segment)
+    }
+
+    fn finalize(&mut self) -> Result<(), String> {
+        self.inner.finalize()
+    }
+
+    fn buffered_segments(&self) -> usize {
+        self.inner.buffered_segments()
+    }
+
+    fn buffered_bytes(&self) -> u64 {
+        self.inner.buffered_bytes()
+    }
+
+    fn segment_offset(&self, init_len: u64, segments_len: u64) -> u64 {
+        self.inner.segment_offset(init_len, segments_len)
+    }
+}
+
+/// Failure points of a `FaultyStore`
+#[derive(Debug, Clone, Default)]
+pub struct StoreFaults {
+    /// Chunk files fail to write
+    pub chunk_writes: Fault,
+    /// Other files (init segment, manifest, WAL, snapshot...) fail to write
+    pub file_writes: Fault,
+    /// Chunk files read back with their last byte flipped
+    pub chunk_reads: Fault,
+}
+
+/// `ChunkStore` that fails writes and corrupts reads per its `StoreFaults`
+pub struct FaultyStore<S> {
+    inner: S,
+    faults: StoreFaults,
+}
+
+impl<S: ChunkStore> FaultyStore<S> {
+    pub fn new(inner: S, faults: StoreFaults) -> Self {
+        Self { inner, faults }
+    }
+
+    /// The wrapped store, to check what really got written
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: ChunkStore> ChunkStore for FaultyStore<S> {
+    async fn put_file(&self, session: &SessionId, name: &str, data: &[u8]) -> Result<(), String> {
+        let fault = match parse_chunk_file_name(session, name) {
+            Some(_) => &self.faults.chunk_writes,
+            None => &self.faults.file_writes,
+        };
+        if fault.check() {
+            return Err(injected(format!("write of {}/{}", session, name)));
+        }
+        self.inner.put_file(session, name, data).await
+    }
+
+    async fn get_file(&self, session: &SessionId, name: &str) -> Result<Option<Vec<u8>>, String> {
+        let mut data = self.inner.get_file(session, name).await?;
+        if let Some(last) = data.as_mut().and_then(|data| data.last_mut()) {
+            if parse_chunk_file_name(session, name).is_some() && self.faults.chunk_reads.check() {
+                *last ^= 0xFF;
+            }
+        }
+        Ok(data)
+    }
+
+    async fn delete_file(&self, session: &SessionId, name: &str) -> Result<(), String> {
+        self.inner.delete_file(session, name).await
+    }
+
+    async fn list_files(&self, session: &SessionId) -> Result<Vec<String>, String> {
+        self.inner.list_files(session).await
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<SessionId>, String> {
+        self.inner.list_sessions().await
+    }
+
+    async fn delete_session(&self, session: &SessionId) -> Result<(), String> {
+        self.inner.delete_session(session).await
+    }
+}
+
+/// `UploadTransport` whose uploads fail when `fault` fires
+#[cfg(feature = "native")]
+pub struct FaultyTransport<T> {
+    inner: T,
+    fault: Fault,
+}
+
+#[cfg(feature = "native")]
+impl<T: crate::uploader::UploadTransport> FaultyTransport<T> {
+    pub fn new(inner: T, fault: Fault) -> Self {
+        Self { inner, fault }
+    }
+}
+
+#[cfg(feature = "native")]
+impl<T: crate::uploader::UploadTransport> crate::uploader::UploadTransport for FaultyTransport<T> {
+    async fn put_init_segment(&self, session: &SessionId, data: &[u8]) -> Result<(), String> {
+        if self.fault.check() {
+            return Err(injected(format!("upload of {} init segment", session)));
+        }
+        self.inner.put_init_segment(session, data).await
+    }
+
+    async fn put_chunk(
+        &self,
+        id: &crate::chunk::ChunkId,
+        data: &[u8],
+        hash: &str,
+    ) -> Result<(), String> {
+        if self.fault.check() {
+            return Err(injected(format!("upload of chunk {}", id.sequence)));
+        }
+        self.inner.put_chunk(id, data, hash).await
+    }
+}
+
+/// A timestamp jump from one frame of a track on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampJump {
+    /// `Video`, `Audio`, or `Muxed` for both
+    pub track: TrackKind,
+    /// Frame of the track (counting from 0) the jump starts at
+    pub frame: u64,
+    /// Offset added to that frame and all after it; negative goes back
+    pub jump_us: i64,
+}
+
+/// Shifts frame timestamps by the jumps they come after
+#[derive(Debug, Clone, Default)]
+pub struct TimestampFaults {
+    jumps: Vec<TimestampJump>,
+    frames: BTreeMap<TrackKind, u64>,
+}
+
+impl TimestampFaults {
+    pub fn new(jumps: Vec<TimestampJump>) -> Self {
+        Self {
+            jumps,
+            frames: BTreeMap::new(),
+        }
+    }
+
+    /// Timestamp to push for the next frame of `track`, scheduled at
+    /// `timestamp_us`
+    pub fn apply(&mut self, track: TrackKind, timestamp_us: u64) -> u64 {
+        let frame = self.frames.entry(track).or_insert(0);
+        let offset: i64 = self
+            .jumps
+            .iter()
+            .filter(|jump| {
+                (jump.track == track || jump.track == TrackKind::Muxed) && jump.frame <= *frame
+            })
+            .map(|jump| jump.jump_us)
+            .sum();
+        *frame += 1;
+        timestamp_us.saturating_add_signed(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::ChunkManifest;
+    use crate::muxide_muxer::{MuxideConfig, MuxideMuxerState};
+    use crate::recorder::{RecorderEvent, RecorderState};
+    use crate::segment_sink::BufferedSink;
+    use crate::storage::memory::MemoryStore;
+
+    fn video_config() -> MuxideConfig {
+        MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E, 0xD9, 0x00, 0x50, 0x05]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            ..Default::default()
+        }
+    }
+
+    fn frame() -> Vec<u8> {
+        vec![0x00, 0x00, 0x00, 0x04, 0x65, 0x88, 0x84, 0x00]
+    }
+
+    #[test]
+    fn test_faulty_sink_and_timestamp_jumps() {
+        let fault = Fault::new(FaultTrigger::Once(2));
+        let sink = FaultySink::new(BufferedSink::default(), fault.clone());
+        let mut muxer = MuxideMuxerState::with_sink(video_config(), sink);
+        muxer.init().unwrap();
+        let mut errors = 0;
+        for i in 0..150u64 {
+            if muxer
+                .push_video_chunk(&frame(), i * 33_333, i % 30 == 0)
+                .is_err()
+            {
+                errors += 1;
+            }
+        }
+        // The init segment and one segment went through before the failure
+        assert_eq!((fault.fired(), errors), (1, 1));
+        assert!(fault.calls() > 2);
+
+        // Forward jumps leave a gap, backward ones are clamped
+        let mut timestamps = TimestampFaults::new(vec![
+            TimestampJump {
+                track: TrackKind::Video,
+                frame: 30,
+                jump_us: 3_000_000,
+            },
+            TimestampJump {
+                track: TrackKind::Muxed,
+                frame: 60,
+                jump_us: -2_000_000,
+            },
+        ]);
+        assert_eq!(timestamps.apply(TrackKind::Audio, 5), 5);
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        recorder.start().unwrap();
+        for i in 0..90u64 {
+            let ts = timestamps.apply(TrackKind::Video, i * 33_333);
+            recorder.push_video(&frame(), ts, i % 30 == 0).unwrap();
+        }
+        recorder.stop().unwrap();
+        let quality = recorder.quality_report();
+        assert!(quality
+            .gaps
+            .iter()
+            .any(|gap| gap.end_us - gap.start_us > 2_900_000));
+        assert!(quality.corrected_frames > 0);
+    }
+
+    #[tokio::test]
+    async fn test_store_faults_and_wal_replay() {
+        let session = SessionId::from("s1");
+        let faults = StoreFaults {
+            chunk_writes: Fault::new(FaultTrigger::Range { start: 1, count: 2 }),
+            chunk_reads: Fault::new(FaultTrigger::Once(0)),
+            ..Default::default()
+        };
+        let store = FaultyStore::new(MemoryStore::default(), faults.clone());
+
+        let mut recorder = RecorderState::new(session.clone(), video_config());
+        recorder.enable_wal(500).unwrap();
+        store
+            .put_init_segment(&session, &recorder.start().unwrap())
+            .await
+            .unwrap();
+        for i in 0..200u64 {
+            recorder
+                .push_video(&frame(), i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+
+        // Two attempts per chunk: the outage outlasts chunk 1's retry
+        let mut persisted = ChunkManifest::new(session.clone());
+        let mut lost = Vec::new();
+        for event in recorder.take_events() {
+            match event {
+                RecorderEvent::ChunkReady(chunk) => {
+                    let id = &chunk.metadata.chunk_id;
+                    if store.put_chunk(id, &chunk.data).await.is_ok()
+                        || store.put_chunk(id, &chunk.data).await.is_ok()
+                    {
+                        persisted.add_chunk(chunk.metadata).unwrap();
+                    } else {
+                        lost.push(chunk.metadata);
+                    }
+                }
+                RecorderEvent::WalBatch(batch) => {
+                    store.put_wal_batch(&session, &batch).await.unwrap();
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(faults.chunk_writes.fired(), 2);
+        assert_eq!(lost.len(), 1);
+        assert_eq!(lost[0].chunk_id.sequence, 1);
+        assert_eq!(persisted.chunk_count(), 2);
+
+        // A corrupted read no longer matches its hash; the next one does
+        let first = &persisted.chunks[0];
+        let hash = |data: &[u8]| blake3::hash(data).to_hex().to_string();
+        let corrupted = store.get_chunk(&first.chunk_id).await.unwrap();
+        assert_ne!(Some(hash(&corrupted)), first.hash);
+        let intact = store.get_chunk(&first.chunk_id).await.unwrap();
+        assert_eq!(Some(hash(&intact)), first.hash);
+
+        // After a crash, replaying the WAL brings back the lost chunk byte
+        // for byte, and only the chunks that were never stored
+        drop(recorder);
+        let wal = store.get_wal(&session).await.unwrap();
+        let mut recovered =
+            RecorderState::recover(session, video_config(), Some(persisted.clone()), &wal).unwrap();
+        let replayed: Vec<_> = recovered
+            .take_events()
+            .into_iter()
+            .filter_map(|event| match event {
+                RecorderEvent::ChunkReady(chunk) => Some(chunk.metadata),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(replayed[0].hash, lost[0].hash);
+        assert!(replayed
+            .iter()
+            .all(|chunk| !persisted.contains(&chunk.chunk_id)));
+    }
+}
@@ -0,0 +1,200 @@
+//! Per-fragment checksum boxes (`MuxideConfig::fragment_checksums`).
+//!
+//! With the option on, every fragment the muxer writes is followed by a
+//! top-level `uuid` box holding the BLAKE3 hash of the moof + mdat before
+//! it. Players skip boxes they do not know, and anything holding the bytes
+//! (the validator, the server on upload, a recovery tool reading a bare
+//! file) can check each fragment on its own, with no manifest and no other
+//! chunk. Mirrors `Blake3FragmentChecksumVerifier.ts` on the server.
+//!
+//! Box layout: size, `uuid`, `FRAGMENT_CHECKSUM_UUID`, version (0) and
+//! flags (0) as in a full box, then the 32-byte hash.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::muxide_muxer::{build_box, parse_boxes};
+
+/// Extended type of the checksum box (c7f1a2e4-5b3d-4e8a-9f26-8d4c1b7e3a90)
+pub const FRAGMENT_CHECKSUM_UUID: [u8; 16] = [
+    0xC7, 0xF1, 0xA2, 0xE4, 0x5B, 0x3D, 0x4E, 0x8A, 0x9F, 0x26, 0x8D, 0x4C, 0x1B, 0x7E, 0x3A, 0x90,
+];
+
+/// Bytes a checksum box adds to every fragment
+pub const FRAGMENT_CHECKSUM_BOX_SIZE: usize = 8 + 16 + 4 + 32;
+
+/// Build the checksum box of a fragment (moof + mdat)
+pub fn build_checksum_box(fragment: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(FRAGMENT_CHECKSUM_BOX_SIZE - 8);
+    payload.extend_from_slice(&FRAGMENT_CHECKSUM_UUID);
+    payload.extend_from_slice(&[0; 4]);
+    payload.extend_from_slice(blake3::hash(fragment).as_bytes());
+    build_box(b"uuid", &payload)
+}
+
+/// Outcome of checking the fragment checksums of a file or chunk
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct FragmentChecksumReport {
+    /// moof boxes found
+    pub fragments: u32,
+    /// Fragments whose checksum matched
+    pub verified: u32,
+    /// Offsets of the moof of fragments whose checksum did not match
+    pub mismatched: Vec<u64>,
+    /// Fragments without a checksum box (written with the option off)
+    pub unchecked: u32,
+}
+
+impl FragmentChecksumReport {
+    /// Fail if any fragment did not match its checksum
+    pub fn ensure(&self) -> Result<(), String> {
+        match self.mismatched.first() {
+            Some(offset) => Err(format!(
+                "Fragment checksum mismatch: {} of {} fragments, first at offset {}",
+                self.mismatched.len(),
+                self.fragments,
+                offset
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Check every checksum box in `data` (a recording, its chunks or any run of
+/// whole top-level boxes) against the fragment before it
+///
+/// A checksum covers everything from its fragment's moof up to the box
+/// itself. Fails only if the boxes cannot be parsed or a checksum box
+/// follows no moof.
+pub fn verify_fragment_checksums(data: &[u8]) -> Result<FragmentChecksumReport, String> {
+    let mut report = FragmentChecksumReport::default();
+    let mut fragment_start = None;
+    for mp4_box in parse_boxes(data)? {
+        match &mp4_box.typ {
+            b"moof" => {
+                if fragment_start.replace(mp4_box.offset).is_some() {
+                    report.unchecked += 1;
+                }
+                report.fragments += 1;
+            }
+            b"uuid" if mp4_box.payload.starts_with(&FRAGMENT_CHECKSUM_UUID) => {
+                let start = fragment_start.take().ok_or_else(|| {
+                    format!("Checksum box at {} follows no fragment", mp4_box.offset)
+                })?;
+                let hash = mp4_box
+                    .payload
+                    .get(20..52)
+                    .ok_or("Truncated fragment checksum box")?;
+                if blake3::hash(&data[start..mp4_box.offset]).as_bytes() == hash {
+                    report.verified += 1;
+                } else {
+                    report.mismatched.push(start as u64);
+                }
+            }
+            _ => {}
+        }
+    }
+    if fragment_start.is_some() {
+        report.unchecked += 1;
+    }
+    Ok(report)
+}
+
+/// The fragments of `data` with their checksum boxes removed, e.g. for
+/// tools that expect only moof/mdat pairs
+///
+/// Not for `DataOffsetMode::Absolute` output, whose tfhd offsets count the
+/// boxes.
+pub fn strip_fragment_checksums(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut pos = 0;
+    for mp4_box in parse_boxes(data)? {
+        if &mp4_box.typ == b"uuid" && mp4_box.payload.starts_with(&FRAGMENT_CHECKSUM_UUID) {
+            out.extend_from_slice(&data[pos..mp4_box.offset]);
+            pos = mp4_box.offset + 8 + mp4_box.payload.len();
+        }
+    }
+    out.extend_from_slice(&data[pos..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::extract_track;
+    use crate::muxide_muxer::MuxideConfig;
+    use crate::recorder::{RecorderEvent, RecorderState};
+    use crate::session::SessionId;
+
+    #[test]
+    fn test_checksummed_recording() {
+        let mut recorder = RecorderState::new(
+            SessionId::from("s1"),
+            MuxideConfig {
+                sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+                pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+                audio_sample_rate: Some(48000),
+                audio_channels: Some(2),
+                fragment_duration_ms: 1000,
+                fragment_checksums: true,
+                ..Default::default()
+            },
+        );
+        let mut file = recorder.start().unwrap();
+        for i in 0..150u64 {
+            recorder
+                .push_video(&[0, 0, 0, 2, 0x65, i as u8], i * 33_333, i % 30 == 0)
+                .unwrap();
+            recorder
+                .push_audio(&[i as u8; 8], i * 21_333, 21_333)
+                .unwrap();
+        }
+        recorder.stop().unwrap();
+        let mut chunks = Vec::new();
+        for event in recorder.take_events() {
+            match event {
+                RecorderEvent::ChunkReady(chunk) => chunks.push(chunk.data),
+                RecorderEvent::InitSegmentChanged(init) => file = init,
+                _ => {}
+            }
+        }
+        // Each chunk checks out on its own
+        for chunk in &chunks {
+            let report = verify_fragment_checksums(chunk).unwrap();
+            assert_eq!((report.verified, report.unchecked), (report.fragments, 0));
+        }
+        file.extend(chunks.concat());
+
+        let report = verify_fragment_checksums(&file).unwrap();
+        assert_eq!(
+            report.fragments as usize,
+            recorder.manifest().fragments.len()
+        );
+        assert_eq!(report.verified, report.fragments);
+        report.ensure().unwrap();
+
+        // The range map covers the checksum boxes, and readers skip them
+        let last = recorder.manifest().fragments.last().unwrap();
+        assert_eq!(last.offset + last.size, file.len() as u64);
+        assert!(!extract_track(&file, 1).unwrap().is_empty());
+        let stripped = strip_fragment_checksums(&file).unwrap();
+        assert_eq!(
+            file.len() - stripped.len(),
+            report.fragments as usize * FRAGMENT_CHECKSUM_BOX_SIZE
+        );
+        assert_eq!(
+            verify_fragment_checksums(&stripped).unwrap().unchecked,
+            report.fragments
+        );
+
+        // A flipped bit in an mdat is caught
+        let second = recorder.manifest().fragments[1].offset as usize;
+        let position = second + 200;
+        file[position] ^= 1;
+        let report = verify_fragment_checksums(&file).unwrap();
+        assert_eq!(report.mismatched, vec![second as u64]);
+        assert!(report.ensure().unwrap_err().contains("first at offset"));
+    }
+}
@@ -0,0 +1,178 @@
+//! Effective frame rate of the recorded video.
+//!
+//! Cameras and screen capture deliver frames at whatever rate they manage,
+//! which is often not the rate the caller asked for. `FrameRateEstimator`
+//! measures it from the timestamps of the frames actually muxed: a windowed
+//! rate for live display, and a histogram of frame intervals for the final
+//! average, percentiles and variable-frame-rate flag stored in
+//! `RecordingMetadata.framerate`.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::metadata::FrameRateStats;
+
+/// Default length of the window for the current frame rate
+pub const DEFAULT_FRAME_RATE_WINDOW_MS: u32 = 2000;
+
+/// Interval histogram resolution (microseconds)
+const BUCKET_US: u64 = 100;
+/// Spread between the 5th and 95th percentile rate, relative to the median,
+/// above which a recording counts as variable frame rate
+const VFR_TOLERANCE: f64 = 0.1;
+
+/// Estimates frame rate from video timestamps (microseconds)
+#[derive(Debug, Clone)]
+pub struct FrameRateEstimator {
+    window_us: u64,
+    window: VecDeque<u64>,
+    first_us: Option<u64>,
+    last_us: Option<u64>,
+    frame_count: u64,
+    /// Frame interval bucket -> number of intervals
+    intervals: BTreeMap<u64, u64>,
+    interval_count: u64,
+}
+
+impl FrameRateEstimator {
+    /// Create an estimator whose current rate covers the last `window_ms`
+    pub fn new(window_ms: u32) -> Self {
+        Self {
+            window_us: window_ms.max(1) as u64 * 1000,
+            window: VecDeque::new(),
+            first_us: None,
+            last_us: None,
+            frame_count: 0,
+            intervals: BTreeMap::new(),
+            interval_count: 0,
+        }
+    }
+
+    /// Record a frame; frames not after the previous one are ignored
+    pub fn push(&mut self, timestamp_us: u64) {
+        if let Some(last) = self.last_us {
+            if timestamp_us <= last {
+                return;
+            }
+            let bucket = ((timestamp_us - last) + BUCKET_US / 2) / BUCKET_US;
+            *self.intervals.entry(bucket.max(1)).or_default() += 1;
+            self.interval_count += 1;
+        }
+        self.first_us.get_or_insert(timestamp_us);
+        self.last_us = Some(timestamp_us);
+        self.frame_count += 1;
+
+        self.window.push_back(timestamp_us);
+        while let Some(&oldest) = self.window.front() {
+            if timestamp_us - oldest <= self.window_us {
+                break;
+            }
+            self.window.pop_front();
+        }
+    }
+
+    /// Frames recorded so far
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Frame rate over the window ending at the latest frame
+    pub fn current_fps(&self) -> Option<f64> {
+        let (first, last) = (*self.window.front()?, *self.window.back()?);
+        rate(self.window.len() as u64 - 1, last - first)
+    }
+
+    /// Frame rate over the whole recording
+    pub fn average_fps(&self) -> Option<f64> {
+        rate(self.interval_count, self.last_us? - self.first_us?)
+    }
+
+    /// Summary for `RecordingMetadata`, once two frames have been recorded
+    pub fn stats(&self) -> Option<FrameRateStats> {
+        let average_fps = self.average_fps()?;
+        // Long intervals are low rates, so the 5th percentile rate comes from
+        // the 95th percentile interval
+        let p5_fps = self.interval_fps(0.95)?;
+        let median_fps = self.interval_fps(0.5)?;
+        let p95_fps = self.interval_fps(0.05)?;
+        Some(FrameRateStats {
+            average_fps,
+            median_fps,
+            p5_fps,
+            p95_fps,
+            variable: (p95_fps - p5_fps) / median_fps > VFR_TOLERANCE,
+            frame_count: self.frame_count,
+        })
+    }
+
+    /// Instantaneous rate of the interval at `quantile` (0 = shortest)
+    fn interval_fps(&self, quantile: f64) -> Option<f64> {
+        if self.interval_count == 0 {
+            return None;
+        }
+        let target = ((self.interval_count - 1) as f64 * quantile).round() as u64;
+        let mut seen = 0;
+        for (&bucket, &count) in &self.intervals {
+            seen += count;
+            if seen > target {
+                return rate(1, bucket * BUCKET_US);
+            }
+        }
+        None
+    }
+}
+
+impl Default for FrameRateEstimator {
+    fn default() -> Self {
+        Self::new(DEFAULT_FRAME_RATE_WINDOW_MS)
+    }
+}
+
+fn rate(frames: u64, duration_us: u64) -> Option<f64> {
+    (frames > 0 && duration_us > 0).then(|| frames as f64 * 1_000_000.0 / duration_us as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_frame_rate() {
+        let mut estimator = FrameRateEstimator::default();
+        assert_eq!(estimator.stats(), None);
+        for i in 0..300u64 {
+            estimator.push(i * 1_000_000 / 30);
+        }
+        // Out-of-order frames are ignored
+        estimator.push(0);
+
+        let stats = estimator.stats().unwrap();
+        assert_eq!(stats.frame_count, 300);
+        assert!((stats.average_fps - 30.0).abs() < 0.01);
+        assert!((stats.median_fps - 30.0).abs() < 0.1);
+        assert!(!stats.variable);
+        assert!((estimator.current_fps().unwrap() - 30.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_variable_frame_rate() {
+        // 5 s at 30 fps, then 5 s of a screen capture dropping to 10 fps
+        let mut estimator = FrameRateEstimator::new(1000);
+        let mut ts = 0;
+        for _ in 0..150 {
+            estimator.push(ts);
+            ts += 33_333;
+        }
+        for _ in 0..50 {
+            estimator.push(ts);
+            ts += 100_000;
+        }
+
+        assert!((estimator.current_fps().unwrap() - 10.0).abs() < 0.01);
+        let stats = estimator.stats().unwrap();
+        assert!((stats.average_fps - 199.0 / 9.89995).abs() < 0.001);
+        assert!((stats.p95_fps - 30.0).abs() < 0.1);
+        assert!((stats.p5_fps - 10.0).abs() < 0.1);
+        assert!((stats.median_fps - 30.0).abs() < 0.1);
+        assert!(stats.variable);
+    }
+}
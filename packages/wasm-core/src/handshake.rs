@@ -0,0 +1,323 @@
+//! Ingest handshake, exchanged before the first chunk upload.
+//!
+//! Mirrors `handshake.ts` in `@maycast/common-types`: the client sends its
+//! `IngestCapabilities`, the server answers with its own and the outcome of
+//! `negotiate_ingest`, which both sides compute the same way. Peers agree on
+//! the newest protocol version both speak and on the codecs, containers and
+//! features both support, so an old client talking to a new server (or the
+//! reverse) keeps uploading with what they have in common.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::muxide_muxer::MuxideConfig;
+
+/// Ingest protocol version this build speaks
+pub const INGEST_PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest ingest protocol version this build still speaks
+pub const MIN_INGEST_PROTOCOL_VERSION: u32 = 1;
+
+/// Container of chunks as the recorder writes them: init segment + moof/mdat
+pub const CONTAINER_FMP4: &str = "fmp4";
+
+/// Protocol features the recorder can use when the server supports them
+pub const CLIENT_FEATURES: [&str; 2] = ["chunk-hash", "integrity-report"];
+
+/// Feature asking the server to verify fragment checksum boxes, offered
+/// when the muxer writes them (`MuxideConfig::fragment_checksums`)
+pub const FRAGMENT_CHECKSUM_FEATURE: &str = "fragment-checksum";
+
+/// Feature offered when chunks are encrypted (`encryption.rs`): they are not
+/// plain fMP4, so the server skips their fragment checksums
+pub const ENCRYPTED_CHUNKS_FEATURE: &str = "encrypted-chunks";
+
+/// Feature offered when uploading content-defined chunks (`cdc.rs`), which
+/// are cut anywhere inside boxes
+pub const CDC_CHUNKS_FEATURE: &str = "cdc-chunks";
+
+/// What one side of an upload supports; lists are in order of preference
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestCapabilities {
+    /// Newest protocol version spoken
+    pub protocol_version: u32,
+    /// Oldest protocol version spoken; `protocol_version` if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    pub min_protocol_version: Option<u32>,
+    /// RFC 6381 codec strings, e.g. `avc1.42C01E`, `mp4a.40.2`
+    pub codecs: Vec<String>,
+    pub containers: Vec<String>,
+    #[serde(default)]
+    #[tsify(optional)]
+    pub features: Vec<String>,
+    /// Free-form client or server name and version, for logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    pub agent: Option<String>,
+}
+
+impl IngestCapabilities {
+    /// Capabilities of a recorder muxing with `config`
+    pub fn for_config(config: &MuxideConfig) -> Self {
+        let mut features: Vec<String> = CLIENT_FEATURES.iter().map(|f| f.to_string()).collect();
+        if config.fragment_checksums {
+            features.push(FRAGMENT_CHECKSUM_FEATURE.to_string());
+        }
+        Self {
+            protocol_version: INGEST_PROTOCOL_VERSION,
+            min_protocol_version: Some(MIN_INGEST_PROTOCOL_VERSION),
+            codecs: codec_strings(config),
+            containers: vec![CONTAINER_FMP4.to_string()],
+            features,
+            agent: Some(format!("maycast-wasm-core/{}", env!("CARGO_PKG_VERSION"))),
+        }
+    }
+
+    fn min_version(&self) -> u32 {
+        self.min_protocol_version.unwrap_or(self.protocol_version)
+    }
+}
+
+/// Why a handshake failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "kebab-case")]
+pub enum RejectReason {
+    ProtocolVersion,
+    Codecs,
+    Containers,
+}
+
+/// Outcome of a handshake, the same whichever side computes it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(untagged)]
+pub enum IngestNegotiation {
+    Accepted(AcceptedIngest),
+    Rejected(RejectedIngest),
+}
+
+/// What both sides use from now on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub struct AcceptedIngest {
+    /// Always true, to tell it from `RejectedIngest` in JSON
+    pub accepted: bool,
+    pub protocol_version: u32,
+    /// Codecs both support, in the client's order
+    pub codecs: Vec<String>,
+    pub containers: Vec<String>,
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub struct RejectedIngest {
+    /// Always false
+    pub accepted: bool,
+    pub reason: RejectReason,
+    pub message: String,
+}
+
+impl IngestNegotiation {
+    fn rejected(reason: RejectReason, message: String) -> Self {
+        Self::Rejected(RejectedIngest {
+            accepted: false,
+            reason,
+            message,
+        })
+    }
+
+    /// The agreed terms, or the reason the upload cannot go ahead
+    pub fn into_result(self) -> Result<AcceptedIngest, String> {
+        match self {
+            Self::Accepted(accepted) => Ok(accepted),
+            Self::Rejected(rejected) => Err(rejected.message),
+        }
+    }
+}
+
+/// Whether codec strings name the same codec; a bare sample entry
+/// (`avc1`) matches every profile of it
+fn codec_matches(a: &str, b: &str) -> bool {
+    let a = a.to_ascii_lowercase();
+    let b = b.to_ascii_lowercase();
+    let (entry_a, profile_a) = a.split_once('.').unwrap_or((&a, ""));
+    let (entry_b, profile_b) = b.split_once('.').unwrap_or((&b, ""));
+    entry_a == entry_b && (profile_a.is_empty() || profile_b.is_empty() || profile_a == profile_b)
+}
+
+/// Negotiate an upload between `client` and `server`
+///
+/// Picks the newest protocol version both speak and keeps the client's
+/// codecs, containers and features the server also supports. Rejects only
+/// when the version ranges do not overlap or nothing the client sends can
+/// be stored; unknown features are left out.
+pub fn negotiate_ingest(
+    client: &IngestCapabilities,
+    server: &IngestCapabilities,
+) -> IngestNegotiation {
+    let protocol_version = client.protocol_version.min(server.protocol_version);
+    if protocol_version < client.min_version().max(server.min_version()) {
+        return IngestNegotiation::rejected(
+            RejectReason::ProtocolVersion,
+            format!(
+                "Client speaks ingest protocol {}-{}, server {}-{}",
+                client.min_version(),
+                client.protocol_version,
+                server.min_version(),
+                server.protocol_version
+            ),
+        );
+    }
+
+    let codecs: Vec<String> = client
+        .codecs
+        .iter()
+        .filter(|codec| server.codecs.iter().any(|s| codec_matches(codec, s)))
+        .cloned()
+        .collect();
+    if codecs.is_empty() {
+        return IngestNegotiation::rejected(
+            RejectReason::Codecs,
+            format!(
+                "Server supports none of the codecs {}",
+                client.codecs.join(", ")
+            ),
+        );
+    }
+
+    let containers: Vec<String> = client
+        .containers
+        .iter()
+        .filter(|container| server.containers.contains(container))
+        .cloned()
+        .collect();
+    if containers.is_empty() {
+        return IngestNegotiation::rejected(
+            RejectReason::Containers,
+            format!(
+                "Server supports none of the containers {}",
+                client.containers.join(", ")
+            ),
+        );
+    }
+
+    let features = client
+        .features
+        .iter()
+        .filter(|feature| server.features.contains(feature))
+        .cloned()
+        .collect();
+    IngestNegotiation::Accepted(AcceptedIngest {
+        accepted: true,
+        protocol_version,
+        codecs,
+        containers,
+        features,
+    })
+}
+
+/// RFC 6381 codec strings of the tracks `config` describes
+pub(crate) fn codec_strings(config: &MuxideConfig) -> Vec<String> {
+    let mut codecs = Vec::new();
+    if config.has_video() {
+        // Profile, constraint flags and level follow the NAL header byte
+        codecs.push(match config.sps.as_deref().and_then(|sps| sps.get(1..4)) {
+            Some(profile) => format!(
+                "avc1.{:02X}{:02X}{:02X}",
+                profile[0], profile[1], profile[2]
+            ),
+            None => "avc1".to_string(),
+        });
+    }
+    if config.has_audio() {
+        // The audio object type is the top 5 bits of the AudioSpecificConfig
+        let object_type = config
+            .audio_specific_config
+            .as_deref()
+            .and_then(|asc| asc.first())
+            .map_or(2, |byte| byte >> 3);
+        codecs.push(format!("mp4a.40.{}", object_type));
+    }
+    codecs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> IngestCapabilities {
+        IngestCapabilities {
+            protocol_version: 2,
+            min_protocol_version: Some(1),
+            codecs: vec!["avc1".to_string(), "mp4a".to_string()],
+            containers: vec!["fmp4".to_string()],
+            features: vec!["chunk-hash".to_string(), "resumable".to_string()],
+            agent: None,
+        }
+    }
+
+    #[test]
+    fn test_negotiate_ingest() {
+        let config = MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            ..Default::default()
+        };
+        let client = IngestCapabilities::for_config(&config);
+        assert_eq!(client.codecs, vec!["avc1.42C01E", "mp4a.40.2"]);
+
+        // A newer server falls back to the client's version and features
+        let accepted = negotiate_ingest(&client, &server()).into_result().unwrap();
+        assert_eq!(accepted.protocol_version, 1);
+        assert_eq!(accepted.codecs, client.codecs);
+        assert_eq!(accepted.features, vec!["chunk-hash"]);
+
+        // Same JSON as common-types
+        let json = serde_json::to_value(IngestNegotiation::Accepted(accepted)).unwrap();
+        assert_eq!(json["accepted"], true);
+        assert_eq!(json["protocolVersion"], 1);
+        let old: IngestCapabilities = serde_json::from_str(
+            r#"{"protocolVersion":1,"codecs":["avc1"],"containers":["fmp4"]}"#,
+        )
+        .unwrap();
+        assert!(old.features.is_empty());
+        let checksummed = IngestCapabilities::for_config(&MuxideConfig {
+            fragment_checksums: true,
+            ..config.clone()
+        });
+        assert_eq!(
+            checksummed.features.last().unwrap(),
+            FRAGMENT_CHECKSUM_FEATURE
+        );
+
+        // A server that dropped version 1, or another codec, refuses
+        let newer = IngestCapabilities {
+            protocol_version: 3,
+            min_protocol_version: Some(2),
+            ..server()
+        };
+        match negotiate_ingest(&client, &newer) {
+            IngestNegotiation::Rejected(rejected) => {
+                assert_eq!(rejected.reason, RejectReason::ProtocolVersion);
+                assert_eq!(
+                    rejected.message,
+                    "Client speaks ingest protocol 1-1, server 2-3"
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let hevc = IngestCapabilities {
+            codecs: vec!["hvc1.1.6.L93.B0".to_string()],
+            ..client.clone()
+        };
+        let json = serde_json::to_value(negotiate_ingest(&hevc, &server())).unwrap();
+        assert_eq!(json["accepted"], false);
+        assert_eq!(json["reason"], "codecs");
+    }
+}
@@ -0,0 +1,111 @@
+//! BLAKE3 hashing of finished segments off the push path.
+//!
+//! Every chunk carries the BLAKE3 hash of its data. Hashing a 10 MB
+//! keyframe-heavy fragment takes tens of milliseconds on a slow device, and
+//! on the push that finishes the fragment that is enough to drop frames.
+//! `HashStrategy` picks how the recorder spends that time: all at once, across
+//! threads with rayon (native builds with the `parallel-hash` feature), a
+//! slice at a time on the following pushes and in `pump_hashes` calls, or on
+//! a thread pool of Web Workers together with encryption (`threads`), the
+//! chunk being handed out once its hash is complete.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+/// Bytes hashed per push under `HashStrategy::Incremental`, about 1 ms of
+/// work on a slow phone
+pub const HASH_SLICE_BYTES: usize = 512 * 1024;
+
+/// Segments below this size are hashed on one thread even with
+/// `HashStrategy::Parallel`, where spreading the work costs more than it saves
+pub const PARALLEL_HASH_MIN_BYTES: usize = 128 * 1024;
+
+/// How the recorder hashes finished segments
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "lowercase")]
+pub enum HashStrategy {
+    /// On the push that finishes the segment
+    #[default]
+    Inline,
+    /// On the push that finishes the segment, across rayon's thread pool
+    /// (inline without the `parallel-hash` feature)
+    Parallel,
+    /// `HASH_SLICE_BYTES` per push, the chunk being handed out once hashed
+    Incremental,
+    /// Encrypted and hashed on the thread pool while pushes go on, the chunk
+    /// being handed out once done (inline without the `wasm-threads` feature
+    /// or before the pool is started)
+    Background,
+}
+
+/// Hash of `data` (hex), spreading large inputs across threads under
+/// `HashStrategy::Parallel`
+pub fn hash_hex(data: &[u8], strategy: HashStrategy) -> String {
+    #[cfg(feature = "parallel-hash")]
+    if strategy == HashStrategy::Parallel && data.len() >= PARALLEL_HASH_MIN_BYTES {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_rayon(data);
+        return hasher.finalize().to_hex().to_string();
+    }
+    #[cfg(not(feature = "parallel-hash"))]
+    let _ = strategy;
+    blake3::hash(data).to_hex().to_string()
+}
+
+/// A hash computed a slice at a time
+#[derive(Debug, Clone, Default)]
+pub struct IncrementalHash {
+    hasher: blake3::Hasher,
+    /// Bytes of the data hashed so far
+    hashed: usize,
+}
+
+impl IncrementalHash {
+    /// Hash up to `budget` more bytes of `data`, returning how many were
+    ///
+    /// `data` must be the same on every call.
+    pub fn advance(&mut self, data: &[u8], budget: usize) -> usize {
+        let end = data.len().min(self.hashed.saturating_add(budget));
+        let slice = &data[self.hashed..end];
+        self.hasher.update(slice);
+        self.hashed = end;
+        slice.len()
+    }
+
+    /// Whether all of `data` has been hashed
+    pub fn is_done(&self, data: &[u8]) -> bool {
+        self.hashed == data.len()
+    }
+
+    /// Hash (hex) of the bytes hashed so far
+    pub fn finish(&self) -> String {
+        self.hasher.finalize().to_hex().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strategies_agree() {
+        let data: Vec<u8> = (0..3 * PARALLEL_HASH_MIN_BYTES as u32)
+            .map(|i| (i * 7 + i / 251) as u8)
+            .collect();
+        let expected = blake3::hash(&data).to_hex().to_string();
+        assert_eq!(hash_hex(&data, HashStrategy::Inline), expected);
+        assert_eq!(hash_hex(&data, HashStrategy::Parallel), expected);
+
+        let mut hash = IncrementalHash::default();
+        let mut steps = 0;
+        while !hash.is_done(&data) {
+            assert!(hash.advance(&data, 100_000) > 0);
+            steps += 1;
+        }
+        assert_eq!(steps, 4);
+        assert_eq!(hash.advance(&data, 100_000), 0);
+        assert_eq!(hash.finish(), expected);
+        assert!(IncrementalHash::default().is_done(&[]));
+    }
+}
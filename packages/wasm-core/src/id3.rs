@@ -0,0 +1,159 @@
+//! ID3 timed metadata for markers.
+//!
+//! HLS consumers expect in-band events as ID3v2 tags carried in a metadata
+//! elementary stream of the MPEG-TS output (stream type 0x15), each stamped
+//! with the PTS it applies to. This module builds those tags: every marker
+//! becomes an ID3v2.4 tag with a `TXXX` frame (description `MARKER`, value
+//! the marker's label) at the 90 kHz PTS of its position.
+//!
+//! The tags do not depend on the container. The recorder currently only
+//! writes fMP4, so the PES packetization of these samples is left to the
+//! MPEG-TS backend; until then they can be served as an HLS ID3 side
+//! stream.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::manifest::Marker;
+use crate::timebase::{Rounding, TimeBase};
+
+/// MPEG-TS presentation clock
+pub const MPEG_TS_CLOCK: TimeBase = TimeBase::new(90_000);
+
+/// PTS values are 33 bits and wrap around
+const PTS_MASK: u64 = (1 << 33) - 1;
+
+/// `TXXX` description of marker frames
+pub const MARKER_ID3_DESCRIPTION: &str = "MARKER";
+
+/// Text encoding byte for UTF-8 (ID3v2.4)
+const ENCODING_UTF8: u8 = 3;
+
+/// One ID3 tag to be presented at `pts`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct TimedMetadata {
+    /// Presentation time on the 90 kHz MPEG-TS clock
+    pub pts: u64,
+    /// Complete ID3v2.4 tag
+    pub data: Vec<u8>,
+}
+
+/// Timed ID3 tag of a marker; `pts_offset` is the PTS of session time 0 in
+/// the output stream
+pub fn marker_id3(marker: &Marker, pts_offset: u64) -> TimedMetadata {
+    let pts = MPEG_TS_CLOCK.from_us(marker.timestamp_us, Rounding::Nearest);
+    let label = marker.label.as_deref().unwrap_or_default();
+    TimedMetadata {
+        pts: (pts + pts_offset) & PTS_MASK,
+        data: id3_tag(&[txxx_frame(MARKER_ID3_DESCRIPTION, label)]),
+    }
+}
+
+/// Timed ID3 tags of all markers, in presentation order (markers at the same
+/// timestamp keep their input order)
+pub fn marker_id3_track(markers: &[Marker], pts_offset: u64) -> Vec<TimedMetadata> {
+    let mut sorted: Vec<&Marker> = markers.iter().collect();
+    sorted.sort_by_key(|marker| marker.timestamp_us);
+    sorted
+        .into_iter()
+        .map(|marker| marker_id3(marker, pts_offset))
+        .collect()
+}
+
+/// `TXXX` (user-defined text) frame
+fn txxx_frame(description: &str, value: &str) -> Vec<u8> {
+    let mut payload = vec![ENCODING_UTF8];
+    payload.extend_from_slice(description.as_bytes());
+    payload.push(0);
+    payload.extend_from_slice(value.as_bytes());
+    id3_frame(b"TXXX", &payload)
+}
+
+fn id3_frame(id: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut frame = id.to_vec();
+    frame.extend_from_slice(&syncsafe(payload.len()));
+    frame.extend_from_slice(&[0, 0]); // Flags
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// ID3v2.4 tag holding `frames`
+fn id3_tag(frames: &[Vec<u8>]) -> Vec<u8> {
+    let size: usize = frames.iter().map(Vec::len).sum();
+    let mut tag = b"ID3".to_vec();
+    tag.extend_from_slice(&[4, 0, 0]); // Version 2.4.0, no flags
+    tag.extend_from_slice(&syncsafe(size));
+    for frame in frames {
+        tag.extend_from_slice(frame);
+    }
+    tag
+}
+
+/// 28-bit size in four 7-bit bytes
+fn syncsafe(size: usize) -> [u8; 4] {
+    let size = size as u32;
+    [
+        (size >> 21 & 0x7F) as u8,
+        (size >> 14 & 0x7F) as u8,
+        (size >> 7 & 0x7F) as u8,
+        (size & 0x7F) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker_id3_tags() {
+        let markers = [
+            Marker {
+                timestamp_us: 2_500_000,
+                label: Some("Intro".to_string()),
+                bookmark: None,
+            },
+            Marker {
+                timestamp_us: 95_443_718_000,
+                label: None,
+                bookmark: None,
+            },
+        ];
+        let track = marker_id3_track(&markers, 126_000);
+        assert_eq!(track[0].pts, 126_000 + 225_000);
+        assert_eq!(
+            track[0].data,
+            [
+                b"ID3\x04\x00\x00\x00\x00\x00\x17".as_slice(),
+                b"TXXX\x00\x00\x00\x0D\x00\x00\x03MARKER\x00Intro",
+            ]
+            .concat()
+        );
+        // Past 2^33 ticks the PTS wraps
+        assert_eq!(track[1].pts, 126_028);
+        assert!(track[1].data.ends_with(b"MARKER\x00"));
+
+        assert_eq!(syncsafe(300), [0, 0, 2, 0x2C]);
+    }
+
+    #[test]
+    fn test_marker_id3_track_sorts_by_timestamp() {
+        let marker = |timestamp_us, label: &str| Marker {
+            timestamp_us,
+            label: Some(label.to_string()),
+            bookmark: None,
+        };
+        let markers = [
+            marker(3_000_000, "C"),
+            marker(1_000_000, "A"),
+            marker(2_000_000, "B1"),
+            marker(2_000_000, "B2"),
+        ];
+        let track = marker_id3_track(&markers, 0);
+        let pts: Vec<u64> = track.iter().map(|tag| tag.pts).collect();
+        assert_eq!(pts, [90_000, 180_000, 180_000, 270_000]);
+        assert!(track[1].data.ends_with(b"MARKER\x00B1"));
+        assert!(track[2].data.ends_with(b"MARKER\x00B2"));
+    }
+}
@@ -0,0 +1,225 @@
+//! End-of-session integrity report.
+//!
+//! When a recording stops, the recorder sums up what it produced: the
+//! manifest, the head of a hash chain over its chunks, the quality report and
+//! the muxer counters. The report is signed with a keyed BLAKE3 hash under a
+//! per-recording key the server hands out with the recording (see
+//! `CreateRecordingResponse.integrity_key` in `@maycast/common-types`), and
+//! the server checks it against what it received before it marks the
+//! recording synced.
+//!
+//! The signature covers the report's JSON exactly as sent, so the server
+//! verifies the bytes before parsing them and neither side has to
+//! canonicalize JSON.
+//!
+//! The hash chain starts with the BLAKE3 hash of the session ID; every chunk,
+//! in manifest order, extends it with `BLAKE3(previous || chunk hash)`, both
+//! as raw 32-byte digests. A chunk left out, added or reordered changes the
+//! head.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::manifest::ChunkManifest;
+use crate::metadata::QualityReport;
+use crate::muxide_muxer::MuxerStats;
+use crate::session::SessionId;
+
+/// Version of the report format, bumped on incompatible changes
+pub const INTEGRITY_REPORT_VERSION: u32 = 1;
+
+/// Length of the signing key in bytes
+pub const INTEGRITY_KEY_LEN: usize = blake3::KEY_LEN;
+
+/// Summary of a finished recording, as the recorder saw it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrityReport {
+    pub version: u32,
+    pub session_id: SessionId,
+    /// Head of the hash chain over the manifest's chunks (hex)
+    pub chain_head: String,
+    pub chunk_count: u64,
+    /// Combined size of the chunks in bytes
+    pub total_bytes: u64,
+    pub quality: QualityReport,
+    pub stats: MuxerStats,
+    pub manifest: ChunkManifest,
+}
+
+/// An `IntegrityReport` with its signature
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedIntegrityReport {
+    /// JSON of the `IntegrityReport`, signed as is
+    pub report: String,
+    /// Keyed BLAKE3 hash of `report` (hex)
+    pub signature: String,
+}
+
+impl IntegrityReport {
+    /// Sum up a manifest whose chunks all carry their hash
+    pub fn new(
+        manifest: &ChunkManifest,
+        quality: QualityReport,
+        stats: MuxerStats,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            version: INTEGRITY_REPORT_VERSION,
+            session_id: manifest.session_id.clone(),
+            chain_head: hash_chain_head(manifest)?,
+            chunk_count: manifest.chunks.len() as u64,
+            total_bytes: manifest.chunks.iter().map(|chunk| chunk.size).sum(),
+            quality,
+            stats,
+            manifest: manifest.clone(),
+        })
+    }
+
+    /// Serialize and sign the report with the recording's key
+    pub fn sign(&self, key: &[u8; INTEGRITY_KEY_LEN]) -> Result<SignedIntegrityReport, String> {
+        let report = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        let signature = blake3::keyed_hash(key, report.as_bytes())
+            .to_hex()
+            .to_string();
+        Ok(SignedIntegrityReport { report, signature })
+    }
+
+    /// Check that the summary fields agree with the manifest
+    pub fn validate(&self) -> Result<(), String> {
+        if self.version != INTEGRITY_REPORT_VERSION {
+            return Err(format!(
+                "Unsupported integrity report version: {}",
+                self.version
+            ));
+        }
+        if self.session_id != self.manifest.session_id {
+            return Err(format!(
+                "Integrity report for session {} carries the manifest of {}",
+                self.session_id, self.manifest.session_id
+            ));
+        }
+        if self.chain_head != hash_chain_head(&self.manifest)? {
+            return Err("Hash chain head does not match the manifest".to_string());
+        }
+        let total_bytes: u64 = self.manifest.chunks.iter().map(|chunk| chunk.size).sum();
+        if self.chunk_count != self.manifest.chunks.len() as u64 || self.total_bytes != total_bytes
+        {
+            return Err("Chunk count or size does not match the manifest".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl SignedIntegrityReport {
+    /// Check the signature, then the report itself, and return it
+    pub fn verify(&self, key: &[u8; INTEGRITY_KEY_LEN]) -> Result<IntegrityReport, String> {
+        let signature = blake3::Hash::from_hex(&self.signature)
+            .map_err(|e| format!("Invalid integrity report signature: {}", e))?;
+        // blake3::Hash compares in constant time
+        if blake3::keyed_hash(key, self.report.as_bytes()) != signature {
+            return Err("Integrity report signature does not match".to_string());
+        }
+        let report: IntegrityReport = serde_json::from_str(&self.report)
+            .map_err(|e| format!("Invalid integrity report: {}", e))?;
+        report.validate()?;
+        Ok(report)
+    }
+}
+
+/// Head of the hash chain over the chunks of a manifest (hex)
+pub fn hash_chain_head(manifest: &ChunkManifest) -> Result<String, String> {
+    let mut head = blake3::hash(manifest.session_id.as_str().as_bytes());
+    for chunk in &manifest.chunks {
+        let hash = chunk
+            .hash
+            .as_deref()
+            .ok_or_else(|| format!("Chunk {} has no hash", chunk.chunk_id))
+            .and_then(|hash| {
+                blake3::Hash::from_hex(hash)
+                    .map_err(|e| format!("Invalid hash of chunk {}: {}", chunk.chunk_id, e))
+            })?;
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(head.as_bytes());
+        hasher.update(hash.as_bytes());
+        head = hasher.finalize();
+    }
+    Ok(head.to_hex().to_string())
+}
+
+/// Parse a signing key given as 64 hex digits
+pub fn parse_integrity_key(hex: &str) -> Result<[u8; INTEGRITY_KEY_LEN], String> {
+    blake3::Hash::from_hex(hex.trim())
+        .map(|hash| *hash.as_bytes())
+        .map_err(|e| format!("Invalid integrity key: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{ChunkId, ChunkMetadata, TrackKind};
+
+    fn manifest() -> ChunkManifest {
+        let session = SessionId::from("5f0c7d7e-2a61-4b8e-9d55-0c8f1c1e2b3a");
+        let mut manifest = ChunkManifest::new(session.clone());
+        for sequence in 0..3u64 {
+            let data = vec![sequence as u8; 100];
+            manifest
+                .add_chunk(ChunkMetadata {
+                    chunk_id: ChunkId::new(session.clone(), TrackKind::Muxed, sequence),
+                    timestamp_us: sequence * 1_000_000,
+                    size: data.len() as u64,
+                    hash: Some(blake3::hash(&data).to_hex().to_string()),
+                    has_keyframe: Some(true),
+                    created_at: 0,
+                })
+                .unwrap();
+        }
+        manifest
+    }
+
+    #[test]
+    fn test_signed_report_round_trip() {
+        let manifest = manifest();
+        let key = parse_integrity_key(&"42".repeat(32)).unwrap();
+        let report =
+            IntegrityReport::new(&manifest, QualityReport::default(), MuxerStats::default())
+                .unwrap();
+        assert_eq!(report.chunk_count, 3);
+        assert_eq!(report.total_bytes, 300);
+        let signed = report.sign(&key).unwrap();
+        assert_eq!(signed.verify(&key).unwrap(), report);
+
+        // Wrong key, edited report
+        let other = parse_integrity_key(&"43".repeat(32)).unwrap();
+        assert!(signed.verify(&other).is_err());
+        let tampered = SignedIntegrityReport {
+            report: signed
+                .report
+                .replace("\"totalBytes\":300", "\"totalBytes\":301"),
+            ..signed.clone()
+        };
+        assert!(tampered.verify(&key).is_err());
+
+        // A report whose chain does not match its manifest, even if signed
+        let mut dropped = report.clone();
+        dropped.manifest.chunks.remove(1);
+        dropped.chunk_count = 2;
+        dropped.total_bytes = 200;
+        assert!(dropped.sign(&key).unwrap().verify(&key).is_err());
+
+        // Reordering chunks changes the head
+        let mut reordered = manifest.clone();
+        reordered.chunks.swap(0, 1);
+        assert_ne!(
+            hash_chain_head(&reordered).unwrap(),
+            hash_chain_head(&manifest).unwrap()
+        );
+        let mut unhashed = manifest;
+        unhashed.chunks[2].hash = None;
+        assert!(hash_chain_head(&unhashed).is_err());
+        assert!(parse_integrity_key("abc").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Keyframe scheduling aligned with fragmentation.
+//!
+//! The muxer closes a fragment on the first video sample whose DTS is at least
+//! `fragment_duration_ms` past the fragment's first sample; the next sample
+//! starts a new fragment. This scheduler replays that rule on the timestamps
+//! handed to the encoder, so the JS encoder wrapper can force a keyframe on
+//! exactly the frame that will start each fragment, instead of relying on the
+//! encoder's own keyframe interval drifting against the fragment duration.
+//!
+//! It is driven at encode time rather than by muxer state because frames are
+//! still in flight inside the encoder when the next one is submitted.
+
+use crate::muxide_muxer::MuxideConfig;
+use crate::timebase::{Rounding, TimeBase};
+
+/// Predicts which frames start a fragment, in encode order
+#[derive(Debug, Clone)]
+pub struct KeyframeSchedulerState {
+    fragment_duration_ms: u64,
+    time_base: TimeBase,
+    fragment_start_ticks: Option<u64>,
+    closing: bool,
+    force_next: bool,
+}
+
+impl KeyframeSchedulerState {
+    /// Create a scheduler matching the muxer's fragmentation settings
+    pub fn new(config: &MuxideConfig) -> Self {
+        Self {
+            fragment_duration_ms: config.target_fragment_duration_ms() as u64,
+            time_base: config.video_time_base(),
+            fragment_start_ticks: None,
+            closing: false,
+            force_next: false,
+        }
+    }
+
+    /// Request a keyframe on the next frame regardless of fragment position
+    pub fn force_next(&mut self) {
+        self.force_next = true;
+    }
+
+    /// Follow a change of the muxer's fragment duration
+    pub fn set_fragment_duration_ms(&mut self, fragment_duration_ms: u32) {
+        self.fragment_duration_ms = fragment_duration_ms.max(1) as u64;
+    }
+
+    /// Decide whether the frame at `timestamp_us` must be encoded as a keyframe
+    ///
+    /// Call exactly once per frame submitted to the encoder, in order.
+    pub fn next_frame(&mut self, timestamp_us: u64) -> bool {
+        // Same conversion and comparison as the muxer's flush check
+        let ticks = self.time_base.from_us(timestamp_us, Rounding::Floor);
+        let starts_fragment = match self.fragment_start_ticks {
+            None => true,
+            Some(_) if self.closing => true,
+            Some(start) => {
+                let elapsed_ms = self
+                    .time_base
+                    .to_ms(ticks.saturating_sub(start), Rounding::Floor);
+                self.closing = elapsed_ms >= self.fragment_duration_ms;
+                false
+            }
+        };
+        if starts_fragment {
+            self.fragment_start_ticks = Some(ticks);
+            self.closing = false;
+        }
+        std::mem::take(&mut self.force_next) || starts_fragment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::muxide_muxer::MuxideMuxerState;
+
+    #[test]
+    fn test_scheduled_keyframes_start_every_fragment() {
+        let config = MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E, 0xD9, 0x00, 0x50, 0x05]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        };
+        let mut scheduler = KeyframeSchedulerState::new(&config);
+        let mut muxer = MuxideMuxerState::new(config);
+        muxer.init().unwrap();
+
+        // 29.97fps-ish timestamps that never land exactly on a second
+        let mut keyframes = Vec::new();
+        let mut fragment_starts = vec![0];
+        for i in 0..200u64 {
+            let ts = i * 33_367;
+            let is_keyframe = scheduler.next_frame(ts);
+            if is_keyframe {
+                keyframes.push(i);
+            }
+            let segments_before = muxer.stats().segment_count;
+            muxer
+                .push_video_chunk(&[0, 0, 0, 1, 0x65], ts, is_keyframe)
+                .unwrap();
+            if muxer.stats().segment_count > segments_before {
+                fragment_starts.push(i + 1);
+            }
+        }
+        fragment_starts.retain(|&i| i < 200);
+
+        assert!(fragment_starts.len() > 5);
+        assert_eq!(keyframes, fragment_starts);
+    }
+
+    #[test]
+    fn test_force_next_keyframe() {
+        let mut scheduler = KeyframeSchedulerState::new(&MuxideConfig::default());
+        assert!(scheduler.next_frame(0));
+        assert!(!scheduler.next_frame(33_333));
+        scheduler.force_next();
+        assert!(scheduler.next_frame(66_666));
+        assert!(!scheduler.next_frame(100_000));
+    }
+}
@@ -0,0 +1,245 @@
+//! End-to-end latency of media segments.
+//!
+//! Every segment has two moments on the wall clock: when the muxer emitted
+//! it to its `SegmentSink`, and when the sink acknowledged it (the write
+//! returned, the stream reported it written, or the buffered segment was
+//! taken). `SegmentLatencyTracker` compares both with the wall-clock time at
+//! which the end of the segment's media was captured, mapped through the
+//! offset between the first frame's timestamp and its arrival. Emit latency
+//! grows when frames queue up before the muxer, the gap from emit to ack
+//! when the sink falls behind.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+/// Recent segments kept in `LatencyReport::segments`
+pub const LATENCY_WINDOW: usize = 32;
+
+/// Unacknowledged segments tracked at once; past it the oldest is given up
+/// on, so a sink that never acknowledges cannot grow the tracker
+pub const MAX_PENDING_ACKS: usize = 1024;
+
+/// Timing of one media segment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentLatency {
+    /// Segment sequence number
+    pub sequence: u64,
+    /// Input time covered (microseconds)
+    pub start_us: u64,
+    pub end_us: u64,
+    /// Unix time (ms) the muxer handed it to the sink
+    pub emitted_at_ms: u64,
+    /// Milliseconds from the capture of its last media to the emit; down
+    /// to minus a frame, as a frame arrives when it starts
+    pub emit_latency_ms: i64,
+    /// Unix time (ms) the sink acknowledged it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    pub acknowledged_at_ms: Option<u64>,
+    /// Milliseconds from the capture of its last media to the ack
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    pub ack_latency_ms: Option<i64>,
+}
+
+/// Aggregate of one latency over all segments
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencySummary {
+    pub count: u32,
+    pub mean_ms: i64,
+    pub max_ms: i64,
+    pub last_ms: i64,
+    #[serde(skip)]
+    total_ms: i64,
+}
+
+impl LatencySummary {
+    fn push(&mut self, latency_ms: i64) {
+        self.max_ms = if self.count == 0 {
+            latency_ms
+        } else {
+            self.max_ms.max(latency_ms)
+        };
+        self.count += 1;
+        self.total_ms += latency_ms;
+        self.mean_ms = self.total_ms / self.count as i64;
+        self.last_ms = latency_ms;
+    }
+}
+
+/// Segment latencies, returned to JS by `get_latency_report`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyReport {
+    /// The last `LATENCY_WINDOW` segments, oldest first
+    pub segments: Vec<SegmentLatency>,
+    /// Capture to emit, over all segments
+    pub emit: LatencySummary,
+    /// Capture to ack, over all acknowledged segments
+    pub ack: LatencySummary,
+    /// Segments emitted but not acknowledged yet
+    pub unacknowledged: u32,
+    /// How long the oldest of them has waited since its emit (ms)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    pub oldest_unacknowledged_ms: Option<u64>,
+    /// Segments given up on unacknowledged (`MAX_PENDING_ACKS`)
+    #[serde(default)]
+    pub expired: u32,
+}
+
+/// Collects the emit and ack times of segments
+#[derive(Debug, Clone, Default)]
+pub struct SegmentLatencyTracker {
+    /// Unix time (ms) minus input time (ms) of the anchoring frame
+    offset_ms: Option<i64>,
+    recent: VecDeque<SegmentLatency>,
+    /// Sequence -> (emit time, capture time of its end), not acknowledged
+    pending: BTreeMap<u64, (u64, i64)>,
+    expired: u32,
+    emit: LatencySummary,
+    ack: LatencySummary,
+}
+
+impl SegmentLatencyTracker {
+    /// Whether a frame has tied input time to the wall clock
+    pub fn anchored(&self) -> bool {
+        self.offset_ms.is_some()
+    }
+
+    /// Tie input time to the wall clock: the frame at `timestamp_us`
+    /// arrived at `now_ms`
+    pub fn anchor(&mut self, timestamp_us: u64, now_ms: u64) {
+        self.offset_ms = Some(now_ms as i64 - (timestamp_us / 1000) as i64);
+    }
+
+    /// Forget the anchor, so the next frame sets it again (after a pause,
+    /// whose wall-clock time is not in the timeline)
+    pub fn reset_anchor(&mut self) {
+        self.offset_ms = None;
+    }
+
+    /// Record that segment `sequence`, covering `start_us..end_us`, was
+    /// emitted at `now_ms`
+    pub fn emitted(&mut self, sequence: u64, start_us: u64, end_us: u64, now_ms: u64) {
+        if self.offset_ms.is_none() {
+            // No frame anchored it: count from the emit itself
+            self.anchor(end_us, now_ms);
+        }
+        let captured_ms = self.offset_ms.unwrap_or_default() + (end_us / 1000) as i64;
+        let emit_latency_ms = now_ms as i64 - captured_ms;
+        self.emit.push(emit_latency_ms);
+        self.pending.insert(sequence, (now_ms, captured_ms));
+        if self.pending.len() > MAX_PENDING_ACKS {
+            self.pending.pop_first();
+            self.expired += 1;
+        }
+        if self.recent.len() == LATENCY_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(SegmentLatency {
+            sequence,
+            start_us,
+            end_us,
+            emitted_at_ms: now_ms,
+            emit_latency_ms,
+            acknowledged_at_ms: None,
+            ack_latency_ms: None,
+        });
+    }
+
+    /// Record that the sink acknowledged segment `sequence` at `now_ms`;
+    /// unknown or already acknowledged sequences are ignored
+    pub fn acknowledged(&mut self, sequence: u64, now_ms: u64) {
+        let Some((_, captured_ms)) = self.pending.remove(&sequence) else {
+            return;
+        };
+        let ack_latency_ms = now_ms as i64 - captured_ms;
+        self.ack.push(ack_latency_ms);
+        if let Some(segment) = self.recent.iter_mut().find(|s| s.sequence == sequence) {
+            segment.acknowledged_at_ms = Some(now_ms);
+            segment.ack_latency_ms = Some(ack_latency_ms);
+        }
+    }
+
+    /// Latencies so far, with waiting times as of `now_ms`
+    pub fn report(&self, now_ms: u64) -> LatencyReport {
+        LatencyReport {
+            segments: self.recent.iter().cloned().collect(),
+            emit: self.emit.clone(),
+            ack: self.ack.clone(),
+            unacknowledged: self.pending.len() as u32,
+            oldest_unacknowledged_ms: self
+                .pending
+                .values()
+                .map(|&(emitted_at, _)| now_ms.saturating_sub(emitted_at))
+                .max(),
+            expired: self.expired,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emit_and_ack_latency() {
+        let mut tracker = SegmentLatencyTracker::default();
+        // First frame (input time 5 s) arrives at 1000 s
+        tracker.anchor(5_000_000, 1_000_000);
+        // Segment 5..7 s emitted 2.1 s later, acknowledged 300 ms after that
+        tracker.emitted(0, 5_000_000, 7_000_000, 1_002_100);
+        tracker.emitted(1, 7_000_000, 9_000_000, 1_004_050);
+        tracker.acknowledged(0, 1_002_400);
+        tracker.acknowledged(0, 1_009_000);
+
+        let report = tracker.report(1_005_000);
+        assert_eq!(report.segments.len(), 2);
+        assert_eq!(report.segments[0].emit_latency_ms, 100);
+        assert_eq!(report.segments[0].ack_latency_ms, Some(400));
+        assert_eq!(report.segments[1].acknowledged_at_ms, None);
+        assert_eq!((report.emit.count, report.emit.max_ms), (2, 100));
+        assert_eq!(report.emit.mean_ms, 75);
+        assert_eq!((report.ack.count, report.ack.last_ms), (1, 400));
+        assert_eq!(report.unacknowledged, 1);
+        assert_eq!(report.oldest_unacknowledged_ms, Some(950));
+
+        // After a pause of 60 s the next frame re-anchors
+        tracker.reset_anchor();
+        tracker.anchor(9_000_000, 1_065_000);
+        tracker.emitted(2, 9_000_000, 11_000_000, 1_067_020);
+        assert_eq!(tracker.report(1_067_020).emit.last_ms, 20);
+    }
+
+    #[test]
+    fn test_pending_acks_are_capped() {
+        let mut tracker = SegmentLatencyTracker::default();
+        tracker.anchor(0, 1_000);
+        let segments = MAX_PENDING_ACKS as u64 + 10;
+        for sequence in 0..segments {
+            tracker.emitted(
+                sequence,
+                sequence * 1000,
+                (sequence + 1) * 1000,
+                1_000 + sequence,
+            );
+        }
+
+        let report = tracker.report(1_000 + segments);
+        assert_eq!(report.unacknowledged as usize, MAX_PENDING_ACKS);
+        assert_eq!(report.expired, 10);
+        assert_eq!(report.oldest_unacknowledged_ms, Some(segments - 10));
+
+        // A late ack of a segment given up on is ignored
+        tracker.acknowledged(0, 5_000);
+        assert_eq!(tracker.report(5_000).ack.count, 0);
+    }
+}
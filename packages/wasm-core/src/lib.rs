@@ -1,10 +1,207 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::{Rc, Weak};
+
+use serde::Serialize;
 use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
 
+mod adts;
+mod aes;
+#[cfg(feature = "native")]
+mod assembler;
+mod cdc;
+mod chunk;
+mod clock;
+mod compat;
+mod compress;
+mod continuity;
+mod demux;
+mod downmix;
+mod encryption;
+mod error;
+mod extract;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+mod fragment_checksum;
+mod framerate;
+mod handshake;
+mod hashing;
+mod id3;
+mod integrity;
+mod keyframe;
+mod latency;
+mod logging;
+mod loudness;
+mod manifest;
+mod merge;
+mod metadata;
 mod muxide_muxer;
+mod ogg;
+mod presets;
+mod preview;
+#[cfg(feature = "proto")]
+pub mod proto;
+mod range_map;
+mod recorder;
+mod registry;
+mod replace_audio;
+mod retime;
+mod search_index;
+mod segment_sink;
+mod session;
+mod silence;
+#[cfg(feature = "simulator")]
+pub mod simulator;
+mod simulcast;
+mod sizing;
+mod sps;
+mod storage;
+mod streaming;
+mod subtitles;
+mod telemetry;
+mod threads;
+mod timebase;
+mod trace;
+mod transfer;
+mod upload;
+mod upload_queue;
+#[cfg(feature = "native")]
+mod uploader;
+mod wal;
+mod watchdog;
+mod waveform;
+mod zip;
+
+use logging::log_event;
 
+pub use adts::{AdtsWriterState, ADTS_HEADER_SIZE, ADTS_MAX_FRAME_SIZE};
+#[cfg(feature = "native")]
+pub use assembler::{AssemblyOptions, ChunkAssembler, ProgressiveDownload};
+pub use cdc::{cdc_ranges, split_content_defined, CdcConfig, ContentDefinedSplit, CDC_RENDITION};
+pub use chunk::{ChunkId, ChunkMetadata, ChunkRecord, RecordedChunk, TrackKind};
+pub use clock::{Clock, ClockHandle, ManualClock, SystemClock};
+pub use compat::{
+    check_config_against_init, compare_init_segments, CompatChecker, CompatRule, CompatViolation,
+    InitCompatibility,
+};
+pub use compress::{
+    compress, compress_if_smaller, decompress, decompress_if_compressed, is_compressed,
+    DEFAULT_COMPRESSION_LEVEL, FAST_COMPRESSION_LEVEL, GZIP_MAGIC, MAX_DECOMPRESSED_SIZE,
+};
+pub use continuity::{validate_sequences, SequenceBreak, SequenceContinuity};
+pub use demux::{import_mp4, ImportedRecording, MediaSample, SampleInfo, SampleReader};
+pub use downmix::{db_to_gain, DownmixMixerState};
+pub use encryption::{decrypt_segment, segment_iv, SegmentEncryptorState, SegmentKey};
+pub use error::{CoreError, ErrorKind};
+pub use extract::extract_track;
+pub use fragment_checksum::{
+    build_checksum_box, strip_fragment_checksums, verify_fragment_checksums,
+    FragmentChecksumReport, FRAGMENT_CHECKSUM_BOX_SIZE, FRAGMENT_CHECKSUM_UUID,
+};
+pub use framerate::{FrameRateEstimator, DEFAULT_FRAME_RATE_WINDOW_MS};
+pub use handshake::{
+    negotiate_ingest, AcceptedIngest, IngestCapabilities, IngestNegotiation, RejectReason,
+    RejectedIngest, CDC_CHUNKS_FEATURE, ENCRYPTED_CHUNKS_FEATURE, FRAGMENT_CHECKSUM_FEATURE,
+    INGEST_PROTOCOL_VERSION, MIN_INGEST_PROTOCOL_VERSION,
+};
+pub use hashing::{
+    hash_hex, HashStrategy, IncrementalHash, HASH_SLICE_BYTES, PARALLEL_HASH_MIN_BYTES,
+};
+pub use id3::{marker_id3, marker_id3_track, TimedMetadata, MARKER_ID3_DESCRIPTION, MPEG_TS_CLOCK};
+pub use integrity::{
+    hash_chain_head, parse_integrity_key, IntegrityReport, SignedIntegrityReport,
+    INTEGRITY_KEY_LEN, INTEGRITY_REPORT_VERSION,
+};
+pub use keyframe::KeyframeSchedulerState;
+pub use latency::{
+    LatencyReport, LatencySummary, SegmentLatency, SegmentLatencyTracker, LATENCY_WINDOW,
+};
+pub use logging::{LogLevel, LogRecord};
+pub use loudness::{AudioLevels, LoudnessMeterState, SILENCE_DB};
+pub use manifest::{
+    Bookmark, ChunkManifest, FragmentRange, KeyPeriod, KeyframeLocation, Marker, MutedRange,
+    TailMarker,
+};
+pub use merge::{
+    AnchorSource, BundleFile, CanvasSize, DisplayLayout, DisplayRegion, MergeBundle, MergeManifest,
+    MergedParticipant, ParticipantRecording, SessionMerger, MERGE_MANIFEST_VERSION,
+};
+pub use metadata::{
+    AudioConfig, DeviceInfo, DownmixRecipe, DownmixSource, EmbeddedMarker, EmbeddedMetadata,
+    FrameRateStats, LoudnessStats, MediaGap, QualityReport, RecordingMetadata, RetentionPolicy,
+    RetentionStatus, SyncInfo,
+};
 pub use muxide_muxer::{
-    annex_b_to_avcc, extract_sps_pps_from_avcc, MuxideConfig, MuxideMuxerState,
+    annex_b_to_avcc, build_init_segment, build_media_segment, extract_sps_pps_from_avcc,
+    trace_segment, AudioSampleEntry, DataOffsetMode, FragmentTrace, FrameIssue, GaplessInfo,
+    MediaSegmentSpec, MemoryProfile, MuxerStats, MuxideConfig, MuxideMuxerState, Refragmenter,
+    SampleFlags, SegmentSample, SegmentSamples, SkippedFrame, StartAlignment, ValidationMode,
+    DEFAULT_FRAME_RATE, DEFAULT_MAX_FRAME_SIZE, DEFAULT_MOVIE_TIMESCALE, FRAME_FLAG_KEYFRAME,
+    LOW_MEMORY_FRAGMENT_MS, LOW_MEMORY_MAX_BUFFERED_BYTES, LOW_MEMORY_MAX_FRAME_SIZE,
+};
+pub use ogg::{
+    opus_packet_samples, write_ogg_opus, OggOpusConfig, OggOpusWriterState, OPUS_GRANULE_RATE,
+};
+pub use presets::{recording_preset, recording_presets, RecordingPreset, UploadPolicy};
+pub use preview::{LivePreviewState, PreviewSegment, PreviewSegmentInfo};
+pub use range_map::build_range_map;
+pub use recorder::{
+    AudioConfigChange, RecorderEvent, RecorderState, RecorderStatus, SlateSource,
+    AUDIO_CONFIG_LABEL, LOW_POWER_CHUNK_BATCH, LOW_POWER_FRAGMENT_SCALE, MIN_GAP_US,
+    SLATE_END_LABEL, SLATE_START_LABEL,
+};
+pub use registry::{
+    ExpiryAction, ExpiryPolicy, MemorySessionRegistry, RegistrySnapshot, SessionRecord,
+    SessionRegistry, REGISTRY_SNAPSHOT_VERSION,
+};
+pub use replace_audio::replace_audio_track;
+pub use retime::{RetimeOptions, SegmentRetimerState};
+pub use search_index::{search_terms, IndexEntry, IndexRange, SessionIndex, SEARCH_INDEX_VERSION};
+pub use segment_sink::{
+    BufferedSink, CallbackSink, MediaSegment, OpfsSink, SegmentSink, SinkOutput, WritableStreamSink,
 };
+pub use session::{SessionId, SessionState};
+pub use silence::{
+    SilenceChange, SilenceConfig, SilenceDetector, SilenceRange, SILENCE_END_LABEL,
+    SILENCE_START_LABEL,
+};
+pub use simulcast::{RenditionConfig, SimulcastState};
+pub use sizing::{ChunkSizePolicyState, ChunkSizingConfig};
+pub use sps::{parse_sps_timing, SpsTiming};
+pub use storage::{
+    collect_garbage, export_session, import_session, mark_synced, ChunkStorage, ChunkStore,
+    GcPolicy, GcReason, GcReclaim, GcReport, ImportedSession, IndexedDbStore, JournalEntry,
+    ManifestJournal, OpfsStore, StorageBackend, ARCHIVE_CHUNKS_DIR, ARCHIVE_EVENTS_FILE,
+    ARCHIVE_MANIFEST_FILE, ARCHIVE_MARKERS_FILE, ARCHIVE_RECORDING_FILE, JOURNAL_COMPACT_INTERVAL,
+};
+pub use streaming::{
+    ResendReason, SegmentReceiver, SegmentSender, StreamFrame, STREAM_PROTOCOL_VERSION,
+};
+pub use subtitles::{
+    export_subtitles, marker_cues, write_srt, write_webvtt, SubtitleCue, SubtitleFormat,
+    DEFAULT_CUE_DURATION_US,
+};
+pub use telemetry::TelemetrySnapshot;
+#[cfg(feature = "wasm-threads")]
+pub use threads::start_pool;
+pub use threads::{pool_ready, FinishedSegment, SegmentJob};
+pub use timebase::{Rounding, TickCarry, TimeBase};
+pub use trace::{
+    check_trace, fingerprint_output, fingerprint_trace, read_trace, replay_trace, FingerprintCheck,
+    ReplayError, ReplayResult, SegmentFingerprint, SessionFingerprint, TraceRecord, TraceWriter,
+};
+pub use transfer::RecorderTransfer;
+pub use upload::{ChunkUploadStatus, UploadProgress, UploadSample, UploadState, UploadTracker};
+pub use upload_queue::{NetworkStatus, UploadJob, UploadLane, UploadQueueConfig, UploadQueueState};
+#[cfg(feature = "http-upload")]
+pub use uploader::HttpTransport;
+#[cfg(feature = "native")]
+pub use uploader::{ChunkUploader, UploadOptions, UploadTransport};
+pub use wal::{WalBatch, WalFrame, WalLog, WalWriter};
+pub use watchdog::{StallChange, StallWatchdog};
+pub use waveform::{Waveform, WaveformBuilderState, DEFAULT_PEAKS_PER_SECOND};
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global allocator.
 // This is optional and can help reduce WASM binary size.
@@ -32,6 +229,174 @@ pub fn log(message: &str) {
     web_sys::console::log_1(&message.into());
 }
 
+// ===== Logging WASM Bindings =====
+
+/// Forward log records to `callback`, invoked as `(record: LogRecord)`
+///
+/// Replaces console output until `clear_log_callback()` is called.
+#[wasm_bindgen]
+pub fn set_log_callback(callback: js_sys::Function) {
+    logging::set_sink(move |record| {
+        let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+        if let Ok(value) = record.serialize(&serializer) {
+            let _ = callback.call1(&JsValue::NULL, &value);
+        }
+    });
+}
+
+/// Stop forwarding log records and write them to the console again
+#[wasm_bindgen]
+pub fn clear_log_callback() {
+    logging::clear_sink();
+}
+
+/// Set the minimum level for modules without their own level (default: info)
+#[wasm_bindgen]
+pub fn set_log_level(level: LogLevel) {
+    logging::set_level(level);
+}
+
+/// Set the minimum level for a module and its submodules, e.g. `muxide_muxer`
+#[wasm_bindgen]
+pub fn set_module_log_level(module: &str, level: LogLevel) {
+    logging::set_module_level(module, level);
+}
+
+/// Remove every module level and restore the default level (info)
+#[wasm_bindgen]
+pub fn reset_log_levels() {
+    logging::reset_levels();
+}
+
+/// Stamp log records with the time of `clock` (Rust only, e.g. a
+/// `ManualClock` in tests)
+pub fn set_log_clock(clock: ClockHandle) {
+    logging::set_clock(clock);
+}
+
+// ===== Telemetry WASM Bindings =====
+
+/// Snapshot of the internal counters as a JSON string
+///
+/// Cheap enough to call on a timer from the host app's telemetry.
+#[wasm_bindgen]
+pub fn get_telemetry_snapshot() -> String {
+    telemetry::snapshot_json()
+}
+
+/// Set every telemetry counter back to zero
+#[wasm_bindgen]
+pub fn reset_telemetry() {
+    telemetry::reset();
+}
+
+/// Stamp telemetry snapshots with the time of `clock` (Rust only)
+pub fn set_telemetry_clock(clock: ClockHandle) {
+    telemetry::set_clock(clock);
+}
+
+/// Compare two init segments: can media segments of one play behind the other?
+#[wasm_bindgen]
+pub fn check_init_compatibility(a: &[u8], b: &[u8]) -> Result<InitCompatibility, String> {
+    compat::compare_init_segments(a, b)
+}
+
+/// Ingest capabilities to send in the upload handshake for a recorder
+/// muxing with `config`
+#[wasm_bindgen]
+pub fn get_ingest_capabilities(config: MuxideConfig) -> IngestCapabilities {
+    IngestCapabilities::for_config(&config)
+}
+
+/// Negotiate an upload the way the server does, e.g. to check its answer
+#[wasm_bindgen]
+pub fn negotiate_ingest_capabilities(
+    client: IngestCapabilities,
+    server: IngestCapabilities,
+) -> IngestNegotiation {
+    negotiate_ingest(&client, &server)
+}
+
+/// Session id, metadata and markers embedded in a recording at stop, from
+/// its init segment or the whole file; undefined if there are none
+#[wasm_bindgen]
+pub fn read_embedded_metadata(recording: &[u8]) -> Result<Option<EmbeddedMetadata>, String> {
+    EmbeddedMetadata::read(recording)
+}
+
+/// Record a synthetic stream from start to stop and report what came out,
+/// to load-test fragmentation in a WASM build
+#[cfg(feature = "simulator")]
+#[wasm_bindgen]
+pub fn simulate_recording(
+    session_id: String,
+    config: simulator::SimulationConfig,
+) -> Result<simulator::SimulationReport, String> {
+    simulator::Simulator::new(SessionId::new(session_id), config)?.run()
+}
+
+/// Check the fragment checksum boxes of a recording or of chunks
+/// (`MuxideConfig.fragmentChecksums`) without the manifest
+#[wasm_bindgen]
+pub fn verify_recording_fragment_checksums(data: &[u8]) -> Result<FragmentChecksumReport, String> {
+    verify_fragment_checksums(data)
+}
+
+/// Build an audio-only or video-only MP4 from one track of a recording
+#[wasm_bindgen]
+pub fn extract_recording_track(recording: &[u8], track_id: u32) -> Result<Vec<u8>, String> {
+    extract::extract_track(recording, track_id)
+}
+
+/// Remux a recording with the audio samples of another recording (e.g. a
+/// noise-suppressed version), leaving the video samples untouched
+#[wasm_bindgen]
+pub fn replace_recording_audio(recording: &[u8], audio: &[u8]) -> Result<Vec<u8>, String> {
+    replace_audio::replace_audio_track(recording, audio)
+}
+
+/// Build the init segment a muxer with `config` writes, without creating
+/// one; pass the session's gapless info to match its finalized file
+#[wasm_bindgen]
+pub fn build_recording_init_segment(
+    config: MuxideConfig,
+    gapless: Option<GaplessInfo>,
+) -> Result<Vec<u8>, String> {
+    muxide_muxer::build_init_segment(&config, gapless.as_ref())
+}
+
+/// Build a media segment (moof + mdat) from sample lists without a muxer;
+/// timestamps and durations are in track timescale units
+#[wasm_bindgen]
+pub fn build_recording_media_segment(
+    spec: MediaSegmentSpec,
+    samples: SegmentSamples,
+) -> Result<Vec<u8>, String> {
+    muxide_muxer::build_media_segment(&spec, &samples.video, &samples.audio)
+}
+
+/// Re-drive a dry-run muxer from a trace produced by `MuxideMuxer.get_trace`
+#[wasm_bindgen]
+pub fn replay_muxer_trace(trace: &[u8]) -> Result<ReplayResult, String> {
+    trace::replay_trace(trace)
+}
+
+/// Fingerprint the output a muxer trace replays to, for storing alongside
+/// the trace as a regression test vector
+#[wasm_bindgen]
+pub fn fingerprint_muxer_trace(trace: &[u8]) -> Result<SessionFingerprint, String> {
+    trace::fingerprint_trace(trace)
+}
+
+/// Replay a muxer trace and compare its output with a stored fingerprint
+#[wasm_bindgen]
+pub fn check_muxer_trace(
+    trace: &[u8],
+    expected: SessionFingerprint,
+) -> Result<FingerprintCheck, String> {
+    trace::check_trace(trace, &expected)
+}
+
 /// Get the version of the WASM module
 #[wasm_bindgen]
 pub fn version() -> String {
@@ -70,7 +435,24 @@ impl MuxideMuxer {
             audio_sample_rate: None,
             audio_channels: None,
             audio_timescale: None,
+            movie_timescale: None,
             audio_specific_config: None,
+            audio_priming_samples: None,
+            validation: ValidationMode::default(),
+            start_alignment: StartAlignment::default(),
+            dry_run: false,
+            compat_checks: false,
+            audio_skew_correction: false,
+            max_frame_size: None,
+            memory_profile: MemoryProfile::default(),
+            data_offset_mode: DataOffsetMode::default(),
+            aux_info_type: None,
+            video_track_name: None,
+            audio_track_name: None,
+            session_metadata: None,
+            fragment_checksums: false,
+            moov_reserved_size: None,
+            previous_audio_configs: Vec::new(),
         };
         Self {
             state: MuxideMuxerState::new(config),
@@ -99,7 +481,24 @@ impl MuxideMuxer {
             audio_sample_rate: None,
             audio_channels: None,
             audio_timescale: None,
+            movie_timescale: None,
             audio_specific_config: None,
+            audio_priming_samples: None,
+            validation: ValidationMode::default(),
+            start_alignment: StartAlignment::default(),
+            dry_run: false,
+            compat_checks: false,
+            audio_skew_correction: false,
+            max_frame_size: None,
+            memory_profile: MemoryProfile::default(),
+            data_offset_mode: DataOffsetMode::default(),
+            aux_info_type: None,
+            video_track_name: None,
+            audio_track_name: None,
+            session_metadata: None,
+            fragment_checksums: false,
+            moov_reserved_size: None,
+            previous_audio_configs: Vec::new(),
         };
 
         Ok(Self {
@@ -138,7 +537,24 @@ impl MuxideMuxer {
             audio_sample_rate: Some(audio_sample_rate),
             audio_channels: Some(audio_channels),
             audio_timescale: Some(audio_sample_rate), // Use sample rate as timescale
+            movie_timescale: None,
             audio_specific_config,
+            audio_priming_samples: None,
+            validation: ValidationMode::default(),
+            start_alignment: StartAlignment::default(),
+            dry_run: false,
+            compat_checks: false,
+            audio_skew_correction: false,
+            max_frame_size: None,
+            memory_profile: MemoryProfile::default(),
+            data_offset_mode: DataOffsetMode::default(),
+            aux_info_type: None,
+            video_track_name: None,
+            audio_track_name: None,
+            session_metadata: None,
+            fragment_checksums: false,
+            moov_reserved_size: None,
+            previous_audio_configs: Vec::new(),
         };
 
         Ok(Self {
@@ -169,7 +585,24 @@ impl MuxideMuxer {
             audio_sample_rate: Some(audio_sample_rate),
             audio_channels: Some(audio_channels),
             audio_timescale: Some(audio_sample_rate), // Use sample rate as timescale
+            movie_timescale: None,
             audio_specific_config,
+            audio_priming_samples: None,
+            validation: ValidationMode::default(),
+            start_alignment: StartAlignment::default(),
+            dry_run: false,
+            compat_checks: false,
+            audio_skew_correction: false,
+            max_frame_size: None,
+            memory_profile: MemoryProfile::default(),
+            data_offset_mode: DataOffsetMode::default(),
+            aux_info_type: None,
+            video_track_name: None,
+            audio_track_name: None,
+            session_metadata: None,
+            fragment_checksums: false,
+            moov_reserved_size: None,
+            previous_audio_configs: Vec::new(),
         };
 
         MuxideMuxer {
@@ -177,6 +610,17 @@ impl MuxideMuxer {
         }
     }
 
+    /// Create a MuxideMuxer from a full config object
+    ///
+    /// Use this when the fixed-shape constructors above don't fit, e.g. to
+    /// change `fragmentDurationMs` or the video timescale.
+    #[wasm_bindgen]
+    pub fn from_config(config: MuxideConfig) -> MuxideMuxer {
+        MuxideMuxer {
+            state: MuxideMuxerState::new(config),
+        }
+    }
+
     /// Initialize the muxer and get the fMP4 initialization segment (ftyp + moov)
     #[wasm_bindgen]
     pub fn initialize(&mut self) -> Result<Vec<u8>, String> {
@@ -201,6 +645,64 @@ impl MuxideMuxer {
         self.state.push_video_chunk(data, timestamp_us, is_keyframe)
     }
 
+    /// Add a video chunk with explicit decode timestamp and duration
+    ///
+    /// # Arguments
+    /// * `data` - Video frame data in AVCC format (4-byte length prefixed NAL units)
+    /// * `pts` / `dts` - Presentation and decode timestamps in microseconds
+    /// * `duration` - Frame duration in microseconds (`chunk.duration`); when
+    ///   given it takes precedence over the gap to the next frame
+    /// * `flags` - Bit 0: keyframe
+    #[wasm_bindgen]
+    pub fn push_video_full(
+        &mut self,
+        data: &[u8],
+        pts: f64,
+        dts: f64,
+        duration: Option<f64>,
+        flags: u32,
+    ) -> Result<(), String> {
+        self.state.push_video_chunk_full(
+            data,
+            pts as u64,
+            dts as u64,
+            duration.map(|d| d as u32),
+            flags,
+        )
+    }
+
+    /// Add a video chunk with explicit sample flags for the trun
+    ///
+    /// For pre-analyzed streams: `flags` (dependsOn, isDependedOn,
+    /// hasRedundancy, degradationPriority, isNonSync...) are written as given.
+    #[wasm_bindgen]
+    pub fn push_video_with_flags(
+        &mut self,
+        data: &[u8],
+        pts: f64,
+        dts: f64,
+        duration: Option<f64>,
+        flags: SampleFlags,
+    ) -> Result<(), String> {
+        self.state.push_video_chunk_with_flags(
+            data,
+            pts as u64,
+            dts as u64,
+            duration.map(|d| d as u32),
+            flags,
+        )
+    }
+
+    /// Attach auxiliary information (at most 255 bytes, e.g. HDR dynamic
+    /// metadata) to the next video chunk pushed
+    ///
+    /// Needs `auxInfoType` in the config; the fragment references the bytes
+    /// with saiz/saio boxes of that type.
+    #[wasm_bindgen]
+    pub fn set_next_video_aux(&mut self, aux: &[u8]) -> Result<(), String> {
+        self.state.set_next_video_aux(aux)
+    }
+
     /// Add a video chunk with Annex B format data (auto-converts to AVCC)
     ///
     /// Use this when the video data uses start codes (0x00 0x00 0x00 0x01)
@@ -244,6 +746,34 @@ impl MuxideMuxer {
         self.state.has_video()
     }
 
+    /// Change how input anomalies are handled ("strict", "warn" or "lenient")
+    #[wasm_bindgen]
+    pub fn set_validation_mode(&mut self, mode: ValidationMode) {
+        self.state.set_validation_mode(mode);
+    }
+
+    /// Change the fragment duration, starting with the fragment being built
+    #[wasm_bindgen]
+    pub fn set_fragment_duration_ms(&mut self, fragment_duration_ms: u32) {
+        self.state.set_fragment_duration_ms(fragment_duration_ms);
+    }
+
+    /// Switch the audio track to a new encoder configuration, e.g. after the
+    /// input device changed sample rate; returns false if it is unchanged
+    ///
+    /// The buffered frames are flushed first, and `get_init_segment` then
+    /// returns an init segment describing both configurations.
+    #[wasm_bindgen]
+    pub fn change_audio_config(
+        &mut self,
+        sample_rate: u32,
+        channels: u16,
+        description: Option<Vec<u8>>,
+    ) -> Result<bool, String> {
+        self.state
+            .change_audio_config(sample_rate, channels, description)
+    }
+
     /// Force flush the current segment
     #[wasm_bindgen]
     pub fn flush(&mut self) -> Result<(), String> {
@@ -261,12 +791,45 @@ impl MuxideMuxer {
         result
     }
 
+    /// Take all pending media segments with their chunk metadata
+    ///
+    /// Each segment comes with its ChunkId on the muxed track, start timestamp,
+    /// keyframe flag and BLAKE3 hash, so callers never hash segments in JS.
+    #[wasm_bindgen(unchecked_return_type = "{ metadata: ChunkMetadata, data: Uint8Array }[]")]
+    pub fn get_pending_chunks(&mut self, session_id: String) -> Result<js_sys::Array, String> {
+        let chunks = self.state.take_pending_chunks(&SessionId::from(session_id));
+        let result = js_sys::Array::new();
+        for chunk in chunks {
+            let entry = js_sys::Object::new();
+            let metadata =
+                serde_wasm_bindgen::to_value(&chunk.metadata).map_err(|e| e.to_string())?;
+            let data = js_sys::Uint8Array::from(&chunk.data[..]);
+            js_sys::Reflect::set(&entry, &"metadata".into(), &metadata)
+                .map_err(|e| format!("{:?}", e))?;
+            js_sys::Reflect::set(&entry, &"data".into(), &data).map_err(|e| format!("{:?}", e))?;
+            result.push(&entry);
+        }
+        Ok(result)
+    }
+
     /// Check if there are any pending segments
     #[wasm_bindgen]
     pub fn has_pending_segments(&self) -> bool {
         self.state.has_pending_segments()
     }
 
+    /// Set the number of real audio samples, for the gapless remainder count
+    #[wasm_bindgen]
+    pub fn set_audio_valid_samples(&mut self, samples: f64) {
+        self.state.set_audio_valid_samples(samples as u64);
+    }
+
+    /// Gapless sample counts, for audio-only muxers with `audioPrimingSamples`
+    #[wasm_bindgen]
+    pub fn get_gapless_info(&self) -> Option<GaplessInfo> {
+        self.state.gapless_info()
+    }
+
     /// Get the complete fMP4 file (init segment + all media segments)
     #[wasm_bindgen]
     pub fn get_complete_file(&mut self) -> Result<Vec<u8>, String> {
@@ -284,36 +847,2731 @@ impl MuxideMuxer {
     pub fn get_audio_frame_count(&self) -> u32 {
         self.state.audio_frame_count
     }
+
+    /// Get frame, segment and buffer counters
+    #[wasm_bindgen]
+    pub fn get_stats(&self) -> MuxerStats {
+        self.state.stats()
+    }
+
+    /// Get how long after capture each segment was emitted and acknowledged
+    #[wasm_bindgen]
+    pub fn get_latency_report(&mut self) -> LatencyReport {
+        self.state.latency_report()
+    }
+
+    /// Take the frames skipped as empty or oversized since the last call
+    #[wasm_bindgen(unchecked_return_type = "SkippedFrame[]")]
+    pub fn take_skipped_frames(&mut self) -> Result<JsValue, String> {
+        serde_wasm_bindgen::to_value(&self.state.take_skipped_frames()).map_err(|e| e.to_string())
+    }
+
+    /// Start logging pushes to a replayable trace (no media content)
+    #[wasm_bindgen]
+    pub fn enable_trace(&mut self) -> Result<(), String> {
+        self.state.enable_trace()
+    }
+
+    /// Get the trace recorded since `enable_trace`, for attaching to a bug report
+    #[wasm_bindgen]
+    pub fn get_trace(&self) -> Option<Vec<u8>> {
+        self.state.trace().map(<[u8]>::to_vec)
+    }
+
+    /// Serialize the complete muxer state for a checkpoint or worker handoff
+    #[wasm_bindgen]
+    pub fn serialize_state(&self) -> Result<Vec<u8>, String> {
+        self.state.serialize_state()
+    }
+
+    /// Create a MuxideMuxer from bytes produced by `serialize_state`
+    #[wasm_bindgen]
+    pub fn restore_state(bytes: &[u8]) -> Result<MuxideMuxer, String> {
+        Ok(MuxideMuxer {
+            state: MuxideMuxerState::restore_state(bytes)?,
+        })
+    }
 }
 
-// ===== Utility WASM Functions =====
+// ===== StreamingMuxer WASM Bindings =====
 
-/// Convert Annex B format to AVCC format
+/// Muxer that writes its output straight to a destination
 ///
-/// Use this to convert WebCodecs output (Annex B) to the format
-/// expected by the muxer (AVCC).
+/// Unlike `MuxideMuxer`, segments are not buffered for `get_pending_segments`:
+/// the init segment and each finished segment go to a callback, a
+/// `WritableStream` or an OPFS file as soon as they are built.
 #[wasm_bindgen]
-pub fn convert_annex_b_to_avcc(annex_b: &[u8]) -> Vec<u8> {
-    annex_b_to_avcc(annex_b)
+pub struct StreamingMuxer {
+    state: MuxideMuxerState<Box<dyn SegmentSink>>,
+    closed: Option<js_sys::Promise>,
+    writer: Option<web_sys::WritableStreamDefaultWriter>,
 }
 
-/// Extract SPS and PPS from avcC data
+#[wasm_bindgen]
+impl StreamingMuxer {
+    /// Call `callback(data, info)` with the init segment (`info` null) and
+    /// each media segment (`info`: `{ sequence, startUs, hasKeyframe }`)
+    #[wasm_bindgen]
+    pub fn to_callback(config: MuxideConfig, callback: js_sys::Function) -> StreamingMuxer {
+        let sink = CallbackSink::new(move |output: SinkOutput<'_>| {
+            let (data, info) = match output {
+                SinkOutput::Init(data) => (js_sys::Uint8Array::from(data), JsValue::NULL),
+                SinkOutput::Segment(segment) => {
+                    let info = js_sys::Object::new();
+                    for (key, value) in [
+                        ("sequence", JsValue::from_f64(segment.sequence as f64)),
+                        ("startUs", JsValue::from_f64(segment.start_us as f64)),
+                        ("hasKeyframe", JsValue::from_bool(segment.has_keyframe)),
+                    ] {
+                        js_sys::Reflect::set(&info, &key.into(), &value)
+                            .map_err(|e| format!("{:?}", e))?;
+                    }
+                    (js_sys::Uint8Array::from(&segment.data[..]), info.into())
+                }
+                SinkOutput::Finalize => return Ok(()),
+            };
+            callback
+                .call2(&JsValue::NULL, &data, &info)
+                .map_err(|e| format!("Segment callback failed: {:?}", e))?;
+            Ok(())
+        });
+        StreamingMuxer {
+            state: MuxideMuxerState::with_sink(config, Box::new(sink)),
+            closed: None,
+            writer: None,
+        }
+    }
+
+    /// Write the output as one fMP4 byte stream to `stream`
+    #[wasm_bindgen]
+    pub fn to_stream(
+        config: MuxideConfig,
+        stream: web_sys::WritableStream,
+    ) -> Result<StreamingMuxer, String> {
+        let sink = WritableStreamSink::new(&stream)?;
+        Ok(StreamingMuxer {
+            closed: Some(sink.closed()),
+            writer: Some(sink.writer()),
+            state: MuxideMuxerState::with_sink(config, Box::new(sink)),
+        })
+    }
+
+    /// Write the output to the file `name` of a session directory in OPFS
+    #[wasm_bindgen]
+    pub async fn to_opfs(
+        config: MuxideConfig,
+        session_id: SessionId,
+        name: String,
+    ) -> Result<StreamingMuxer, String> {
+        let store = OpfsStore::open().await?;
+        let sink = OpfsSink::create(&store, &session_id, &name).await?;
+        Ok(StreamingMuxer {
+            closed: Some(sink.closed()),
+            writer: Some(sink.writer()),
+            state: MuxideMuxerState::with_sink(config, Box::new(sink)),
+        })
+    }
+
+    /// Initialize the muxer, writing the init segment to the destination
+    #[wasm_bindgen]
+    pub fn initialize(&mut self) -> Result<(), String> {
+        self.state.init()
+    }
+
+    /// Add a video chunk (see `MuxideMuxer.push_video`)
+    #[wasm_bindgen]
+    pub fn push_video(
+        &mut self,
+        data: &[u8],
+        timestamp: f64,
+        is_keyframe: bool,
+    ) -> Result<(), String> {
+        self.state
+            .push_video_chunk(data, timestamp as u64, is_keyframe)
+    }
+
+    /// Add a video chunk with explicit timestamps (see `MuxideMuxer.push_video_full`)
+    #[wasm_bindgen]
+    pub fn push_video_full(
+        &mut self,
+        data: &[u8],
+        pts: f64,
+        dts: f64,
+        duration: Option<f64>,
+        flags: u32,
+    ) -> Result<(), String> {
+        self.state.push_video_chunk_full(
+            data,
+            pts as u64,
+            dts as u64,
+            duration.map(|d| d as u32),
+            flags,
+        )
+    }
+
+    /// Attach auxiliary information to the next video chunk (see
+    /// `MuxideMuxer.set_next_video_aux`)
+    #[wasm_bindgen]
+    pub fn set_next_video_aux(&mut self, aux: &[u8]) -> Result<(), String> {
+        self.state.set_next_video_aux(aux)
+    }
+
+    /// Add an audio chunk (see `MuxideMuxer.push_audio`)
+    #[wasm_bindgen]
+    pub fn push_audio(&mut self, data: &[u8], timestamp: f64, duration: u32) -> Result<(), String> {
+        self.state
+            .push_audio_chunk(data, timestamp as u64, duration)
+    }
+
+    /// Change the fragment duration, starting with the fragment being built
+    #[wasm_bindgen]
+    pub fn set_fragment_duration_ms(&mut self, fragment_duration_ms: u32) {
+        self.state.set_fragment_duration_ms(fragment_duration_ms);
+    }
+
+    /// Force flush the current segment to the destination
+    #[wasm_bindgen]
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.state.force_flush()
+    }
+
+    /// Flush the last segment and close the destination
+    ///
+    /// For streams and OPFS files, resolves once everything is written.
+    #[wasm_bindgen]
+    pub async fn finish(&mut self) -> Result<(), String> {
+        self.state.finish()?;
+        if let Some(closed) = self.closed.take() {
+            JsFuture::from(closed)
+                .await
+                .map_err(|e| format!("Failed to write muxer output: {:?}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Get frame, segment and buffer counters
+    #[wasm_bindgen]
+    pub fn get_stats(&self) -> MuxerStats {
+        self.state.stats()
+    }
+
+    /// Get how long after capture each segment was emitted and acknowledged
+    #[wasm_bindgen]
+    pub fn get_latency_report(&mut self) -> LatencyReport {
+        self.state.latency_report()
+    }
+
+    /// Room left in the destination stream's queue (its `desiredSize`):
+    /// zero or less while it applies backpressure, undefined for callbacks
+    /// or once the stream errored
+    #[wasm_bindgen]
+    pub fn desired_size(&self) -> Option<f64> {
+        self.writer
+            .as_ref()
+            .and_then(|writer| writer.desired_size().ok().flatten())
+    }
+
+    /// Resolves once the destination stream has room for more segments
+    #[wasm_bindgen]
+    pub fn ready(&self) -> js_sys::Promise {
+        match &self.writer {
+            Some(writer) => writer.ready(),
+            None => js_sys::Promise::resolve(&JsValue::UNDEFINED),
+        }
+    }
+}
+
+// ===== Keyframe Scheduler WASM Bindings =====
+
+/// WASM wrapper for KeyframeSchedulerState
 ///
-/// Returns a tuple of (sps, pps) as separate arrays.
-/// Use this to get the codec parameters from WebCodecs VideoEncoder description.
+/// Tells the encoder wrapper which frames to encode with `keyFrame: true` so
+/// every fragment produced by a muxer with the same config starts on a sync
+/// sample.
 #[wasm_bindgen]
-pub fn parse_avcc(avcc: &[u8]) -> Result<JsValue, String> {
-    let (sps, pps) = extract_sps_pps_from_avcc(avcc)?;
+pub struct KeyframeScheduler {
+    state: KeyframeSchedulerState,
+}
 
-    // Return as a JS object with sps and pps properties
-    let result = js_sys::Object::new();
-    let sps_array = js_sys::Uint8Array::from(&sps[..]);
-    let pps_array = js_sys::Uint8Array::from(&pps[..]);
+#[wasm_bindgen]
+impl KeyframeScheduler {
+    /// Create a scheduler for the muxer configuration in use
+    #[wasm_bindgen(constructor)]
+    pub fn new(config: MuxideConfig) -> Self {
+        Self {
+            state: KeyframeSchedulerState::new(&config),
+        }
+    }
 
-    js_sys::Reflect::set(&result, &"sps".into(), &sps_array).map_err(|e| format!("{:?}", e))?;
-    js_sys::Reflect::set(&result, &"pps".into(), &pps_array).map_err(|e| format!("{:?}", e))?;
+    /// Whether the frame at `timestamp` (microseconds) must be a keyframe
+    ///
+    /// Call once per frame, in encode order, before encoding it.
+    #[wasm_bindgen]
+    pub fn next_frame(&mut self, timestamp: f64) -> bool {
+        self.state.next_frame(timestamp as u64)
+    }
 
-    Ok(result.into())
+    /// Request a keyframe on the next frame regardless of fragment position
+    #[wasm_bindgen]
+    pub fn force_next(&mut self) {
+        self.state.force_next();
+    }
+
+    /// Follow a change of the muxer's fragment duration
+    #[wasm_bindgen]
+    pub fn set_fragment_duration_ms(&mut self, fragment_duration_ms: u32) {
+        self.state.set_fragment_duration_ms(fragment_duration_ms);
+    }
+}
+
+// ===== Segment Retiming WASM Bindings =====
+
+/// WASM wrapper for SegmentRetimerState
+///
+/// Pass every segment to stitch through `retime`, in order.
+#[wasm_bindgen]
+pub struct SegmentRetimer {
+    state: SegmentRetimerState,
+}
+
+#[wasm_bindgen]
+impl SegmentRetimer {
+    /// Create a retimer for segments described by `init_segment`
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        init_segment: &[u8],
+        options: Option<RetimeOptions>,
+    ) -> Result<SegmentRetimer, String> {
+        Ok(Self {
+            state: SegmentRetimerState::new(init_segment, options.unwrap_or_default())?,
+        })
+    }
+
+    /// Rewrite the next segment's sequence numbers and decode times
+    #[wasm_bindgen]
+    pub fn retime(&mut self, segment: &[u8]) -> Result<Vec<u8>, String> {
+        self.state.retime(segment)
+    }
+
+    /// Sequence number the next fragment will get
+    #[wasm_bindgen]
+    pub fn next_sequence(&self) -> u32 {
+        self.state.next_sequence()
+    }
+}
+
+// ===== Sequence Continuity WASM Bindings =====
+
+/// WASM wrapper for SequenceContinuity
+///
+/// Pass every segment of a stream through `renumber` (or `check`), in order.
+#[wasm_bindgen]
+pub struct FragmentRenumberer {
+    state: SequenceContinuity,
+}
+
+#[wasm_bindgen]
+impl FragmentRenumberer {
+    /// Create a renumberer whose first fragment gets `first_sequence`
+    /// (default 1)
+    #[wasm_bindgen(constructor)]
+    pub fn new(first_sequence: Option<u32>) -> FragmentRenumberer {
+        Self {
+            state: SequenceContinuity::new(first_sequence.unwrap_or(1)),
+        }
+    }
+
+    /// Rewrite the next segment's mfhd sequence numbers to continue the
+    /// numbering
+    #[wasm_bindgen]
+    pub fn renumber(&mut self, segment: &[u8]) -> Result<Vec<u8>, String> {
+        self.state.renumber(segment)
+    }
+
+    /// Check the next segment without rewriting it, returning the fragments
+    /// whose sequence number does not increase
+    #[wasm_bindgen(unchecked_return_type = "SequenceBreak[]")]
+    pub fn check(&mut self, segment: &[u8]) -> Result<JsValue, String> {
+        let breaks = self.state.check(segment)?;
+        serde_wasm_bindgen::to_value(&breaks).map_err(|e| e.to_string())
+    }
+
+    /// Sequence number the next fragment will get
+    #[wasm_bindgen]
+    pub fn next_sequence(&self) -> u32 {
+        self.state.next_sequence()
+    }
+
+    /// Whether every sequence number seen so far increased
+    #[wasm_bindgen]
+    pub fn is_continuous(&self) -> bool {
+        self.state.is_continuous()
+    }
+}
+
+// ===== Chunk Sizing WASM Bindings =====
+
+/// WASM wrapper for ChunkSizePolicyState
+///
+/// Feed it the chunks from `onChunkReady` and the uploads as they finish, call
+/// `update` with the upload backlog, and pass a returned duration to
+/// `Recorder.set_fragment_duration_ms`.
+#[wasm_bindgen]
+pub struct ChunkSizePolicy {
+    state: ChunkSizePolicyState,
+}
+
+#[wasm_bindgen]
+impl ChunkSizePolicy {
+    /// Create a policy starting from the recorder's fragment duration
+    #[wasm_bindgen(constructor)]
+    pub fn new(config: Option<ChunkSizingConfig>, fragment_duration_ms: u32) -> Self {
+        Self {
+            state: ChunkSizePolicyState::new(config.unwrap_or_default(), fragment_duration_ms),
+        }
+    }
+
+    /// Record a chunk produced by the recorder
+    #[wasm_bindgen]
+    pub fn observe_chunk(&mut self, metadata: ChunkMetadata) {
+        self.state.observe_chunk(&metadata);
+    }
+
+    /// Record a finished upload of `bytes` that took `elapsed_ms`
+    #[wasm_bindgen]
+    pub fn observe_upload(&mut self, bytes: f64, elapsed_ms: f64) {
+        self.state.observe_upload(bytes as u64, elapsed_ms as u64);
+    }
+
+    /// Recompute for `backlog_bytes` still to upload
+    ///
+    /// Returns the new fragment duration (ms) when it should change.
+    #[wasm_bindgen]
+    pub fn update(&mut self, backlog_bytes: f64) -> Option<u32> {
+        self.state.update(backlog_bytes as u64)
+    }
+
+    /// Fragment duration currently recommended (ms)
+    #[wasm_bindgen]
+    pub fn fragment_duration_ms(&self) -> u32 {
+        self.state.fragment_duration_ms()
+    }
+
+    /// Smoothed upload throughput in bytes per second
+    #[wasm_bindgen]
+    pub fn throughput(&self) -> Option<f64> {
+        self.state.throughput()
+    }
+
+    /// Chunk size aimed for at the last update
+    #[wasm_bindgen]
+    pub fn target_chunk_bytes(&self) -> Option<f64> {
+        self.state.target_chunk_bytes().map(|bytes| bytes as f64)
+    }
+}
+
+// ===== Upload Queue WASM Bindings =====
+
+/// WASM wrapper for UploadQueueState
+///
+/// Decides which init segment or chunk to upload next across sessions; the
+/// caller uploads it and reports back with `complete` or `fail`. Call
+/// `next_job` until it returns undefined whenever an upload finishes, a
+/// manifest grows or the network changes, and again at `ready_at_ms` when
+/// the bandwidth cap held it back.
+#[wasm_bindgen]
+pub struct UploadQueue {
+    state: UploadQueueState,
+}
+
+#[wasm_bindgen]
+impl UploadQueue {
+    #[wasm_bindgen(constructor)]
+    pub fn new(config: Option<UploadQueueConfig>) -> Self {
+        Self {
+            state: UploadQueueState::new(config.unwrap_or_default()),
+        }
+    }
+
+    /// Queue a session, resuming from a tracker saved with `tracker_json`
+    #[wasm_bindgen]
+    pub fn add_session(
+        &mut self,
+        session_id: SessionId,
+        lane: UploadLane,
+        tracker_json: Option<String>,
+    ) -> Result<(), String> {
+        let tracker = match tracker_json {
+            Some(json) => UploadTracker::from_json(&json)?,
+            None => UploadTracker::new(session_id.clone()),
+        };
+        if *tracker.session_id() != session_id {
+            return Err(format!(
+                "Saved tracker belongs to session {}",
+                tracker.session_id()
+            ));
+        }
+        self.state.add_session(tracker, lane)
+    }
+
+    /// Stop queueing a session; returns its tracker as JSON
+    #[wasm_bindgen]
+    pub fn remove_session(&mut self, session_id: SessionId) -> Result<Option<String>, String> {
+        self.state
+            .remove_session(&session_id)
+            .map(|tracker| tracker.to_json())
+            .transpose()
+    }
+
+    /// Tracker of a session as JSON, to persist for resuming
+    #[wasm_bindgen]
+    pub fn tracker_json(&self, session_id: SessionId) -> Result<String, String> {
+        self.state
+            .tracker(&session_id)
+            .ok_or_else(|| format!("Session {} is not queued", session_id))?
+            .to_json()
+    }
+
+    #[wasm_bindgen]
+    pub fn progress(&self, session_id: SessionId) -> Option<UploadProgress> {
+        self.state.tracker(&session_id).map(UploadTracker::progress)
+    }
+
+    /// Queue the new chunks of a session's manifest
+    #[wasm_bindgen]
+    pub fn sync_manifest(&mut self, manifest: ChunkManifest) -> Result<u32, String> {
+        self.state
+            .sync_manifest(&manifest)
+            .map(|added| added as u32)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_lane(&mut self, session_id: SessionId, lane: UploadLane) -> Result<(), String> {
+        self.state.set_lane(&session_id, lane)
+    }
+
+    /// Next upload to start, or undefined if none may start now
+    #[wasm_bindgen]
+    pub fn next_job(&mut self) -> Result<Option<UploadJob>, String> {
+        self.state.next_job()
+    }
+
+    #[wasm_bindgen]
+    pub fn complete(&mut self, job: UploadJob) -> Result<(), String> {
+        self.state.complete(&job)
+    }
+
+    /// Report a failed upload; returns "pending" if it will be retried
+    #[wasm_bindgen]
+    pub fn fail(
+        &mut self,
+        job: UploadJob,
+        error: &str,
+        max_retries: u32,
+    ) -> Result<UploadState, String> {
+        self.state.fail(&job, error, max_retries)
+    }
+
+    #[wasm_bindgen]
+    pub fn pause(&mut self) {
+        self.state.pause();
+    }
+
+    #[wasm_bindgen]
+    pub fn resume(&mut self) {
+        self.state.resume();
+    }
+
+    /// Apply `online`/`offline` events and `navigator.connection` changes
+    #[wasm_bindgen]
+    pub fn set_network(&mut self, status: NetworkStatus) {
+        self.state.set_network(status);
+    }
+
+    /// When to call `next_job` again if the bandwidth cap held it back
+    /// (Unix time in milliseconds)
+    #[wasm_bindgen]
+    pub fn ready_at_ms(&self) -> Option<f64> {
+        self.state.ready_at_ms().map(|ms| ms as f64)
+    }
+
+    #[wasm_bindgen]
+    pub fn in_flight(&self) -> u32 {
+        self.state.in_flight()
+    }
+}
+
+// ===== ADTS Writer WASM Bindings =====
+
+/// WASM wrapper for AdtsWriterState
+///
+/// Frames raw AAC from WebCodecs as ADTS for endpoints that cannot take fMP4.
+#[wasm_bindgen]
+pub struct AdtsWriter {
+    state: AdtsWriterState,
+}
+
+#[wasm_bindgen]
+impl AdtsWriter {
+    /// Create a writer for AAC-LC at the given sample rate and channel count
+    #[wasm_bindgen(constructor)]
+    pub fn new(sample_rate: u32, channels: u16) -> Result<AdtsWriter, String> {
+        Ok(Self {
+            state: AdtsWriterState::new(sample_rate, channels)?,
+        })
+    }
+
+    /// Create a writer from an AudioSpecificConfig (WebCodecs
+    /// `decoderConfig.description`)
+    #[wasm_bindgen]
+    pub fn from_audio_specific_config(config: &[u8]) -> Result<AdtsWriter, String> {
+        Ok(Self {
+            state: AdtsWriterState::from_audio_specific_config(config)?,
+        })
+    }
+
+    /// Wrap a raw AAC frame in an ADTS header
+    #[wasm_bindgen]
+    pub fn wrap(&self, frame: &[u8]) -> Result<Vec<u8>, String> {
+        self.state.wrap(frame)
+    }
+}
+
+// ===== Ogg/Opus Writer WASM Bindings =====
+
+/// WASM wrapper for OggOpusWriterState
+///
+/// Writes the Opus packets of a WebCodecs `AudioEncoder` as an `.opus` file
+/// for audio-only exports.
+#[wasm_bindgen]
+pub struct OggOpusWriter {
+    state: OggOpusWriterState,
+}
+
+#[wasm_bindgen]
+impl OggOpusWriter {
+    /// Create a writer; headers are written with the first output
+    #[wasm_bindgen(constructor)]
+    pub fn new(config: OggOpusConfig) -> Result<OggOpusWriter, String> {
+        Ok(Self {
+            state: OggOpusWriterState::new(config)?,
+        })
+    }
+
+    /// Add an Opus packet; returns the Ogg bytes it completed (may be empty)
+    #[wasm_bindgen]
+    pub fn push(&mut self, packet: &[u8]) -> Result<Vec<u8>, String> {
+        self.state.push_packet(packet)
+    }
+
+    /// End the stream; returns the last page
+    #[wasm_bindgen]
+    pub fn finish(&mut self) -> Result<Vec<u8>, String> {
+        self.state.finish()
+    }
+
+    /// Duration written so far, in microseconds
+    #[wasm_bindgen]
+    pub fn duration_us(&self) -> f64 {
+        (self.state.duration_samples() * 1_000_000 / OPUS_GRANULE_RATE) as f64
+    }
+}
+
+// ===== Loudness Meter WASM Bindings =====
+
+/// WASM wrapper for LoudnessMeterState
+///
+/// Fed with the PCM an AudioWorklet sees; `levels()` drives the input meter
+/// and `stats()` is stored with `Recorder.set_loudness_stats()` when
+/// recording stops.
+#[wasm_bindgen]
+pub struct LoudnessMeter {
+    state: LoudnessMeterState,
+}
+
+#[wasm_bindgen]
+impl LoudnessMeter {
+    /// Create a meter for the input's sample rate and channel count
+    #[wasm_bindgen(constructor)]
+    pub fn new(sample_rate: u32, channels: u16) -> Result<LoudnessMeter, String> {
+        Ok(Self {
+            state: LoudnessMeterState::new(sample_rate, channels)?,
+        })
+    }
+
+    /// Add interleaved samples in [-1, 1]
+    #[wasm_bindgen]
+    pub fn push(&mut self, samples: &[f32]) -> Result<(), String> {
+        self.state.push_interleaved(samples)
+    }
+
+    /// Get RMS and peak since the previous call, plus the current loudness
+    #[wasm_bindgen]
+    pub fn levels(&mut self) -> AudioLevels {
+        self.state.levels()
+    }
+
+    /// Get the loudness summary of everything pushed so far
+    #[wasm_bindgen]
+    pub fn stats(&self) -> LoudnessStats {
+        self.state.stats()
+    }
+
+    /// Forget everything measured so far
+    #[wasm_bindgen]
+    pub fn reset(&mut self) {
+        self.state.reset();
+    }
+}
+
+// ===== Waveform Builder WASM Bindings =====
+
+/// WASM wrapper for WaveformBuilderState
+///
+/// Fed with the same AudioWorklet PCM as the `LoudnessMeter`; `take_peaks()`
+/// returns the peaks to draw while recording, and `finish()` the waveform to
+/// store with `ChunkSink.put_waveform()` when recording stops.
+#[wasm_bindgen]
+pub struct WaveformBuilder {
+    state: WaveformBuilderState,
+}
+
+#[wasm_bindgen]
+impl WaveformBuilder {
+    /// Create a builder for the input's sample rate and channel count,
+    /// 100 peaks per second unless `peaks_per_second` is given
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        sample_rate: u32,
+        channels: u16,
+        peaks_per_second: Option<u32>,
+    ) -> Result<WaveformBuilder, String> {
+        Ok(Self {
+            state: WaveformBuilderState::new(
+                sample_rate,
+                channels,
+                peaks_per_second.unwrap_or(DEFAULT_PEAKS_PER_SECOND),
+            )?,
+        })
+    }
+
+    /// Add interleaved samples in [-1, 1]
+    #[wasm_bindgen]
+    pub fn push(&mut self, samples: &[f32]) -> Result<(), String> {
+        self.state.push_interleaved(samples)
+    }
+
+    /// Get the peaks completed since the previous call (0-255)
+    #[wasm_bindgen]
+    pub fn take_peaks(&mut self) -> Vec<u8> {
+        self.state.take_peaks()
+    }
+
+    /// Get every completed peak so far
+    #[wasm_bindgen]
+    pub fn waveform(&self) -> Waveform {
+        self.state.waveform().clone()
+    }
+
+    /// Close the last partial interval and get the whole waveform
+    #[wasm_bindgen]
+    pub fn finish(&mut self) -> Waveform {
+        self.state.finish()
+    }
+}
+
+// ===== Downmix Mixer WASM Bindings =====
+
+/// WASM wrapper for DownmixMixerState
+///
+/// Fed with the PCM of each source's AudioWorklet (e.g. screen and
+/// microphone); `take_mixed()` returns the mix to encode, and `recipe()` is
+/// stored with `Recorder.set_downmix()`.
+#[wasm_bindgen]
+pub struct DownmixMixer {
+    state: DownmixMixerState,
+}
+
+#[wasm_bindgen]
+impl DownmixMixer {
+    /// Create a mixer for the sources of `recipe`, whose PCM all has
+    /// `channels` channels
+    #[wasm_bindgen(constructor)]
+    pub fn new(recipe: DownmixRecipe, channels: u16) -> Result<DownmixMixer, String> {
+        Ok(Self {
+            state: DownmixMixerState::new(recipe, channels)?,
+        })
+    }
+
+    /// Add interleaved samples of the source at index `source` in the recipe
+    #[wasm_bindgen]
+    pub fn push(&mut self, source: usize, samples: &[f32]) -> Result<(), String> {
+        self.state.push(source, samples)
+    }
+
+    /// Get the mix of the frames every source has delivered
+    #[wasm_bindgen]
+    pub fn take_mixed(&mut self) -> Vec<f32> {
+        self.state.take_mixed()
+    }
+
+    /// Get the mix of everything left, when recording stops
+    #[wasm_bindgen]
+    pub fn finish(&mut self) -> Vec<f32> {
+        self.state.finish()
+    }
+
+    /// Get the number of mixed samples clamped to full scale
+    #[wasm_bindgen]
+    pub fn clipped_samples(&self) -> f64 {
+        self.state.clipped_samples() as f64
+    }
+
+    /// Get the number of frames mixed with a stalled source taken as silent
+    #[wasm_bindgen]
+    pub fn filled_frames(&self) -> f64 {
+        self.state.filled_frames() as f64
+    }
+
+    /// Get the recipe to store in the recording metadata, marked as applied
+    #[wasm_bindgen]
+    pub fn recipe(&self) -> DownmixRecipe {
+        self.state.recipe()
+    }
+}
+
+// ===== Recorder WASM Bindings =====
+
+/// WASM wrapper for RecorderState
+///
+/// Drives a muxer for one session and reports results through callbacks:
+/// `onChunkReady(metadata, data)` for every finished segment and
+/// `onStateChange(from, to)` for lifecycle changes, plus `onStall` and
+/// `onStallRecovered` when the stall watchdog is enabled. On `stop()` all
+/// remaining chunks are reported before the `stopped` state change.
+#[wasm_bindgen]
+pub struct Recorder {
+    state: RecorderState,
+    on_chunk_ready: Option<js_sys::Function>,
+    on_state_change: Option<js_sys::Function>,
+    on_stall: Option<js_sys::Function>,
+    on_stall_recovered: Option<js_sys::Function>,
+    on_thumbnail: Option<js_sys::Function>,
+    on_adts_frame: Option<js_sys::Function>,
+    on_silence: Option<js_sys::Function>,
+    on_silence_ended: Option<js_sys::Function>,
+    on_frame_skipped: Option<js_sys::Function>,
+    on_audio_config_change: Option<js_sys::Function>,
+    sink: Option<ChunkSink>,
+    stream: Option<SegmentStream>,
+    /// Recording to continue with `resume_transfer()`
+    pending_transfer: Option<RecorderTransfer>,
+}
+
+#[wasm_bindgen]
+impl Recorder {
+    /// Create a recorder for a session
+    #[wasm_bindgen(constructor)]
+    pub fn new(session_id: SessionId, config: MuxideConfig) -> Self {
+        Self::new_with_state(RecorderState::new(session_id, config))
+    }
+
+    /// Persist the init segment, every chunk and the manifest to a chunk sink
+    ///
+    /// Writes are queued in order and run in the background; await
+    /// `sink.flush()` after `stop()` to make sure everything reached disk.
+    #[wasm_bindgen]
+    pub fn set_sink(&mut self, sink: &ChunkSink) {
+        self.sink = Some(sink.clone());
+        if let Some(stream) = &self.stream {
+            stream.set_sink(sink);
+        }
+    }
+
+    /// Stream the init segment and every chunk to a server while recording
+    ///
+    /// The stream is ended after `stop()`; `stream.isFinished()` turns true
+    /// once the server has stored everything. Chunks the server asks for
+    /// again are read back from the recorder's sink.
+    #[wasm_bindgen]
+    pub fn set_stream(&mut self, stream: &SegmentStream) {
+        if let Some(sink) = &self.sink {
+            stream.set_sink(sink);
+        }
+        self.stream = Some(stream.clone());
+    }
+
+    /// Log every accepted frame to a write-ahead log in the sink before muxing
+    ///
+    /// Bounds the loss on a crash to `flush_interval_ms` of media instead of a
+    /// whole fragment; recover with `ChunkSink.recover()`. Requires a sink and
+    /// must be called before `start()`. The log is deleted after `stop()`.
+    #[wasm_bindgen]
+    pub fn enable_wal(&mut self, flush_interval_ms: u32) -> Result<(), String> {
+        if self.sink.is_none() {
+            return Err("Write-ahead log requires a sink".to_string());
+        }
+        self.state.enable_wal(flush_interval_ms)
+    }
+
+    /// Keep the last `max_segments` segments for a near-live preview
+    ///
+    /// Must be called before `start()`. Append `get_preview_init_segment()`
+    /// and then the segments listed by `get_preview_window()` to an MSE
+    /// `SourceBuffer`, or serve them with `get_preview_playlist()`.
+    #[wasm_bindgen]
+    pub fn enable_preview(&mut self, max_segments: u32) -> Result<(), String> {
+        self.state.enable_preview(max_segments)
+    }
+
+    /// Set the callback invoked as `(metadata: ChunkMetadata, data: Uint8Array)`
+    #[wasm_bindgen]
+    pub fn set_on_chunk_ready(&mut self, callback: js_sys::Function) {
+        self.on_chunk_ready = Some(callback);
+    }
+
+    /// Set the callback invoked as `(from: RecorderStatus, to: RecorderStatus)`
+    #[wasm_bindgen]
+    pub fn set_on_state_change(&mut self, callback: js_sys::Function) {
+        self.on_state_change = Some(callback);
+    }
+
+    /// Report a stream as stalled when it delivers no frames for `threshold_ms`
+    ///
+    /// Must be called before `start()`. Call `check_stalls()` on a timer so a
+    /// stall is noticed even when no stream is pushing.
+    #[wasm_bindgen]
+    pub fn enable_stall_watchdog(&mut self, threshold_ms: u32) -> Result<(), String> {
+        self.state.enable_stall_watchdog(threshold_ms)
+    }
+
+    /// Set the callback invoked as `(track: TrackKind, stalledMs: number)` on a stall
+    #[wasm_bindgen]
+    pub fn set_on_stall(&mut self, callback: js_sys::Function) {
+        self.on_stall = Some(callback);
+    }
+
+    /// Set the callback invoked as `(track: TrackKind, stalledMs: number)` when
+    /// a stalled stream delivers frames again
+    #[wasm_bindgen]
+    pub fn set_on_stall_recovered(&mut self, callback: js_sys::Function) {
+        self.on_stall_recovered = Some(callback);
+    }
+
+    /// Pass every `every_nth` keyframe to the thumbnail callback
+    ///
+    /// Must be called before `start()`. The first keyframe is always passed.
+    #[wasm_bindgen]
+    pub fn enable_thumbnails(&mut self, every_nth: u32) -> Result<(), String> {
+        self.state.enable_thumbnails(every_nth)
+    }
+
+    /// Set the callback invoked as `(timestamp: number, data: Uint8Array)` with
+    /// a keyframe (AVCC) to decode into a thumbnail; the timestamp is in
+    /// microseconds of recording time
+    #[wasm_bindgen]
+    pub fn set_on_thumbnail(&mut self, callback: js_sys::Function) {
+        self.on_thumbnail = Some(callback);
+    }
+
+    /// Pass every muxed audio frame, wrapped in an ADTS header, to the ADTS
+    /// callback, e.g. to stream it to a legacy endpoint
+    ///
+    /// Must be called before `start()`.
+    #[wasm_bindgen]
+    pub fn enable_adts_output(&mut self) -> Result<(), String> {
+        self.state.enable_adts_output()
+    }
+
+    /// Set the callback invoked as `(timestamp: number, data: Uint8Array)` with
+    /// an ADTS frame; the timestamp is in microseconds of recording time
+    #[wasm_bindgen]
+    pub fn set_on_adts_frame(&mut self, callback: js_sys::Function) {
+        self.on_adts_frame = Some(callback);
+    }
+
+    /// Encrypt chunks with AES-128 (`{ uri, key }`, 16 key bytes) before they
+    /// are emitted; the preview playlist and the manifest's `keys` point
+    /// players at the key URI
+    ///
+    /// Must be called before `start()` or `resume_session()`.
+    #[wasm_bindgen]
+    pub fn enable_segment_encryption(&mut self, key: SegmentKey) -> Result<(), String> {
+        self.state.enable_segment_encryption(key)
+    }
+
+    /// Encrypt the chunks emitted from now on with another key
+    #[wasm_bindgen]
+    pub fn rotate_encryption_key(&mut self, key: SegmentKey) -> Result<(), String> {
+        self.state.rotate_encryption_key(key)
+    }
+
+    /// Stop recording `"audio"` or `"video"` until `enable_track()`, e.g.
+    /// while the microphone is muted
+    ///
+    /// Keep pushing frames: audio is recorded as silence, video holds the
+    /// last picture. The muted range ends up in the manifest's `muted` list.
+    #[wasm_bindgen]
+    pub fn disable_track(&mut self, track: &str) -> Result<(), String> {
+        self.state.disable_track(track.parse()?)
+    }
+
+    /// Record a disabled track again; video resumes at the next keyframe
+    #[wasm_bindgen]
+    pub fn enable_track(&mut self, track: &str) -> Result<(), String> {
+        self.state.enable_track(track.parse()?);
+        Ok(())
+    }
+
+    /// Whether frames pushed on `track` are recorded
+    #[wasm_bindgen]
+    pub fn is_track_enabled(&self, track: &str) -> Result<bool, String> {
+        Ok(self.state.track_enabled(track.parse()?))
+    }
+
+    /// Report sustained silence and add `silence-start` / `silence-end`
+    /// markers to the manifest
+    ///
+    /// Must be called before `start()`. Audio frames are classified by their
+    /// encoded size unless `fromAacFrames` is false; then feed levels with
+    /// `push_audio_level()`.
+    #[wasm_bindgen]
+    pub fn enable_silence_detection(&mut self, config: SilenceConfig) -> Result<(), String> {
+        self.state.enable_silence_detection(config)
+    }
+
+    /// Feed the silence detector with the RMS level (dBFS) of the audio at
+    /// `timestamp` (microseconds, same clock as `push_audio`)
+    #[wasm_bindgen]
+    pub fn push_audio_level(
+        &mut self,
+        timestamp: f64,
+        duration: u32,
+        level_db: f64,
+    ) -> Result<(), String> {
+        self.state
+            .push_audio_level(timestamp as u64, duration, level_db);
+        self.dispatch_events()
+    }
+
+    /// Set the callback invoked as `(startUs: number)` once the audio has been
+    /// silent for the minimum duration, e.g. to warn about a muted microphone
+    #[wasm_bindgen]
+    pub fn set_on_silence(&mut self, callback: js_sys::Function) {
+        self.on_silence = Some(callback);
+    }
+
+    /// Set the callback invoked as `(range: SilenceRange)` when sound resumes
+    #[wasm_bindgen]
+    pub fn set_on_silence_ended(&mut self, callback: js_sys::Function) {
+        self.on_silence_ended = Some(callback);
+    }
+
+    /// Set the callback invoked as `(frame: SkippedFrame)` when an empty or
+    /// oversized frame is skipped (e.g. a buggy encoder)
+    #[wasm_bindgen]
+    pub fn set_on_frame_skipped(&mut self, callback: js_sys::Function) {
+        self.on_frame_skipped = Some(callback);
+    }
+
+    /// Set the callback invoked as `(change: AudioConfigChange, init:
+    /// Uint8Array)` when the audio encoder switched configuration
+    ///
+    /// `init` replaces the init segment of the whole recording; the sink and
+    /// stream, if attached, already store and send it.
+    #[wasm_bindgen]
+    pub fn set_on_audio_config_change(&mut self, callback: js_sys::Function) {
+        self.on_audio_config_change = Some(callback);
+    }
+
+    /// Check for stalled streams now
+    #[wasm_bindgen]
+    pub fn check_stalls(&mut self) -> Result<(), String> {
+        self.state.check_stalls();
+        self.dispatch_events()
+    }
+
+    /// Start recording and get the fMP4 initialization segment (ftyp + moov)
+    #[wasm_bindgen]
+    pub fn start(&mut self) -> Result<Vec<u8>, String> {
+        let result = self.state.start();
+        if let (Ok(init), Some(sink)) = (&result, &self.sink) {
+            sink.queue_init_segment(self.state.manifest().session_id.clone(), init.clone());
+        }
+        if let (Ok(init), Some(stream)) = (&result, &self.stream) {
+            stream.send_init_segment(init.clone());
+        }
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Continue this session after a page reload, instead of `start()`
+    ///
+    /// Loads the manifest and recorder snapshot the sink persisted for the
+    /// session, checks that they line up and that the config still produces
+    /// the stored init segment's tracks, then keeps appending chunks to the
+    /// same recording: all of its chunks still concatenate into one file.
+    /// Requires a sink; set callbacks before calling. Returns the init segment.
+    #[wasm_bindgen]
+    pub async fn resume_session(&mut self) -> Result<Vec<u8>, String> {
+        let sink = self
+            .sink
+            .clone()
+            .ok_or("Resuming a session requires a sink")?;
+        let session_id = self.state.manifest().session_id.clone();
+        let manifest = sink
+            .storage
+            .get_manifest(&session_id)
+            .await?
+            .ok_or_else(|| format!("No manifest persisted for session {}", session_id))?;
+        let snapshot = sink
+            .storage
+            .get_snapshot(&session_id)
+            .await?
+            .ok_or_else(|| format!("No recorder snapshot persisted for session {}", session_id))?;
+        let stored_init = sink.storage.get_init_segment(&session_id).await?;
+        compat::check_config_against_init(self.state.config(), &stored_init)?.ensure()?;
+
+        let init = self.state.resume_session(manifest, &snapshot)?;
+        if let Some(stream) = &self.stream {
+            stream.send_init_segment(init.clone());
+        }
+        self.dispatch_events()?;
+        Ok(init)
+    }
+
+    /// Pack this recording so another worker can continue it (see
+    /// `Recorder.from_transfer`)
+    ///
+    /// Post the bytes with their buffer in the transfer list, e.g. before the
+    /// worker is terminated, or periodically so a crashed worker can be
+    /// replaced; stop pushing frames here once handed off.
+    #[wasm_bindgen]
+    pub fn transfer(&self) -> Result<Vec<u8>, String> {
+        self.state.transfer()?.encode()
+    }
+
+    /// Create a recorder for a recording packed by `transfer()` in another
+    /// worker
+    ///
+    /// Set the sink, stream, callbacks and features as for the original
+    /// recorder, then call `resume_transfer()`.
+    #[wasm_bindgen]
+    pub fn from_transfer(package: &[u8]) -> Result<Recorder, String> {
+        let transfer = RecorderTransfer::decode(package)?;
+        let state = RecorderState::new(transfer.session_id().clone(), transfer.config.clone());
+        let mut recorder = Self::new_with_state(state);
+        recorder.pending_transfer = Some(transfer);
+        Ok(recorder)
+    }
+
+    /// Continue the recording this recorder was created for by
+    /// `from_transfer()`, instead of `start()`; returns the init segment
+    #[wasm_bindgen]
+    pub fn resume_transfer(&mut self) -> Result<Vec<u8>, String> {
+        let transfer = self
+            .pending_transfer
+            .take()
+            .ok_or("Recorder was not created from a transfer")?;
+        let result = self.state.resume_transfer(transfer);
+        if let (Ok(init), Some(sink)) = (&result, &self.sink) {
+            sink.queue_init_segment(self.state.manifest().session_id.clone(), init.clone());
+        }
+        if let (Ok(init), Some(stream)) = (&result, &self.stream) {
+            stream.send_init_segment(init.clone());
+        }
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Pause recording; frames pushed while paused are dropped
+    #[wasm_bindgen]
+    pub fn pause(&mut self) -> Result<(), String> {
+        let result = self.state.pause();
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Resume recording; the paused interval is removed from the timeline
+    #[wasm_bindgen]
+    pub fn resume(&mut self) -> Result<(), String> {
+        let result = self.state.resume();
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Stop recording, flushing and reporting every remaining chunk
+    #[wasm_bindgen]
+    pub fn stop(&mut self) -> Result<(), String> {
+        let result = self.state.stop();
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Save as much as possible before the page goes away; call it from
+    /// `pagehide`, `visibilitychange` (hidden) and `beforeunload`
+    ///
+    /// Emits the fragment being built and every pending chunk, hashing them
+    /// for at most `budget_ms`, and marks the manifest's `tail`. The
+    /// write-ahead log, chunks, manifest and snapshot are queued on the sink
+    /// right away; await `sink.flush()` to know they landed. Returns the
+    /// tail, or undefined unless recording or paused; recording goes on if
+    /// the page stays.
+    #[wasm_bindgen]
+    pub fn emergency_flush(&mut self, budget_ms: u32) -> Result<Option<TailMarker>, String> {
+        let result = self.state.emergency_flush(budget_ms);
+        self.dispatch_events()?;
+        if let (Ok(Some(_)), Some(sink)) = (&result, &self.sink) {
+            sink.queue_manifest(self.state.manifest().clone());
+        }
+        result
+    }
+
+    /// Change the fragment (chunk) duration for the rest of the recording
+    #[wasm_bindgen]
+    pub fn set_fragment_duration_ms(&mut self, fragment_duration_ms: u32) {
+        self.state.set_fragment_duration_ms(fragment_duration_ms);
+    }
+
+    /// Enter or leave low-power mode
+    ///
+    /// Call from a `visibilitychange` listener with `document.hidden`, so
+    /// recording keeps up while the tab is in the background. Silence
+    /// callbacks are not called while it is on.
+    #[wasm_bindgen]
+    pub fn set_low_power(&mut self, enabled: bool) -> Result<(), String> {
+        let result = self.state.set_low_power(enabled);
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Choose how finished segments are hashed (default `inline`)
+    ///
+    /// With `incremental`, a chunk is handed to `onChunkReady` once its hash
+    /// is complete, a slice of it being hashed per push; call `pump_hashes`
+    /// from idle time to finish sooner.
+    /// With `background` (after `init_thread_pool`), chunks are encrypted
+    /// and hashed on the thread pool and handed out in order once done, on
+    /// the following pushes and `pump_hashes` calls.
+    #[wasm_bindgen]
+    pub fn set_hash_strategy(&mut self, strategy: HashStrategy) -> Result<(), String> {
+        let result = self.state.set_hash_strategy(strategy);
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Hash up to `budget_bytes` of the chunks waiting for their hash,
+    /// returning whether chunks are still waiting
+    #[wasm_bindgen]
+    pub fn pump_hashes(&mut self, budget_bytes: u32) -> Result<bool, String> {
+        let result = self.state.pump_hashes(budget_bytes as usize);
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Whether low-power mode is on
+    #[wasm_bindgen]
+    pub fn is_low_power(&self) -> bool {
+        self.state.low_power()
+    }
+
+    /// Whether the next video frame to encode (timestamp in microseconds) must
+    /// be a keyframe
+    ///
+    /// Call once per frame, in encode order, before encoding it.
+    #[wasm_bindgen]
+    pub fn keyframe_due(&mut self, timestamp: f64) -> bool {
+        self.state.keyframe_due(timestamp as u64)
+    }
+
+    /// Add a video frame (AVCC, timestamp in microseconds)
+    ///
+    /// Returns false if the frame was dropped.
+    #[wasm_bindgen]
+    pub fn push_video(
+        &mut self,
+        data: &[u8],
+        timestamp: f64,
+        is_keyframe: bool,
+    ) -> Result<bool, String> {
+        let result = self.state.push_video(data, timestamp as u64, is_keyframe);
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Add an audio frame (raw AAC, timestamp and duration in microseconds)
+    ///
+    /// Returns false if the frame was dropped.
+    #[wasm_bindgen]
+    pub fn push_audio(
+        &mut self,
+        data: &[u8],
+        timestamp: f64,
+        duration: u32,
+    ) -> Result<bool, String> {
+        let result = self.state.push_audio(data, timestamp as u64, duration);
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Pass on the audio encoder's `decoderConfig` (sample rate, channel
+    /// count and `description`) whenever an output carries one
+    ///
+    /// Returns true if it differs from the config in use, e.g. after the
+    /// input device changed; audio pushed from then on is muxed under it.
+    #[wasm_bindgen]
+    pub fn change_audio_config(
+        &mut self,
+        sample_rate: u32,
+        channels: u16,
+        description: Option<Vec<u8>>,
+    ) -> Result<bool, String> {
+        let result = self
+            .state
+            .change_audio_config(sample_rate, channels, description);
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Show a still picture for `duration_ms` where the recording stands,
+    /// e.g. a countdown before the first frame or an outro before `stop()`
+    ///
+    /// `keyframe` is an H.264 keyframe (AVCC) encoded with the session's
+    /// encoder; without one the last keyframe recorded is repeated. Emits
+    /// the slate's chunks like pushed frames.
+    #[wasm_bindgen]
+    pub fn insert_slate(
+        &mut self,
+        keyframe: Option<Vec<u8>>,
+        duration_ms: u32,
+    ) -> Result<(), String> {
+        let source = match keyframe {
+            Some(data) => SlateSource::Keyframe(data),
+            None => SlateSource::LastKeyframe,
+        };
+        let result = self.state.insert_slate(source, duration_ms);
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Bookmark the frame last pushed, e.g. when the user presses a bookmark
+    /// button; returns its timestamp in microseconds
+    ///
+    /// The marker lands in the manifest right away. Its `bookmark.keyframe`
+    /// (fragment sequence and byte offsets of the keyframe to seek to) is
+    /// filled in once that keyframe's chunk is emitted, at the latest on
+    /// `stop()`.
+    #[wasm_bindgen]
+    pub fn add_bookmark(&mut self, label: Option<String>) -> Result<f64, String> {
+        Ok(self.state.add_bookmark(label)? as f64)
+    }
+
+    /// Render the markers of the stopped recording as a sidecar subtitle
+    /// file (`"webvtt"` or `"srt"`), timed to the assembled MP4
+    #[wasm_bindgen]
+    pub fn export_subtitles(&self, format: SubtitleFormat) -> Result<String, String> {
+        self.state.subtitles(format)
+    }
+
+    /// Get the search index of the stopped recording (chapters, captions,
+    /// silence and talk ranges, search terms), timed to the assembled MP4
+    #[wasm_bindgen]
+    pub fn get_search_index(&self) -> Result<SessionIndex, String> {
+        self.state.search_index()
+    }
+
+    /// Also embed the search index in the file's session metadata at
+    /// `stop()` (default off)
+    #[wasm_bindgen]
+    pub fn set_embed_search_index(&mut self, enabled: bool) {
+        self.state.set_embed_search_index(enabled);
+    }
+
+    /// Sum up the stopped recording in a report signed with the
+    /// recording's `integrityKey` (hex), to send with the `synced` state
+    #[wasm_bindgen]
+    pub fn integrity_report(&self, key: &str) -> Result<SignedIntegrityReport, String> {
+        self.state.integrity_report(&parse_integrity_key(key)?)
+    }
+
+    /// Get the current lifecycle state
+    #[wasm_bindgen]
+    pub fn get_status(&self) -> RecorderStatus {
+        self.state.status()
+    }
+
+    /// Get the session manifest with every chunk reported so far
+    #[wasm_bindgen]
+    pub fn get_manifest(&self) -> ChunkManifest {
+        self.state.manifest().clone()
+    }
+
+    /// Get frame, segment and buffer counters
+    #[wasm_bindgen]
+    pub fn get_stats(&self) -> MuxerStats {
+        self.state.stats()
+    }
+
+    /// Get how long after capture each segment was muxed and reported in a
+    /// chunk-ready callback
+    #[wasm_bindgen]
+    pub fn get_latency_report(&mut self) -> LatencyReport {
+        self.state.latency_report()
+    }
+
+    /// Get the video frame rate over the last couple of seconds
+    #[wasm_bindgen]
+    pub fn get_current_fps(&self) -> Option<f64> {
+        self.state.current_fps()
+    }
+
+    /// Get the measured video frame rate of everything recorded so far
+    ///
+    /// Stored in the manifest's `metadata.framerate` on stop.
+    #[wasm_bindgen]
+    pub fn get_frame_rate_stats(&self) -> Option<FrameRateStats> {
+        self.state.frame_rate_stats()
+    }
+
+    /// Get the dropped, rejected and corrected frames and media gaps so far
+    ///
+    /// Stored in the manifest's `metadata.quality` on stop, e.g. to warn
+    /// that a recording has gaps.
+    #[wasm_bindgen]
+    pub fn get_quality_report(&self) -> QualityReport {
+        self.state.quality_report()
+    }
+
+    /// Store loudness stats (from a `LoudnessMeter`) in the recording metadata
+    #[wasm_bindgen]
+    pub fn set_loudness_stats(&mut self, stats: LoudnessStats) {
+        self.state.set_loudness(stats);
+    }
+
+    /// Store how the audio sources combine into one mix in the recording
+    /// metadata (from `DownmixMixer.recipe()` when the PCM was mixed)
+    #[wasm_bindgen]
+    pub fn set_downmix(&mut self, recipe: DownmixRecipe) {
+        self.state.set_downmix(recipe);
+    }
+
+    /// Get the init segment for the preview, once recording has started
+    #[wasm_bindgen]
+    pub fn get_preview_init_segment(&self) -> Option<Vec<u8>> {
+        self.preview()?.init_segment().map(<[u8]>::to_vec)
+    }
+
+    /// Describe the segments currently in the preview window, oldest first
+    #[wasm_bindgen(unchecked_return_type = "PreviewSegmentInfo[]")]
+    pub fn get_preview_window(&self) -> Result<JsValue, String> {
+        let window = self
+            .preview()
+            .map(LivePreviewState::window)
+            .unwrap_or_default();
+        serde_wasm_bindgen::to_value(&window).map_err(|e| e.to_string())
+    }
+
+    /// Get a preview segment (moof + mdat) by sequence number
+    #[wasm_bindgen]
+    pub fn get_preview_segment(&self, sequence: f64) -> Option<Vec<u8>> {
+        let segment = self.preview()?.segment(sequence as u64)?;
+        Some(segment.data.clone())
+    }
+
+    /// Render an HLS media playlist for the preview window
+    ///
+    /// URIs are the storage file names (`init.mp4`, `chunk-00000000.fmp4`)
+    /// prefixed with `uri_prefix`.
+    #[wasm_bindgen]
+    pub fn get_preview_playlist(&self, uri_prefix: &str) -> Option<String> {
+        Some(self.preview()?.hls_playlist(uri_prefix))
+    }
+
+    /// Render a multivariant HLS playlist pointing at the preview's media
+    /// playlist, served at `media_uri`
+    ///
+    /// Carries the tracks' codecs, which lets players start audio-only
+    /// sessions without waiting for video.
+    #[wasm_bindgen]
+    pub fn get_preview_multivariant_playlist(&self, media_uri: &str) -> Option<String> {
+        Some(self.preview()?.hls_multivariant_playlist(media_uri))
+    }
+
+    fn preview(&self) -> Option<&LivePreviewState> {
+        self.state.preview()
+    }
+
+    fn new_with_state(state: RecorderState) -> Self {
+        Self {
+            state,
+            on_chunk_ready: None,
+            on_state_change: None,
+            on_stall: None,
+            on_stall_recovered: None,
+            on_thumbnail: None,
+            on_adts_frame: None,
+            on_silence: None,
+            on_silence_ended: None,
+            on_frame_skipped: None,
+            on_audio_config_change: None,
+            sink: None,
+            stream: None,
+            pending_transfer: None,
+        }
+    }
+
+    /// Forward queued recorder events to the sink and the JS callbacks
+    ///
+    /// The events are already taken, so a throwing callback must not stop
+    /// the rest: every event reaches the sink and stream first, and the
+    /// first callback error is returned once all are dispatched.
+    fn dispatch_events(&mut self) -> Result<(), String> {
+        let mut chunks_ready = false;
+        let mut callback_error = None;
+        // Silence notifications are informational; skip them in the background
+        let quiet = self.state.low_power();
+        for event in self.state.take_events() {
+            let called = match event {
+                RecorderEvent::StateChanged { from, to } => {
+                    if let Some(sink) = &self.sink {
+                        sink.queue_manifest(self.state.manifest().clone());
+                        if to == RecorderStatus::Stopped {
+                            let session_id = &self.state.manifest().session_id;
+                            if self.state.wal_enabled() {
+                                sink.queue_delete_wal(session_id.clone());
+                            }
+                            sink.queue_delete_snapshot(session_id.clone());
+                        }
+                    }
+                    if let (RecorderStatus::Stopped, Some(stream)) = (to, &self.stream) {
+                        stream.end();
+                    }
+                    self.on_state_change
+                        .as_ref()
+                        .map_or(Ok(()), |callback| call_on_state_change(callback, from, to))
+                }
+                RecorderEvent::WalBatch(batch) => {
+                    if let Some(sink) = &self.sink {
+                        sink.queue_wal_batch(self.state.manifest().session_id.clone(), batch);
+                    }
+                    Ok(())
+                }
+                RecorderEvent::StreamStalled { track, stalled_ms } => {
+                    self.on_stall.as_ref().map_or(Ok(()), |callback| {
+                        callback
+                            .call2(
+                                &JsValue::NULL,
+                                &JsValue::from_str(track.as_str()),
+                                &JsValue::from_f64(stalled_ms as f64),
+                            )
+                            .map(drop)
+                            .map_err(|e| format!("onStall callback failed: {:?}", e))
+                    })
+                }
+                RecorderEvent::StreamRecovered { track, stalled_ms } => {
+                    self.on_stall_recovered.as_ref().map_or(Ok(()), |callback| {
+                        callback
+                            .call2(
+                                &JsValue::NULL,
+                                &JsValue::from_str(track.as_str()),
+                                &JsValue::from_f64(stalled_ms as f64),
+                            )
+                            .map(drop)
+                            .map_err(|e| format!("onStallRecovered callback failed: {:?}", e))
+                    })
+                }
+                RecorderEvent::SilenceStarted { start_us } => {
+                    let callback = self.on_silence.as_ref().filter(|_| !quiet);
+                    callback.map_or(Ok(()), |callback| {
+                        callback
+                            .call1(&JsValue::NULL, &JsValue::from_f64(start_us as f64))
+                            .map(drop)
+                            .map_err(|e| format!("onSilence callback failed: {:?}", e))
+                    })
+                }
+                RecorderEvent::SilenceEnded(range) => {
+                    let callback = self.on_silence_ended.as_ref().filter(|_| !quiet);
+                    callback.map_or(Ok(()), |callback| {
+                        let range =
+                            serde_wasm_bindgen::to_value(&range).map_err(|e| e.to_string())?;
+                        callback
+                            .call1(&JsValue::NULL, &range)
+                            .map(drop)
+                            .map_err(|e| format!("onSilenceEnded callback failed: {:?}", e))
+                    })
+                }
+                RecorderEvent::FrameSkipped(frame) => {
+                    self.on_frame_skipped.as_ref().map_or(Ok(()), |callback| {
+                        let frame =
+                            serde_wasm_bindgen::to_value(&frame).map_err(|e| e.to_string())?;
+                        callback
+                            .call1(&JsValue::NULL, &frame)
+                            .map(drop)
+                            .map_err(|e| format!("onFrameSkipped callback failed: {:?}", e))
+                    })
+                }
+                RecorderEvent::AudioConfigChanged {
+                    change,
+                    init_segment,
+                } => {
+                    if let Some(sink) = &self.sink {
+                        let session_id = self.state.manifest().session_id.clone();
+                        sink.queue_init_segment(session_id, init_segment.clone());
+                        sink.queue_manifest(self.state.manifest().clone());
+                    }
+                    if let Some(stream) = &self.stream {
+                        stream.send_init_segment(init_segment.clone());
+                    }
+                    self.on_audio_config_change
+                        .as_ref()
+                        .map_or(Ok(()), |callback| {
+                            let change =
+                                serde_wasm_bindgen::to_value(&change).map_err(|e| e.to_string())?;
+                            callback
+                                .call2(
+                                    &JsValue::NULL,
+                                    &change,
+                                    &js_sys::Uint8Array::from(&init_segment[..]),
+                                )
+                                .map(drop)
+                                .map_err(|e| {
+                                    format!("onAudioConfigChange callback failed: {:?}", e)
+                                })
+                        })
+                }
+                RecorderEvent::ChunkHashed(_) => {
+                    if let Some(sink) = &self.sink {
+                        sink.queue_manifest(self.state.manifest().clone());
+                    }
+                    Ok(())
+                }
+                RecorderEvent::InitSegmentChanged(init_segment) => {
+                    if let Some(sink) = &self.sink {
+                        let session_id = self.state.manifest().session_id.clone();
+                        sink.queue_init_segment(session_id, init_segment.clone());
+                        sink.queue_manifest(self.state.manifest().clone());
+                    }
+                    if let Some(stream) = &self.stream {
+                        stream.send_init_segment(init_segment);
+                    }
+                    Ok(())
+                }
+                RecorderEvent::Thumbnail { timestamp_us, data } => {
+                    self.on_thumbnail.as_ref().map_or(Ok(()), |callback| {
+                        callback
+                            .call2(
+                                &JsValue::NULL,
+                                &JsValue::from_f64(timestamp_us as f64),
+                                &js_sys::Uint8Array::from(&data[..]),
+                            )
+                            .map(drop)
+                            .map_err(|e| format!("onThumbnail callback failed: {:?}", e))
+                    })
+                }
+                RecorderEvent::AdtsFrame { timestamp_us, data } => {
+                    self.on_adts_frame.as_ref().map_or(Ok(()), |callback| {
+                        callback
+                            .call2(
+                                &JsValue::NULL,
+                                &JsValue::from_f64(timestamp_us as f64),
+                                &js_sys::Uint8Array::from(&data[..]),
+                            )
+                            .map(drop)
+                            .map_err(|e| format!("onAdtsFrame callback failed: {:?}", e))
+                    })
+                }
+                RecorderEvent::ChunkReady(chunk) => {
+                    chunks_ready = true;
+                    if let Some(sink) = &self.sink {
+                        sink.queue_chunk(chunk.metadata.chunk_id.clone(), chunk.data.clone());
+                        sink.queue_manifest(self.state.manifest().clone());
+                    }
+                    if let Some(stream) = &self.stream {
+                        stream.send_chunk(chunk.metadata.clone(), chunk.data.clone());
+                    }
+                    self.on_chunk_ready
+                        .as_ref()
+                        .map_or(Ok(()), |callback| call_on_chunk_ready(callback, &chunk))
+                }
+            };
+            keep_first_error(&mut callback_error, called);
+        }
+        // Persisted with the manifest so a reload can resume from it; a
+        // stopped recorder has nothing left to resume, and one still hashing
+        // chunks snapshots with the next ones
+        if let (true, Some(sink)) = (chunks_ready, &self.sink) {
+            if self.state.can_snapshot() {
+                let snapshot = self.state.snapshot()?;
+                sink.queue_snapshot(self.state.manifest().session_id.clone(), snapshot);
+            }
+        }
+        callback_error.map_or(Ok(()), Err)
+    }
+}
+
+/// Log a failed JS callback and keep the first failure to report
+fn keep_first_error(first: &mut Option<String>, result: Result<(), String>) {
+    if let Err(e) = result {
+        log_event!(LogLevel::Warn, "Recorder callback failed", error = e);
+        first.get_or_insert(e);
+    }
+}
+
+/// Invoke an `onChunkReady` callback as `(metadata, data)`
+fn call_on_chunk_ready(callback: &js_sys::Function, chunk: &RecordedChunk) -> Result<(), String> {
+    let metadata = serde_wasm_bindgen::to_value(&chunk.metadata).map_err(|e| e.to_string())?;
+    let data = js_sys::Uint8Array::from(&chunk.data[..]);
+    callback
+        .call2(&JsValue::NULL, &metadata, &data)
+        .map_err(|e| format!("onChunkReady callback failed: {:?}", e))?;
+    Ok(())
+}
+
+/// Invoke an `onStateChange` callback as `(from, to)`
+fn call_on_state_change(
+    callback: &js_sys::Function,
+    from: RecorderStatus,
+    to: RecorderStatus,
+) -> Result<(), String> {
+    callback
+        .call2(
+            &JsValue::NULL,
+            &JsValue::from_str(from.as_str()),
+            &JsValue::from_str(to.as_str()),
+        )
+        .map_err(|e| format!("onStateChange callback failed: {:?}", e))?;
+    Ok(())
+}
+
+// ===== Simulcast WASM Bindings =====
+
+/// WASM wrapper for SimulcastState
+///
+/// Records several renditions of one session (e.g. 1080p plus a 360p proxy)
+/// from separate encoders. Chunks of every rendition go to the same
+/// `onChunkReady` callback and manifest, labeled with `chunkId.rendition`.
+#[wasm_bindgen]
+pub struct Simulcast {
+    state: SimulcastState,
+    on_chunk_ready: Option<js_sys::Function>,
+    on_state_change: Option<js_sys::Function>,
+}
+
+#[wasm_bindgen]
+impl Simulcast {
+    /// Create a coordinator for a session; add renditions before `start()`
+    #[wasm_bindgen(constructor)]
+    pub fn new(session_id: SessionId) -> Self {
+        Self {
+            state: SimulcastState::new(session_id),
+            on_chunk_ready: None,
+            on_state_change: None,
+        }
+    }
+
+    /// Add a rendition with its own muxer configuration
+    #[wasm_bindgen]
+    pub fn add_rendition(&mut self, rendition: RenditionConfig) -> Result<(), String> {
+        self.state.add_rendition(rendition)
+    }
+
+    /// Set the callback invoked as `(metadata: ChunkMetadata, data: Uint8Array)`
+    #[wasm_bindgen]
+    pub fn set_on_chunk_ready(&mut self, callback: js_sys::Function) {
+        self.on_chunk_ready = Some(callback);
+    }
+
+    /// Set the callback invoked as `(from: RecorderStatus, to: RecorderStatus)`
+    #[wasm_bindgen]
+    pub fn set_on_state_change(&mut self, callback: js_sys::Function) {
+        self.on_state_change = Some(callback);
+    }
+
+    /// Start every rendition
+    #[wasm_bindgen]
+    pub fn start(&mut self) -> Result<(), String> {
+        let result = self.state.start();
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Pause every rendition
+    #[wasm_bindgen]
+    pub fn pause(&mut self) -> Result<(), String> {
+        let result = self.state.pause();
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Resume every rendition
+    #[wasm_bindgen]
+    pub fn resume(&mut self) -> Result<(), String> {
+        let result = self.state.resume();
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Stop every rendition; remaining chunks are emitted before the state change
+    #[wasm_bindgen]
+    pub fn stop(&mut self) -> Result<(), String> {
+        let result = self.state.stop();
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Whether the next frame for a rendition's encoder must be a keyframe
+    #[wasm_bindgen]
+    pub fn keyframe_due(&mut self, rendition: &str, timestamp: f64) -> Result<bool, String> {
+        self.state.keyframe_due(rendition, timestamp as u64)
+    }
+
+    /// Add a video frame to a rendition (AVCC, timestamp in microseconds)
+    ///
+    /// Returns false if the frame was dropped.
+    #[wasm_bindgen]
+    pub fn push_video(
+        &mut self,
+        rendition: &str,
+        data: &[u8],
+        timestamp: f64,
+        is_keyframe: bool,
+    ) -> Result<bool, String> {
+        let result = self
+            .state
+            .push_video(rendition, data, timestamp as u64, is_keyframe);
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Add an audio frame to a rendition (raw AAC, timestamp and duration in microseconds)
+    ///
+    /// Returns false if the frame was dropped.
+    #[wasm_bindgen]
+    pub fn push_audio(
+        &mut self,
+        rendition: &str,
+        data: &[u8],
+        timestamp: f64,
+        duration: u32,
+    ) -> Result<bool, String> {
+        let result = self
+            .state
+            .push_audio(rendition, data, timestamp as u64, duration);
+        self.dispatch_events()?;
+        result
+    }
+
+    /// Get the init segment of a rendition, available after `start()`
+    #[wasm_bindgen]
+    pub fn get_init_segment(&self, rendition: &str) -> Result<Vec<u8>, String> {
+        self.state.init_segment(rendition).map(<[u8]>::to_vec)
+    }
+
+    /// Get the names of all renditions
+    #[wasm_bindgen]
+    pub fn get_rendition_names(&self) -> Vec<String> {
+        self.state.rendition_names()
+    }
+
+    /// Get the current lifecycle state
+    #[wasm_bindgen]
+    pub fn get_status(&self) -> RecorderStatus {
+        self.state.status()
+    }
+
+    /// Get the session manifest with the chunks of every rendition
+    #[wasm_bindgen]
+    pub fn get_manifest(&self) -> ChunkManifest {
+        self.state.manifest().clone()
+    }
+
+    fn dispatch_events(&mut self) -> Result<(), String> {
+        let mut callback_error = None;
+        for event in self.state.take_events() {
+            let called = match event {
+                RecorderEvent::StateChanged { from, to } => self
+                    .on_state_change
+                    .as_ref()
+                    .map_or(Ok(()), |callback| call_on_state_change(callback, from, to)),
+                RecorderEvent::ChunkReady(chunk) => self
+                    .on_chunk_ready
+                    .as_ref()
+                    .map_or(Ok(()), |callback| call_on_chunk_ready(callback, &chunk)),
+                _ => Ok(()),
+            };
+            keep_first_error(&mut callback_error, called);
+        }
+        callback_error.map_or(Ok(()), Err)
+    }
+}
+
+// ===== Chunk Sink WASM Bindings =====
+
+/// WASM wrapper for ChunkStorage
+///
+/// Stores init segments, chunks and manifests per session in OPFS or
+/// IndexedDB, so recordings survive page reloads. The backend is chosen at
+/// runtime; every method behaves the same regardless of backend.
+/// Writes queued by a `Recorder` run strictly in order; `flush()` waits for
+/// them and reports the first failure.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct ChunkSink {
+    storage: ChunkStorage,
+    tail: Rc<RefCell<js_sys::Promise>>,
+    error: Rc<RefCell<Option<String>>>,
+    /// Manifest journal writers of the sessions recorded through the sink
+    journals: Rc<RefCell<HashMap<SessionId, ManifestJournal>>>,
+}
+
+#[wasm_bindgen]
+impl ChunkSink {
+    /// Open a sink on the given storage backend
+    #[wasm_bindgen]
+    pub async fn open(backend: StorageBackend) -> Result<ChunkSink, String> {
+        Ok(Self::with_storage(ChunkStorage::open(backend).await?))
+    }
+
+    /// Open a sink on OPFS, falling back to IndexedDB if OPFS is unavailable
+    #[wasm_bindgen]
+    pub async fn open_preferred() -> Result<ChunkSink, String> {
+        Ok(Self::with_storage(ChunkStorage::open_preferred().await?))
+    }
+
+    /// Get the storage backend in use
+    #[wasm_bindgen]
+    pub fn backend(&self) -> StorageBackend {
+        self.storage.backend()
+    }
+
+    /// Wait for all queued writes, failing if any of them failed
+    #[wasm_bindgen]
+    pub async fn flush(&self) -> Result<(), String> {
+        let tail = self.tail.borrow().clone();
+        let _ = JsFuture::from(tail).await;
+        match self.error.borrow_mut().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the init segment of a session
+    #[wasm_bindgen]
+    pub async fn put_init_segment(
+        &self,
+        session_id: SessionId,
+        data: Vec<u8>,
+    ) -> Result<(), String> {
+        self.storage.put_init_segment(&session_id, &data).await
+    }
+
+    /// Read the init segment of a session
+    #[wasm_bindgen]
+    pub async fn get_init_segment(&self, session_id: SessionId) -> Result<Vec<u8>, String> {
+        self.storage.get_init_segment(&session_id).await
+    }
+
+    /// Write a chunk's data
+    #[wasm_bindgen]
+    pub async fn put_chunk(&self, chunk_id: ChunkId, data: Vec<u8>) -> Result<(), String> {
+        self.storage.put_chunk(&chunk_id, &data).await
+    }
+
+    /// Read a chunk's data
+    #[wasm_bindgen]
+    pub async fn get_chunk(&self, chunk_id: ChunkId) -> Result<Vec<u8>, String> {
+        self.storage.get_chunk(&chunk_id).await
+    }
+
+    /// Persist a session's manifest
+    ///
+    /// Runs in order with the writes a `Recorder` queued, so a journal
+    /// update queued before it cannot land after it.
+    #[wasm_bindgen]
+    pub async fn put_manifest(&self, manifest: ChunkManifest) -> Result<(), String> {
+        let storage = self.storage.clone();
+        let journals = self.journals.clone();
+        self.write_in_order(async move {
+            // The next queued update starts a fresh journal after this one
+            journals.borrow_mut().remove(&manifest.session_id);
+            storage.put_manifest(&manifest).await
+        })
+        .await
+    }
+
+    /// Read a session's manifest, or undefined if none was persisted
+    #[wasm_bindgen(unchecked_return_type = "ChunkManifest | undefined")]
+    pub async fn get_manifest(&self, session_id: SessionId) -> Result<JsValue, String> {
+        match self.storage.get_manifest(&session_id).await? {
+            Some(manifest) => serde_wasm_bindgen::to_value(&manifest).map_err(|e| e.to_string()),
+            None => Ok(JsValue::UNDEFINED),
+        }
+    }
+
+    /// List the chunks stored for a session
+    ///
+    /// Includes chunks written after the last persisted manifest.
+    #[wasm_bindgen(unchecked_return_type = "ChunkId[]")]
+    pub async fn list(&self, session_id: SessionId) -> Result<JsValue, String> {
+        let chunks = self.storage.list(&session_id).await?;
+        serde_wasm_bindgen::to_value(&chunks).map_err(|e| e.to_string())
+    }
+
+    /// List the IDs of all stored sessions
+    #[wasm_bindgen]
+    pub async fn list_sessions(&self) -> Result<Vec<String>, String> {
+        let sessions = self.storage.list_sessions().await?;
+        Ok(sessions
+            .into_iter()
+            .map(|s| s.as_str().to_string())
+            .collect())
+    }
+
+    /// Delete a session and all of its data
+    #[wasm_bindgen]
+    pub async fn delete_session(&self, session_id: SessionId) -> Result<(), String> {
+        let storage = self.storage.clone();
+        let journals = self.journals.clone();
+        self.write_in_order(async move {
+            journals.borrow_mut().remove(&session_id);
+            storage.delete_session(&session_id).await
+        })
+        .await
+    }
+
+    /// Persist a session's waveform peaks (from a `WaveformBuilder`)
+    #[wasm_bindgen]
+    pub async fn put_waveform(
+        &self,
+        session_id: SessionId,
+        waveform: Waveform,
+    ) -> Result<(), String> {
+        self.storage.put_waveform(&session_id, &waveform).await
+    }
+
+    /// Read a session's waveform peaks, or undefined if none were persisted
+    #[wasm_bindgen(unchecked_return_type = "Waveform | undefined")]
+    pub async fn get_waveform(&self, session_id: SessionId) -> Result<JsValue, String> {
+        match self.storage.get_waveform(&session_id).await? {
+            Some(waveform) => serde_wasm_bindgen::to_value(&waveform).map_err(|e| e.to_string()),
+            None => Ok(JsValue::UNDEFINED),
+        }
+    }
+
+    /// Mark a finished session as confirmed by the server
+    ///
+    /// Synced sessions are the only ones `collect_garbage` may delete.
+    #[wasm_bindgen]
+    pub async fn mark_synced(&self, session_id: SessionId) -> Result<(), String> {
+        storage::mark_synced(&self.storage, &session_id).await
+    }
+
+    /// Delete synced sessions according to `policy`
+    ///
+    /// With `dry_run` set, only reports what would be reclaimed. Sessions
+    /// that are not synced are never deleted.
+    #[wasm_bindgen(unchecked_return_type = "GcReport")]
+    pub async fn collect_garbage(
+        &self,
+        policy: GcPolicy,
+        dry_run: bool,
+    ) -> Result<JsValue, String> {
+        let report =
+            storage::collect_garbage(&self.storage, &policy, clock::now_ms(), dry_run).await?;
+        serde_wasm_bindgen::to_value(&report).map_err(|e| e.to_string())
+    }
+
+    /// Pack a stored session into a single ZIP archive for download
+    ///
+    /// The archive holds the playable recording, the manifest, markers,
+    /// caption files and `events`, the session's log records if the host
+    /// kept them (see `set_log_callback`).
+    #[wasm_bindgen]
+    pub async fn export_session(
+        &self,
+        session_id: SessionId,
+        #[wasm_bindgen(unchecked_param_type = "LogRecord[] | undefined")] events: JsValue,
+    ) -> Result<Vec<u8>, String> {
+        let events: Vec<LogRecord> = if events.is_undefined() || events.is_null() {
+            Vec::new()
+        } else {
+            serde_wasm_bindgen::from_value(events).map_err(|e| e.to_string())?
+        };
+        storage::export_session(&self.storage, &session_id, &events).await
+    }
+
+    /// Recreate a session from an archive made by `export_session`
+    ///
+    /// Fails if the session already exists or the archive is corrupt.
+    #[wasm_bindgen(unchecked_return_type = "ImportedSession")]
+    pub async fn import_session(&self, archive: Vec<u8>) -> Result<JsValue, String> {
+        let imported = storage::import_session(&self.storage, &archive).await?;
+        serde_wasm_bindgen::to_value(&imported).map_err(|e| e.to_string())
+    }
+
+    /// Import an external MP4 file as a new finished session
+    ///
+    /// The file's H.264 and AAC tracks are re-fragmented with `config`
+    /// (its codec fields are taken from the file) and stored like a
+    /// recording, ready for upload. Returns the session's manifest.
+    #[wasm_bindgen(unchecked_return_type = "ChunkManifest")]
+    pub async fn import_mp4(
+        &self,
+        data: Vec<u8>,
+        session_id: SessionId,
+        config: MuxideConfig,
+    ) -> Result<JsValue, String> {
+        let recording = demux::import_mp4(&data, session_id, config)?;
+        let chunks: Vec<(&ChunkId, &[u8])> = recording
+            .chunks
+            .iter()
+            .map(|chunk| (&chunk.metadata.chunk_id, chunk.data.as_slice()))
+            .collect();
+        self.storage
+            .put_new_session(&recording.manifest, &recording.init_segment, &chunks)
+            .await?;
+        serde_wasm_bindgen::to_value(&recording.manifest).map_err(|e| e.to_string())
+    }
+
+    /// Recover a session that crashed while writing a write-ahead log
+    ///
+    /// Replays the log into a fresh muxer, writes the chunks missing from the
+    /// persisted manifest plus the updated manifest, then deletes the log.
+    /// Fails if `config` does not produce the stored init segment's tracks.
+    /// Returns the stopped recorder; await `flush()` for the writes to finish.
+    #[wasm_bindgen]
+    pub async fn recover(
+        &self,
+        session_id: SessionId,
+        config: MuxideConfig,
+    ) -> Result<Recorder, String> {
+        let persisted = self.storage.get_manifest(&session_id).await?;
+        // Chunks already written were muxed for the stored init segment
+        if persisted.is_some() {
+            if let Ok(init) = self.storage.get_init_segment(&session_id).await {
+                compat::check_config_against_init(&config, &init)?.ensure()?;
+            }
+        }
+        let wal = self.storage.get_wal(&session_id).await?;
+        let state = RecorderState::recover(session_id.clone(), config, persisted, &wal)?;
+
+        let mut recorder = Recorder::new_with_state(state);
+        recorder.sink = Some(self.clone());
+        recorder.dispatch_events()?;
+        self.queue_manifest(recorder.state.manifest().clone());
+        self.queue_delete_snapshot(session_id.clone());
+        self.queue_delete_wal(session_id);
+        Ok(recorder)
+    }
+}
+
+impl ChunkSink {
+    fn with_storage(storage: ChunkStorage) -> Self {
+        Self {
+            storage,
+            tail: Rc::new(RefCell::new(js_sys::Promise::resolve(&JsValue::UNDEFINED))),
+            error: Rc::new(RefCell::new(None)),
+            journals: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    fn queue_init_segment(&self, session_id: SessionId, data: Vec<u8>) {
+        let storage = self.storage.clone();
+        self.enqueue(async move { storage.put_init_segment(&session_id, &data).await });
+    }
+
+    fn queue_chunk(&self, chunk_id: ChunkId, data: Vec<u8>) {
+        let storage = self.storage.clone();
+        self.enqueue(async move { storage.put_chunk(&chunk_id, &data).await });
+    }
+
+    fn queue_wal_batch(&self, session_id: SessionId, batch: WalBatch) {
+        let storage = self.storage.clone();
+        self.enqueue(async move { storage.put_wal_batch(&session_id, &batch).await });
+    }
+
+    fn queue_delete_wal(&self, session_id: SessionId) {
+        let storage = self.storage.clone();
+        self.enqueue(async move { storage.delete_wal(&session_id).await });
+    }
+
+    fn queue_snapshot(&self, session_id: SessionId, snapshot: Vec<u8>) {
+        let storage = self.storage.clone();
+        self.enqueue(async move { storage.put_snapshot(&session_id, &snapshot).await });
+    }
+
+    fn queue_delete_snapshot(&self, session_id: SessionId) {
+        let storage = self.storage.clone();
+        self.enqueue(async move { storage.delete_snapshot(&session_id).await });
+    }
+
+    /// Read a chunk once every previously queued write has finished
+    async fn read_chunk(&self, chunk_id: &ChunkId) -> Result<Vec<u8>, String> {
+        let tail = self.tail.borrow().clone();
+        let _ = JsFuture::from(tail).await;
+        self.storage.get_chunk(chunk_id).await
+    }
+
+    /// Queue an update of the session's manifest journal
+    fn queue_manifest(&self, manifest: ChunkManifest) {
+        let storage = self.storage.clone();
+        let journals = self.journals.clone();
+        self.enqueue(async move {
+            let session_id = &manifest.session_id;
+            let mut journal = journals.borrow_mut().remove(session_id).unwrap_or_default();
+            let result = storage.append_manifest(&mut journal, &manifest).await;
+            journals.borrow_mut().insert(session_id.clone(), journal);
+            result
+        });
+    }
+
+    /// Run a write after every previously queued write has finished and
+    /// wait for it, returning its failure rather than keeping it for
+    /// `flush()`
+    async fn write_in_order<F>(&self, write: F) -> Result<(), String>
+    where
+        F: Future<Output = Result<(), String>> + 'static,
+    {
+        let outcome = Rc::new(RefCell::new(None));
+        let slot = outcome.clone();
+        self.enqueue(async move {
+            *slot.borrow_mut() = Some(write.await);
+            Ok(())
+        });
+        let tail = self.tail.borrow().clone();
+        let _ = JsFuture::from(tail).await;
+        let result = outcome.borrow_mut().take();
+        result.unwrap_or(Ok(()))
+    }
+
+    /// Run a write after every previously queued write has finished
+    fn enqueue<F>(&self, write: F)
+    where
+        F: Future<Output = Result<(), String>> + 'static,
+    {
+        let previous = self.tail.borrow().clone();
+        let error = self.error.clone();
+        let next = future_to_promise(async move {
+            let _ = JsFuture::from(previous).await;
+            if let Err(e) = write.await {
+                log_event!(LogLevel::Error, "Chunk sink write failed", error = e);
+                error.borrow_mut().get_or_insert(e);
+            }
+            Ok(JsValue::UNDEFINED)
+        });
+        *self.tail.borrow_mut() = next;
+    }
+}
+
+// ===== Segment Stream WASM Bindings =====
+
+/// Streams a recording's segments to a server over a WebSocket
+///
+/// Attach to a `Recorder` with `set_stream()` to stream live while the same
+/// chunks are stored locally. Segments stay queued until the server
+/// acknowledges them, with at most `max_in_flight` unacknowledged on the
+/// wire. After the connection drops, call `connect()` again; everything not
+/// acknowledged is resent. Acknowledged chunks the server asks for again are
+/// read from the sink set with `set_sink()`. See `streaming.rs` for the
+/// frame format.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct SegmentStream {
+    inner: Rc<RefCell<SegmentStreamInner>>,
+}
+
+struct SegmentStreamInner {
+    url: String,
+    sender: SegmentSender,
+    /// Local copy of the session, for chunks the server asks for again
+    sink: Option<ChunkSink>,
+    socket: Option<web_sys::WebSocket>,
+    /// Keeps the socket's event handlers alive
+    handlers: Vec<Closure<dyn FnMut(JsValue)>>,
+}
+
+#[wasm_bindgen]
+impl SegmentStream {
+    /// Create a stream of `session_id` to the WebSocket endpoint at `url`
+    #[wasm_bindgen(constructor)]
+    pub fn new(url: String, session_id: SessionId, max_in_flight: u32) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(SegmentStreamInner {
+                url,
+                sender: SegmentSender::new(session_id, max_in_flight),
+                sink: None,
+                socket: None,
+                handlers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Open the connection, replacing any previous one
+    #[wasm_bindgen]
+    pub fn connect(&self) -> Result<(), String> {
+        let mut inner = self.inner.borrow_mut();
+        inner.close_socket();
+        let socket = web_sys::WebSocket::new(&inner.url).map_err(|e| format!("{:?}", e))?;
+        socket.set_binary_type(web_sys::BinaryType::Arraybuffer);
+
+        let weak = Rc::downgrade(&self.inner);
+        let on_open = Closure::<dyn FnMut(JsValue)>::new({
+            let weak = weak.clone();
+            move |_| SegmentStreamInner::update(&weak, SegmentSender::connect)
+        });
+        let on_message = Closure::<dyn FnMut(JsValue)>::new({
+            let weak = weak.clone();
+            move |event: JsValue| {
+                let Some(event) = event.dyn_ref::<web_sys::MessageEvent>() else {
+                    return;
+                };
+                let message = js_sys::Uint8Array::new(&event.data()).to_vec();
+                SegmentStreamInner::update(&weak, |sender| sender.receive(&message));
+                SegmentStreamInner::serve_repairs(&weak);
+            }
+        });
+        let on_close = Closure::<dyn FnMut(JsValue)>::new(move |_| {
+            SegmentStreamInner::update(&weak, |sender| {
+                sender.disconnect();
+                Ok(Vec::new())
+            })
+        });
+        socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        inner.socket = Some(socket);
+        inner.handlers = vec![on_open, on_message, on_close];
+        Ok(())
+    }
+
+    /// Read chunks the server asks for again from `sink`
+    #[wasm_bindgen]
+    pub fn set_sink(&self, sink: &ChunkSink) {
+        self.inner.borrow_mut().sink = Some(sink.clone());
+    }
+
+    /// Close the connection; queued segments are kept for a later `connect()`
+    #[wasm_bindgen]
+    pub fn close(&self) {
+        self.inner.borrow_mut().close_socket();
+    }
+
+    /// Number of segments the server has not acknowledged yet
+    #[wasm_bindgen]
+    pub fn pending(&self) -> u32 {
+        self.inner.borrow().sender.pending() as u32
+    }
+
+    /// Whether the server has stored the whole stream
+    #[wasm_bindgen]
+    pub fn is_finished(&self) -> bool {
+        self.inner.borrow().sender.is_finished()
+    }
+}
+
+impl SegmentStream {
+    fn send_init_segment(&self, data: Vec<u8>) {
+        let mut inner = self.inner.borrow_mut();
+        let frames = inner.sender.push_init_segment(data);
+        inner.send(frames);
+    }
+
+    fn send_chunk(&self, metadata: ChunkMetadata, data: Vec<u8>) {
+        let mut inner = self.inner.borrow_mut();
+        let frames = inner.sender.push_segment(metadata, data);
+        inner.send(frames);
+    }
+
+    fn end(&self) {
+        let mut inner = self.inner.borrow_mut();
+        let frames = inner.sender.end();
+        inner.send(frames);
+    }
+}
+
+impl SegmentStreamInner {
+    /// Run a sender update from a socket event and send the resulting frames
+    fn update(
+        weak: &Weak<RefCell<Self>>,
+        update: impl FnOnce(&mut SegmentSender) -> Result<Vec<Vec<u8>>, String>,
+    ) {
+        if let Some(inner) = weak.upgrade() {
+            let mut inner = inner.borrow_mut();
+            let frames = update(&mut inner.sender);
+            inner.send(frames);
+        }
+    }
+
+    /// Read the chunks the server asked for again and send them as repairs
+    fn serve_repairs(weak: &Weak<RefCell<Self>>) {
+        let Some(inner) = weak.upgrade() else {
+            return;
+        };
+        let (chunk_ids, sink) = {
+            let mut inner = inner.borrow_mut();
+            (inner.sender.take_repair_requests(), inner.sink.clone())
+        };
+        if chunk_ids.is_empty() {
+            return;
+        }
+        let Some(sink) = sink else {
+            log_event!(
+                LogLevel::Error,
+                "Segment stream has no sink to repair chunks from",
+                chunks = chunk_ids.len(),
+            );
+            return;
+        };
+        let weak = weak.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            for chunk_id in chunk_ids {
+                match sink.read_chunk(&chunk_id).await {
+                    Ok(data) => Self::update(&weak, |sender| sender.push_repair(chunk_id, data)),
+                    Err(e) => log_event!(
+                        LogLevel::Error,
+                        "Cannot repair chunk from local storage",
+                        chunk = chunk_id,
+                        error = e,
+                    ),
+                }
+            }
+        });
+    }
+
+    fn send(&self, frames: Result<Vec<Vec<u8>>, String>) {
+        let frames = match frames {
+            Ok(frames) => frames,
+            Err(e) => {
+                log_event!(LogLevel::Error, "Segment stream error", error = e);
+                return;
+            }
+        };
+        let Some(socket) = &self.socket else {
+            return;
+        };
+        for frame in frames {
+            if let Err(e) = socket.send_with_u8_array(&frame) {
+                log_event!(
+                    LogLevel::Warn,
+                    "Segment stream send failed",
+                    error = format!("{:?}", e)
+                );
+                return;
+            }
+        }
+    }
+
+    fn close_socket(&mut self) {
+        if let Some(socket) = self.socket.take() {
+            socket.set_onopen(None);
+            socket.set_onmessage(None);
+            socket.set_onclose(None);
+            let _ = socket.close();
+        }
+        self.handlers.clear();
+        self.sender.disconnect();
+    }
+}
+
+// ===== Sample Reader WASM Bindings =====
+
+/// WASM wrapper for SampleReader
+///
+/// Walks a finished recording (or a progressive MP4) sample by sample, e.g.
+/// to pick frames for thumbnails or feed audio to a captioning model.
+#[wasm_bindgen]
+pub struct SampleIterator {
+    reader: SampleReader<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl SampleIterator {
+    /// Read the tracks of `recording`
+    #[wasm_bindgen(constructor)]
+    pub fn new(recording: Vec<u8>) -> Result<SampleIterator, String> {
+        Ok(Self {
+            reader: SampleReader::new(recording)?,
+        })
+    }
+
+    /// The next sample, or undefined after the last one
+    #[wasm_bindgen(unchecked_return_type = "{ info: SampleInfo, data: Uint8Array } | undefined")]
+    pub fn next_sample(&mut self) -> Result<JsValue, String> {
+        let Some(sample) = self.reader.next_sample()? else {
+            return Ok(JsValue::UNDEFINED);
+        };
+        let entry = js_sys::Object::new();
+        let info = serde_wasm_bindgen::to_value(&sample.info).map_err(|e| e.to_string())?;
+        let data = js_sys::Uint8Array::from(&sample.data[..]);
+        js_sys::Reflect::set(&entry, &"info".into(), &info).map_err(|e| format!("{:?}", e))?;
+        js_sys::Reflect::set(&entry, &"data".into(), &data).map_err(|e| format!("{:?}", e))?;
+        Ok(entry.into())
+    }
+}
+
+// ===== Thread Pool WASM Bindings =====
+
+/// Start the thread pool finishing segments under the `background` hash
+/// strategy
+///
+/// Needs a cross-origin isolated page and a build with atomics and shared
+/// memory. `spawn_worker` is called as `(module, memory, thread)` once per
+/// thread; it must start a Web Worker that instantiates `module` on the
+/// shared `memory` and calls `run_pool_thread(thread)`. Chunks are waited
+/// for at `stop()`, so the recorder must run in a worker too.
+#[cfg(feature = "wasm-threads")]
+#[wasm_bindgen]
+pub fn init_thread_pool(num_threads: u32, spawn_worker: js_sys::Function) -> Result<(), String> {
+    threads::start_pool(num_threads as usize, |thread| {
+        let thread = Box::into_raw(Box::new(thread)) as usize;
+        spawn_worker
+            .call3(
+                &JsValue::NULL,
+                &wasm_bindgen::module(),
+                &wasm_bindgen::memory(),
+                &JsValue::from(thread),
+            )
+            .map(drop)
+            .map_err(|e| {
+                // SAFETY: no worker got the thread
+                drop(unsafe { Box::from_raw(thread as *mut rayon_core::ThreadBuilder) });
+                std::io::Error::other(format!("{:?}", e))
+            })
+    })
+}
+
+/// Run a thread handed to `spawn_worker` by `init_thread_pool`, from its
+/// worker; returns when the pool shuts down
+#[cfg(feature = "wasm-threads")]
+#[wasm_bindgen]
+pub fn run_pool_thread(thread: usize) {
+    // SAFETY: `init_thread_pool` hands each thread to a single worker
+    let thread = unsafe { Box::from_raw(thread as *mut rayon_core::ThreadBuilder) };
+    thread.run();
+}
+
+/// Whether the thread pool is running, so that the `background` hash
+/// strategy finishes segments off the recorder's thread
+#[wasm_bindgen]
+pub fn thread_pool_ready() -> bool {
+    pool_ready()
+}
+
+// ===== Utility WASM Functions =====
+
+/// Convert Annex B format to AVCC format
+///
+/// Use this to convert WebCodecs output (Annex B) to the format
+/// expected by the muxer (AVCC).
+#[wasm_bindgen]
+pub fn convert_annex_b_to_avcc(annex_b: &[u8]) -> Vec<u8> {
+    annex_b_to_avcc(annex_b)
+}
+
+/// Extract SPS and PPS from avcC data
+///
+/// Returns a tuple of (sps, pps) as separate arrays.
+/// Use this to get the codec parameters from WebCodecs VideoEncoder description.
+#[wasm_bindgen]
+pub fn parse_avcc(avcc: &[u8]) -> Result<JsValue, String> {
+    let (sps, pps) = extract_sps_pps_from_avcc(avcc)?;
+
+    // Return as a JS object with sps and pps properties
+    let result = js_sys::Object::new();
+    let sps_array = js_sys::Uint8Array::from(&sps[..]);
+    let pps_array = js_sys::Uint8Array::from(&pps[..]);
+
+    js_sys::Reflect::set(&result, &"sps".into(), &sps_array).map_err(|e| format!("{:?}", e))?;
+    js_sys::Reflect::set(&result, &"pps".into(), &pps_array).map_err(|e| format!("{:?}", e))?;
+
+    Ok(result.into())
+}
+
+// ===== Common Type WASM Bindings =====
+//
+// The shapes of these values are defined by the Rust types in `chunk`,
+// `session`, `metadata` and `manifest`, and exported to TypeScript via tsify,
+// so the JS side never has to mirror them by hand. Invalid input throws before
+// the function body runs; typed failures throw a `CoreError` object.
+
+/// Parse a chunk ID string (`{session}/{track}/{sequence}` or a bare sequence number)
+#[wasm_bindgen]
+pub fn parse_chunk_id(id: &str) -> Result<ChunkId, CoreError> {
+    id.parse::<ChunkId>().map_err(CoreError::invalid_input)
+}
+
+/// Format a chunk ID as its canonical string
+#[wasm_bindgen]
+pub fn format_chunk_id(id: ChunkId) -> String {
+    id.to_string()
+}
+
+/// Check whether a session may move from state `from` to state `to`
+#[wasm_bindgen]
+pub fn can_transition_session_state(from: SessionState, to: SessionState) -> bool {
+    from.can_transition_to(to)
+}
+
+/// Validate and normalize a ChunkMetadata object, keeping its `recordingId`
+/// (filled in from a composite chunk ID); fails if it names no session
+#[wasm_bindgen]
+pub fn normalize_chunk_metadata(metadata: ChunkRecord) -> Result<ChunkRecord, CoreError> {
+    metadata.normalize().map_err(CoreError::invalid_input)
+}
+
+/// Validate and normalize a RecordingMetadata object
+#[wasm_bindgen]
+pub fn normalize_recording_metadata(metadata: RecordingMetadata) -> RecordingMetadata {
+    metadata
+}
+
+/// Whether a finished recording may be purged at `now_ms` (Unix ms), as the
+/// server's GC decides it; `default_expire_after_ms` is the server's
+/// retention for recordings without their own
+#[wasm_bindgen]
+pub fn recording_retention_status(
+    metadata: Option<RecordingMetadata>,
+    default_expire_after_ms: Option<f64>,
+    finished_at_ms: f64,
+    now_ms: f64,
+) -> RetentionStatus {
+    RecordingMetadata::retention_status(
+        metadata.as_ref(),
+        default_expire_after_ms.map(|ms| ms as u64),
+        finished_at_ms as u64,
+        now_ms as u64,
+    )
+}
+
+/// Create an empty ChunkManifest for a session
+#[wasm_bindgen]
+pub fn create_chunk_manifest(session_id: SessionId) -> ChunkManifest {
+    ChunkManifest::new(session_id)
+}
+
+/// Validate and normalize a ChunkManifest object
+#[wasm_bindgen]
+pub fn normalize_chunk_manifest(manifest: ChunkManifest) -> ChunkManifest {
+    manifest
+}
+
+/// Append a chunk to a manifest, returning the updated manifest
+///
+/// Fails if the chunk belongs to another session or is already listed.
+#[wasm_bindgen]
+pub fn manifest_add_chunk(
+    mut manifest: ChunkManifest,
+    chunk: ChunkMetadata,
+) -> Result<ChunkManifest, CoreError> {
+    manifest
+        .add_chunk(chunk)
+        .map_err(CoreError::invalid_state)?;
+    Ok(manifest)
+}
+
+/// Insert a marker into a manifest, returning the updated manifest
+#[wasm_bindgen]
+pub fn manifest_add_marker(mut manifest: ChunkManifest, marker: Marker) -> ChunkManifest {
+    manifest.add_marker(marker);
+    manifest
+}
+
+/// Cut a finished recording into content-defined chunks for deduplicating
+/// archival storage, returning the manifest with its muxed chunks replaced;
+/// chunk `n` is the next `size` bytes of the recording after the init
+/// segment and chunks `0..n`
+#[wasm_bindgen]
+pub fn manifest_split_content_defined(
+    manifest: ChunkManifest,
+    recording: &[u8],
+    config: Option<CdcConfig>,
+) -> Result<ChunkManifest, String> {
+    split_content_defined(&manifest, recording, &config.unwrap_or_default())
+        .map(|split| split.manifest)
+}
+
+/// Decrypt a chunk stored with segment encryption, given its key and
+/// sequence number
+#[wasm_bindgen]
+pub fn decrypt_chunk(key: &[u8], sequence: f64, data: &[u8]) -> Result<Vec<u8>, String> {
+    decrypt_segment(key, sequence as u64, data)
+}
+
+/// Get the markers of a manifest as timed ID3 tags (`{ pts, data }[]`) for
+/// an HLS metadata stream whose session start is at `pts_offset` (90 kHz)
+#[wasm_bindgen]
+pub fn manifest_marker_id3(manifest: ChunkManifest, pts_offset: f64) -> Result<JsValue, String> {
+    let tags = marker_id3_track(&manifest.markers, pts_offset as u64);
+    serde_wasm_bindgen::to_value(&tags).map_err(|e| e.to_string())
+}
+
+/// Render the labeled markers of a manifest as a sidecar subtitle file
+/// (`"webvtt"` or `"srt"`), timed to the assembled MP4; cues end by
+/// `end_us` (session time) if given
+#[wasm_bindgen]
+pub fn manifest_subtitles(
+    manifest: ChunkManifest,
+    format: SubtitleFormat,
+    end_us: Option<f64>,
+) -> String {
+    export_subtitles(&manifest, end_us.map(|end| end as u64), format)
+}
+
+/// Build the search index of a manifest, e.g. for recordings made before
+/// the recorder produced one; the index runs to `end_us` (session time) if
+/// given, else to the metadata's duration
+#[wasm_bindgen]
+pub fn manifest_search_index(manifest: ChunkManifest, end_us: Option<f64>) -> SessionIndex {
+    SessionIndex::from_manifest(&manifest, end_us.map(|end| end as u64))
+}
+
+/// Whether every word of `query` is part of one of the index's terms
+#[wasm_bindgen]
+pub fn search_index_matches(index: SessionIndex, query: &str) -> bool {
+    index.matches(query)
+}
+
+/// Check a signed integrity report with the recording's key (hex) and
+/// return the report
+#[wasm_bindgen]
+pub fn verify_integrity_report(
+    signed: SignedIntegrityReport,
+    key: &str,
+) -> Result<IntegrityReport, String> {
+    signed.verify(&parse_integrity_key(key)?)
+}
+
+// ===== Metadata Compression WASM Bindings =====
+
+/// Gzip metadata (manifest JSON, event logs, traces) before storing or
+/// uploading it; send it with `Content-Encoding: gzip`
+#[wasm_bindgen]
+pub fn compress_metadata(data: &[u8]) -> Vec<u8> {
+    compress(data, DEFAULT_COMPRESSION_LEVEL)
+}
+
+/// Read metadata that may have been stored gzipped: compressed data (by
+/// its magic bytes) is inflated, anything else is returned as is
+#[wasm_bindgen]
+pub fn decompress_metadata(data: &[u8]) -> Result<Vec<u8>, String> {
+    decompress_if_compressed(data).map(|data| data.into_owned())
+}
+
+// ===== Recording Presets WASM Bindings =====
+
+/// Get the built-in recording presets (`RecordingPreset[]`), in display order
+#[wasm_bindgen(unchecked_return_type = "RecordingPreset[]")]
+pub fn get_recording_presets() -> Result<JsValue, String> {
+    serde_wasm_bindgen::to_value(&recording_presets()?).map_err(|e| e.to_string())
+}
+
+/// Get a built-in recording preset by ID
+///
+/// Its `muxer` config plus the encoder's SPS/PPS goes to `new Recorder()`,
+/// `chunking` to `new ChunkSizePolicy()` and `upload` to the uploader.
+#[wasm_bindgen]
+pub fn get_recording_preset(id: &str) -> Result<RecordingPreset, String> {
+    recording_preset(id)
 }
 
 #[cfg(test)]
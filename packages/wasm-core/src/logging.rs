@@ -0,0 +1,280 @@
+//! Leveled, structured logging.
+//!
+//! Modules log through `log_event!`, which records the module path, a level,
+//! a message and `key = value` fields. Records pass a per-module level filter
+//! and go to the registered sink (a JS callback in the browser). Without a
+//! sink, records are written to the browser console.
+//!
+//! Module filters match on `::` boundaries of the path below the crate root,
+//! so a level set for `storage` also applies to `storage::opfs` unless that
+//! module has its own level.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::clock::ClockHandle;
+
+/// Severity of a log record; `Off` disables logging for a module
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Tsify,
+)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+    Off,
+}
+
+impl LogLevel {
+    /// Get the lowercase name used in records
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Trace => "trace",
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+            LogLevel::Off => "off",
+        }
+    }
+}
+
+/// A single log record as passed to the sink
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct LogRecord {
+    /// Module path below the crate root, e.g. `storage::opfs`
+    pub module: String,
+    pub level: LogLevel,
+    pub message: String,
+    pub fields: BTreeMap<String, String>,
+    /// Unix timestamp in milliseconds
+    pub timestamp: u64,
+}
+
+type Sink = Rc<dyn Fn(&LogRecord)>;
+
+#[derive(Default)]
+struct Logger {
+    default_level: LogLevel,
+    module_levels: BTreeMap<String, LogLevel>,
+    sink: Option<Sink>,
+    clock: ClockHandle,
+}
+
+impl Logger {
+    fn level_for(&self, module: &str) -> LogLevel {
+        let mut path = module;
+        loop {
+            if let Some(level) = self.module_levels.get(path) {
+                return *level;
+            }
+            match path.rsplit_once("::") {
+                Some((parent, _)) => path = parent,
+                None => return self.default_level,
+            }
+        }
+    }
+}
+
+thread_local! {
+    static LOGGER: RefCell<Logger> = RefCell::new(Logger::default());
+}
+
+/// Strip the crate name from a `module_path!()`
+fn short_module(module_path: &str) -> &str {
+    match module_path.split_once("::") {
+        Some((_, rest)) => rest,
+        None => "lib",
+    }
+}
+
+/// Route records to `sink` instead of the console
+pub fn set_sink(sink: impl Fn(&LogRecord) + 'static) {
+    LOGGER.with(|logger| logger.borrow_mut().sink = Some(Rc::new(sink)));
+}
+
+/// Remove the sink; records go to the console again
+pub fn clear_sink() {
+    LOGGER.with(|logger| logger.borrow_mut().sink = None);
+}
+
+/// Stamp records with the time of `clock` instead of the system clock
+pub fn set_clock(clock: ClockHandle) {
+    LOGGER.with(|logger| logger.borrow_mut().clock = clock);
+}
+
+/// Set the level for modules without their own level
+pub fn set_level(level: LogLevel) {
+    LOGGER.with(|logger| logger.borrow_mut().default_level = level);
+}
+
+/// Set the level for a module and its submodules, e.g. `muxide_muxer`
+pub fn set_module_level(module: &str, level: LogLevel) {
+    LOGGER.with(|logger| {
+        logger
+            .borrow_mut()
+            .module_levels
+            .insert(module.to_string(), level)
+    });
+}
+
+/// Remove every module level and reset the default level
+pub fn reset_levels() {
+    LOGGER.with(|logger| {
+        let mut logger = logger.borrow_mut();
+        logger.default_level = LogLevel::default();
+        logger.module_levels.clear();
+    });
+}
+
+/// Whether a record at `level` from `module_path` would be emitted
+pub fn enabled(level: LogLevel, module_path: &str) -> bool {
+    level != LogLevel::Off
+        && LOGGER.with(|logger| level >= logger.borrow().level_for(short_module(module_path)))
+}
+
+/// Emit a record; use `log_event!` instead of calling this directly
+pub fn emit(level: LogLevel, module_path: &str, message: String, fields: Vec<(&str, String)>) {
+    // Release the logger before calling out, so the sink may log itself
+    let (sink, timestamp) = LOGGER.with(|logger| {
+        let logger = logger.borrow();
+        (logger.sink.clone(), logger.clock.now_ms())
+    });
+    let record = LogRecord {
+        module: short_module(module_path).to_string(),
+        level,
+        message,
+        fields: fields
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+        timestamp,
+    };
+    match sink {
+        Some(sink) => sink(&record),
+        None => write_console(&record),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_console(record: &LogRecord) {
+    let mut line = format!("[{}] {}", record.module, record.message);
+    for (key, value) in &record.fields {
+        line.push_str(&format!(" {}={}", key, value));
+    }
+    let line = wasm_bindgen::JsValue::from_str(&line);
+    match record.level {
+        LogLevel::Trace | LogLevel::Debug => web_sys::console::debug_1(&line),
+        LogLevel::Info | LogLevel::Off => web_sys::console::log_1(&line),
+        LogLevel::Warn => web_sys::console::warn_1(&line),
+        LogLevel::Error => web_sys::console::error_1(&line),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_console(_record: &LogRecord) {}
+
+/// Log a record with optional `key = value` fields
+///
+/// ```text
+/// log_event!(LogLevel::Warn, "Stream stalled", track = track, stalled_ms = ms);
+/// ```
+macro_rules! log_event {
+    ($level:expr, $message:expr $(, $key:ident = $value:expr)* $(,)?) => {
+        if $crate::logging::enabled($level, module_path!()) {
+            $crate::logging::emit(
+                $level,
+                module_path!(),
+                ($message).to_string(),
+                vec![$((stringify!($key), ($value).to_string())),*],
+            );
+        }
+    };
+}
+
+pub(crate) use log_event;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn capture() -> Rc<RefCell<Vec<LogRecord>>> {
+        let records = Rc::new(RefCell::new(Vec::new()));
+        let sink = records.clone();
+        set_sink(move |record| sink.borrow_mut().push(record.clone()));
+        records
+    }
+
+    #[test]
+    fn test_log_event_structured_record() {
+        reset_levels();
+        let records = capture();
+
+        log_event!(
+            LogLevel::Warn,
+            "Segment flushed",
+            samples = 60,
+            bytes = 1024
+        );
+        log_event!(LogLevel::Debug, "Below default level");
+
+        let records = records.borrow();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].module, "logging::tests");
+        assert_eq!(records[0].level, LogLevel::Warn);
+        assert_eq!(records[0].message, "Segment flushed");
+        assert_eq!(records[0].fields["samples"], "60");
+        assert_eq!(records[0].fields["bytes"], "1024");
+        clear_sink();
+    }
+
+    #[test]
+    fn test_records_use_injected_clock() {
+        reset_levels();
+        let records = capture();
+        let clock = ManualClock::new(1_000);
+        set_clock(ClockHandle::new(clock.clone()));
+
+        log_event!(LogLevel::Warn, "First");
+        clock.advance(250);
+        log_event!(LogLevel::Warn, "Second");
+
+        let timestamps: Vec<u64> = records.borrow().iter().map(|r| r.timestamp).collect();
+        assert_eq!(timestamps, vec![1_000, 1_250]);
+        set_clock(ClockHandle::default());
+        clear_sink();
+    }
+
+    #[test]
+    fn test_module_level_filtering() {
+        reset_levels();
+        set_level(LogLevel::Warn);
+        set_module_level("storage", LogLevel::Debug);
+        set_module_level("storage::opfs", LogLevel::Off);
+
+        assert!(enabled(LogLevel::Debug, "maycast_wasm_core::storage"));
+        assert!(enabled(
+            LogLevel::Debug,
+            "maycast_wasm_core::storage::indexed_db"
+        ));
+        assert!(!enabled(
+            LogLevel::Error,
+            "maycast_wasm_core::storage::opfs"
+        ));
+        assert!(!enabled(LogLevel::Info, "maycast_wasm_core::recorder"));
+        assert!(enabled(LogLevel::Error, "maycast_wasm_core::recorder"));
+        reset_levels();
+    }
+}
@@ -0,0 +1,378 @@
+//! Input level and loudness metering.
+//!
+//! `LoudnessMeterState` takes interleaved PCM as it comes out of an
+//! AudioWorklet and keeps two kinds of measurement: RMS and sample peak over
+//! the samples since the last `levels()` call, for driving input meters, and
+//! ITU-R BS.1770 loudness (K-weighted, in LUFS) over 400 ms momentary and
+//! 3 s short-term windows. Momentary blocks are kept for the whole recording
+//! so the gated integrated loudness can be stored in `RecordingMetadata`.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::metadata::LoudnessStats;
+
+/// Level reported for digital silence (dBFS)
+pub const SILENCE_DB: f64 = -120.0;
+
+/// Sub-blocks (100 ms) per momentary window
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+/// Sub-blocks (100 ms) per short-term window
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+/// Blocks below this loudness are ignored for integrated loudness
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Blocks this far below the ungated loudness are ignored as well
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// Meter readings for the samples pushed since the previous reading
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioLevels {
+    /// RMS level (dBFS) over all channels
+    pub rms_db: f64,
+    /// Sample peak (dBFS) over all channels
+    pub peak_db: f64,
+    /// Loudness of the last 400 ms (LUFS), once that much audio was pushed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub momentary_lufs: Option<f64>,
+    /// Loudness of the last 3 s (LUFS), once that much audio was pushed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub short_term_lufs: Option<f64>,
+}
+
+/// Second-order IIR filter section (direct form II transposed)
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The two K-weighting stages of BS.1770 for a sample rate
+///
+/// Coefficients are derived from the analog prototypes so rates other than
+/// 48 kHz get the same response.
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    // Stage 1: high shelf modelling the acoustic effect of the head
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    // Stage 2: RLB high-pass
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+fn to_db(amplitude: f64) -> f64 {
+    if amplitude > 0.0 {
+        (20.0 * amplitude.log10()).max(SILENCE_DB)
+    } else {
+        SILENCE_DB
+    }
+}
+
+/// Loudness (LUFS) of a channel-summed mean square
+fn to_lufs(mean_square: f64) -> Option<f64> {
+    (mean_square > 0.0).then(|| -0.691 + 10.0 * mean_square.log10())
+}
+
+/// Level and loudness meter for one audio input
+#[derive(Debug, Clone)]
+pub struct LoudnessMeterState {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    sub_block_frames: usize,
+    /// K-weighted energy of the current sub-block, summed over channels
+    sub_block_energy: f64,
+    sub_block_pos: usize,
+    /// Channel-summed mean square of the most recent 100 ms sub-blocks
+    sub_blocks: VecDeque<f64>,
+    /// Channel-summed mean square of every momentary block so far
+    blocks: Vec<f64>,
+    max_momentary: Option<f64>,
+    max_short_term: Option<f64>,
+    peak: f64,
+    window_sum_sq: f64,
+    window_samples: u64,
+    window_peak: f64,
+}
+
+impl LoudnessMeterState {
+    /// Create a meter for PCM with the given rate and channel count
+    pub fn new(sample_rate: u32, channels: u16) -> Result<Self, String> {
+        if sample_rate < 8000 {
+            return Err(format!("Unsupported sample rate: {}", sample_rate));
+        }
+        if channels == 0 {
+            return Err("Channel count must be at least 1".to_string());
+        }
+        Ok(Self {
+            channels: channels as usize,
+            filters: vec![k_weighting(sample_rate as f64); channels as usize],
+            sub_block_frames: (sample_rate / 10) as usize,
+            sub_block_energy: 0.0,
+            sub_block_pos: 0,
+            sub_blocks: VecDeque::with_capacity(SHORT_TERM_SUB_BLOCKS),
+            blocks: Vec::new(),
+            max_momentary: None,
+            max_short_term: None,
+            peak: 0.0,
+            window_sum_sq: 0.0,
+            window_samples: 0,
+            window_peak: 0.0,
+        })
+    }
+
+    /// Add interleaved samples in [-1, 1]
+    pub fn push_interleaved(&mut self, samples: &[f32]) -> Result<(), String> {
+        if !samples.len().is_multiple_of(self.channels) {
+            return Err(format!(
+                "{} samples is not a whole number of {}-channel frames",
+                samples.len(),
+                self.channels
+            ));
+        }
+        for frame in samples.chunks_exact(self.channels) {
+            for (sample, filters) in frame.iter().zip(&mut self.filters) {
+                let x = *sample as f64;
+                let magnitude = x.abs();
+                self.window_peak = self.window_peak.max(magnitude);
+                self.window_sum_sq += x * x;
+                let shelved = filters[0].process(x);
+                let weighted = filters[1].process(shelved);
+                self.sub_block_energy += weighted * weighted;
+            }
+            self.window_samples += self.channels as u64;
+            self.sub_block_pos += 1;
+            if self.sub_block_pos == self.sub_block_frames {
+                self.finish_sub_block();
+            }
+        }
+        self.peak = self.peak.max(self.window_peak);
+        Ok(())
+    }
+
+    /// Levels since the previous call, plus the current loudness
+    pub fn levels(&mut self) -> AudioLevels {
+        let rms = if self.window_samples > 0 {
+            (self.window_sum_sq / self.window_samples as f64).sqrt()
+        } else {
+            0.0
+        };
+        let levels = AudioLevels {
+            rms_db: to_db(rms),
+            peak_db: to_db(self.window_peak),
+            momentary_lufs: self.momentary_lufs(),
+            short_term_lufs: self.short_term_lufs(),
+        };
+        self.window_sum_sq = 0.0;
+        self.window_samples = 0;
+        self.window_peak = 0.0;
+        levels
+    }
+
+    /// Loudness of the last 400 ms (LUFS)
+    pub fn momentary_lufs(&self) -> Option<f64> {
+        self.window_loudness(MOMENTARY_SUB_BLOCKS)
+    }
+
+    /// Loudness of the last 3 s (LUFS)
+    pub fn short_term_lufs(&self) -> Option<f64> {
+        self.window_loudness(SHORT_TERM_SUB_BLOCKS)
+    }
+
+    /// Gated integrated loudness of everything pushed so far (LUFS)
+    pub fn integrated_lufs(&self) -> Option<f64> {
+        let absolute_gate = 10f64.powf((ABSOLUTE_GATE_LUFS + 0.691) / 10.0);
+        let above_absolute: Vec<f64> = self
+            .blocks
+            .iter()
+            .copied()
+            .filter(|&energy| energy > absolute_gate)
+            .collect();
+        if above_absolute.is_empty() {
+            return None;
+        }
+        let ungated = above_absolute.iter().sum::<f64>() / above_absolute.len() as f64;
+        let relative_gate = ungated * 10f64.powf(RELATIVE_GATE_LU / 10.0);
+        let gated: Vec<f64> = above_absolute
+            .into_iter()
+            .filter(|&energy| energy > relative_gate)
+            .collect();
+        to_lufs(gated.iter().sum::<f64>() / gated.len() as f64)
+    }
+
+    /// Summary for `RecordingMetadata`
+    pub fn stats(&self) -> LoudnessStats {
+        LoudnessStats {
+            integrated_lufs: self.integrated_lufs(),
+            max_momentary_lufs: self.max_momentary,
+            max_short_term_lufs: self.max_short_term,
+            peak_dbfs: to_db(self.peak),
+        }
+    }
+
+    /// Forget everything measured so far
+    pub fn reset(&mut self) {
+        for filters in &mut self.filters {
+            for filter in filters.iter_mut() {
+                filter.z = [0.0; 2];
+            }
+        }
+        self.sub_block_energy = 0.0;
+        self.sub_block_pos = 0;
+        self.sub_blocks.clear();
+        self.blocks.clear();
+        self.max_momentary = None;
+        self.max_short_term = None;
+        self.peak = 0.0;
+        self.window_sum_sq = 0.0;
+        self.window_samples = 0;
+        self.window_peak = 0.0;
+    }
+
+    fn finish_sub_block(&mut self) {
+        if self.sub_blocks.len() == SHORT_TERM_SUB_BLOCKS {
+            self.sub_blocks.pop_front();
+        }
+        self.sub_blocks
+            .push_back(self.sub_block_energy / self.sub_block_frames as f64);
+        self.sub_block_energy = 0.0;
+        self.sub_block_pos = 0;
+
+        // Momentary blocks overlap by 75%: one ends on every sub-block
+        if let Some(energy) = self.window_energy(MOMENTARY_SUB_BLOCKS) {
+            self.blocks.push(energy);
+            self.max_momentary = max_loudness(self.max_momentary, to_lufs(energy));
+        }
+        let short_term = self.short_term_lufs();
+        self.max_short_term = max_loudness(self.max_short_term, short_term);
+    }
+
+    fn window_energy(&self, sub_blocks: usize) -> Option<f64> {
+        if self.sub_blocks.len() < sub_blocks {
+            return None;
+        }
+        let sum: f64 = self.sub_blocks.iter().rev().take(sub_blocks).sum();
+        Some(sum / sub_blocks as f64)
+    }
+
+    fn window_loudness(&self, sub_blocks: usize) -> Option<f64> {
+        self.window_energy(sub_blocks).and_then(to_lufs)
+    }
+}
+
+fn max_loudness(current: Option<f64>, value: Option<f64>) -> Option<f64> {
+    match (current, value) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Interleaved stereo sine at the given frequency and amplitude
+    fn sine(sample_rate: u32, frequency: f64, amplitude: f32, seconds: f64) -> Vec<f32> {
+        let frames = (sample_rate as f64 * seconds) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let phase = 2.0 * std::f64::consts::PI * frequency * i as f64 / sample_rate as f64;
+                let sample = amplitude * phase.sin() as f32;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_meter_matches_bs1770_reference() {
+        // A 0 dBFS 997 Hz sine on both channels of a stereo signal measures
+        // 0 LUFS; at -20 dBFS it is 20 LU lower.
+        let mut meter = LoudnessMeterState::new(48000, 2).unwrap();
+        let amplitude = 10f32.powf(-20.0 / 20.0);
+        for block in sine(48000, 997.0, amplitude, 5.0).chunks(256) {
+            meter.push_interleaved(block).unwrap();
+        }
+        let integrated = meter.integrated_lufs().unwrap();
+        assert!((integrated - -20.0).abs() < 0.05, "{}", integrated);
+        assert!((meter.short_term_lufs().unwrap() - -20.0).abs() < 0.05);
+
+        let levels = meter.levels();
+        assert!((levels.peak_db - -20.0).abs() < 0.01);
+        assert!((levels.rms_db - -23.01).abs() < 0.05);
+
+        // Levels cover only what was pushed since the previous reading
+        assert_eq!(meter.levels().rms_db, SILENCE_DB);
+        let stats = meter.stats();
+        assert!((stats.peak_dbfs - -20.0).abs() < 0.01);
+        assert!(stats.max_momentary_lufs.unwrap() >= integrated - 0.1);
+
+        // Sample rates other than 48 kHz get the same weighting
+        let mut meter = LoudnessMeterState::new(44100, 2).unwrap();
+        meter
+            .push_interleaved(&sine(44100, 997.0, amplitude, 5.0))
+            .unwrap();
+        assert!((meter.integrated_lufs().unwrap() - -20.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_meter_gating_and_validation() {
+        let mut meter = LoudnessMeterState::new(48000, 2).unwrap();
+        assert!(meter.push_interleaved(&[0.0; 3]).is_err());
+
+        // Silence never passes the absolute gate
+        meter.push_interleaved(&vec![0.0; 48000 * 2]).unwrap();
+        assert_eq!(meter.integrated_lufs(), None);
+        assert_eq!(meter.momentary_lufs(), None);
+
+        // Quiet passages are gated out relative to the loud ones; only the
+        // blocks straddling the transition count
+        meter
+            .push_interleaved(&sine(48000, 997.0, 0.1, 5.0))
+            .unwrap();
+        let loud_only = meter.integrated_lufs().unwrap();
+        meter
+            .push_interleaved(&sine(48000, 997.0, 0.001, 5.0))
+            .unwrap();
+        assert!((meter.integrated_lufs().unwrap() - loud_only).abs() < 0.2);
+
+        meter.reset();
+        assert_eq!(meter.stats().peak_dbfs, SILENCE_DB);
+        assert!(LoudnessMeterState::new(48000, 0).is_err());
+    }
+}
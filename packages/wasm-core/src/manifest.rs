@@ -0,0 +1,417 @@
+//! Per-session chunk manifest.
+//!
+//! The manifest is the authoritative list of chunks produced for a session,
+//! plus user markers placed on the recording timeline. It is what gets
+//! persisted next to the chunk data and compared against the server copy.
+//! Once the session is finalizing it also maps every fragment of the
+//! assembled file to its byte range.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::chunk::{ChunkId, ChunkMetadata, TrackKind};
+use crate::metadata::RecordingMetadata;
+use crate::session::{SessionId, SessionState};
+
+/// A user-placed marker on the recording timeline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct Marker {
+    /// Position in microseconds from session start
+    #[serde(rename = "timestamp")]
+    pub timestamp_us: u64,
+    /// Optional label shown in the UI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Set on bookmarks, which jump links point to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bookmark: Option<Bookmark>,
+}
+
+/// Frame-accurate anchor of a bookmark
+///
+/// The marker's own timestamp is the exact frame the user bookmarked; a
+/// player jumps to `keyframe` and decodes up to it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct Bookmark {
+    /// Latest video keyframe at or before the marker; unset until the
+    /// fragment holding it is muxed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyframe: Option<KeyframeLocation>,
+}
+
+/// Where a video keyframe lies in the assembled file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyframeLocation {
+    /// Decode time in microseconds
+    #[serde(rename = "timestamp")]
+    pub timestamp_us: u64,
+    /// Sequence number from the mfhd of the fragment holding it
+    pub sequence: u32,
+    /// Byte offset of that fragment's moof, counting the init segment
+    pub fragment_offset: u64,
+    /// Byte offset of the keyframe's sample data, counting the init segment
+    pub sample_offset: u64,
+}
+
+/// Where one fragment (moof + mdat) lies in the assembled file
+///
+/// Offsets and sizes are those of the plaintext file, also when chunks are
+/// stored encrypted: `ChunkMetadata.size` and `hash` describe the stored
+/// (encrypted) bytes, so fragment ranges only line up with the chunks once
+/// these are decrypted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct FragmentRange {
+    /// Sequence number from the fragment's mfhd
+    pub sequence: u32,
+    /// Byte offset of the moof, counting the init segment
+    pub offset: u64,
+    /// Bytes of moof + mdat
+    pub size: u64,
+    /// Earliest decode time of the fragment in microseconds
+    #[serde(rename = "timestamp")]
+    pub timestamp_us: u64,
+    /// Chunk holding the fragment
+    pub chunk_id: ChunkId,
+}
+
+/// A stretch of the timeline during which a track was disabled
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct MutedRange {
+    /// `audio` (recorded as silence) or `video` (last picture held)
+    pub track: TrackKind,
+    pub start_us: u64,
+    pub end_us: u64,
+}
+
+/// Where a session's data ended at its last emergency flush
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct TailMarker {
+    /// End of the flushed media in microseconds from session start
+    pub timestamp_us: u64,
+    /// Chunks in the manifest after the flush
+    pub chunk_count: u64,
+    /// Unix time (ms) of the flush
+    pub flushed_at_ms: u64,
+}
+
+/// Key a run of encrypted chunks uses, up to the next period
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyPeriod {
+    /// Sequence number of the first chunk encrypted with the key
+    pub first_sequence: u64,
+    /// Where players fetch the key (`EXT-X-KEY` `URI`)
+    pub key_uri: String,
+}
+
+/// List of chunks and markers belonging to one session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkManifest {
+    pub session_id: SessionId,
+    #[serde(default)]
+    pub state: SessionState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<RecordingMetadata>,
+    /// Chunks in the order they were added
+    #[serde(default)]
+    pub chunks: Vec<ChunkMetadata>,
+    /// Markers sorted by timestamp
+    #[serde(default)]
+    pub markers: Vec<Marker>,
+    /// Fragments of the muxed file in file order, the init segment being
+    /// everything before the first one (plaintext offsets, see
+    /// `FragmentRange`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fragments: Vec<FragmentRange>,
+    /// Key periods of whole-segment encrypted chunks, in sequence order;
+    /// empty if chunks are stored in the clear
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<KeyPeriod>,
+    /// Ranges where a track was disabled, in the order they ended
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub muted: Vec<MutedRange>,
+    /// Set by an emergency flush (page hidden or closing) and cleared when
+    /// the recording stops; a stored session that still has it was cut off
+    /// after that point
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tail: Option<TailMarker>,
+}
+
+impl ChunkManifest {
+    /// Create an empty manifest for a session
+    pub fn new(session_id: SessionId) -> Self {
+        Self {
+            session_id,
+            state: SessionState::default(),
+            metadata: None,
+            chunks: Vec::new(),
+            markers: Vec::new(),
+            fragments: Vec::new(),
+            keys: Vec::new(),
+            muted: Vec::new(),
+            tail: None,
+        }
+    }
+
+    /// Append a chunk to the manifest
+    ///
+    /// Fails if the chunk belongs to another session or is already listed.
+    /// Legacy chunk IDs (no session) are accepted as-is.
+    pub fn add_chunk(&mut self, chunk: ChunkMetadata) -> Result<(), String> {
+        let id = &chunk.chunk_id;
+        if !id.is_legacy() && id.session != self.session_id {
+            return Err(format!(
+                "Chunk {} does not belong to session {}",
+                id, self.session_id
+            ));
+        }
+        if self.contains(id) {
+            return Err(format!("Chunk {} already in manifest", id));
+        }
+        self.chunks.push(chunk);
+        Ok(())
+    }
+
+    /// Check whether a chunk is listed in the manifest
+    pub fn contains(&self, id: &ChunkId) -> bool {
+        self.chunks.iter().any(|c| &c.chunk_id == id)
+    }
+
+    /// Look up a chunk's metadata by ID
+    pub fn get(&self, id: &ChunkId) -> Option<&ChunkMetadata> {
+        self.chunks.iter().find(|c| &c.chunk_id == id)
+    }
+
+    /// Iterate over the chunks of one track in sequence order
+    pub fn chunks_for_track(&self, track: TrackKind) -> Vec<&ChunkMetadata> {
+        let mut chunks: Vec<&ChunkMetadata> = self
+            .chunks
+            .iter()
+            .filter(|c| c.chunk_id.track == track)
+            .collect();
+        chunks.sort_by_key(|c| c.chunk_id.sequence);
+        chunks
+    }
+
+    /// Get the sequence number the next chunk of `track` should use
+    pub fn next_sequence(&self, track: TrackKind) -> u64 {
+        self.chunks
+            .iter()
+            .filter(|c| c.chunk_id.track == track)
+            .map(|c| c.chunk_id.sequence + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Number of chunks in the manifest
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Total size of all chunks in bytes
+    pub fn total_size(&self) -> u64 {
+        self.chunks.iter().map(|c| c.size).sum()
+    }
+
+    /// Fragment to start reading from to play from `timestamp_us`
+    pub fn fragment_at(&self, timestamp_us: u64) -> Option<&FragmentRange> {
+        let index = self
+            .fragments
+            .partition_point(|f| f.timestamp_us <= timestamp_us);
+        self.fragments.get(index.saturating_sub(1))
+    }
+
+    /// Key period covering the chunk with sequence number `sequence`, if it
+    /// is encrypted
+    pub fn key_for(&self, sequence: u64) -> Option<&KeyPeriod> {
+        let index = self.keys.partition_point(|k| k.first_sequence <= sequence);
+        index.checked_sub(1).map(|index| &self.keys[index])
+    }
+
+    /// Insert a marker, keeping markers sorted by timestamp
+    pub fn add_marker(&mut self, marker: Marker) {
+        let index = self
+            .markers
+            .partition_point(|m| m.timestamp_us <= marker.timestamp_us);
+        self.markers.insert(index, marker);
+    }
+
+    /// Anchor every bookmark at or after `keyframe` to it, unless a later
+    /// keyframe already anchors the bookmark
+    ///
+    /// Called for each keyframe of the file in order, so every bookmark ends
+    /// up on the latest keyframe before it.
+    pub fn locate_bookmarks(&mut self, keyframe: &KeyframeLocation) {
+        let start = self
+            .markers
+            .partition_point(|m| m.timestamp_us < keyframe.timestamp_us);
+        for bookmark in self.markers[start..]
+            .iter_mut()
+            .filter_map(|m| m.bookmark.as_mut())
+        {
+            if bookmark
+                .keyframe
+                .as_ref()
+                .is_none_or(|k| k.timestamp_us < keyframe.timestamp_us)
+            {
+                bookmark.keyframe = Some(keyframe.clone());
+            }
+        }
+    }
+
+    /// Move every byte offset `bytes` later, after the init segment was
+    /// replaced by one that much longer
+    pub fn shift_offsets(&mut self, bytes: u64) {
+        for fragment in self.fragments.iter_mut() {
+            fragment.offset += bytes;
+        }
+        for keyframe in self
+            .markers
+            .iter_mut()
+            .filter_map(|m| m.bookmark.as_mut()?.keyframe.as_mut())
+        {
+            keyframe.fragment_offset += bytes;
+            keyframe.sample_offset += bytes;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(session: &SessionId, track: TrackKind, sequence: u64, size: u64) -> ChunkMetadata {
+        ChunkMetadata {
+            chunk_id: ChunkId::new(session.clone(), track, sequence),
+            timestamp_us: sequence * 2_000_000,
+            size,
+            hash: None,
+            has_keyframe: None,
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_manifest_add_and_query_chunks() {
+        let session = SessionId::from("session-a");
+        let mut manifest = ChunkManifest::new(session.clone());
+
+        manifest
+            .add_chunk(chunk(&session, TrackKind::Video, 1, 100))
+            .unwrap();
+        manifest
+            .add_chunk(chunk(&session, TrackKind::Video, 0, 200))
+            .unwrap();
+        manifest
+            .add_chunk(chunk(&session, TrackKind::Audio, 0, 50))
+            .unwrap();
+
+        assert_eq!(manifest.chunk_count(), 3);
+        assert_eq!(manifest.total_size(), 350);
+        assert_eq!(manifest.next_sequence(TrackKind::Video), 2);
+        assert_eq!(manifest.next_sequence(TrackKind::Muxed), 0);
+
+        let video: Vec<u64> = manifest
+            .chunks_for_track(TrackKind::Video)
+            .iter()
+            .map(|c| c.chunk_id.sequence)
+            .collect();
+        assert_eq!(video, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_manifest_rejects_foreign_and_duplicate_chunks() {
+        let session = SessionId::from("session-a");
+        let other = SessionId::from("session-b");
+        let mut manifest = ChunkManifest::new(session.clone());
+
+        let result = manifest.add_chunk(chunk(&other, TrackKind::Video, 0, 1));
+        assert!(result.unwrap_err().contains("does not belong to session"));
+
+        manifest
+            .add_chunk(chunk(&session, TrackKind::Video, 0, 1))
+            .unwrap();
+        let result = manifest.add_chunk(chunk(&session, TrackKind::Video, 0, 1));
+        assert!(result.unwrap_err().contains("already in manifest"));
+    }
+
+    #[test]
+    fn test_markers_stay_sorted() {
+        let mut manifest = ChunkManifest::new(SessionId::from("session-a"));
+        for ts in [5_000_000, 1_000_000, 3_000_000] {
+            manifest.add_marker(Marker {
+                timestamp_us: ts,
+                label: None,
+                bookmark: None,
+            });
+        }
+        let timestamps: Vec<u64> = manifest.markers.iter().map(|m| m.timestamp_us).collect();
+        assert_eq!(timestamps, vec![1_000_000, 3_000_000, 5_000_000]);
+    }
+
+    #[test]
+    fn test_manifest_serde_roundtrip() {
+        let session = SessionId::from("session-a");
+        let mut manifest = ChunkManifest::new(session.clone());
+        manifest
+            .add_chunk(chunk(&session, TrackKind::Muxed, 0, 10))
+            .unwrap();
+        manifest.add_marker(Marker {
+            timestamp_us: 42,
+            label: Some("intro".to_string()),
+            bookmark: None,
+        });
+
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert!(json.contains(r#""sessionId":"session-a""#));
+        assert!(json.contains(r#""state":"standby""#));
+        let parsed: ChunkManifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, manifest);
+    }
+
+    #[test]
+    fn test_bookmarks_anchor_to_latest_keyframe_before_them() {
+        let mut manifest = ChunkManifest::new(SessionId::from("session-a"));
+        for (ts, bookmark) in [(1_500_000, true), (2_500_000, false), (4_000_000, true)] {
+            manifest.add_marker(Marker {
+                timestamp_us: ts,
+                label: None,
+                bookmark: bookmark.then(Bookmark::default),
+            });
+        }
+        let keyframe = |sequence: u32| KeyframeLocation {
+            timestamp_us: sequence as u64 * 1_000_000,
+            sequence,
+            fragment_offset: sequence as u64 * 100,
+            sample_offset: sequence as u64 * 100 + 40,
+        };
+        for sequence in 1..4 {
+            manifest.locate_bookmarks(&keyframe(sequence));
+        }
+        manifest.shift_offsets(8);
+
+        let anchors: Vec<Option<u32>> = manifest
+            .markers
+            .iter()
+            .map(|m| Some(m.bookmark.as_ref()?.keyframe.as_ref()?.sequence))
+            .collect();
+        assert_eq!(anchors, vec![Some(1), None, Some(3)]);
+        let last = manifest.markers[2].bookmark.as_ref().unwrap();
+        assert_eq!(last.keyframe.as_ref().unwrap().sample_offset, 348);
+    }
+}
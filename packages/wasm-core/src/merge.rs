@@ -0,0 +1,1059 @@
+//! Merging the recordings of several participants of one room.
+//!
+//! Every participant records on their own device, with a timeline that
+//! starts when their recorder started. `SessionMerger` places those
+//! timelines on one wall clock, using the `prft` box of the first segment
+//! when a recording has one and the clock-synced start time from `SyncInfo`
+//! otherwise. The result is either a single MP4 holding every participant's
+//! tracks, or a bundle of the unchanged per-participant files plus a
+//! `MergeManifest` describing how they line up.
+//!
+//! A merged MP4 with several audio tracks puts them in one alternate group
+//! with only the primary mix enabled, so players play one track by default
+//! instead of all of them at once.
+//!
+//! Every track of a merged MP4 is named after its recording's label in a
+//! `udta/name` box, followed by the track's own name ("alice - Camera") when
+//! the recorder gave it one. A capture of several displays is recorded as one
+//! recording per display and merged the same way; with a `DisplayLayout`
+//! the manifest places each display's video on a shared canvas, and the
+//! tkhd matrix of each video track is translated to its region so players
+//! that honour it show the displays side by side.
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::ChunkManifest;
+use crate::muxide_muxer::{
+    build_box, build_ftyp, build_mvhd, build_trex, find_box, parse_boxes, read_u32, read_u64,
+    DEFAULT_MOVIE_TIMESCALE,
+};
+use crate::session::SessionId;
+use crate::timebase::{Rounding, TimeBase};
+
+/// Version written into merge manifests
+pub const MERGE_MANIFEST_VERSION: u32 = 1;
+
+/// Alternate group shared by the audio tracks of a merged MP4
+const AUDIO_ALTERNATE_GROUP: u16 = 1;
+
+/// track_enabled flag of a tkhd box
+const TKHD_ENABLED: u32 = 0x0000_0001;
+
+/// Offset of the matrix in a version 0 tkhd payload (+12 in version 1)
+const TKHD_MATRIX_OFFSET: usize = 40;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970)
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+/// One participant's recording as stored by the server
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParticipantRecording {
+    /// Name used in the merge manifest
+    pub label: String,
+    pub manifest: ChunkManifest,
+    pub init_segment: Vec<u8>,
+    /// Media segments in timeline order
+    pub segments: Vec<Vec<u8>>,
+}
+
+impl ParticipantRecording {
+    /// Create a recording labelled with its participant name, or its
+    /// session ID when the metadata has none
+    pub fn new(manifest: ChunkManifest, init_segment: Vec<u8>, segments: Vec<Vec<u8>>) -> Self {
+        let label = manifest
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.participant_name.clone())
+            .unwrap_or_else(|| manifest.session_id.to_string());
+        Self {
+            label,
+            manifest,
+            init_segment,
+            segments,
+        }
+    }
+}
+
+/// Where a participant's wall-clock start time came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AnchorSource {
+    /// `prft` (producer reference time) box of the first segment
+    Prft,
+    /// Clock-synced start time in the recording metadata
+    SyncInfo,
+}
+
+/// How the videos of the merged recordings are arranged on one canvas
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DisplayLayout {
+    /// No layout; every video keeps the origin
+    #[default]
+    None,
+    /// Left to right in the order recordings were added, tops aligned
+    SideBySide,
+    /// Top to bottom in the order recordings were added, left edges aligned
+    Stacked,
+}
+
+/// Region of the canvas showing one recording's video, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisplayRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Size of the canvas holding every region of a layout, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CanvasSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Placement of one participant on the merged timeline
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergedParticipant {
+    pub label: String,
+    pub session_id: SessionId,
+    pub anchor: AnchorSource,
+    /// Server time (Unix ms) of the start of this participant's timeline
+    pub start_time_ms: f64,
+    /// Where this participant's timeline starts on the merged timeline
+    pub offset_us: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_us: Option<u64>,
+    /// IDs of this participant's tracks in the merged MP4
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub track_ids: Vec<u32>,
+    /// Name of this participant's file in a bundle
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Where this participant's video sits on the layout canvas
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<DisplayRegion>,
+}
+
+/// Combined manifest of a merged session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeManifest {
+    pub version: u32,
+    /// Server time (Unix ms) of the start of the merged timeline
+    pub start_time_ms: f64,
+    /// Participants in the order they were added
+    pub participants: Vec<MergedParticipant>,
+    /// Audio track of the merged MP4 that plays by default, when it has
+    /// several
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_audio_track_id: Option<u32>,
+    /// Canvas of the display layout, when one was chosen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canvas: Option<CanvasSize>,
+}
+
+/// One file of a bundle
+#[derive(Debug, Clone, PartialEq)]
+pub struct BundleFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Per-participant MP4 files plus the manifest that aligns them
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeBundle {
+    pub manifest: MergeManifest,
+    pub files: Vec<BundleFile>,
+}
+
+/// A track of a participant's init segment
+#[derive(Debug, Clone)]
+pub(crate) struct TrackInfo {
+    pub(crate) track_id: u32,
+    timescale: u32,
+    /// Whether the handler is `soun`
+    pub(crate) is_audio: bool,
+    /// Whether the handler is `vide`
+    is_video: bool,
+    /// Name from the trak's `udta/name` box
+    name: Option<String>,
+    /// Presentation size from the tkhd, in whole pixels
+    width: u32,
+    height: u32,
+    /// The complete trak box
+    pub(crate) trak: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+struct Participant {
+    recording: ParticipantRecording,
+    tracks: Vec<TrackInfo>,
+    anchor: AnchorSource,
+    start_time_ms: f64,
+}
+
+/// Aligns and merges the recordings of one room
+#[derive(Debug, Clone, Default)]
+pub struct SessionMerger {
+    participants: Vec<Participant>,
+    /// Participant whose audio is the primary mix
+    primary_audio: Option<SessionId>,
+    display_layout: DisplayLayout,
+}
+
+impl SessionMerger {
+    /// Create an empty merger
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a participant's recording
+    ///
+    /// Fails if the init segment cannot be read or the recording has
+    /// neither a `prft` box nor sync info to anchor it.
+    pub fn add(&mut self, recording: ParticipantRecording) -> Result<(), String> {
+        let tracks = read_tracks(&recording.init_segment)
+            .map_err(|e| format!("Recording of {}: {}", recording.label, e))?;
+        let prft = match recording.segments.first() {
+            Some(segment) => read_prft_anchor(segment, &tracks)?,
+            None => None,
+        };
+        let (anchor, start_time_ms) = match prft {
+            Some(start) => (AnchorSource::Prft, start),
+            None => {
+                let sync = recording
+                    .manifest
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.sync_info.as_ref())
+                    .ok_or_else(|| {
+                        format!(
+                            "Recording of {} has no prft box or sync info to align it",
+                            recording.label
+                        )
+                    })?;
+                (
+                    AnchorSource::SyncInfo,
+                    sync.actual_start_time + sync.clock_offset_ms,
+                )
+            }
+        };
+        self.participants.push(Participant {
+            recording,
+            tracks,
+            anchor,
+            start_time_ms,
+        });
+        Ok(())
+    }
+
+    /// Make a participant's audio the track merged MP4s play by default
+    ///
+    /// Without one, the first participant with audio is the primary mix.
+    pub fn set_primary_audio(&mut self, session_id: &SessionId) -> Result<(), String> {
+        let participant = self
+            .participants
+            .iter()
+            .find(|p| &p.recording.manifest.session_id == session_id)
+            .ok_or_else(|| format!("No recording of session {} was added", session_id))?;
+        if !participant.tracks.iter().any(|t| t.is_audio) {
+            return Err(format!(
+                "Recording of {} has no audio",
+                participant.recording.label
+            ));
+        }
+        self.primary_audio = Some(session_id.clone());
+        Ok(())
+    }
+
+    /// Arrange the recordings' videos on one canvas
+    ///
+    /// Recordings without video get no region.
+    pub fn set_display_layout(&mut self, layout: DisplayLayout) {
+        self.display_layout = layout;
+    }
+
+    /// Number of participants added
+    pub fn len(&self) -> usize {
+        self.participants.len()
+    }
+
+    /// Whether no participant has been added
+    pub fn is_empty(&self) -> bool {
+        self.participants.is_empty()
+    }
+
+    /// Manifest with every participant's offset on the merged timeline
+    ///
+    /// The merged timeline starts when the earliest participant started.
+    pub fn manifest(&self) -> Result<MergeManifest, String> {
+        let start_time_ms = self
+            .participants
+            .iter()
+            .map(|p| p.start_time_ms)
+            .min_by(f64::total_cmp)
+            .ok_or("No recordings to merge")?;
+        let mut participants: Vec<MergedParticipant> = self
+            .participants
+            .iter()
+            .map(|p| MergedParticipant {
+                label: p.recording.label.clone(),
+                session_id: p.recording.manifest.session_id.clone(),
+                anchor: p.anchor,
+                start_time_ms: p.start_time_ms,
+                offset_us: ((p.start_time_ms - start_time_ms) * 1000.0).round() as u64,
+                duration_us: p
+                    .recording
+                    .manifest
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.duration_us),
+                track_ids: Vec::new(),
+                file: None,
+                region: None,
+            })
+            .collect();
+
+        let mut canvas = None;
+        if self.display_layout != DisplayLayout::None {
+            let mut size = CanvasSize {
+                width: 0,
+                height: 0,
+            };
+            for (participant, entry) in self.participants.iter().zip(&mut participants) {
+                let Some(video) = participant.tracks.iter().find(|t| t.is_video) else {
+                    continue;
+                };
+                let region = match self.display_layout {
+                    DisplayLayout::Stacked => DisplayRegion {
+                        x: 0,
+                        y: size.height,
+                        width: video.width,
+                        height: video.height,
+                    },
+                    _ => DisplayRegion {
+                        x: size.width,
+                        y: 0,
+                        width: video.width,
+                        height: video.height,
+                    },
+                };
+                size.width = size.width.max(region.x + region.width);
+                size.height = size.height.max(region.y + region.height);
+                entry.region = Some(region);
+            }
+            canvas = Some(size);
+        }
+
+        Ok(MergeManifest {
+            version: MERGE_MANIFEST_VERSION,
+            start_time_ms,
+            participants,
+            primary_audio_track_id: None,
+            canvas,
+        })
+    }
+
+    /// Bundle the unchanged recordings with a manifest naming their files
+    pub fn bundle(&self) -> Result<MergeBundle, String> {
+        let mut manifest = self.manifest()?;
+        let mut files = Vec::with_capacity(self.participants.len());
+        for (index, (participant, entry)) in self
+            .participants
+            .iter()
+            .zip(&mut manifest.participants)
+            .enumerate()
+        {
+            let recording = &participant.recording;
+            let name = format!("{:02}-{}.mp4", index + 1, recording.manifest.session_id);
+            let mut data = recording.init_segment.clone();
+            for segment in &recording.segments {
+                data.extend_from_slice(segment);
+            }
+            entry.file = Some(name.clone());
+            files.push(BundleFile { name, data });
+        }
+        Ok(MergeBundle { manifest, files })
+    }
+
+    /// Merge every participant's tracks into one fragmented MP4
+    ///
+    /// Tracks are renumbered in the order participants were added and named
+    /// after their participant, video tracks are moved to their layout
+    /// region, each fragment's decode times are shifted by its participant's
+    /// offset, and fragments are interleaved by start time.
+    pub fn write_mp4(&self) -> Result<(Vec<u8>, MergeManifest), String> {
+        let mut manifest = self.manifest()?;
+
+        // Renumber tracks: (participant, original ID) -> merged ID
+        let mut next_track_id = 1u32;
+        let mut track_maps: Vec<Vec<(u32, u32)>> = Vec::with_capacity(self.participants.len());
+        let mut traks = Vec::new();
+        let audio_tracks = self
+            .participants
+            .iter()
+            .flat_map(|p| &p.tracks)
+            .filter(|t| t.is_audio)
+            .count();
+        let primary = self.primary_audio.as_ref().or_else(|| {
+            self.participants
+                .iter()
+                .find(|p| p.tracks.iter().any(|t| t.is_audio))
+                .map(|p| &p.recording.manifest.session_id)
+        });
+        for (participant, entry) in self.participants.iter().zip(&mut manifest.participants) {
+            let mut map = Vec::with_capacity(participant.tracks.len());
+            for track in &participant.tracks {
+                let mut trak = renumber_trak(&track.trak, next_track_id)?;
+                if track.is_audio && audio_tracks > 1 {
+                    let enabled = manifest.primary_audio_track_id.is_none()
+                        && primary == Some(&participant.recording.manifest.session_id);
+                    if enabled {
+                        manifest.primary_audio_track_id = Some(next_track_id);
+                    }
+                    set_alternate_group(&mut trak, AUDIO_ALTERNATE_GROUP, enabled)?;
+                }
+                if let (true, Some(region)) = (track.is_video, entry.region) {
+                    set_translation(&mut trak, region.x, region.y)?;
+                }
+                let label = &participant.recording.label;
+                let name = match &track.name {
+                    Some(name) => format!("{} - {}", label, name),
+                    None => label.clone(),
+                };
+                let trak = set_track_name(&trak, &name)?;
+                traks.extend_from_slice(&trak);
+                map.push((track.track_id, next_track_id));
+                entry.track_ids.push(next_track_id);
+                next_track_id += 1;
+            }
+            track_maps.push(map);
+        }
+
+        let mut moov = build_mvhd(DEFAULT_MOVIE_TIMESCALE, next_track_id);
+        moov.extend_from_slice(&traks);
+        let mut mvex = Vec::new();
+        for track_id in 1..next_track_id {
+            mvex.extend_from_slice(&build_trex(track_id));
+        }
+        moov.extend_from_slice(&build_box(b"mvex", &mvex));
+
+        let mut out = build_ftyp();
+        out.extend_from_slice(&build_box(b"moov", &moov));
+
+        // Interleave fragments of all participants by their merged start time
+        let mut order = Vec::new();
+        for (index, participant) in self.participants.iter().enumerate() {
+            let offset_us = manifest.participants[index].offset_us;
+            for (segment_index, segment) in participant.recording.segments.iter().enumerate() {
+                let start_us = segment_start_us(segment, &participant.tracks)?;
+                order.push((offset_us + start_us, index, segment_index));
+            }
+        }
+        order.sort();
+
+        let mut sequence_number = 1u32;
+        for (_, index, segment_index) in order {
+            let participant = &self.participants[index];
+            let shift = Shift {
+                tracks: &participant.tracks,
+                track_map: &track_maps[index],
+                offset_us: manifest.participants[index].offset_us,
+            };
+            let segment = &participant.recording.segments[segment_index];
+            let mut data = segment.clone();
+            for b in parse_boxes(segment)? {
+                let range = b.offset..b.offset + 8 + b.payload.len();
+                match &b.typ {
+                    b"moof" => {
+                        shift.patch_moof(&mut data, range.start + 8..range.end, sequence_number)?;
+                        sequence_number += 1;
+                        out.extend_from_slice(&data[range]);
+                    }
+                    b"mdat" => out.extend_from_slice(&data[range]),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok((out, manifest))
+    }
+}
+
+/// Track renumbering and time shift applied to one participant's fragments
+struct Shift<'a> {
+    tracks: &'a [TrackInfo],
+    track_map: &'a [(u32, u32)],
+    offset_us: u64,
+}
+
+impl Shift<'_> {
+    fn patch_moof(&self, data: &mut [u8], moof: Range<usize>, sequence: u32) -> Result<(), String> {
+        for (typ, range) in child_boxes(data, moof)? {
+            match &typ {
+                b"mfhd" => write_u32(data, range.start + 4, sequence)?,
+                b"traf" => self.patch_traf(data, range)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn patch_traf(&self, data: &mut [u8], traf: Range<usize>) -> Result<(), String> {
+        let mut track = None;
+        for (typ, range) in child_boxes(data, traf)? {
+            match &typ {
+                b"tfhd" => {
+                    let track_id = read_u32(data, range.start + 4)?;
+                    let (_, merged_id) = self
+                        .track_map
+                        .iter()
+                        .find(|(original, _)| *original == track_id)
+                        .ok_or_else(|| format!("Segment references unknown track {}", track_id))?;
+                    write_u32(data, range.start + 4, *merged_id)?;
+                    track = self.tracks.iter().find(|t| t.track_id == track_id);
+                }
+                b"tfdt" => {
+                    let track = track.ok_or("tfdt before tfhd in traf")?;
+                    let shift = TimeBase::new(track.timescale as u64)
+                        .from_us(self.offset_us, Rounding::Floor);
+                    if data.get(range.start) == Some(&1) {
+                        let time = read_u64(data, range.start + 4)? + shift;
+                        data[range.start + 4..range.start + 12]
+                            .copy_from_slice(&time.to_be_bytes());
+                    } else {
+                        let time =
+                            u32::try_from(read_u32(data, range.start + 4)? as u64 + shift)
+                                .map_err(|_| "Shifted decode time does not fit a version 0 tfdt")?;
+                        write_u32(data, range.start + 4, time)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read the tracks of an init segment
+pub(crate) fn read_tracks(init_segment: &[u8]) -> Result<Vec<TrackInfo>, String> {
+    let moov = find_box(init_segment, b"moov")?.ok_or("Init segment has no moov box")?;
+    let mut tracks = Vec::new();
+    for trak in parse_boxes(moov.payload)?
+        .into_iter()
+        .filter(|b| &b.typ == b"trak")
+    {
+        let tkhd = find_box(trak.payload, b"tkhd")?.ok_or("trak has no tkhd box")?;
+        let id_offset = if tkhd.payload.first() == Some(&1) {
+            20
+        } else {
+            12
+        };
+        let mdia = find_box(trak.payload, b"mdia")?.ok_or("trak has no mdia box")?;
+        let mdhd = find_box(mdia.payload, b"mdhd")?.ok_or("mdia has no mdhd box")?;
+        let timescale_offset = if mdhd.payload.first() == Some(&1) {
+            20
+        } else {
+            12
+        };
+        let timescale = read_u32(mdhd.payload, timescale_offset)?;
+        if timescale == 0 {
+            return Err("Track has a timescale of 0".to_string());
+        }
+        let hdlr = find_box(mdia.payload, b"hdlr")?.ok_or("mdia has no hdlr box")?;
+        let name = match find_box(trak.payload, b"udta")? {
+            Some(udta) => find_box(udta.payload, b"name")?
+                .map(|name| String::from_utf8_lossy(name.payload).into_owned()),
+            None => None,
+        };
+        // Width and height follow the 36-byte matrix, in 16.16 fixed point
+        let size_offset = tkhd_matrix_offset(tkhd.payload.first().copied().unwrap_or(0)) + 36;
+        tracks.push(TrackInfo {
+            track_id: read_u32(tkhd.payload, id_offset)?,
+            timescale,
+            is_audio: hdlr.payload.get(8..12) == Some(b"soun"),
+            is_video: hdlr.payload.get(8..12) == Some(b"vide"),
+            name,
+            width: read_u32(tkhd.payload, size_offset)? >> 16,
+            height: read_u32(tkhd.payload, size_offset + 4)? >> 16,
+            trak: moov.payload[trak.offset..trak.offset + 8 + trak.payload.len()].to_vec(),
+        });
+    }
+    if tracks.is_empty() {
+        return Err("Init segment has no tracks".to_string());
+    }
+    Ok(tracks)
+}
+
+/// Copy a trak box with a new track ID in its tkhd
+pub(crate) fn renumber_trak(trak: &[u8], track_id: u32) -> Result<Vec<u8>, String> {
+    let mut trak = trak.to_vec();
+    let (_, tkhd) = child_boxes(&trak, 8..trak.len())?
+        .into_iter()
+        .find(|(typ, _)| typ == b"tkhd")
+        .ok_or("trak has no tkhd box")?;
+    let id_offset = if trak[tkhd.start] == 1 { 20 } else { 12 };
+    write_u32(&mut trak, tkhd.start + id_offset, track_id)?;
+    Ok(trak)
+}
+
+/// Put a trak box in an alternate group, enabled or not
+///
+/// Players play one enabled track per alternate group; group 0 means the
+/// track is not an alternative to any other.
+pub(crate) fn set_alternate_group(
+    trak: &mut [u8],
+    group: u16,
+    enabled: bool,
+) -> Result<(), String> {
+    let (_, tkhd) = child_boxes(trak, 8..trak.len())?
+        .into_iter()
+        .find(|(typ, _)| typ == b"tkhd")
+        .ok_or("trak has no tkhd box")?;
+    let flags = read_u32(trak, tkhd.start)?;
+    let flags = if enabled {
+        flags | TKHD_ENABLED
+    } else {
+        flags & !TKHD_ENABLED
+    };
+    write_u32(trak, tkhd.start, flags)?;
+    let group_offset = if trak[tkhd.start] == 1 { 46 } else { 34 };
+    trak.get_mut(tkhd.start + group_offset..tkhd.start + group_offset + 2)
+        .ok_or("Truncated tkhd box")?
+        .copy_from_slice(&group.to_be_bytes());
+    Ok(())
+}
+
+/// Move a trak's picture by whole pixels through the tkhd matrix
+pub(crate) fn set_translation(trak: &mut [u8], x: u32, y: u32) -> Result<(), String> {
+    let (_, tkhd) = child_boxes(trak, 8..trak.len())?
+        .into_iter()
+        .find(|(typ, _)| typ == b"tkhd")
+        .ok_or("trak has no tkhd box")?;
+    let matrix = tkhd.start + tkhd_matrix_offset(trak[tkhd.start]);
+    // Matrix entries 6 and 7 are the translation, in 16.16 fixed point
+    let fixed = |pixels: u32| {
+        u32::try_from(u64::from(pixels) << 16)
+            .map_err(|_| format!("Translation of {} px does not fit the tkhd matrix", pixels))
+    };
+    let (x, y) = (fixed(x)?, fixed(y)?);
+    write_u32(trak, matrix + 24, x)?;
+    write_u32(trak, matrix + 28, y)?;
+    Ok(())
+}
+
+/// Offset of the matrix in a tkhd payload of the given version
+fn tkhd_matrix_offset(version: u8) -> usize {
+    if version == 1 {
+        TKHD_MATRIX_OFFSET + 12
+    } else {
+        TKHD_MATRIX_OFFSET
+    }
+}
+
+/// Copy a trak box with its `udta/name` box set to `name`
+///
+/// Other boxes of an existing udta are kept.
+pub(crate) fn set_track_name(trak: &[u8], name: &str) -> Result<Vec<u8>, String> {
+    let mut payload = Vec::with_capacity(trak.len() + name.len() + 16);
+    let mut udta = Vec::new();
+    for (typ, range) in child_boxes(trak, 8..trak.len())? {
+        if &typ == b"udta" {
+            for (child, child_range) in child_boxes(trak, range)? {
+                if &child != b"name" {
+                    udta.extend_from_slice(&trak[child_range.start - 8..child_range.end]);
+                }
+            }
+        } else {
+            payload.extend_from_slice(&trak[range.start - 8..range.end]);
+        }
+    }
+    udta.extend_from_slice(&build_box(b"name", name.as_bytes()));
+    payload.extend_from_slice(&build_box(b"udta", &udta));
+    Ok(build_box(b"trak", &payload))
+}
+
+/// Wall-clock start (Unix ms) of a timeline from a segment's prft box
+fn read_prft_anchor(segment: &[u8], tracks: &[TrackInfo]) -> Result<Option<f64>, String> {
+    let Some(prft) = find_box(segment, b"prft")? else {
+        return Ok(None);
+    };
+    let track_id = read_u32(prft.payload, 4)?;
+    let ntp = read_u64(prft.payload, 8)?;
+    let media_time = if prft.payload.first() == Some(&1) {
+        read_u64(prft.payload, 16)?
+    } else {
+        read_u32(prft.payload, 16)? as u64
+    };
+    let track = tracks
+        .iter()
+        .find(|t| t.track_id == track_id)
+        .ok_or_else(|| format!("prft references unknown track {}", track_id))?;
+    let secs = (ntp >> 32)
+        .checked_sub(NTP_UNIX_OFFSET_SECS)
+        .ok_or("prft time is before the Unix epoch")?;
+    let fraction = (ntp & 0xFFFF_FFFF) as f64 / (1u64 << 32) as f64;
+    let wall_clock_ms = (secs as f64 + fraction) * 1000.0;
+    Ok(Some(
+        wall_clock_ms - media_time as f64 * 1000.0 / track.timescale as f64,
+    ))
+}
+
+/// Earliest decode time of a segment, in microseconds
+fn segment_start_us(segment: &[u8], tracks: &[TrackInfo]) -> Result<u64, String> {
+    let mut start: Option<u64> = None;
+    for moof in parse_boxes(segment)?
+        .into_iter()
+        .filter(|b| &b.typ == b"moof")
+    {
+        for traf in parse_boxes(moof.payload)?
+            .into_iter()
+            .filter(|b| &b.typ == b"traf")
+        {
+            let tfhd = find_box(traf.payload, b"tfhd")?.ok_or("traf has no tfhd box")?;
+            let track_id = read_u32(tfhd.payload, 4)?;
+            let track = tracks
+                .iter()
+                .find(|t| t.track_id == track_id)
+                .ok_or_else(|| format!("Segment references unknown track {}", track_id))?;
+            let tfdt = find_box(traf.payload, b"tfdt")?.ok_or("traf has no tfdt box")?;
+            let time = if tfdt.payload.first() == Some(&1) {
+                read_u64(tfdt.payload, 4)?
+            } else {
+                read_u32(tfdt.payload, 4)? as u64
+            };
+            let time_us = TimeBase::new(track.timescale as u64).to_us(time, Rounding::Floor);
+            start = Some(start.map_or(time_us, |s| s.min(time_us)));
+        }
+    }
+    start.ok_or_else(|| "Segment has no track fragments".to_string())
+}
+
+/// Box types with the absolute range of each payload
+type BoxRanges = Vec<([u8; 4], Range<usize>)>;
+
+/// Boxes inside `range` of `data`
+fn child_boxes(data: &[u8], range: Range<usize>) -> Result<BoxRanges, String> {
+    let base = range.start;
+    Ok(parse_boxes(&data[range])?
+        .into_iter()
+        .map(|b| {
+            let start = base + b.offset + 8;
+            (b.typ, start..start + b.payload.len())
+        })
+        .collect())
+}
+
+fn write_u32(data: &mut [u8], pos: usize, value: u32) -> Result<(), String> {
+    data.get_mut(pos..pos + 4)
+        .ok_or("Unexpected end of box")?
+        .copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::{RecordingMetadata, SyncInfo};
+    use crate::muxide_muxer::{MuxideConfig, MuxideMuxerState};
+
+    fn recording(session: &str, name: &str, start_ms: f64) -> ParticipantRecording {
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(1),
+            audio_timescale: Some(48000),
+            fragment_duration_ms: 1000,
+            audio_track_name: Some("Microphone".to_string()),
+            ..Default::default()
+        });
+        muxer.init().unwrap();
+        let init = muxer.get_init_segment().unwrap();
+        for i in 0..100u64 {
+            muxer
+                .push_audio_chunk(&[0x21, i as u8], i * 21_333, 21_333)
+                .unwrap();
+        }
+        muxer.force_flush().unwrap();
+        let mut manifest = ChunkManifest::new(SessionId::from(session));
+        manifest.metadata = Some(RecordingMetadata {
+            participant_name: Some(name.to_string()),
+            sync_info: Some(SyncInfo {
+                scheduled_start_time: 0.0,
+                actual_start_time: start_ms - 250.0,
+                clock_offset_ms: 250.0,
+                clock_offset_accuracy_ms: 2.0,
+                sync_sample_count: 8,
+            }),
+            ..Default::default()
+        });
+        ParticipantRecording::new(manifest, init, muxer.get_pending_segments())
+    }
+
+    /// (track ID, base decode time) of every traf in a buffer
+    fn track_fragments(data: &[u8]) -> Vec<(u32, u64)> {
+        let mut out = Vec::new();
+        for moof in parse_boxes(data)
+            .unwrap()
+            .into_iter()
+            .filter(|b| &b.typ == b"moof")
+        {
+            for traf in parse_boxes(moof.payload)
+                .unwrap()
+                .into_iter()
+                .filter(|b| &b.typ == b"traf")
+            {
+                let tfhd = find_box(traf.payload, b"tfhd").unwrap().unwrap();
+                let tfdt = find_box(traf.payload, b"tfdt").unwrap().unwrap();
+                out.push((
+                    read_u32(tfhd.payload, 4).unwrap(),
+                    read_u64(tfdt.payload, 4).unwrap(),
+                ));
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_merge_aligns_participants() {
+        let mut merger = SessionMerger::new();
+        merger.add(recording("s1", "alice", 1_000_000.0)).unwrap();
+        merger.add(recording("s2", "bob", 1_000_500.0)).unwrap();
+
+        let manifest = merger.manifest().unwrap();
+        assert_eq!(manifest.start_time_ms, 1_000_000.0);
+        let offsets: Vec<u64> = manifest.participants.iter().map(|p| p.offset_us).collect();
+        assert_eq!(offsets, vec![0, 500_000]);
+        assert_eq!(manifest.participants[1].label, "bob");
+        assert_eq!(manifest.participants[1].anchor, AnchorSource::SyncInfo);
+
+        let (mp4, manifest) = merger.write_mp4().unwrap();
+        assert_eq!(manifest.participants[0].track_ids, vec![1]);
+        assert_eq!(manifest.participants[1].track_ids, vec![2]);
+
+        let moov = find_box(&mp4, b"moov").unwrap().unwrap();
+        let tracks = read_tracks(&mp4).unwrap();
+        assert_eq!(
+            tracks.iter().map(|t| t.track_id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        let mvex = find_box(moov.payload, b"mvex").unwrap().unwrap();
+        assert_eq!(parse_boxes(mvex.payload).unwrap().len(), 2);
+
+        // Bob's fragments are shifted by 0.5 s and interleaved with Alice's
+        let fragments = track_fragments(&mp4);
+        assert_eq!(
+            fragments.len(),
+            2 * merger.participants[0].recording.segments.len()
+        );
+        assert_eq!(fragments[0], (1, 0));
+        assert_eq!(fragments[1], (2, 24_000));
+        assert!(fragments.windows(2).all(|w| w[0].1 <= w[1].1));
+        let bob = &merger.participants[1].recording;
+        let original: Vec<u64> = track_fragments(&bob.segments.concat())
+            .into_iter()
+            .map(|(_, time)| time + 24_000)
+            .collect();
+        let shifted: Vec<u64> = fragments
+            .iter()
+            .filter(|(track, _)| *track == 2)
+            .map(|(_, time)| *time)
+            .collect();
+        assert_eq!(shifted, original);
+
+        let bundle = merger.bundle().unwrap();
+        assert_eq!(bundle.files.len(), 2);
+        assert_eq!(
+            bundle.manifest.participants[1].file.as_deref(),
+            Some("02-s2.mp4")
+        );
+        assert_eq!(
+            bundle.files[1].data,
+            [bob.init_segment.clone(), bob.segments.concat()].concat()
+        );
+    }
+
+    /// (track_enabled, alternate group) of every trak in a file
+    fn track_groups(mp4: &[u8]) -> Vec<(bool, u16)> {
+        read_tracks(mp4)
+            .unwrap()
+            .iter()
+            .map(|t| {
+                let tkhd = find_box(&t.trak[8..], b"tkhd").unwrap().unwrap();
+                let enabled = read_u32(tkhd.payload, 0).unwrap() & TKHD_ENABLED != 0;
+                (
+                    enabled,
+                    u16::from_be_bytes([tkhd.payload[34], tkhd.payload[35]]),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_audio_alternate_group() {
+        let mut merger = SessionMerger::new();
+        merger.add(recording("s1", "alice", 1_000_000.0)).unwrap();
+        let (mp4, manifest) = merger.write_mp4().unwrap();
+        assert_eq!(track_groups(&mp4), vec![(true, 0)]);
+        assert_eq!(manifest.primary_audio_track_id, None);
+
+        // The first participant is the primary mix by default
+        merger.add(recording("s2", "bob", 1_000_500.0)).unwrap();
+        merger.add(recording("s3", "mix", 1_000_000.0)).unwrap();
+        let (mp4, manifest) = merger.write_mp4().unwrap();
+        assert_eq!(track_groups(&mp4), vec![(true, 1), (false, 1), (false, 1)]);
+        assert_eq!(manifest.primary_audio_track_id, Some(1));
+
+        merger.set_primary_audio(&SessionId::from("s3")).unwrap();
+        let (mp4, manifest) = merger.write_mp4().unwrap();
+        assert_eq!(track_groups(&mp4), vec![(false, 1), (false, 1), (true, 1)]);
+        assert_eq!(manifest.primary_audio_track_id, Some(3));
+        assert!(merger.set_primary_audio(&SessionId::from("s4")).is_err());
+
+        // Extracted on its own, a disabled alternative plays again
+        let bob = crate::extract::extract_track(&mp4, 2).unwrap();
+        assert_eq!(track_groups(&bob), vec![(true, 0)]);
+    }
+
+    /// Video-only recording of one display
+    fn display(session: &str, name: &str, width: u32, height: u32) -> ParticipantRecording {
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+            pps: Some(vec![0x68, 0xCE, 0x38, 0x80]),
+            video_width: Some(width),
+            video_height: Some(height),
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        });
+        muxer.init().unwrap();
+        let init = muxer.get_init_segment().unwrap();
+        for i in 0..50u64 {
+            muxer
+                .push_video_chunk(&[0, 0, 0, 1, 0x65], i * 40_000, i % 25 == 0)
+                .unwrap();
+        }
+        muxer.force_flush().unwrap();
+        let mut recording = recording(session, name, 1_000_000.0);
+        recording.init_segment = init;
+        recording.segments = muxer.get_pending_segments();
+        recording
+    }
+
+    #[test]
+    fn test_display_layout() {
+        let mut merger = SessionMerger::new();
+        merger.add(display("s1", "Display 1", 1920, 1080)).unwrap();
+        merger.add(display("s2", "Display 2", 1280, 1024)).unwrap();
+        merger.add(recording("s3", "mic", 1_000_000.0)).unwrap();
+        assert_eq!(merger.manifest().unwrap().canvas, None);
+
+        merger.set_display_layout(DisplayLayout::SideBySide);
+        let (mp4, manifest) = merger.write_mp4().unwrap();
+        assert_eq!(
+            manifest.canvas,
+            Some(CanvasSize {
+                width: 3200,
+                height: 1080
+            })
+        );
+        let regions: Vec<_> = manifest.participants.iter().map(|p| p.region).collect();
+        assert_eq!(
+            regions,
+            vec![
+                Some(DisplayRegion {
+                    x: 0,
+                    y: 0,
+                    width: 1920,
+                    height: 1080
+                }),
+                Some(DisplayRegion {
+                    x: 1920,
+                    y: 0,
+                    width: 1280,
+                    height: 1024
+                }),
+                None,
+            ]
+        );
+
+        // Each track keeps its own size, is named and sits at its region
+        let tracks = read_tracks(&mp4).unwrap();
+        let sizes: Vec<_> = tracks.iter().map(|t| (t.width, t.height)).collect();
+        assert_eq!(sizes, vec![(1920, 1080), (1280, 1024), (0, 0)]);
+        let names: Vec<_> = tracks
+            .iter()
+            .map(|t| {
+                let udta = find_box(&t.trak[8..], b"udta").unwrap().unwrap();
+                let name = find_box(udta.payload, b"name").unwrap().unwrap();
+                String::from_utf8(name.payload.to_vec()).unwrap()
+            })
+            .collect();
+        assert_eq!(names, vec!["Display 1", "Display 2", "mic - Microphone"]);
+        let tkhd = find_box(&tracks[1].trak[8..], b"tkhd").unwrap().unwrap();
+        assert_eq!(read_u32(tkhd.payload, 64).unwrap(), 1920 << 16);
+        assert_eq!(read_u32(tkhd.payload, 68).unwrap(), 0);
+        let mut wide = tracks[1].trak.clone();
+        assert!(set_translation(&mut wide, 65_536, 0).is_err());
+        assert_eq!(wide, tracks[1].trak);
+
+        // Renaming replaces the name box instead of adding another
+        let renamed = set_track_name(&tracks[0].trak, "Left").unwrap();
+        let udta = find_box(&renamed[8..], b"udta").unwrap().unwrap();
+        let boxes = parse_boxes(udta.payload).unwrap();
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(boxes[0].payload, b"Left");
+
+        merger.set_display_layout(DisplayLayout::Stacked);
+        let manifest = merger.manifest().unwrap();
+        assert_eq!(manifest.participants[1].region.unwrap().y, 1080);
+        assert_eq!(
+            manifest.canvas,
+            Some(CanvasSize {
+                width: 1920,
+                height: 2104
+            })
+        );
+
+        // An extracted display is back at the origin
+        let (mp4, _) = merger.write_mp4().unwrap();
+        let second = crate::extract::extract_track(&mp4, 2).unwrap();
+        let tracks = read_tracks(&second).unwrap();
+        let tkhd = find_box(&tracks[0].trak[8..], b"tkhd").unwrap().unwrap();
+        assert_eq!(read_u32(tkhd.payload, 68).unwrap(), 0);
+        assert_eq!((tracks[0].width, tracks[0].height), (1280, 1024));
+    }
+
+    #[test]
+    fn test_prft_anchor_takes_precedence() {
+        let mut prft = Vec::new();
+        prft.extend_from_slice(&0x0100_0000_u32.to_be_bytes()); // Version 1
+        prft.extend_from_slice(&1u32.to_be_bytes()); // Reference track
+        prft.extend_from_slice(&((NTP_UNIX_OFFSET_SECS + 2_000) << 32).to_be_bytes());
+        prft.extend_from_slice(&48_000u64.to_be_bytes()); // 1 s into the track
+        let mut late = recording("s2", "bob", 0.0);
+        late.segments[0] = [build_box(b"prft", &prft), late.segments[0].clone()].concat();
+
+        let mut merger = SessionMerger::new();
+        merger.add(recording("s1", "alice", 1_000_000.0)).unwrap();
+        merger.add(late).unwrap();
+        let manifest = merger.manifest().unwrap();
+        assert_eq!(manifest.participants[1].anchor, AnchorSource::Prft);
+        assert_eq!(manifest.participants[1].start_time_ms, 1_999_000.0);
+        assert_eq!(manifest.participants[1].offset_us, 999_000_000);
+
+        // The prft box itself is not copied into the merged file
+        let (mp4, _) = merger.write_mp4().unwrap();
+        assert!(find_box(&mp4, b"prft").unwrap().is_none());
+
+        let mut unanchored = recording("s3", "carol", 0.0);
+        unanchored.manifest.metadata = None;
+        assert!(merger.add(unanchored).is_err());
+        assert!(SessionMerger::new().write_mp4().is_err());
+    }
+}
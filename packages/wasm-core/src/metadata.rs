@@ -0,0 +1,407 @@
+//! Recording metadata attached to a session.
+//!
+//! Mirrors `RecordingMetadata` in `@maycast/common-types` field-for-field so
+//! the same JSON can be read on either side of the WASM boundary.
+//! `EmbeddedMetadata` is the copy the recorder writes into the MP4 itself.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::manifest::ChunkManifest;
+use crate::muxide_muxer::{read_itunes_tag, SESSION_METADATA_MEAN, SESSION_METADATA_NAME};
+use crate::search_index::SessionIndex;
+use crate::session::SessionId;
+use crate::subtitles::marker_cues;
+
+/// User-visible and post-production metadata for a recording
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingMetadata {
+    /// User-defined display name for the recording
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// Participant name (set from guest name when linked in a room)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub participant_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_info: Option<DeviceInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_config: Option<AudioConfig>,
+    /// Total duration in microseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_us: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_info: Option<SyncInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loudness: Option<LoudnessStats>,
+    /// Frame rate measured from the recorded video timestamps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub framerate: Option<FrameRateStats>,
+    /// Frames lost or altered and gaps in the media, to warn users about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityReport>,
+    /// How the audio sources (e.g. screen and microphone) combine into one
+    /// mix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downmix: Option<DownmixRecipe>,
+    /// How long the recording is kept once finished
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<RetentionPolicy>,
+}
+
+impl RecordingMetadata {
+    /// Whether and when a recording with this metadata may be purged (see
+    /// `RetentionPolicy::status`)
+    pub fn retention_status(
+        metadata: Option<&Self>,
+        default_expire_after_ms: Option<u64>,
+        finished_at_ms: u64,
+        now_ms: u64,
+    ) -> RetentionStatus {
+        metadata
+            .and_then(|metadata| metadata.retention)
+            .unwrap_or_default()
+            .status(default_expire_after_ms, finished_at_ms, now_ms)
+    }
+}
+
+/// Device the recording was captured on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceInfo {
+    pub browser: String,
+    pub os: String,
+    pub screen_resolution: String,
+}
+
+/// Audio encoder configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioConfig {
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channel_count: u16,
+    pub bitrate: u32,
+}
+
+/// Synchronized start information for post-production alignment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncInfo {
+    /// Scheduled server start time (ms since epoch)
+    pub scheduled_start_time: f64,
+    /// Local time when recording actually started (ms since epoch)
+    pub actual_start_time: f64,
+    /// Clock offset used: localTime + offsetMs ≈ serverTime
+    pub clock_offset_ms: f64,
+    /// Estimated accuracy of the offset (standard deviation, ms)
+    pub clock_offset_accuracy_ms: f64,
+    /// Number of samples used for clock sync
+    pub sync_sample_count: u32,
+}
+
+/// Loudness of the recorded input, measured per ITU-R BS.1770
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct LoudnessStats {
+    /// Gated integrated loudness (LUFS), absent if everything was below the gate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrated_lufs: Option<f64>,
+    /// Highest momentary (400 ms) loudness (LUFS)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_momentary_lufs: Option<f64>,
+    /// Highest short-term (3 s) loudness (LUFS)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_short_term_lufs: Option<f64>,
+    /// Highest sample peak (dBFS)
+    pub peak_dbfs: f64,
+}
+
+/// Effective video frame rate over a recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameRateStats {
+    /// Frames per second over the whole recording
+    pub average_fps: f64,
+    pub median_fps: f64,
+    /// Rate of the slowest 5% of frame intervals
+    pub p5_fps: f64,
+    /// Rate of the fastest 5% of frame intervals
+    pub p95_fps: f64,
+    /// Whether the frame rate varied noticeably (VFR)
+    pub variable: bool,
+    pub frame_count: u64,
+}
+
+/// Gains that combine several audio sources into one mix
+///
+/// With `applied` set the recorded audio already is that mix (see
+/// `DownmixMixerState`); otherwise the sources were recorded as separate
+/// tracks and the recipe tells an editor or player how to combine them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct DownmixRecipe {
+    /// Sources in mixing order
+    pub sources: Vec<DownmixSource>,
+    /// Whether the recorder mixed the PCM before encoding
+    #[serde(default)]
+    pub applied: bool,
+}
+
+/// One audio source of a downmix
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct DownmixSource {
+    /// Name of the source, e.g. "screen" or "mic"
+    pub label: String,
+    /// Gain applied to the source (dB, 0 keeps its level)
+    pub gain_db: f64,
+    /// Track holding the source on its own, if it was recorded separately
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_id: Option<u32>,
+}
+
+/// Frames the recorder or muxer lost or altered, and periods without media
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct QualityReport {
+    /// Frames dropped: video waiting for a keyframe after start or resume,
+    /// and invalid frames in warn validation mode
+    pub dropped_frames: u32,
+    /// Frames refused with an error in strict validation mode
+    pub rejected_frames: u32,
+    /// Frames kept with a clamped timestamp or cut-off NAL units
+    pub corrected_frames: u32,
+    /// Empty frames skipped (included in `dropped_frames`)
+    #[serde(default)]
+    pub empty_frames: u32,
+    /// Frames over the size limit skipped (included in `dropped_frames`)
+    #[serde(default)]
+    pub oversized_frames: u32,
+    /// Largest frame recorded, in bytes
+    #[serde(default)]
+    pub largest_frame: u32,
+    /// Frame size limit the recording ran with, in bytes
+    #[serde(default)]
+    pub max_frame_size: u32,
+    /// Periods in which a track received no frames, sorted; gaps of the
+    /// audio and video tracks that overlap are merged
+    pub gaps: Vec<MediaGap>,
+    /// Combined length of `gaps` in microseconds
+    pub total_gap_us: u64,
+}
+
+impl QualityReport {
+    /// Add a gap, merging it with the gaps it overlaps
+    pub fn add_gap(&mut self, gap: MediaGap) {
+        let index = self.gaps.partition_point(|g| g.end_us < gap.start_us);
+        let mut merged = gap;
+        while let Some(next) = self.gaps.get(index).filter(|g| g.start_us <= merged.end_us) {
+            merged.start_us = merged.start_us.min(next.start_us);
+            merged.end_us = merged.end_us.max(next.end_us);
+            self.gaps.remove(index);
+        }
+        self.gaps.insert(index, merged);
+        self.total_gap_us = self.gaps.iter().map(|g| g.end_us - g.start_us).sum();
+    }
+}
+
+/// Per-recording retention, set by the owner and honored by every purge
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionPolicy {
+    /// Keep the recording this long after it finished; the purger's default
+    /// applies if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    pub expire_after_ms: Option<u64>,
+    /// Never purge while set, whatever the expiry says
+    #[serde(default)]
+    #[tsify(optional)]
+    pub legal_hold: bool,
+}
+
+/// Whether a recording may be purged, as server GC and listings show it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionStatus {
+    pub legal_hold: bool,
+    /// Unix timestamp (ms) from which the recording may be purged; absent if
+    /// it is kept indefinitely
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    pub expires_at_ms: Option<u64>,
+    /// May be purged now: expired and not on hold
+    pub purgeable: bool,
+}
+
+impl RetentionPolicy {
+    /// Status at `now_ms` of a recording that finished at `finished_at_ms`
+    ///
+    /// `default_expire_after_ms` is the purger's own retention, used when the
+    /// policy sets none; without either the recording is kept indefinitely.
+    pub fn status(
+        &self,
+        default_expire_after_ms: Option<u64>,
+        finished_at_ms: u64,
+        now_ms: u64,
+    ) -> RetentionStatus {
+        let expires_at_ms = self
+            .expire_after_ms
+            .or(default_expire_after_ms)
+            .map(|expire_after| finished_at_ms.saturating_add(expire_after));
+        RetentionStatus {
+            legal_hold: self.legal_hold,
+            expires_at_ms,
+            purgeable: !self.legal_hold && expires_at_ms.is_some_and(|at| now_ms >= at),
+        }
+    }
+}
+
+/// A period without frames on the recording timeline (microseconds)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaGap {
+    pub start_us: u64,
+    pub end_us: u64,
+}
+
+/// What a recording file says about itself, as compact JSON in an iTunes
+/// freeform tag of its moov (see `MuxideConfig::session_metadata`)
+///
+/// Lets a file separated from its manifest still be traced back to its
+/// session and shown with its name and markers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddedMetadata {
+    pub session_id: SessionId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<RecordingMetadata>,
+    /// Markers in the manifest, internal ones included
+    pub marker_count: usize,
+    /// User-labeled markers, in file time
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub markers: Vec<EmbeddedMarker>,
+    /// Search index of the recording, when the recorder embeds it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<SessionIndex>,
+}
+
+/// A labeled marker of `EmbeddedMetadata`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddedMarker {
+    /// Microseconds from the start of the file
+    pub timestamp_us: u64,
+    pub label: String,
+}
+
+impl EmbeddedMetadata {
+    /// Summary of a session's manifest
+    pub fn from_manifest(manifest: &ChunkManifest) -> Self {
+        Self {
+            session_id: manifest.session_id.clone(),
+            metadata: manifest.metadata.clone(),
+            marker_count: manifest.markers.len(),
+            markers: marker_cues(manifest, None)
+                .into_iter()
+                .map(|cue| EmbeddedMarker {
+                    timestamp_us: cue.start_us,
+                    label: cue.text,
+                })
+                .collect(),
+            index: None,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string(self).map_err(|e| e.to_string())
+    }
+
+    /// Read back from an init segment or a whole file; None if it carries
+    /// no session metadata
+    pub fn read(file: &[u8]) -> Result<Option<Self>, String> {
+        read_itunes_tag(file, SESSION_METADATA_MEAN, SESSION_METADATA_NAME)?
+            .map(|json| {
+                serde_json::from_str(&json)
+                    .map_err(|e| format!("Invalid embedded session metadata: {}", e))
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_metadata_accepts_typescript_json() {
+        let json = r#"{
+            "displayName": "Interview",
+            "audioConfig": { "codec": "mp4a.40.2", "sampleRate": 48000, "channelCount": 2, "bitrate": 128000 },
+            "durationUs": 61000000,
+            "syncInfo": {
+                "scheduledStartTime": 1700000000000,
+                "actualStartTime": 1700000000012.5,
+                "clockOffsetMs": -3.25,
+                "clockOffsetAccuracyMs": 0.8,
+                "syncSampleCount": 8
+            }
+        }"#;
+
+        let metadata: RecordingMetadata = serde_json::from_str(json).unwrap();
+        assert_eq!(metadata.display_name.as_deref(), Some("Interview"));
+        assert_eq!(metadata.audio_config.as_ref().unwrap().sample_rate, 48000);
+        assert_eq!(metadata.sync_info.as_ref().unwrap().sync_sample_count, 8);
+        assert!(metadata.device_info.is_none());
+
+        let value = serde_json::to_value(&metadata).unwrap();
+        assert!(value.get("participantName").is_none());
+        assert_eq!(value["durationUs"], 61_000_000);
+    }
+
+    #[test]
+    fn test_retention_status() {
+        let metadata: RecordingMetadata =
+            serde_json::from_str(r#"{ "retention": { "expireAfterMs": 1000 } }"#).unwrap();
+        let status = |metadata: Option<&RecordingMetadata>, now_ms| {
+            RecordingMetadata::retention_status(metadata, Some(5000), 10_000, now_ms)
+        };
+
+        // The recording's own expiry wins over the default, either way
+        assert!(!status(Some(&metadata), 10_999).purgeable);
+        assert_eq!(status(Some(&metadata), 11_000).expires_at_ms, Some(11_000));
+        assert!(status(Some(&metadata), 11_000).purgeable);
+        assert_eq!(status(None, 11_000).expires_at_ms, Some(15_000));
+        assert!(!status(None, 11_000).purgeable);
+
+        // A legal hold keeps an expired recording
+        let held = RecordingMetadata {
+            retention: Some(RetentionPolicy {
+                legal_hold: true,
+                ..metadata.retention.unwrap()
+            }),
+            ..metadata
+        };
+        let status = status(Some(&held), u64::MAX);
+        assert!(status.legal_hold && !status.purgeable);
+        assert_eq!(
+            serde_json::to_value(status).unwrap(),
+            serde_json::json!({ "legalHold": true, "expiresAtMs": 11_000, "purgeable": false })
+        );
+        assert!(
+            !RetentionPolicy::default()
+                .status(None, 0, u64::MAX)
+                .purgeable
+        );
+    }
+}
@@ -5,27 +5,318 @@
 //!
 //! Supports both H.264 video and AAC audio tracks.
 
+use std::collections::VecDeque;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::chunk::{ChunkId, ChunkMetadata, RecordedChunk, TrackKind};
+use crate::clock::ClockHandle;
+use crate::compat::CompatChecker;
+use crate::fragment_checksum::build_checksum_box;
+use crate::latency::{LatencyReport, SegmentLatencyTracker};
+use crate::logging::{log_event, LogLevel};
+use crate::segment_sink::{BufferedSink, MediaSegment, SegmentSink};
+use crate::session::SessionId;
+use crate::sps::parse_sps_timing;
+use crate::telemetry;
+use crate::timebase::{Rounding, TickCarry, TimeBase};
+use crate::trace::{TraceRecord, TraceWriter};
+
 /// Configuration for the muxer
-#[derive(Debug, Clone)]
+///
+/// Also the JS-facing config object accepted by `MuxideMuxer.from_config`;
+/// omitted optional fields are treated as not configured.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
 pub struct MuxideConfig {
     // Video settings (optional - None for audio-only mode)
+    #[serde(default)]
+    #[tsify(optional)]
     pub video_width: Option<u32>,
+    #[serde(default)]
+    #[tsify(optional)]
     pub video_height: Option<u32>,
+    #[serde(default)]
+    #[tsify(optional)]
     pub video_timescale: Option<u32>,
+    #[serde(default = "default_fragment_duration_ms")]
+    #[tsify(optional)]
     pub fragment_duration_ms: u32,
     /// SPS NAL unit (without start code, required for H.264)
+    #[serde(default)]
+    #[tsify(optional, type = "Uint8Array | number[]")]
     pub sps: Option<Vec<u8>>,
     /// PPS NAL unit (without start code, required for H.264)
+    #[serde(default)]
+    #[tsify(optional, type = "Uint8Array | number[]")]
     pub pps: Option<Vec<u8>>,
 
     // Audio settings (optional)
+    #[serde(default)]
+    #[tsify(optional)]
     pub audio_sample_rate: Option<u32>,
+    #[serde(default)]
+    #[tsify(optional)]
     pub audio_channels: Option<u16>,
+    /// Audio track timescale, defaulting to the sample rate
+    ///
+    /// May differ from it, e.g. 90000 to share the video clock: frame
+    /// durations are converted without drift either way.
+    #[serde(default)]
+    #[tsify(optional)]
     pub audio_timescale: Option<u32>,
+    /// Movie (mvhd) timescale, defaulting to 1000
+    ///
+    /// Independent of the track timescales: movie-level durations such as
+    /// edit list segments are counted in it.
+    #[serde(default)]
+    #[tsify(optional)]
+    pub movie_timescale: Option<u32>,
     /// AudioSpecificConfig from WebCodecs (decoderConfig.description)
+    #[serde(default)]
+    #[tsify(optional, type = "Uint8Array | number[]")]
+    pub audio_specific_config: Option<Vec<u8>>,
+    /// Encoder delay at the start of the AAC stream, in samples
+    ///
+    /// Audio-only output then carries gapless playback metadata: an edit list
+    /// skipping the priming samples, and in `get_complete_file` an iTunes
+    /// `iTunSMPB` tag with the priming and remainder sample counts.
+    #[serde(default)]
+    #[tsify(optional)]
+    pub audio_priming_samples: Option<u32>,
+    /// How anomalies in pushed frames are handled
+    #[serde(default)]
+    #[tsify(optional)]
+    pub validation: ValidationMode,
+    /// What happens to audio recorded before the first video frame
+    #[serde(default)]
+    #[tsify(optional)]
+    pub start_alignment: StartAlignment,
+    /// Metadata-only dry run: frames go through all timing and fragmentation
+    /// logic, but their bytes are not kept. Segments hold the moof and an
+    /// mdat header declaring the full size, with no payload, so they are a
+    /// compact structural trace (see `trace_segment`) rather than playable
+    /// media.
+    #[serde(default)]
+    #[tsify(optional)]
+    pub dry_run: bool,
+    /// Check every segment against QuickTime compatibility rules (see
+    /// `CompatChecker`), logging violations. Always on in debug builds.
+    #[serde(default)]
+    #[tsify(optional)]
+    pub compat_checks: bool,
+    /// Keep the audio track on its timestamps: when the summed frame
+    /// durations drift from the PTS progression (encoders round frame
+    /// durations), nudge durations by one tick at a time until they agree
+    /// again (see `MuxerStats::audio_skew_correction_ticks`)
+    #[serde(default)]
+    #[tsify(optional)]
+    pub audio_skew_correction: bool,
+    /// Largest frame accepted, in bytes (default `DEFAULT_MAX_FRAME_SIZE`)
+    ///
+    /// Larger frames and empty ones come from broken encoders. Strict
+    /// validation rejects them with an error; the other modes skip them and
+    /// report a `SkippedFrame`. Neither ever reaches a trun.
+    #[serde(default)]
+    #[tsify(optional)]
+    pub max_frame_size: Option<u32>,
+    /// How much memory the muxer may hold on to
+    #[serde(default)]
+    #[tsify(optional)]
+    pub memory_profile: MemoryProfile,
+    /// What the trun data offsets of fragments count from
+    #[serde(default)]
+    #[tsify(optional)]
+    pub data_offset_mode: DataOffsetMode,
+    /// Four-character aux_info_type of per-sample auxiliary information
+    /// attached to video frames (`set_next_video_aux`), e.g. HDR dynamic
+    /// metadata or frame annotations
+    ///
+    /// Fragments whose frames carry auxiliary data store it in the mdat
+    /// after the samples and reference it from saiz/saio boxes of this type.
+    #[serde(default)]
+    #[tsify(optional)]
+    pub aux_info_type: Option<String>,
+    /// Human-readable name of the video track, e.g. "Screen" or "Camera"
+    ///
+    /// Written as the hdlr name and in a `udta/name` box of the trak, which
+    /// editors show instead of "Track 1".
+    #[serde(default)]
+    #[tsify(optional)]
+    pub video_track_name: Option<String>,
+    /// Human-readable name of the audio track, e.g. "Microphone"
+    #[serde(default)]
+    #[tsify(optional)]
+    pub audio_track_name: Option<String>,
+    /// JSON describing the session (see `EmbeddedMetadata`), written into
+    /// the moov as an iTunes freeform tag so the file still describes itself
+    /// without our manifest; set by the recorder when it stops
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    pub session_metadata: Option<String>,
+    /// Follow every fragment with a `uuid` box holding a BLAKE3 checksum of
+    /// its moof + mdat (see `fragment_checksum`), so corruption is found
+    /// even without the manifest
+    #[serde(default)]
+    #[tsify(optional)]
+    pub fragment_checksums: bool,
+    /// Bytes reserved for the moov in the init segment
+    ///
+    /// The moov is followed by a `free` box filling the rest, so a moov
+    /// rebuilt later (session metadata on stop, gapless counts) keeps the
+    /// init segment's size as long as it fits: sinks overwrite it in place
+    /// instead of rewriting the file, and absolute data offsets stay valid.
+    /// A moov that outgrows it is written unpadded.
+    #[serde(default)]
+    #[tsify(optional)]
+    pub moov_reserved_size: Option<u32>,
+    /// Audio configurations used before the current one, oldest first
+    ///
+    /// Filled by `change_audio_config`: the audio stsd lists them ahead of
+    /// the current one, which fragments then refer to with a tfhd
+    /// `sample_description_index`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[tsify(optional)]
+    pub previous_audio_configs: Vec<AudioSampleEntry>,
+}
+
+/// One AAC sample description (mp4a entry) of the audio track
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioSampleEntry {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// AudioSpecificConfig from WebCodecs, generated for AAC-LC if absent
+    #[serde(default)]
+    #[tsify(optional, type = "Uint8Array | number[]")]
     pub audio_specific_config: Option<Vec<u8>>,
 }
 
+impl AudioSampleEntry {
+    /// AudioSpecificConfig written to the esds box
+    fn audio_specific_config_or_default(&self) -> Vec<u8> {
+        self.audio_specific_config
+            .clone()
+            .unwrap_or_else(|| build_audio_specific_config(self.sample_rate, self.channels))
+    }
+
+    /// Whether both entries describe the same decoder configuration
+    fn same_as(&self, other: &AudioSampleEntry) -> bool {
+        self.sample_rate == other.sample_rate
+            && self.channels == other.channels
+            && self.audio_specific_config_or_default() == other.audio_specific_config_or_default()
+    }
+}
+
+/// How the muxer treats anomalies in its input
+///
+/// Anomalies are non-monotonic timestamps, video that does not start with a
+/// keyframe, and AVCC frames whose NAL length prefixes do not add up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationMode {
+    /// Reject the frame with an error (CI, tests)
+    Strict,
+    /// Log a warning and correct the frame: clamp the timestamp, drop frames
+    /// before the first keyframe, cut off incomplete NAL units
+    #[default]
+    Warn,
+    /// Accept the frame as is, only clamping timestamps so the file stays valid
+    Lenient,
+}
+
+/// How a session whose audio starts before its video is lined up
+///
+/// The first video frame (a keyframe) starts the first fragment, so audio
+/// pushed before it is buffered until then. Without alignment both tracks
+/// would start at decode time 0, and that audio would play too early.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "lowercase")]
+pub enum StartAlignment {
+    /// Drop the audio frames that start before the first video frame, also
+    /// when they arrive after it, so the file starts with the video. The
+    /// first frame kept starts at its own offset from the video, a fraction
+    /// of a frame in
+    #[default]
+    Trim,
+    /// Keep all audio and delay the video track by the audio lead: the file
+    /// starts with audio only
+    Delay,
+}
+
+/// How much memory a muxer (and the recorder around it) may hold on to
+///
+/// Chosen when the muxer is created, e.g. from `navigator.deviceMemory`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryProfile {
+    #[default]
+    Standard,
+    /// For low-end Chromebooks and Android tablets, where the WASM heap is
+    /// tight: fragments of at most `LOW_MEMORY_FRAGMENT_MS`, frames of at
+    /// most `LOW_MEMORY_MAX_FRAME_SIZE` bytes, a fragment cut early once its
+    /// samples hold `LOW_MEMORY_MAX_BUFFERED_BYTES`, and no complete file
+    /// assembled in memory; the recorder hands every chunk on as soon as it
+    /// is built, even in low-power mode
+    Low,
+}
+
+/// What trun data offsets count from (the tfhd base data offset)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "lowercase")]
+pub enum DataOffsetMode {
+    /// tfhd default-base-is-moof: the start of each moof
+    #[default]
+    Moof,
+    /// An explicit tfhd base_data_offset holding the moof's position in the
+    /// file, for older decoders that mishandle default-base-is-moof
+    ///
+    /// The position comes from `SegmentSink::segment_offset`. The init
+    /// segment must keep its size, so gapless audio metadata and
+    /// `change_audio_config` are not available, and session metadata only
+    /// within `MuxideConfig::moov_reserved_size`.
+    Absolute,
+}
+
+/// Frame rate assumed for video whose SPS has no VUI timing
+pub const DEFAULT_FRAME_RATE: u32 = 30;
+
+/// Longest fragment under the low-memory profile
+pub const LOW_MEMORY_FRAGMENT_MS: u32 = 1000;
+/// Largest frame accepted under the low-memory profile, in bytes
+pub const LOW_MEMORY_MAX_FRAME_SIZE: u32 = 2 * 1024 * 1024;
+/// Sample bytes a fragment may buffer under the low-memory profile before
+/// it is cut, whatever its duration
+pub const LOW_MEMORY_MAX_BUFFERED_BYTES: u64 = 4 * 1024 * 1024;
+
+fn default_fragment_duration_ms() -> u32 {
+    2000
+}
+
+/// Movie timescale used unless configured: milliseconds, which every tool
+/// reads correctly
+pub const DEFAULT_MOVIE_TIMESCALE: u32 = 1000;
+
+/// Frame size limit used unless configured: far above any real 4K keyframe
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Skipped frames kept for `take_skipped_frames`; older ones are dropped
+const MAX_SKIPPED_FRAMES: usize = 64;
+
+/// Audio skew left alone by `audio_skew_correction`, against timestamp jitter
+const AUDIO_SKEW_TOLERANCE_US: u64 = 1_000;
+/// Audio skew beyond which timestamps jumped (a gap, a restarted encoder)
+/// rather than drifted, and skew correction starts measuring afresh
+const AUDIO_SKEW_MAX_US: u64 = 100_000;
+
 impl MuxideConfig {
     /// Returns true if video track is configured
     pub fn has_video(&self) -> bool {
@@ -44,6 +335,140 @@ impl MuxideConfig {
     pub fn video_timescale_or_default(&self) -> u32 {
         self.video_timescale.unwrap_or(90000)
     }
+
+    /// Duration of one video frame in video timescale units, for frames
+    /// with no neighbour to derive it from
+    ///
+    /// Taken from the SPS's VUI timing when it has some, otherwise one frame
+    /// at `DEFAULT_FRAME_RATE`.
+    pub fn default_video_frame_duration(&self) -> u32 {
+        let timescale = self.video_timescale_or_default();
+        self.sps
+            .as_deref()
+            .and_then(|sps| parse_sps_timing(sps).ok().flatten())
+            .map_or(timescale / DEFAULT_FRAME_RATE, |timing| {
+                timing.frame_duration(timescale)
+            })
+            .max(1)
+    }
+
+    /// Get audio timescale, defaulting to the sample rate
+    pub fn audio_timescale_or_default(&self) -> u32 {
+        self.audio_timescale
+            .unwrap_or(self.audio_sample_rate.unwrap_or(48000))
+    }
+
+    /// Get movie timescale, defaulting to 1000
+    pub fn movie_timescale_or_default(&self) -> u32 {
+        self.movie_timescale.unwrap_or(DEFAULT_MOVIE_TIMESCALE)
+    }
+
+    /// Get the frame size limit, defaulting to `DEFAULT_MAX_FRAME_SIZE`
+    pub fn max_frame_size_or_default(&self) -> u32 {
+        let max_frame_size = self.max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE);
+        match self.memory_profile {
+            MemoryProfile::Standard => max_frame_size,
+            MemoryProfile::Low => max_frame_size.min(LOW_MEMORY_MAX_FRAME_SIZE),
+        }
+    }
+
+    /// Fragment duration the muxer aims for: `fragment_duration_ms`, capped
+    /// under the low-memory profile
+    pub fn target_fragment_duration_ms(&self) -> u32 {
+        match self.memory_profile {
+            MemoryProfile::Standard => self.fragment_duration_ms,
+            MemoryProfile::Low => self.fragment_duration_ms.min(LOW_MEMORY_FRAGMENT_MS),
+        }
+    }
+
+    /// Whether the low-memory profile is selected
+    pub fn low_memory(&self) -> bool {
+        self.memory_profile == MemoryProfile::Low
+    }
+
+    /// Current audio sample description
+    pub fn audio_sample_entry(&self) -> AudioSampleEntry {
+        AudioSampleEntry {
+            sample_rate: self.audio_sample_rate.unwrap_or(48000),
+            channels: self.audio_channels.unwrap_or(2),
+            audio_specific_config: self.audio_specific_config.clone(),
+        }
+    }
+
+    /// 1-based index of the current audio sample description in the stsd
+    pub fn audio_description_index(&self) -> u32 {
+        self.previous_audio_configs.len() as u32 + 1
+    }
+
+    /// The configured `aux_info_type` as a four-character code, if valid
+    pub(crate) fn aux_info_fourcc(&self) -> Option<[u8; 4]> {
+        self.aux_info_type
+            .as_deref()
+            .and_then(|t| parse_fourcc(t).ok())
+    }
+
+    /// Returns true if gapless metadata is written (audio-only with priming set)
+    pub fn has_gapless_audio(&self) -> bool {
+        self.has_audio() && !self.has_video() && self.audio_priming_samples.is_some()
+    }
+
+    /// Clock of the video track
+    pub(crate) fn video_time_base(&self) -> TimeBase {
+        TimeBase::new(self.video_timescale_or_default() as u64)
+    }
+
+    /// Clock of the audio track
+    pub(crate) fn audio_time_base(&self) -> TimeBase {
+        TimeBase::new(self.audio_timescale_or_default() as u64)
+    }
+
+    /// Clock of movie-level durations
+    pub(crate) fn movie_time_base(&self) -> TimeBase {
+        TimeBase::new(self.movie_timescale_or_default() as u64)
+    }
+
+    /// Clock counting audio samples
+    fn audio_sample_time_base(&self) -> TimeBase {
+        TimeBase::new(self.audio_sample_rate.unwrap_or(48000) as u64)
+    }
+
+    /// Convert a count of audio samples to audio timescale ticks
+    fn audio_samples_to_ticks(&self, samples: u64) -> u64 {
+        self.audio_sample_time_base()
+            .convert(samples, self.audio_time_base(), Rounding::Floor)
+    }
+
+    /// Convert audio timescale ticks to a count of audio samples
+    fn audio_ticks_to_samples(&self, ticks: u64) -> u64 {
+        self.audio_time_base()
+            .convert(ticks, self.audio_sample_time_base(), Rounding::Floor)
+    }
+}
+
+/// Gapless playback sample counts of an audio-only recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct GaplessInfo {
+    /// Encoder delay samples before the audio starts
+    pub priming_samples: u32,
+    /// Padding samples after the audio ends
+    pub remainder_samples: u32,
+    /// Samples of actual audio
+    pub valid_samples: u64,
+}
+
+impl GaplessInfo {
+    /// Value of the iTunes `iTunSMPB` tag
+    pub fn itunsmpb(&self) -> String {
+        format!(
+            " 00000000 {:08X} {:08X} {:016X}{}",
+            self.priming_samples,
+            self.remainder_samples,
+            self.valid_samples,
+            " 00000000".repeat(8)
+        )
+    }
 }
 
 impl Default for MuxideConfig {
@@ -58,7 +483,108 @@ impl Default for MuxideConfig {
             audio_sample_rate: None,
             audio_channels: None,
             audio_timescale: None,
+            movie_timescale: None,
             audio_specific_config: None,
+            audio_priming_samples: None,
+            validation: ValidationMode::default(),
+            start_alignment: StartAlignment::default(),
+            dry_run: false,
+            compat_checks: false,
+            audio_skew_correction: false,
+            max_frame_size: None,
+            memory_profile: MemoryProfile::default(),
+            data_offset_mode: DataOffsetMode::default(),
+            aux_info_type: None,
+            video_track_name: None,
+            audio_track_name: None,
+            session_metadata: None,
+            fragment_checksums: false,
+            moov_reserved_size: None,
+            previous_audio_configs: Vec::new(),
+        }
+    }
+}
+
+/// `push_video_chunk_full` flag: the frame is a keyframe (sync sample)
+pub const FRAME_FLAG_KEYFRAME: u32 = 0x1;
+
+/// Sample flags written to the trun for a video sample (ISO/IEC 14496-12
+/// 8.8.3.1)
+///
+/// The two-bit fields take 0 (unknown), 1 (yes) or 2 (no), matching the
+/// spec: `depends_on` 2 means the sample is an I-frame, `is_depended_on` 2
+/// that no other sample references it (disposable). All zero is a sync
+/// sample with unknown dependencies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SampleFlags {
+    pub is_leading: u8,
+    pub depends_on: u8,
+    pub is_depended_on: u8,
+    pub has_redundancy: u8,
+    /// Not a sync sample (not a keyframe)
+    pub is_non_sync: bool,
+    pub degradation_priority: u16,
+}
+
+impl SampleFlags {
+    /// What `push_video_chunk` writes for a keyframe: depends on nothing
+    pub fn keyframe() -> Self {
+        Self {
+            depends_on: 2,
+            ..Default::default()
+        }
+    }
+
+    /// What `push_video_chunk` writes for other frames: depends on others
+    pub fn delta() -> Self {
+        Self {
+            depends_on: 1,
+            is_non_sync: true,
+            ..Default::default()
+        }
+    }
+
+    /// Whether this is a sync sample
+    pub fn is_sync(&self) -> bool {
+        !self.is_non_sync
+    }
+
+    /// Check that the two-bit fields fit
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("isLeading", self.is_leading),
+            ("dependsOn", self.depends_on),
+            ("isDependedOn", self.is_depended_on),
+            ("hasRedundancy", self.has_redundancy),
+        ] {
+            if value > 3 {
+                return Err(format!("Sample flag {} must be 0-3, got {}", name, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// The 32-bit trun encoding
+    pub fn to_bits(&self) -> u32 {
+        (self.is_leading as u32 & 3) << 26
+            | (self.depends_on as u32 & 3) << 24
+            | (self.is_depended_on as u32 & 3) << 22
+            | (self.has_redundancy as u32 & 3) << 20
+            | (self.is_non_sync as u32) << 16
+            | self.degradation_priority as u32
+    }
+
+    /// Decode the 32-bit trun encoding
+    pub fn from_bits(bits: u32) -> Self {
+        Self {
+            is_leading: (bits >> 26 & 3) as u8,
+            depends_on: (bits >> 24 & 3) as u8,
+            is_depended_on: (bits >> 22 & 3) as u8,
+            has_redundancy: (bits >> 20 & 3) as u8,
+            is_non_sync: bits & 0x0001_0000 != 0,
+            degradation_priority: bits as u16,
         }
     }
 }
@@ -70,10 +596,19 @@ struct VideoSample {
     pts: u64,
     /// Decode timestamp in timescale units
     dts: u64,
-    /// Sample data (AVCC format)
+    /// Sample data (AVCC format), empty in dry-run mode
     data: Vec<u8>,
-    /// Whether this is a sync sample (keyframe)
-    is_sync: bool,
+    /// Sample size in bytes
+    size: u32,
+    /// trun sample flags, including whether this is a sync sample (keyframe)
+    flags: SampleFlags,
+    /// Duration in timescale units given by the caller, which takes
+    /// precedence over the delta to the next sample (see `video_durations`)
+    duration: Option<u32>,
+    /// Auxiliary information (saiz/saio), empty in dry-run mode
+    aux: Vec<u8>,
+    /// Auxiliary information size in bytes, 0 if none
+    aux_size: u8,
 }
 
 /// Audio sample information
@@ -82,23 +617,111 @@ struct AudioSample {
     /// Presentation timestamp in timescale units
     #[allow(dead_code)] // May be used for future per-sample audio PTS adjustments
     pts: u64,
-    /// Sample data (raw AAC frame, no ADTS header)
+    /// Sample data (raw AAC frame, no ADTS header), empty in dry-run mode
     data: Vec<u8>,
+    /// Sample size in bytes
+    size: u32,
     /// Duration in timescale units
     duration: u32,
 }
 
+/// Why a frame was not muxed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub enum FrameIssue {
+    /// No sample data
+    Empty,
+    /// Larger than `MuxideConfig.max_frame_size`
+    Oversized,
+}
+
+impl FrameIssue {
+    /// Error code counted in telemetry when strict mode rejects the frame
+    pub fn code(&self) -> &'static str {
+        match self {
+            FrameIssue::Empty => "empty_frame",
+            FrameIssue::Oversized => "frame_too_large",
+        }
+    }
+}
+
+/// A frame skipped because of a `FrameIssue` (warn and lenient modes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedFrame {
+    pub track: TrackKind,
+    /// Timestamp the frame was pushed with, in microseconds
+    pub timestamp_us: u64,
+    /// Size in bytes
+    pub size: u32,
+    pub issue: FrameIssue,
+}
+
+/// Snapshot of muxer counters, returned to JS by `MuxideMuxer.get_stats`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct MuxerStats {
+    pub video_frame_count: u32,
+    pub audio_frame_count: u32,
+    /// Media segments (moof + mdat) produced so far
+    pub segment_count: u32,
+    /// Total bytes of media segments produced so far (init segment excluded)
+    pub segment_bytes: u64,
+    /// Segments produced but not yet taken with `get_pending_segments`
+    pub pending_segment_count: u32,
+    /// Samples buffered for the segment currently being built
+    pub buffered_video_samples: u32,
+    pub buffered_audio_samples: u32,
+    /// Input anomalies corrected or ignored (see `ValidationMode`)
+    pub anomaly_count: u32,
+    /// Frames refused with an error in strict mode
+    pub rejected_frames: u32,
+    /// Frames dropped in warn mode (video before the first keyframe, frames
+    /// without a complete NAL unit)
+    pub dropped_frames: u32,
+    /// Frames kept with a clamped timestamp or cut-off NAL units
+    pub corrected_frames: u32,
+    /// Segments breaking QuickTime compatibility rules (see `compat_checks`)
+    pub compat_violations: u32,
+    /// Empty frames skipped (also counted in `dropped_frames`)
+    pub empty_frames: u32,
+    /// Frames over `max_frame_size` skipped (also counted in `dropped_frames`)
+    pub oversized_frames: u32,
+    /// Largest frame muxed so far, in bytes
+    pub largest_frame: u32,
+    /// Frame size limit in effect
+    pub max_frame_size: u32,
+    /// Audio ticks added (positive) or removed by `audio_skew_correction`
+    pub audio_skew_correction_ticks: i64,
+}
+
 /// State machine for fMP4 muxing with video and audio support
-pub struct MuxideMuxerState {
+///
+/// Finished segments go to the `SegmentSink` `S`; with the default
+/// `BufferedSink` they wait in memory for `get_pending_segments` /
+/// `take_pending_chunks`.
+pub struct MuxideMuxerState<S: SegmentSink = BufferedSink> {
     config: MuxideConfig,
     initialized: bool,
     init_segment: Vec<u8>,
-    pending_segments: Vec<Vec<u8>>,
+    sink: S,
     pub video_frame_count: u32,
     pub audio_frame_count: u32,
+    segment_count: u32,
+    segment_bytes: u64,
+
+    // Segment being built
+    segment_start_us: Option<u64>,
+    segment_has_keyframe: bool,
 
     // Video state
     video_samples: Vec<VideoSample>,
+    /// Duration of a lone frame (see `default_video_frame_duration`)
+    default_video_duration: u32,
+    /// Auxiliary information for the next video frame pushed
+    pending_video_aux: Option<Vec<u8>>,
     video_sequence_number: u32,
     video_base_media_decode_time: u64,
 
@@ -107,24 +730,106 @@ pub struct MuxideMuxerState {
     #[allow(dead_code)] // May be used for future multi-segment audio sync
     audio_sequence_number: u32,
     audio_base_media_decode_time: u64,
+    /// Video start in audio ticks, until the first audio frame after it is
+    /// kept (see `align_session_start`)
+    audio_start: Option<u64>,
+    /// Audio length set by the caller for gapless metadata
+    audio_valid_samples: Option<u64>,
+
+    // Input validation state
+    last_video_dts: Option<u64>,
+    last_audio_pts: Option<u64>,
+    anomaly_count: u32,
+    rejected_frames: u32,
+    dropped_frames: u32,
+    corrected_frames: u32,
+    empty_frames: u32,
+    oversized_frames: u32,
+    largest_frame: u32,
+    /// Frames skipped since the last `take_skipped_frames`, newest last
+    skipped: Vec<SkippedFrame>,
+
+    /// Converts audio durations, carrying rounding errors (see
+    /// `audio_duration_ticks`)
+    audio_ticks: TickCarry,
+    /// Converts caller-given video durations, carrying rounding errors
+    video_ticks: TickCarry,
+    /// PTS of the audio frame skew is measured from, and the track duration
+    /// muxed since (see `correct_audio_skew`)
+    audio_skew_anchor: Option<u64>,
+    audio_skew_elapsed: u64,
+    /// Ticks added to (or removed from) audio durations against skew
+    audio_skew_correction: i64,
+
+    /// Bytes held in buffered samples, for telemetry (the sink reports its own)
+    sample_bytes: u64,
+
+    /// Replayable log of calls, when enabled (not part of the serialized state)
+    trace: Option<TraceWriter>,
+
+    /// Compatibility checks on produced segments, when enabled
+    compat: Option<CompatChecker>,
+    compat_violations: u32,
+
+    /// Time source of chunk creation times and segment latencies
+    clock: ClockHandle,
+    /// Emit and ack times of segments (not part of the serialized state)
+    latency: SegmentLatencyTracker,
 }
 
-impl MuxideMuxerState {
-    /// Create a new MuxideMuxerState with the given configuration
-    pub fn new(config: MuxideConfig) -> Self {
+impl<S: SegmentSink> MuxideMuxerState<S> {
+    /// Create a muxer that writes its output to `sink`
+    pub fn with_sink(config: MuxideConfig, sink: S) -> Self {
+        let compat = (cfg!(debug_assertions) || config.compat_checks)
+            .then(|| CompatChecker::new(config.has_video().then_some(1)));
+        let audio_ticks = TickCarry::new(
+            TimeBase::new(config.audio_sample_time_base().rate() * 1_000_000),
+            config.audio_time_base(),
+        );
+        let video_ticks = TickCarry::new(TimeBase::MICROS, config.video_time_base());
+        let default_video_duration = config.default_video_frame_duration();
         Self {
             config,
             initialized: false,
             init_segment: Vec::new(),
-            pending_segments: Vec::new(),
+            sink,
             video_frame_count: 0,
             audio_frame_count: 0,
+            segment_count: 0,
+            segment_bytes: 0,
+            segment_start_us: None,
+            segment_has_keyframe: false,
             video_samples: Vec::new(),
+            default_video_duration,
+            pending_video_aux: None,
             video_sequence_number: 1,
             video_base_media_decode_time: 0,
             audio_samples: Vec::new(),
             audio_sequence_number: 1,
             audio_base_media_decode_time: 0,
+            audio_start: None,
+            audio_valid_samples: None,
+            last_video_dts: None,
+            last_audio_pts: None,
+            anomaly_count: 0,
+            rejected_frames: 0,
+            dropped_frames: 0,
+            corrected_frames: 0,
+            empty_frames: 0,
+            oversized_frames: 0,
+            largest_frame: 0,
+            skipped: Vec::new(),
+            audio_ticks,
+            video_ticks,
+            audio_skew_anchor: None,
+            audio_skew_elapsed: 0,
+            audio_skew_correction: 0,
+            sample_bytes: 0,
+            trace: None,
+            compat,
+            compat_violations: 0,
+            clock: ClockHandle::default(),
+            latency: SegmentLatencyTracker::default(),
         }
     }
 
@@ -138,38 +843,211 @@ impl MuxideMuxerState {
         self.config.has_video()
     }
 
-    /// Initialize the muxer and generate fMP4 header (ftyp + moov)
-    pub fn init(&mut self) -> Result<(), String> {
-        if self.initialized {
-            return Err("Muxer already initialized".to_string());
+    /// Configuration this muxer was created with
+    pub fn config(&self) -> &MuxideConfig {
+        &self.config
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn set_clock(&mut self, clock: ClockHandle) {
+        self.clock = clock;
+    }
+
+    /// Output destination
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Output destination, e.g. to take buffered segments
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Give up the muxer, keeping its output destination
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+
+    /// Change how input anomalies are handled
+    pub fn set_validation_mode(&mut self, mode: ValidationMode) {
+        self.config.validation = mode;
+    }
+
+    /// Change the fragment duration, starting with the fragment being built
+    pub fn set_fragment_duration_ms(&mut self, fragment_duration_ms: u32) {
+        self.record(TraceRecord::FragmentDuration(fragment_duration_ms));
+        self.config.fragment_duration_ms = fragment_duration_ms.max(1);
+    }
+
+    /// Switch the audio track to a new encoder configuration, e.g. after the
+    /// input device changed from 48 kHz to 44.1 kHz
+    ///
+    /// Frames pushed so far are flushed as a segment first. The new
+    /// configuration is then added to the track's sample descriptions and
+    /// the init segment is rebuilt and written to the sink again: it still
+    /// describes every earlier segment, and later ones refer to the new
+    /// description. The track timescale stays as it was (the first sample
+    /// rate unless `audio_timescale` is set), and frame durations are
+    /// converted from the new rate, so timestamps stay continuous.
+    ///
+    /// Returns false, changing nothing, if the configuration is the current
+    /// one. Not supported with gapless metadata, whose sample counts assume
+    /// a single rate.
+    pub fn change_audio_config(
+        &mut self,
+        sample_rate: u32,
+        channels: u16,
+        audio_specific_config: Option<Vec<u8>>,
+    ) -> Result<bool, String> {
+        if !self.initialized {
+            return Err(telemetry::error("not_initialized", "Muxer not initialized"));
+        }
+        if !self.has_audio() {
+            return Err(telemetry::error(
+                "track_not_configured",
+                "Audio not configured",
+            ));
+        }
+        if sample_rate == 0 || channels == 0 {
+            return Err(telemetry::error(
+                "invalid_input",
+                format!(
+                    "Invalid audio config: {} Hz, {} channels",
+                    sample_rate, channels
+                ),
+            ));
+        }
+        let entry = AudioSampleEntry {
+            sample_rate,
+            channels,
+            audio_specific_config,
+        };
+        let current = self.config.audio_sample_entry();
+        if entry.same_as(&current) {
+            return Ok(false);
+        }
+        if self.config.has_gapless_audio() {
+            return Err(telemetry::error(
+                "unsupported",
+                "Audio config cannot change in a recording with gapless metadata",
+            ));
+        }
+        if self.config.data_offset_mode == DataOffsetMode::Absolute {
+            return Err(telemetry::error(
+                "unsupported",
+                "Audio config cannot change in a recording with absolute data offsets",
+            ));
+        }
+        self.record(TraceRecord::AudioConfig {
+            sample_rate,
+            channels,
+        });
+        self.flush_segments()?;
+
+        let mut config = self.config.clone();
+        config.audio_timescale = Some(config.audio_timescale_or_default());
+        config.previous_audio_configs.push(current.clone());
+        config.audio_sample_rate = Some(sample_rate);
+        config.audio_channels = Some(channels);
+        config.audio_specific_config = entry.audio_specific_config;
+        let init_segment = build_init_segment(&config, None)?;
+        // Only commit once the sink has it, so a failed write changes nothing
+        self.sink
+            .write_init(&init_segment)
+            .map_err(|e| telemetry::error("sink_failed", e))?;
+
+        log_event!(
+            LogLevel::Info,
+            "Audio config changed",
+            from_sample_rate = current.sample_rate,
+            from_channels = current.channels,
+            sample_rate = sample_rate,
+            channels = channels,
+        );
+        self.config = config;
+        self.init_segment = init_segment;
+        self.audio_ticks = TickCarry::new(
+            TimeBase::new(self.config.audio_sample_time_base().rate() * 1_000_000),
+            self.config.audio_time_base(),
+        );
+        Ok(true)
+    }
+
+    /// Embed `metadata` (see `MuxideConfig::session_metadata`) in the moov
+    ///
+    /// The init segment is rebuilt and written to the sink again; segments
+    /// are unchanged. Returns false, changing nothing, if it is already the
+    /// embedded value. With absolute data offsets, which a longer init
+    /// segment would invalidate, the new one must keep its size (see
+    /// `MuxideConfig::moov_reserved_size`).
+    pub fn set_session_metadata(&mut self, metadata: Option<String>) -> Result<bool, String> {
+        if !self.initialized {
+            return Err(telemetry::error("not_initialized", "Muxer not initialized"));
+        }
+        if self.config.session_metadata == metadata {
+            return Ok(false);
+        }
+        let config = MuxideConfig {
+            session_metadata: metadata,
+            ..self.config.clone()
+        };
+        let init_segment = build_init_segment(&config, None)?;
+        if self.config.data_offset_mode == DataOffsetMode::Absolute
+            && init_segment.len() != self.init_segment.len()
+        {
+            return Err(telemetry::error(
+                "unsupported",
+                "Session metadata must fit the reserved moov space with absolute data offsets",
+            ));
         }
+        // Only commit once the sink has it, so a failed write changes nothing
+        self.sink
+            .write_init(&init_segment)
+            .map_err(|e| telemetry::error("sink_failed", e))?;
+        self.config = config;
+        self.init_segment = init_segment;
+        Ok(true)
+    }
 
-        let has_video = self.config.has_video();
-        let has_audio = self.config.has_audio();
+    /// Start logging calls to a trace that `replay_trace` can re-drive
+    ///
+    /// The trace holds the current config and, for each later push, flush and
+    /// fragment duration change, the timestamps, sizes and flags but no media.
+    pub fn enable_trace(&mut self) -> Result<(), String> {
+        self.trace = Some(TraceWriter::new(&self.config)?);
+        Ok(())
+    }
+
+    /// Trace recorded since `enable_trace`, if enabled
+    pub fn trace(&self) -> Option<&[u8]> {
+        self.trace.as_ref().map(TraceWriter::as_bytes)
+    }
 
-        if !has_video && !has_audio {
-            return Err("At least one track (video or audio) must be configured".to_string());
+    fn record(&mut self, record: TraceRecord) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(record);
         }
+    }
 
-        if has_video {
-            let sps = self.config.sps.as_ref().unwrap();
-            let pps = self.config.pps.as_ref().unwrap();
-            if sps.is_empty() || pps.is_empty() {
-                return Err("SPS and PPS are required for video initialization".to_string());
-            }
+    /// Initialize the muxer and generate fMP4 header (ftyp + moov)
+    pub fn init(&mut self) -> Result<(), String> {
+        if self.initialized {
+            return Err(telemetry::error(
+                "invalid_state",
+                "Muxer already initialized",
+            ));
         }
 
         // Build init segment with video and/or audio
-        self.init_segment = build_init_segment(&self.config);
+        self.init_segment = build_init_segment(&self.config, None)?;
         self.initialized = true;
-
-        Ok(())
+        self.sink.write_init(&self.init_segment)
     }
 
     /// Get the initialization segment (ftyp + moov)
     pub fn get_init_segment(&self) -> Result<Vec<u8>, String> {
         if !self.initialized {
-            return Err("Muxer not initialized".to_string());
+            return Err(telemetry::error("not_initialized", "Muxer not initialized"));
         }
         Ok(self.init_segment.clone())
     }
@@ -186,31 +1064,218 @@ impl MuxideMuxerState {
         timestamp: u64,
         is_keyframe: bool,
     ) -> Result<(), String> {
+        self.check_video_ready()?;
+        let valid_len = avcc_valid_len(data);
+        self.record(TraceRecord::Video {
+            timestamp_us: timestamp,
+            size: data.len() as u32,
+            valid_len: valid_len as u32,
+            is_keyframe,
+        });
+        let flags = if is_keyframe {
+            SampleFlags::keyframe()
+        } else {
+            SampleFlags::delta()
+        };
+        self.push_video(data, valid_len, timestamp, timestamp, None, flags)
+    }
+
+    /// Add a video chunk with explicit decode timestamp and duration
+    ///
+    /// # Arguments
+    /// * `data` - Video frame data in AVCC format (4-byte length prefixed NAL units)
+    /// * `pts` - Presentation timestamp in microseconds
+    /// * `dts` - Decode timestamp in microseconds (equal to `pts` without B-frames)
+    /// * `duration` - Frame duration in microseconds (WebCodecs `chunk.duration`)
+    /// * `flags` - `FRAME_FLAG_*` bits; unknown bits are ignored
+    ///
+    /// A duration given here is what the fragment records for the frame,
+    /// converted without drift across frames. Without one (None or 0), the
+    /// duration is inferred as with `push_video_chunk`: the DTS delta to the
+    /// next frame, or to the previous one for the last frame of a fragment.
+    pub fn push_video_chunk_full(
+        &mut self,
+        data: &[u8],
+        pts: u64,
+        dts: u64,
+        duration: Option<u32>,
+        flags: u32,
+    ) -> Result<(), String> {
+        self.check_video_ready()?;
+        let valid_len = avcc_valid_len(data);
+        let duration = duration.filter(|&d| d > 0);
+        self.record(TraceRecord::VideoFull {
+            pts_us: pts,
+            dts_us: dts,
+            duration_us: duration.unwrap_or(0),
+            size: data.len() as u32,
+            valid_len: valid_len as u32,
+            flags,
+        });
+        let flags = if flags & FRAME_FLAG_KEYFRAME != 0 {
+            SampleFlags::keyframe()
+        } else {
+            SampleFlags::delta()
+        };
+        self.push_video(data, valid_len, pts, dts, duration, flags)
+    }
+
+    /// Add a video chunk with explicit timestamps, duration and sample flags
+    ///
+    /// Like `push_video_chunk_full`, for streams analyzed upstream: `flags`
+    /// are written to the trun as given instead of being derived from a
+    /// keyframe bit. Whether the frame is a keyframe comes from
+    /// `flags.is_non_sync`.
+    pub fn push_video_chunk_with_flags(
+        &mut self,
+        data: &[u8],
+        pts: u64,
+        dts: u64,
+        duration: Option<u32>,
+        flags: SampleFlags,
+    ) -> Result<(), String> {
+        self.check_video_ready()?;
+        flags
+            .validate()
+            .map_err(|e| telemetry::error("invalid_input", e))?;
+        let valid_len = avcc_valid_len(data);
+        let duration = duration.filter(|&d| d > 0);
+        self.record(TraceRecord::VideoWithFlags {
+            pts_us: pts,
+            dts_us: dts,
+            duration_us: duration.unwrap_or(0),
+            size: data.len() as u32,
+            valid_len: valid_len as u32,
+            sample_flags: flags.to_bits(),
+        });
+        self.push_video(data, valid_len, pts, dts, duration, flags)
+    }
+
+    /// Attach auxiliary information to the next video frame pushed
+    ///
+    /// The bytes (at most 255, the largest size a saiz box records) are
+    /// referenced from the fragment with saiz/saio boxes of the configured
+    /// `aux_info_type`. They are discarded with the frame if it is skipped.
+    pub fn set_next_video_aux(&mut self, aux: &[u8]) -> Result<(), String> {
+        self.check_video_ready()?;
+        if self.config.aux_info_fourcc().is_none() {
+            return Err(telemetry::error(
+                "invalid_config",
+                "Auxiliary information needs an auxInfoType",
+            ));
+        }
+        if aux.len() > u8::MAX as usize {
+            return Err(telemetry::error(
+                "invalid_input",
+                format!(
+                    "Auxiliary information of {} bytes exceeds {}",
+                    aux.len(),
+                    u8::MAX
+                ),
+            ));
+        }
+        self.record(TraceRecord::VideoAux {
+            size: aux.len() as u32,
+        });
+        self.pending_video_aux = Some(aux.to_vec());
+        Ok(())
+    }
+
+    fn check_video_ready(&self) -> Result<(), String> {
         if !self.initialized {
-            return Err("Muxer not initialized".to_string());
+            return Err(telemetry::error("not_initialized", "Muxer not initialized"));
         }
 
         if !self.has_video() {
-            return Err("Video not supported in audio-only mode".to_string());
+            return Err(telemetry::error(
+                "track_not_configured",
+                "Video not supported in audio-only mode",
+            ));
         }
+        Ok(())
+    }
 
-        // Convert timestamp from microseconds to timescale units
-        let video_timescale = self.config.video_timescale_or_default();
-        let pts = (timestamp * video_timescale as u64) / 1_000_000;
-        let dts = pts; // No B-frames, so PTS == DTS
+    /// Validate and buffer a video frame (timestamps in microseconds)
+    fn push_video(
+        &mut self,
+        data: &[u8],
+        valid_len: usize,
+        pts_us: u64,
+        dts_us: u64,
+        duration_us: Option<u32>,
+        flags: SampleFlags,
+    ) -> Result<(), String> {
+        let timestamp = pts_us;
+        let aux = self.pending_video_aux.take().unwrap_or_default();
+        if self.skip_frame(TrackKind::Video, timestamp, data.len())? {
+            return Ok(());
+        }
+        let is_keyframe = flags.is_sync();
+        if self.video_frame_count == 0 && !is_keyframe {
+            self.anomaly(format!(
+                "Video starts with a non-keyframe at {} us",
+                timestamp
+            ))?;
+            if self.config.validation == ValidationMode::Warn {
+                self.dropped_frames += 1;
+                return Ok(());
+            }
+        }
+
+        let mut data = data;
+        if valid_len != data.len() {
+            self.anomaly(format!(
+                "Suspicious NAL sizes in video frame at {} us: {} of {} bytes are complete NAL units",
+                timestamp,
+                valid_len,
+                data.len()
+            ))?;
+            if self.config.validation == ValidationMode::Warn {
+                if valid_len == 0 {
+                    self.dropped_frames += 1;
+                    return Ok(());
+                }
+                self.corrected_frames += 1;
+                data = &data[..valid_len];
+            }
+        }
+
+        if self.video_frame_count == 0 {
+            self.align_session_start(dts_us);
+        }
+
+        // Convert timestamps from microseconds to timescale units
+        let time_base = self.config.video_time_base();
+        let mut pts = time_base.from_us(pts_us, Rounding::Floor);
+        let mut dts = time_base.from_us(dts_us, Rounding::Floor);
+        if let Some(last) = self.last_video_dts.filter(|&last| dts <= last) {
+            self.anomaly(format!(
+                "Non-monotonic video timestamp {} us ({} ticks, previous {})",
+                dts_us, dts, last
+            ))?;
+            pts += last + 1 - dts;
+            dts = last + 1;
+            self.corrected_frames += 1;
+        }
+        self.last_video_dts = Some(dts);
+        let duration = duration_us.map(|d| self.video_ticks.push(d as u64) as u32);
 
         self.video_samples.push(VideoSample {
             pts,
             dts,
-            data: data.to_vec(),
-            is_sync: is_keyframe,
+            data: self.sample_data(data),
+            size: data.len() as u32,
+            flags,
+            duration,
+            aux: self.sample_data(&aux),
+            aux_size: aux.len() as u8,
         });
         self.video_frame_count += 1;
+        self.note_frame_in(data.len());
+        self.note_sample(timestamp, is_keyframe);
 
         // Check if we have enough samples to flush
-        self.check_and_flush_segments();
-
-        Ok(())
+        self.check_and_flush_segments()
     }
 
     /// Add an audio chunk
@@ -226,95 +1291,361 @@ impl MuxideMuxerState {
         duration: u32,
     ) -> Result<(), String> {
         if !self.initialized {
-            return Err("Muxer not initialized".to_string());
+            return Err(telemetry::error("not_initialized", "Muxer not initialized"));
         }
 
         if !self.has_audio() {
-            return Err("Audio not configured".to_string());
+            return Err(telemetry::error(
+                "track_not_configured",
+                "Audio not configured",
+            ));
         }
 
-        let audio_timescale = self
-            .config
-            .audio_timescale
-            .unwrap_or(self.config.audio_sample_rate.unwrap_or(48000));
+        self.record(TraceRecord::Audio {
+            timestamp_us: timestamp,
+            duration_us: duration,
+            size: data.len() as u32,
+        });
+        if self.skip_frame(TrackKind::Audio, timestamp, data.len())? {
+            return Ok(());
+        }
 
         // Convert timestamp from microseconds to timescale units
-        let pts = (timestamp * audio_timescale as u64) / 1_000_000;
-        // Use rounding instead of truncation to avoid cumulative drift.
-        // e.g. 21333µs * 48000 / 1_000_000 = 1023.984 → truncated to 1023, but should be 1024.
-        // Over 20000+ frames, 1-tick loss per frame accumulates to ~0.3s of A/V desync.
-        let duration_ts = ((duration as u64 * audio_timescale as u64 + 500_000) / 1_000_000) as u32;
+        let mut pts = self
+            .config
+            .audio_time_base()
+            .from_us(timestamp, Rounding::Floor);
+        if self.audio_start.is_some_and(|start| pts < start) {
+            log_event!(
+                LogLevel::Debug,
+                "Trimmed audio recorded before the first video frame",
+                timestamp_us = timestamp,
+            );
+            return Ok(());
+        }
+        if let Some(last) = self.last_audio_pts.filter(|&last| pts <= last) {
+            self.anomaly(format!(
+                "Non-monotonic audio timestamp {} us ({} ticks, previous {})",
+                timestamp, pts, last
+            ))?;
+            pts = last + 1;
+            self.corrected_frames += 1;
+        }
+        self.last_audio_pts = Some(pts);
+        let duration_ts = self.audio_duration_ticks(duration);
+        let duration_ts = self.correct_audio_skew(pts, duration_ts);
 
         self.audio_samples.push(AudioSample {
             pts,
-            data: data.to_vec(),
+            data: self.sample_data(data),
+            size: data.len() as u32,
             duration: duration_ts,
         });
         self.audio_frame_count += 1;
+        self.place_first_audio();
+        self.note_frame_in(data.len());
+        self.note_sample(timestamp, false);
 
         // In audio-only mode, audio drives segment flushing
         if !self.has_video() {
-            self.check_and_flush_segments();
+            self.check_and_flush_segments()?;
         }
 
         Ok(())
     }
 
-    /// Check if we should flush segments based on video or audio duration
-    fn check_and_flush_segments(&mut self) {
-        if self.has_video() {
-            // Video-based flush: check video sample duration
-            if self.video_samples.len() < 2 {
-                return;
-            }
+    /// Line up the audio track with the first video frame, which starts at
+    /// `video_start_us`, according to `config.start_alignment`
+    ///
+    /// Only `Delay` with audio buffered from before the video keeps that
+    /// audio. Otherwise the file starts exactly at the video start: audio
+    /// from before it is trimmed, now or as it arrives (encoders often
+    /// deliver audio late), and the first audio frame kept is placed at its
+    /// offset from the video start rather than at 0, so the partial frame
+    /// cut at the boundary does not pull the whole track early.
+    fn align_session_start(&mut self, video_start_us: u64) {
+        if !self.has_audio() {
+            return;
+        }
+        let audio_time_base = self.config.audio_time_base();
+        let video_start = audio_time_base.from_us(video_start_us, Rounding::Floor);
+        let audio_start = self.audio_samples.first().map(|s| s.pts);
+        if let Some(audio_start) = audio_start.filter(|&audio_start| {
+            audio_start < video_start && self.config.start_alignment == StartAlignment::Delay
+        }) {
+            let lead_us = audio_time_base.to_us(video_start - audio_start, Rounding::Nearest);
+            self.video_base_media_decode_time = self
+                .config
+                .video_time_base()
+                .from_us(lead_us, Rounding::Nearest);
+            log_event!(
+                LogLevel::Debug,
+                "Delayed video behind audio recorded before it",
+                lead_us = lead_us,
+            );
+            return;
+        }
 
-            let first_dts = self.video_samples[0].dts;
-            let last_dts = self.video_samples.last().unwrap().dts;
-            let duration_ticks = last_dts - first_dts;
-            let video_timescale = self.config.video_timescale_or_default();
-            let duration_ms = duration_ticks * 1000 / video_timescale as u64;
+        let early = self
+            .audio_samples
+            .iter()
+            .take_while(|s| s.pts < video_start)
+            .count();
+        if early > 0 {
+            let bytes: u64 = self.audio_samples[..early]
+                .iter()
+                .map(|s| s.data.len() as u64)
+                .sum();
+            self.audio_samples.drain(..early);
+            self.audio_frame_count -= early as u32;
+            self.sample_bytes -= bytes;
+            log_event!(
+                LogLevel::Debug,
+                "Trimmed audio recorded before the first video frame",
+                frames = early,
+                video_start_us = video_start_us,
+            );
+        }
+        self.segment_start_us = Some(video_start_us);
+        self.audio_start = Some(video_start);
+        self.place_first_audio();
+    }
 
-            if duration_ms >= self.config.fragment_duration_ms as u64 {
-                self.flush_segments();
-            }
-        } else {
-            // Audio-only flush: check accumulated audio duration
-            if self.audio_samples.len() < 2 {
-                return;
+    /// Start the audio track at the offset of its first kept frame from the
+    /// video start, once there is one
+    fn place_first_audio(&mut self) {
+        let (Some(start), Some(first)) = (self.audio_start, self.audio_samples.first()) else {
+            return;
+        };
+        self.audio_base_media_decode_time = first.pts - start;
+        self.audio_start = None;
+    }
+
+    /// Convert an audio frame duration to timescale ticks without drift
+    ///
+    /// Durations arrive in whole microseconds, so an AAC frame of 1024
+    /// samples at 44.1 kHz reads 23220 us for 23219.95 us. A duration within
+    /// 1 us of a whole number of samples is taken as exactly that many
+    /// samples. It is then converted with exact rational math, and the
+    /// rounding error of each frame is carried into the next (across
+    /// fragments too), so the track never drifts by more than half a tick
+    /// whatever the timescale. e.g. at a 90000 timescale and 44.1 kHz, each
+    /// frame is 2089.8 ticks: rounding per call would add 0.2 tick per frame.
+    fn audio_duration_ticks(&mut self, duration_us: u32) -> u32 {
+        let sample_rate = self.config.audio_sample_rate.unwrap_or(48000).max(1) as u64;
+        // Exact duration in units of 1 / (sample_rate * 1e6) seconds, the
+        // clock of `audio_ticks`
+        let us = duration_us as u64 * sample_rate;
+        let samples = self
+            .config
+            .audio_sample_time_base()
+            .from_us(duration_us as u64, Rounding::Nearest);
+        let exact = if (samples * 1_000_000).abs_diff(us) < sample_rate {
+            samples * 1_000_000
+        } else {
+            us
+        };
+        self.audio_ticks.push(exact) as u32
+    }
+
+    /// Lengthen or shorten an audio frame by a tick against skew
+    ///
+    /// With `audio_skew_correction`, where the frame lands on the track (the
+    /// durations muxed since an anchor frame) is compared with its PTS. Past
+    /// `AUDIO_SKEW_TOLERANCE_US` the duration moves one tick toward the PTS,
+    /// so drift is taken back over many frames without an audible jump. Past
+    /// `AUDIO_SKEW_MAX_US` the timestamps jumped instead, and the frame
+    /// becomes the new anchor.
+    fn correct_audio_skew(&mut self, pts: u64, duration: u32) -> u32 {
+        if !self.config.audio_skew_correction {
+            return duration;
+        }
+        let time_base = self.config.audio_time_base();
+        let skew = match self.audio_skew_anchor {
+            Some(anchor) => (pts - anchor) as i64 - self.audio_skew_elapsed as i64,
+            None => 0,
+        };
+        if self.audio_skew_anchor.is_none()
+            || skew.unsigned_abs() > time_base.from_us(AUDIO_SKEW_MAX_US, Rounding::Floor)
+        {
+            self.audio_skew_anchor = Some(pts);
+            self.audio_skew_elapsed = duration as u64;
+            return duration;
+        }
+        let mut corrected = duration;
+        if skew.unsigned_abs() > time_base.from_us(AUDIO_SKEW_TOLERANCE_US, Rounding::Ceil) {
+            if skew > 0 {
+                corrected = duration.saturating_add(1);
+            } else if duration > 1 {
+                corrected = duration - 1;
+            }
+            self.audio_skew_correction += corrected as i64 - duration as i64;
+        }
+        self.audio_skew_elapsed += corrected as u64;
+        corrected
+    }
+
+    /// Reject or skip an empty or oversized frame, returning whether it is
+    /// skipped
+    ///
+    /// Strict mode rejects it with an error. Otherwise it is counted, logged
+    /// in warn mode and queued for `take_skipped_frames`.
+    fn skip_frame(
+        &mut self,
+        track: TrackKind,
+        timestamp_us: u64,
+        size: usize,
+    ) -> Result<bool, String> {
+        let max_frame_size = self.config.max_frame_size_or_default();
+        let size = size.min(u32::MAX as usize) as u32;
+        let (issue, message) = if size == 0 {
+            (
+                FrameIssue::Empty,
+                format!("Empty {} frame at {} us", track.as_str(), timestamp_us),
+            )
+        } else if size > max_frame_size {
+            (
+                FrameIssue::Oversized,
+                format!(
+                    "{} frame at {} us is {} bytes, over the {} byte limit",
+                    track.as_str(),
+                    timestamp_us,
+                    size,
+                    max_frame_size
+                ),
+            )
+        } else {
+            self.largest_frame = self.largest_frame.max(size);
+            return Ok(false);
+        };
+        match self.config.validation {
+            ValidationMode::Strict => {
+                self.rejected_frames += 1;
+                return Err(telemetry::error(issue.code(), message));
+            }
+            ValidationMode::Warn => {
+                log_event!(LogLevel::Warn, "Skipped frame", detail = message)
+            }
+            ValidationMode::Lenient => {}
+        }
+        match issue {
+            FrameIssue::Empty => self.empty_frames += 1,
+            FrameIssue::Oversized => self.oversized_frames += 1,
+        }
+        self.dropped_frames += 1;
+        if self.skipped.len() == MAX_SKIPPED_FRAMES {
+            self.skipped.remove(0);
+        }
+        self.skipped.push(SkippedFrame {
+            track,
+            timestamp_us,
+            size,
+            issue,
+        });
+        Ok(true)
+    }
+
+    /// Take the frames skipped as empty or oversized since the last call
+    ///
+    /// At most the last 64 are kept; the counters in `stats` are complete.
+    pub fn take_skipped_frames(&mut self) -> Vec<SkippedFrame> {
+        std::mem::take(&mut self.skipped)
+    }
+
+    /// Handle an input anomaly according to the validation mode
+    ///
+    /// Strict mode turns it into an error; otherwise it is counted (and
+    /// logged in warn mode) and the caller corrects or accepts the frame.
+    fn anomaly(&mut self, message: String) -> Result<(), String> {
+        match self.config.validation {
+            ValidationMode::Strict => {
+                self.rejected_frames += 1;
+                return Err(telemetry::error("invalid_input", message));
+            }
+            ValidationMode::Warn => {
+                log_event!(LogLevel::Warn, "Input anomaly", detail = message)
+            }
+            ValidationMode::Lenient => {}
+        }
+        self.anomaly_count += 1;
+        Ok(())
+    }
+
+    /// Bytes to keep for a pushed frame: none in dry-run mode
+    fn sample_data(&self, data: &[u8]) -> Vec<u8> {
+        if self.config.dry_run {
+            Vec::new()
+        } else {
+            data.to_vec()
+        }
+    }
+
+    /// Update telemetry for a frame of `bytes` added to the buffered samples
+    fn note_frame_in(&mut self, bytes: usize) {
+        if self.config.dry_run {
+            telemetry::frame_in(bytes);
+            return;
+        }
+        self.sample_bytes += bytes as u64;
+        telemetry::frame_in(bytes);
+        telemetry::buffered_bytes(self.sample_bytes + self.sink.buffered_bytes());
+    }
+
+    /// Track where the segment being built starts and whether it has a keyframe
+    fn note_sample(&mut self, timestamp_us: u64, is_keyframe: bool) {
+        if !self.latency.anchored() {
+            self.latency.anchor(timestamp_us, self.clock.now_ms());
+        }
+        self.segment_start_us.get_or_insert(timestamp_us);
+        self.segment_has_keyframe |= is_keyframe;
+    }
+
+    /// Check if we should flush segments based on video or audio duration
+    fn check_and_flush_segments(&mut self) -> Result<(), String> {
+        if self.config.low_memory() && self.sample_bytes >= LOW_MEMORY_MAX_BUFFERED_BYTES {
+            return self.flush_segments();
+        }
+        let fragment_duration_ms = self.config.target_fragment_duration_ms() as u64;
+        if self.has_video() {
+            // Video-based flush: check video sample duration
+            if self.video_samples.len() < 2 {
+                return Ok(());
             }
 
-            let audio_timescale = self
+            let first_dts = self.video_samples[0].dts;
+            let last_dts = self.video_samples.last().unwrap().dts;
+            let duration_ms = self
                 .config
-                .audio_timescale
-                .unwrap_or(self.config.audio_sample_rate.unwrap_or(48000));
+                .video_time_base()
+                .to_ms(last_dts - first_dts, Rounding::Floor);
+
+            if duration_ms >= fragment_duration_ms {
+                return self.flush_aligned_segments();
+            }
+        } else {
+            // Audio-only flush: check accumulated audio duration
+            if self.audio_samples.len() < 2 {
+                return Ok(());
+            }
+
             let total_duration_ticks: u64 =
                 self.audio_samples.iter().map(|s| s.duration as u64).sum();
-            let duration_ms = total_duration_ticks * 1000 / audio_timescale as u64;
+            let duration_ms = self
+                .config
+                .audio_time_base()
+                .to_ms(total_duration_ticks, Rounding::Floor);
 
-            if duration_ms >= self.config.fragment_duration_ms as u64 {
-                self.flush_segments();
+            if duration_ms >= fragment_duration_ms {
+                return self.flush_segments();
             }
         }
+        Ok(())
     }
 
     /// Calculate total video duration matching trun box logic exactly.
     /// This ensures segment[N].tfdt + sum(trun_durations) == segment[N+1].tfdt.
     fn calculate_video_trun_total_duration(samples: &[VideoSample]) -> u64 {
-        if samples.is_empty() {
-            return 0;
-        }
-        let mut total: u64 = 0;
-        for i in 0..samples.len() {
-            let duration = if i + 1 < samples.len() {
-                (samples[i + 1].dts - samples[i].dts) as u32
-            } else if i > 0 {
-                (samples[i].dts - samples[i - 1].dts) as u32
-            } else {
-                3000 // Default: 1 frame at 30fps
-            };
-            total += duration as u64;
-        }
-        total
+        video_durations(samples).map(|d| d as u64).sum()
     }
 
     /// Calculate total audio duration from sample durations.
@@ -323,20 +1654,66 @@ impl MuxideMuxerState {
     }
 
     /// Flush all pending samples into a media segment
-    fn flush_segments(&mut self) {
+    fn flush_segments(&mut self) -> Result<(), String> {
+        self.flush_fragment(false)
+    }
+
+    /// Cut a fragment at the end of its video
+    ///
+    /// Audio samples that do not end by the last video sample's end belong
+    /// to the next fragment and stay buffered, so both tracks of a fragment
+    /// cover the same time and MSE sees no A/V skew at segment edges. An
+    /// audio frame straddling the cut cannot be split and is carried forward
+    /// whole.
+    fn flush_aligned_segments(&mut self) -> Result<(), String> {
+        self.flush_fragment(true)
+    }
+
+    /// Flush the pending samples, with `align_audio` as in
+    /// `flush_aligned_segments`
+    fn flush_fragment(&mut self, align_audio: bool) -> Result<(), String> {
         if self.has_video() {
             // Video (+ optional audio) mode
             if self.video_samples.is_empty() {
-                return;
+                return Ok(());
+            }
+            // A lone frame has no neighbour to take its duration from
+            if let [sample] = self.video_samples.as_mut_slice() {
+                sample.duration.get_or_insert(self.default_video_duration);
             }
 
+            let carried = if align_audio {
+                let video_end = self.video_base_media_decode_time
+                    + Self::calculate_video_trun_total_duration(&self.video_samples);
+                let cut = self.config.video_time_base().convert(
+                    video_end,
+                    self.config.audio_time_base(),
+                    Rounding::Nearest,
+                );
+                let mut audio_end = self.audio_base_media_decode_time;
+                let split = self
+                    .audio_samples
+                    .iter()
+                    .position(|sample| {
+                        audio_end += sample.duration as u64;
+                        audio_end > cut
+                    })
+                    .unwrap_or(self.audio_samples.len());
+                self.audio_samples.split_off(split)
+            } else {
+                Vec::new()
+            };
+
             let segment = build_media_segment_av(
                 &self.video_samples,
                 &self.audio_samples,
                 self.video_sequence_number,
                 self.video_base_media_decode_time,
                 self.audio_base_media_decode_time,
-                &self.config,
+                self.config.audio_description_index(),
+                self.config.has_audio(),
+                self.config.aux_info_fourcc(),
+                self.base_data_offset(),
             );
 
             // Update state for next segment using cumulative duration.
@@ -349,19 +1726,30 @@ impl MuxideMuxerState {
                 Self::calculate_audio_trun_total_duration(&self.audio_samples);
             self.audio_base_media_decode_time += audio_total_duration;
 
+            let frames = self.video_samples.len() + self.audio_samples.len();
             self.video_samples.clear();
-            self.audio_samples.clear();
-            self.pending_segments.push(segment);
+            self.audio_samples = carried;
+            let duration_us = self
+                .config
+                .video_time_base()
+                .to_us(video_total_duration, Rounding::Nearest);
+            self.record_segment(segment, frames, duration_us)?;
+            if !self.config.dry_run {
+                self.sample_bytes = self.audio_samples.iter().map(|s| s.size as u64).sum();
+            }
+            Ok(())
         } else {
             // Audio-only mode
             if self.audio_samples.is_empty() {
-                return;
+                return Ok(());
             }
 
             let segment = build_media_segment_audio_only(
                 &self.audio_samples,
                 self.audio_sequence_number,
                 self.audio_base_media_decode_time,
+                self.config.audio_description_index(),
+                self.base_data_offset(),
             );
 
             self.audio_sequence_number += 1;
@@ -369,49 +1757,525 @@ impl MuxideMuxerState {
                 Self::calculate_audio_trun_total_duration(&self.audio_samples);
             self.audio_base_media_decode_time += audio_total_duration;
 
+            let frames = self.audio_samples.len();
             self.audio_samples.clear();
-            self.pending_segments.push(segment);
+            let duration_us = self
+                .config
+                .audio_time_base()
+                .to_us(audio_total_duration, Rounding::Nearest);
+            self.record_segment(segment, frames, duration_us)
+        }
+    }
+
+    /// tfhd base data offset of the next segment, under
+    /// `DataOffsetMode::Absolute`
+    fn base_data_offset(&self) -> Option<u64> {
+        (self.config.data_offset_mode == DataOffsetMode::Absolute).then(|| {
+            self.sink
+                .segment_offset(self.init_segment.len() as u64, self.segment_bytes)
+        })
+    }
+
+    /// Hand a finished media segment to the sink and update counters
+    fn record_segment(
+        &mut self,
+        mut segment: Vec<u8>,
+        frames: usize,
+        duration_us: u64,
+    ) -> Result<(), String> {
+        log_event!(
+            LogLevel::Trace,
+            "Media segment built",
+            sequence = self.segment_count,
+            bytes = segment.len(),
+            video_decode_time = self.video_base_media_decode_time,
+            audio_decode_time = self.audio_base_media_decode_time,
+        );
+        self.check_compat(&segment);
+        if self.config.fragment_checksums {
+            let checksum = build_checksum_box(&segment);
+            segment.extend_from_slice(&checksum);
+        }
+        let sequence = self.segment_count as u64;
+        self.segment_count += 1;
+        self.segment_bytes += segment.len() as u64;
+        self.sample_bytes = 0;
+        telemetry::segment_out(frames, segment.len());
+        let start_us = self.segment_start_us.take().unwrap_or(0);
+        let emitted_at_ms = self.clock.now_ms();
+        let written = self.sink.write_segment(MediaSegment {
+            sequence,
+            start_us,
+            has_keyframe: std::mem::take(&mut self.segment_has_keyframe),
+            data: segment,
+        });
+        telemetry::buffered_bytes(self.sink.buffered_bytes());
+        written.map_err(|e| telemetry::error("sink_failed", e))?;
+        self.latency
+            .emitted(sequence, start_us, start_us + duration_us, emitted_at_ms);
+        if self.sink.acknowledges_on_write() {
+            self.acknowledge_segment(sequence);
+        }
+        self.collect_acknowledged();
+        Ok(())
+    }
+
+    /// Record that segment `sequence` is done with, for the latency report
+    ///
+    /// Segments are acknowledged on their own when the sink's write returns,
+    /// or when they are taken from a `BufferedSink` (except with
+    /// `take_unhashed_chunks`, whose caller acknowledges them once done).
+    pub fn acknowledge_segment(&mut self, sequence: u64) {
+        self.latency.acknowledged(sequence, self.clock.now_ms());
+    }
+
+    /// Re-tie input time to the wall clock at the next frame, e.g. after a
+    /// pause removed from the timeline
+    pub fn reset_latency_anchor(&mut self) {
+        self.latency.reset_anchor();
+    }
+
+    /// Emit and ack latency of the segments so far, collecting the writes
+    /// the sink has completed first
+    pub fn latency_report(&mut self) -> LatencyReport {
+        self.collect_acknowledged();
+        self.latency.report(self.clock.now_ms())
+    }
+
+    /// Note the writes the sink completed on its own time
+    fn collect_acknowledged(&mut self) {
+        for (sequence, at_ms) in self.sink.take_acknowledged() {
+            self.latency.acknowledged(sequence, at_ms);
+        }
+    }
+
+    /// Run the compatibility checks on a new segment
+    ///
+    /// Violations that only the muxer can cause fail a debug assertion.
+    fn check_compat(&mut self, segment: &[u8]) {
+        let Some(checker) = &mut self.compat else {
+            return;
+        };
+        let violations = match checker.check_segment(segment) {
+            Ok(violations) => violations,
+            Err(e) => {
+                log_event!(LogLevel::Error, "Unreadable media segment", detail = e);
+                debug_assert!(false, "Unreadable media segment: {}", e);
+                return;
+            }
+        };
+        if !violations.is_empty() {
+            self.compat_violations += 1;
+        }
+        for violation in violations {
+            log_event!(
+                LogLevel::Warn,
+                "QuickTime compatibility",
+                detail = violation.message
+            );
+            debug_assert!(!violation.rule.is_muxer_bug(), "{}", violation);
         }
     }
 
     /// Force flush the current segment even if it hasn't reached the target duration
     pub fn force_flush(&mut self) -> Result<(), String> {
         if !self.initialized {
-            return Err("Muxer not initialized".to_string());
+            return Err(telemetry::error("not_initialized", "Muxer not initialized"));
         }
 
-        self.flush_segments();
+        self.record(TraceRecord::Flush);
+        self.flush_segments()
+    }
 
-        Ok(())
+    /// Flush the current segment and finalize the sink: the output is complete
+    ///
+    /// Gapless metadata needs the final audio length in the init segment, so
+    /// streaming sinks (which already wrote it) only get it through
+    /// `get_complete_file` on a buffered muxer.
+    pub fn finish(&mut self) -> Result<(), String> {
+        self.force_flush()?;
+        self.sink
+            .finalize()
+            .map_err(|e| telemetry::error("sink_failed", e))
+    }
+
+    /// Get a snapshot of the muxer counters
+    pub fn stats(&self) -> MuxerStats {
+        MuxerStats {
+            video_frame_count: self.video_frame_count,
+            audio_frame_count: self.audio_frame_count,
+            segment_count: self.segment_count,
+            segment_bytes: self.segment_bytes,
+            pending_segment_count: self.sink.buffered_segments() as u32,
+            buffered_video_samples: self.video_samples.len() as u32,
+            buffered_audio_samples: self.audio_samples.len() as u32,
+            anomaly_count: self.anomaly_count,
+            rejected_frames: self.rejected_frames,
+            dropped_frames: self.dropped_frames,
+            corrected_frames: self.corrected_frames,
+            compat_violations: self.compat_violations,
+            empty_frames: self.empty_frames,
+            oversized_frames: self.oversized_frames,
+            largest_frame: self.largest_frame,
+            max_frame_size: self.config.max_frame_size_or_default(),
+            audio_skew_correction_ticks: self.audio_skew_correction,
+        }
+    }
+
+    /// Set the number of real audio samples, for the gapless remainder count
+    ///
+    /// Without it, all samples after the priming ones count as audio.
+    pub fn set_audio_valid_samples(&mut self, samples: u64) {
+        self.audio_valid_samples = Some(samples);
+    }
+
+    /// Gapless sample counts of the audio muxed so far
+    ///
+    /// None unless the muxer is audio-only with `audio_priming_samples` set.
+    pub fn gapless_info(&self) -> Option<GaplessInfo> {
+        if !self.config.has_gapless_audio() {
+            return None;
+        }
+        let priming_samples = self.config.audio_priming_samples.unwrap_or(0);
+        let ticks = self.audio_base_media_decode_time
+            + Self::calculate_audio_trun_total_duration(&self.audio_samples);
+        let available = self
+            .config
+            .audio_ticks_to_samples(ticks)
+            .saturating_sub(priming_samples as u64);
+        let valid_samples = self.audio_valid_samples.unwrap_or(available).min(available);
+        Some(GaplessInfo {
+            priming_samples,
+            remainder_samples: (available - valid_samples) as u32,
+            valid_samples,
+        })
+    }
+}
+
+impl MuxideMuxerState {
+    /// Create a new MuxideMuxerState with the given configuration
+    pub fn new(config: MuxideConfig) -> Self {
+        Self::with_sink(config, BufferedSink::default())
     }
 
     /// Get all pending media segments and clear them
     pub fn get_pending_segments(&mut self) -> Vec<Vec<u8>> {
-        std::mem::take(&mut self.pending_segments)
+        (self.take_segments().into_iter())
+            .map(|segment| segment.data)
+            .collect()
+    }
+
+    /// Take the buffered segments, acknowledging them
+    fn take_segments(&mut self) -> Vec<MediaSegment> {
+        let segments = self.sink.take();
+        for segment in &segments {
+            self.acknowledge_segment(segment.sequence);
+        }
+        segments
+    }
+
+    /// Take all pending media segments as chunks of `session_id`
+    ///
+    /// Each chunk comes with its manifest entry: the sequence number counts
+    /// every segment this muxer has produced, the timestamp is that of the
+    /// first sample pushed into the segment, and the BLAKE3 hash is computed
+    /// here so callers never hash segment data themselves.
+    pub fn take_pending_chunks(&mut self, session_id: &SessionId) -> Vec<RecordedChunk> {
+        let mut chunks = self.take_unhashed_chunks(session_id);
+        for chunk in &mut chunks {
+            chunk.metadata.hash = Some(blake3::hash(&chunk.data).to_hex().to_string());
+            self.acknowledge_segment(chunk.metadata.chunk_id.sequence);
+        }
+        chunks
+    }
+
+    /// Take all pending media segments as chunks, like
+    /// `take_pending_chunks`, leaving the hash and `acknowledge_segment` to
+    /// the caller
+    pub fn take_unhashed_chunks(&mut self, session_id: &SessionId) -> Vec<RecordedChunk> {
+        (self.sink.take().into_iter())
+            .map(|segment| RecordedChunk {
+                metadata: ChunkMetadata {
+                    chunk_id: ChunkId::new(session_id.clone(), TrackKind::Muxed, segment.sequence),
+                    timestamp_us: segment.start_us,
+                    size: segment.data.len() as u64,
+                    hash: None,
+                    has_keyframe: Some(segment.has_keyframe),
+                    created_at: self.clock.now_ms(),
+                },
+                data: segment.data,
+            })
+            .collect()
     }
 
     /// Check if there are any pending segments
     pub fn has_pending_segments(&self) -> bool {
-        !self.pending_segments.is_empty()
+        !self.sink.segments().is_empty()
     }
 
     /// Get the complete fMP4 file (init segment + all media segments)
+    ///
+    /// For gapless audio-only output the init segment is rebuilt with the
+    /// final edit list duration and `iTunSMPB` tag. Not available under the
+    /// low-memory profile: take segments as they come instead.
     pub fn get_complete_file(&mut self) -> Result<Vec<u8>, String> {
         if !self.initialized {
-            return Err("Muxer not initialized".to_string());
+            return Err(telemetry::error("not_initialized", "Muxer not initialized"));
+        }
+        if self.config.low_memory() {
+            return Err(telemetry::error(
+                "low_memory",
+                "The low-memory profile does not assemble the complete file in memory",
+            ));
         }
 
         // Force flush any remaining data
         self.force_flush()?;
 
-        let mut result = self.init_segment.clone();
-        for segment in &self.pending_segments {
-            result.extend(segment);
+        let mut result = match self.gapless_info() {
+            Some(info) => write_init_segment(&self.config, Some(&info)),
+            None => self.init_segment.clone(),
+        };
+        for segment in self.take_segments() {
+            result.extend(&segment.data);
         }
-        self.pending_segments.clear();
 
         Ok(result)
     }
+
+    /// Serialize the complete muxer state
+    ///
+    /// Captures config, counters, sequence numbers, base decode times, buffered
+    /// samples and pending segments, so `restore_state` resumes exactly where
+    /// this muxer left off (e.g. after a checkpoint or in another worker).
+    ///
+    /// Layout (little endian): `MXST` magic, version byte, length-prefixed
+    /// JSON config, fixed-width counters, then length-prefixed sample and
+    /// segment lists.
+    pub fn serialize_state(&self) -> Result<Vec<u8>, String> {
+        let config = serde_json::to_vec(&self.config).map_err(|e| e.to_string())?;
+
+        let mut out = Vec::new();
+        out.extend_from_slice(STATE_MAGIC);
+        out.push(STATE_VERSION);
+        put_bytes(&mut out, &config);
+        out.push(self.initialized as u8);
+        out.extend_from_slice(&self.video_frame_count.to_le_bytes());
+        out.extend_from_slice(&self.audio_frame_count.to_le_bytes());
+        out.extend_from_slice(&self.segment_count.to_le_bytes());
+        out.extend_from_slice(&self.segment_bytes.to_le_bytes());
+        out.extend_from_slice(&self.video_sequence_number.to_le_bytes());
+        out.extend_from_slice(&self.video_base_media_decode_time.to_le_bytes());
+        out.extend_from_slice(&self.audio_sequence_number.to_le_bytes());
+        out.extend_from_slice(&self.audio_base_media_decode_time.to_le_bytes());
+        out.push(self.segment_start_us.is_some() as u8);
+        out.extend_from_slice(&self.segment_start_us.unwrap_or(0).to_le_bytes());
+        out.push(self.segment_has_keyframe as u8);
+        for last in [self.last_video_dts, self.last_audio_pts, self.audio_start] {
+            out.push(last.is_some() as u8);
+            out.extend_from_slice(&last.unwrap_or(0).to_le_bytes());
+        }
+        out.extend_from_slice(&self.anomaly_count.to_le_bytes());
+        for count in [
+            self.rejected_frames,
+            self.dropped_frames,
+            self.corrected_frames,
+            self.empty_frames,
+            self.oversized_frames,
+            self.largest_frame,
+        ] {
+            out.extend_from_slice(&count.to_le_bytes());
+        }
+        out.extend_from_slice(&self.audio_ticks.remainder().to_le_bytes());
+        out.extend_from_slice(&self.video_ticks.remainder().to_le_bytes());
+        out.push(self.audio_skew_anchor.is_some() as u8);
+        out.extend_from_slice(&self.audio_skew_anchor.unwrap_or(0).to_le_bytes());
+        out.extend_from_slice(&self.audio_skew_elapsed.to_le_bytes());
+        out.extend_from_slice(&self.audio_skew_correction.to_le_bytes());
+
+        out.extend_from_slice(&(self.video_samples.len() as u32).to_le_bytes());
+        for sample in &self.video_samples {
+            out.extend_from_slice(&sample.pts.to_le_bytes());
+            out.extend_from_slice(&sample.dts.to_le_bytes());
+            out.extend_from_slice(&sample.flags.to_bits().to_le_bytes());
+            out.extend_from_slice(&sample.duration.unwrap_or(0).to_le_bytes());
+            out.extend_from_slice(&sample.size.to_le_bytes());
+            put_bytes(&mut out, &sample.data);
+            out.push(sample.aux_size);
+            put_bytes(&mut out, &sample.aux);
+        }
+
+        out.extend_from_slice(&(self.audio_samples.len() as u32).to_le_bytes());
+        for sample in &self.audio_samples {
+            out.extend_from_slice(&sample.pts.to_le_bytes());
+            out.extend_from_slice(&sample.duration.to_le_bytes());
+            out.extend_from_slice(&sample.size.to_le_bytes());
+            put_bytes(&mut out, &sample.data);
+        }
+
+        let segments = self.sink.segments();
+        out.extend_from_slice(&(segments.len() as u32).to_le_bytes());
+        for segment in segments {
+            out.extend_from_slice(&segment.start_us.to_le_bytes());
+            out.push(segment.has_keyframe as u8);
+            put_bytes(&mut out, &segment.data);
+        }
+
+        Ok(out)
+    }
+
+    /// Rebuild a muxer from bytes produced by `serialize_state`
+    ///
+    /// The init segment is regenerated from the restored config.
+    pub fn restore_state(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = StateReader { bytes, pos: 0 };
+        if reader.take(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(telemetry::error(
+                "corrupt_state",
+                "Invalid muxer state: bad magic",
+            ));
+        }
+        let version = reader.u8()?;
+        if !(MIN_STATE_VERSION..=STATE_VERSION).contains(&version) {
+            return Err(telemetry::error(
+                "corrupt_state",
+                format!("Unsupported muxer state version: {}", version),
+            ));
+        }
+
+        let config: MuxideConfig = serde_json::from_slice(reader.bytes()?)
+            .map_err(|e| format!("Invalid muxer state config: {}", e))?;
+        let mut state = Self::new(config);
+
+        let initialized = reader.u8()? != 0;
+        state.video_frame_count = reader.u32()?;
+        state.audio_frame_count = reader.u32()?;
+        state.segment_count = reader.u32()?;
+        state.segment_bytes = reader.u64()?;
+        state.video_sequence_number = reader.u32()?;
+        state.video_base_media_decode_time = reader.u64()?;
+        state.audio_sequence_number = reader.u32()?;
+        state.audio_base_media_decode_time = reader.u64()?;
+        let has_segment_start = reader.u8()? != 0;
+        let segment_start_us = reader.u64()?;
+        state.segment_start_us = has_segment_start.then_some(segment_start_us);
+        state.segment_has_keyframe = reader.u8()? != 0;
+        let has_last_video = reader.u8()? != 0;
+        state.last_video_dts = has_last_video.then_some(reader.u64()?);
+        let has_last_audio = reader.u8()? != 0;
+        state.last_audio_pts = has_last_audio.then_some(reader.u64()?);
+        let has_audio_start = reader.u8()? != 0;
+        state.audio_start = has_audio_start.then_some(reader.u64()?);
+        state.anomaly_count = reader.u32()?;
+        state.rejected_frames = reader.u32()?;
+        state.dropped_frames = reader.u32()?;
+        state.corrected_frames = reader.u32()?;
+        state.empty_frames = reader.u32()?;
+        state.oversized_frames = reader.u32()?;
+        state.largest_frame = reader.u32()?;
+        state.audio_ticks.set_remainder(reader.u64()? as i64);
+        state.video_ticks.set_remainder(reader.u64()? as i64);
+        // Version 11 predates skew correction, which then starts afresh
+        if version >= 12 {
+            let has_skew_anchor = reader.u8()? != 0;
+            state.audio_skew_anchor = has_skew_anchor.then_some(reader.u64()?);
+            state.audio_skew_elapsed = reader.u64()?;
+            state.audio_skew_correction = reader.u64()? as i64;
+        }
+
+        for _ in 0..reader.u32()? {
+            state.video_samples.push(VideoSample {
+                pts: reader.u64()?,
+                dts: reader.u64()?,
+                flags: SampleFlags::from_bits(reader.u32()?),
+                duration: Some(reader.u32()?).filter(|&d| d > 0),
+                size: reader.u32()?,
+                data: reader.bytes()?.to_vec(),
+                aux_size: reader.u8()?,
+                aux: reader.bytes()?.to_vec(),
+            });
+        }
+        for _ in 0..reader.u32()? {
+            state.audio_samples.push(AudioSample {
+                pts: reader.u64()?,
+                duration: reader.u32()?,
+                size: reader.u32()?,
+                data: reader.bytes()?.to_vec(),
+            });
+        }
+        let segment_count = reader.u32()?;
+        let first_sequence = (state.segment_count as u64).saturating_sub(segment_count as u64);
+        for sequence in first_sequence..first_sequence + segment_count as u64 {
+            state.sink.write_segment(MediaSegment {
+                sequence,
+                start_us: reader.u64()?,
+                has_keyframe: reader.u8()? != 0,
+                data: reader.bytes()?.to_vec(),
+            })?;
+        }
+        state.sample_bytes = (state.video_samples.iter().map(|s| s.data.len()))
+            .chain(state.audio_samples.iter().map(|s| s.data.len()))
+            .sum::<usize>() as u64;
+        if reader.pos != bytes.len() {
+            return Err(telemetry::error(
+                "corrupt_state",
+                "Invalid muxer state: trailing bytes",
+            ));
+        }
+
+        if initialized {
+            state.init()?;
+        }
+        Ok(state)
+    }
+}
+
+const STATE_MAGIC: &[u8] = b"MXST";
+const STATE_VERSION: u8 = 12;
+/// Oldest state version `restore_state` still reads
+const MIN_STATE_VERSION: u8 = 11;
+
+/// Append a u32 length prefix followed by the bytes
+pub(crate) fn put_bytes(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+/// Cursor over serialized muxer state
+pub(crate) struct StateReader<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.pos < len {
+            return Err(telemetry::error(
+                "corrupt_state",
+                "Invalid muxer state: unexpected end of data",
+            ));
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
 }
 
 /// Extract SPS and PPS from avcC box (codec configuration from WebCodecs)
@@ -573,7 +2437,7 @@ pub fn annex_b_to_avcc(annex_b: &[u8]) -> Vec<u8> {
 // ============================================================================
 
 /// Build a generic MP4 box with type and payload
-fn build_box(typ: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+pub(crate) fn build_box(typ: &[u8; 4], payload: &[u8]) -> Vec<u8> {
     let size = (8 + payload.len()) as u32;
     let mut buf = Vec::with_capacity(size as usize);
     buf.extend_from_slice(&size.to_be_bytes());
@@ -582,8 +2446,59 @@ fn build_box(typ: &[u8; 4], payload: &[u8]) -> Vec<u8> {
     buf
 }
 
-/// Build the complete init segment (ftyp + moov)
-fn build_init_segment(config: &MuxideConfig) -> Vec<u8> {
+/// Parse a four-character code such as an aux_info_type
+fn parse_fourcc(code: &str) -> Result<[u8; 4], String> {
+    code.as_bytes()
+        .try_into()
+        .ok()
+        .filter(|bytes: &[u8; 4]| bytes.iter().all(|b| b.is_ascii_graphic() || *b == b' '))
+        .ok_or_else(|| format!("Invalid four-character code: {:?}", code))
+}
+
+/// Build the init segment (ftyp + moov) a muxer with `config` writes
+///
+/// Byte-identical to `get_init_segment()`, or with `gapless` to the one
+/// `get_complete_file()` starts with, so a server can regenerate the init
+/// segment of a recovered or merged session from its stored config without
+/// creating a muxer.
+pub fn build_init_segment(
+    config: &MuxideConfig,
+    gapless: Option<&GaplessInfo>,
+) -> Result<Vec<u8>, String> {
+    if !config.has_video() && !config.has_audio() {
+        return Err(telemetry::error(
+            "invalid_config",
+            "At least one track (video or audio) must be configured",
+        ));
+    }
+    if config.data_offset_mode == DataOffsetMode::Absolute && config.has_gapless_audio() {
+        return Err(telemetry::error(
+            "invalid_config",
+            "Absolute data offsets cannot be combined with gapless audio metadata",
+        ));
+    }
+    if let Some(info_type) = &config.aux_info_type {
+        parse_fourcc(info_type).map_err(|e| telemetry::error("invalid_config", e))?;
+    }
+    for name in [&config.video_track_name, &config.audio_track_name]
+        .into_iter()
+        .flatten()
+    {
+        if name.contains('\0') {
+            return Err(telemetry::error(
+                "invalid_config",
+                format!("Track name {:?} contains a NUL character", name),
+            ));
+        }
+    }
+    Ok(write_init_segment(config, gapless))
+}
+
+/// Build the complete init segment (ftyp + moov, then any `free` padding)
+/// of a validated config
+///
+/// `gapless` carries the final sample counts once the recording is complete.
+fn write_init_segment(config: &MuxideConfig, gapless: Option<&GaplessInfo>) -> Vec<u8> {
     let mut buf = Vec::new();
 
     // ftyp box
@@ -591,14 +2506,20 @@ fn build_init_segment(config: &MuxideConfig) -> Vec<u8> {
     buf.extend_from_slice(&ftyp);
 
     // moov box
-    let moov = build_moov(config);
+    let moov = build_moov(config, gapless);
     buf.extend_from_slice(&moov);
 
+    // free box up to the reserved size; a box cannot be under 8 bytes
+    let reserved = config.moov_reserved_size.unwrap_or(0) as usize;
+    if let Some(padding) = reserved.checked_sub(moov.len()).filter(|&p| p >= 8) {
+        buf.extend_from_slice(&build_box(b"free", &vec![0; padding - 8]));
+    }
+
     buf
 }
 
 /// Build ftyp box for fMP4
-fn build_ftyp() -> Vec<u8> {
+pub(crate) fn build_ftyp() -> Vec<u8> {
     let mut payload = Vec::new();
     payload.extend_from_slice(b"iso5"); // Major brand
     payload.extend_from_slice(&0u32.to_be_bytes()); // Minor version
@@ -609,7 +2530,7 @@ fn build_ftyp() -> Vec<u8> {
 }
 
 /// Build moov box with video and/or audio tracks
-fn build_moov(config: &MuxideConfig) -> Vec<u8> {
+fn build_moov(config: &MuxideConfig, gapless: Option<&GaplessInfo>) -> Vec<u8> {
     let mut payload = Vec::new();
 
     let has_video = config.has_video();
@@ -624,15 +2545,8 @@ fn build_moov(config: &MuxideConfig) -> Vec<u8> {
     }
     let next_track_id = track_count + 1;
 
-    // mvhd (movie header) - use video timescale if available, else audio timescale
-    let timescale = if has_video {
-        config.video_timescale_or_default()
-    } else {
-        config
-            .audio_timescale
-            .unwrap_or(config.audio_sample_rate.unwrap_or(48000))
-    };
-    let mvhd = build_mvhd(timescale, next_track_id);
+    // mvhd (movie header) - own timescale, independent of the tracks'
+    let mvhd = build_mvhd(config.movie_timescale_or_default(), next_track_id);
     payload.extend_from_slice(&mvhd);
 
     // mvex (movie extends) - required for fMP4
@@ -649,15 +2563,34 @@ fn build_moov(config: &MuxideConfig) -> Vec<u8> {
     // track_id = 2 when video present, track_id = 1 when audio-only
     if has_audio {
         let audio_track_id = if has_video { 2 } else { 1 };
-        let audio_trak = build_audio_trak(config, audio_track_id);
+        let audio_trak = build_audio_trak(config, audio_track_id, gapless);
         payload.extend_from_slice(&audio_trak);
     }
 
+    // iTunes gapless tag, once the sample counts are known, and the
+    // session description
+    let itunsmpb = gapless.map(GaplessInfo::itunsmpb);
+    let tags: Vec<(&str, &str, &str)> = [
+        itunsmpb
+            .as_deref()
+            .map(|value| (ITUNES_TAG_MEAN, "iTunSMPB", value)),
+        config
+            .session_metadata
+            .as_deref()
+            .map(|value| (SESSION_METADATA_MEAN, SESSION_METADATA_NAME, value)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !tags.is_empty() {
+        payload.extend_from_slice(&build_itunes_udta(&tags));
+    }
+
     build_box(b"moov", &payload)
 }
 
 /// Build mvhd (movie header) box
-fn build_mvhd(timescale: u32, next_track_id: u32) -> Vec<u8> {
+pub(crate) fn build_mvhd(timescale: u32, next_track_id: u32) -> Vec<u8> {
     let mut payload = Vec::new();
     payload.extend_from_slice(&0u32.to_be_bytes()); // Version + flags
     payload.extend_from_slice(&0u32.to_be_bytes()); // Creation time
@@ -699,7 +2632,7 @@ fn build_mvex(has_video: bool, has_audio: bool) -> Vec<u8> {
 }
 
 /// Build trex (track extends) box
-fn build_trex(track_id: u32) -> Vec<u8> {
+pub(crate) fn build_trex(track_id: u32) -> Vec<u8> {
     let mut payload = Vec::new();
     payload.extend_from_slice(&0u32.to_be_bytes()); // Version + flags
     payload.extend_from_slice(&track_id.to_be_bytes()); // Track ID
@@ -722,6 +2655,11 @@ fn build_video_trak(config: &MuxideConfig) -> Vec<u8> {
     let mdia = build_video_mdia(config);
     payload.extend_from_slice(&mdia);
 
+    // udta (user data) with the track name
+    if let Some(name) = &config.video_track_name {
+        payload.extend_from_slice(&build_track_name_udta(name));
+    }
+
     build_box(b"trak", &payload)
 }
 
@@ -763,7 +2701,7 @@ fn build_video_mdia(config: &MuxideConfig) -> Vec<u8> {
     payload.extend_from_slice(&mdhd);
 
     // hdlr (handler) - video
-    let hdlr = build_hdlr(b"vide", b"VideoHandler\0");
+    let hdlr = build_hdlr(b"vide", config.video_track_name.as_deref(), "VideoHandler");
     payload.extend_from_slice(&hdlr);
 
     // minf (media info)
@@ -802,17 +2740,26 @@ fn encode_language_code(language: &str) -> [u8; 2] {
     packed.to_be_bytes()
 }
 
-/// Build hdlr (handler) box
-fn build_hdlr(handler_type: &[u8; 4], name: &[u8]) -> Vec<u8> {
+/// Build hdlr (handler) box named `name`, or `default_name` without one
+fn build_hdlr(handler_type: &[u8; 4], name: Option<&str>, default_name: &str) -> Vec<u8> {
     let mut payload = Vec::new();
     payload.extend_from_slice(&0u32.to_be_bytes()); // Version + flags
     payload.extend_from_slice(&0u32.to_be_bytes()); // Pre-defined
     payload.extend_from_slice(handler_type); // Handler type
     payload.extend_from_slice(&[0u8; 12]); // Reserved
-    payload.extend_from_slice(name); // Name (null-terminated)
+    payload.extend_from_slice(name.unwrap_or(default_name).as_bytes()); // Name
+    payload.push(0); // Null terminator
     build_box(b"hdlr", &payload)
 }
 
+/// Build a trak-level udta box holding the track's name
+///
+/// The `name` box is plain UTF-8 without a terminator, as QuickTime and
+/// most editors read it.
+fn build_track_name_udta(name: &str) -> Vec<u8> {
+    build_box(b"udta", &build_box(b"name", name.as_bytes()))
+}
+
 /// Build video minf (media info) box
 fn build_video_minf(config: &MuxideConfig) -> Vec<u8> {
     let mut payload = Vec::new();
@@ -970,20 +2917,146 @@ fn build_empty_stco() -> Vec<u8> {
 // ============================================================================
 
 /// Build audio trak box
-fn build_audio_trak(config: &MuxideConfig, track_id: u32) -> Vec<u8> {
+fn build_audio_trak(
+    config: &MuxideConfig,
+    track_id: u32,
+    gapless: Option<&GaplessInfo>,
+) -> Vec<u8> {
     let mut payload = Vec::new();
 
     // tkhd (track header)
     let tkhd = build_audio_tkhd(track_id);
     payload.extend_from_slice(&tkhd);
 
+    // edts (edit list) skipping the encoder priming samples. Only audio-only
+    // output has one, as there is no video track to keep in sync. The segment
+    // duration is in the movie timescale (rounded up so no valid sample is
+    // cut) and unknown (0) until the recording is complete; the media time is
+    // in the audio timescale.
+    if config.has_gapless_audio() {
+        let priming = config.audio_priming_samples.unwrap_or(0) as u64;
+        let duration = gapless.map_or(0, |info| {
+            config.audio_time_base().convert(
+                config.audio_samples_to_ticks(info.valid_samples),
+                config.movie_time_base(),
+                Rounding::Ceil,
+            )
+        });
+        let edts = build_edts(duration, config.audio_samples_to_ticks(priming));
+        payload.extend_from_slice(&edts);
+    }
+
     // mdia (media)
     let mdia = build_audio_mdia(config);
     payload.extend_from_slice(&mdia);
 
+    // udta (user data) with the track name
+    if let Some(name) = &config.audio_track_name {
+        payload.extend_from_slice(&build_track_name_udta(name));
+    }
+
     build_box(b"trak", &payload)
 }
 
+/// Build edts box with a single edit starting at `media_time`
+fn build_edts(segment_duration: u64, media_time: u64) -> Vec<u8> {
+    let mut payload = Vec::new();
+    if segment_duration > u32::MAX as u64 || media_time > i32::MAX as u64 {
+        payload.extend_from_slice(&0x0100_0000_u32.to_be_bytes()); // Version 1 + flags
+        payload.extend_from_slice(&1u32.to_be_bytes()); // Entry count
+        payload.extend_from_slice(&segment_duration.to_be_bytes());
+        payload.extend_from_slice(&media_time.to_be_bytes());
+    } else {
+        payload.extend_from_slice(&0u32.to_be_bytes()); // Version 0 + flags
+        payload.extend_from_slice(&1u32.to_be_bytes()); // Entry count
+        payload.extend_from_slice(&(segment_duration as u32).to_be_bytes());
+        payload.extend_from_slice(&(media_time as u32).to_be_bytes());
+    }
+    payload.extend_from_slice(&0x0001_0000_u32.to_be_bytes()); // Media rate 1.0
+    build_box(b"edts", &build_box(b"elst", &payload))
+}
+
+/// `mean` of the standard iTunes freeform tags
+const ITUNES_TAG_MEAN: &str = "com.apple.iTunes";
+
+/// `mean` and `name` of the freeform tag holding `session_metadata`
+pub const SESSION_METADATA_MEAN: &str = "com.maycast.recorder";
+pub const SESSION_METADATA_NAME: &str = "session";
+
+/// Build udta box with iTunes freeform (`----`) text tags, each given as
+/// (mean, name, value)
+fn build_itunes_udta(tags: &[(&str, &str, &str)]) -> Vec<u8> {
+    // hdlr inside meta: 'mdir' handler, 'appl' reserved
+    let mut hdlr = Vec::new();
+    hdlr.extend_from_slice(&0u32.to_be_bytes()); // Version + flags
+    hdlr.extend_from_slice(&0u32.to_be_bytes()); // Pre-defined
+    hdlr.extend_from_slice(b"mdir");
+    hdlr.extend_from_slice(b"appl");
+    hdlr.extend_from_slice(&[0u8; 8]); // Reserved
+    hdlr.push(0); // Empty name
+
+    let mut items = Vec::new();
+    for (mean, name, value) in tags {
+        let mut mean_box = 0u32.to_be_bytes().to_vec(); // Version + flags
+        mean_box.extend_from_slice(mean.as_bytes());
+        let mut name_box = 0u32.to_be_bytes().to_vec(); // Version + flags
+        name_box.extend_from_slice(name.as_bytes());
+        let mut data = 1u32.to_be_bytes().to_vec(); // Type: UTF-8 text
+        data.extend_from_slice(&0u32.to_be_bytes()); // Locale
+        data.extend_from_slice(value.as_bytes());
+
+        let mut tag = build_box(b"mean", &mean_box);
+        tag.extend_from_slice(&build_box(b"name", &name_box));
+        tag.extend_from_slice(&build_box(b"data", &data));
+        items.extend_from_slice(&build_box(b"----", &tag));
+    }
+    let ilst = build_box(b"ilst", &items);
+
+    let mut meta = 0u32.to_be_bytes().to_vec(); // Version + flags
+    meta.extend_from_slice(&build_box(b"hdlr", &hdlr));
+    meta.extend_from_slice(&ilst);
+    build_box(b"udta", &build_box(b"meta", &meta))
+}
+
+/// Value of an iTunes freeform tag in the moov of `file` (an init segment
+/// or a whole file), if present
+pub(crate) fn read_itunes_tag(
+    file: &[u8],
+    mean: &str,
+    name: &str,
+) -> Result<Option<String>, String> {
+    let Some(moov) = find_box(file, b"moov")? else {
+        return Ok(None);
+    };
+    let Some(udta) = find_box(moov.payload, b"udta")? else {
+        return Ok(None);
+    };
+    let Some(meta) = find_box(udta.payload, b"meta")? else {
+        return Ok(None);
+    };
+    let Some(ilst) = find_box(meta.payload.get(4..).unwrap_or(&[]), b"ilst")? else {
+        return Ok(None);
+    };
+    for item in parse_boxes(ilst.payload)?
+        .into_iter()
+        .filter(|b| &b.typ == b"----")
+    {
+        let field = |typ: &[u8; 4], skip: usize| -> Result<Vec<u8>, String> {
+            Ok(find_box(item.payload, typ)?
+                .and_then(|b| b.payload.get(skip..))
+                .unwrap_or(&[])
+                .to_vec())
+        };
+        if field(b"mean", 4)? == mean.as_bytes() && field(b"name", 4)? == name.as_bytes() {
+            let value = field(b"data", 8)?;
+            return String::from_utf8(value)
+                .map(Some)
+                .map_err(|_| format!("Tag {} is not UTF-8", name));
+        }
+    }
+    Ok(None)
+}
+
 /// Build audio tkhd (track header) box
 fn build_audio_tkhd(track_id: u32) -> Vec<u8> {
     let mut payload = Vec::new();
@@ -1023,7 +3096,7 @@ fn build_audio_mdia(config: &MuxideConfig) -> Vec<u8> {
     payload.extend_from_slice(&mdhd);
 
     // hdlr (handler) - sound
-    let hdlr = build_hdlr(b"soun", b"SoundHandler\0");
+    let hdlr = build_hdlr(b"soun", config.audio_track_name.as_deref(), "SoundHandler");
     payload.extend_from_slice(&hdlr);
 
     // minf (media info)
@@ -1079,20 +3152,24 @@ fn build_audio_stbl(config: &MuxideConfig) -> Vec<u8> {
 }
 
 /// Build audio stsd (sample description) box
+///
+/// Lists the configurations used before a `change_audio_config` first and
+/// the current one last.
 fn build_audio_stsd(config: &MuxideConfig) -> Vec<u8> {
-    let mp4a = build_mp4a(config);
-
     let mut payload = Vec::new();
     payload.extend_from_slice(&0u32.to_be_bytes()); // Version + flags
-    payload.extend_from_slice(&1u32.to_be_bytes()); // Entry count
-    payload.extend_from_slice(&mp4a);
+    payload.extend_from_slice(&config.audio_description_index().to_be_bytes()); // Entry count
+    for entry in &config.previous_audio_configs {
+        payload.extend_from_slice(&build_mp4a(entry));
+    }
+    payload.extend_from_slice(&build_mp4a(&config.audio_sample_entry()));
     build_box(b"stsd", &payload)
 }
 
 /// Build mp4a (AAC sample entry) box
-fn build_mp4a(config: &MuxideConfig) -> Vec<u8> {
-    let sample_rate = config.audio_sample_rate.unwrap_or(48000);
-    let channels = config.audio_channels.unwrap_or(2);
+fn build_mp4a(entry: &AudioSampleEntry) -> Vec<u8> {
+    let sample_rate = entry.sample_rate;
+    let channels = entry.channels;
 
     let mut payload = Vec::new();
     payload.extend_from_slice(&[0u8; 6]); // Reserved
@@ -1108,22 +3185,16 @@ fn build_mp4a(config: &MuxideConfig) -> Vec<u8> {
     payload.extend_from_slice(&(sample_rate << 16).to_be_bytes());
 
     // esds (Elementary Stream Descriptor) box
-    let esds = build_esds(config);
+    let esds = build_esds(entry);
     payload.extend_from_slice(&esds);
 
     build_box(b"mp4a", &payload)
 }
 
 /// Build esds (Elementary Stream Descriptor) box
-fn build_esds(config: &MuxideConfig) -> Vec<u8> {
-    let sample_rate = config.audio_sample_rate.unwrap_or(48000);
-    let channels = config.audio_channels.unwrap_or(2);
-
+fn build_esds(entry: &AudioSampleEntry) -> Vec<u8> {
     // Build or use provided AudioSpecificConfig
-    let audio_specific_config = config
-        .audio_specific_config
-        .clone()
-        .unwrap_or_else(|| build_audio_specific_config(sample_rate, channels));
+    let audio_specific_config = entry.audio_specific_config_or_default();
 
     // ES Descriptor
     let mut es_descriptor = Vec::new();
@@ -1183,6 +3254,19 @@ fn build_descriptor(tag: u8, data: &[u8]) -> Vec<u8> {
     result
 }
 
+/// Sample rates of the samplingFrequencyIndex values 0-12 (ISO 14496-3)
+pub(crate) const SAMPLING_FREQUENCIES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// samplingFrequencyIndex of a sample rate, if it has one
+pub(crate) fn sampling_frequency_index(sample_rate: u32) -> Option<u8> {
+    SAMPLING_FREQUENCIES
+        .iter()
+        .position(|&rate| rate == sample_rate)
+        .map(|index| index as u8)
+}
+
 /// Build AudioSpecificConfig for AAC-LC
 fn build_audio_specific_config(sample_rate: u32, channels: u16) -> Vec<u8> {
     // AudioSpecificConfig structure (ISO 14496-3):
@@ -1190,22 +3274,7 @@ fn build_audio_specific_config(sample_rate: u32, channels: u16) -> Vec<u8> {
     // - samplingFrequencyIndex (4 bits): index into frequency table
     // - channelConfiguration (4 bits): channel count
 
-    let sample_rate_index = match sample_rate {
-        96000 => 0,
-        88200 => 1,
-        64000 => 2,
-        48000 => 3,
-        44100 => 4,
-        32000 => 5,
-        24000 => 6,
-        22050 => 7,
-        16000 => 8,
-        12000 => 9,
-        11025 => 10,
-        8000 => 11,
-        7350 => 12,
-        _ => 3, // Default to 48000 Hz
-    };
+    let sample_rate_index = sampling_frequency_index(sample_rate).unwrap_or(3); // Default to 48000 Hz
 
     let channel_config = channels.min(7) as u8; // Max 7 for standard configs
 
@@ -1218,17 +3287,209 @@ fn build_audio_specific_config(sample_rate: u32, channels: u16) -> Vec<u8> {
     vec![byte0, byte1]
 }
 
-// ============================================================================
-// Media Segment Building Functions (moof + mdat)
-// ============================================================================
-
+/// A silent AAC-LC frame (1024 samples) for the configured channel layout,
+/// to keep the audio timeline going while the input is muted
+pub(crate) fn silent_aac_frame(config: &MuxideConfig) -> Result<&'static [u8], String> {
+    if let Some(asc) = &config.audio_specific_config {
+        if asc.first().map(|b| b >> 3) != Some(2) {
+            return Err("Silent audio is only available for AAC-LC".to_string());
+        }
+    }
+    // What libfdk_aac encodes for digital silence
+    match config.audio_channels {
+        Some(1) => Ok(&[0x00, 0xC8, 0x00, 0x80, 0x23, 0x80]),
+        Some(2) => Ok(&[0x21, 0x00, 0x49, 0x90, 0x02, 0x19, 0x00, 0x23, 0x80]),
+        channels => Err(format!(
+            "Silent audio is not available for {} channels",
+            channels.unwrap_or_default()
+        )),
+    }
+}
+
+// ============================================================================
+// Media Segment Building Functions (moof + mdat)
+// ============================================================================
+
+/// A sample supplied from outside the muxer, for `build_media_segment`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentSample {
+    /// Sample data: AVCC video or a raw AAC frame
+    #[tsify(type = "Uint8Array | number[]")]
+    pub data: Vec<u8>,
+    /// Presentation timestamp in track timescale units
+    pub pts: u64,
+    /// Decode timestamp in track timescale units; audio ignores it
+    pub dts: u64,
+    /// Duration in track timescale units; 0 infers a video sample's
+    /// duration from the next one, as the muxer does. Required for audio.
+    #[serde(default)]
+    pub duration: u32,
+    /// trun sample flags; audio ignores them
+    #[serde(default)]
+    pub flags: SampleFlags,
+    /// Auxiliary information of a video sample (at most 255 bytes), see
+    /// `MediaSegmentSpec::aux_info_type`
+    #[serde(default)]
+    #[tsify(optional, type = "Uint8Array | number[]")]
+    pub aux: Vec<u8>,
+}
+
+/// Sample lists of a standalone media segment, as passed from JS
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentSamples {
+    #[serde(default)]
+    pub video: Vec<SegmentSample>,
+    #[serde(default)]
+    pub audio: Vec<SegmentSample>,
+}
+
+/// Where a standalone media segment sits in its track layout and timeline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaSegmentSpec {
+    /// mfhd sequence number
+    pub sequence_number: u32,
+    /// Whether the init segment has a video track (track 1); audio is then
+    /// track 2, otherwise track 1
+    pub has_video: bool,
+    pub has_audio: bool,
+    /// tfdt of the video track, in video timescale units
+    #[serde(default)]
+    pub video_base_decode_time: u64,
+    /// tfdt of the audio track, in audio timescale units
+    #[serde(default)]
+    pub audio_base_decode_time: u64,
+    /// Audio sample description, 1 unless the audio config changed
+    #[serde(default = "default_description_index")]
+    pub audio_description_index: u32,
+    /// Position of the segment in the file, written as the tfhd base data
+    /// offset (`DataOffsetMode::Absolute`); default-base-is-moof if absent
+    #[serde(default)]
+    #[tsify(optional)]
+    pub base_data_offset: Option<u64>,
+    /// Four-character type of the video samples' auxiliary information,
+    /// required if any sample has some
+    #[serde(default)]
+    #[tsify(optional)]
+    pub aux_info_type: Option<String>,
+}
+
+fn default_description_index() -> u32 {
+    1
+}
+
+/// Build a media segment (moof + mdat) from sample lists, without a muxer
+///
+/// The layout matches what `MuxideMuxerState` writes for the same tracks,
+/// so the segment plays after the init segment of an equivalent config; a
+/// server-side re-fragmenter or a test constructs segments directly with it.
+/// Video-and-audio segments need at least one video sample.
+pub fn build_media_segment(
+    spec: &MediaSegmentSpec,
+    video: &[SegmentSample],
+    audio: &[SegmentSample],
+) -> Result<Vec<u8>, String> {
+    if spec.audio_description_index == 0 {
+        return Err("Audio sample description index starts at 1".to_string());
+    }
+    if !spec.has_audio && !audio.is_empty() {
+        return Err("Audio samples for a segment without an audio track".to_string());
+    }
+    if let Some(i) = audio.iter().position(|s| s.duration == 0) {
+        return Err(format!("Audio sample {} has no duration", i));
+    }
+    if audio.iter().any(|s| !s.aux.is_empty()) {
+        return Err("Auxiliary information is only supported for video samples".to_string());
+    }
+    let aux_info_type = spec
+        .aux_info_type
+        .as_deref()
+        .map(parse_fourcc)
+        .transpose()?;
+    if aux_info_type.is_none() && video.iter().any(|s| !s.aux.is_empty()) {
+        return Err("Auxiliary information needs an auxInfoType".to_string());
+    }
+    let audio_samples: Vec<AudioSample> = audio
+        .iter()
+        .map(|s| AudioSample {
+            pts: s.pts,
+            data: s.data.clone(),
+            size: s.data.len() as u32,
+            duration: s.duration,
+        })
+        .collect();
+
+    if !spec.has_video {
+        if !video.is_empty() {
+            return Err("Video samples for a segment without a video track".to_string());
+        }
+        if audio_samples.is_empty() {
+            return Err("Media segment has no samples".to_string());
+        }
+        return Ok(build_media_segment_audio_only(
+            &audio_samples,
+            spec.sequence_number,
+            spec.audio_base_decode_time,
+            spec.audio_description_index,
+            spec.base_data_offset,
+        ));
+    }
+    if video.is_empty() {
+        return Err("Media segment has no video samples".to_string());
+    }
+    let mut video_samples = Vec::with_capacity(video.len());
+    for (i, s) in video.iter().enumerate() {
+        s.flags.validate()?;
+        if i > 0 && s.dts <= video[i - 1].dts {
+            return Err(format!("Video sample {} does not increase the DTS", i));
+        }
+        let aux_size = u8::try_from(s.aux.len()).map_err(|_| {
+            format!(
+                "Auxiliary information of video sample {} exceeds 255 bytes",
+                i
+            )
+        })?;
+        video_samples.push(VideoSample {
+            pts: s.pts,
+            dts: s.dts,
+            data: s.data.clone(),
+            size: s.data.len() as u32,
+            flags: s.flags,
+            duration: (s.duration > 0).then_some(s.duration),
+            aux: s.aux.clone(),
+            aux_size,
+        });
+    }
+    Ok(build_media_segment_av(
+        &video_samples,
+        &audio_samples,
+        spec.sequence_number,
+        spec.video_base_decode_time,
+        spec.audio_base_decode_time,
+        spec.audio_description_index,
+        spec.has_audio,
+        aux_info_type,
+        spec.base_data_offset,
+    ))
+}
+
 /// Build media segment with audio only (no video track)
+///
+/// `base_data_offset` is the segment's position in the file, written to
+/// the tfhd instead of default-base-is-moof (`DataOffsetMode::Absolute`).
 fn build_media_segment_audio_only(
     audio_samples: &[AudioSample],
     sequence_number: u32,
     audio_base_decode_time: u64,
+    audio_description_index: u32,
+    base_data_offset: Option<u64>,
 ) -> Vec<u8> {
-    let audio_data_size: usize = audio_samples.iter().map(|s| s.data.len()).sum();
+    let audio_data_size: usize = audio_samples.iter().map(|s| s.size as usize).sum();
     let mdat_payload_size = audio_data_size;
 
     // Build moof to get its size (with placeholder offset)
@@ -1236,7 +3497,9 @@ fn build_media_segment_audio_only(
         audio_samples,
         sequence_number,
         audio_base_decode_time,
+        audio_description_index,
         0, // placeholder offset
+        base_data_offset,
     );
     let moof_size = moof_placeholder.len() as u32;
 
@@ -1248,7 +3511,9 @@ fn build_media_segment_audio_only(
         audio_samples,
         sequence_number,
         audio_base_decode_time,
+        audio_description_index,
         audio_data_offset,
+        base_data_offset,
     );
 
     // Build complete segment
@@ -1273,7 +3538,9 @@ fn build_moof_audio_only(
     audio_samples: &[AudioSample],
     sequence_number: u32,
     audio_base_decode_time: u64,
+    audio_description_index: u32,
     audio_data_offset: u32,
+    base_data_offset: Option<u64>,
 ) -> Vec<u8> {
     let mut payload = Vec::new();
 
@@ -1282,28 +3549,44 @@ fn build_moof_audio_only(
     payload.extend_from_slice(&mfhd);
 
     // Audio traf (track_id = 1 in audio-only mode)
-    let audio_traf =
-        build_audio_traf_with_track_id(audio_samples, audio_base_decode_time, audio_data_offset, 1);
+    let audio_traf = build_audio_traf_with_track_id(
+        audio_samples,
+        audio_base_decode_time,
+        audio_description_index,
+        audio_data_offset,
+        1,
+        base_data_offset,
+    );
     payload.extend_from_slice(&audio_traf);
 
     build_box(b"moof", &payload)
 }
 
 /// Build media segment with video and audio
+///
+/// `base_data_offset` as for `build_media_segment_audio_only`. Auxiliary
+/// information of the video samples follows the audio samples in the mdat,
+/// referenced with saiz/saio boxes of `aux_info_type`.
+#[allow(clippy::too_many_arguments)]
 fn build_media_segment_av(
     video_samples: &[VideoSample],
     audio_samples: &[AudioSample],
     sequence_number: u32,
     video_base_decode_time: u64,
     audio_base_decode_time: u64,
-    config: &MuxideConfig,
+    audio_description_index: u32,
+    has_audio_track: bool,
+    aux_info_type: Option<[u8; 4]>,
+    base_data_offset: Option<u64>,
 ) -> Vec<u8> {
-    let has_audio = config.has_audio() && !audio_samples.is_empty();
+    let has_audio = has_audio_track && !audio_samples.is_empty();
 
     // Calculate total mdat size
-    let video_data_size: usize = video_samples.iter().map(|s| s.data.len()).sum();
-    let audio_data_size: usize = audio_samples.iter().map(|s| s.data.len()).sum();
-    let mdat_payload_size = video_data_size + audio_data_size;
+    let video_data_size: usize = video_samples.iter().map(|s| s.size as usize).sum();
+    let audio_data_size: usize = audio_samples.iter().map(|s| s.size as usize).sum();
+    let aux_data_size: usize = video_samples.iter().map(|s| s.aux_size as usize).sum();
+    let mdat_payload_size = video_data_size + audio_data_size + aux_data_size;
+    let aux_info_type = aux_info_type.filter(|_| aux_data_size > 0);
 
     // Build moof to get its size (with placeholder offset)
     let moof_placeholder = build_moof_av(
@@ -1312,9 +3595,15 @@ fn build_media_segment_av(
         sequence_number,
         video_base_decode_time,
         audio_base_decode_time,
+        audio_description_index,
         0, // placeholder video offset
         0, // placeholder audio offset
+        aux_info_type.map(|info_type| SampleAuxInfo {
+            info_type,
+            offset: 0, // placeholder
+        }),
         has_audio,
+        base_data_offset,
     );
     let moof_size = moof_placeholder.len() as u32;
 
@@ -1323,6 +3612,11 @@ fn build_media_segment_av(
     let video_data_offset = moof_size + 8;
     // Audio data starts after video data
     let audio_data_offset = video_data_offset + video_data_size as u32;
+    // Auxiliary information comes last
+    let aux_info = aux_info_type.map(|info_type| SampleAuxInfo {
+        info_type,
+        offset: audio_data_offset + audio_data_size as u32,
+    });
 
     // Rebuild moof with correct offsets
     let moof = build_moof_av(
@@ -1331,9 +3625,12 @@ fn build_media_segment_av(
         sequence_number,
         video_base_decode_time,
         audio_base_decode_time,
+        audio_description_index,
         video_data_offset,
         audio_data_offset,
+        aux_info,
         has_audio,
+        base_data_offset,
     );
 
     // Build complete segment
@@ -1345,13 +3642,19 @@ fn build_media_segment_av(
     segment.extend_from_slice(&mdat_size.to_be_bytes());
     segment.extend_from_slice(b"mdat");
 
-    // mdat payload: video samples first, then audio samples
+    // mdat payload: video samples first, then audio samples, then the
+    // auxiliary information of the video samples
     for sample in video_samples {
         segment.extend_from_slice(&sample.data);
     }
     for sample in audio_samples {
         segment.extend_from_slice(&sample.data);
     }
+    if aux_info.is_some() {
+        for sample in video_samples {
+            segment.extend_from_slice(&sample.aux);
+        }
+    }
 
     segment
 }
@@ -1364,9 +3667,12 @@ fn build_moof_av(
     sequence_number: u32,
     video_base_decode_time: u64,
     audio_base_decode_time: u64,
+    audio_description_index: u32,
     video_data_offset: u32,
     audio_data_offset: u32,
+    video_aux_info: Option<SampleAuxInfo>,
     has_audio: bool,
+    base_data_offset: Option<u64>,
 ) -> Vec<u8> {
     let mut payload = Vec::new();
 
@@ -1375,12 +3681,24 @@ fn build_moof_av(
     payload.extend_from_slice(&mfhd);
 
     // Video traf
-    let video_traf = build_video_traf(video_samples, video_base_decode_time, video_data_offset);
+    let video_traf = build_video_traf(
+        video_samples,
+        video_base_decode_time,
+        video_data_offset,
+        video_aux_info,
+        base_data_offset,
+    );
     payload.extend_from_slice(&video_traf);
 
     // Audio traf (if enabled and has samples)
     if has_audio && !audio_samples.is_empty() {
-        let audio_traf = build_audio_traf(audio_samples, audio_base_decode_time, audio_data_offset);
+        let audio_traf = build_audio_traf(
+            audio_samples,
+            audio_base_decode_time,
+            audio_description_index,
+            audio_data_offset,
+            base_data_offset,
+        );
         payload.extend_from_slice(&audio_traf);
     }
 
@@ -1400,11 +3718,13 @@ fn build_video_traf(
     samples: &[VideoSample],
     base_media_decode_time: u64,
     data_offset: u32,
+    aux_info: Option<SampleAuxInfo>,
+    base_data_offset: Option<u64>,
 ) -> Vec<u8> {
     let mut payload = Vec::new();
 
     // tfhd (track fragment header)
-    let tfhd = build_tfhd(1); // track_id = 1
+    let tfhd = build_tfhd(1, 1, base_data_offset); // track_id = 1
     payload.extend_from_slice(&tfhd);
 
     // tfdt (track fragment decode time)
@@ -1415,6 +3735,12 @@ fn build_video_traf(
     let trun = build_video_trun(samples, data_offset);
     payload.extend_from_slice(&trun);
 
+    // saiz + saio (sample auxiliary information sizes and offset)
+    if let Some(aux_info) = aux_info {
+        payload.extend_from_slice(&build_saiz(samples, aux_info.info_type));
+        payload.extend_from_slice(&build_saio(aux_info));
+    }
+
     build_box(b"traf", &payload)
 }
 
@@ -1422,22 +3748,33 @@ fn build_video_traf(
 fn build_audio_traf(
     samples: &[AudioSample],
     base_media_decode_time: u64,
+    sample_description_index: u32,
     data_offset: u32,
+    base_data_offset: Option<u64>,
 ) -> Vec<u8> {
-    build_audio_traf_with_track_id(samples, base_media_decode_time, data_offset, 2)
+    build_audio_traf_with_track_id(
+        samples,
+        base_media_decode_time,
+        sample_description_index,
+        data_offset,
+        2,
+        base_data_offset,
+    )
 }
 
 /// Build audio traf (track fragment) box with configurable track_id
 fn build_audio_traf_with_track_id(
     samples: &[AudioSample],
     base_media_decode_time: u64,
+    sample_description_index: u32,
     data_offset: u32,
     track_id: u32,
+    base_data_offset: Option<u64>,
 ) -> Vec<u8> {
     let mut payload = Vec::new();
 
     // tfhd (track fragment header)
-    let tfhd = build_tfhd(track_id);
+    let tfhd = build_tfhd(track_id, sample_description_index, base_data_offset);
     payload.extend_from_slice(&tfhd);
 
     // tfdt (track fragment decode time)
@@ -1451,15 +3788,176 @@ fn build_audio_traf_with_track_id(
     build_box(b"traf", &payload)
 }
 
+/// Type and location of the auxiliary information of a traf's samples
+#[derive(Debug, Clone, Copy)]
+struct SampleAuxInfo {
+    info_type: [u8; 4],
+    /// Offset of the first sample's information from the tfhd base (the
+    /// moof), with the others following contiguously
+    offset: u32,
+}
+
+/// saiz/saio flag: aux_info_type and aux_info_type_parameter are present
+const AUX_INFO_TYPE_PRESENT: u32 = 0x000001;
+
+/// Build saiz (sample auxiliary information sizes) box
+fn build_saiz(samples: &[VideoSample], info_type: [u8; 4]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&AUX_INFO_TYPE_PRESENT.to_be_bytes()); // Version 0 + flags
+    payload.extend_from_slice(&info_type);
+    payload.extend_from_slice(&0u32.to_be_bytes()); // aux_info_type_parameter
+
+    // A default size replaces the table when all samples agree
+    let first = samples.first().map_or(0, |s| s.aux_size);
+    if samples.iter().all(|s| s.aux_size == first) {
+        payload.push(first);
+        payload.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    } else {
+        payload.push(0);
+        payload.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+        payload.extend(samples.iter().map(|s| s.aux_size));
+    }
+    build_box(b"saiz", &payload)
+}
+
+/// Build saio (sample auxiliary information offsets) box with one entry
+fn build_saio(aux_info: SampleAuxInfo) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&AUX_INFO_TYPE_PRESENT.to_be_bytes()); // Version 0 + flags
+    payload.extend_from_slice(&aux_info.info_type);
+    payload.extend_from_slice(&0u32.to_be_bytes()); // aux_info_type_parameter
+    payload.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+    payload.extend_from_slice(&aux_info.offset.to_be_bytes());
+    build_box(b"saio", &payload)
+}
+
+/// Auxiliary information read back from a traf
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TrafAuxInfo {
+    pub info_type: [u8; 4],
+    /// Bytes of each sample, in order
+    pub samples: Vec<Vec<u8>>,
+}
+
+/// Auxiliary information of a traf's samples, or None without saiz/saio
+/// boxes
+///
+/// `moof_offset` is where the traf's moof starts in `segment`, from which
+/// the saio offset counts.
+pub(crate) fn read_sample_aux(
+    segment: &[u8],
+    moof_offset: usize,
+    traf: &[u8],
+) -> Result<Option<TrafAuxInfo>, String> {
+    let (Some(saiz), Some(saio)) = (find_box(traf, b"saiz")?, find_box(traf, b"saio")?) else {
+        return Ok(None);
+    };
+    let flags = read_u32(saiz.payload, 0)? & 0x00FF_FFFF;
+    if flags & AUX_INFO_TYPE_PRESENT == 0 {
+        return Err("saiz box has no aux_info_type".to_string());
+    }
+    let info_type: [u8; 4] = saiz
+        .payload
+        .get(4..8)
+        .ok_or("Truncated saiz box")?
+        .try_into()
+        .unwrap();
+    let default_size = *saiz.payload.get(12).ok_or("Truncated saiz box")?;
+    let count = read_u32(saiz.payload, 13)? as usize;
+    let sizes: Vec<u8> = if default_size != 0 {
+        vec![default_size; count]
+    } else {
+        saiz.payload
+            .get(17..17 + count)
+            .ok_or("Truncated saiz box")?
+            .to_vec()
+    };
+
+    let version = *saio.payload.first().ok_or("Truncated saio box")?;
+    let mut pos = if read_u32(saio.payload, 0)? & AUX_INFO_TYPE_PRESENT != 0 {
+        12
+    } else {
+        4
+    };
+    if read_u32(saio.payload, pos)? != 1 {
+        return Err("Only a single saio offset is supported".to_string());
+    }
+    pos += 4;
+    let offset = if version == 1 {
+        read_u64(saio.payload, pos)? as usize
+    } else {
+        read_u32(saio.payload, pos)? as usize
+    };
+
+    let mut data_pos = moof_offset + offset;
+    let mut aux = Vec::with_capacity(count);
+    for size in sizes {
+        aux.push(
+            segment
+                .get(data_pos..data_pos + size as usize)
+                .ok_or("Auxiliary information outside of segment")?
+                .to_vec(),
+        );
+        data_pos += size as usize;
+    }
+    Ok(Some(TrafAuxInfo {
+        info_type,
+        samples: aux,
+    }))
+}
+
 /// Build tfhd (track fragment header) box
-fn build_tfhd(track_id: u32) -> Vec<u8> {
-    // Flags: 0x020000 = default-base-is-moof
+///
+/// The sample description index is only written when it differs from the
+/// trex default of 1. With a `base_data_offset`, trun data offsets count
+/// from that file position instead of from the moof (default-base-is-moof).
+fn build_tfhd(
+    track_id: u32,
+    sample_description_index: u32,
+    base_data_offset: Option<u64>,
+) -> Vec<u8> {
+    // Flags: 0x020000 = default-base-is-moof, 0x000001 =
+    // base-data-offset-present, 0x000002 = sample-description-index-present
+    let mut flags = match base_data_offset {
+        Some(_) => TFHD_BASE_DATA_OFFSET,
+        None => TFHD_DEFAULT_BASE_IS_MOOF,
+    };
+    if sample_description_index != 1 {
+        flags |= TFHD_SAMPLE_DESCRIPTION_INDEX;
+    }
     let mut payload = Vec::new();
-    payload.extend_from_slice(&0x0002_0000_u32.to_be_bytes()); // Version 0 + flags
+    payload.extend_from_slice(&flags.to_be_bytes()); // Version 0 + flags
     payload.extend_from_slice(&track_id.to_be_bytes());
+    if let Some(offset) = base_data_offset {
+        payload.extend_from_slice(&offset.to_be_bytes());
+    }
+    if sample_description_index != 1 {
+        payload.extend_from_slice(&sample_description_index.to_be_bytes());
+    }
     build_box(b"tfhd", &payload)
 }
 
+/// tfhd flag: a sample description index follows the track ID
+const TFHD_SAMPLE_DESCRIPTION_INDEX: u32 = 0x000002;
+/// tfhd flag: a base data offset follows the track ID
+const TFHD_BASE_DATA_OFFSET: u32 = 0x000001;
+/// tfhd flag: data offsets count from the start of the moof
+const TFHD_DEFAULT_BASE_IS_MOOF: u32 = 0x020000;
+
+/// Sample description index of a tfhd box, 1 (the trex default) if absent
+pub(crate) fn read_tfhd_description_index(tfhd: &[u8]) -> Result<u32, String> {
+    let flags = read_u32(tfhd, 0)? & 0x00FF_FFFF;
+    if flags & TFHD_SAMPLE_DESCRIPTION_INDEX == 0 {
+        return Ok(1);
+    }
+    let pos = if flags & TFHD_BASE_DATA_OFFSET != 0 {
+        16
+    } else {
+        8
+    };
+    read_u32(tfhd, pos)
+}
+
 /// Build tfdt (track fragment decode time) box
 fn build_tfdt(base_media_decode_time: u64) -> Vec<u8> {
     // Version 1 for 64-bit decode time
@@ -1477,45 +3975,64 @@ fn build_video_trun(samples: &[VideoSample], data_offset: u32) -> Vec<u8> {
     // 0x000200 = sample-size-present
     // 0x000400 = sample-flags-present
     // 0x000800 = sample-composition-time-offset-present
-    let flags: u32 = 0x000001 | 0x000100 | 0x000200 | 0x000400 | 0x000800;
+    //
+    // Composition offsets are written only when a sample of the run has one
+    // (B-frames); without them the trun stays version 0, which some legacy
+    // players require, and saves 4 bytes per sample.
+    let has_composition_offsets = samples.iter().any(|s| s.pts != s.dts);
+    let mut flags: u32 = 0x000001 | 0x000100 | 0x000200 | 0x000400;
+    if has_composition_offsets {
+        flags |= 0x000800;
+    }
 
     let mut payload = Vec::new();
     // Version 1 for signed composition time offsets
-    payload.extend_from_slice(&(0x0100_0000 | flags).to_be_bytes());
+    let version: u32 = if has_composition_offsets { 1 } else { 0 };
+    payload.extend_from_slice(&(version << 24 | flags).to_be_bytes());
     payload.extend_from_slice(&(samples.len() as u32).to_be_bytes());
     payload.extend_from_slice(&data_offset.to_be_bytes());
 
     // Per-sample data
-    for (i, sample) in samples.iter().enumerate() {
+    for (sample, duration) in samples.iter().zip(video_durations(samples)) {
         // Sample duration
-        let duration = if i + 1 < samples.len() {
-            (samples[i + 1].dts - sample.dts) as u32
-        } else if i > 0 {
-            (sample.dts - samples[i - 1].dts) as u32
-        } else {
-            3000 // Default: 1 frame at 30fps
-        };
         payload.extend_from_slice(&duration.to_be_bytes());
 
         // Sample size
-        payload.extend_from_slice(&(sample.data.len() as u32).to_be_bytes());
+        payload.extend_from_slice(&sample.size.to_be_bytes());
 
         // Sample flags
-        let flags = if sample.is_sync {
-            0x0200_0000_u32 // depends_on = 2, is_non_sync = 0
-        } else {
-            0x0101_0000_u32 // depends_on = 1, is_non_sync = 1
-        };
-        payload.extend_from_slice(&flags.to_be_bytes());
+        payload.extend_from_slice(&sample.flags.to_bits().to_be_bytes());
 
         // Composition time offset (signed, pts - dts)
-        let cts = (sample.pts as i64 - sample.dts as i64) as i32;
-        payload.extend_from_slice(&cts.to_be_bytes());
+        if has_composition_offsets {
+            let cts = (sample.pts as i64 - sample.dts as i64) as i32;
+            payload.extend_from_slice(&cts.to_be_bytes());
+        }
     }
 
     build_box(b"trun", &payload)
 }
 
+/// Duration of each video sample in a trun
+///
+/// A duration given by the caller wins; otherwise the DTS delta to the next
+/// sample, or to the previous one for the last sample. The muxer gives a
+/// lone sample its default frame duration; without one it lasts one frame at
+/// `DEFAULT_FRAME_RATE` in the default 90 kHz timescale.
+fn video_durations(samples: &[VideoSample]) -> impl Iterator<Item = u32> + '_ {
+    samples.iter().enumerate().map(|(i, sample)| {
+        if let Some(duration) = sample.duration {
+            duration
+        } else if i + 1 < samples.len() {
+            (samples[i + 1].dts - sample.dts) as u32
+        } else if i > 0 {
+            (sample.dts - samples[i - 1].dts) as u32
+        } else {
+            90000 / DEFAULT_FRAME_RATE
+        }
+    })
+}
+
 /// Build audio trun (track run) box
 fn build_audio_trun(samples: &[AudioSample], data_offset: u32) -> Vec<u8> {
     // Flags:
@@ -1535,15 +4052,629 @@ fn build_audio_trun(samples: &[AudioSample], data_offset: u32) -> Vec<u8> {
         payload.extend_from_slice(&sample.duration.to_be_bytes());
 
         // Sample size
-        payload.extend_from_slice(&(sample.data.len() as u32).to_be_bytes());
+        payload.extend_from_slice(&sample.size.to_be_bytes());
     }
 
     build_box(b"trun", &payload)
 }
 
+/// Merges consecutive media segments of one recording into larger fragments
+///
+/// Parses the segments back into samples and rebuilds them with the same box
+/// layout as `MuxideMuxerState`, so every output fragment spans
+/// `segments_per_fragment` input segments and still starts where an input
+/// segment started. Only segments produced by this muxer are supported.
+///
+/// A fragment holds one audio sample description, so a segment switching to
+/// another one (see `change_audio_config`) closes the fragment being built
+/// early.
+pub struct Refragmenter {
+    video_track_id: Option<u32>,
+    audio_track_id: Option<u32>,
+    /// Handler and media timescale of every track of the init segment
+    handlers: Vec<(u32, [u8; 4], u32)>,
+    /// Only track whose samples are kept, see `select_track`
+    selected_track_id: Option<u32>,
+    segments_per_fragment: usize,
+    buffered_segments: usize,
+    sequence_number: u32,
+    video_base_media_decode_time: Option<u64>,
+    audio_base_media_decode_time: Option<u64>,
+    /// Sample description of the buffered audio samples
+    audio_description_index: u32,
+    /// aux_info_type of the input's video sample auxiliary information
+    video_aux_info_type: Option<[u8; 4]>,
+    video_samples: Vec<VideoSample>,
+    audio_samples: Vec<AudioSample>,
+    /// Audio samples used instead of the input's, see `replace_audio`
+    replacement_audio: Option<ReplacementAudio>,
+    /// The fragment being built is the last one (`push_last_segment`)
+    last_fragment: bool,
+}
+
+impl Refragmenter {
+    /// Create a refragmenter for segments described by `init_segment`
+    pub fn new(init_segment: &[u8], segments_per_fragment: u32) -> Result<Self, String> {
+        if segments_per_fragment == 0 {
+            return Err("Segments per fragment must be at least 1".to_string());
+        }
+        let mut video_track_id = None;
+        let mut audio_track_id = None;
+        let mut handlers = Vec::new();
+        let moov = find_box(init_segment, b"moov")?.ok_or("Init segment has no moov box")?;
+        for trak in parse_boxes(moov.payload)?
+            .into_iter()
+            .filter(|b| &b.typ == b"trak")
+        {
+            let tkhd = find_box(trak.payload, b"tkhd")?.ok_or("trak has no tkhd box")?;
+            let id_offset = if tkhd.payload.first() == Some(&1) {
+                20
+            } else {
+                12
+            };
+            let track_id = read_u32(tkhd.payload, id_offset)?;
+            let mdia = find_box(trak.payload, b"mdia")?.ok_or("trak has no mdia box")?;
+            let hdlr = find_box(mdia.payload, b"hdlr")?.ok_or("mdia has no hdlr box")?;
+            let handler: [u8; 4] = hdlr
+                .payload
+                .get(8..12)
+                .and_then(|h| h.try_into().ok())
+                .ok_or("Truncated hdlr box")?;
+            match &handler {
+                b"vide" => video_track_id = Some(track_id),
+                b"soun" => audio_track_id = Some(track_id),
+                _ => {}
+            }
+            let mdhd = find_box(mdia.payload, b"mdhd")?.ok_or("mdia has no mdhd box")?;
+            let timescale_offset = if mdhd.payload.first() == Some(&1) {
+                20
+            } else {
+                12
+            };
+            let timescale = read_u32(mdhd.payload, timescale_offset)?;
+            handlers.push((track_id, handler, timescale));
+        }
+        if video_track_id.is_none() && audio_track_id.is_none() {
+            return Err("Init segment has no video or audio track".to_string());
+        }
+        Ok(Self {
+            video_track_id,
+            audio_track_id,
+            handlers,
+            selected_track_id: None,
+            segments_per_fragment: segments_per_fragment as usize,
+            buffered_segments: 0,
+            sequence_number: 1,
+            video_base_media_decode_time: None,
+            audio_base_media_decode_time: None,
+            audio_description_index: 1,
+            video_aux_info_type: None,
+            video_samples: Vec::new(),
+            audio_samples: Vec::new(),
+            replacement_audio: None,
+            last_fragment: false,
+        })
+    }
+
+    /// Keep only `track_id`, dropping the samples of every other track
+    ///
+    /// Output fragments then have the layout of a video-only or audio-only
+    /// muxer, so the kept track is track 1 in them. Works on init segments
+    /// with any number of tracks, such as merged recordings.
+    pub fn select_track(&mut self, track_id: u32) -> Result<(), String> {
+        let (_, handler, _) = self
+            .handlers
+            .iter()
+            .find(|(id, _, _)| *id == track_id)
+            .ok_or_else(|| format!("Init segment has no track {}", track_id))?;
+        match handler {
+            b"vide" => {
+                self.video_track_id = Some(track_id);
+                self.audio_track_id = None;
+            }
+            b"soun" => {
+                self.video_track_id = None;
+                self.audio_track_id = Some(track_id);
+            }
+            _ => return Err(format!("Track {} is neither video nor audio", track_id)),
+        }
+        self.selected_track_id = Some(track_id);
+        Ok(())
+    }
+
+    /// Take the audio samples from `source` instead of the input segments
+    ///
+    /// `source` is a recording (init segment and fragments) whose audio
+    /// track has the timescale of the input's and one sample description.
+    /// The input's audio samples are dropped, video samples are kept as they
+    /// are, and each output fragment gets the replacement samples that start
+    /// before the end of its video, starting from the source's own decode
+    /// times. The last fragment (see `push_last_segment`) takes all that are
+    /// left.
+    pub fn replace_audio(&mut self, source: &[u8]) -> Result<(), String> {
+        let (Some(video_id), Some(audio_id)) = (self.video_track_id, self.audio_track_id) else {
+            return Err("Replacing audio needs a video and an audio track".to_string());
+        };
+        if self.selected_track_id.is_some() {
+            return Err("Cannot replace the audio of a selected track".to_string());
+        }
+        let mut reader = Refragmenter::new(source, u32::MAX)?;
+        let source_id = reader
+            .audio_track_id
+            .ok_or("Replacement has no audio track")?;
+        reader.select_track(source_id)?;
+        let (timescale, source_timescale) =
+            (self.timescale(audio_id)?, reader.timescale(source_id)?);
+        if timescale != source_timescale {
+            return Err(format!(
+                "Replacement audio timescale {} does not match the recording's {}",
+                source_timescale, timescale
+            ));
+        }
+
+        let mut fragment_start = None;
+        for b in parse_boxes(source)? {
+            match &b.typ {
+                b"moof" => fragment_start = Some(b.offset),
+                b"mdat" => {
+                    let start = fragment_start
+                        .take()
+                        .ok_or_else(|| format!("mdat at offset {} has no moof", b.offset))?;
+                    reader.push_segment(&source[start..b.offset + 8 + b.payload.len()])?;
+                }
+                _ => {}
+            }
+        }
+        // A change of sample description closes a fragment
+        if reader.sequence_number != 1 || reader.audio_description_index != 1 {
+            return Err("Replacement audio must have one sample description".to_string());
+        }
+        self.replacement_audio = Some(ReplacementAudio {
+            end: reader.audio_base_media_decode_time.unwrap_or(0),
+            samples: reader.audio_samples.into(),
+            video_time_base: TimeBase::new(self.timescale(video_id)? as u64),
+            audio_time_base: TimeBase::new(timescale as u64),
+        });
+        Ok(())
+    }
+
+    /// Add the final input segment, returning every fragment still open
+    pub fn push_last_segment(&mut self, segment: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        self.last_fragment = true;
+        let fragment = self.push_segment(segment)?;
+        Ok(match (fragment, self.finish()) {
+            (Some(mut fragment), Some(rest)) => {
+                fragment.extend_from_slice(&rest);
+                Some(fragment)
+            }
+            (fragment, rest) => fragment.or(rest),
+        })
+    }
+
+    /// Media timescale of `track_id`
+    fn timescale(&self, track_id: u32) -> Result<u32, String> {
+        self.handlers
+            .iter()
+            .find(|(id, _, _)| *id == track_id)
+            .map(|(_, _, timescale)| *timescale)
+            .filter(|timescale| *timescale > 0)
+            .ok_or_else(|| format!("Track {} has no timescale", track_id))
+    }
+
+    /// Add an input segment, returning an output fragment when one is complete
+    ///
+    /// When the segment changes the audio sample description, the fragment
+    /// cut short by it is returned too, ahead of any fragment it completes.
+    pub fn push_segment(&mut self, segment: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let mut trafs = Vec::new();
+        for moof in parse_boxes(segment)?
+            .into_iter()
+            .filter(|b| &b.typ == b"moof")
+        {
+            for traf in parse_boxes(moof.payload)?
+                .into_iter()
+                .filter(|b| &b.typ == b"traf")
+            {
+                trafs.push((moof.offset, traf.payload));
+            }
+        }
+        let mut cut = None;
+        for (_, traf) in &trafs {
+            let tfhd = find_box(traf, b"tfhd")?.ok_or("traf has no tfhd box")?;
+            if self.replacement_audio.is_some()
+                || Some(read_u32(tfhd.payload, 4)?) != self.audio_track_id
+            {
+                continue;
+            }
+            let index = read_tfhd_description_index(tfhd.payload)?;
+            if index != self.audio_description_index {
+                cut = self.finish();
+                self.audio_description_index = index;
+            }
+        }
+        for (moof_offset, traf) in trafs {
+            self.read_traf(segment, moof_offset, traf)?;
+        }
+        self.buffered_segments += 1;
+        let complete = if self.buffered_segments == self.segments_per_fragment {
+            self.finish()
+        } else {
+            None
+        };
+        Ok(match (cut, complete) {
+            (Some(mut cut), Some(complete)) => {
+                cut.extend_from_slice(&complete);
+                Some(cut)
+            }
+            (cut, complete) => cut.or(complete),
+        })
+    }
+
+    /// Build a fragment from whatever is still buffered
+    pub fn finish(&mut self) -> Option<Vec<u8>> {
+        if self.video_samples.is_empty() && self.audio_samples.is_empty() {
+            return None;
+        }
+        self.take_replacement_audio();
+        let video_base = self.video_base_media_decode_time.take().unwrap_or(0);
+        let audio_base = self.audio_base_media_decode_time.take().unwrap_or(0);
+        let fragment = if self.video_track_id.is_some() {
+            build_media_segment_av(
+                &self.video_samples,
+                &self.audio_samples,
+                self.sequence_number,
+                video_base,
+                audio_base,
+                self.audio_description_index,
+                self.audio_track_id.is_some(),
+                self.video_aux_info_type,
+                None,
+            )
+        } else {
+            build_media_segment_audio_only(
+                &self.audio_samples,
+                self.sequence_number,
+                audio_base,
+                self.audio_description_index,
+                None,
+            )
+        };
+        self.sequence_number += 1;
+        self.buffered_segments = 0;
+        self.video_samples.clear();
+        self.audio_samples.clear();
+        Some(fragment)
+    }
+
+    /// Move the replacement audio samples that start before the end of the
+    /// buffered video into the fragment
+    fn take_replacement_audio(&mut self) {
+        let Some(replacement) = self.replacement_audio.as_mut() else {
+            return;
+        };
+        let video_end = self.video_base_media_decode_time.unwrap_or(0)
+            + video_durations(&self.video_samples)
+                .map(|d| d as u64)
+                .sum::<u64>();
+        let cut = replacement.video_time_base.convert(
+            video_end,
+            replacement.audio_time_base,
+            Rounding::Nearest,
+        );
+        // An empty audio run starts where the replacement got to
+        self.audio_base_media_decode_time = Some(replacement.end);
+        while let Some(sample) = replacement.samples.front() {
+            if sample.pts >= cut && !self.last_fragment {
+                break;
+            }
+            if self.audio_samples.is_empty() {
+                self.audio_base_media_decode_time = Some(sample.pts);
+            }
+            replacement.end = sample.pts + sample.duration as u64;
+            self.audio_samples.extend(replacement.samples.pop_front());
+        }
+    }
+
+    /// Collect the samples of one track fragment
+    fn read_traf(&mut self, segment: &[u8], moof_offset: usize, traf: &[u8]) -> Result<(), String> {
+        let tfhd = find_box(traf, b"tfhd")?.ok_or("traf has no tfhd box")?;
+        let track_id = read_u32(tfhd.payload, 4)?;
+        if self.selected_track_id.is_some_and(|id| id != track_id)
+            || (self.replacement_audio.is_some() && Some(track_id) == self.audio_track_id)
+        {
+            return Ok(());
+        }
+        let tfdt = find_box(traf, b"tfdt")?.ok_or("traf has no tfdt box")?;
+        let base_decode_time = if tfdt.payload.first() == Some(&1) {
+            read_u64(tfdt.payload, 4)?
+        } else {
+            read_u32(tfdt.payload, 4)? as u64
+        };
+        let trun = find_box(traf, b"trun")?.ok_or("traf has no trun box")?;
+        let samples = read_trun(trun.payload)?;
+        let mut aux = Vec::new();
+        if Some(track_id) == self.video_track_id {
+            if let Some(info) = read_sample_aux(segment, moof_offset, traf)? {
+                self.video_aux_info_type = Some(info.info_type);
+                aux = info.samples;
+            }
+        }
+
+        let mut data_pos = moof_offset + samples.data_offset as usize;
+        let mut dts = base_decode_time;
+        for (i, entry) in samples.entries.into_iter().enumerate() {
+            let data = segment
+                .get(data_pos..data_pos + entry.size as usize)
+                .ok_or("Sample data outside of segment")?
+                .to_vec();
+            data_pos += entry.size as usize;
+
+            if Some(track_id) == self.video_track_id {
+                self.video_base_media_decode_time
+                    .get_or_insert(base_decode_time);
+                self.video_samples.push(VideoSample {
+                    pts: (dts as i64 + entry.composition_offset as i64) as u64,
+                    dts,
+                    data,
+                    size: entry.size,
+                    flags: SampleFlags::from_bits(entry.flags),
+                    duration: Some(entry.duration),
+                    aux_size: aux.get(i).map_or(0, |a| a.len() as u8),
+                    aux: aux.get(i).cloned().unwrap_or_default(),
+                });
+            } else if Some(track_id) == self.audio_track_id {
+                self.audio_base_media_decode_time
+                    .get_or_insert(base_decode_time);
+                self.audio_samples.push(AudioSample {
+                    pts: dts,
+                    data,
+                    size: entry.size,
+                    duration: entry.duration,
+                });
+            } else {
+                return Err(format!("Segment references unknown track {}", track_id));
+            }
+            dts += entry.duration as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Audio samples a `Refragmenter` uses instead of its input's
+struct ReplacementAudio {
+    samples: VecDeque<AudioSample>,
+    video_time_base: TimeBase,
+    audio_time_base: TimeBase,
+    /// End of the samples taken so far, in audio timescale units
+    end: u64,
+}
+
+/// A box found in a byte buffer
+pub(crate) struct Mp4Box<'a> {
+    pub(crate) typ: [u8; 4],
+    /// Offset of the box header in the parsed buffer
+    pub(crate) offset: usize,
+    pub(crate) payload: &'a [u8],
+}
+
+/// Split a buffer into its top-level boxes
+pub(crate) fn parse_boxes(data: &[u8]) -> Result<Vec<Mp4Box<'_>>, String> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let size = read_u32(data, pos)? as usize;
+        let typ: [u8; 4] = data
+            .get(pos + 4..pos + 8)
+            .ok_or("Truncated box header")?
+            .try_into()
+            .unwrap();
+        if size < 8 || data.len() - pos < size {
+            return Err(format!(
+                "Invalid size {} for box '{}'",
+                size,
+                String::from_utf8_lossy(&typ)
+            ));
+        }
+        boxes.push(Mp4Box {
+            typ,
+            offset: pos,
+            payload: &data[pos + 8..pos + size],
+        });
+        pos += size;
+    }
+    Ok(boxes)
+}
+
+/// Find the first top-level box of a type
+pub(crate) fn find_box<'a>(data: &'a [u8], typ: &[u8; 4]) -> Result<Option<Mp4Box<'a>>, String> {
+    Ok(parse_boxes(data)?.into_iter().find(|b| &b.typ == typ))
+}
+
+/// Entries of a trun box
+pub(crate) struct TrackRun {
+    pub(crate) data_offset: u32,
+    pub(crate) entries: Vec<TrackRunEntry>,
+}
+
+pub(crate) struct TrackRunEntry {
+    pub(crate) duration: u32,
+    pub(crate) size: u32,
+    pub(crate) flags: u32,
+    pub(crate) composition_offset: i32,
+}
+
+/// Structure of one track fragment of a media segment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct FragmentTrace {
+    /// mfhd sequence number
+    pub sequence: u32,
+    pub track_id: u32,
+    /// tfdt in track timescale units
+    pub base_decode_time: u64,
+    pub sample_count: u32,
+    /// Sum of sample durations in track timescale units
+    pub duration: u64,
+    pub sync_samples: u32,
+    /// Sum of sample sizes
+    pub bytes: u64,
+}
+
+impl fmt::Display for FragmentTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} track {}: tfdt={} samples={} duration={} sync={} bytes={}",
+            self.sequence,
+            self.track_id,
+            self.base_decode_time,
+            self.sample_count,
+            self.duration,
+            self.sync_samples,
+            self.bytes
+        )
+    }
+}
+
+/// Read the structure of media segments back from their moof boxes
+///
+/// Sample data is never touched, so this also reads dry-run segments,
+/// whose mdat boxes declare more bytes than they contain.
+pub fn trace_segment(segment: &[u8]) -> Result<Vec<FragmentTrace>, String> {
+    let mut traces = Vec::new();
+    let mut pos = 0;
+    while pos < segment.len() {
+        let size = read_u32(segment, pos)? as usize;
+        if size < 8 {
+            return Err(format!("Invalid box size {} at offset {}", size, pos));
+        }
+        let start = pos;
+        pos += size;
+        if segment.get(start + 4..start + 8) != Some(b"moof") {
+            continue;
+        }
+        let moof = segment.get(start + 8..pos).ok_or("Truncated moof box")?;
+        let mfhd = find_box(moof, b"mfhd")?.ok_or("moof has no mfhd box")?;
+        let sequence = read_u32(mfhd.payload, 4)?;
+        for traf in parse_boxes(moof)?.iter().filter(|b| &b.typ == b"traf") {
+            let tfhd = find_box(traf.payload, b"tfhd")?.ok_or("traf has no tfhd box")?;
+            let tfdt = find_box(traf.payload, b"tfdt")?.ok_or("traf has no tfdt box")?;
+            let trun = find_box(traf.payload, b"trun")?.ok_or("traf has no trun box")?;
+            let run = read_trun(trun.payload)?;
+            traces.push(FragmentTrace {
+                sequence,
+                track_id: read_u32(tfhd.payload, 4)?,
+                base_decode_time: if tfdt.payload.first() == Some(&1) {
+                    read_u64(tfdt.payload, 4)?
+                } else {
+                    read_u32(tfdt.payload, 4)? as u64
+                },
+                sample_count: run.entries.len() as u32,
+                duration: run.entries.iter().map(|e| e.duration as u64).sum(),
+                sync_samples: run
+                    .entries
+                    .iter()
+                    .filter(|e| e.flags & 0x0001_0000 == 0)
+                    .count() as u32,
+                bytes: run.entries.iter().map(|e| e.size as u64).sum(),
+            });
+        }
+    }
+    Ok(traces)
+}
+
+pub(crate) fn read_trun(payload: &[u8]) -> Result<TrackRun, String> {
+    let flags = read_u32(payload, 0)? & 0x00FF_FFFF;
+    let count = read_u32(payload, 4)?;
+    let header = 8 + 4 * (flags & 0x000005).count_ones() as usize;
+    // Per-sample fields present: duration, size, flags, composition offset
+    let entry_len = 4 * (flags & 0x000F00).count_ones() as usize;
+    let max_count = match entry_len {
+        0 => MAX_TRUN_SAMPLES,
+        len => payload.len().saturating_sub(header) / len,
+    };
+    if count as usize > max_count {
+        return Err(format!(
+            "trun claims {} samples, room for {}",
+            count, max_count
+        ));
+    }
+    let mut pos = 8;
+    let mut next = || -> Result<u32, String> {
+        let value = read_u32(payload, pos)?;
+        pos += 4;
+        Ok(value)
+    };
+    let data_offset = if flags & 0x000001 != 0 { next()? } else { 0 };
+    let first_sample_flags = if flags & 0x000004 != 0 {
+        Some(next()?)
+    } else {
+        None
+    };
+    let mut entries = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        let duration = if flags & 0x000100 != 0 { next()? } else { 0 };
+        let size = if flags & 0x000200 != 0 { next()? } else { 0 };
+        let sample_flags = if flags & 0x000400 != 0 {
+            next()?
+        } else if i == 0 {
+            first_sample_flags.unwrap_or(0)
+        } else {
+            0
+        };
+        let composition_offset = if flags & 0x000800 != 0 {
+            next()? as i32
+        } else {
+            0
+        };
+        entries.push(TrackRunEntry {
+            duration,
+            size,
+            flags: sample_flags,
+            composition_offset,
+        });
+    }
+    Ok(TrackRun {
+        data_offset,
+        entries,
+    })
+}
+
+/// Most samples a trun without per-sample fields may claim; such entries
+/// take no bytes, so the payload cannot bound them
+const MAX_TRUN_SAMPLES: usize = 1 << 20;
+
+/// Length of the prefix of AVCC data made of complete, non-empty NAL units
+fn avcc_valid_len(data: &[u8]) -> usize {
+    let mut pos = 0;
+    while let Ok(len) = read_u32(data, pos) {
+        let len = len as usize;
+        if len == 0 || data.len() - pos - 4 < len {
+            break;
+        }
+        pos += 4 + len;
+    }
+    pos
+}
+
+pub(crate) fn read_u32(data: &[u8], pos: usize) -> Result<u32, String> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| "Unexpected end of box".to_string())
+}
+
+pub(crate) fn read_u64(data: &[u8], pos: usize) -> Result<u64, String> {
+    data.get(pos..pos + 8)
+        .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| "Unexpected end of box".to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::ManualClock;
+    use crate::compat::{compare_init_segments, read_init_tracks};
     use std::fs::File;
     use std::io::Write as IoWrite;
 
@@ -1631,7 +4762,24 @@ mod tests {
             audio_sample_rate: Some(48000),
             audio_channels: Some(2),
             audio_timescale: Some(48000),
+            movie_timescale: None,
             audio_specific_config: None, // Will be auto-generated
+            audio_priming_samples: None,
+            validation: ValidationMode::default(),
+            start_alignment: StartAlignment::default(),
+            dry_run: false,
+            compat_checks: false,
+            audio_skew_correction: false,
+            max_frame_size: None,
+            memory_profile: MemoryProfile::default(),
+            data_offset_mode: DataOffsetMode::default(),
+            aux_info_type: None,
+            video_track_name: None,
+            audio_track_name: None,
+            session_metadata: None,
+            fragment_checksums: false,
+            moov_reserved_size: None,
+            previous_audio_configs: Vec::new(),
         };
 
         let mut muxer = MuxideMuxerState::new(config);
@@ -1749,7 +4897,24 @@ mod tests {
             audio_sample_rate: Some(48000),
             audio_channels: Some(2),
             audio_timescale: Some(48000),
+            movie_timescale: None,
             audio_specific_config: None, // Will be auto-generated
+            audio_priming_samples: None,
+            validation: ValidationMode::default(),
+            start_alignment: StartAlignment::default(),
+            dry_run: false,
+            compat_checks: false,
+            audio_skew_correction: false,
+            max_frame_size: None,
+            memory_profile: MemoryProfile::default(),
+            data_offset_mode: DataOffsetMode::default(),
+            aux_info_type: None,
+            video_track_name: None,
+            audio_track_name: None,
+            session_metadata: None,
+            fragment_checksums: false,
+            moov_reserved_size: None,
+            previous_audio_configs: Vec::new(),
         };
 
         let mut muxer = MuxideMuxerState::new(config);
@@ -1807,20 +4972,1073 @@ mod tests {
     }
 
     #[test]
-    fn test_no_tracks_configured_error() {
+    fn test_gapless_audio_only() {
         let config = MuxideConfig {
             video_width: None,
             video_height: None,
-            video_timescale: None,
-            fragment_duration_ms: 2000,
             sps: None,
             pps: None,
-            audio_sample_rate: None,
-            audio_channels: None,
-            audio_timescale: None,
-            audio_specific_config: None,
-        };
-
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            audio_timescale: Some(48000),
+            audio_priming_samples: Some(2112),
+            ..Default::default()
+        };
+        let mut muxer = MuxideMuxerState::new(config);
+        muxer.init().unwrap();
+
+        // The live init segment already skips the priming samples
+        let init_segment = muxer.get_init_segment().unwrap();
+        let elst = [0u8, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0x08, 0x40];
+        assert!(init_segment.windows(elst.len()).any(|w| w == elst));
+        assert!(!init_segment.windows(8).any(|w| w == b"iTunSMPB"));
+
+        for i in 0..10u64 {
+            muxer
+                .push_audio_chunk(&[0x21, 0x10, 0x04, 0x60], i * 21333, 21333)
+                .unwrap();
+        }
+        muxer.set_audio_valid_samples(10 * 1024 - 2112 - 500);
+        let info = muxer.gapless_info().unwrap();
+        assert_eq!(info.remainder_samples, 500);
+        assert_eq!(
+            info.itunsmpb(),
+            " 00000000 00000840 000001F4 0000000000001DCC 00000000 00000000 00000000 \
+             00000000 00000000 00000000 00000000 00000000"
+        );
+
+        let file = muxer.get_complete_file().unwrap();
+        let moov = find_box(&file, b"moov").unwrap().unwrap();
+        let trak = find_box(moov.payload, b"trak").unwrap().unwrap();
+        let elst = find_box(
+            find_box(trak.payload, b"edts").unwrap().unwrap().payload,
+            b"elst",
+        )
+        .unwrap()
+        .unwrap();
+        // 7628 valid samples in the 1000 Hz movie timescale, rounded up; the
+        // priming samples in the 48 kHz media timescale
+        let mvhd = find_box(moov.payload, b"mvhd").unwrap().unwrap();
+        assert_eq!(read_u32(mvhd.payload, 12).unwrap(), 1000);
+        assert_eq!(read_u32(elst.payload, 8).unwrap(), 159);
+        assert_eq!(read_u32(elst.payload, 12).unwrap(), 2112);
+        let udta = find_box(moov.payload, b"udta").unwrap().unwrap();
+        let tag = info.itunsmpb();
+        assert!(udta.payload.windows(tag.len()).any(|w| w == tag.as_bytes()));
+
+        // Files with video never get an edit list
+        let (sps, pps) = create_test_sps_pps();
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            sps: Some(sps),
+            pps: Some(pps),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            audio_priming_samples: Some(2112),
+            movie_timescale: Some(600),
+            ..Default::default()
+        });
+        muxer.init().unwrap();
+        assert!(muxer.gapless_info().is_none());
+        let init_segment = muxer.get_init_segment().unwrap();
+        assert!(!init_segment.windows(4).any(|w| w == b"edts"));
+
+        // The movie timescale is not the video timescale
+        let moov = find_box(&init_segment, b"moov").unwrap().unwrap();
+        let mvhd = find_box(moov.payload, b"mvhd").unwrap().unwrap();
+        assert_eq!(read_u32(mvhd.payload, 12).unwrap(), 600);
+    }
+
+    #[test]
+    fn test_audio_timescale_without_drift() {
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            video_width: None,
+            video_height: None,
+            sps: None,
+            pps: None,
+            audio_sample_rate: Some(44100),
+            audio_channels: Some(2),
+            audio_timescale: Some(90000),
+            ..Default::default()
+        });
+        muxer.init().unwrap();
+        // 1024-sample AAC frames at 44.1 kHz, reported as 23220 us
+        for i in 0..3000u64 {
+            let duration = if i % 3 == 0 { 23219 } else { 23220 };
+            muxer
+                .push_audio_chunk(&[0x21, 0x10], i * 1024 * 1_000_000 / 44100, duration)
+                .unwrap();
+            if i == 1500 {
+                // The rounding error survives a checkpoint
+                let bytes = muxer.serialize_state().unwrap();
+                muxer = MuxideMuxerState::restore_state(&bytes).unwrap();
+            }
+        }
+        muxer.force_flush().unwrap();
+
+        let mut end = 0;
+        for segment in muxer.get_pending_segments() {
+            for trace in trace_segment(&segment).unwrap() {
+                assert_eq!(trace.base_decode_time, end);
+                end += trace.duration;
+            }
+        }
+        // 3000 * 1024 * 90000 / 44100 = 6269387.75; per-frame rounding
+        // to 2090 ticks would end at 6270000, 6.8 ms late
+        assert_eq!(end, 6_269_388);
+    }
+
+    #[test]
+    fn test_audio_skew_correction() {
+        // An encoder reporting 21330 us for 1024-sample frames at 48 kHz
+        // (21333.3 us) falls 0.16 tick behind its timestamps every frame
+        let run = |correction: bool| {
+            let mut muxer = MuxideMuxerState::new(MuxideConfig {
+                video_width: None,
+                video_height: None,
+                sps: None,
+                pps: None,
+                audio_sample_rate: Some(48000),
+                audio_channels: Some(2),
+                audio_skew_correction: correction,
+                ..Default::default()
+            });
+            muxer.init().unwrap();
+            let mut last_pts = 0;
+            for i in 0..6000u64 {
+                // Half a second of audio lost at frame 4000
+                let samples = i * 1024 + if i >= 4000 { 24_000 } else { 0 };
+                last_pts = samples;
+                muxer
+                    .push_audio_chunk(&[0x21, 0x10], samples * 1_000_000 / 48000, 21330)
+                    .unwrap();
+                if i == 3000 {
+                    let bytes = muxer.serialize_state().unwrap();
+                    muxer = MuxideMuxerState::restore_state(&bytes).unwrap();
+                }
+            }
+            muxer.force_flush().unwrap();
+            let end: u64 = muxer
+                .get_pending_segments()
+                .iter()
+                .flat_map(|segment| trace_segment(segment).unwrap())
+                .map(|trace| trace.duration)
+                .sum();
+            (end, last_pts, muxer.stats().audio_skew_correction_ticks)
+        };
+
+        let (end, _, correction) = run(false);
+        assert_eq!(correction, 0);
+        // 6000 * 1023.84 ticks
+        assert_eq!(end, 6_143_040);
+
+        // The gap is not made up for, but the drift on either side of it is
+        let (corrected_end, last_pts, correction) = run(true);
+        assert_eq!(corrected_end as i64, end as i64 + correction);
+        let expected_end = last_pts + 1024 - 24_000;
+        assert!(corrected_end.abs_diff(expected_end) <= 2 * (48 + 1));
+        // 960 ticks of drift, less up to 1 ms (48 ticks) left on each side
+        assert!(correction > 960 - 2 * 49);
+    }
+
+    #[test]
+    fn test_build_media_segment_matches_muxer() {
+        let (sps, pps) = create_test_sps_pps();
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            sps: Some(sps),
+            pps: Some(pps),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            ..Default::default()
+        });
+        muxer.init().unwrap();
+        muxer.push_audio_chunk(&[0x21, 0x10], 0, 21_333).unwrap();
+        muxer
+            .push_video_chunk(&[0, 0, 0, 1, 0x65], 0, true)
+            .unwrap();
+        muxer
+            .push_audio_chunk(&[0x21, 0x11], 21_333, 21_333)
+            .unwrap();
+        muxer
+            .push_video_chunk(&[0, 0, 0, 1, 0x41], 40_000, false)
+            .unwrap();
+        muxer.force_flush().unwrap();
+        let expected = muxer.get_pending_segments().remove(0);
+
+        let spec = MediaSegmentSpec {
+            sequence_number: 1,
+            has_video: true,
+            has_audio: true,
+            video_base_decode_time: 0,
+            audio_base_decode_time: 0,
+            audio_description_index: 1,
+            base_data_offset: None,
+            aux_info_type: None,
+        };
+        let video = vec![
+            SegmentSample {
+                data: vec![0, 0, 0, 1, 0x65],
+                pts: 0,
+                dts: 0,
+                duration: 0,
+                flags: SampleFlags::keyframe(),
+                aux: Vec::new(),
+            },
+            SegmentSample {
+                data: vec![0, 0, 0, 1, 0x41],
+                pts: 3600,
+                dts: 3600,
+                duration: 0,
+                flags: SampleFlags::delta(),
+                aux: Vec::new(),
+            },
+        ];
+        let audio: Vec<SegmentSample> = [(0u64, 0x10u8), (1024, 0x11)]
+            .iter()
+            .map(|&(pts, byte)| SegmentSample {
+                data: vec![0x21, byte],
+                pts,
+                duration: 1024,
+                ..Default::default()
+            })
+            .collect();
+        assert_eq!(
+            build_media_segment(&spec, &video, &audio).unwrap(),
+            expected
+        );
+
+        // Audio-only layout puts audio on track 1
+        let audio_only = MediaSegmentSpec {
+            has_video: false,
+            audio_base_decode_time: 2048,
+            ..spec.clone()
+        };
+        let segment = build_media_segment(&audio_only, &[], &audio).unwrap();
+        let traces = trace_segment(&segment).unwrap();
+        assert_eq!(traces.len(), 1);
+        assert_eq!(
+            (
+                traces[0].track_id,
+                traces[0].base_decode_time,
+                traces[0].duration
+            ),
+            (1, 2048, 2048)
+        );
+
+        assert!(build_media_segment(&spec, &[], &audio).is_err());
+        assert!(build_media_segment(&audio_only, &video, &audio).is_err());
+        let mut untimed = audio.clone();
+        untimed[1].duration = 0;
+        assert!(build_media_segment(&spec, &video, &untimed).is_err());
+        let mut reversed = video.clone();
+        reversed.swap(0, 1);
+        assert!(build_media_segment(&spec, &reversed, &audio).is_err());
+    }
+
+    #[test]
+    fn test_absolute_data_offsets() {
+        let (sps, pps) = create_test_sps_pps();
+        let config = MuxideConfig {
+            sps: Some(sps),
+            pps: Some(pps),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            fragment_duration_ms: 1000,
+            data_offset_mode: DataOffsetMode::Absolute,
+            ..Default::default()
+        };
+        let mut muxer = MuxideMuxerState::new(config.clone());
+        muxer.init().unwrap();
+        for i in 0..75u64 {
+            muxer
+                .push_audio_chunk(&[0x21, i as u8], i * 40_000, 40_000)
+                .unwrap();
+            muxer
+                .push_video_chunk(&[0, 0, 0, 1, 0x65, i as u8], i * 40_000, i % 25 == 0)
+                .unwrap();
+        }
+        let file = muxer.get_complete_file().unwrap();
+
+        // Every tfhd points at its own moof, and the trun data offsets
+        // counted from there land on the samples in the mdat
+        let mut pos = muxer.get_init_segment().unwrap().len();
+        let mut fragments = 0;
+        while pos < file.len() {
+            let size = read_u32(&file, pos).unwrap() as usize;
+            if &file[pos + 4..pos + 8] == b"moof" {
+                fragments += 1;
+                let moof = find_box(&file[pos..pos + size], b"moof").unwrap().unwrap();
+                for traf in parse_boxes(moof.payload)
+                    .unwrap()
+                    .into_iter()
+                    .filter(|b| &b.typ == b"traf")
+                {
+                    let tfhd = find_box(traf.payload, b"tfhd").unwrap().unwrap();
+                    let flags = read_u32(tfhd.payload, 0).unwrap();
+                    assert_eq!(flags & 0x00FF_FFFF, TFHD_BASE_DATA_OFFSET);
+                    let base = read_u64(tfhd.payload, 8).unwrap();
+                    assert_eq!(base, pos as u64);
+                    let trun = find_box(traf.payload, b"trun").unwrap().unwrap();
+                    let sample =
+                        base as usize + read_trun(trun.payload).unwrap().data_offset as usize;
+                    // AVCC video starts with a length, AAC with 0x21
+                    let (at, byte) = match read_u32(tfhd.payload, 4).unwrap() {
+                        1 => (4, 0x65),
+                        _ => (0, 0x21),
+                    };
+                    assert_eq!(file[sample + at], byte);
+                }
+            }
+            pos += size;
+        }
+        assert_eq!(fragments, 3);
+
+        assert!(muxer.change_audio_config(44100, 2, None).is_err());
+        let gapless = MuxideConfig {
+            sps: None,
+            pps: None,
+            audio_priming_samples: Some(1024),
+            ..config
+        };
+        assert!(build_init_segment(&gapless, None).is_err());
+    }
+
+    #[test]
+    fn test_moov_reserved_size() {
+        let (sps, pps) = create_test_sps_pps();
+        let config = MuxideConfig {
+            sps: Some(sps),
+            pps: Some(pps),
+            fragment_duration_ms: 1000,
+            data_offset_mode: DataOffsetMode::Absolute,
+            moov_reserved_size: Some(4096),
+            ..Default::default()
+        };
+        let mut muxer = MuxideMuxerState::new(config.clone());
+        muxer.init().unwrap();
+        let init = muxer.get_init_segment().unwrap();
+        let boxes = parse_boxes(&init).unwrap();
+        let types: Vec<&[u8; 4]> = boxes.iter().map(|b| &b.typ).collect();
+        assert_eq!(types, [b"ftyp", b"moov", b"free"]);
+        assert_eq!(init.len() - boxes[0].payload.len() - 8, 4096);
+        for i in 0..50u64 {
+            muxer
+                .push_video_chunk(&[0, 0, 0, 1, 0x65, i as u8], i * 40_000, i % 25 == 0)
+                .unwrap();
+        }
+        muxer.force_flush().unwrap();
+
+        // The moov grows into the padding, so absolute offsets stay valid
+        assert!(muxer
+            .set_session_metadata(Some("{\"sessionId\":\"s1\"}".to_string()))
+            .unwrap());
+        let rebuilt = muxer.get_init_segment().unwrap();
+        assert_eq!(rebuilt.len(), init.len());
+        let moov = find_box(&rebuilt, b"moov").unwrap().unwrap();
+        assert!(moov.payload.len() > boxes[1].payload.len());
+        let file = muxer.get_complete_file().unwrap();
+        assert_eq!(&file[..rebuilt.len()], rebuilt.as_slice());
+
+        // Beyond it, the init segment would move the fragments
+        assert!(muxer.set_session_metadata(Some("x".repeat(8192))).is_err());
+        assert_eq!(muxer.get_init_segment().unwrap(), rebuilt);
+        let unpadded = MuxideConfig {
+            moov_reserved_size: Some(16),
+            ..config
+        };
+        let init = build_init_segment(&unpadded, None).unwrap();
+        assert!(find_box(&init, b"free").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_track_names() {
+        let (sps, pps) = create_test_sps_pps();
+        let config = MuxideConfig {
+            sps: Some(sps),
+            pps: Some(pps),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            video_track_name: Some("Screen".to_string()),
+            audio_track_name: Some("System audio".to_string()),
+            ..Default::default()
+        };
+        let init = build_init_segment(&config, None).unwrap();
+        let moov = find_box(&init, b"moov").unwrap().unwrap();
+        let names: Vec<(String, Vec<u8>)> = parse_boxes(moov.payload)
+            .unwrap()
+            .into_iter()
+            .filter(|b| &b.typ == b"trak")
+            .map(|trak| {
+                let udta = find_box(trak.payload, b"udta").unwrap().unwrap();
+                let name = find_box(udta.payload, b"name").unwrap().unwrap();
+                let mdia = find_box(trak.payload, b"mdia").unwrap().unwrap();
+                let hdlr = find_box(mdia.payload, b"hdlr").unwrap().unwrap();
+                (
+                    String::from_utf8(name.payload.to_vec()).unwrap(),
+                    hdlr.payload[24..].to_vec(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("Screen".to_string(), b"Screen\0".to_vec()),
+                ("System audio".to_string(), b"System audio\0".to_vec()),
+            ]
+        );
+
+        // Unnamed tracks keep the handler names and get no udta
+        let unnamed = MuxideConfig {
+            video_track_name: None,
+            audio_track_name: None,
+            ..config.clone()
+        };
+        let init = build_init_segment(&unnamed, None).unwrap();
+        let moov = find_box(&init, b"moov").unwrap().unwrap();
+        let trak = find_box(moov.payload, b"trak").unwrap().unwrap();
+        assert!(find_box(trak.payload, b"udta").unwrap().is_none());
+        assert!(init.windows(13).any(|w| w == b"VideoHandler\0"));
+
+        let invalid = MuxideConfig {
+            audio_track_name: Some("Mic\0".to_string()),
+            ..config
+        };
+        assert!(build_init_segment(&invalid, None).is_err());
+    }
+
+    #[test]
+    fn test_sample_aux_info() {
+        let (sps, pps) = create_test_sps_pps();
+        let config = MuxideConfig {
+            sps: Some(sps),
+            pps: Some(pps),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            fragment_duration_ms: 1000,
+            aux_info_type: Some("hdrm".to_string()),
+            ..Default::default()
+        };
+        let mut muxer = MuxideMuxerState::new(config.clone());
+        muxer.init().unwrap();
+        let init = muxer.get_init_segment().unwrap();
+        // Dynamic metadata on every fifth frame, of varying size
+        let aux = |i: u64| vec![i as u8; (i % 3 + 1) as usize];
+        for i in 0..75u64 {
+            if i.is_multiple_of(5) {
+                muxer.set_next_video_aux(&aux(i)).unwrap();
+            }
+            muxer
+                .push_video_chunk(&[0, 0, 0, 2, 0x65, i as u8], i * 40_000, i % 25 == 0)
+                .unwrap();
+            muxer
+                .push_audio_chunk(&[0x21, i as u8], i * 40_000, 40_000)
+                .unwrap();
+            if i == 30 {
+                // Attached data survives a checkpoint
+                let bytes = muxer.serialize_state().unwrap();
+                muxer = MuxideMuxerState::restore_state(&bytes).unwrap();
+            }
+        }
+        muxer.force_flush().unwrap();
+        let segments = muxer.get_pending_segments();
+        assert_eq!(segments.len(), 3);
+
+        let mut frame = 0u64;
+        for segment in &segments {
+            let moof = find_box(segment, b"moof").unwrap().unwrap();
+            let traf = find_box(moof.payload, b"traf").unwrap().unwrap();
+            let info = read_sample_aux(segment, 0, traf.payload).unwrap().unwrap();
+            assert_eq!(&info.info_type, b"hdrm");
+            for data in info.samples {
+                let expected = if frame.is_multiple_of(5) {
+                    aux(frame)
+                } else {
+                    Vec::new()
+                };
+                assert_eq!(data, expected);
+                frame += 1;
+            }
+        }
+        assert_eq!(frame, 75);
+
+        // Refragmenting keeps the auxiliary information
+        let mut identity = Refragmenter::new(&init, 1).unwrap();
+        for segment in &segments {
+            assert_eq!(
+                identity.push_segment(segment).unwrap().as_ref(),
+                Some(segment)
+            );
+        }
+
+        assert!(muxer.set_next_video_aux(&[0; 256]).is_err());
+        let mut untyped = MuxideMuxerState::new(MuxideConfig {
+            aux_info_type: None,
+            ..config.clone()
+        });
+        untyped.init().unwrap();
+        assert!(untyped.set_next_video_aux(&[1]).is_err());
+        let invalid = MuxideConfig {
+            aux_info_type: Some("hdr".to_string()),
+            ..config
+        };
+        assert!(build_init_segment(&invalid, None).is_err());
+    }
+
+    #[test]
+    fn test_audio_split_at_video_fragment_boundaries() {
+        let (sps, pps) = create_test_sps_pps();
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            sps: Some(sps),
+            pps: Some(pps),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        });
+        muxer.init().unwrap();
+        // The microphone runs 200 ms ahead of the camera, so audio past each
+        // video cut is already buffered when the fragment is flushed
+        let mut audio = 0u64;
+        for i in 0..120u64 {
+            let video_us = i * 33_333;
+            while audio * 21_333 <= video_us + 200_000 {
+                muxer
+                    .push_audio_chunk(&[0x21, 0x10], audio * 21_333, 21_333)
+                    .unwrap();
+                audio += 1;
+            }
+            muxer
+                .push_video_chunk(&[0, 0, 0, 1, 0x65], video_us, i % 30 == 0)
+                .unwrap();
+        }
+        muxer.force_flush().unwrap();
+
+        let traces: Vec<FragmentTrace> = muxer
+            .get_pending_segments()
+            .iter()
+            .flat_map(|segment| trace_segment(segment).unwrap())
+            .collect();
+        let end_us = |track_id: u32, sequence: u32| {
+            let t = traces
+                .iter()
+                .find(|t| t.track_id == track_id && t.sequence == sequence)
+                .unwrap();
+            let rate = if track_id == 1 { 90_000.0 } else { 48_000.0 };
+            (t.base_decode_time + t.duration) as f64 / rate * 1e6
+        };
+        let sequences = traces.iter().map(|t| t.sequence).max().unwrap();
+        assert_eq!(sequences, 4);
+        for sequence in 1..sequences {
+            // Each fragment's audio ends at its video cut, short of it by
+            // less than the frame carried forward
+            let gap = end_us(1, sequence) - end_us(2, sequence);
+            assert!(
+                (0.0..21_334.0).contains(&gap),
+                "fragment {}: {}",
+                sequence,
+                gap
+            );
+        }
+        // The last fragment takes everything still buffered
+        assert!(end_us(2, sequences) > end_us(1, sequences));
+        let audio_samples: u32 = traces
+            .iter()
+            .filter(|t| t.track_id == 2)
+            .map(|t| t.sample_count)
+            .sum();
+        assert_eq!(audio_samples as u64, audio);
+    }
+
+    #[test]
+    fn test_audio_before_video_start_alignment() {
+        let (sps, pps) = create_test_sps_pps();
+        let first_fragment = |start_alignment, late_audio: bool| {
+            let mut muxer = MuxideMuxerState::new(MuxideConfig {
+                sps: Some(sps.clone()),
+                pps: Some(pps.clone()),
+                audio_sample_rate: Some(48000),
+                audio_channels: Some(2),
+                start_alignment,
+                ..Default::default()
+            });
+            muxer.init().unwrap();
+            let push_audio = |muxer: &mut MuxideMuxerState| {
+                for i in 0..10u64 {
+                    muxer
+                        .push_audio_chunk(&[0x21, 0x10], i * 21_333, 21_333)
+                        .unwrap();
+                }
+            };
+            // The microphone delivers 10 frames before the camera's first
+            // keyframe, or its encoder delivers them after it
+            if !late_audio {
+                push_audio(&mut muxer);
+            }
+            for i in 0..3u64 {
+                muxer
+                    .push_video_chunk(&[0, 0, 0, 1, 0x65], 100_000 + i * 33_333, i == 0)
+                    .unwrap();
+            }
+            if late_audio {
+                push_audio(&mut muxer);
+            }
+            // A state round trip keeps the alignment
+            let mut muxer =
+                MuxideMuxerState::restore_state(&muxer.serialize_state().unwrap()).unwrap();
+            muxer.force_flush().unwrap();
+            let segment = muxer.get_pending_segments().remove(0);
+            let traces = trace_segment(&segment).unwrap();
+            let tfdt = |track_id| {
+                traces
+                    .iter()
+                    .find(|t| t.track_id == track_id)
+                    .map(|t| (t.base_decode_time, t.sample_count))
+                    .unwrap()
+            };
+            (tfdt(1), tfdt(2))
+        };
+
+        // Trim drops the 5 audio frames starting before 100 ms. The file
+        // starts with the video; the 6th frame starts 6.665 ms (319 ticks)
+        // after it, where it was recorded
+        assert_eq!(
+            first_fragment(StartAlignment::Trim, false),
+            ((0, 3), (319, 5))
+        );
+        // The same frames are trimmed when they arrive after the video
+        assert_eq!(
+            first_fragment(StartAlignment::Trim, true),
+            ((0, 3), (319, 5))
+        );
+        // Delay keeps them and starts the video 100 ms (9000 ticks) later
+        assert_eq!(
+            first_fragment(StartAlignment::Delay, false),
+            ((9000, 3), (0, 10))
+        );
+        // but cannot delay video already muxed for audio that comes late
+        assert_eq!(
+            first_fragment(StartAlignment::Delay, true),
+            ((0, 3), (319, 5))
+        );
+    }
+
+    #[test]
+    fn test_explicit_video_durations() {
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        });
+        muxer.init().unwrap();
+        // Capture jitter in the timestamps, exact durations from the encoder
+        for i in 0..60u64 {
+            let ts = i * 33_333 + [0, 4_000, 1_500][i as usize % 3];
+            let flags = if i == 0 { FRAME_FLAG_KEYFRAME } else { 0 };
+            muxer
+                .push_video_chunk_full(&[0, 0, 0, 1, 0x65], ts, ts, Some(33_333), flags)
+                .unwrap();
+        }
+        muxer.force_flush().unwrap();
+        // Without a duration, the gap to the next frame is used
+        for ts in [2_000_000, 2_040_000, 2_080_000] {
+            muxer
+                .push_video_chunk_full(&[0, 0, 0, 1, 0x41], ts, ts, None, 0)
+                .unwrap();
+        }
+        muxer.force_flush().unwrap();
+
+        let traces: Vec<FragmentTrace> = muxer
+            .get_pending_segments()
+            .iter()
+            .flat_map(|segment| trace_segment(segment).unwrap())
+            .collect();
+        let mut end = 0;
+        for trace in &traces {
+            assert_eq!(trace.base_decode_time, end);
+            end += trace.duration;
+        }
+        // 60 * 2999.97 ticks, then three frames of 3600
+        assert_eq!(end, 179_998 + 3 * 3600);
+    }
+
+    #[test]
+    fn test_custom_sample_flags() {
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            ..Default::default()
+        });
+        muxer.init().unwrap();
+        let disposable = SampleFlags {
+            depends_on: 1,
+            is_depended_on: 2,
+            is_non_sync: true,
+            degradation_priority: 7,
+            ..Default::default()
+        };
+        muxer
+            .push_video_chunk(&[0, 0, 0, 1, 0x65], 0, true)
+            .unwrap();
+        muxer
+            .push_video_chunk_with_flags(&[0, 0, 0, 1, 0x01], 33_333, 33_333, None, disposable)
+            .unwrap();
+        let invalid = SampleFlags {
+            depends_on: 4,
+            ..Default::default()
+        };
+        assert!(muxer
+            .push_video_chunk_with_flags(&[0, 0, 0, 1, 0x01], 66_666, 66_666, None, invalid)
+            .is_err());
+        muxer.force_flush().unwrap();
+
+        let segment = muxer.get_pending_segments().remove(0);
+        let moof = find_box(&segment, b"moof").unwrap().unwrap();
+        let traf = find_box(moof.payload, b"traf").unwrap().unwrap();
+        let trun = find_box(traf.payload, b"trun").unwrap().unwrap();
+        let flags: Vec<u32> = read_trun(trun.payload)
+            .unwrap()
+            .entries
+            .iter()
+            .map(|e| e.flags)
+            .collect();
+        assert_eq!(flags, vec![0x0200_0000, 0x0181_0007]);
+        assert_eq!(SampleFlags::from_bits(flags[1]), disposable);
+        assert_eq!(SampleFlags::delta().to_bits(), 0x0101_0000);
+    }
+
+    #[test]
+    fn test_trun_composition_offsets_only_when_needed() {
+        let trun_of = |frames: &[(u64, u64)]| {
+            let (sps, pps) = create_test_sps_pps();
+            let mut muxer = MuxideMuxerState::new(MuxideConfig {
+                sps: Some(sps),
+                pps: Some(pps),
+                ..Default::default()
+            });
+            muxer.init().unwrap();
+            for (i, &(pts, dts)) in frames.iter().enumerate() {
+                let flags = if i == 0 { FRAME_FLAG_KEYFRAME } else { 0 };
+                muxer
+                    .push_video_chunk_full(&[0, 0, 0, 1, 0x65], pts, dts, None, flags)
+                    .unwrap();
+            }
+            muxer.force_flush().unwrap();
+            let segment = muxer.get_pending_segments().remove(0);
+            let moof = find_box(&segment, b"moof").unwrap().unwrap();
+            let traf = find_box(moof.payload, b"traf").unwrap().unwrap();
+            let trun = find_box(traf.payload, b"trun").unwrap().unwrap();
+            // The data offset must still point at the first sample
+            let run = read_trun(trun.payload).unwrap();
+            assert_eq!(
+                &segment[run.data_offset as usize..][..5],
+                &[0, 0, 0, 1, 0x65]
+            );
+            (trun.payload.to_vec(), run)
+        };
+
+        // No B-frames: version 0, no offset field
+        let (payload, run) = trun_of(&[(0, 0), (33_333, 33_333), (66_666, 66_666)]);
+        assert_eq!(read_u32(&payload, 0).unwrap(), 0x0000_0701);
+        assert_eq!(payload.len(), 12 + 3 * 12);
+        assert!(run.entries.iter().all(|e| e.composition_offset == 0));
+
+        // One reordered frame puts offsets on every sample of the run
+        let (payload, run) = trun_of(&[(40_000, 0), (120_000, 40_000), (80_000, 80_000)]);
+        assert_eq!(read_u32(&payload, 0).unwrap(), 0x0100_0F01);
+        assert_eq!(payload.len(), 12 + 3 * 16);
+        let offsets: Vec<i32> = run.entries.iter().map(|e| e.composition_offset).collect();
+        assert_eq!(offsets, vec![3600, 7200, 0]);
+
+        // Sample counts the payload cannot hold are refused up front
+        let mut forged = payload.clone();
+        forged[4..8].copy_from_slice(&4u32.to_be_bytes());
+        assert!(read_trun(&forged).is_err());
+        let mut empty_entries = 0u32.to_be_bytes().to_vec();
+        empty_entries.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert!(read_trun(&empty_entries).is_err());
+        empty_entries[4..8].copy_from_slice(&30u32.to_be_bytes());
+        assert_eq!(read_trun(&empty_entries).unwrap().entries.len(), 30);
+    }
+
+    #[test]
+    fn test_validation_modes() {
+        let muxer = |validation| {
+            let (sps, pps) = create_test_sps_pps();
+            let mut muxer = MuxideMuxerState::new(MuxideConfig {
+                sps: Some(sps),
+                pps: Some(pps),
+                validation,
+                ..Default::default()
+            });
+            muxer.init().unwrap();
+            muxer
+        };
+        let frame = [0x00, 0x00, 0x00, 0x01, 0x41];
+
+        let mut strict = muxer(ValidationMode::Strict);
+        assert!(strict.push_video_chunk(&frame, 0, false).is_err());
+        strict.push_video_chunk(&frame, 0, true).unwrap();
+        assert!(strict.push_video_chunk(&frame, 0, false).is_err());
+        assert!(strict
+            .push_video_chunk(&[0, 0, 0, 9, 0x41], 33_333, false)
+            .is_err());
+        assert_eq!(strict.stats().video_frame_count, 1);
+        assert_eq!(strict.stats().anomaly_count, 0);
+        assert_eq!(strict.stats().rejected_frames, 3);
+
+        let mut warn = muxer(ValidationMode::Warn);
+        warn.push_video_chunk(&frame, 0, false).unwrap();
+        assert_eq!(warn.stats().video_frame_count, 0);
+        warn.push_video_chunk(&frame, 33_333, true).unwrap();
+        // Clamped to one tick after the previous frame
+        warn.push_video_chunk(&frame, 0, false).unwrap();
+        // Incomplete NAL units are cut off, frames without any are dropped
+        warn.push_video_chunk(&[0, 0, 0, 1, 0x41, 0xff], 66_666, false)
+            .unwrap();
+        warn.push_video_chunk(&[0, 0, 0, 9, 0x41], 100_000, false)
+            .unwrap();
+        let stats = warn.stats();
+        assert_eq!(stats.video_frame_count, 3);
+        assert_eq!(stats.anomaly_count, 4);
+        assert_eq!((stats.dropped_frames, stats.corrected_frames), (2, 2));
+        assert_eq!(warn.video_samples[1].dts, warn.video_samples[0].dts + 1);
+        assert_eq!(warn.video_samples[2].data, frame);
+
+        let mut lenient = muxer(ValidationMode::Lenient);
+        lenient.push_video_chunk(&frame, 0, false).unwrap();
+        lenient
+            .push_video_chunk(&[0, 0, 0, 9, 0x41], 33_333, false)
+            .unwrap();
+        assert_eq!(lenient.stats().video_frame_count, 2);
+        assert_eq!(lenient.stats().anomaly_count, 2);
+
+        // The previous timestamps survive a state round trip
+        let mut restored =
+            MuxideMuxerState::restore_state(&warn.serialize_state().unwrap()).unwrap();
+        restored.push_video_chunk(&frame, 0, false).unwrap();
+        assert_eq!(restored.stats().anomaly_count, 5);
+        assert_eq!(restored.stats().corrected_frames, 3);
+    }
+
+    #[test]
+    fn test_empty_and_oversized_frames() {
+        let muxer = |validation| {
+            let (sps, pps) = create_test_sps_pps();
+            let mut muxer = MuxideMuxerState::new(MuxideConfig {
+                sps: Some(sps),
+                pps: Some(pps),
+                audio_sample_rate: Some(48000),
+                audio_channels: Some(2),
+                max_frame_size: Some(8),
+                validation,
+                ..Default::default()
+            });
+            muxer.init().unwrap();
+            muxer
+        };
+        let frame = [0x00, 0x00, 0x00, 0x01, 0x41];
+        let oversized = [0x00, 0x00, 0x00, 0x05, 0x41, 0, 0, 0, 0];
+
+        let mut strict = muxer(ValidationMode::Strict);
+        strict.push_video_chunk(&frame, 0, true).unwrap();
+        assert!(strict.push_video_chunk(&[], 33_333, false).is_err());
+        assert!(strict.push_video_chunk(&oversized, 33_333, false).is_err());
+        assert!(strict.push_audio_chunk(&[], 0, 21_333).is_err());
+        let stats = strict.stats();
+        assert_eq!((stats.video_frame_count, stats.rejected_frames), (1, 3));
+        assert!(strict.take_skipped_frames().is_empty());
+
+        let mut warn = muxer(ValidationMode::Warn);
+        // An empty first keyframe is skipped, so the next frame must be one
+        warn.push_video_chunk(&[], 0, true).unwrap();
+        warn.push_video_chunk(&frame, 33_333, true).unwrap();
+        warn.push_video_chunk(&oversized, 66_666, false).unwrap();
+        warn.push_audio_chunk(&[], 0, 21_333).unwrap();
+        warn.push_audio_chunk(&[0x21, 0x10], 42_666, 21_333)
+            .unwrap();
+        let stats = warn.stats();
+        assert_eq!((stats.video_frame_count, stats.audio_frame_count), (1, 1));
+        assert_eq!((stats.empty_frames, stats.oversized_frames), (2, 1));
+        assert_eq!(stats.dropped_frames, 3);
+        assert_eq!((stats.largest_frame, stats.max_frame_size), (5, 8));
+        assert_eq!(
+            warn.take_skipped_frames(),
+            [
+                SkippedFrame {
+                    track: TrackKind::Video,
+                    timestamp_us: 0,
+                    size: 0,
+                    issue: FrameIssue::Empty,
+                },
+                SkippedFrame {
+                    track: TrackKind::Video,
+                    timestamp_us: 66_666,
+                    size: 9,
+                    issue: FrameIssue::Oversized,
+                },
+                SkippedFrame {
+                    track: TrackKind::Audio,
+                    timestamp_us: 0,
+                    size: 0,
+                    issue: FrameIssue::Empty,
+                },
+            ]
+        );
+        assert!(warn.take_skipped_frames().is_empty());
+        // No zero-size sample ever reaches a trun
+        warn.force_flush().unwrap();
+        let segment = warn.get_pending_segments().remove(0);
+        let traces = trace_segment(&segment).unwrap();
+        assert!(traces.iter().all(|t| t.sample_count == 1));
+
+        let restored = MuxideMuxerState::restore_state(&warn.serialize_state().unwrap()).unwrap();
+        let restored_stats = restored.stats();
+        assert_eq!(
+            (
+                restored_stats.empty_frames,
+                restored_stats.oversized_frames,
+                restored_stats.largest_frame
+            ),
+            (2, 1, 5)
+        );
+
+        let mut lenient = muxer(ValidationMode::Lenient);
+        lenient.push_video_chunk(&[], 0, true).unwrap();
+        assert_eq!(lenient.stats().empty_frames, 1);
+        assert_eq!(lenient.take_skipped_frames().len(), 1);
+    }
+
+    #[test]
+    fn test_low_memory_profile() {
+        let (sps, pps) = create_test_sps_pps();
+        let config = MuxideConfig {
+            sps: Some(sps),
+            pps: Some(pps),
+            fragment_duration_ms: 4000,
+            memory_profile: MemoryProfile::Low,
+            ..Default::default()
+        };
+        assert_eq!(config.target_fragment_duration_ms(), LOW_MEMORY_FRAGMENT_MS);
+        assert_eq!(
+            config.max_frame_size_or_default(),
+            LOW_MEMORY_MAX_FRAME_SIZE
+        );
+        let frame = |size: usize| {
+            let mut frame = ((size - 4) as u32).to_be_bytes().to_vec();
+            frame.push(0x41);
+            frame.resize(size, 0);
+            frame
+        };
+
+        // Short fragments despite the configured 4 s
+        let mut muxer = MuxideMuxerState::new(config.clone());
+        muxer.init().unwrap();
+        for i in 0..90u64 {
+            muxer
+                .push_video_chunk(&frame(16), i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+        assert_eq!(muxer.stats().segment_count, 2);
+        assert!(muxer.get_complete_file().is_err());
+
+        // Large frames cut a fragment early and oversized ones are skipped
+        let mut muxer = MuxideMuxerState::new(config);
+        muxer.init().unwrap();
+        for i in 0..20u64 {
+            muxer
+                .push_video_chunk(&frame(512 * 1024), i * 33_333, i == 0)
+                .unwrap();
+        }
+        muxer
+            .push_video_chunk(&frame(3 * 1024 * 1024), 20 * 33_333, false)
+            .unwrap();
+        let segments = muxer.get_pending_segments();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(trace_segment(&segments[0]).unwrap()[0].sample_count, 8);
+        assert_eq!(muxer.stats().oversized_frames, 1);
+    }
+
+    #[test]
+    fn test_dry_run_matches_structure() {
+        let run = |dry_run| {
+            let (sps, pps) = create_test_sps_pps();
+            let mut muxer = MuxideMuxerState::new(MuxideConfig {
+                sps: Some(sps),
+                pps: Some(pps),
+                audio_sample_rate: Some(48000),
+                audio_channels: Some(2),
+                dry_run,
+                ..Default::default()
+            });
+            muxer.init().unwrap();
+            let frame = [0u8, 0, 0, 60, 0x41]
+                .into_iter()
+                .chain([0u8; 60])
+                .collect::<Vec<_>>();
+            for i in 0..150u64 {
+                muxer
+                    .push_video_chunk(&frame, i * 33_333, i % 30 == 0)
+                    .unwrap();
+                muxer
+                    .push_audio_chunk(&[0u8; 200], i * 33_333, 21_333)
+                    .unwrap();
+            }
+            muxer.force_flush().unwrap();
+            muxer.get_pending_segments()
+        };
+        let full = run(false);
+        let dry = run(true);
+        assert_eq!(full.len(), dry.len());
+        for (full, dry) in full.iter().zip(&dry) {
+            let trace = trace_segment(full).unwrap();
+            assert_eq!(trace, trace_segment(dry).unwrap());
+            assert_eq!(trace.len(), 2);
+            // Same moof and mdat header, without the payload
+            assert_eq!(&full[..dry.len()], &dry[..]);
+            assert_eq!(
+                dry.len() + trace.iter().map(|t| t.bytes).sum::<u64>() as usize,
+                full.len()
+            );
+        }
+        let first = &trace_segment(&dry[0]).unwrap()[0];
+        assert_eq!(
+            first.to_string(),
+            "#1 track 1: tfdt=0 samples=62 duration=185998 sync=3 bytes=3968"
+        );
+    }
+
+    #[test]
+    fn test_no_tracks_configured_error() {
+        let config = MuxideConfig {
+            video_width: None,
+            video_height: None,
+            video_timescale: None,
+            fragment_duration_ms: 2000,
+            sps: None,
+            pps: None,
+            audio_sample_rate: None,
+            audio_channels: None,
+            audio_timescale: None,
+            movie_timescale: None,
+            audio_specific_config: None,
+            audio_priming_samples: None,
+            validation: ValidationMode::default(),
+            start_alignment: StartAlignment::default(),
+            dry_run: false,
+            compat_checks: false,
+            audio_skew_correction: false,
+            max_frame_size: None,
+            memory_profile: MemoryProfile::default(),
+            data_offset_mode: DataOffsetMode::default(),
+            aux_info_type: None,
+            video_track_name: None,
+            audio_track_name: None,
+            session_metadata: None,
+            fragment_checksums: false,
+            moov_reserved_size: None,
+            previous_audio_configs: Vec::new(),
+        };
+
         let mut muxer = MuxideMuxerState::new(config);
         let result = muxer.init();
         assert!(result.is_err());
@@ -1829,6 +6047,424 @@ mod tests {
             .contains("At least one track (video or audio) must be configured"));
     }
 
+    #[test]
+    fn test_build_init_segment_round_trip() {
+        let (sps, pps) = create_test_sps_pps();
+        let config = MuxideConfig {
+            video_width: Some(1280),
+            video_height: Some(720),
+            sps: Some(sps.clone()),
+            pps: Some(pps),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            ..Default::default()
+        };
+        let init_segment = build_init_segment(&config, None).unwrap();
+        let mut muxer = MuxideMuxerState::new(config.clone());
+        muxer.init().unwrap();
+        assert_eq!(init_segment, muxer.get_init_segment().unwrap());
+
+        // The demuxer reads back the tracks the config describes
+        let size = init_segment.len() as u64;
+        let mp4 = mp4::Mp4Reader::read_header(std::io::Cursor::new(&init_segment), size).unwrap();
+        assert_eq!(mp4.timescale(), DEFAULT_MOVIE_TIMESCALE);
+        let video = &mp4.tracks()[&1];
+        assert_eq!(video.track_type().unwrap(), mp4::TrackType::Video);
+        assert_eq!(video.media_type().unwrap(), mp4::MediaType::H264);
+        assert_eq!((video.width(), video.height()), (1280, 720));
+        assert_eq!(video.timescale(), 90000);
+        assert_eq!(video.sequence_parameter_set().unwrap(), sps.as_slice());
+        let audio = &mp4.tracks()[&2];
+        assert_eq!(audio.media_type().unwrap(), mp4::MediaType::AAC);
+        assert_eq!(audio.timescale(), 48000);
+        assert_eq!(audio.sample_freq_index().unwrap().freq(), 48000);
+        assert_eq!(audio.channel_config().unwrap(), mp4::ChannelConfig::Stereo);
+
+        // With the session's gapless info it matches the finalized file
+        let config = MuxideConfig {
+            audio_sample_rate: Some(44100),
+            audio_channels: Some(1),
+            audio_priming_samples: Some(2112),
+            ..Default::default()
+        };
+        let mut muxer = MuxideMuxerState::new(config.clone());
+        muxer.init().unwrap();
+        for i in 0..10u64 {
+            muxer
+                .push_audio_chunk(&[0x21, 0x10, 0x04, 0x60], i * 23220, 23220)
+                .unwrap();
+        }
+        let info = muxer.gapless_info().unwrap();
+        let file = muxer.get_complete_file().unwrap();
+        let init_segment = build_init_segment(&config, Some(&info)).unwrap();
+        assert!(file.starts_with(&init_segment));
+        assert_ne!(init_segment, build_init_segment(&config, None).unwrap());
+        let size = init_segment.len() as u64;
+        let mp4 = mp4::Mp4Reader::read_header(std::io::Cursor::new(&init_segment), size).unwrap();
+        let audio = &mp4.tracks()[&1];
+        assert_eq!(audio.timescale(), 44100);
+        assert_eq!(audio.channel_config().unwrap(), mp4::ChannelConfig::Mono);
+
+        assert!(build_init_segment(&MuxideConfig::default(), None).is_err());
+    }
+
+    #[test]
+    fn test_muxer_stats() {
+        let (sps, pps) = create_test_sps_pps();
+        let config = MuxideConfig {
+            sps: Some(sps),
+            pps: Some(pps),
+            ..Default::default()
+        };
+
+        let mut muxer = MuxideMuxerState::new(config);
+        muxer.init().unwrap();
+        assert_eq!(
+            muxer.stats(),
+            MuxerStats {
+                max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+                ..Default::default()
+            }
+        );
+
+        // 3 seconds at 30fps: one 2-second segment flushed, the rest buffered
+        for i in 0..90u64 {
+            let data = [0x00, 0x00, 0x00, 0x01, 0x41];
+            muxer
+                .push_video_chunk(&data, i * 33333, i % 30 == 0)
+                .unwrap();
+        }
+
+        let stats = muxer.stats();
+        assert_eq!(stats.video_frame_count, 90);
+        assert_eq!(stats.segment_count, 1);
+        assert_eq!(stats.pending_segment_count, 1);
+        assert!(stats.segment_bytes > 0);
+        assert_eq!(stats.buffered_video_samples, 28);
+
+        let segments = muxer.get_pending_segments();
+        let stats = muxer.stats();
+        assert_eq!(stats.pending_segment_count, 0);
+        assert_eq!(stats.segment_bytes, segments[0].len() as u64);
+    }
+
+    #[test]
+    fn test_take_pending_chunks() {
+        let (sps, pps) = create_test_sps_pps();
+        let config = MuxideConfig {
+            sps: Some(sps),
+            pps: Some(pps),
+            ..Default::default()
+        };
+
+        let mut muxer = MuxideMuxerState::new(config);
+        let clock = ManualClock::new(1_700_000_000_000);
+        muxer.set_clock(ClockHandle::new(clock.clone()));
+        muxer.init().unwrap();
+        for i in 0..90u64 {
+            let data = [0x00, 0x00, 0x00, 0x01, 0x41];
+            muxer
+                .push_video_chunk(&data, i * 33333, i % 30 == 0)
+                .unwrap();
+        }
+        let session = SessionId::from("muxer-session");
+        let first = muxer.take_pending_chunks(&session);
+        muxer.force_flush().unwrap();
+        clock.advance(1500);
+        let second = muxer.take_pending_chunks(&session);
+        assert!(!muxer.has_pending_segments());
+
+        let chunks: Vec<RecordedChunk> = first.into_iter().chain(second).collect();
+        assert_eq!(chunks.len(), 2);
+        for (i, chunk) in chunks.iter().enumerate() {
+            let metadata = &chunk.metadata;
+            assert_eq!(
+                metadata.chunk_id,
+                ChunkId::new(session.clone(), TrackKind::Muxed, i as u64)
+            );
+            assert_eq!(metadata.size, chunk.data.len() as u64);
+            assert_eq!(
+                metadata.hash.as_deref(),
+                Some(blake3::hash(&chunk.data).to_hex().as_str())
+            );
+        }
+        // The first segment runs through frame 61; the rest has no keyframe
+        assert_eq!(chunks[0].metadata.timestamp_us, 0);
+        assert_eq!(chunks[0].metadata.has_keyframe, Some(true));
+        assert_eq!(chunks[1].metadata.timestamp_us, 62 * 33333);
+        assert_eq!(chunks[1].metadata.has_keyframe, Some(false));
+        assert_eq!(chunks[0].metadata.created_at, 1_700_000_000_000);
+        assert_eq!(chunks[1].metadata.created_at, 1_700_000_001_500);
+    }
+
+    #[test]
+    fn test_config_from_js_shape() {
+        let config: MuxideConfig = serde_json::from_str(
+            r#"{ "audioSampleRate": 48000, "audioChannels": 1, "audioSpecificConfig": [17, 136] }"#,
+        )
+        .unwrap();
+        assert!(config.has_audio());
+        assert!(!config.has_video());
+        assert_eq!(config.fragment_duration_ms, 2000);
+        assert_eq!(config.audio_specific_config, Some(vec![0x11, 0x88]));
+
+        let decl = <MuxideConfig as Tsify>::DECL;
+        assert!(decl.contains("sps?: Uint8Array | number[]"), "{}", decl);
+        assert!(decl.contains("fragmentDurationMs?: number"), "{}", decl);
+    }
+
+    #[test]
+    fn test_state_snapshot_restore() {
+        let (sps, pps) = create_test_sps_pps();
+        let config = MuxideConfig {
+            sps: Some(sps),
+            pps: Some(pps),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            audio_timescale: Some(48000),
+            ..Default::default()
+        };
+        let video = [0x00, 0x00, 0x00, 0x02, 0x65, 0x88];
+        let audio = [0x21, 0x10, 0x05];
+
+        let push = |muxer: &mut MuxideMuxerState, range: std::ops::Range<u64>| {
+            for i in range {
+                muxer
+                    .push_video_chunk(&video, i * 33_333, i % 30 == 0)
+                    .unwrap();
+                muxer.push_audio_chunk(&audio, i * 33_333, 21_333).unwrap();
+            }
+        };
+
+        let mut original = MuxideMuxerState::new(config);
+        original.init().unwrap();
+        push(&mut original, 0..75);
+        assert!(original.has_pending_segments());
+
+        let snapshot = original.serialize_state().unwrap();
+        let original_stats = original.stats();
+        let mut restored = MuxideMuxerState::restore_state(&snapshot).unwrap();
+        assert_eq!(restored.stats(), original_stats);
+        assert_eq!(
+            restored.get_init_segment().unwrap(),
+            original.get_init_segment().unwrap()
+        );
+
+        // Both continue identically, including the segment that was in progress
+        push(&mut original, 75..150);
+        push(&mut restored, 75..150);
+        assert_eq!(
+            restored.get_complete_file().unwrap(),
+            original.get_complete_file().unwrap()
+        );
+
+        assert!(MuxideMuxerState::restore_state(&snapshot[..snapshot.len() - 1]).is_err());
+        assert!(MuxideMuxerState::restore_state(b"nope").is_err());
+
+        // The previous version, without the skew correction fields, still
+        // restores
+        let config_len = u32::from_le_bytes(snapshot[5..9].try_into().unwrap()) as usize;
+        let skew = 9 + config_len + 126;
+        let mut previous = snapshot.clone();
+        previous[4] = STATE_VERSION - 1;
+        previous.drain(skew..skew + 25);
+        let restored = MuxideMuxerState::restore_state(&previous).unwrap();
+        assert_eq!(restored.stats(), original_stats);
+        previous[4] = MIN_STATE_VERSION - 1;
+        assert!(MuxideMuxerState::restore_state(&previous).is_err());
+    }
+
+    #[test]
+    fn test_refragmenter_merges_segments() {
+        let (sps, pps) = create_test_sps_pps();
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            sps: Some(sps),
+            pps: Some(pps),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            audio_timescale: Some(48000),
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        });
+        muxer.init().unwrap();
+        let init = muxer.get_init_segment().unwrap();
+        for i in 0..180u64 {
+            muxer
+                .push_video_chunk(&[0, 0, 0, 2, 0x65, i as u8], i * 33_333, i % 30 == 0)
+                .unwrap();
+            muxer
+                .push_audio_chunk(&[0x21, i as u8], i * 33_333, 21_333)
+                .unwrap();
+        }
+        muxer.force_flush().unwrap();
+        let segments = muxer.get_pending_segments();
+        assert_eq!(segments.len(), 6);
+
+        // One segment per fragment reproduces the input byte for byte
+        let mut identity = Refragmenter::new(&init, 1).unwrap();
+        for segment in &segments {
+            assert_eq!(
+                identity.push_segment(segment).unwrap().as_ref(),
+                Some(segment)
+            );
+        }
+        assert!(identity.finish().is_none());
+
+        let mut merger = Refragmenter::new(&init, 4).unwrap();
+        let mut merged: Vec<Vec<u8>> = segments
+            .iter()
+            .filter_map(|segment| merger.push_segment(segment).unwrap())
+            .collect();
+        merged.extend(merger.finish());
+        assert_eq!(merged.len(), 2);
+
+        // Same samples on the same timeline
+        let collect = |segments: &[Vec<u8>]| {
+            let mut all = Refragmenter::new(&init, u32::MAX).unwrap();
+            for segment in segments {
+                all.push_segment(segment).unwrap();
+            }
+            let video: Vec<(u64, bool, Vec<u8>)> = all
+                .video_samples
+                .iter()
+                .map(|s| (s.dts, s.flags.is_sync(), s.data.clone()))
+                .collect();
+            let audio: Vec<(u32, Vec<u8>)> = all
+                .audio_samples
+                .iter()
+                .map(|s| (s.duration, s.data.clone()))
+                .collect();
+            (video, audio)
+        };
+        let (video, audio) = collect(&merged);
+        assert_eq!(video.len(), 180);
+        assert_eq!(audio.len(), 180);
+        assert_eq!((video, audio), collect(&segments));
+
+        assert!(Refragmenter::new(&init, 0).is_err());
+        assert!(Refragmenter::new(b"nope", 1).is_err());
+    }
+
+    #[test]
+    fn test_change_audio_config() {
+        let (sps, pps) = create_test_sps_pps();
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            sps: Some(sps),
+            pps: Some(pps),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        });
+        muxer.init().unwrap();
+        let first_init = muxer.get_init_segment().unwrap();
+        let push = |muxer: &mut MuxideMuxerState, from: u64, to: u64, audio_us: u32| {
+            for i in from..to {
+                let ts = i * 33_333;
+                muxer
+                    .push_video_chunk(&[0, 0, 0, 2, 0x65, i as u8], ts, i % 30 == 0)
+                    .unwrap();
+                muxer
+                    .push_audio_chunk(&[0x21, i as u8], ts, audio_us)
+                    .unwrap();
+            }
+        };
+        push(&mut muxer, 0, 45, 21_333);
+        assert!(!muxer.change_audio_config(48000, 2, None).unwrap());
+        assert!(muxer.change_audio_config(44100, 1, None).unwrap());
+        assert_eq!(muxer.stats().buffered_audio_samples, 0);
+        assert!(!muxer.change_audio_config(44100, 1, None).unwrap());
+        push(&mut muxer, 45, 90, 23_220);
+        muxer.force_flush().unwrap();
+
+        // The new init segment still plays the segments muxed for the first
+        let init = muxer.get_init_segment().unwrap();
+        assert!(
+            compare_init_segments(&first_init, &init)
+                .unwrap()
+                .compatible
+        );
+        let tracks = read_init_tracks(&init).unwrap();
+        assert_eq!(tracks[&2].timescale, 48000);
+        assert_eq!(muxer.config().audio_timescale, Some(48000));
+        assert_eq!(
+            muxer.config().previous_audio_configs,
+            [AudioSampleEntry {
+                sample_rate: 48000,
+                channels: 2,
+                audio_specific_config: None,
+            }]
+        );
+
+        let segments = muxer.get_pending_segments();
+        let audio_runs: Vec<(u32, u64, u64)> = segments
+            .iter()
+            .map(|segment| {
+                let moof = find_box(segment, b"moof").unwrap().unwrap();
+                let traf = parse_boxes(moof.payload)
+                    .unwrap()
+                    .into_iter()
+                    .filter(|b| &b.typ == b"traf")
+                    .nth(1)
+                    .unwrap();
+                let tfhd = find_box(traf.payload, b"tfhd").unwrap().unwrap();
+                let tfdt = find_box(traf.payload, b"tfdt").unwrap().unwrap();
+                let trun = find_box(traf.payload, b"trun").unwrap().unwrap();
+                let duration = read_trun(trun.payload)
+                    .unwrap()
+                    .entries
+                    .iter()
+                    .map(|e| e.duration as u64)
+                    .sum();
+                (
+                    read_tfhd_description_index(tfhd.payload).unwrap(),
+                    read_u64(tfdt.payload, 4).unwrap(),
+                    duration,
+                )
+            })
+            .collect();
+        assert_eq!(
+            audio_runs.iter().map(|r| r.0).collect::<Vec<_>>(),
+            [1, 1, 2, 2]
+        );
+        // Continuous decode times; 1024 samples at 44.1 kHz in 48 kHz ticks
+        for pair in audio_runs.windows(2) {
+            assert_eq!(pair[0].1 + pair[0].2, pair[1].1);
+        }
+        let after: u64 = audio_runs[2..].iter().map(|r| r.2).sum();
+        assert_eq!(after, (45 * 1024 * 48000 + 44100 / 2) / 44100);
+
+        // Merging never mixes sample descriptions in one fragment
+        let mut merger = Refragmenter::new(&init, 3).unwrap();
+        let mut merged: Vec<Vec<u8>> = segments
+            .iter()
+            .filter_map(|segment| merger.push_segment(segment).unwrap())
+            .collect();
+        merged.extend(merger.finish());
+        assert_eq!(merged.len(), 2);
+        let audio = trace_segment(&merged[0])
+            .unwrap()
+            .into_iter()
+            .find(|t| t.track_id == 2)
+            .unwrap();
+        assert_eq!(audio.duration, audio_runs[0].2 + audio_runs[1].2);
+
+        // Snapshots keep every description
+        let restored = MuxideMuxerState::restore_state(&muxer.serialize_state().unwrap()).unwrap();
+        assert_eq!(restored.get_init_segment().unwrap(), init);
+
+        let mut gapless = MuxideMuxerState::new(MuxideConfig {
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            audio_priming_samples: Some(1024),
+            sps: None,
+            pps: None,
+            ..Default::default()
+        });
+        gapless.init().unwrap();
+        assert!(gapless.change_audio_config(44100, 2, None).is_err());
+    }
+
     #[test]
     fn test_extract_sps_pps() {
         // Sample avcC data
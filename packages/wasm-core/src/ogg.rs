@@ -0,0 +1,413 @@
+//! Ogg/Opus export of audio-only recordings.
+//!
+//! Podcast tools tend to prefer `.opus` (Opus in Ogg, RFC 7845) over Opus in
+//! MP4. `OggOpusWriterState` takes the raw Opus packets of a WebCodecs
+//! `AudioEncoder` (codec `"opus"`) in order and writes them out as an Ogg
+//! stream: an `OpusHead` page, an `OpusTags` page, then audio pages. Each
+//! page's granule position counts 48 kHz samples, read from the packets'
+//! TOC bytes, up to its last complete packet, with the decoder pre-skip
+//! included.
+//!
+//! Output is produced incrementally, so a session can be written to storage
+//! or streamed page by page as it is recorded. Only mono and stereo (channel
+//! mapping family 0) are supported.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+/// Opus granule positions always count samples at 48 kHz
+pub const OPUS_GRANULE_RATE: u64 = 48000;
+
+/// Audio pages are closed once they hold this many samples (1 s), so seeking
+/// needs at most a page of decoding
+const MAX_PAGE_SAMPLES: u64 = OPUS_GRANULE_RATE;
+/// Lacing values a page can hold
+const MAX_PAGE_SEGMENTS: usize = 255;
+
+/// Page header flags
+const FLAG_CONTINUED: u8 = 0x01;
+const FLAG_BEGIN_OF_STREAM: u8 = 0x02;
+const FLAG_END_OF_STREAM: u8 = 0x04;
+
+/// Stream settings written to `OpusHead` and `OpusTags`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OggOpusConfig {
+    /// 1 or 2
+    pub channels: u8,
+    /// Sample rate of the captured audio, for information only
+    pub input_sample_rate: u32,
+    /// Samples (48 kHz) the decoder discards at the start: the encoder's
+    /// lookahead
+    pub pre_skip: u16,
+    /// Ogg logical stream serial number
+    pub serial: u32,
+    /// `KEY=value` comments for `OpusTags`, e.g. `TITLE=Episode 12`
+    pub comments: Vec<String>,
+}
+
+impl Default for OggOpusConfig {
+    fn default() -> Self {
+        Self {
+            channels: 2,
+            input_sample_rate: 48000,
+            // libopus lookahead at 48 kHz
+            pre_skip: 312,
+            serial: 0x6d61_7963,
+            comments: Vec::new(),
+        }
+    }
+}
+
+/// Granule position of a page on which no packet ends
+const NO_GRANULE: u64 = u64::MAX;
+
+/// Writes Opus packets as an Ogg stream
+#[derive(Debug, Clone)]
+pub struct OggOpusWriterState {
+    config: OggOpusConfig,
+    page_sequence: u32,
+    /// Samples of all packets pushed so far, pre-skip included
+    granule: u64,
+    /// Packets of the page being built
+    lacing: Vec<u8>,
+    body: Vec<u8>,
+    page_samples: u64,
+    headers_written: bool,
+    finished: bool,
+}
+
+impl OggOpusWriterState {
+    pub fn new(config: OggOpusConfig) -> Result<Self, String> {
+        if !(1..=2).contains(&config.channels) {
+            return Err(format!(
+                "Ogg/Opus export supports 1 or 2 channels, not {}",
+                config.channels
+            ));
+        }
+        Ok(Self {
+            granule: config.pre_skip as u64,
+            config,
+            page_sequence: 0,
+            lacing: Vec::new(),
+            body: Vec::new(),
+            page_samples: 0,
+            headers_written: false,
+            finished: false,
+        })
+    }
+
+    /// Total samples (48 kHz) pushed so far, without the pre-skip
+    pub fn duration_samples(&self) -> u64 {
+        self.granule - self.config.pre_skip as u64
+    }
+
+    /// Add an Opus packet; returns the bytes completed by it (header pages
+    /// on the first call, then finished audio pages), possibly none
+    pub fn push_packet(&mut self, packet: &[u8]) -> Result<Vec<u8>, String> {
+        if self.finished {
+            return Err("Ogg/Opus stream already finished".to_string());
+        }
+        let samples = opus_packet_samples(packet)?;
+        let segments = packet.len() / 255 + 1;
+        if segments > MAX_PAGE_SEGMENTS {
+            return Err(format!(
+                "Opus packet of {} bytes is too large for one Ogg page",
+                packet.len()
+            ));
+        }
+
+        let mut out = self.take_headers();
+        if self.lacing.len() + segments > MAX_PAGE_SEGMENTS {
+            self.flush_page(0, &mut out);
+        }
+        self.lacing.extend(std::iter::repeat_n(255, segments - 1));
+        self.lacing.push((packet.len() % 255) as u8);
+        self.body.extend_from_slice(packet);
+        self.granule += samples;
+        self.page_samples += samples;
+        if self.page_samples >= MAX_PAGE_SAMPLES {
+            self.flush_page(0, &mut out);
+        }
+        Ok(out)
+    }
+
+    /// End the stream; returns the remaining bytes, the last page marked
+    /// end-of-stream
+    pub fn finish(&mut self) -> Result<Vec<u8>, String> {
+        if self.finished {
+            return Err("Ogg/Opus stream already finished".to_string());
+        }
+        let mut out = self.take_headers();
+        self.flush_page(FLAG_END_OF_STREAM, &mut out);
+        self.finished = true;
+        Ok(out)
+    }
+
+    /// The OpusHead and OpusTags pages, once
+    fn take_headers(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        if self.headers_written {
+            return out;
+        }
+        self.headers_written = true;
+
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // Version
+        head.push(self.config.channels);
+        head.extend_from_slice(&self.config.pre_skip.to_le_bytes());
+        head.extend_from_slice(&self.config.input_sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // Output gain
+        head.push(0); // Channel mapping family
+        self.write_header_packet(FLAG_BEGIN_OF_STREAM, &head, &mut out);
+
+        let vendor = concat!("maycast-wasm-core ", env!("CARGO_PKG_VERSION"));
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&(self.config.comments.len() as u32).to_le_bytes());
+        for comment in &self.config.comments {
+            tags.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            tags.extend_from_slice(comment.as_bytes());
+        }
+        self.write_header_packet(0, &tags, &mut out);
+        out
+    }
+
+    /// Write a header packet on pages of its own, as many as it needs
+    /// (comments can make `OpusTags` larger than one page holds): pages
+    /// after the first continue it, and only the last one ends it
+    fn write_header_packet(&mut self, flags: u8, packet: &[u8], out: &mut Vec<u8>) {
+        let mut lacing = vec![255; packet.len() / 255];
+        lacing.push((packet.len() % 255) as u8);
+        let pages = lacing.len().div_ceil(MAX_PAGE_SEGMENTS);
+        let mut body = packet;
+        for (index, page_lacing) in lacing.chunks(MAX_PAGE_SEGMENTS).enumerate() {
+            let len = page_lacing.iter().map(|&l| l as usize).sum();
+            let (page_body, rest) = body.split_at(len);
+            let flags = if index == 0 { flags } else { FLAG_CONTINUED };
+            let granule = if index + 1 == pages { 0 } else { NO_GRANULE };
+            self.write_page(flags, granule, page_lacing, page_body, out);
+            body = rest;
+        }
+    }
+
+    /// Write the page being built, if it has packets or ends the stream
+    fn flush_page(&mut self, flags: u8, out: &mut Vec<u8>) {
+        if self.lacing.is_empty() && flags & FLAG_END_OF_STREAM == 0 {
+            return;
+        }
+        let lacing = std::mem::take(&mut self.lacing);
+        let body = std::mem::take(&mut self.body);
+        self.write_page(flags, self.granule, &lacing, &body, out);
+        self.page_samples = 0;
+    }
+
+    fn write_page(
+        &mut self,
+        flags: u8,
+        granule: u64,
+        lacing: &[u8],
+        body: &[u8],
+        out: &mut Vec<u8>,
+    ) {
+        debug_assert!(lacing.len() <= MAX_PAGE_SEGMENTS);
+        let start = out.len();
+        out.extend_from_slice(b"OggS");
+        out.push(0); // Version
+        out.push(flags);
+        out.extend_from_slice(&granule.to_le_bytes());
+        out.extend_from_slice(&self.config.serial.to_le_bytes());
+        out.extend_from_slice(&self.page_sequence.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes()); // CRC, filled in below
+        out.push(lacing.len() as u8);
+        out.extend_from_slice(lacing);
+        out.extend_from_slice(body);
+        let crc = ogg_crc(&out[start..]);
+        out[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+        self.page_sequence += 1;
+    }
+}
+
+/// Write a whole Ogg/Opus file from the packets of a recording
+pub fn write_ogg_opus<'a>(
+    config: OggOpusConfig,
+    packets: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<Vec<u8>, String> {
+    let mut writer = OggOpusWriterState::new(config)?;
+    let mut out = Vec::new();
+    for packet in packets {
+        out.extend(writer.push_packet(packet)?);
+    }
+    out.extend(writer.finish()?);
+    Ok(out)
+}
+
+/// Samples (48 kHz) in an Opus packet, from its TOC byte (RFC 6716 3.1)
+pub fn opus_packet_samples(packet: &[u8]) -> Result<u64, String> {
+    let toc = *packet.first().ok_or("Empty Opus packet")?;
+    let config = toc >> 3;
+    // Frame size in units of 2.5 ms (120 samples)
+    let frame_units: u64 = match config {
+        // SILK: 10, 20, 40, 60 ms
+        0..=11 => [4, 8, 16, 24][(config % 4) as usize],
+        // Hybrid: 10, 20 ms
+        12..=15 => [4, 8][(config % 2) as usize],
+        // CELT: 2.5, 5, 10, 20 ms
+        _ => [1, 2, 4, 8][(config % 4) as usize],
+    };
+    let frames: u64 = match toc & 0x3 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => (*packet.get(1).ok_or("Opus packet has no frame count")? & 0x3F) as u64,
+    };
+    let samples = frames * frame_units * 120;
+    // At most 120 ms per packet
+    if frames == 0 || samples > 5760 {
+        return Err(format!("Invalid Opus packet TOC: {:#04x}", toc));
+    }
+    Ok(samples)
+}
+
+/// Ogg page checksum: CRC-32, polynomial 0x04C11DB7, no reflection
+fn ogg_crc(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (flags, granule, sequence, packets ending on it) of every page
+    fn pages(mut data: &[u8]) -> Vec<(u8, u64, u32, Vec<Vec<u8>>)> {
+        let mut pages = Vec::new();
+        let mut packet = Vec::new();
+        while !data.is_empty() {
+            assert_eq!(&data[..4], b"OggS");
+            let segments = data[26] as usize;
+            let lacing = &data[27..27 + segments];
+            let size = 27 + segments + lacing.iter().map(|&l| l as usize).sum::<usize>();
+            let mut page = data[..size].to_vec();
+            page[22..26].fill(0);
+            assert_eq!(
+                ogg_crc(&page),
+                u32::from_le_bytes(data[22..26].try_into().unwrap())
+            );
+
+            let mut packets = Vec::new();
+            let mut pos = 27 + segments;
+            for &l in lacing {
+                packet.extend_from_slice(&data[pos..pos + l as usize]);
+                pos += l as usize;
+                if l < 255 {
+                    packets.push(std::mem::take(&mut packet));
+                }
+            }
+            pages.push((
+                data[5],
+                u64::from_le_bytes(data[6..14].try_into().unwrap()),
+                u32::from_le_bytes(data[18..22].try_into().unwrap()),
+                packets,
+            ));
+            data = &data[size..];
+        }
+        pages
+    }
+
+    #[test]
+    fn test_ogg_opus_export() {
+        // CELT 20 ms stereo frames (config 31, code 0): 960 samples each
+        let packet = [0xFC, 0x01, 0x02, 0x03];
+        assert_eq!(opus_packet_samples(&packet).unwrap(), 960);
+        // SILK 60 ms, code 3 with 2 frames: 120 ms
+        assert_eq!(opus_packet_samples(&[0x1B, 0x02]).unwrap(), 5760);
+        assert!(opus_packet_samples(&[0x1B, 0x03]).is_err());
+        assert_eq!(ogg_crc(b"123456789"), 0x89A1_897F);
+
+        let config = OggOpusConfig {
+            comments: vec!["TITLE=Episode 12".to_string()],
+            ..Default::default()
+        };
+        let file = write_ogg_opus(config, std::iter::repeat_n(&packet[..], 60)).unwrap();
+        let pages = pages(&file);
+        assert_eq!(pages.len(), 4);
+
+        let (flags, granule, sequence, packets) = &pages[0];
+        assert_eq!((*flags, *granule, *sequence), (FLAG_BEGIN_OF_STREAM, 0, 0));
+        assert_eq!(
+            packets[0],
+            [
+                b"OpusHead".as_slice(),
+                &[1, 2, 0x38, 0x01, 0x80, 0xBB, 0, 0, 0, 0, 0]
+            ]
+            .concat()
+        );
+        let tags = &pages[1].3[0];
+        assert!(tags.starts_with(b"OpusTags"));
+        assert!(tags.ends_with(b"\x10\0\0\0TITLE=Episode 12"));
+
+        // 50 packets make a 1 s page; the rest end the stream
+        assert_eq!(pages[2].1, 312 + 48000);
+        assert_eq!(pages[2].3.len(), 50);
+        let (flags, granule, sequence, packets) = &pages[3];
+        assert_eq!(
+            (*flags, *granule, *sequence),
+            (FLAG_END_OF_STREAM, 312 + 57600, 3)
+        );
+        assert_eq!(packets.len(), 10);
+        assert_eq!(packets[0], packet);
+
+        assert!(OggOpusWriterState::new(OggOpusConfig {
+            channels: 6,
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_large_tags_span_pages() {
+        // A cover-art sized comment needs more than 255 lacing values
+        let comment = format!("METADATA_BLOCK_PICTURE={}", "A".repeat(140_000));
+        let config = OggOpusConfig {
+            comments: vec![comment.clone()],
+            ..Default::default()
+        };
+        let file = write_ogg_opus(config, [&[0xFC, 0x01][..]]).unwrap();
+        let tag_pages = pages(&file);
+        assert_eq!(tag_pages.len(), 5);
+        // OpusTags continues over pages 2 and 3 and ends on page 3
+        assert_eq!((tag_pages[1].0, tag_pages[1].1), (0, NO_GRANULE));
+        assert!(tag_pages[1].3.is_empty());
+        assert_eq!(
+            (tag_pages[2].0, tag_pages[2].1),
+            (FLAG_CONTINUED, NO_GRANULE)
+        );
+        assert_eq!((tag_pages[3].0, tag_pages[3].1), (FLAG_CONTINUED, 0));
+        let tags = &tag_pages[3].3[0];
+        assert!(tags.starts_with(b"OpusTags"));
+        assert!(tags.ends_with(comment.as_bytes()));
+        assert_eq!(tag_pages[4].3, [vec![0xFC, 0x01]]);
+        assert_eq!(tag_pages[4].2, 4);
+
+        // A packet of exactly 255 * 255 bytes ends with a lacing value of 0
+        // on a page of its own
+        let mut writer = OggOpusWriterState::new(OggOpusConfig::default()).unwrap();
+        let mut out = Vec::new();
+        writer.write_header_packet(0, &[7; 255 * 255], &mut out);
+        let pages = pages(&out);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[1].3, [vec![7; 255 * 255]]);
+    }
+}
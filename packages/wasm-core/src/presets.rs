@@ -0,0 +1,130 @@
+//! Named recording presets.
+//!
+//! A preset bundles everything a product picks when it sets up a recording:
+//! the muxer configuration, the adaptive chunk sizing bounds and the upload
+//! concurrency and retry settings. The presets are data: `presets.json`
+//! next to this file is embedded here and a copy ships in
+//! `@maycast/common-types` (`src/presets.json`), so JS selects one by ID on
+//! either side of the WASM boundary and tuning them needs no new glue code.
+//! Edit both copies together; a test fails while they differ.
+//!
+//! Encoder-provided fields (SPS/PPS, AudioSpecificConfig) are never part of
+//! a preset; they are added to its `muxer` config at runtime.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::muxide_muxer::MuxideConfig;
+use crate::sizing::ChunkSizingConfig;
+
+/// The built-in presets, copied to `@maycast/common-types`
+const PRESETS_JSON: &str = include_str!("presets.json");
+
+/// Upload concurrency and retry settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadPolicy {
+    pub max_concurrent_uploads: u32,
+    /// Retries per chunk before it is marked failed
+    pub max_retries: u32,
+    /// Wait before a retry, multiplied by the retry count
+    pub retry_delay_ms: u32,
+}
+
+#[cfg(feature = "native")]
+impl From<UploadPolicy> for crate::uploader::UploadOptions {
+    fn from(policy: UploadPolicy) -> Self {
+        Self {
+            max_concurrent_uploads: policy.max_concurrent_uploads as usize,
+            max_retries: policy.max_retries,
+            retry_delay: std::time::Duration::from_millis(policy.retry_delay_ms as u64),
+        }
+    }
+}
+
+/// A named, complete recording configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingPreset {
+    /// Stable identifier to select the preset by
+    pub id: String,
+    /// Name shown in the UI
+    pub name: String,
+    pub description: String,
+    /// Muxer configuration, without the encoder-provided fields
+    pub muxer: MuxideConfig,
+    /// Bounds of the adaptive chunk size policy
+    pub chunking: ChunkSizingConfig,
+    pub upload: UploadPolicy,
+}
+
+/// All built-in presets, in display order
+pub fn recording_presets() -> Result<Vec<RecordingPreset>, String> {
+    serde_json::from_str(PRESETS_JSON).map_err(|e| format!("Invalid recording presets: {}", e))
+}
+
+/// The built-in preset with ID `id`
+pub fn recording_preset(id: &str) -> Result<RecordingPreset, String> {
+    recording_presets()?
+        .into_iter()
+        .find(|preset| preset.id == id)
+        .ok_or_else(|| format!("Unknown recording preset: {}", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::muxide_muxer::MuxideMuxerState;
+
+    #[test]
+    fn test_builtin_presets() {
+        let presets = recording_presets().unwrap();
+        assert!(presets.len() >= 3);
+        for (i, preset) in presets.iter().enumerate() {
+            assert!(
+                presets[..i].iter().all(|other| other.id != preset.id),
+                "duplicate preset {}",
+                preset.id
+            );
+            let chunking = &preset.chunking;
+            assert!(chunking.min_fragment_ms <= preset.muxer.fragment_duration_ms);
+            assert!(preset.muxer.fragment_duration_ms <= chunking.max_fragment_ms);
+            assert!(preset.upload.max_concurrent_uploads > 0);
+            assert!(preset.muxer.has_audio() || preset.muxer.has_video());
+        }
+
+        let podcast = recording_preset("audio-only-podcast").unwrap();
+        assert!(!podcast.muxer.has_video());
+        assert!(podcast.muxer.has_gapless_audio());
+
+        // A video preset becomes a working muxer once the encoder's
+        // parameter sets are added
+        let screen = recording_preset("screen-1080p30-talk").unwrap();
+        assert_eq!(screen.muxer.video_width, Some(1920));
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            ..screen.muxer
+        });
+        muxer.init().unwrap();
+        muxer
+            .push_video_chunk(&[0, 0, 0, 1, 0x65], 0, true)
+            .unwrap();
+
+        assert!(recording_preset("nope").is_err());
+    }
+
+    #[test]
+    fn test_common_types_copy_matches() {
+        // Only checked in the monorepo; the crate builds on its own
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../common-types/src/presets.json"
+        );
+        if let Ok(copy) = std::fs::read_to_string(path) {
+            assert_eq!(copy, PRESETS_JSON, "presets.json copies differ");
+        }
+    }
+}
@@ -0,0 +1,283 @@
+//! Near-live preview of a recording in progress.
+//!
+//! Keeps the init segment and a rolling window of the most recent media
+//! segments produced by the recorder. The host app can append them to a
+//! `SourceBuffer` for an MSE preview, or serve them with the HLS playlist
+//! generated here, without running a second muxer. Voice-only sessions go
+//! through the same window: their segments are fMP4 audio (M4A) fragments,
+//! each decodable on its own, and the multivariant playlist names the audio
+//! codec so players do not wait for a video track.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::chunk::ChunkId;
+use crate::storage::{chunk_file_name, INIT_SEGMENT_FILE};
+
+/// A media segment held in the preview window
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreviewSegment {
+    pub chunk_id: ChunkId,
+    pub timestamp_us: u64,
+    pub duration_us: u64,
+    pub data: Vec<u8>,
+    /// URI of the AES-128 key `data` is encrypted with, if any
+    pub key_uri: Option<String>,
+}
+
+/// Description of one segment in the window, without its bytes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewSegmentInfo {
+    /// Chunk sequence number, also the HLS media sequence number
+    pub sequence: u64,
+    pub timestamp_us: u64,
+    pub duration_us: u64,
+    pub size: u64,
+}
+
+/// Rolling window of the most recent segments
+#[derive(Debug, Clone)]
+pub struct LivePreviewState {
+    max_segments: usize,
+    init_segment: Option<Vec<u8>>,
+    /// RFC 6381 codec strings of the init segment's tracks
+    codecs: Vec<String>,
+    segments: VecDeque<PreviewSegment>,
+    ended: bool,
+}
+
+impl LivePreviewState {
+    /// Create a preview keeping at most `max_segments` media segments
+    pub fn new(max_segments: u32) -> Self {
+        Self {
+            max_segments: max_segments.max(1) as usize,
+            init_segment: None,
+            codecs: Vec::new(),
+            segments: VecDeque::new(),
+            ended: false,
+        }
+    }
+
+    /// Set the init segment (ftyp + moov) the window's segments depend on,
+    /// with the codec strings of its tracks
+    pub fn set_init_segment(&mut self, data: Vec<u8>, codecs: Vec<String>) {
+        self.init_segment = Some(data);
+        self.codecs = codecs;
+    }
+
+    /// Get the init segment to append before any media segment
+    pub fn init_segment(&self) -> Option<&[u8]> {
+        self.init_segment.as_deref()
+    }
+
+    /// Add a finished segment, evicting the oldest one if the window is full
+    pub fn push_segment(&mut self, segment: PreviewSegment) {
+        if self.segments.len() == self.max_segments {
+            self.segments.pop_front();
+        }
+        self.segments.push_back(segment);
+    }
+
+    /// Whether the recording has only an audio track, whose segments all
+    /// start with a sync sample
+    pub fn audio_only(&self) -> bool {
+        !self.codecs.is_empty() && self.codecs.iter().all(|codec| codec.starts_with("mp4a"))
+    }
+
+    /// Mark the recording as finished; the playlist gets an end tag
+    pub fn end(&mut self) {
+        self.ended = true;
+    }
+
+    /// Get the segments currently in the window, oldest first
+    pub fn segments(&self) -> impl Iterator<Item = &PreviewSegment> {
+        self.segments.iter()
+    }
+
+    /// Find a segment in the window by its sequence number
+    pub fn segment(&self, sequence: u64) -> Option<&PreviewSegment> {
+        self.segments
+            .iter()
+            .find(|segment| segment.chunk_id.sequence == sequence)
+    }
+
+    /// Describe the segments currently in the window, oldest first
+    pub fn window(&self) -> Vec<PreviewSegmentInfo> {
+        self.segments
+            .iter()
+            .map(|segment| PreviewSegmentInfo {
+                sequence: segment.chunk_id.sequence,
+                timestamp_us: segment.timestamp_us,
+                duration_us: segment.duration_us,
+                size: segment.data.len() as u64,
+            })
+            .collect()
+    }
+
+    /// Render a live HLS media playlist (fMP4 segments) for the window
+    ///
+    /// Segment and init URIs are the storage file names prefixed with
+    /// `uri_prefix`, so a playlist can also point at persisted chunks.
+    /// Encrypted segments get an `EXT-X-KEY` tag wherever the key changes.
+    /// Key URIs are written as given.
+    pub fn hls_playlist(&self, uri_prefix: &str) -> String {
+        let target_duration = self
+            .segments
+            .iter()
+            .map(|segment| segment.duration_us.div_ceil(1_000_000))
+            .max()
+            .unwrap_or(1)
+            .max(1);
+        let media_sequence = self
+            .segments
+            .front()
+            .map(|segment| segment.chunk_id.sequence)
+            .unwrap_or(0);
+
+        let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:7\n");
+        if self.audio_only() {
+            playlist.push_str("#EXT-X-INDEPENDENT-SEGMENTS\n");
+        }
+        playlist.push_str(&format!("#EXT-X-TARGETDURATION:{}\n", target_duration));
+        playlist.push_str(&format!("#EXT-X-MEDIA-SEQUENCE:{}\n", media_sequence));
+        playlist.push_str(&format!(
+            "#EXT-X-MAP:URI=\"{}{}\"\n",
+            uri_prefix, INIT_SEGMENT_FILE
+        ));
+        let mut key_uri = None;
+        for segment in &self.segments {
+            if segment.key_uri != key_uri {
+                key_uri = segment.key_uri.clone();
+                match &key_uri {
+                    Some(uri) => {
+                        playlist.push_str(&format!("#EXT-X-KEY:METHOD=AES-128,URI=\"{}\"\n", uri))
+                    }
+                    None => playlist.push_str("#EXT-X-KEY:METHOD=NONE\n"),
+                }
+            }
+            playlist.push_str(&format!(
+                "#EXTINF:{:.3},\n{}{}\n",
+                segment.duration_us as f64 / 1_000_000.0,
+                uri_prefix,
+                chunk_file_name(&segment.chunk_id)
+            ));
+        }
+        if self.ended {
+            playlist.push_str("#EXT-X-ENDLIST\n");
+        }
+        playlist
+    }
+
+    /// Render a multivariant HLS playlist with the media playlist at
+    /// `media_uri` as its only variant
+    ///
+    /// BANDWIDTH is the peak bit rate of the segments in the window, CODECS
+    /// those of the init segment; audio-only players need the latter to
+    /// start without a video track.
+    pub fn hls_multivariant_playlist(&self, media_uri: &str) -> String {
+        let bandwidth = self
+            .segments
+            .iter()
+            .filter(|segment| segment.duration_us > 0)
+            .map(|segment| segment.data.len() as u64 * 8 * 1_000_000 / segment.duration_us)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+
+        let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:7\n");
+        if self.audio_only() {
+            playlist.push_str("#EXT-X-INDEPENDENT-SEGMENTS\n");
+        }
+        playlist.push_str(&format!("#EXT-X-STREAM-INF:BANDWIDTH={}", bandwidth));
+        if !self.codecs.is_empty() {
+            playlist.push_str(&format!(",CODECS=\"{}\"", self.codecs.join(",")));
+        }
+        playlist.push_str(&format!("\n{}\n", media_uri));
+        playlist
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::TrackKind;
+    use crate::session::SessionId;
+
+    fn segment(sequence: u64) -> PreviewSegment {
+        PreviewSegment {
+            chunk_id: ChunkId::new(SessionId::from("s1"), TrackKind::Muxed, sequence),
+            timestamp_us: sequence * 2_000_000,
+            duration_us: 2_033_333,
+            data: vec![sequence as u8; 4],
+            key_uri: None,
+        }
+    }
+
+    #[test]
+    fn test_preview_keeps_rolling_window() {
+        let mut preview = LivePreviewState::new(3);
+        for sequence in 0..5 {
+            preview.push_segment(segment(sequence));
+        }
+
+        let window = preview.window();
+        assert_eq!(
+            window.iter().map(|s| s.sequence).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+        assert!(preview.segment(1).is_none());
+        assert_eq!(preview.segment(4).unwrap().data, vec![4; 4]);
+    }
+
+    #[test]
+    fn test_preview_hls_playlist() {
+        let mut preview = LivePreviewState::new(2);
+        for sequence in 0..3 {
+            preview.push_segment(segment(sequence));
+        }
+
+        let playlist = preview.hls_playlist("/preview/");
+        assert_eq!(
+            playlist,
+            "#EXTM3U\n\
+             #EXT-X-VERSION:7\n\
+             #EXT-X-TARGETDURATION:3\n\
+             #EXT-X-MEDIA-SEQUENCE:1\n\
+             #EXT-X-MAP:URI=\"/preview/init.mp4\"\n\
+             #EXTINF:2.033,\n/preview/chunk-00000001.fmp4\n\
+             #EXTINF:2.033,\n/preview/chunk-00000002.fmp4\n"
+        );
+
+        preview.end();
+        assert!(preview.hls_playlist("").ends_with("#EXT-X-ENDLIST\n"));
+    }
+
+    #[test]
+    fn test_preview_audio_only_playlists() {
+        let mut preview = LivePreviewState::new(3);
+        preview.set_init_segment(vec![0; 8], vec!["mp4a.40.2".to_string()]);
+        assert!(preview.audio_only());
+        for sequence in 0..3 {
+            preview.push_segment(segment(sequence));
+        }
+        assert!(preview
+            .hls_playlist("")
+            .starts_with("#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n"));
+        assert_eq!(
+            preview.hls_multivariant_playlist("live.m3u8"),
+            "#EXTM3U\n\
+             #EXT-X-VERSION:7\n\
+             #EXT-X-INDEPENDENT-SEGMENTS\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=15,CODECS=\"mp4a.40.2\"\n\
+             live.m3u8\n"
+        );
+
+        preview.set_init_segment(vec![0; 8], vec!["avc1.42C01E".into(), "mp4a.40.2".into()]);
+        assert!(!preview.audio_only());
+        assert!(!preview.hls_playlist("").contains("INDEPENDENT"));
+    }
+}
@@ -0,0 +1,945 @@
+//! Protobuf messages for metadata interchange (`proto` feature).
+//!
+//! The schema is `proto/maycast.proto` (package `maycast.v1`); gRPC services
+//! and non-Rust consumers generate their types from it. The message structs
+//! below are the prost equivalent of that schema, written out with
+//! `prost::Message` derives so the crate builds without `protoc`. Keep both in
+//! sync and never renumber or reuse a tag.
+//!
+//! Domain types convert into messages infallibly; the way back is `TryFrom`,
+//! since a message may carry unknown enum values or miss required fields.
+
+use crate::{chunk, manifest, metadata, session};
+
+/// Kind of media carried by a chunk
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum TrackKind {
+    Muxed = 0,
+    Video = 1,
+    Audio = 2,
+}
+
+/// Session lifecycle state
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum SessionState {
+    Standby = 0,
+    Recording = 1,
+    Finalizing = 2,
+    Synced = 3,
+    Interrupted = 4,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+pub struct ChunkId {
+    /// Empty for legacy (pre-composite) IDs
+    #[prost(string, tag = "1")]
+    pub session_id: String,
+    #[prost(enumeration = "TrackKind", tag = "2")]
+    pub track: i32,
+    #[prost(uint64, tag = "3")]
+    pub sequence: u64,
+    /// Simulcast rendition name, unset for single-rendition recordings
+    #[prost(string, optional, tag = "4")]
+    pub rendition: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+pub struct ChunkMetadata {
+    #[prost(message, optional, tag = "1")]
+    pub chunk_id: Option<ChunkId>,
+    /// Microseconds from session start
+    #[prost(uint64, tag = "2")]
+    pub timestamp_us: u64,
+    #[prost(uint64, tag = "3")]
+    pub size: u64,
+    /// BLAKE3 hash of the chunk data (hex)
+    #[prost(string, optional, tag = "4")]
+    pub hash: Option<String>,
+    #[prost(bool, optional, tag = "5")]
+    pub has_keyframe: Option<bool>,
+    /// Unix timestamp in milliseconds
+    #[prost(uint64, tag = "6")]
+    pub created_at: u64,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+pub struct DeviceInfo {
+    #[prost(string, tag = "1")]
+    pub browser: String,
+    #[prost(string, tag = "2")]
+    pub os: String,
+    #[prost(string, tag = "3")]
+    pub screen_resolution: String,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+pub struct AudioConfig {
+    #[prost(string, tag = "1")]
+    pub codec: String,
+    #[prost(uint32, tag = "2")]
+    pub sample_rate: u32,
+    #[prost(uint32, tag = "3")]
+    pub channel_count: u32,
+    #[prost(uint32, tag = "4")]
+    pub bitrate: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SyncInfo {
+    #[prost(double, tag = "1")]
+    pub scheduled_start_time: f64,
+    #[prost(double, tag = "2")]
+    pub actual_start_time: f64,
+    #[prost(double, tag = "3")]
+    pub clock_offset_ms: f64,
+    #[prost(double, tag = "4")]
+    pub clock_offset_accuracy_ms: f64,
+    #[prost(uint32, tag = "5")]
+    pub sync_sample_count: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LoudnessStats {
+    #[prost(double, optional, tag = "1")]
+    pub integrated_lufs: Option<f64>,
+    #[prost(double, optional, tag = "2")]
+    pub max_momentary_lufs: Option<f64>,
+    #[prost(double, optional, tag = "3")]
+    pub max_short_term_lufs: Option<f64>,
+    #[prost(double, tag = "4")]
+    pub peak_dbfs: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FrameRateStats {
+    #[prost(double, tag = "1")]
+    pub average_fps: f64,
+    #[prost(double, tag = "2")]
+    pub median_fps: f64,
+    #[prost(double, tag = "3")]
+    pub p5_fps: f64,
+    #[prost(double, tag = "4")]
+    pub p95_fps: f64,
+    #[prost(bool, tag = "5")]
+    pub variable: bool,
+    #[prost(uint64, tag = "6")]
+    pub frame_count: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RecordingMetadata {
+    #[prost(string, optional, tag = "1")]
+    pub display_name: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub participant_name: Option<String>,
+    #[prost(message, optional, tag = "3")]
+    pub device_info: Option<DeviceInfo>,
+    #[prost(message, optional, tag = "4")]
+    pub audio_config: Option<AudioConfig>,
+    #[prost(uint64, optional, tag = "5")]
+    pub duration_us: Option<u64>,
+    #[prost(message, optional, tag = "6")]
+    pub sync_info: Option<SyncInfo>,
+    #[prost(message, optional, tag = "7")]
+    pub loudness: Option<LoudnessStats>,
+    #[prost(message, optional, tag = "8")]
+    pub framerate: Option<FrameRateStats>,
+    #[prost(message, optional, tag = "9")]
+    pub quality: Option<QualityReport>,
+    #[prost(message, optional, tag = "10")]
+    pub downmix: Option<DownmixRecipe>,
+    #[prost(message, optional, tag = "11")]
+    pub retention: Option<RetentionPolicy>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, prost::Message)]
+pub struct RetentionPolicy {
+    #[prost(uint64, optional, tag = "1")]
+    pub expire_after_ms: Option<u64>,
+    #[prost(bool, tag = "2")]
+    pub legal_hold: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DownmixRecipe {
+    #[prost(message, repeated, tag = "1")]
+    pub sources: Vec<DownmixSource>,
+    #[prost(bool, tag = "2")]
+    pub applied: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DownmixSource {
+    #[prost(string, tag = "1")]
+    pub label: String,
+    #[prost(double, tag = "2")]
+    pub gain_db: f64,
+    #[prost(uint32, optional, tag = "3")]
+    pub track_id: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Eq, prost::Message)]
+pub struct QualityReport {
+    #[prost(uint32, tag = "1")]
+    pub dropped_frames: u32,
+    #[prost(uint32, tag = "2")]
+    pub rejected_frames: u32,
+    #[prost(uint32, tag = "3")]
+    pub corrected_frames: u32,
+    #[prost(message, repeated, tag = "4")]
+    pub gaps: Vec<MediaGap>,
+    #[prost(uint64, tag = "5")]
+    pub total_gap_us: u64,
+    #[prost(uint32, tag = "6")]
+    pub empty_frames: u32,
+    #[prost(uint32, tag = "7")]
+    pub oversized_frames: u32,
+    #[prost(uint32, tag = "8")]
+    pub largest_frame: u32,
+    #[prost(uint32, tag = "9")]
+    pub max_frame_size: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, prost::Message)]
+pub struct MediaGap {
+    #[prost(uint64, tag = "1")]
+    pub start_us: u64,
+    #[prost(uint64, tag = "2")]
+    pub end_us: u64,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+pub struct Marker {
+    /// Microseconds from session start
+    #[prost(uint64, tag = "1")]
+    pub timestamp_us: u64,
+    #[prost(string, optional, tag = "2")]
+    pub label: Option<String>,
+    /// Set on bookmarks
+    #[prost(message, optional, tag = "3")]
+    pub bookmark: Option<Bookmark>,
+}
+
+/// Frame-accurate anchor of a bookmark
+#[derive(Clone, Copy, PartialEq, Eq, Hash, prost::Message)]
+pub struct Bookmark {
+    /// Unset until the fragment holding the keyframe is muxed
+    #[prost(message, optional, tag = "1")]
+    pub keyframe: Option<KeyframeLocation>,
+}
+
+/// Where a video keyframe lies in the assembled file
+#[derive(Clone, Copy, PartialEq, Eq, Hash, prost::Message)]
+pub struct KeyframeLocation {
+    /// Decode time in microseconds
+    #[prost(uint64, tag = "1")]
+    pub timestamp_us: u64,
+    #[prost(uint32, tag = "2")]
+    pub sequence: u32,
+    #[prost(uint64, tag = "3")]
+    pub fragment_offset: u64,
+    #[prost(uint64, tag = "4")]
+    pub sample_offset: u64,
+}
+
+/// Where one fragment (moof + mdat) lies in the assembled file
+#[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+pub struct FragmentRange {
+    /// Sequence number from the fragment's mfhd
+    #[prost(uint32, tag = "1")]
+    pub sequence: u32,
+    /// Byte offset of the moof, counting the init segment
+    #[prost(uint64, tag = "2")]
+    pub offset: u64,
+    #[prost(uint64, tag = "3")]
+    pub size: u64,
+    /// Earliest decode time in microseconds
+    #[prost(uint64, tag = "4")]
+    pub timestamp_us: u64,
+    #[prost(message, optional, tag = "5")]
+    pub chunk_id: Option<ChunkId>,
+}
+
+/// A stretch of the timeline during which a track was disabled
+#[derive(Clone, Copy, PartialEq, Eq, Hash, prost::Message)]
+pub struct MutedRange {
+    #[prost(enumeration = "TrackKind", tag = "1")]
+    pub track: i32,
+    #[prost(uint64, tag = "2")]
+    pub start_us: u64,
+    #[prost(uint64, tag = "3")]
+    pub end_us: u64,
+}
+
+/// Where a session's data ended at its last emergency flush
+#[derive(Clone, Copy, PartialEq, Eq, Hash, prost::Message)]
+pub struct TailMarker {
+    /// Microseconds from session start
+    #[prost(uint64, tag = "1")]
+    pub timestamp_us: u64,
+    #[prost(uint64, tag = "2")]
+    pub chunk_count: u64,
+    /// Unix time (ms) of the flush
+    #[prost(uint64, tag = "3")]
+    pub flushed_at_ms: u64,
+}
+
+/// Key a run of AES-128 encrypted chunks uses, up to the next period
+#[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+pub struct KeyPeriod {
+    /// Sequence number of the first chunk encrypted with the key
+    #[prost(uint64, tag = "1")]
+    pub first_sequence: u64,
+    #[prost(string, tag = "2")]
+    pub key_uri: String,
+}
+
+/// A session with its chunk manifest (`ChunkManifest` in Rust)
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Session {
+    #[prost(string, tag = "1")]
+    pub session_id: String,
+    #[prost(enumeration = "SessionState", tag = "2")]
+    pub state: i32,
+    #[prost(message, optional, tag = "3")]
+    pub metadata: Option<RecordingMetadata>,
+    #[prost(message, repeated, tag = "4")]
+    pub chunks: Vec<ChunkMetadata>,
+    #[prost(message, repeated, tag = "5")]
+    pub markers: Vec<Marker>,
+    #[prost(message, repeated, tag = "6")]
+    pub fragments: Vec<FragmentRange>,
+    #[prost(message, repeated, tag = "7")]
+    pub keys: Vec<KeyPeriod>,
+    #[prost(message, repeated, tag = "8")]
+    pub muted: Vec<MutedRange>,
+    #[prost(message, optional, tag = "9")]
+    pub tail: Option<TailMarker>,
+}
+
+// ===== Conversions =====
+
+impl From<chunk::TrackKind> for TrackKind {
+    fn from(track: chunk::TrackKind) -> Self {
+        match track {
+            chunk::TrackKind::Muxed => TrackKind::Muxed,
+            chunk::TrackKind::Video => TrackKind::Video,
+            chunk::TrackKind::Audio => TrackKind::Audio,
+        }
+    }
+}
+
+impl From<TrackKind> for chunk::TrackKind {
+    fn from(track: TrackKind) -> Self {
+        match track {
+            TrackKind::Muxed => chunk::TrackKind::Muxed,
+            TrackKind::Video => chunk::TrackKind::Video,
+            TrackKind::Audio => chunk::TrackKind::Audio,
+        }
+    }
+}
+
+impl From<session::SessionState> for SessionState {
+    fn from(state: session::SessionState) -> Self {
+        match state {
+            session::SessionState::Standby => SessionState::Standby,
+            session::SessionState::Recording => SessionState::Recording,
+            session::SessionState::Finalizing => SessionState::Finalizing,
+            session::SessionState::Synced => SessionState::Synced,
+            session::SessionState::Interrupted => SessionState::Interrupted,
+        }
+    }
+}
+
+impl From<SessionState> for session::SessionState {
+    fn from(state: SessionState) -> Self {
+        match state {
+            SessionState::Standby => session::SessionState::Standby,
+            SessionState::Recording => session::SessionState::Recording,
+            SessionState::Finalizing => session::SessionState::Finalizing,
+            SessionState::Synced => session::SessionState::Synced,
+            SessionState::Interrupted => session::SessionState::Interrupted,
+        }
+    }
+}
+
+impl From<&chunk::ChunkId> for ChunkId {
+    fn from(id: &chunk::ChunkId) -> Self {
+        Self {
+            session_id: id.session.as_str().to_string(),
+            track: TrackKind::from(id.track) as i32,
+            sequence: id.sequence,
+            rendition: id.rendition.clone(),
+        }
+    }
+}
+
+impl TryFrom<ChunkId> for chunk::ChunkId {
+    type Error = String;
+
+    fn try_from(id: ChunkId) -> Result<Self, String> {
+        let track = TrackKind::try_from(id.track)
+            .map_err(|_| format!("Unknown track kind: {}", id.track))?;
+        Ok(Self {
+            session: session::SessionId::from(id.session_id),
+            track: track.into(),
+            rendition: id.rendition,
+            sequence: id.sequence,
+        })
+    }
+}
+
+impl From<&chunk::ChunkMetadata> for ChunkMetadata {
+    fn from(chunk: &chunk::ChunkMetadata) -> Self {
+        Self {
+            chunk_id: Some((&chunk.chunk_id).into()),
+            timestamp_us: chunk.timestamp_us,
+            size: chunk.size,
+            hash: chunk.hash.clone(),
+            has_keyframe: chunk.has_keyframe,
+            created_at: chunk.created_at,
+        }
+    }
+}
+
+impl TryFrom<ChunkMetadata> for chunk::ChunkMetadata {
+    type Error = String;
+
+    fn try_from(chunk: ChunkMetadata) -> Result<Self, String> {
+        let chunk_id = chunk
+            .chunk_id
+            .ok_or_else(|| "Chunk metadata without chunk ID".to_string())?;
+        Ok(Self {
+            chunk_id: chunk_id.try_into()?,
+            timestamp_us: chunk.timestamp_us,
+            size: chunk.size,
+            hash: chunk.hash,
+            has_keyframe: chunk.has_keyframe,
+            created_at: chunk.created_at,
+        })
+    }
+}
+
+impl From<&metadata::RecordingMetadata> for RecordingMetadata {
+    fn from(metadata: &metadata::RecordingMetadata) -> Self {
+        Self {
+            display_name: metadata.display_name.clone(),
+            participant_name: metadata.participant_name.clone(),
+            device_info: metadata.device_info.as_ref().map(|device| DeviceInfo {
+                browser: device.browser.clone(),
+                os: device.os.clone(),
+                screen_resolution: device.screen_resolution.clone(),
+            }),
+            audio_config: metadata.audio_config.as_ref().map(|audio| AudioConfig {
+                codec: audio.codec.clone(),
+                sample_rate: audio.sample_rate,
+                channel_count: audio.channel_count.into(),
+                bitrate: audio.bitrate,
+            }),
+            duration_us: metadata.duration_us,
+            sync_info: metadata.sync_info.as_ref().map(|sync| SyncInfo {
+                scheduled_start_time: sync.scheduled_start_time,
+                actual_start_time: sync.actual_start_time,
+                clock_offset_ms: sync.clock_offset_ms,
+                clock_offset_accuracy_ms: sync.clock_offset_accuracy_ms,
+                sync_sample_count: sync.sync_sample_count,
+            }),
+            loudness: metadata.loudness.as_ref().map(|loudness| LoudnessStats {
+                integrated_lufs: loudness.integrated_lufs,
+                max_momentary_lufs: loudness.max_momentary_lufs,
+                max_short_term_lufs: loudness.max_short_term_lufs,
+                peak_dbfs: loudness.peak_dbfs,
+            }),
+            framerate: metadata.framerate.as_ref().map(|framerate| FrameRateStats {
+                average_fps: framerate.average_fps,
+                median_fps: framerate.median_fps,
+                p5_fps: framerate.p5_fps,
+                p95_fps: framerate.p95_fps,
+                variable: framerate.variable,
+                frame_count: framerate.frame_count,
+            }),
+            quality: metadata.quality.as_ref().map(|quality| QualityReport {
+                dropped_frames: quality.dropped_frames,
+                rejected_frames: quality.rejected_frames,
+                corrected_frames: quality.corrected_frames,
+                gaps: quality
+                    .gaps
+                    .iter()
+                    .map(|gap| MediaGap {
+                        start_us: gap.start_us,
+                        end_us: gap.end_us,
+                    })
+                    .collect(),
+                total_gap_us: quality.total_gap_us,
+                empty_frames: quality.empty_frames,
+                oversized_frames: quality.oversized_frames,
+                largest_frame: quality.largest_frame,
+                max_frame_size: quality.max_frame_size,
+            }),
+            downmix: metadata.downmix.as_ref().map(|downmix| DownmixRecipe {
+                sources: downmix
+                    .sources
+                    .iter()
+                    .map(|source| DownmixSource {
+                        label: source.label.clone(),
+                        gain_db: source.gain_db,
+                        track_id: source.track_id,
+                    })
+                    .collect(),
+                applied: downmix.applied,
+            }),
+            retention: metadata.retention.map(|retention| RetentionPolicy {
+                expire_after_ms: retention.expire_after_ms,
+                legal_hold: retention.legal_hold,
+            }),
+        }
+    }
+}
+
+impl TryFrom<RecordingMetadata> for metadata::RecordingMetadata {
+    type Error = String;
+
+    fn try_from(metadata: RecordingMetadata) -> Result<Self, String> {
+        let audio_config = match metadata.audio_config {
+            Some(audio) => Some(metadata::AudioConfig {
+                codec: audio.codec,
+                sample_rate: audio.sample_rate,
+                channel_count: u16::try_from(audio.channel_count)
+                    .map_err(|_| format!("Invalid channel count: {}", audio.channel_count))?,
+                bitrate: audio.bitrate,
+            }),
+            None => None,
+        };
+        Ok(Self {
+            display_name: metadata.display_name,
+            participant_name: metadata.participant_name,
+            device_info: metadata.device_info.map(|device| metadata::DeviceInfo {
+                browser: device.browser,
+                os: device.os,
+                screen_resolution: device.screen_resolution,
+            }),
+            audio_config,
+            duration_us: metadata.duration_us,
+            sync_info: metadata.sync_info.map(|sync| metadata::SyncInfo {
+                scheduled_start_time: sync.scheduled_start_time,
+                actual_start_time: sync.actual_start_time,
+                clock_offset_ms: sync.clock_offset_ms,
+                clock_offset_accuracy_ms: sync.clock_offset_accuracy_ms,
+                sync_sample_count: sync.sync_sample_count,
+            }),
+            loudness: metadata.loudness.map(|loudness| metadata::LoudnessStats {
+                integrated_lufs: loudness.integrated_lufs,
+                max_momentary_lufs: loudness.max_momentary_lufs,
+                max_short_term_lufs: loudness.max_short_term_lufs,
+                peak_dbfs: loudness.peak_dbfs,
+            }),
+            framerate: metadata
+                .framerate
+                .map(|framerate| metadata::FrameRateStats {
+                    average_fps: framerate.average_fps,
+                    median_fps: framerate.median_fps,
+                    p5_fps: framerate.p5_fps,
+                    p95_fps: framerate.p95_fps,
+                    variable: framerate.variable,
+                    frame_count: framerate.frame_count,
+                }),
+            quality: metadata.quality.map(|quality| metadata::QualityReport {
+                dropped_frames: quality.dropped_frames,
+                rejected_frames: quality.rejected_frames,
+                corrected_frames: quality.corrected_frames,
+                gaps: quality
+                    .gaps
+                    .into_iter()
+                    .map(|gap| metadata::MediaGap {
+                        start_us: gap.start_us,
+                        end_us: gap.end_us,
+                    })
+                    .collect(),
+                total_gap_us: quality.total_gap_us,
+                empty_frames: quality.empty_frames,
+                oversized_frames: quality.oversized_frames,
+                largest_frame: quality.largest_frame,
+                max_frame_size: quality.max_frame_size,
+            }),
+            downmix: metadata.downmix.map(|downmix| metadata::DownmixRecipe {
+                sources: downmix
+                    .sources
+                    .into_iter()
+                    .map(|source| metadata::DownmixSource {
+                        label: source.label,
+                        gain_db: source.gain_db,
+                        track_id: source.track_id,
+                    })
+                    .collect(),
+                applied: downmix.applied,
+            }),
+            retention: metadata
+                .retention
+                .map(|retention| metadata::RetentionPolicy {
+                    expire_after_ms: retention.expire_after_ms,
+                    legal_hold: retention.legal_hold,
+                }),
+        })
+    }
+}
+
+impl From<&manifest::Marker> for Marker {
+    fn from(marker: &manifest::Marker) -> Self {
+        Self {
+            timestamp_us: marker.timestamp_us,
+            label: marker.label.clone(),
+            bookmark: marker.bookmark.as_ref().map(|bookmark| Bookmark {
+                keyframe: bookmark.keyframe.as_ref().map(|k| KeyframeLocation {
+                    timestamp_us: k.timestamp_us,
+                    sequence: k.sequence,
+                    fragment_offset: k.fragment_offset,
+                    sample_offset: k.sample_offset,
+                }),
+            }),
+        }
+    }
+}
+
+impl From<Marker> for manifest::Marker {
+    fn from(marker: Marker) -> Self {
+        Self {
+            timestamp_us: marker.timestamp_us,
+            label: marker.label,
+            bookmark: marker.bookmark.map(|bookmark| manifest::Bookmark {
+                keyframe: bookmark.keyframe.map(|k| manifest::KeyframeLocation {
+                    timestamp_us: k.timestamp_us,
+                    sequence: k.sequence,
+                    fragment_offset: k.fragment_offset,
+                    sample_offset: k.sample_offset,
+                }),
+            }),
+        }
+    }
+}
+
+impl From<&manifest::FragmentRange> for FragmentRange {
+    fn from(fragment: &manifest::FragmentRange) -> Self {
+        Self {
+            sequence: fragment.sequence,
+            offset: fragment.offset,
+            size: fragment.size,
+            timestamp_us: fragment.timestamp_us,
+            chunk_id: Some((&fragment.chunk_id).into()),
+        }
+    }
+}
+
+impl TryFrom<FragmentRange> for manifest::FragmentRange {
+    type Error = String;
+
+    fn try_from(fragment: FragmentRange) -> Result<Self, String> {
+        let chunk_id = fragment
+            .chunk_id
+            .ok_or_else(|| "Fragment range without chunk ID".to_string())?;
+        Ok(Self {
+            sequence: fragment.sequence,
+            offset: fragment.offset,
+            size: fragment.size,
+            timestamp_us: fragment.timestamp_us,
+            chunk_id: chunk_id.try_into()?,
+        })
+    }
+}
+
+impl From<&manifest::KeyPeriod> for KeyPeriod {
+    fn from(period: &manifest::KeyPeriod) -> Self {
+        Self {
+            first_sequence: period.first_sequence,
+            key_uri: period.key_uri.clone(),
+        }
+    }
+}
+
+impl From<KeyPeriod> for manifest::KeyPeriod {
+    fn from(period: KeyPeriod) -> Self {
+        Self {
+            first_sequence: period.first_sequence,
+            key_uri: period.key_uri,
+        }
+    }
+}
+
+impl From<&manifest::MutedRange> for MutedRange {
+    fn from(range: &manifest::MutedRange) -> Self {
+        Self {
+            track: TrackKind::from(range.track) as i32,
+            start_us: range.start_us,
+            end_us: range.end_us,
+        }
+    }
+}
+
+impl TryFrom<MutedRange> for manifest::MutedRange {
+    type Error = String;
+
+    fn try_from(range: MutedRange) -> Result<Self, String> {
+        let track = TrackKind::try_from(range.track)
+            .map_err(|_| format!("Unknown track kind: {}", range.track))?;
+        Ok(Self {
+            track: track.into(),
+            start_us: range.start_us,
+            end_us: range.end_us,
+        })
+    }
+}
+
+impl From<&manifest::TailMarker> for TailMarker {
+    fn from(tail: &manifest::TailMarker) -> Self {
+        Self {
+            timestamp_us: tail.timestamp_us,
+            chunk_count: tail.chunk_count,
+            flushed_at_ms: tail.flushed_at_ms,
+        }
+    }
+}
+
+impl From<TailMarker> for manifest::TailMarker {
+    fn from(tail: TailMarker) -> Self {
+        Self {
+            timestamp_us: tail.timestamp_us,
+            chunk_count: tail.chunk_count,
+            flushed_at_ms: tail.flushed_at_ms,
+        }
+    }
+}
+
+impl From<&manifest::ChunkManifest> for Session {
+    fn from(manifest: &manifest::ChunkManifest) -> Self {
+        Self {
+            session_id: manifest.session_id.as_str().to_string(),
+            state: SessionState::from(manifest.state) as i32,
+            metadata: manifest.metadata.as_ref().map(Into::into),
+            chunks: manifest.chunks.iter().map(Into::into).collect(),
+            markers: manifest.markers.iter().map(Into::into).collect(),
+            fragments: manifest.fragments.iter().map(Into::into).collect(),
+            keys: manifest.keys.iter().map(Into::into).collect(),
+            muted: manifest.muted.iter().map(Into::into).collect(),
+            tail: manifest.tail.as_ref().map(Into::into),
+        }
+    }
+}
+
+impl TryFrom<Session> for manifest::ChunkManifest {
+    type Error = String;
+
+    fn try_from(session: Session) -> Result<Self, String> {
+        let state = SessionState::try_from(session.state)
+            .map_err(|_| format!("Unknown session state: {}", session.state))?;
+        Ok(Self {
+            session_id: session::SessionId::from(session.session_id),
+            state: state.into(),
+            metadata: session.metadata.map(TryInto::try_into).transpose()?,
+            chunks: session
+                .chunks
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            markers: session.markers.into_iter().map(Into::into).collect(),
+            fragments: session
+                .fragments
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            keys: session.keys.into_iter().map(Into::into).collect(),
+            muted: session
+                .muted
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            tail: session.tail.map(Into::into),
+        })
+    }
+}
+
+// ===== Codecs =====
+
+fn decode<M: prost::Message + Default>(data: &[u8]) -> Result<M, String> {
+    M::decode(data).map_err(|e| format!("Invalid protobuf message: {}", e))
+}
+
+/// Encode a session manifest as a `maycast.v1.Session` message
+pub fn encode_session(manifest: &manifest::ChunkManifest) -> Vec<u8> {
+    prost::Message::encode_to_vec(&Session::from(manifest))
+}
+
+/// Decode a `maycast.v1.Session` message into a session manifest
+pub fn decode_session(data: &[u8]) -> Result<manifest::ChunkManifest, String> {
+    decode::<Session>(data)?.try_into()
+}
+
+/// Encode chunk metadata as a `maycast.v1.ChunkMetadata` message
+pub fn encode_chunk_metadata(chunk: &chunk::ChunkMetadata) -> Vec<u8> {
+    prost::Message::encode_to_vec(&ChunkMetadata::from(chunk))
+}
+
+/// Decode a `maycast.v1.ChunkMetadata` message
+pub fn decode_chunk_metadata(data: &[u8]) -> Result<chunk::ChunkMetadata, String> {
+    decode::<ChunkMetadata>(data)?.try_into()
+}
+
+/// Encode recording metadata as a `maycast.v1.RecordingMetadata` message
+pub fn encode_recording_metadata(metadata: &metadata::RecordingMetadata) -> Vec<u8> {
+    prost::Message::encode_to_vec(&RecordingMetadata::from(metadata))
+}
+
+/// Decode a `maycast.v1.RecordingMetadata` message
+pub fn decode_recording_metadata(data: &[u8]) -> Result<metadata::RecordingMetadata, String> {
+    decode::<RecordingMetadata>(data)?.try_into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    #[test]
+    fn test_session_round_trip() {
+        let session_id = session::SessionId::from("s1");
+        let mut manifest = manifest::ChunkManifest::new(session_id.clone());
+        manifest.state = session::SessionState::Finalizing;
+        manifest.metadata = Some(metadata::RecordingMetadata {
+            display_name: Some("Interview".to_string()),
+            audio_config: Some(metadata::AudioConfig {
+                codec: "mp4a.40.2".to_string(),
+                sample_rate: 48000,
+                channel_count: 2,
+                bitrate: 128000,
+            }),
+            duration_us: Some(61_000_000),
+            loudness: Some(metadata::LoudnessStats {
+                integrated_lufs: Some(-16.2),
+                max_momentary_lufs: Some(-9.5),
+                max_short_term_lufs: None,
+                peak_dbfs: -1.3,
+            }),
+            framerate: Some(metadata::FrameRateStats {
+                average_fps: 29.7,
+                median_fps: 30.0,
+                p5_fps: 24.0,
+                p95_fps: 30.0,
+                variable: true,
+                frame_count: 1830,
+            }),
+            quality: Some(metadata::QualityReport {
+                dropped_frames: 3,
+                gaps: vec![metadata::MediaGap {
+                    start_us: 2_000_000,
+                    end_us: 9_200_000,
+                }],
+                total_gap_us: 7_200_000,
+                ..Default::default()
+            }),
+            downmix: Some(metadata::DownmixRecipe {
+                sources: vec![
+                    metadata::DownmixSource {
+                        label: "screen".to_string(),
+                        gain_db: -6.0,
+                        track_id: None,
+                    },
+                    metadata::DownmixSource {
+                        label: "mic".to_string(),
+                        gain_db: 0.0,
+                        track_id: Some(2),
+                    },
+                ],
+                applied: true,
+            }),
+            ..Default::default()
+        });
+        for (track, rendition) in [
+            (chunk::TrackKind::Muxed, None),
+            (chunk::TrackKind::Audio, None),
+            (chunk::TrackKind::Muxed, Some("360p")),
+        ] {
+            let mut chunk_id = chunk::ChunkId::new(session_id.clone(), track, 0);
+            if let Some(rendition) = rendition {
+                chunk_id = chunk_id.with_rendition(rendition);
+            }
+            manifest
+                .add_chunk(chunk::ChunkMetadata {
+                    chunk_id,
+                    timestamp_us: 0,
+                    size: 1024,
+                    hash: Some("ab".repeat(32)),
+                    has_keyframe: Some(true),
+                    created_at: 1_700_000_000_000,
+                })
+                .unwrap();
+        }
+        manifest.add_marker(manifest::Marker {
+            timestamp_us: 5_000_000,
+            label: Some("Intro".to_string()),
+            bookmark: None,
+        });
+        manifest.add_marker(manifest::Marker {
+            timestamp_us: 6_000_000,
+            label: None,
+            bookmark: Some(manifest::Bookmark {
+                keyframe: Some(manifest::KeyframeLocation {
+                    timestamp_us: 4_000_000,
+                    sequence: 3,
+                    fragment_offset: 90_000,
+                    sample_offset: 90_400,
+                }),
+            }),
+        });
+        manifest.fragments.push(manifest::FragmentRange {
+            sequence: 1,
+            offset: 700,
+            size: 1024,
+            timestamp_us: 0,
+            chunk_id: manifest.chunks[0].chunk_id.clone(),
+        });
+        manifest.keys.push(manifest::KeyPeriod {
+            first_sequence: 0,
+            key_uri: "keys/1".to_string(),
+        });
+        manifest.muted.push(manifest::MutedRange {
+            track: chunk::TrackKind::Audio,
+            start_us: 12_000_000,
+            end_us: 15_500_000,
+        });
+        manifest.tail = Some(manifest::TailMarker {
+            timestamp_us: 16_000_000,
+            chunk_count: 3,
+            flushed_at_ms: 1_700_000_016_000,
+        });
+
+        let encoded = encode_session(&manifest);
+        assert_eq!(decode_session(&encoded).unwrap(), manifest);
+
+        let chunk = &manifest.chunks[2];
+        assert_eq!(
+            decode_chunk_metadata(&encode_chunk_metadata(chunk)).unwrap(),
+            *chunk
+        );
+        let metadata = manifest.metadata.as_ref().unwrap();
+        assert_eq!(
+            decode_recording_metadata(&encode_recording_metadata(metadata)).unwrap(),
+            *metadata
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_messages() {
+        assert!(decode_session(&[0xFF, 0xFF]).is_err());
+
+        let session = Session {
+            session_id: "s1".to_string(),
+            state: 42,
+            ..Default::default()
+        };
+        let err = decode_session(&session.encode_to_vec()).unwrap_err();
+        assert_eq!(err, "Unknown session state: 42");
+
+        let chunk = ChunkMetadata::default();
+        assert!(decode_chunk_metadata(&chunk.encode_to_vec()).is_err());
+    }
+}
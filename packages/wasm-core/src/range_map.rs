@@ -0,0 +1,277 @@
+//! Byte-range map of an assembled recording.
+//!
+//! The assembled file is the init segment followed by every chunk in order,
+//! so where each fragment (moof + mdat) lands is known as soon as its chunk
+//! is muxed. `RangeMapBuilder` walks chunks in file order and lists, for
+//! every fragment, its mfhd sequence number, byte range, start time and the
+//! chunk holding it. With that list in the manifest a player or a server can
+//! seek with plain range requests, or fetch part of the file from object
+//! storage, without downloading and parsing a sidx.
+//!
+//! The same walk locates every video keyframe, which anchors bookmarks.
+
+use std::collections::BTreeMap;
+
+use crate::chunk::ChunkId;
+use crate::compat::read_init_tracks;
+use crate::manifest::{FragmentRange, KeyframeLocation};
+use crate::muxide_muxer::{parse_boxes, read_trun, read_u32, read_u64};
+use crate::timebase::{Rounding, TimeBase};
+
+/// sample_is_non_sync_sample bit of trun sample flags
+const SAMPLE_IS_NON_SYNC: u32 = 0x0001_0000;
+
+/// Fragments and video keyframes of one chunk
+#[derive(Debug, Clone)]
+pub(crate) struct MappedChunk {
+    pub(crate) fragments: Vec<FragmentRange>,
+    /// In file order
+    pub(crate) keyframes: Vec<KeyframeLocation>,
+}
+
+/// Maps the fragments of consecutive chunks of one file
+#[derive(Debug, Clone)]
+pub(crate) struct RangeMapBuilder {
+    /// Timescale of each track of the init segment
+    timescales: BTreeMap<u32, u32>,
+    /// Track whose sync samples are reported as keyframes
+    video_track: Option<u32>,
+    /// File offset where the next chunk starts
+    offset: u64,
+}
+
+impl RangeMapBuilder {
+    /// Start a map of a file beginning with `init_segment`
+    pub(crate) fn new(init_segment: &[u8]) -> Result<Self, String> {
+        let tracks = read_init_tracks(init_segment)?;
+        let video_track = tracks
+            .iter()
+            .find(|(_, track)| &track.handler == b"vide")
+            .map(|(track_id, _)| *track_id);
+        let timescales = tracks
+            .into_iter()
+            .map(|(track_id, track)| (track_id, track.timescale))
+            .collect();
+        Ok(Self {
+            timescales,
+            video_track,
+            offset: init_segment.len() as u64,
+        })
+    }
+
+    /// Continue a map whose first fragments are `fragments`
+    pub(crate) fn resume(init_segment: &[u8], fragments: &[FragmentRange]) -> Result<Self, String> {
+        let mut builder = Self::new(init_segment)?;
+        if let Some(last) = fragments.last() {
+            builder.offset = last.offset + last.size;
+        }
+        Ok(builder)
+    }
+
+    /// Start the chunks still to come `bytes` later in the file, after the
+    /// init segment was replaced by one that much longer
+    pub(crate) fn shift(&mut self, bytes: u64) {
+        self.offset += bytes;
+    }
+
+    /// Whether the file has a video track to locate keyframes on
+    pub(crate) fn has_video(&self) -> bool {
+        self.video_track.is_some()
+    }
+
+    /// Map the fragments of the next chunk of the file
+    pub(crate) fn push_chunk(
+        &mut self,
+        chunk_id: &ChunkId,
+        data: &[u8],
+    ) -> Result<MappedChunk, String> {
+        let mut fragments: Vec<FragmentRange> = Vec::new();
+        let mut keyframes = Vec::new();
+        for b in parse_boxes(data)? {
+            let end = self.offset + (b.offset + 8 + b.payload.len()) as u64;
+            if &b.typ == b"moof" {
+                let offset = self.offset + b.offset as u64;
+                let (sequence, timestamp_us) = self.read_moof(b.payload, offset, &mut keyframes)?;
+                fragments.push(FragmentRange {
+                    sequence,
+                    offset,
+                    size: 0,
+                    timestamp_us,
+                    chunk_id: chunk_id.clone(),
+                });
+            }
+            // Boxes after a moof (its mdat) belong to that fragment
+            if let Some(fragment) = fragments.last_mut() {
+                fragment.size = end - fragment.offset;
+            }
+        }
+        if fragments.is_empty() {
+            return Err(format!("Chunk {} holds no fragment", chunk_id));
+        }
+        self.offset += data.len() as u64;
+        Ok(MappedChunk {
+            fragments,
+            keyframes,
+        })
+    }
+
+    /// Sequence number and earliest decode time (µs) of the moof at file
+    /// offset `offset`; its video keyframes are added to `keyframes`
+    fn read_moof(
+        &self,
+        moof: &[u8],
+        offset: u64,
+        keyframes: &mut Vec<KeyframeLocation>,
+    ) -> Result<(u32, u64), String> {
+        let first_keyframe = keyframes.len();
+        let mut sequence = None;
+        let mut start_us: Option<u64> = None;
+        for child in parse_boxes(moof)? {
+            match &child.typ {
+                b"mfhd" => sequence = Some(read_u32(child.payload, 4)?),
+                b"traf" => {
+                    let mut track_id = None;
+                    let mut decode_time = None;
+                    let mut trun = None;
+                    for b in parse_boxes(child.payload)? {
+                        match &b.typ {
+                            b"tfhd" => track_id = Some(read_u32(b.payload, 4)?),
+                            b"trun" => trun = Some(b.payload),
+                            b"tfdt" if b.payload.first() == Some(&1) => {
+                                decode_time = Some(read_u64(b.payload, 4)?)
+                            }
+                            b"tfdt" => decode_time = Some(read_u32(b.payload, 4)? as u64),
+                            _ => {}
+                        }
+                    }
+                    let track_id = track_id.ok_or("traf has no tfhd box")?;
+                    let decode_time = decode_time.ok_or("traf has no tfdt box")?;
+                    let timescale = *self
+                        .timescales
+                        .get(&track_id)
+                        .ok_or_else(|| format!("Fragment references unknown track {}", track_id))?;
+                    let time_base = TimeBase::new(timescale as u64);
+                    let us = time_base.to_us(decode_time, Rounding::Floor);
+                    start_us = Some(start_us.map_or(us, |start| start.min(us)));
+
+                    if Some(track_id) != self.video_track {
+                        continue;
+                    }
+                    let run = read_trun(trun.ok_or("traf has no trun box")?)?;
+                    // Sample data offsets are relative to the moof
+                    let mut sample_offset = offset + run.data_offset as u64;
+                    let mut sample_time = decode_time;
+                    for entry in &run.entries {
+                        if entry.flags & SAMPLE_IS_NON_SYNC == 0 {
+                            keyframes.push(KeyframeLocation {
+                                timestamp_us: time_base.to_us(sample_time, Rounding::Floor),
+                                sequence: 0,
+                                fragment_offset: offset,
+                                sample_offset,
+                            });
+                        }
+                        sample_offset += entry.size as u64;
+                        sample_time += entry.duration as u64;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let sequence = sequence.ok_or("moof has no mfhd box")?;
+        for keyframe in &mut keyframes[first_keyframe..] {
+            keyframe.sequence = sequence;
+        }
+        Ok((sequence, start_us.unwrap_or(0)))
+    }
+}
+
+/// Map every fragment of the file made of `init_segment` and `chunks`, in
+/// file order
+pub fn build_range_map<'a>(
+    init_segment: &[u8],
+    chunks: impl IntoIterator<Item = (&'a ChunkId, &'a [u8])>,
+) -> Result<Vec<FragmentRange>, String> {
+    let mut builder = RangeMapBuilder::new(init_segment)?;
+    let mut fragments = Vec::new();
+    for (chunk_id, data) in chunks {
+        fragments.extend(builder.push_chunk(chunk_id, data)?.fragments);
+    }
+    Ok(fragments)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::muxide_muxer::MuxideConfig;
+    use crate::recorder::{RecorderEvent, RecorderState};
+    use crate::session::SessionId;
+
+    #[test]
+    fn test_range_map_of_finalized_recording() {
+        let mut recorder = RecorderState::new(
+            SessionId::from("s1"),
+            MuxideConfig {
+                sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+                pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+                audio_sample_rate: Some(48000),
+                audio_channels: Some(2),
+                fragment_duration_ms: 1000,
+                ..Default::default()
+            },
+        );
+        let mut file = recorder.start().unwrap();
+        let mut init_size = file.len() as u64;
+        for i in 0..150u64 {
+            recorder
+                .push_video(&[0, 0, 0, 1, 0x65, i as u8], i * 33_333, i % 30 == 0)
+                .unwrap();
+            recorder
+                .push_audio(&[i as u8; 8], 10_000 + i * 21_333, 21_333)
+                .unwrap();
+        }
+        recorder.stop().unwrap();
+        let mut chunk_ids = Vec::new();
+        for event in recorder.take_events() {
+            match event {
+                RecorderEvent::ChunkReady(chunk) => {
+                    chunk_ids.push(chunk.metadata.chunk_id);
+                    file.extend_from_slice(&chunk.data);
+                }
+                // Stopping embeds the session metadata in the init segment
+                RecorderEvent::InitSegmentChanged(init) => {
+                    let size = init.len() as u64;
+                    file.splice(..init_size as usize, init);
+                    init_size = size;
+                }
+                _ => {}
+            }
+        }
+
+        // Every fragment of the file, back to back after the init segment
+        let manifest = recorder.manifest();
+        let fragments = &manifest.fragments;
+        assert!(fragments.len() >= chunk_ids.len());
+        let last = fragments.last().unwrap();
+        assert_eq!(last.offset + last.size, file.len() as u64);
+        for (i, fragment) in fragments.iter().enumerate() {
+            assert_eq!(fragment.sequence, i as u32 + 1);
+            let start = fragment.offset as usize;
+            assert_eq!(&file[start + 4..start + 8], b"moof");
+            if let Some(next) = fragments.get(i + 1) {
+                assert_eq!(fragment.offset + fragment.size, next.offset);
+                assert!(fragment.timestamp_us < next.timestamp_us);
+            }
+            assert!(chunk_ids.contains(&fragment.chunk_id));
+        }
+        assert_eq!(fragments[0].offset, init_size);
+        assert_eq!(fragments[0].timestamp_us, 0);
+
+        // Seeking picks the fragment holding the time
+        let second = &fragments[1];
+        assert_eq!(manifest.fragment_at(second.timestamp_us), Some(second));
+        assert_eq!(
+            manifest.fragment_at(second.timestamp_us - 1),
+            Some(&fragments[0])
+        );
+        assert_eq!(manifest.fragment_at(u64::MAX), Some(last));
+    }
+}
@@ -0,0 +1,3499 @@
+//! Recorder orchestration on top of the muxer.
+//!
+//! `RecorderState` owns a `MuxideMuxerState`, the session's `ChunkManifest`
+//! and the recorder lifecycle (`idle → recording ⇄ paused → stopped`). Every
+//! media segment the muxer produces becomes a chunk with a `ChunkId`, a BLAKE3
+//! hash and a manifest entry. Chunks and state changes are queued as
+//! `RecorderEvent`s which the WASM wrapper forwards to JS callbacks.
+//!
+//! Ordering guarantees at `stop()`: the muxer is flushed first, every remaining
+//! chunk is emitted, and only then is the `stopped` state change emitted.
+//!
+//! A recording interrupted by a page reload continues with `resume_session()`
+//! instead of `start()`: the muxer is restored from a `snapshot()` persisted
+//! next to the manifest, so chunk sequence numbers and decode times carry on
+//! and all chunks of the session still form one file. `transfer()` packs the
+//! same state for another worker, which continues with `resume_transfer()`.
+//!
+//! While the page is hidden, browsers throttle timers and the host should
+//! call `set_low_power(true)`: fragments get longer, finished fragments are
+//! hashed and emitted in batches, frame-rate statistics are only gathered
+//! when the mode ends, and thumbnails and stall detection (which throttled
+//! timers would set off) are suspended. Nothing that ends up in the file or
+//! the manifest is skipped. Under the low-memory profile
+//! (`MuxideConfig.memory_profile`) fragments stay short and every chunk is
+//! emitted as soon as it is built, low-power mode included.
+//!
+//! `add_bookmark()` places a marker on the last video frame pushed and, once
+//! the fragment holding it is muxed, records where the keyframe to start
+//! decoding from lies in the file, so jump links can seek frame-accurately.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::adts::AdtsWriterState;
+use crate::chunk::{ChunkId, ChunkMetadata, RecordedChunk, TrackKind};
+use crate::clock::ClockHandle;
+use crate::compat;
+use crate::encryption::{SegmentEncryptorState, SegmentKey};
+use crate::framerate::FrameRateEstimator;
+use crate::handshake::codec_strings;
+use crate::hashing::{hash_hex, HashStrategy, IncrementalHash, HASH_SLICE_BYTES};
+use crate::integrity::{IntegrityReport, SignedIntegrityReport, INTEGRITY_KEY_LEN};
+use crate::keyframe::KeyframeSchedulerState;
+use crate::latency::LatencyReport;
+use crate::logging::{log_event, LogLevel};
+use crate::manifest::{
+    Bookmark, ChunkManifest, KeyPeriod, KeyframeLocation, Marker, MutedRange, TailMarker,
+};
+use crate::metadata::{
+    DownmixRecipe, EmbeddedMetadata, FrameRateStats, LoudnessStats, MediaGap, QualityReport,
+};
+use crate::muxide_muxer::{
+    build_init_segment, put_bytes, silent_aac_frame, AudioSampleEntry, DataOffsetMode, MuxerStats,
+    MuxideConfig, MuxideMuxerState, SkippedFrame, StateReader, FRAME_FLAG_KEYFRAME,
+};
+use crate::preview::{LivePreviewState, PreviewSegment};
+use crate::range_map::RangeMapBuilder;
+use crate::search_index::SessionIndex;
+use crate::session::{SessionId, SessionState};
+use crate::silence::{
+    SilenceChange, SilenceConfig, SilenceDetector, SilenceRange, SILENCE_END_LABEL,
+    SILENCE_START_LABEL,
+};
+use crate::sps;
+use crate::subtitles::{export_subtitles, SubtitleFormat};
+use crate::threads::{FinishedSegment, SegmentJob};
+use crate::timebase::Rounding;
+use crate::transfer::RecorderTransfer;
+use crate::wal::{self, WalBatch, WalFrame, WalWriter};
+use crate::watchdog::{StallChange, StallWatchdog};
+
+/// Magic bytes of a recorder snapshot
+const SNAPSHOT_MAGIC: &[u8] = b"RCSN";
+/// Version of the snapshot layout (2 added the quality report, 3 the last
+/// keyframe)
+const SNAPSHOT_VERSION: u8 = 3;
+/// Oldest snapshot version `resume_session` still reads
+const MIN_SNAPSHOT_VERSION: u8 = 1;
+
+/// Intervals between consecutive frames of a track longer than this (µs)
+/// are reported as gaps in the media
+pub const MIN_GAP_US: u64 = 250_000;
+
+/// Fragments are this many times longer in low-power mode
+pub const LOW_POWER_FRAGMENT_SCALE: u32 = 4;
+/// Finished fragments become chunks this many at a time in low-power mode
+pub const LOW_POWER_CHUNK_BATCH: u32 = 2;
+
+/// Label of the manifest marker placed where the audio config changed
+pub const AUDIO_CONFIG_LABEL: &str = "audio-config";
+/// Labels of the manifest markers around a slate (`insert_slate`)
+pub const SLATE_START_LABEL: &str = "slate-start";
+pub const SLATE_END_LABEL: &str = "slate-end";
+
+/// AAC frames of silence filling the audio of a slate hold this many samples
+const SLATE_AUDIO_FRAME_SAMPLES: u64 = 1024;
+
+/// Recorder lifecycle state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "lowercase")]
+pub enum RecorderStatus {
+    #[default]
+    Idle,
+    Recording,
+    Paused,
+    Stopped,
+}
+
+impl RecorderStatus {
+    /// Get the lowercase name passed to JS state-change callbacks
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecorderStatus::Idle => "idle",
+            RecorderStatus::Recording => "recording",
+            RecorderStatus::Paused => "paused",
+            RecorderStatus::Stopped => "stopped",
+        }
+    }
+}
+
+/// Notification produced by the recorder, in the order it happened
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecorderEvent {
+    StateChanged {
+        from: RecorderStatus,
+        to: RecorderStatus,
+    },
+    ChunkReady(RecordedChunk),
+    /// Write-ahead log records to persist before the frames they cover are lost
+    WalBatch(WalBatch),
+    /// A stream has delivered no frames for longer than the stall threshold
+    StreamStalled {
+        track: TrackKind,
+        stalled_ms: u64,
+    },
+    /// A stalled stream delivered a frame again after `stalled_ms`
+    StreamRecovered {
+        track: TrackKind,
+        stalled_ms: u64,
+    },
+    /// An accepted keyframe (AVCC, output time) picked for a thumbnail
+    Thumbnail {
+        timestamp_us: u64,
+        data: Vec<u8>,
+    },
+    /// An accepted audio frame (output time) wrapped in an ADTS header
+    AdtsFrame {
+        timestamp_us: u64,
+        data: Vec<u8>,
+    },
+    /// Audio has been silent since `start_us` for the minimum duration
+    SilenceStarted {
+        start_us: u64,
+    },
+    /// Sound resumed; the range has been added to the manifest's markers
+    SilenceEnded(SilenceRange),
+    /// The muxer skipped an empty or oversized frame
+    FrameSkipped(SkippedFrame),
+    /// The audio encoder switched configuration; `init_segment` replaces the
+    /// one returned by `start()` for the whole recording
+    AudioConfigChanged {
+        change: AudioConfigChange,
+        init_segment: Vec<u8>,
+    },
+    /// The init segment was rebuilt for another reason than an audio config
+    /// change (e.g. session metadata embedded at `stop()`); it replaces the
+    /// one returned by `start()` for the whole recording
+    InitSegmentChanged(Vec<u8>),
+    /// A chunk an emergency flush emitted without a hash has been hashed;
+    /// its manifest entry now carries the hash
+    ChunkHashed(ChunkMetadata),
+}
+
+/// An audio encoder configuration change, e.g. after a device switch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioConfigChange {
+    /// Output time from which audio uses the new configuration
+    pub timestamp_us: u64,
+    pub previous: AudioSampleEntry,
+    pub current: AudioSampleEntry,
+}
+
+/// Picture shown by a slate (`RecorderState::insert_slate`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlateSource {
+    /// A pre-encoded H.264 keyframe (AVCC) using the recording's SPS/PPS,
+    /// e.g. a countdown image or logo encoded with the session's encoder
+    Keyframe(Vec<u8>),
+    /// The last keyframe recorded, e.g. to hold the final picture
+    LastKeyframe,
+}
+
+/// A track disabled with `disable_track()`
+#[derive(Debug, Clone, Copy, Default)]
+struct MutedTrack {
+    /// Output time covered by muted frames so far
+    range: Option<(u64, u64)>,
+    /// Video enabled again, waiting for a keyframe
+    enabling: bool,
+}
+
+impl MutedTrack {
+    fn note(&mut self, start_us: u64, end_us: u64) {
+        self.range = Some((self.range.map_or(start_us, |(start, _)| start), end_us));
+    }
+}
+
+/// A finished chunk waiting for its hash before it is emitted
+struct HashingChunk {
+    metadata: ChunkMetadata,
+    /// Chunk data as emitted (encrypted when encryption is on)
+    data: Vec<u8>,
+    key_uri: Option<String>,
+    /// End of the timeline when the chunk was taken, for the preview
+    end_us: u64,
+    hash: IncrementalHash,
+    /// Encryption and hashing running on the thread pool
+    /// (`HashStrategy::Background`); `data` is empty until it is done
+    job: Option<SegmentJob>,
+    /// Hash computed by the job
+    job_hash: Option<String>,
+}
+
+/// A chunk emitted without a hash by `emergency_flush`, hashed afterwards
+struct UnhashedChunk {
+    chunk_id: ChunkId,
+    data: Vec<u8>,
+    hash: IncrementalHash,
+}
+
+impl HashingChunk {
+    /// Hash (hex) of the whole chunk, finishing an incremental hash or
+    /// waiting for the chunk's job
+    fn finish_hash(&mut self, strategy: HashStrategy) -> Result<String, String> {
+        if let Some(job) = self.job.take() {
+            self.job_finished(job.finish()?);
+        }
+        if let Some(hash) = self.job_hash.take() {
+            return Ok(hash);
+        }
+        Ok(if strategy == HashStrategy::Incremental {
+            self.hash.advance(&self.data, usize::MAX);
+            self.hash.finish()
+        } else {
+            hash_hex(&self.data, strategy)
+        })
+    }
+
+    /// Take the data and hash of the chunk's job
+    fn job_finished(&mut self, finished: FinishedSegment) {
+        self.metadata.size = finished.data.len() as u64;
+        self.data = finished.data;
+        self.job_hash = Some(finished.hash);
+        self.job = None;
+    }
+}
+
+/// Orchestrates muxer, chunking, session state and manifest for one session
+pub struct RecorderState {
+    muxer: MuxideMuxerState,
+    manifest: ChunkManifest,
+    status: RecorderStatus,
+    events: Vec<RecorderEvent>,
+
+    // Timeline continuity across pause/resume (all in output time)
+    pause_offset_us: u64,
+    /// Output time input timestamps start from; set when resuming a session
+    timeline_base_us: u64,
+    resync_pending: bool,
+    awaiting_keyframe: bool,
+    last_video_us: Option<u64>,
+    last_video_delta_us: u64,
+    last_audio_end_us: Option<u64>,
+
+    wal: Option<WalWriter>,
+    watchdog: Option<StallWatchdog>,
+    keyframes: KeyframeSchedulerState,
+    preview: Option<LivePreviewState>,
+    thumbnail_interval: Option<u32>,
+    adts: Option<AdtsWriterState>,
+    encryptor: Option<SegmentEncryptorState>,
+    keyframe_count: u64,
+    silence: Option<SilenceDetector>,
+    frame_rate: FrameRateEstimator,
+    /// Maps chunks into the manifest's fragment list; None when the start of
+    /// the file is unknown
+    range_map: Option<RangeMapBuilder>,
+    /// Latest video keyframe mapped, anchoring new bookmarks
+    last_keyframe: Option<KeyframeLocation>,
+    /// Data of the latest video keyframe pushed, for `SlateSource::LastKeyframe`
+    last_keyframe_data: Option<Vec<u8>>,
+
+    /// Fragment duration requested by the host while in low-power mode
+    low_power_fragment_ms: Option<u32>,
+    /// Video timestamps not fed to `frame_rate` yet (low-power mode)
+    deferred_frame_times: Vec<u64>,
+
+    /// Tracks currently disabled by the host
+    disabled: BTreeMap<TrackKind, MutedTrack>,
+
+    /// Frames dropped by the recorder and gaps seen so far; the muxer's
+    /// counts are added by `quality_report()`
+    quality: QualityReport,
+
+    /// Time source of the stall watchdog, shared with the muxer
+    clock: ClockHandle,
+
+    hash_strategy: HashStrategy,
+    /// Add the search index to the metadata embedded at `stop()`
+    embed_search_index: bool,
+    /// Chunks taken from the muxer but not hashed yet, oldest first
+    hashing: VecDeque<HashingChunk>,
+    /// Chunks emitted by an emergency flush before they were hashed, hashed
+    /// with the next pushes, `pump_hashes` or `stop()`
+    unhashed: VecDeque<UnhashedChunk>,
+}
+
+impl RecorderState {
+    /// Create a recorder for a session with the given muxer configuration
+    pub fn new(session_id: SessionId, config: MuxideConfig) -> Self {
+        // Until two frames are seen, the next one is due a frame later
+        let frame_us = config.video_time_base().to_us(
+            config.default_video_frame_duration() as u64,
+            Rounding::Nearest,
+        );
+        Self {
+            keyframes: KeyframeSchedulerState::new(&config),
+            muxer: MuxideMuxerState::new(config),
+            manifest: ChunkManifest::new(session_id),
+            status: RecorderStatus::Idle,
+            events: Vec::new(),
+            pause_offset_us: 0,
+            timeline_base_us: 0,
+            resync_pending: false,
+            awaiting_keyframe: true,
+            last_video_us: None,
+            last_video_delta_us: frame_us,
+            last_audio_end_us: None,
+            wal: None,
+            watchdog: None,
+            preview: None,
+            thumbnail_interval: None,
+            adts: None,
+            encryptor: None,
+            keyframe_count: 0,
+            silence: None,
+            frame_rate: FrameRateEstimator::default(),
+            range_map: None,
+            last_keyframe: None,
+            last_keyframe_data: None,
+            low_power_fragment_ms: None,
+            deferred_frame_times: Vec::new(),
+            disabled: BTreeMap::new(),
+            quality: QualityReport::default(),
+            clock: ClockHandle::default(),
+            hash_strategy: HashStrategy::default(),
+            embed_search_index: false,
+            hashing: VecDeque::new(),
+            unhashed: VecDeque::new(),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system clock, here and in
+    /// the muxer
+    pub fn set_clock(&mut self, clock: ClockHandle) {
+        self.muxer.set_clock(clock.clone());
+        self.clock = clock;
+    }
+
+    /// Rebuild a session from its write-ahead log after a crash
+    ///
+    /// The log is replayed into a fresh muxer and the recording is stopped.
+    /// Muxing is deterministic, so chunks already listed in `persisted` are
+    /// reproduced byte for byte; only the chunks missing from it are queued as
+    /// `ChunkReady` events. The returned recorder is in the `stopped` state.
+    pub fn recover(
+        session_id: SessionId,
+        config: MuxideConfig,
+        persisted: Option<ChunkManifest>,
+        wal_bytes: &[u8],
+    ) -> Result<Self, String> {
+        let log = wal::decode(wal_bytes)?;
+        log_event!(
+            LogLevel::Info,
+            "Recovering session from write-ahead log",
+            session = session_id,
+            frames = log.frames.len(),
+            truncated = log.truncated,
+        );
+        let mut recorder = Self::new(session_id, config);
+        recorder.start()?;
+        for frame in log.frames {
+            match frame {
+                WalFrame::Video {
+                    timestamp_us,
+                    is_keyframe,
+                    data,
+                } => {
+                    recorder.mux_video(&data, timestamp_us, is_keyframe)?;
+                }
+                WalFrame::Audio {
+                    timestamp_us,
+                    duration_us,
+                    data,
+                } => {
+                    recorder.mux_audio(&data, timestamp_us, duration_us)?;
+                }
+                WalFrame::AudioConfig {
+                    sample_rate,
+                    channels,
+                    audio_specific_config,
+                    ..
+                } => {
+                    recorder.switch_audio_config(sample_rate, channels, audio_specific_config)?;
+                }
+                WalFrame::Slate {
+                    timestamp_us,
+                    duration_us,
+                    keyframe,
+                } => {
+                    recorder.mux_slate(&keyframe, timestamp_us, duration_us as u64)?;
+                }
+            }
+        }
+        recorder.stop()?;
+
+        let events = recorder.take_events();
+        if let Some(persisted) = persisted {
+            for chunk in recorder.manifest.chunks.iter_mut() {
+                if let Some(existing) = persisted.get(&chunk.chunk_id) {
+                    *chunk = existing.clone();
+                }
+            }
+            recorder.manifest.metadata = persisted.metadata.clone();
+            recorder.manifest.markers = persisted.markers.clone();
+            recorder.events = events
+                .into_iter()
+                .filter(|event| match event {
+                    RecorderEvent::ChunkReady(chunk) => {
+                        !persisted.contains(&chunk.metadata.chunk_id)
+                    }
+                    _ => false,
+                })
+                .collect();
+        } else {
+            recorder.events = events
+                .into_iter()
+                .filter(|event| matches!(event, RecorderEvent::ChunkReady(_)))
+                .collect();
+        }
+        Ok(recorder)
+    }
+
+    /// Log every accepted frame to a write-ahead log before muxing it
+    ///
+    /// Records are emitted as `WalBatch` events every `flush_interval_ms` of
+    /// media time. Must be called before `start()`.
+    pub fn enable_wal(&mut self, flush_interval_ms: u32) -> Result<(), String> {
+        if self.status != RecorderStatus::Idle {
+            return Err(format!(
+                "Cannot enable write-ahead log in state: {}",
+                self.status.as_str()
+            ));
+        }
+        self.wal = Some(WalWriter::new(flush_interval_ms));
+        Ok(())
+    }
+
+    /// Report streams that stop delivering frames for more than `threshold_ms`
+    ///
+    /// Stalls are detected on every push and on `check_stalls()`; call the
+    /// latter on a timer so a stall of every stream is noticed too. Must be
+    /// called before `start()`.
+    pub fn enable_stall_watchdog(&mut self, threshold_ms: u32) -> Result<(), String> {
+        if self.status != RecorderStatus::Idle {
+            return Err(format!(
+                "Cannot enable stall watchdog in state: {}",
+                self.status.as_str()
+            ));
+        }
+        self.watchdog = Some(StallWatchdog::new(
+            threshold_ms,
+            self.muxer.has_video(),
+            self.muxer.has_audio(),
+        ));
+        Ok(())
+    }
+
+    /// Keep the last `max_segments` segments for a near-live preview
+    ///
+    /// Must be called before `start()`.
+    pub fn enable_preview(&mut self, max_segments: u32) -> Result<(), String> {
+        if self.status != RecorderStatus::Idle {
+            return Err(format!(
+                "Cannot enable preview in state: {}",
+                self.status.as_str()
+            ));
+        }
+        self.preview = Some(LivePreviewState::new(max_segments));
+        Ok(())
+    }
+
+    /// Emit every `every_nth` accepted keyframe as a `Thumbnail` event
+    ///
+    /// The first keyframe is always included. Must be called before `start()`.
+    pub fn enable_thumbnails(&mut self, every_nth: u32) -> Result<(), String> {
+        if self.status != RecorderStatus::Idle {
+            return Err(format!(
+                "Cannot enable thumbnails in state: {}",
+                self.status.as_str()
+            ));
+        }
+        if every_nth == 0 {
+            return Err("Thumbnail interval must be at least 1".to_string());
+        }
+        self.thumbnail_interval = Some(every_nth);
+        Ok(())
+    }
+
+    /// Emit every accepted audio frame wrapped in an ADTS header as an
+    /// `AdtsFrame` event
+    ///
+    /// Must be called before `start()`; fails if the audio track cannot be
+    /// described by an ADTS header.
+    pub fn enable_adts_output(&mut self) -> Result<(), String> {
+        if self.status != RecorderStatus::Idle {
+            return Err(format!(
+                "Cannot enable ADTS output in state: {}",
+                self.status.as_str()
+            ));
+        }
+        self.adts = Some(AdtsWriterState::from_muxer_config(self.muxer.config())?);
+        Ok(())
+    }
+
+    /// Encrypt every chunk with AES-128-CBC before it is emitted
+    ///
+    /// Chunk sizes and hashes describe the encrypted bytes, while fragment
+    /// byte ranges stay those of the decrypted file. Must be called before
+    /// `start()`; to continue an encrypted session with `resume_session()`,
+    /// pass the key in use when the snapshot was taken.
+    pub fn enable_segment_encryption(&mut self, key: SegmentKey) -> Result<(), String> {
+        if self.status != RecorderStatus::Idle {
+            return Err(format!(
+                "Cannot enable segment encryption in state: {}",
+                self.status.as_str()
+            ));
+        }
+        self.encryptor = Some(SegmentEncryptorState::new(key)?);
+        Ok(())
+    }
+
+    /// Encrypt the chunks emitted from now on with another key
+    pub fn rotate_encryption_key(&mut self, key: SegmentKey) -> Result<(), String> {
+        let encryptor = self
+            .encryptor
+            .as_mut()
+            .ok_or("Segment encryption is not enabled")?;
+        encryptor.rotate_key(key)
+    }
+
+    /// Report sustained silence in the audio and mark it in the manifest
+    ///
+    /// With `from_aac_frames` set, pushed audio frames are classified by
+    /// encoded size; otherwise feed levels with `push_audio_level()`. Must be
+    /// called before `start()`.
+    pub fn enable_silence_detection(&mut self, config: SilenceConfig) -> Result<(), String> {
+        if self.status != RecorderStatus::Idle {
+            return Err(format!(
+                "Cannot enable silence detection in state: {}",
+                self.status.as_str()
+            ));
+        }
+        if !self.muxer.has_audio() {
+            return Err("Silence detection requires an audio track".to_string());
+        }
+        self.silence = Some(SilenceDetector::new(config));
+        Ok(())
+    }
+
+    /// Feed the silence detector with the level (dBFS) of the audio at
+    /// `timestamp_us` (input time, like `push_audio`)
+    pub fn push_audio_level(&mut self, timestamp_us: u64, duration_us: u32, level_db: f64) {
+        if self.status != RecorderStatus::Recording {
+            return;
+        }
+        // Frames decide the pause offset after a resume; levels only follow it
+        let offset = if self.resync_pending {
+            self.resynced_offset(timestamp_us)
+        } else {
+            self.pause_offset_us
+        };
+        let ts = self.to_output(timestamp_us, offset);
+        let change = self
+            .silence
+            .as_mut()
+            .and_then(|detector| detector.push_level(ts, duration_us as u64, level_db));
+        self.push_silence_change(change);
+    }
+
+    /// Check for stalled streams against the current wall-clock time
+    pub fn check_stalls(&mut self) {
+        self.check_stalls_at(self.clock.now_ms());
+    }
+
+    /// Check for stalled streams at `now_ms` (Unix time in milliseconds)
+    ///
+    /// Streams are only watched while recording; pauses never count as stalls,
+    /// and neither does low-power mode, where the host's timers are throttled.
+    pub fn check_stalls_at(&mut self, now_ms: u64) {
+        if self.status != RecorderStatus::Recording || self.low_power() {
+            return;
+        }
+        if let Some(watchdog) = self.watchdog.as_mut() {
+            let changes = watchdog.check(now_ms);
+            self.push_stall_changes(changes);
+        }
+    }
+
+    /// Choose how finished segments are hashed; chunks being hashed are
+    /// finished first
+    pub fn set_hash_strategy(&mut self, strategy: HashStrategy) -> Result<(), String> {
+        self.hash_chunks(usize::MAX)?;
+        self.hash_strategy = strategy;
+        Ok(())
+    }
+
+    /// Embed the search index (see `search_index()`) with the session
+    /// metadata at `stop()`
+    pub fn set_embed_search_index(&mut self, enabled: bool) {
+        self.embed_search_index = enabled;
+    }
+
+    /// Hash up to `budget_bytes` of the chunks waiting for their hash
+    /// (`HashStrategy::Incremental`, or emitted unhashed by an emergency
+    /// flush), emitting the ones finished
+    ///
+    /// Call from idle time (e.g. `requestIdleCallback`) so chunks do not
+    /// wait for the next pushes. Returns whether chunks are still waiting.
+    pub fn pump_hashes(&mut self, budget_bytes: usize) -> Result<bool, String> {
+        self.hash_chunks(budget_bytes)?;
+        Ok(!self.hashing.is_empty() || !self.unhashed.is_empty())
+    }
+
+    /// Whether a write-ahead log is being written
+    pub fn wal_enabled(&self) -> bool {
+        self.wal.is_some()
+    }
+
+    /// Current lifecycle state
+    pub fn status(&self) -> RecorderStatus {
+        self.status
+    }
+
+    /// Muxer configuration of the session
+    pub fn config(&self) -> &MuxideConfig {
+        self.muxer.config()
+    }
+
+    /// Session manifest with every chunk emitted so far
+    pub fn manifest(&self) -> &ChunkManifest {
+        &self.manifest
+    }
+
+    /// Store the input's loudness in the manifest's recording metadata
+    pub fn set_loudness(&mut self, stats: LoudnessStats) {
+        self.manifest
+            .metadata
+            .get_or_insert_with(Default::default)
+            .loudness = Some(stats);
+    }
+
+    /// Store the downmix recipe of the audio sources in the manifest's
+    /// recording metadata
+    pub fn set_downmix(&mut self, recipe: DownmixRecipe) {
+        self.manifest
+            .metadata
+            .get_or_insert_with(Default::default)
+            .downmix = Some(recipe);
+    }
+
+    /// Video frame rate over the last couple of seconds
+    pub fn current_fps(&self) -> Option<f64> {
+        self.frame_rate.current_fps()
+    }
+
+    /// Dropped, rejected and corrected frames and media gaps so far
+    pub fn quality_report(&self) -> QualityReport {
+        let stats = self.muxer.stats();
+        let mut report = self.quality.clone();
+        report.dropped_frames += stats.dropped_frames;
+        report.rejected_frames += stats.rejected_frames;
+        report.corrected_frames += stats.corrected_frames;
+        report.empty_frames += stats.empty_frames;
+        report.oversized_frames += stats.oversized_frames;
+        report.largest_frame = report.largest_frame.max(stats.largest_frame);
+        report.max_frame_size = stats.max_frame_size;
+        report
+    }
+
+    /// How long after capture each segment was muxed and emitted as a
+    /// `ChunkReady` event (its acknowledgement), e.g. to see whether hashing
+    /// holds chunks back
+    pub fn latency_report(&mut self) -> LatencyReport {
+        self.muxer.latency_report()
+    }
+
+    /// Video frame rate summary of everything muxed so far
+    pub fn frame_rate_stats(&self) -> Option<FrameRateStats> {
+        self.frame_rate.stats()
+    }
+
+    /// Live preview window, if enabled
+    pub fn preview(&self) -> Option<&LivePreviewState> {
+        self.preview.as_ref()
+    }
+
+    /// Muxer counters
+    pub fn stats(&self) -> MuxerStats {
+        self.muxer.stats()
+    }
+
+    /// Total time removed from the timeline by pauses, in microseconds
+    pub fn pause_offset_us(&self) -> u64 {
+        self.pause_offset_us
+    }
+
+    /// Take all queued events
+    pub fn take_events(&mut self) -> Vec<RecorderEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Start recording and return the init segment (ftyp + moov)
+    pub fn start(&mut self) -> Result<Vec<u8>, String> {
+        if self.status != RecorderStatus::Idle {
+            return Err(format!(
+                "Cannot start recorder in state: {}",
+                self.status.as_str()
+            ));
+        }
+        self.muxer.init()?;
+        self.manifest.state.transition_to(SessionState::Recording)?;
+        self.set_status(RecorderStatus::Recording);
+        self.reset_watchdog();
+        let init = self.muxer.get_init_segment()?;
+        self.range_map = Some(RangeMapBuilder::new(&init)?);
+        if let Some(preview) = self.preview.as_mut() {
+            preview.set_init_segment(init.clone(), codec_strings(self.muxer.config()));
+        }
+        Ok(init)
+    }
+
+    /// Continue a session interrupted by a page reload, instead of `start()`
+    ///
+    /// `manifest` and `snapshot` are the last ones persisted for the session;
+    /// they must list the same chunks, and this recorder's config must produce
+    /// the same tracks as the snapshot. Frames the muxer had buffered are
+    /// flushed as a chunk, and the first frame pushed afterwards (a keyframe,
+    /// as after `resume()`) continues right where they ended, whatever clock
+    /// its timestamps come from. Returns the init segment.
+    ///
+    /// Frame rate statistics and silence detection cover the resumed part
+    /// only. Sessions recorded with a write-ahead log cannot be resumed, as
+    /// recovery replays the log from the start of the recording.
+    pub fn resume_session(
+        &mut self,
+        manifest: ChunkManifest,
+        snapshot: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        if self.status != RecorderStatus::Idle {
+            return Err(format!(
+                "Cannot resume session in state: {}",
+                self.status.as_str()
+            ));
+        }
+        if self.wal.is_some() {
+            return Err("Cannot resume a session with a write-ahead log".to_string());
+        }
+        let session_id = &self.manifest.session_id;
+        if &manifest.session_id != session_id {
+            return Err(format!(
+                "Manifest of session {} cannot resume session {}",
+                manifest.session_id, session_id
+            ));
+        }
+        if manifest.state != SessionState::Recording {
+            return Err(format!(
+                "Session {} is {} and cannot be resumed",
+                session_id, manifest.state
+            ));
+        }
+
+        let mut reader = StateReader {
+            bytes: snapshot,
+            pos: 0,
+        };
+        if reader.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err("Invalid recorder snapshot: bad magic".to_string());
+        }
+        let version = reader.u8()?;
+        if !(MIN_SNAPSHOT_VERSION..=SNAPSHOT_VERSION).contains(&version) {
+            return Err(format!(
+                "Unsupported recorder snapshot version: {}",
+                version
+            ));
+        }
+        let snapshot_session = String::from_utf8_lossy(reader.bytes()?).into_owned();
+        if snapshot_session != session_id.as_str() {
+            return Err(format!(
+                "Snapshot of session {} cannot resume session {}",
+                snapshot_session, session_id
+            ));
+        }
+        let timeline_end_us = reader.u64()?;
+        let muxer = MuxideMuxerState::restore_state(reader.bytes()?)?;
+        let quality: QualityReport = match version {
+            1 => QualityReport::default(),
+            _ => serde_json::from_slice(reader.bytes()?)
+                .map_err(|e| format!("Invalid recorder snapshot quality report: {}", e))?,
+        };
+        let last_keyframe_data = match version {
+            1 | 2 => None,
+            _ => match reader.u8()? {
+                0 => None,
+                _ => Some(reader.bytes()?.to_vec()),
+            },
+        };
+        if reader.pos != snapshot.len() {
+            return Err("Invalid recorder snapshot: trailing bytes".to_string());
+        }
+
+        // Chunks muxed before and after the reload must play as one file
+        let stats = muxer.stats();
+        let muxed = (stats.segment_count - stats.pending_segment_count) as usize;
+        if muxed != manifest.chunk_count() {
+            return Err(format!(
+                "Snapshot of session {} covers {} chunks but the manifest lists {}",
+                session_id,
+                muxed,
+                manifest.chunk_count()
+            ));
+        }
+        compat::check_config_against_init(self.muxer.config(), &muxer.get_init_segment()?)?
+            .ensure()?;
+
+        log_event!(
+            LogLevel::Info,
+            "Resuming session from snapshot",
+            session = session_id,
+            chunks = muxed,
+            timeline_end_us = timeline_end_us,
+        );
+        self.muxer = muxer;
+        self.muxer.set_clock(self.clock.clone());
+        self.manifest = manifest;
+        self.quality = quality;
+        self.last_keyframe_data = last_keyframe_data;
+        let init = self.muxer.get_init_segment()?;
+        // The map continues only if it covers every chunk written so far
+        let mapped = self.manifest.chunks.iter().all(|chunk| {
+            self.manifest
+                .fragments
+                .iter()
+                .any(|f| f.chunk_id == chunk.chunk_id)
+        });
+        self.range_map = if mapped {
+            Some(RangeMapBuilder::resume(&init, &self.manifest.fragments)?)
+        } else {
+            self.manifest.fragments.clear();
+            None
+        };
+        self.timeline_base_us = timeline_end_us;
+        self.resync_pending = true;
+        self.awaiting_keyframe = self.muxer.has_video();
+        self.muxer.reset_latency_anchor();
+        self.set_status(RecorderStatus::Recording);
+        self.reset_watchdog();
+        self.muxer.force_flush()?;
+        self.collect_segments()?;
+        if let Some(preview) = self.preview.as_mut() {
+            preview.set_init_segment(init.clone(), codec_strings(self.muxer.config()));
+        }
+        Ok(init)
+    }
+
+    /// Whether `snapshot()` can be taken now: while recording or paused,
+    /// with no chunk being hashed
+    pub fn can_snapshot(&self) -> bool {
+        self.is_active() && self.hashing.is_empty()
+    }
+
+    fn is_active(&self) -> bool {
+        matches!(
+            self.status,
+            RecorderStatus::Recording | RecorderStatus::Paused
+        )
+    }
+
+    /// Serialize what `resume_session()` needs to continue this recording
+    ///
+    /// Holds the muxer state, buffered frames included, the end of the
+    /// timeline and the quality counters so far; persist it together with
+    /// the manifest.
+    pub fn snapshot(&self) -> Result<Vec<u8>, String> {
+        if !self.is_active() {
+            return Err(format!(
+                "Cannot snapshot recorder in state: {}",
+                self.status.as_str()
+            ));
+        }
+        // Those chunks have left the muxer but are not in the manifest yet
+        if !self.hashing.is_empty() {
+            return Err(
+                "Cannot snapshot recorder while chunks are being hashed; pump_hashes first"
+                    .to_string(),
+            );
+        }
+        let mut out = Vec::new();
+        out.extend_from_slice(SNAPSHOT_MAGIC);
+        out.push(SNAPSHOT_VERSION);
+        put_bytes(&mut out, self.manifest.session_id.as_str().as_bytes());
+        out.extend_from_slice(&self.timeline_end_us().to_le_bytes());
+        put_bytes(&mut out, &self.muxer.serialize_state()?);
+        let quality = serde_json::to_vec(&self.quality).map_err(|e| e.to_string())?;
+        put_bytes(&mut out, &quality);
+        // For `SlateSource::LastKeyframe` after a reload
+        match &self.last_keyframe_data {
+            Some(keyframe) => {
+                out.push(1);
+                put_bytes(&mut out, keyframe);
+            }
+            None => out.push(0),
+        }
+        Ok(out)
+    }
+
+    /// Pack this recording so another worker can continue it with
+    /// `resume_transfer()`
+    ///
+    /// Frames and segments not emitted as chunks yet travel in the package,
+    /// so nothing is lost between the last chunk and the hand-off. Stop
+    /// feeding this recorder once the package is sent; when packages are
+    /// taken periodically (to survive a worker crash), continue from the
+    /// newest one: chunks emitted after it are emitted again.
+    pub fn transfer(&self) -> Result<RecorderTransfer, String> {
+        Ok(RecorderTransfer {
+            config: self.muxer.config().clone(),
+            manifest: self.manifest.clone(),
+            snapshot: self.snapshot()?,
+            paused: self.status == RecorderStatus::Paused,
+        })
+    }
+
+    /// Continue a recording packed by `transfer()` in another worker, instead
+    /// of `start()`
+    ///
+    /// Create this recorder with the session ID and config of `transfer` and
+    /// enable the same features first, as for `resume_session()`, which this
+    /// builds on: buffered frames are flushed as a chunk and the next frame
+    /// continues the timeline. A paused recording stays paused. Returns the
+    /// init segment.
+    pub fn resume_transfer(&mut self, transfer: RecorderTransfer) -> Result<Vec<u8>, String> {
+        let init = self.resume_session(transfer.manifest, &transfer.snapshot)?;
+        if transfer.paused {
+            self.pause()?;
+        }
+        Ok(init)
+    }
+
+    /// Pause recording; frames pushed while paused are dropped
+    pub fn pause(&mut self) -> Result<(), String> {
+        if self.status != RecorderStatus::Recording {
+            return Err(format!(
+                "Cannot pause recorder in state: {}",
+                self.status.as_str()
+            ));
+        }
+        self.collect_segments()?;
+        self.set_status(RecorderStatus::Paused);
+        Ok(())
+    }
+
+    /// Resume recording; the paused interval is removed from the timeline
+    pub fn resume(&mut self) -> Result<(), String> {
+        if self.status != RecorderStatus::Paused {
+            return Err(format!(
+                "Cannot resume recorder in state: {}",
+                self.status.as_str()
+            ));
+        }
+        self.resync_pending = true;
+        self.awaiting_keyframe = self.muxer.has_video();
+        self.muxer.reset_latency_anchor();
+        self.set_status(RecorderStatus::Recording);
+        self.reset_watchdog();
+        Ok(())
+    }
+
+    /// Stop recording: flush the muxer, emit remaining chunks, then change state
+    pub fn stop(&mut self) -> Result<(), String> {
+        if !matches!(
+            self.status,
+            RecorderStatus::Recording | RecorderStatus::Paused
+        ) {
+            return Err(format!(
+                "Cannot stop recorder in state: {}",
+                self.status.as_str()
+            ));
+        }
+        if let Some(batch) = self.wal.as_mut().and_then(|wal| wal.flush()) {
+            self.events.push(RecorderEvent::WalBatch(batch));
+        }
+        self.muxer.force_flush()?;
+        self.collect_segments()?;
+        for (track, muted) in std::mem::take(&mut self.disabled) {
+            self.end_muted_range(track, muted);
+        }
+        if let Some(preview) = self.preview.as_mut() {
+            preview.end();
+        }
+        self.catch_up_frame_rate();
+        if let Some(stats) = self.frame_rate.stats() {
+            self.manifest
+                .metadata
+                .get_or_insert_with(Default::default)
+                .framerate = Some(stats);
+        }
+        self.manifest
+            .metadata
+            .get_or_insert_with(Default::default)
+            .quality = Some(self.quality_report());
+        let end_us = self.last_audio_end_us.unwrap_or_default();
+        let change = self
+            .silence
+            .as_mut()
+            .and_then(|detector| detector.finish(end_us));
+        self.push_silence_change(change);
+        self.manifest.tail = None;
+        // Best effort: the recording is complete without the metadata box
+        if let Err(e) = self.embed_session_metadata() {
+            log_event!(LogLevel::Warn, "Session metadata not embedded", error = e);
+        }
+        self.manifest
+            .state
+            .transition_to(SessionState::Finalizing)?;
+        self.set_status(RecorderStatus::Stopped);
+        Ok(())
+    }
+
+    /// Save as much of the recording as possible before the page may be
+    /// killed; for the host to call from `pagehide`, `visibilitychange`
+    /// (hidden) or `beforeunload`
+    ///
+    /// Flushes the write-ahead log, closes the fragment being built and
+    /// emits every chunk, then marks the manifest's `tail` where the data
+    /// ends. Hashing stops once `budget_ms` have passed: the remaining
+    /// chunks are emitted without a hash rather than lost. If the page
+    /// survives, recording goes on and those chunks are hashed like
+    /// incremental ones, each raising a `ChunkHashed` event once its
+    /// manifest entry has the hash. Returns None unless recording or
+    /// paused.
+    pub fn emergency_flush(&mut self, budget_ms: u32) -> Result<Option<TailMarker>, String> {
+        if !matches!(
+            self.status,
+            RecorderStatus::Recording | RecorderStatus::Paused
+        ) {
+            return Ok(None);
+        }
+        let deadline_ms = self.clock.now_ms() + budget_ms as u64;
+        if let Some(batch) = self.wal.as_mut().and_then(|wal| wal.flush()) {
+            self.events.push(RecorderEvent::WalBatch(batch));
+        }
+        self.muxer.force_flush()?;
+        self.take_segments()?;
+        let mut unhashed = 0;
+        while let Some(mut chunk) = self.hashing.pop_front() {
+            // A chunk's job has its data, and the hash comes with it
+            let hash = if chunk.job.is_some() || self.clock.now_ms() < deadline_ms {
+                Some(chunk.finish_hash(self.hash_strategy)?)
+            } else {
+                unhashed += 1;
+                self.unhashed.push_back(UnhashedChunk {
+                    chunk_id: chunk.metadata.chunk_id.clone(),
+                    data: chunk.data.clone(),
+                    hash: std::mem::take(&mut chunk.hash),
+                });
+                None
+            };
+            self.emit_chunk(chunk, hash)?;
+        }
+        let tail = TailMarker {
+            timestamp_us: self.timeline_end_us(),
+            chunk_count: self.manifest.chunks.len() as u64,
+            flushed_at_ms: self.clock.now_ms(),
+        };
+        log_event!(
+            LogLevel::Warn,
+            "Recording flushed for shutdown",
+            session = self.manifest.session_id,
+            timestamp_us = tail.timestamp_us,
+            chunks = tail.chunk_count,
+            unhashed = unhashed,
+        );
+        self.manifest.tail = Some(tail.clone());
+        Ok(Some(tail))
+    }
+
+    /// Change the fragment (chunk) duration for the rest of the recording
+    ///
+    /// Applies from the fragment being built; keyframe scheduling follows.
+    /// In low-power mode the duration is scaled until the mode ends; the
+    /// low-memory profile caps it.
+    pub fn set_fragment_duration_ms(&mut self, fragment_duration_ms: u32) {
+        let applied = match self.low_power_fragment_ms.as_mut() {
+            Some(requested) => {
+                *requested = fragment_duration_ms;
+                fragment_duration_ms.saturating_mul(LOW_POWER_FRAGMENT_SCALE)
+            }
+            None => fragment_duration_ms,
+        };
+        self.muxer.set_fragment_duration_ms(applied);
+        let target = self.muxer.config().target_fragment_duration_ms();
+        self.keyframes.set_fragment_duration_ms(target);
+    }
+
+    /// Take a new audio encoder configuration (WebCodecs `decoderConfig`),
+    /// e.g. after the input device switched from 48 kHz to 44.1 kHz
+    ///
+    /// Call it with the config of every `AudioEncoder` output that carries
+    /// one; returns false if the configuration did not change. On a change
+    /// the current fragment is closed as a chunk, later audio is muxed under
+    /// a new sample description (see `MuxideMuxerState::change_audio_config`)
+    /// and an `AudioConfigChanged` event hands over the new init segment,
+    /// which must replace the stored one. The manifest gets an
+    /// `AUDIO_CONFIG_LABEL` marker, and its fragment byte offsets move with
+    /// the longer init segment.
+    pub fn change_audio_config(
+        &mut self,
+        sample_rate: u32,
+        channels: u16,
+        audio_specific_config: Option<Vec<u8>>,
+    ) -> Result<bool, String> {
+        if !matches!(
+            self.status,
+            RecorderStatus::Recording | RecorderStatus::Paused
+        ) {
+            return Err(format!(
+                "Cannot change audio config in state: {}",
+                self.status.as_str()
+            ));
+        }
+        let frame = WalFrame::AudioConfig {
+            timestamp_us: self.timeline_end_us(),
+            sample_rate,
+            channels,
+            audio_specific_config: audio_specific_config.clone(),
+        };
+        if !self.switch_audio_config(sample_rate, channels, audio_specific_config)? {
+            return Ok(false);
+        }
+        self.log_frame(frame);
+        Ok(true)
+    }
+
+    /// Apply an audio config change to the muxer and everything derived
+    /// from the init segment
+    fn switch_audio_config(
+        &mut self,
+        sample_rate: u32,
+        channels: u16,
+        audio_specific_config: Option<Vec<u8>>,
+    ) -> Result<bool, String> {
+        let previous = self.muxer.config().audio_sample_entry();
+        let previous_init_len = self.muxer.get_init_segment()?.len() as u64;
+        if !self
+            .muxer
+            .change_audio_config(sample_rate, channels, audio_specific_config)?
+        {
+            return Ok(false);
+        }
+        // Chunks muxed before the change are mapped behind the old init segment
+        self.collect_segments()?;
+        let init = self.init_segment_rebuilt(previous_init_len)?;
+        if self.adts.is_some() {
+            self.adts = match AdtsWriterState::from_muxer_config(self.muxer.config()) {
+                Ok(adts) => Some(adts),
+                Err(e) => {
+                    log_event!(LogLevel::Warn, "ADTS output stopped", detail = e);
+                    None
+                }
+            };
+        }
+
+        let timestamp_us = self.timeline_end_us();
+        self.manifest.add_marker(Marker {
+            timestamp_us,
+            label: Some(AUDIO_CONFIG_LABEL.to_string()),
+            bookmark: None,
+        });
+        self.events.push(RecorderEvent::AudioConfigChanged {
+            change: AudioConfigChange {
+                timestamp_us,
+                previous,
+                current: self.muxer.config().audio_sample_entry(),
+            },
+            init_segment: init,
+        });
+        Ok(true)
+    }
+
+    /// Move everything derived from the init segment after the muxer
+    /// rebuilt it, and return the new one
+    fn init_segment_rebuilt(&mut self, previous_init_len: u64) -> Result<Vec<u8>, String> {
+        let init = self.muxer.get_init_segment()?;
+        let grown = init.len() as u64 - previous_init_len;
+        if let Some(range_map) = self.range_map.as_mut() {
+            range_map.shift(grown);
+            self.manifest.shift_offsets(grown);
+            if let Some(keyframe) = self.last_keyframe.as_mut() {
+                keyframe.fragment_offset += grown;
+                keyframe.sample_offset += grown;
+            }
+        }
+        if let Some(preview) = self.preview.as_mut() {
+            preview.set_init_segment(init.clone(), codec_strings(self.muxer.config()));
+        }
+        Ok(init)
+    }
+
+    /// Embed the session id, metadata and labeled markers in the moov (see
+    /// `EmbeddedMetadata`), so the file describes itself without the
+    /// manifest
+    ///
+    /// With absolute data offsets, which a longer init segment would
+    /// invalidate, skipped unless it fits `moov_reserved_size`.
+    fn embed_session_metadata(&mut self) -> Result<(), String> {
+        let mut embedded = EmbeddedMetadata::from_manifest(&self.manifest);
+        if self.embed_search_index {
+            embedded.index = Some(self.build_search_index());
+        }
+        let json = embedded.to_json()?;
+        let previous_init_len = self.muxer.get_init_segment()?.len() as u64;
+        if self.muxer.config().data_offset_mode == DataOffsetMode::Absolute {
+            if self.muxer.config().moov_reserved_size.is_none() {
+                return Ok(());
+            }
+            let config = MuxideConfig {
+                session_metadata: Some(json.clone()),
+                ..self.muxer.config().clone()
+            };
+            if build_init_segment(&config, None)?.len() as u64 != previous_init_len {
+                log_event!(
+                    LogLevel::Warn,
+                    "Session metadata not embedded: it does not fit the reserved moov space",
+                    reserved = config.moov_reserved_size.unwrap_or(0),
+                );
+                return Ok(());
+            }
+        }
+        if self.muxer.set_session_metadata(Some(json))? {
+            let init = self.init_segment_rebuilt(previous_init_len)?;
+            self.events.push(RecorderEvent::InitSegmentChanged(init));
+        }
+        Ok(())
+    }
+
+    /// Show a still picture for `duration_ms` at the end of the timeline,
+    /// e.g. a countdown slate before the first frame or an outro before
+    /// `stop()`
+    ///
+    /// The picture is muxed as synthetic keyframes, one per fragment
+    /// duration, whose durations add up to exactly `duration_ms`; audio gets
+    /// silent AAC frames over the same time. The slate gets fragments of its
+    /// own and a single write-ahead log record. Frames pushed afterwards
+    /// continue right after the slate, whatever their timestamps, video from
+    /// the next keyframe (as after `resume()`). The slate is marked in the
+    /// manifest with `SLATE_START_LABEL` and `SLATE_END_LABEL` markers.
+    pub fn insert_slate(&mut self, source: SlateSource, duration_ms: u32) -> Result<(), String> {
+        if self.status != RecorderStatus::Recording {
+            return Err(format!(
+                "Cannot insert a slate in state: {}",
+                self.status.as_str()
+            ));
+        }
+        if !self.muxer.has_video() {
+            return Err("Slates need a video track".to_string());
+        }
+        if duration_ms == 0 {
+            return Err("Slate duration must be positive".to_string());
+        }
+        let keyframe = match source {
+            SlateSource::Keyframe(data) => {
+                let config = self.muxer.config();
+                let (Some(sps), Some(pps)) = (&config.sps, &config.pps) else {
+                    return Err("Slates need the session's SPS and PPS".to_string());
+                };
+                sps::check_keyframe(&data, sps, pps)
+                    .map_err(|e| format!("Invalid slate keyframe: {}", e))?;
+                data
+            }
+            SlateSource::LastKeyframe => self
+                .last_keyframe_data
+                .clone()
+                .ok_or("No keyframe recorded yet to repeat")?,
+        };
+        let start_us = self.timeline_end_us();
+        let duration_us = duration_ms as u64 * 1000;
+        let Ok(logged_duration_us) = u32::try_from(duration_us) else {
+            return Err(format!("Slate duration too long: {} ms", duration_ms));
+        };
+        let end_us = start_us + duration_us;
+        self.log_frame(WalFrame::Slate {
+            timestamp_us: start_us,
+            duration_us: logged_duration_us,
+            keyframe: keyframe.clone(),
+        });
+        self.mux_slate(&keyframe, start_us, duration_us)?;
+        log_event!(
+            LogLevel::Info,
+            "Slate inserted",
+            start_us = start_us,
+            duration_us = duration_us,
+        );
+        // As in `resume_session()`, the next frame continues from the slate
+        // whatever clock its timestamp comes from
+        self.timeline_base_us = end_us;
+        self.pause_offset_us = 0;
+        self.resync_pending = true;
+        self.awaiting_keyframe = true;
+        self.collect_finished_segments()
+    }
+
+    /// Bookmark the last video frame pushed, with an optional label
+    ///
+    /// The marker's `bookmark` is anchored to the latest keyframe at or
+    /// before that frame as soon as the fragment holding the keyframe is
+    /// muxed; until then its `keyframe` is unset. Needs a video track and the
+    /// byte-range map. Returns the marker's timestamp.
+    pub fn add_bookmark(&mut self, label: Option<String>) -> Result<u64, String> {
+        if !matches!(
+            self.status,
+            RecorderStatus::Recording | RecorderStatus::Paused
+        ) {
+            return Err(format!(
+                "Cannot add a bookmark in state: {}",
+                self.status.as_str()
+            ));
+        }
+        if !self.range_map.as_ref().is_some_and(|m| m.has_video()) {
+            return Err("Bookmarks need a video track and the byte-range map".to_string());
+        }
+        let timestamp_us = self.last_video_us.ok_or("No video frame to bookmark yet")?;
+        self.manifest.add_marker(Marker {
+            timestamp_us,
+            label,
+            bookmark: Some(Bookmark {
+                keyframe: self.last_keyframe.clone(),
+            }),
+        });
+        Ok(timestamp_us)
+    }
+
+    /// Sidecar subtitles of the stopped recording's markers (see
+    /// `subtitles::marker_cues`), cut at the end of the last frame
+    pub fn subtitles(&self, format: SubtitleFormat) -> Result<String, String> {
+        if self.status != RecorderStatus::Stopped {
+            return Err(format!(
+                "Cannot export subtitles in state: {}",
+                self.status.as_str()
+            ));
+        }
+        Ok(export_subtitles(
+            &self.manifest,
+            Some(self.timeline_end_us()),
+            format,
+        ))
+    }
+
+    /// Search index of the stopped recording (see `SessionIndex`), cut at
+    /// the end of the last frame
+    pub fn search_index(&self) -> Result<SessionIndex, String> {
+        if self.status != RecorderStatus::Stopped {
+            return Err(format!(
+                "Cannot build search index in state: {}",
+                self.status.as_str()
+            ));
+        }
+        Ok(self.build_search_index())
+    }
+
+    fn build_search_index(&self) -> SessionIndex {
+        SessionIndex::from_manifest(&self.manifest, Some(self.timeline_end_us()))
+    }
+
+    /// Sum up the stopped recording in a report signed with `key`, for the
+    /// server to check before it marks the recording synced
+    pub fn integrity_report(
+        &self,
+        key: &[u8; INTEGRITY_KEY_LEN],
+    ) -> Result<SignedIntegrityReport, String> {
+        if self.status != RecorderStatus::Stopped {
+            return Err(format!(
+                "Cannot create integrity report in state: {}",
+                self.status.as_str()
+            ));
+        }
+        IntegrityReport::new(&self.manifest, self.quality_report(), self.stats())?.sign(key)
+    }
+
+    /// Enter or leave low-power mode, e.g. when the page is hidden or shown
+    ///
+    /// Leaving it emits the chunks held back for batching and catches up on
+    /// frame-rate statistics.
+    pub fn set_low_power(&mut self, enabled: bool) -> Result<(), String> {
+        if self.status == RecorderStatus::Stopped {
+            return Err("Cannot change power mode of a stopped recorder".to_string());
+        }
+        if enabled == self.low_power() {
+            return Ok(());
+        }
+        log_event!(
+            LogLevel::Info,
+            "Recorder power mode changed",
+            session = self.manifest.session_id,
+            low_power = enabled,
+        );
+        let fragment_duration_ms = self.muxer.config().fragment_duration_ms;
+        if enabled {
+            self.low_power_fragment_ms = Some(fragment_duration_ms);
+            self.set_fragment_duration_ms(fragment_duration_ms);
+            return Ok(());
+        }
+        let requested = self
+            .low_power_fragment_ms
+            .take()
+            .unwrap_or(fragment_duration_ms);
+        self.set_fragment_duration_ms(requested);
+        self.catch_up_frame_rate();
+        self.reset_watchdog();
+        self.collect_segments()
+    }
+
+    /// Whether low-power mode is on
+    pub fn low_power(&self) -> bool {
+        self.low_power_fragment_ms.is_some()
+    }
+
+    /// Stop recording a track until `enable_track()`, e.g. while the user
+    /// mutes their microphone
+    ///
+    /// Keep pushing frames: audio is recorded as silence so the tracks stay
+    /// in sync, while video frames are dropped and players hold the last
+    /// picture. The muted stretch is added to the manifest's `muted` ranges
+    /// when the track is enabled again or recording stops. Frames already
+    /// handed to the recorder are not affected.
+    pub fn disable_track(&mut self, track: TrackKind) -> Result<(), String> {
+        if self.status == RecorderStatus::Stopped {
+            return Err(format!(
+                "Cannot disable a track in state: {}",
+                self.status.as_str()
+            ));
+        }
+        match track {
+            TrackKind::Audio if self.muxer.has_audio() => {
+                silent_aac_frame(self.muxer.config())?;
+            }
+            TrackKind::Video if self.muxer.has_video() => {}
+            _ => return Err(format!("Cannot disable track: {}", track)),
+        }
+        self.disabled.entry(track).or_default().enabling = false;
+        Ok(())
+    }
+
+    /// Record a track disabled with `disable_track()` again
+    ///
+    /// Video resumes with the next keyframe, which `keyframe_due()` asks for.
+    pub fn enable_track(&mut self, track: TrackKind) {
+        if track == TrackKind::Video {
+            if let Some(muted) = self.disabled.get_mut(&track) {
+                muted.enabling = true;
+            }
+        } else if let Some(muted) = self.disabled.remove(&track) {
+            self.end_muted_range(track, muted);
+        }
+    }
+
+    /// Whether frames pushed on `track` are recorded
+    pub fn track_enabled(&self, track: TrackKind) -> bool {
+        !self.disabled.contains_key(&track)
+    }
+
+    fn end_muted_range(&mut self, track: TrackKind, muted: MutedTrack) {
+        if let Some((start_us, end_us)) = muted.range {
+            log_event!(
+                LogLevel::Info,
+                "Track was muted",
+                track = track.as_str(),
+                start_us = start_us,
+                duration_us = end_us - start_us,
+            );
+            self.manifest.muted.push(MutedRange {
+                track,
+                start_us,
+                end_us,
+            });
+        }
+    }
+
+    /// Whether the next video frame handed to the encoder must be a keyframe
+    ///
+    /// Call once per frame, in encode order, before encoding it. Keyframes are
+    /// requested on the frames that will start a fragment, and on the first
+    /// frame after start/resume.
+    pub fn keyframe_due(&mut self, timestamp_us: u64) -> bool {
+        if self.status != RecorderStatus::Recording || !self.muxer.has_video() {
+            return false;
+        }
+        let offset = if self.resync_pending {
+            self.resynced_offset(timestamp_us)
+        } else {
+            self.pause_offset_us
+        };
+        let due = self
+            .keyframes
+            .next_frame(self.to_output(timestamp_us, offset));
+        due || self.awaiting_keyframe
+            || self
+                .disabled
+                .get(&TrackKind::Video)
+                .is_some_and(|m| m.enabling)
+    }
+
+    /// Push an encoded video frame (AVCC, timestamp in microseconds)
+    ///
+    /// Returns false if the frame was dropped (paused, or waiting for a keyframe
+    /// after start/resume).
+    pub fn push_video(
+        &mut self,
+        data: &[u8],
+        timestamp_us: u64,
+        is_keyframe: bool,
+    ) -> Result<bool, String> {
+        if !self.accepting_frames()? {
+            return Ok(false);
+        }
+        self.note_push(TrackKind::Video);
+        if let Some(mut muted) = self.disabled.get(&TrackKind::Video).copied() {
+            let ts = self.output_timestamp(timestamp_us);
+            if !(muted.enabling && is_keyframe) {
+                muted.note(ts, ts + self.last_video_delta_us);
+                self.disabled.insert(TrackKind::Video, muted);
+                return Ok(false);
+            }
+            // The keyframe ends the held picture
+            self.disabled.remove(&TrackKind::Video);
+            if let Some(range) = muted.range.as_mut() {
+                range.1 = ts;
+            }
+            self.end_muted_range(TrackKind::Video, muted);
+        }
+        if self.awaiting_keyframe {
+            if !is_keyframe {
+                log_event!(
+                    LogLevel::Debug,
+                    "Dropped video frame while waiting for a keyframe",
+                    timestamp_us = timestamp_us,
+                );
+                self.quality.dropped_frames += 1;
+                return Ok(false);
+            }
+            self.awaiting_keyframe = false;
+        }
+
+        let ts = self.output_timestamp(timestamp_us);
+        self.log_frame(WalFrame::Video {
+            timestamp_us: ts,
+            is_keyframe,
+            data: data.to_vec(),
+        });
+        if !self.mux_video(data, ts, is_keyframe)? {
+            return Ok(false);
+        }
+        if is_keyframe {
+            self.tap_keyframe(data, ts);
+            self.last_keyframe_data = Some(data.to_vec());
+        }
+        Ok(true)
+    }
+
+    /// Push an encoded audio frame (raw AAC, timestamp/duration in microseconds)
+    ///
+    /// Returns false if the frame was dropped because the recorder is paused.
+    pub fn push_audio(
+        &mut self,
+        data: &[u8],
+        timestamp_us: u64,
+        duration_us: u32,
+    ) -> Result<bool, String> {
+        if !self.accepting_frames()? {
+            return Ok(false);
+        }
+        self.note_push(TrackKind::Audio);
+
+        let ts = self.output_timestamp(timestamp_us);
+        let data = match self.disabled.get_mut(&TrackKind::Audio) {
+            Some(muted) => {
+                muted.note(ts, ts + duration_us as u64);
+                silent_aac_frame(self.muxer.config())?
+            }
+            None => data,
+        };
+        self.log_frame(WalFrame::Audio {
+            timestamp_us: ts,
+            duration_us,
+            data: data.to_vec(),
+        });
+        if !self.mux_audio(data, ts, duration_us)? {
+            return Ok(false);
+        }
+        if let Some(adts) = &self.adts {
+            self.events.push(RecorderEvent::AdtsFrame {
+                timestamp_us: ts,
+                data: adts.wrap(data)?,
+            });
+        }
+        if let Some(detector) = self
+            .silence
+            .as_mut()
+            .filter(|detector| detector.config().from_aac_frames)
+        {
+            let change = detector.push_aac_frame(ts, duration_us as u64, data.len());
+            self.push_silence_change(change);
+        }
+        Ok(true)
+    }
+
+    /// Mux a slate already in output time: `keyframe` from `start_us` for
+    /// `duration_us`, in fragments of its own
+    fn mux_slate(
+        &mut self,
+        keyframe: &[u8],
+        start_us: u64,
+        duration_us: u64,
+    ) -> Result<(), String> {
+        let end_us = start_us + duration_us;
+        let silence = match self.muxer.has_audio() {
+            true => Some(silent_aac_frame(self.muxer.config())?),
+            false => None,
+        };
+        self.muxer.force_flush()?;
+
+        let fragment_us = self.muxer.config().fragment_duration_ms.max(1) as u64 * 1000;
+        let frames = duration_us.div_ceil(fragment_us);
+        let frame_us = |i: u64| start_us + duration_us * i / frames;
+        for i in 0..frames {
+            let (ts, next) = (frame_us(i), frame_us(i + 1));
+            self.muxer.push_video_chunk_full(
+                keyframe,
+                ts,
+                ts,
+                Some((next - ts) as u32),
+                FRAME_FLAG_KEYFRAME,
+            )?;
+        }
+        if self.note_skipped_frames() {
+            return Err("The slate keyframe was skipped".to_string());
+        }
+        self.last_video_us = Some(frame_us(frames - 1));
+        self.last_video_delta_us = end_us - frame_us(frames - 1);
+
+        if let Some(silence) = silence {
+            // Whole frames only, so audio does not run past the picture
+            let rate = self
+                .muxer
+                .config()
+                .audio_sample_rate
+                .unwrap_or(48000)
+                .max(1) as u64;
+            let audio_start = self.last_audio_end_us.unwrap_or(start_us).max(start_us);
+            let frames = (end_us - audio_start) * rate / 1_000_000 / SLATE_AUDIO_FRAME_SAMPLES;
+            let frame_us = |n: u64| audio_start + n * SLATE_AUDIO_FRAME_SAMPLES * 1_000_000 / rate;
+            for n in 0..frames {
+                let (ts, next) = (frame_us(n), frame_us(n + 1));
+                self.muxer
+                    .push_audio_chunk(silence, ts, (next - ts) as u32)?;
+                self.last_audio_end_us = Some(next);
+            }
+        }
+        // Frames after the slate start a fragment of their own
+        self.muxer.force_flush()?;
+
+        for (timestamp_us, label) in [(start_us, SLATE_START_LABEL), (end_us, SLATE_END_LABEL)] {
+            self.manifest.add_marker(Marker {
+                timestamp_us,
+                label: Some(label.to_string()),
+                bookmark: None,
+            });
+        }
+        self.collect_finished_segments()
+    }
+
+    /// Mux a video frame already in output time
+    ///
+    /// Returns false if the muxer skipped it as empty or oversized.
+    fn mux_video(&mut self, data: &[u8], ts: u64, is_keyframe: bool) -> Result<bool, String> {
+        self.muxer.push_video_chunk(data, ts, is_keyframe)?;
+        if self.note_skipped_frames() {
+            return Ok(false);
+        }
+        if self.low_power() {
+            self.deferred_frame_times.push(ts);
+        } else {
+            self.frame_rate.push(ts);
+        }
+
+        if let Some(last) = self.last_video_us {
+            if ts.saturating_sub(last) > MIN_GAP_US {
+                // The gap starts when the next frame was due
+                self.note_gap(TrackKind::Video, last + self.last_video_delta_us, ts);
+            }
+            self.last_video_delta_us = ts.saturating_sub(last);
+        }
+        self.last_video_us = Some(ts);
+        self.collect_finished_segments()?;
+        Ok(true)
+    }
+
+    /// Mux an audio frame already in output time
+    ///
+    /// Returns false if the muxer skipped it as empty or oversized.
+    fn mux_audio(&mut self, data: &[u8], ts: u64, duration_us: u32) -> Result<bool, String> {
+        self.muxer.push_audio_chunk(data, ts, duration_us)?;
+        if self.note_skipped_frames() {
+            return Ok(false);
+        }
+
+        if let Some(end) = self.last_audio_end_us {
+            if ts.saturating_sub(end) > MIN_GAP_US {
+                self.note_gap(TrackKind::Audio, end, ts);
+            }
+        }
+        self.last_audio_end_us = Some(ts + duration_us as u64);
+        self.collect_finished_segments()?;
+        Ok(true)
+    }
+
+    /// Report frames the muxer skipped, returning whether there were any
+    fn note_skipped_frames(&mut self) -> bool {
+        let skipped = self.muxer.take_skipped_frames();
+        let any = !skipped.is_empty();
+        self.events
+            .extend(skipped.into_iter().map(RecorderEvent::FrameSkipped));
+        any
+    }
+
+    /// Record that `track` received no frames from `start_us` to `end_us`
+    ///
+    /// Gaps left by a disabled track are expected and not reported, nor is
+    /// a frame arriving when due after a long one (the last of a slate).
+    fn note_gap(&mut self, track: TrackKind, start_us: u64, end_us: u64) {
+        if end_us <= start_us
+            || self
+                .manifest
+                .muted
+                .iter()
+                .any(|r| r.track == track && r.start_us < end_us && r.end_us > start_us)
+        {
+            return;
+        }
+        log_event!(
+            LogLevel::Warn,
+            "Gap in recorded media",
+            session = self.manifest.session_id,
+            track = track.as_str(),
+            start_us = start_us,
+            duration_us = end_us.saturating_sub(start_us),
+        );
+        self.quality.add_gap(MediaGap {
+            start_us: start_us.min(end_us),
+            end_us,
+        });
+    }
+
+    /// Collect segments after a push; batched in low-power mode, and
+    /// hashed `HASH_SLICE_BYTES` per push with `HashStrategy::Incremental`
+    fn collect_finished_segments(&mut self) -> Result<(), String> {
+        let batching = self.low_power()
+            && !self.muxer.config().low_memory()
+            && self.muxer.stats().pending_segment_count < LOW_POWER_CHUNK_BATCH;
+        if !batching {
+            self.take_segments()?;
+        }
+        self.hash_chunks(HASH_SLICE_BYTES)
+    }
+
+    /// Feed the frame-rate estimator with the timestamps held back in
+    /// low-power mode
+    fn catch_up_frame_rate(&mut self) {
+        for ts in std::mem::take(&mut self.deferred_frame_times) {
+            self.frame_rate.push(ts);
+        }
+    }
+
+    /// Feed the stall watchdog with a push on `track`
+    fn note_push(&mut self, track: TrackKind) {
+        if self.low_power() {
+            return;
+        }
+        let Some(watchdog) = self.watchdog.as_mut() else {
+            return;
+        };
+        let now = self.clock.now_ms();
+        let mut changes: Vec<StallChange> = watchdog.note_push(track, now).into_iter().collect();
+        changes.extend(watchdog.check(now));
+        self.push_stall_changes(changes);
+    }
+
+    fn reset_watchdog(&mut self) {
+        if let Some(watchdog) = self.watchdog.as_mut() {
+            watchdog.reset(self.clock.now_ms());
+        }
+    }
+
+    fn push_silence_change(&mut self, change: Option<SilenceChange>) {
+        match change {
+            Some(SilenceChange::Started { start_us }) => {
+                self.events.push(RecorderEvent::SilenceStarted { start_us });
+            }
+            Some(SilenceChange::Ended(range)) => {
+                for (timestamp_us, label) in [
+                    (range.start_us, SILENCE_START_LABEL),
+                    (range.end_us, SILENCE_END_LABEL),
+                ] {
+                    self.manifest.add_marker(Marker {
+                        timestamp_us,
+                        label: Some(label.to_string()),
+                        bookmark: None,
+                    });
+                }
+                self.events.push(RecorderEvent::SilenceEnded(range));
+            }
+            None => {}
+        }
+    }
+
+    fn push_stall_changes(&mut self, changes: Vec<StallChange>) {
+        self.events
+            .extend(changes.into_iter().map(|change| match change {
+                StallChange::Stalled { track, stalled_ms } => {
+                    log_event!(
+                        LogLevel::Warn,
+                        "Stream stalled",
+                        track = track,
+                        stalled_ms = stalled_ms,
+                    );
+                    RecorderEvent::StreamStalled { track, stalled_ms }
+                }
+                StallChange::Recovered { track, stalled_ms } => {
+                    log_event!(
+                        LogLevel::Info,
+                        "Stream recovered",
+                        track = track,
+                        stalled_ms = stalled_ms,
+                    );
+                    RecorderEvent::StreamRecovered { track, stalled_ms }
+                }
+            }));
+    }
+
+    /// Queue a `Thumbnail` event for every Nth keyframe, if enabled and not
+    /// in low-power mode
+    fn tap_keyframe(&mut self, data: &[u8], ts: u64) {
+        let Some(interval) = self.thumbnail_interval.filter(|_| !self.low_power()) else {
+            return;
+        };
+        if self.keyframe_count.is_multiple_of(interval as u64) {
+            self.events.push(RecorderEvent::Thumbnail {
+                timestamp_us: ts,
+                data: data.to_vec(),
+            });
+        }
+        self.keyframe_count += 1;
+    }
+
+    /// Append a frame to the write-ahead log, if enabled
+    fn log_frame(&mut self, frame: WalFrame) {
+        if let Some(batch) = self.wal.as_mut().and_then(|wal| wal.append(&frame)) {
+            self.events.push(RecorderEvent::WalBatch(batch));
+        }
+    }
+
+    /// Check the lifecycle state for a push; paused drops frames silently
+    fn accepting_frames(&self) -> Result<bool, String> {
+        match self.status {
+            RecorderStatus::Recording => Ok(true),
+            RecorderStatus::Paused => Ok(false),
+            RecorderStatus::Idle | RecorderStatus::Stopped => Err(format!(
+                "Recorder is not recording (state: {})",
+                self.status.as_str()
+            )),
+        }
+    }
+
+    /// Map an input timestamp to output time, closing the gap left by a pause
+    fn output_timestamp(&mut self, timestamp_us: u64) -> u64 {
+        if self.resync_pending {
+            self.resync_pending = false;
+            self.pause_offset_us = self.resynced_offset(timestamp_us);
+        }
+        self.to_output(timestamp_us, self.pause_offset_us)
+    }
+
+    /// Output time of an input timestamp under a given pause offset
+    fn to_output(&self, timestamp_us: u64, offset: u64) -> u64 {
+        timestamp_us.saturating_sub(offset) + self.timeline_base_us
+    }
+
+    /// Pause offset that makes `timestamp_us` continue right after the last frame
+    fn resynced_offset(&self, timestamp_us: u64) -> u64 {
+        let ts = self.to_output(timestamp_us, self.pause_offset_us);
+        self.pause_offset_us + ts.saturating_sub(self.timeline_end_us())
+    }
+
+    /// End of the last muxed frame in output time
+    fn timeline_end_us(&self) -> u64 {
+        let video_end = self
+            .last_video_us
+            .map(|v| v + self.last_video_delta_us)
+            .unwrap_or(0);
+        video_end
+            .max(self.last_audio_end_us.unwrap_or(0))
+            .max(self.timeline_base_us)
+    }
+
+    /// Turn finished muxer segments into chunks and queue ChunkReady events,
+    /// hashing them all now whatever the hash strategy
+    fn collect_segments(&mut self) -> Result<(), String> {
+        self.take_segments()?;
+        self.hash_chunks(usize::MAX)
+    }
+
+    /// Take finished muxer segments to be hashed, mapping and encrypting
+    /// them
+    fn take_segments(&mut self) -> Result<(), String> {
+        if !self.muxer.has_pending_segments() {
+            return Ok(());
+        }
+        let end_us = self.timeline_end_us();
+        let chunks = self.muxer.take_unhashed_chunks(&self.manifest.session_id);
+        for RecordedChunk { mut metadata, data } in chunks {
+            log_event!(
+                LogLevel::Debug,
+                "Chunk ready",
+                chunk = metadata.chunk_id,
+                size = metadata.size,
+                timestamp_us = metadata.timestamp_us,
+            );
+            if let Some(range_map) = self.range_map.as_mut() {
+                let mapped = range_map.push_chunk(&metadata.chunk_id, &data)?;
+                self.manifest.fragments.extend(mapped.fragments);
+                for keyframe in &mapped.keyframes {
+                    self.manifest.locate_bookmarks(keyframe);
+                }
+                if let Some(keyframe) = mapped.keyframes.last() {
+                    self.last_keyframe = Some(keyframe.clone());
+                }
+            }
+            let sequence = metadata.chunk_id.sequence;
+            let key_uri = self.encryptor.as_ref().map(|encryptor| {
+                let key_uri = encryptor.key_uri();
+                if self.manifest.keys.last().map(|k| k.key_uri.as_str()) != Some(key_uri) {
+                    self.manifest.keys.push(KeyPeriod {
+                        first_sequence: sequence,
+                        key_uri: key_uri.to_string(),
+                    });
+                }
+                key_uri.to_string()
+            });
+            let (data, job) = match self.hash_strategy {
+                HashStrategy::Background => {
+                    match SegmentJob::spawn(data, self.encryptor.clone(), sequence) {
+                        Ok(job) => (Vec::new(), Some(job)),
+                        Err(data) => (data, None),
+                    }
+                }
+                _ => (data, None),
+            };
+            let data = match (&self.encryptor, &job) {
+                (Some(encryptor), None) => {
+                    let data = encryptor.encrypt(sequence, &data);
+                    metadata.size = data.len() as u64;
+                    data
+                }
+                _ => data,
+            };
+            self.hashing.push_back(HashingChunk {
+                metadata,
+                data,
+                key_uri,
+                end_us,
+                hash: IncrementalHash::default(),
+                job,
+                job_hash: None,
+            });
+        }
+        Ok(())
+    }
+
+    /// Hash the waiting chunks in order and emit the finished ones
+    ///
+    /// Only `HashStrategy::Incremental` stops after `budget_bytes`, and
+    /// `HashStrategy::Background` at a job still running unless the budget
+    /// is unlimited (then waiting for it); the other strategies hash every
+    /// waiting chunk at once.
+    fn hash_chunks(&mut self, mut budget_bytes: usize) -> Result<(), String> {
+        while let Some(mut chunk) = self.hashing.pop_front() {
+            if let Some(job) = chunk.job.as_ref() {
+                if budget_bytes != usize::MAX {
+                    match job.try_finish()? {
+                        Some(finished) => chunk.job_finished(finished),
+                        None => {
+                            self.hashing.push_front(chunk);
+                            return Ok(());
+                        }
+                    }
+                }
+            } else if self.hash_strategy == HashStrategy::Incremental {
+                budget_bytes -= chunk.hash.advance(&chunk.data, budget_bytes);
+                if !chunk.hash.is_done(&chunk.data) {
+                    self.hashing.push_front(chunk);
+                    return Ok(());
+                }
+            }
+            let hash = chunk.finish_hash(self.hash_strategy)?;
+            self.emit_chunk(chunk, Some(hash))?;
+        }
+        while let Some(mut chunk) = self.unhashed.pop_front() {
+            budget_bytes =
+                budget_bytes.saturating_sub(chunk.hash.advance(&chunk.data, budget_bytes));
+            if !chunk.hash.is_done(&chunk.data) {
+                self.unhashed.push_front(chunk);
+                return Ok(());
+            }
+            let hash = chunk.hash.finish();
+            if let Some(metadata) = self
+                .manifest
+                .chunks
+                .iter_mut()
+                .find(|c| c.chunk_id == chunk.chunk_id)
+            {
+                metadata.hash = Some(hash);
+                self.events
+                    .push(RecorderEvent::ChunkHashed(metadata.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// List a chunk in the manifest and queue its ChunkReady event
+    fn emit_chunk(&mut self, chunk: HashingChunk, hash: Option<String>) -> Result<(), String> {
+        let HashingChunk {
+            mut metadata,
+            data,
+            key_uri,
+            end_us,
+            ..
+        } = chunk;
+        metadata.hash = hash;
+        self.manifest.add_chunk(metadata.clone())?;
+        self.muxer.acknowledge_segment(metadata.chunk_id.sequence);
+        if let Some(preview) = self.preview.as_mut() {
+            preview.push_segment(PreviewSegment {
+                chunk_id: metadata.chunk_id.clone(),
+                timestamp_us: metadata.timestamp_us,
+                duration_us: end_us.saturating_sub(metadata.timestamp_us),
+                data: data.clone(),
+                key_uri,
+            });
+        }
+        self.events
+            .push(RecorderEvent::ChunkReady(RecordedChunk { metadata, data }));
+        Ok(())
+    }
+
+    fn set_status(&mut self, to: RecorderStatus) {
+        let from = self.status;
+        log_event!(
+            LogLevel::Info,
+            "Recorder state changed",
+            session = self.manifest.session_id,
+            from = from.as_str(),
+            to = to.as_str(),
+        );
+        self.status = to;
+        self.events.push(RecorderEvent::StateChanged { from, to });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::encryption::decrypt_segment;
+    use crate::muxide_muxer::MemoryProfile;
+
+    fn video_config() -> MuxideConfig {
+        MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E, 0xD9, 0x00, 0x50, 0x05]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            ..Default::default()
+        }
+    }
+
+    fn frame() -> Vec<u8> {
+        vec![0x00, 0x00, 0x00, 0x04, 0x65, 0x88, 0x84, 0x00]
+    }
+
+    fn chunks(events: &[RecorderEvent]) -> Vec<&RecordedChunk> {
+        events
+            .iter()
+            .filter_map(|e| match e {
+                RecorderEvent::ChunkReady(chunk) => Some(chunk),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The last init segment that replaced the one returned by `start()`
+    fn replaced_init(events: &[RecorderEvent]) -> Option<Vec<u8>> {
+        events.iter().rev().find_map(|e| match e {
+            RecorderEvent::AudioConfigChanged { init_segment, .. }
+            | RecorderEvent::InitSegmentChanged(init_segment) => Some(init_segment.clone()),
+            _ => None,
+        })
+    }
+
+    /// Swap the init segment at the start of `file` for a replacement
+    fn replace_init(file: &mut Vec<u8>, init_len: usize, events: &[RecorderEvent]) {
+        if let Some(init) = replaced_init(events) {
+            file.splice(..init_len, init);
+        }
+    }
+
+    #[test]
+    fn test_incremental_hashing() {
+        let config = MuxideConfig {
+            fragment_duration_ms: 1000,
+            ..video_config()
+        };
+        let mut recorder = RecorderState::new(SessionId::from("s1"), config);
+        recorder
+            .set_hash_strategy(HashStrategy::Incremental)
+            .unwrap();
+        recorder.start().unwrap();
+        recorder.take_events();
+
+        // 200 KB frames: the first 1 s fragment is about 5 MB
+        let mut frame = vec![0x41; 200_000];
+        frame[..4].copy_from_slice(&199_996u32.to_be_bytes());
+        for i in 0..30u64 {
+            frame[4] = if i % 25 == 0 { 0x65 } else { 0x41 };
+            recorder
+                .push_video(&frame, i * 40_000, i % 25 == 0)
+                .unwrap();
+        }
+        assert_eq!(recorder.stats().segment_count, 1);
+        assert!(chunks(&recorder.take_events()).is_empty());
+        assert_eq!(recorder.manifest().chunk_count(), 0);
+        assert!(recorder.snapshot().is_err());
+
+        assert!(recorder.pump_hashes(HASH_SLICE_BYTES).unwrap());
+        assert!(!recorder.pump_hashes(usize::MAX).unwrap());
+        let events = recorder.take_events();
+        let ready = chunks(&events);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(
+            ready[0].metadata.hash.as_deref(),
+            Some(blake3::hash(&ready[0].data).to_hex().as_str())
+        );
+        assert_eq!(recorder.manifest().chunks[0], ready[0].metadata);
+        assert!(recorder.snapshot().is_ok());
+
+        // Stopping hashes what is left at once
+        recorder.stop().unwrap();
+        let events = recorder.take_events();
+        let ready = chunks(&events);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].metadata.chunk_id.sequence, 1);
+        assert!(ready[0].metadata.hash.is_some());
+        assert!(matches!(
+            events.last(),
+            Some(RecorderEvent::StateChanged {
+                to: RecorderStatus::Stopped,
+                ..
+            })
+        ));
+    }
+
+    #[cfg(feature = "wasm-threads")]
+    #[test]
+    fn test_background_hashing() {
+        crate::threads::start_pool(2, |thread| {
+            std::thread::Builder::new().spawn(|| thread.run()).map(drop)
+        })
+        .unwrap();
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        recorder
+            .set_hash_strategy(HashStrategy::Background)
+            .unwrap();
+        recorder
+            .enable_segment_encryption(SegmentKey {
+                uri: "k1".into(),
+                key: vec![1; 16],
+            })
+            .unwrap();
+        recorder.start().unwrap();
+        // 5 seconds at 30fps, 2-second fragments
+        for i in 0..150u64 {
+            recorder
+                .push_video(&frame(), i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+        assert_eq!(recorder.stats().segment_count, 2);
+        while recorder.pump_hashes(HASH_SLICE_BYTES).unwrap() {
+            std::thread::yield_now();
+        }
+        assert_eq!(recorder.manifest().chunk_count(), 2);
+        assert_eq!(recorder.manifest().keys.len(), 1);
+
+        // Stopping waits for the last job
+        recorder.stop().unwrap();
+        let events = recorder.take_events();
+        let ready = chunks(&events);
+        assert_eq!(ready.len(), 3);
+        for (sequence, chunk) in ready.iter().enumerate() {
+            assert_eq!(chunk.metadata.chunk_id.sequence, sequence as u64);
+            assert_eq!(chunk.metadata.size, chunk.data.len() as u64);
+            assert_eq!(
+                chunk.metadata.hash.as_deref(),
+                Some(blake3::hash(&chunk.data).to_hex().as_str())
+            );
+            let plain = decrypt_segment(&[1; 16], sequence as u64, &chunk.data).unwrap();
+            assert_eq!(&plain[4..8], b"moof");
+        }
+        assert_eq!(recorder.manifest().chunks[2], ready[2].metadata);
+    }
+
+    #[test]
+    fn test_emergency_flush() {
+        let clock = ManualClock::new(1_700_000_000_000);
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        recorder.set_clock(ClockHandle::new(clock.clone()));
+        recorder
+            .set_hash_strategy(HashStrategy::Incremental)
+            .unwrap();
+        recorder.enable_wal(60_000).unwrap();
+        assert_eq!(recorder.emergency_flush(100).unwrap(), None);
+        recorder.start().unwrap();
+        for i in 0..10u64 {
+            recorder.push_video(&frame(), i * 33_333, i == 0).unwrap();
+        }
+        recorder.take_events();
+
+        // The open fragment and the logged frames go out at once
+        let tail = recorder.emergency_flush(100).unwrap().unwrap();
+        let events = recorder.take_events();
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, RecorderEvent::WalBatch(_))));
+        let ready = chunks(&events);
+        assert_eq!(ready.len(), 1);
+        assert!(ready[0].metadata.hash.is_some());
+        assert_eq!(tail.timestamp_us, 10 * 33_333);
+        assert_eq!(tail.chunk_count, 1);
+        assert_eq!(tail.flushed_at_ms, 1_700_000_000_000);
+        assert_eq!(recorder.manifest().tail, Some(tail));
+        assert!(recorder.snapshot().is_ok());
+
+        // The page survived: recording goes on; without budget left the
+        // chunks are not hashed
+        for i in 10..20u64 {
+            recorder.push_video(&frame(), i * 33_333, false).unwrap();
+        }
+        clock.advance(1_000);
+        let tail = recorder.emergency_flush(0).unwrap().unwrap();
+        let events = recorder.take_events();
+        let ready = chunks(&events);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].metadata.hash, None);
+        assert_eq!(ready[0].metadata.timestamp_us, 10 * 33_333);
+        assert_eq!(
+            (tail.chunk_count, tail.flushed_at_ms),
+            (2, 1_700_000_001_000)
+        );
+
+        // ...until the recorder gets to it, filling in the manifest entry
+        let id = ready[0].metadata.chunk_id.clone();
+        let expected = blake3::hash(&ready[0].data).to_hex().to_string();
+        assert!(recorder.pump_hashes(1).unwrap());
+        assert!(recorder.take_events().is_empty());
+        assert!(!recorder.pump_hashes(usize::MAX).unwrap());
+        let events = recorder.take_events();
+        assert!(matches!(
+            &events[..],
+            [RecorderEvent::ChunkHashed(metadata)] if metadata.chunk_id == id
+        ));
+        assert_eq!(recorder.manifest().get(&id).unwrap().hash, Some(expected));
+
+        recorder.stop().unwrap();
+        assert_eq!(recorder.manifest().tail, None);
+        assert_eq!(recorder.emergency_flush(100).unwrap(), None);
+    }
+
+    #[test]
+    fn test_recorder_lifecycle_and_chunks() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        let init = recorder.start().unwrap();
+        assert_eq!(&init[4..8], b"ftyp");
+
+        // 3 seconds at 30fps with a keyframe every second
+        for i in 0..90u64 {
+            recorder
+                .push_video(&frame(), i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+        assert!((recorder.current_fps().unwrap() - 30.0).abs() < 0.01);
+        recorder.stop().unwrap();
+
+        // The measured frame rate is stored with the recording
+        let framerate = recorder
+            .manifest()
+            .metadata
+            .as_ref()
+            .unwrap()
+            .framerate
+            .as_ref();
+        assert_eq!(framerate.unwrap().frame_count, 90);
+        assert!(!framerate.unwrap().variable);
+
+        let events = recorder.take_events();
+        let chunks = chunks(&events);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].metadata.chunk_id.sequence, 0);
+        assert_eq!(chunks[1].metadata.chunk_id.sequence, 1);
+        assert_eq!(chunks[0].metadata.timestamp_us, 0);
+        assert_eq!(
+            chunks[0].metadata.hash.as_deref(),
+            Some(blake3::hash(&chunks[0].data).to_hex().as_str())
+        );
+
+        // The stopped state change comes after the final chunk
+        assert_eq!(
+            events.last(),
+            Some(&RecorderEvent::StateChanged {
+                from: RecorderStatus::Recording,
+                to: RecorderStatus::Stopped,
+            })
+        );
+        assert_eq!(recorder.manifest().chunk_count(), 2);
+        assert_eq!(recorder.manifest().state, SessionState::Finalizing);
+    }
+
+    #[test]
+    fn test_recorder_drops_frames_until_keyframe() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        recorder.start().unwrap();
+
+        assert!(!recorder.push_video(&frame(), 0, false).unwrap());
+        assert!(recorder.push_video(&frame(), 33_333, true).unwrap());
+        assert!(recorder.push_video(&frame(), 66_666, false).unwrap());
+        assert_eq!(recorder.stats().video_frame_count, 2);
+    }
+
+    #[test]
+    fn test_recorder_pause_removes_gap() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        recorder.start().unwrap();
+
+        recorder.push_video(&frame(), 0, true).unwrap();
+        recorder.push_video(&frame(), 33_333, false).unwrap();
+        recorder.pause().unwrap();
+        assert!(!recorder.push_video(&frame(), 66_666, false).unwrap());
+        recorder.resume().unwrap();
+
+        // Non-keyframes after resume are dropped until the next keyframe
+        assert!(!recorder.push_video(&frame(), 5_000_000, false).unwrap());
+        assert!(recorder.push_video(&frame(), 5_033_333, true).unwrap());
+
+        // Next frame lands one frame interval after the last pre-pause frame
+        assert_eq!(recorder.pause_offset_us(), 5_033_333 - 66_666);
+    }
+
+    #[test]
+    fn test_recorder_keyframe_due_aligns_chunks_across_pause() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        assert!(!recorder.keyframe_due(0));
+        recorder.start().unwrap();
+
+        let mut keyframe_times = Vec::new();
+        let mut push = |recorder: &mut RecorderState, ts: u64| {
+            let is_keyframe = recorder.keyframe_due(ts);
+            assert!(recorder.push_video(&frame(), ts, is_keyframe).unwrap());
+            if is_keyframe {
+                keyframe_times.push(ts - recorder.pause_offset_us());
+            }
+        };
+        for i in 0..100u64 {
+            push(&mut recorder, i * 33_367);
+        }
+        recorder.pause().unwrap();
+        assert!(!recorder.keyframe_due(4_000_000));
+        recorder.resume().unwrap();
+        for i in 0..100u64 {
+            push(&mut recorder, 7_000_000 + i * 33_367);
+        }
+        recorder.stop().unwrap();
+
+        // The first frame after resume is a keyframe, continuing the timeline
+        assert!(keyframe_times.contains(&3_336_700));
+        let events = recorder.take_events();
+        let chunks = chunks(&events);
+        assert!(chunks.len() > 2);
+        for chunk in chunks {
+            assert!(keyframe_times.contains(&chunk.metadata.timestamp_us));
+        }
+    }
+
+    #[test]
+    fn test_recorder_feeds_preview_window() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        recorder.enable_preview(2).unwrap();
+        let init = recorder.start().unwrap();
+        assert!(recorder.enable_preview(2).is_err());
+
+        // 7 seconds at 30fps, 2-second fragments
+        for i in 0..210u64 {
+            recorder
+                .push_video(&frame(), i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+        let preview = recorder.preview().unwrap();
+        assert_eq!(preview.init_segment(), Some(init.as_slice()));
+        let window = preview.window();
+        assert_eq!(
+            window.iter().map(|s| s.sequence).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            window[1].timestamp_us,
+            window[0].timestamp_us + window[0].duration_us
+        );
+        assert!(!preview.hls_playlist("").contains("#EXT-X-ENDLIST"));
+
+        recorder.stop().unwrap();
+        let preview = recorder.preview().unwrap();
+        assert_eq!(preview.window().last().unwrap().sequence, 3);
+        assert!(preview.hls_playlist("").ends_with("#EXT-X-ENDLIST\n"));
+    }
+
+    #[test]
+    fn test_recorder_previews_audio_only_session() {
+        let mut recorder = RecorderState::new(
+            SessionId::from("s1"),
+            MuxideConfig {
+                sps: None,
+                pps: None,
+                audio_sample_rate: Some(48000),
+                audio_channels: Some(1),
+                ..Default::default()
+            },
+        );
+        recorder.enable_preview(3).unwrap();
+        recorder.start().unwrap();
+
+        // 7 seconds of AAC frames, 2-second fragments
+        for i in 0..329u64 {
+            recorder
+                .push_audio(&[0x21, 0x10, i as u8], i * 21_333, 21_333)
+                .unwrap();
+        }
+        recorder.stop().unwrap();
+
+        let preview = recorder.preview().unwrap();
+        assert!(preview.audio_only());
+        let window = preview.window();
+        assert_eq!(
+            window.iter().map(|s| s.sequence).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        for segment in &window[..2] {
+            assert!(segment.duration_us.abs_diff(2_000_000) < 21_333);
+        }
+        let playlist = preview.hls_playlist("");
+        assert!(playlist.contains("#EXT-X-INDEPENDENT-SEGMENTS\n"));
+        assert!(playlist.ends_with("chunk-00000003.fmp4\n#EXT-X-ENDLIST\n"));
+        assert!(preview
+            .hls_multivariant_playlist("audio.m3u8")
+            .contains(",CODECS=\"mp4a.40.2\"\naudio.m3u8\n"));
+    }
+
+    #[test]
+    fn test_recorder_encrypts_segments() {
+        let key = |uri: &str, byte: u8| SegmentKey {
+            uri: uri.to_string(),
+            key: vec![byte; 16],
+        };
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        assert!(recorder.rotate_encryption_key(key("k0", 0)).is_err());
+        assert!(recorder.enable_segment_encryption(key("k0", 0)).is_ok());
+        recorder.enable_segment_encryption(key("k1", 1)).unwrap();
+        recorder.enable_preview(4).unwrap();
+        let init = recorder.start().unwrap();
+        assert_eq!(&init[4..8], b"ftyp");
+
+        // 7 seconds at 30fps, 2-second fragments; the key changes after 2 chunks
+        for i in 0..210u64 {
+            if i == 130 {
+                recorder.rotate_encryption_key(key("k2", 2)).unwrap();
+            }
+            recorder
+                .push_video(&frame(), i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+        recorder.stop().unwrap();
+
+        let manifest = recorder.manifest().clone();
+        assert_eq!(
+            manifest
+                .keys
+                .iter()
+                .map(|k| (k.first_sequence, k.key_uri.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(0, "k1"), (2, "k2")]
+        );
+        let events = recorder.take_events();
+        let chunks = chunks(&events);
+        assert_eq!(chunks.len(), 4);
+        for chunk in chunks {
+            let sequence = chunk.metadata.chunk_id.sequence;
+            let key_byte = if sequence < 2 { 1 } else { 2 };
+            assert_eq!(
+                manifest.key_for(sequence).unwrap().key_uri,
+                format!("k{}", key_byte)
+            );
+            assert_eq!(chunk.metadata.size, chunk.data.len() as u64);
+            assert_eq!(
+                chunk.metadata.hash.as_deref(),
+                Some(blake3::hash(&chunk.data).to_hex().as_str())
+            );
+            assert_ne!(&chunk.data[4..8], b"moof");
+            let plain = decrypt_segment(&[key_byte; 16], sequence, &chunk.data).unwrap();
+            assert_eq!(&plain[4..8], b"moof");
+        }
+
+        let playlist = recorder.preview().unwrap().hls_playlist("");
+        assert_eq!(playlist.matches("#EXT-X-KEY:").count(), 2);
+        let k2 = playlist
+            .find("#EXT-X-KEY:METHOD=AES-128,URI=\"k2\"\n")
+            .unwrap();
+        assert!(playlist[..k2].ends_with("chunk-00000001.fmp4\n"));
+    }
+
+    #[test]
+    fn test_recorder_taps_every_nth_keyframe() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        assert!(recorder.enable_thumbnails(0).is_err());
+        recorder.enable_thumbnails(2).unwrap();
+        recorder.start().unwrap();
+
+        for i in 0..150u64 {
+            recorder
+                .push_video(&frame(), i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+        let thumbnails: Vec<u64> = recorder
+            .take_events()
+            .into_iter()
+            .filter_map(|e| match e {
+                RecorderEvent::Thumbnail { timestamp_us, data } => {
+                    assert_eq!(data, frame());
+                    Some(timestamp_us)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(thumbnails, vec![0, 1_999_980, 3_999_960]);
+    }
+
+    #[test]
+    fn test_recorder_low_power_mode() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        assert!(recorder.set_low_power(true).is_ok());
+        recorder.set_low_power(false).unwrap();
+        recorder.enable_thumbnails(1).unwrap();
+        recorder.start().unwrap();
+        recorder.set_low_power(true).unwrap();
+        assert!(recorder.low_power());
+
+        // 8 s fragments (4 x 2 s), emitted two at a time, no thumbnails
+        for i in 0..750u64 {
+            recorder
+                .push_video(&frame(), i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+        let events = recorder.take_events();
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, RecorderEvent::Thumbnail { .. })));
+        let starts: Vec<u64> = chunks(&events)
+            .iter()
+            .map(|c| c.metadata.timestamp_us)
+            .collect();
+        assert_eq!(starts.len(), 2);
+        assert!(starts[1] >= 7_900_000);
+
+        // Leaving the mode emits the held-back chunk and restores 2 s fragments
+        recorder.set_low_power(false).unwrap();
+        let events = recorder.take_events();
+        assert_eq!(chunks(&events).len(), 1);
+        assert_eq!(recorder.muxer.config().fragment_duration_ms, 2000);
+        for i in 750..900u64 {
+            recorder
+                .push_video(&frame(), i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+        recorder.stop().unwrap();
+        assert_eq!(recorder.frame_rate_stats().unwrap().frame_count, 900);
+        assert!(recorder.set_low_power(true).is_err());
+    }
+
+    #[test]
+    fn test_recorder_low_memory_profile() {
+        let config = MuxideConfig {
+            memory_profile: MemoryProfile::Low,
+            ..video_config()
+        };
+        let mut recorder = RecorderState::new(SessionId::from("s1"), config);
+        recorder.start().unwrap();
+        recorder.set_low_power(true).unwrap();
+
+        // 1 s fragments starting on scheduled keyframes, each emitted as
+        // soon as it is built
+        let mut keyframes = Vec::new();
+        for i in 0..100u64 {
+            let ts = i * 33_333;
+            let is_keyframe = recorder.keyframe_due(ts);
+            if is_keyframe {
+                keyframes.push(ts);
+            }
+            recorder.push_video(&frame(), ts, is_keyframe).unwrap();
+            assert_eq!(recorder.muxer.stats().pending_segment_count, 0);
+        }
+        let starts: Vec<u64> = chunks(&recorder.take_events())
+            .iter()
+            .map(|c| c.metadata.timestamp_us)
+            .collect();
+        assert_eq!(starts.len(), 3);
+        assert_eq!(starts, keyframes[..3]);
+    }
+
+    #[test]
+    fn test_recorder_disables_tracks() {
+        let mut recorder = RecorderState::new(
+            SessionId::from("s1"),
+            MuxideConfig {
+                audio_sample_rate: Some(48000),
+                audio_channels: Some(2),
+                ..video_config()
+            },
+        );
+        assert!(recorder.disable_track(TrackKind::Muxed).is_err());
+        recorder.start().unwrap();
+
+        // Audio muted from 1 s to 2 s
+        for i in 0..141u64 {
+            match i {
+                47 => recorder.disable_track(TrackKind::Audio).unwrap(),
+                94 => recorder.enable_track(TrackKind::Audio),
+                _ => {}
+            }
+            assert!(recorder
+                .push_audio(&[0x21, 0x10, 0x04, 0x60], i * 21_333, 21_333)
+                .unwrap());
+        }
+        // Video disabled at 1.5 s, back with the keyframe requested at 2.2 s
+        for i in 0..90u64 {
+            let ts = i * 33_333;
+            if i == 45 {
+                recorder.disable_track(TrackKind::Video).unwrap();
+                assert!(!recorder.track_enabled(TrackKind::Video));
+            }
+            if i == 66 {
+                recorder.enable_track(TrackKind::Video);
+                assert!(recorder.keyframe_due(ts));
+            }
+            let recorded = recorder
+                .push_video(&frame(), ts, i % 30 == 0 || i == 70)
+                .unwrap();
+            assert_eq!(recorded, !(45..70).contains(&i));
+        }
+        assert!(recorder.track_enabled(TrackKind::Video));
+        recorder.stop().unwrap();
+        assert!(recorder.disable_track(TrackKind::Audio).is_err());
+
+        let manifest = recorder.manifest().clone();
+        assert_eq!(
+            manifest.muted,
+            vec![
+                MutedRange {
+                    track: TrackKind::Audio,
+                    start_us: 1_002_651,
+                    end_us: 2_005_302,
+                },
+                MutedRange {
+                    track: TrackKind::Video,
+                    start_us: 1_499_985,
+                    end_us: 2_333_310,
+                },
+            ]
+        );
+        // The muted audio was recorded as silence; the held picture is no gap
+        let events = recorder.take_events();
+        let file: Vec<u8> = chunks(&events)
+            .iter()
+            .flat_map(|chunk| chunk.data.clone())
+            .collect();
+        let silent = silent_aac_frame(recorder.config()).unwrap();
+        assert_eq!(
+            file.windows(silent.len()).filter(|w| w == &silent).count(),
+            47
+        );
+        let quality = manifest
+            .metadata
+            .as_ref()
+            .unwrap()
+            .quality
+            .as_ref()
+            .unwrap();
+        assert!(quality.gaps.is_empty());
+    }
+
+    #[test]
+    fn test_recorder_inserts_slates() {
+        let mut recorder = RecorderState::new(
+            SessionId::from("s1"),
+            MuxideConfig {
+                audio_sample_rate: Some(48000),
+                audio_channels: Some(2),
+                ..video_config()
+            },
+        );
+        recorder.start().unwrap();
+        assert!(recorder
+            .insert_slate(SlateSource::LastKeyframe, 1000)
+            .is_err());
+        let slate = vec![0x00, 0x00, 0x00, 0x03, 0x65, 0x88, 0x80];
+        // Annex B, a P slice, or a slice referring to another PPS
+        for invalid in [
+            vec![0x00, 0x00, 0x01, 0x65, 0x88, 0x80],
+            vec![0x00, 0x00, 0x00, 0x03, 0x41, 0x88, 0x80],
+            vec![0x00, 0x00, 0x00, 0x03, 0x65, 0x88, 0x40],
+        ] {
+            assert!(recorder
+                .insert_slate(SlateSource::Keyframe(invalid), 3000)
+                .is_err());
+        }
+        recorder
+            .insert_slate(SlateSource::Keyframe(slate.clone()), 3000)
+            .unwrap();
+
+        // The camera's clock starts at 10 s; its first frame follows the slate
+        assert!(recorder.keyframe_due(10_000_000));
+        for i in 0..60u64 {
+            let ts = 10_000_000 + i * 33_333;
+            assert!(recorder.push_video(&frame(), ts, i % 30 == 0).unwrap());
+        }
+        for i in 0..94u64 {
+            let ts = 10_000_000 + i * 21_333;
+            recorder
+                .push_audio(&[0x21, 0x10, 0x04, 0x60], ts, 21_333)
+                .unwrap();
+        }
+        let outro_us = recorder.timeline_end_us();
+        recorder
+            .insert_slate(SlateSource::LastKeyframe, 1000)
+            .unwrap();
+        recorder.stop().unwrap();
+
+        let manifest = recorder.manifest().clone();
+        let slates: Vec<(u64, &str)> = (manifest.markers.iter())
+            .map(|m| (m.timestamp_us, m.label.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            slates,
+            [
+                (0, SLATE_START_LABEL),
+                (3_000_000, SLATE_END_LABEL),
+                (outro_us, SLATE_START_LABEL),
+                (outro_us + 1_000_000, SLATE_END_LABEL),
+            ]
+        );
+        let events = recorder.take_events();
+        let starts: Vec<u64> = (chunks(&events).iter())
+            .map(|chunk| chunk.metadata.timestamp_us)
+            .collect();
+        assert_eq!(starts[0], 0);
+        assert!(starts.contains(&3_000_000), "{:?}", starts);
+        assert!(starts.contains(&outro_us), "{:?}", starts);
+        let file: Vec<u8> = chunks(&events)
+            .iter()
+            .flat_map(|chunk| chunk.data.clone())
+            .collect();
+        assert_eq!(file.windows(slate.len()).filter(|w| w == &slate).count(), 2);
+        let silent = silent_aac_frame(recorder.config()).unwrap();
+        assert_eq!(
+            file.windows(silent.len()).filter(|w| w == &silent).count(),
+            140 + 46
+        );
+        let quality = recorder.quality_report();
+        assert!(quality.gaps.is_empty(), "{:?}", quality.gaps);
+    }
+
+    #[test]
+    fn test_recorder_quality_report() {
+        let mut recorder = RecorderState::new(
+            SessionId::from("s1"),
+            MuxideConfig {
+                audio_sample_rate: Some(48000),
+                audio_channels: Some(2),
+                ..video_config()
+            },
+        );
+        recorder.start().unwrap();
+        assert!(!recorder.push_video(&frame(), 0, false).unwrap());
+        // Video freezes from 0.1 s to 1 s; audio drops out at 43 ms and 1.32 s
+        for ts in [33_333, 66_666, 1_000_000, 1_033_333] {
+            recorder.push_video(&frame(), ts, ts == 33_333).unwrap();
+        }
+        for ts in [0, 21_333, 300_000, 500_000, 700_000, 900_000, 1_100_000] {
+            recorder.push_audio(&[0x21, 0x10], ts, 21_333).unwrap();
+        }
+        for ts in [1_300_000, 1_600_000, 1_600_000] {
+            // The last one is clamped by the muxer
+            recorder.push_audio(&[0x21, 0x10], ts, 21_333).unwrap();
+        }
+        recorder.stop().unwrap();
+
+        let report = recorder
+            .manifest()
+            .metadata
+            .as_ref()
+            .unwrap()
+            .quality
+            .clone()
+            .unwrap();
+        assert_eq!(report, recorder.quality_report());
+        assert_eq!(report.dropped_frames, 1);
+        assert_eq!(report.corrected_frames, 1);
+        assert_eq!(
+            report.gaps,
+            vec![
+                MediaGap {
+                    start_us: 42_666,
+                    end_us: 1_000_000
+                },
+                MediaGap {
+                    start_us: 1_321_333,
+                    end_us: 1_600_000
+                },
+            ]
+        );
+        assert_eq!(report.total_gap_us, 1_236_001);
+    }
+
+    #[test]
+    fn test_recorder_reports_skipped_frames() {
+        let mut recorder = RecorderState::new(
+            SessionId::from("s1"),
+            MuxideConfig {
+                audio_sample_rate: Some(48000),
+                audio_channels: Some(2),
+                max_frame_size: Some(64),
+                ..video_config()
+            },
+        );
+        recorder.start().unwrap();
+        assert!(recorder.push_video(&frame(), 0, true).unwrap());
+        assert!(!recorder.push_video(&[], 33_333, false).unwrap());
+        assert!(!recorder.push_audio(&[0; 65], 0, 21_333).unwrap());
+        let skipped: Vec<SkippedFrame> = (recorder.take_events().into_iter())
+            .filter_map(|event| match event {
+                RecorderEvent::FrameSkipped(frame) => Some(frame),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            skipped
+                .iter()
+                .map(|f| (f.track, f.size))
+                .collect::<Vec<_>>(),
+            [(TrackKind::Video, 0), (TrackKind::Audio, 65)]
+        );
+        recorder.stop().unwrap();
+
+        let report = recorder.quality_report();
+        assert_eq!((report.empty_frames, report.oversized_frames), (1, 1));
+        assert_eq!(report.dropped_frames, 2);
+        assert_eq!(report.largest_frame, frame().len() as u32);
+        assert_eq!(report.max_frame_size, 64);
+    }
+
+    #[test]
+    fn test_recorder_changes_audio_config() {
+        let config = MuxideConfig {
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            ..video_config()
+        };
+        let mut recorder = RecorderState::new(SessionId::from("s1"), config.clone());
+        recorder.enable_wal(250).unwrap();
+        assert!(recorder.change_audio_config(44100, 2, None).is_err());
+        recorder.start().unwrap();
+        let push = |recorder: &mut RecorderState, from: u64, to: u64, audio_us: u32| {
+            for i in from..to {
+                recorder
+                    .push_video(&frame(), i * 33_333, i % 30 == 0)
+                    .unwrap();
+                recorder
+                    .push_audio(&[0x21, 0x10], i * 33_333, audio_us)
+                    .unwrap();
+            }
+        };
+        push(&mut recorder, 0, 45, 21_333);
+        assert!(!recorder.change_audio_config(48000, 2, None).unwrap());
+        assert!(recorder.change_audio_config(44100, 1, None).unwrap());
+        push(&mut recorder, 45, 90, 23_220);
+        recorder.stop().unwrap();
+
+        let events = recorder.take_events();
+        let (change, init) = events
+            .iter()
+            .find_map(|event| match event {
+                RecorderEvent::AudioConfigChanged {
+                    change,
+                    init_segment,
+                } => Some((change, init_segment.clone())),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(change.previous.sample_rate, 48000);
+        assert_eq!(
+            (change.current.sample_rate, change.current.channels),
+            (44100, 1)
+        );
+        assert!(recorder
+            .manifest()
+            .markers
+            .iter()
+            .any(|m| m.timestamp_us == change.timestamp_us
+                && m.label.as_deref() == Some(AUDIO_CONFIG_LABEL)));
+
+        // Fragment offsets point into the file assembled behind the last init,
+        // which also embeds the session metadata
+        let mut file = replaced_init(&events).unwrap();
+        assert!(file.len() > init.len());
+        for chunk in chunks(&events) {
+            file.extend_from_slice(&chunk.data);
+        }
+        assert_eq!(recorder.manifest().fragments.len(), 2);
+        for fragment in &recorder.manifest().fragments {
+            let at = fragment.offset as usize;
+            assert_eq!(&file[at + 4..at + 8], b"moof");
+        }
+        assert_eq!(
+            recorder
+                .manifest()
+                .fragments
+                .last()
+                .map(|f| f.offset + f.size),
+            Some(file.len() as u64)
+        );
+
+        // The change is in the write-ahead log
+        let wal_bytes: Vec<u8> = (events.iter())
+            .filter_map(|event| match event {
+                RecorderEvent::WalBatch(batch) => Some(batch.data.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        let mut recovered =
+            RecorderState::recover(SessionId::from("s1"), config, None, &wal_bytes).unwrap();
+        let recovered_events = recovered.take_events();
+        let recovered_chunks: Vec<&[u8]> = (chunks(&recovered_events).into_iter())
+            .map(|c| &c.data[..])
+            .collect();
+        let original_chunks: Vec<&[u8]> =
+            (chunks(&events).into_iter()).map(|c| &c.data[..]).collect();
+        assert_eq!(recovered_chunks, original_chunks);
+    }
+
+    #[test]
+    fn test_recorder_emits_adts_frames() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        assert!(recorder.enable_adts_output().is_err());
+        let mut recorder = RecorderState::new(
+            SessionId::from("s1"),
+            MuxideConfig {
+                audio_sample_rate: Some(48000),
+                audio_channels: Some(2),
+                ..video_config()
+            },
+        );
+        recorder.enable_adts_output().unwrap();
+        recorder.start().unwrap();
+        recorder.push_audio(&[0x21, 0x10], 0, 21_333).unwrap();
+        recorder.pause().unwrap();
+        recorder.push_audio(&[0x21, 0x10], 21_333, 21_333).unwrap();
+
+        let frames: Vec<(u64, Vec<u8>)> = recorder
+            .take_events()
+            .into_iter()
+            .filter_map(|e| match e {
+                RecorderEvent::AdtsFrame { timestamp_us, data } => Some((timestamp_us, data)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            frames,
+            vec![(
+                0,
+                vec![0xFF, 0xF1, 0x4C, 0x80, 0x01, 0x3F, 0xFC, 0x21, 0x10]
+            )]
+        );
+        assert!(recorder.enable_adts_output().is_err());
+    }
+
+    #[test]
+    fn test_recorder_invalid_transitions() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        assert!(recorder.pause().unwrap_err().contains("state: idle"));
+        assert!(recorder
+            .push_video(&frame(), 0, true)
+            .unwrap_err()
+            .contains("not recording"));
+
+        recorder.start().unwrap();
+        assert!(recorder.start().is_err());
+        assert!(recorder.resume().is_err());
+        recorder.stop().unwrap();
+        assert!(recorder.stop().unwrap_err().contains("state: stopped"));
+    }
+
+    #[test]
+    fn test_recorder_wal_batches_precede_final_chunks() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        recorder.enable_wal(500).unwrap();
+        recorder.start().unwrap();
+        for i in 0..20u64 {
+            recorder.push_video(&frame(), i * 33_333, i == 0).unwrap();
+        }
+        recorder.stop().unwrap();
+
+        let events = recorder.take_events();
+        let first_chunk = events
+            .iter()
+            .position(|e| matches!(e, RecorderEvent::ChunkReady(_)))
+            .unwrap();
+        let wal_batches = events
+            .iter()
+            .filter(|e| matches!(e, RecorderEvent::WalBatch(_)))
+            .count();
+        assert_eq!(wal_batches, 2);
+        assert!(matches!(
+            events[first_chunk - 1],
+            RecorderEvent::WalBatch(_)
+        ));
+        assert!(recorder.enable_wal(500).is_err());
+    }
+
+    #[test]
+    fn test_recover_from_wal_emits_only_missing_chunks() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        recorder.enable_wal(250).unwrap();
+        recorder.start().unwrap();
+        for i in 0..90u64 {
+            recorder
+                .push_video(&frame(), i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+
+        // Crash before stop(): one chunk was persisted, the rest is in the log
+        let mut wal_bytes = Vec::new();
+        let mut persisted_chunks = Vec::new();
+        for event in recorder.take_events() {
+            match event {
+                RecorderEvent::WalBatch(batch) => wal_bytes.extend(batch.data),
+                RecorderEvent::ChunkReady(chunk) => persisted_chunks.push(chunk),
+                _ => {}
+            }
+        }
+        assert_eq!(persisted_chunks.len(), 1);
+        let persisted = recorder.manifest().clone();
+
+        // Replaying without a manifest reproduces the persisted chunk exactly
+        let mut replayed =
+            RecorderState::recover(SessionId::from("s1"), video_config(), None, &wal_bytes)
+                .unwrap();
+        let replayed_events = replayed.take_events();
+        assert_eq!(chunks(&replayed_events)[0].data, persisted_chunks[0].data);
+
+        let mut recovered = RecorderState::recover(
+            SessionId::from("s1"),
+            video_config(),
+            Some(persisted),
+            &wal_bytes,
+        )
+        .unwrap();
+        assert_eq!(recovered.status(), RecorderStatus::Stopped);
+        assert_eq!(recovered.manifest().chunk_count(), 2);
+        assert_eq!(recovered.manifest().chunks[0], persisted_chunks[0].metadata);
+
+        let events = recovered.take_events();
+        let chunks = chunks(&events);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].metadata.chunk_id.sequence, 1);
+    }
+
+    #[test]
+    fn test_recorder_reports_stalls_only_while_recording() {
+        let clock = ManualClock::new(1_700_000_000_000);
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        recorder.set_clock(ClockHandle::new(clock.clone()));
+        recorder.enable_stall_watchdog(1000).unwrap();
+        recorder.start().unwrap();
+        recorder.push_video(&frame(), 0, true).unwrap();
+        recorder.take_events();
+
+        clock.advance(5_000);
+        recorder.pause().unwrap();
+        recorder.check_stalls();
+        recorder.resume().unwrap();
+        assert!(!recorder
+            .take_events()
+            .iter()
+            .any(|e| matches!(e, RecorderEvent::StreamStalled { .. })));
+
+        // Resuming restarts the watchdog: 1 s later is not a stall yet
+        clock.advance(999);
+        recorder.check_stalls();
+        assert!(recorder.take_events().is_empty());
+        clock.advance(10_000);
+        recorder.check_stalls();
+        let events = recorder.take_events();
+        assert!(matches!(
+            events.as_slice(),
+            [RecorderEvent::StreamStalled {
+                track: TrackKind::Video,
+                ..
+            }]
+        ));
+        assert!(recorder.enable_stall_watchdog(1000).is_err());
+    }
+
+    #[test]
+    fn test_recorder_marks_silence() {
+        let config = MuxideConfig {
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(1),
+            audio_timescale: Some(48000),
+            ..Default::default()
+        };
+        let silence = SilenceConfig {
+            min_duration_ms: 100,
+            ..Default::default()
+        };
+        let mut video_only = RecorderState::new(SessionId::from("s0"), video_config());
+        assert!(video_only.enable_silence_detection(silence).is_err());
+
+        let mut recorder = RecorderState::new(SessionId::from("s1"), config);
+        recorder.enable_silence_detection(silence).unwrap();
+        recorder.start().unwrap();
+        // Loud, silent, loud, silent until stop (frame sizes in bytes)
+        let sizes = [[200; 5], [6; 5], [200; 5], [6; 5]].concat();
+        for (i, size) in sizes.into_iter().enumerate() {
+            recorder
+                .push_audio(&vec![0x21; size], i as u64 * 25_000, 25_000)
+                .unwrap();
+        }
+        recorder.stop().unwrap();
+
+        let silences: Vec<RecorderEvent> = recorder
+            .take_events()
+            .into_iter()
+            .filter(|e| {
+                matches!(
+                    e,
+                    RecorderEvent::SilenceStarted { .. } | RecorderEvent::SilenceEnded(_)
+                )
+            })
+            .collect();
+        let first = SilenceRange {
+            start_us: 125_000,
+            end_us: 250_000,
+        };
+        let last = SilenceRange {
+            start_us: 375_000,
+            end_us: 500_000,
+        };
+        assert_eq!(
+            silences,
+            vec![
+                RecorderEvent::SilenceStarted { start_us: 125_000 },
+                RecorderEvent::SilenceEnded(first),
+                RecorderEvent::SilenceStarted { start_us: 375_000 },
+                RecorderEvent::SilenceEnded(last),
+            ]
+        );
+        let markers: Vec<(u64, &str)> = recorder
+            .manifest()
+            .markers
+            .iter()
+            .map(|m| (m.timestamp_us, m.label.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            markers,
+            vec![
+                (125_000, SILENCE_START_LABEL),
+                (250_000, SILENCE_END_LABEL),
+                (375_000, SILENCE_START_LABEL),
+                (500_000, SILENCE_END_LABEL),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resume_session_after_reload() {
+        let session = SessionId::from("s1");
+        let mut recorder = RecorderState::new(session.clone(), video_config());
+        let init = recorder.start().unwrap();
+
+        // Persist manifest and snapshot with every chunk, as the sink does
+        let mut persisted = None;
+        let mut chunks_before = Vec::new();
+        for i in 0..80u64 {
+            recorder
+                .push_video(&frame(), i * 33_333, i % 30 == 0)
+                .unwrap();
+            let events = recorder.take_events();
+            if !chunks(&events).is_empty() {
+                chunks_before.extend(chunks(&events).into_iter().cloned());
+                persisted = Some((recorder.manifest().clone(), recorder.snapshot().unwrap()));
+            }
+        }
+        let (manifest, snapshot) = persisted.unwrap();
+        assert_eq!(manifest.chunk_count(), 1);
+
+        // Missing chunks or a stopped session cannot be resumed
+        let mut stale = manifest.clone();
+        stale.chunks.clear();
+        let mut reloaded = RecorderState::new(session.clone(), video_config());
+        let error = reloaded.resume_session(stale, &snapshot).unwrap_err();
+        assert!(error.contains("covers 1 chunks but the manifest lists 0"));
+        let mut stopped = manifest.clone();
+        stopped.state = SessionState::Finalizing;
+        assert!(reloaded.resume_session(stopped, &snapshot).is_err());
+        let mut other = RecorderState::new(SessionId::from("s2"), video_config());
+        assert!(other.resume_session(manifest.clone(), &snapshot).is_err());
+
+        // The last keyframe survives the reload for slates; snapshots of
+        // the previous layout resume without it
+        let mut slated = RecorderState::new(session.clone(), video_config());
+        slated.resume_session(manifest.clone(), &snapshot).unwrap();
+        slated.insert_slate(SlateSource::LastKeyframe, 500).unwrap();
+        let mut previous = snapshot[..snapshot.len() - 5 - frame().len()].to_vec();
+        previous[SNAPSHOT_MAGIC.len()] = 2;
+        let mut slated = RecorderState::new(session.clone(), video_config());
+        slated.resume_session(manifest.clone(), &previous).unwrap();
+        assert!(slated.insert_slate(SlateSource::LastKeyframe, 500).is_err());
+        // Version 1 has no quality report either
+        let len_at = |pos: usize| {
+            4 + u32::from_le_bytes(previous[pos..pos + 4].try_into().unwrap()) as usize
+        };
+        let muxer_at = SNAPSHOT_MAGIC.len() + 1 + len_at(SNAPSHOT_MAGIC.len() + 1) + 8;
+        let quality_at = muxer_at + len_at(muxer_at);
+        let mut first = previous[..quality_at].to_vec();
+        first[SNAPSHOT_MAGIC.len()] = 1;
+        let mut unrated = RecorderState::new(session.clone(), video_config());
+        unrated.resume_session(manifest.clone(), &first).unwrap();
+        first[SNAPSHOT_MAGIC.len()] = 0;
+        let mut unrated = RecorderState::new(session.clone(), video_config());
+        assert!(unrated.resume_session(manifest.clone(), &first).is_err());
+
+        // After the reload the encoder clock starts over
+        let resumed_init = reloaded.resume_session(manifest, &snapshot).unwrap();
+        assert_eq!(resumed_init, init);
+        assert!(!reloaded.push_video(&frame(), 0, false).unwrap());
+        for i in 0..90u64 {
+            reloaded
+                .push_video(&frame(), 1_000 + i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+        reloaded.stop().unwrap();
+        let events = reloaded.take_events();
+        let chunks_after = chunks(&events);
+
+        // One recording: sequence numbers and decode times carry on
+        let all: Vec<&RecordedChunk> = chunks_before.iter().chain(chunks_after).collect();
+        let sequences: Vec<u64> = all.iter().map(|c| c.metadata.chunk_id.sequence).collect();
+        assert_eq!(sequences, (0..all.len() as u64).collect::<Vec<_>>());
+        assert_eq!(reloaded.manifest().chunk_count(), all.len());
+        let data: Vec<u8> = all.iter().flat_map(|c| c.data.clone()).collect();
+        let traces = crate::muxide_muxer::trace_segment(&data).unwrap();
+        for pair in traces.windows(2) {
+            assert_eq!(pair[1].sequence, pair[0].sequence + 1);
+            assert_eq!(
+                pair[1].base_decode_time,
+                pair[0].base_decode_time + pair[0].duration
+            );
+        }
+        // The first chunk and the 90 frames after the reload; frames pushed
+        // after the snapshot are lost
+        let samples: Vec<u32> = traces.iter().map(|t| t.sample_count).collect();
+        assert_eq!(samples, vec![62, 62, 28]);
+        // So does the byte-range map
+        let chunks = all
+            .iter()
+            .map(|c| (&c.metadata.chunk_id, c.data.as_slice()));
+        assert_eq!(
+            reloaded.manifest().fragments,
+            crate::range_map::build_range_map(&replaced_init(&events).unwrap(), chunks).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_recorder_transfer_between_workers() {
+        let session = SessionId::from("s1");
+        let mut recorder = RecorderState::new(session.clone(), video_config());
+        recorder.start().unwrap();
+        let mut chunks_before = Vec::new();
+        for i in 0..80u64 {
+            recorder
+                .push_video(&frame(), i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+        chunks_before.extend(chunks(&recorder.take_events()).into_iter().cloned());
+        assert_eq!(chunks_before.len(), 1);
+        recorder.pause().unwrap();
+        recorder.take_events();
+
+        // The package crosses the worker boundary as bytes
+        let package = recorder.transfer().unwrap().encode().unwrap();
+        let transfer = RecorderTransfer::decode(&package).unwrap();
+        assert_eq!(transfer.session_id(), &session);
+        assert!(transfer.paused);
+
+        let mut other = RecorderState::new(SessionId::from("s2"), transfer.config.clone());
+        assert!(other.resume_transfer(transfer.clone()).is_err());
+
+        let mut worker = RecorderState::new(session, transfer.config.clone());
+        worker.resume_transfer(transfer).unwrap();
+        assert_eq!(worker.status(), RecorderStatus::Paused);
+        worker.resume().unwrap();
+        for i in 0..40u64 {
+            worker
+                .push_video(&frame(), 1_000 + i * 33_333, i % 30 == 0)
+                .unwrap();
+        }
+        worker.stop().unwrap();
+        let events = worker.take_events();
+
+        // Buffered frames are not lost and the sequence carries on
+        let all: Vec<&RecordedChunk> = chunks_before.iter().chain(chunks(&events)).collect();
+        let sequences: Vec<u64> = all.iter().map(|c| c.metadata.chunk_id.sequence).collect();
+        assert_eq!(sequences, (0..all.len() as u64).collect::<Vec<_>>());
+        let data: Vec<u8> = all.iter().flat_map(|c| c.data.clone()).collect();
+        let traces = crate::muxide_muxer::trace_segment(&data).unwrap();
+        let samples: u32 = traces.iter().map(|t| t.sample_count).sum();
+        assert_eq!(samples, 120);
+    }
+
+    #[test]
+    fn test_bookmarks_locate_their_keyframe() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        assert!(recorder.add_bookmark(None).is_err());
+        let mut file = recorder.start().unwrap();
+        assert!(recorder
+            .add_bookmark(None)
+            .unwrap_err()
+            .contains("No video"));
+
+        // Keyframes every 20 frames, several per fragment
+        let numbered = |i: u64| vec![0x00, 0x00, 0x00, 0x04, 0x65, i as u8, 0x84, 0x00];
+        let mut bookmarks = Vec::new();
+        for i in 0..100u64 {
+            recorder
+                .push_video(&numbered(i), i * 33_333, i % 20 == 0)
+                .unwrap();
+            if [5, 40, 90].contains(&i) {
+                let label = format!("frame {}", i);
+                let timestamp_us = recorder.add_bookmark(Some(label)).unwrap();
+                assert_eq!(timestamp_us, i * 33_333);
+                bookmarks.push((timestamp_us, i - i % 20));
+            }
+        }
+        // Only keyframes already muxed into a chunk are known: the last one
+        // anchors the bookmark until the one at frame 80 is
+        let marker = &recorder.manifest().markers[2];
+        let keyframe = marker.bookmark.as_ref().unwrap().keyframe.as_ref();
+        assert_eq!(keyframe.unwrap().sequence, 1);
+        let init_len = recorder.muxer.get_init_segment().unwrap().len();
+        recorder.stop().unwrap();
+        let events = recorder.take_events();
+        for chunk in chunks(&events) {
+            file.extend_from_slice(&chunk.data);
+        }
+        replace_init(&mut file, init_len, &events);
+
+        let manifest = recorder.manifest();
+        assert_eq!(manifest.markers.len(), bookmarks.len());
+        for (marker, (timestamp_us, keyframe_frame)) in manifest.markers.iter().zip(bookmarks) {
+            assert_eq!(marker.timestamp_us, timestamp_us);
+            let keyframe = marker.bookmark.as_ref().unwrap().keyframe.as_ref().unwrap();
+            // Decode times are in 90 kHz ticks
+            assert!(keyframe_frame * 33_333 - keyframe.timestamp_us < 12);
+            let fragment = manifest
+                .fragments
+                .iter()
+                .find(|f| f.offset == keyframe.fragment_offset)
+                .unwrap();
+            assert_eq!(fragment.sequence, keyframe.sequence);
+            assert!(keyframe.sample_offset < fragment.offset + fragment.size);
+            let sample = keyframe.sample_offset as usize;
+            assert_eq!(&file[sample..sample + 8], numbered(keyframe_frame));
+        }
+    }
+
+    #[test]
+    fn test_stop_embeds_session_metadata() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        let init = recorder.start().unwrap();
+        assert_eq!(EmbeddedMetadata::read(&init).unwrap(), None);
+        for i in 0..60u64 {
+            recorder
+                .push_video(&frame(), i * 33_333, i % 30 == 0)
+                .unwrap();
+            if i == 40 {
+                recorder.add_bookmark(Some("Intro".to_string())).unwrap();
+            }
+        }
+        recorder.stop().unwrap();
+
+        let events = recorder.take_events();
+        let embedded = EmbeddedMetadata::read(&replaced_init(&events).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            embedded,
+            EmbeddedMetadata::from_manifest(recorder.manifest())
+        );
+        assert_eq!(embedded.session_id, SessionId::from("s1"));
+        assert!(embedded.metadata.unwrap().quality.is_some());
+        assert_eq!(embedded.marker_count, 1);
+        assert_eq!(embedded.markers[0].label, "Intro");
+        assert_eq!(embedded.markers[0].timestamp_us, 40 * 33_333);
+    }
+
+    #[test]
+    fn test_stop_embeds_search_index() {
+        let mut recorder = RecorderState::new(SessionId::from("s1"), video_config());
+        recorder.set_embed_search_index(true);
+        recorder.start().unwrap();
+        for i in 0..60u64 {
+            recorder
+                .push_video(&frame(), i * 33_333, i % 30 == 0)
+                .unwrap();
+            if i == 40 {
+                recorder.add_bookmark(Some("Intro".to_string())).unwrap();
+            }
+        }
+        assert!(recorder.search_index().is_err());
+        recorder.stop().unwrap();
+
+        let index = recorder.search_index().unwrap();
+        assert_eq!(index.duration_us, Some(60 * 33_333));
+        assert_eq!(index.chapters[0].start_us, 40 * 33_333);
+        assert_eq!(index.chapters[0].end_us, 60 * 33_333);
+        assert!(index.matches("intro"));
+        let embedded = EmbeddedMetadata::read(&replaced_init(&recorder.take_events()).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(embedded.index, Some(index));
+    }
+}
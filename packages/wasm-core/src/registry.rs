@@ -0,0 +1,462 @@
+//! Server-side registry of recording sessions.
+//!
+//! A `SessionRecord` holds everything the sync server knows about one
+//! session: its manifest (and with it the lifecycle state), the upload
+//! status of every chunk, and when it was created and last touched.
+//! `SessionRegistry` backends only store and look up records; manifest
+//! merging, state transitions, upload bookkeeping and expiry are shared
+//! provided methods, like chunk handling on top of `ChunkStore`.
+//!
+//! Timestamps are passed in explicitly (Unix ms), so expiry can be driven
+//! from a timer and tested without a clock. The whole registry converts to
+//! a serializable `RegistrySnapshot` for persistence.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::ChunkId;
+use crate::manifest::ChunkManifest;
+use crate::metadata::RecordingMetadata;
+use crate::session::{SessionId, SessionState};
+use crate::upload::{UploadProgress, UploadTracker};
+
+/// Version written into snapshots
+pub const REGISTRY_SNAPSHOT_VERSION: u32 = 1;
+
+/// Everything the registry tracks for one session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionRecord {
+    pub manifest: ChunkManifest,
+    pub uploads: UploadTracker,
+    /// Unix timestamp (ms) the session was registered
+    pub created_at: u64,
+    /// Unix timestamp (ms) of the last change
+    pub updated_at: u64,
+}
+
+impl SessionRecord {
+    /// Create a record for a new session in the `standby` state
+    pub fn new(session_id: SessionId, now_ms: u64) -> Self {
+        Self {
+            manifest: ChunkManifest::new(session_id.clone()),
+            uploads: UploadTracker::new(session_id),
+            created_at: now_ms,
+            updated_at: now_ms,
+        }
+    }
+
+    /// Session this record belongs to
+    pub fn session_id(&self) -> &SessionId {
+        &self.manifest.session_id
+    }
+
+    /// Current lifecycle state
+    pub fn state(&self) -> SessionState {
+        self.manifest.state
+    }
+}
+
+/// When sessions are interrupted or dropped by `SessionRegistry::expire`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpiryPolicy {
+    /// Mark an active session `interrupted` after this long without changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_ms: Option<u64>,
+    /// Remove a `synced` or `interrupted` session this long after its last
+    /// change, unless its metadata sets its own `RetentionPolicy`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_ms: Option<u64>,
+}
+
+/// What `expire` did to a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryAction {
+    /// Active session went idle and was marked `interrupted`
+    Interrupted,
+    /// Finished session was removed from the registry
+    Removed,
+}
+
+/// Serializable copy of a whole registry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrySnapshot {
+    pub version: u32,
+    /// Records sorted by session ID
+    pub sessions: Vec<SessionRecord>,
+}
+
+/// Storage of session records
+///
+/// Implementors provide record access; everything else is built on top.
+pub trait SessionRegistry {
+    /// Look up a session's record
+    fn get(&self, session: &SessionId) -> Option<SessionRecord>;
+
+    /// Insert or replace a session's record
+    fn put(&mut self, record: SessionRecord);
+
+    /// Remove a session's record, returning it if it existed
+    fn remove(&mut self, session: &SessionId) -> Option<SessionRecord>;
+
+    /// List the IDs of all registered sessions, sorted
+    fn list(&self) -> Vec<SessionId>;
+
+    /// Register a new session in the `standby` state
+    fn create(&mut self, session: SessionId, now_ms: u64) -> Result<SessionRecord, String> {
+        if self.get(&session).is_some() {
+            return Err(format!("Session {} is already registered", session));
+        }
+        let record = SessionRecord::new(session, now_ms);
+        self.put(record.clone());
+        Ok(record)
+    }
+
+    /// Merge a manifest reported by the client into the session's record
+    ///
+    /// The state moves to the manifest's state (failing on an invalid
+    /// transition), new chunks are appended and become pending uploads, and
+    /// metadata, markers and the fragment map are replaced. Known chunks are
+    /// left as they are.
+    fn update_manifest(
+        &mut self,
+        manifest: &ChunkManifest,
+        now_ms: u64,
+    ) -> Result<SessionRecord, String> {
+        modify(self, &manifest.session_id, now_ms, |record| {
+            if manifest.state != record.manifest.state {
+                record.manifest.state.transition_to(manifest.state)?;
+            }
+            for chunk in &manifest.chunks {
+                if !record.manifest.contains(&chunk.chunk_id) {
+                    record.manifest.add_chunk(chunk.clone())?;
+                }
+            }
+            if manifest.metadata.is_some() {
+                record.manifest.metadata = manifest.metadata.clone();
+            }
+            record.manifest.markers = manifest.markers.clone();
+            record.manifest.fragments = manifest.fragments.clone();
+            record.uploads.sync_manifest(&record.manifest)?;
+            Ok(record.clone())
+        })
+    }
+
+    /// Move a session to `state`
+    fn transition(
+        &mut self,
+        session: &SessionId,
+        state: SessionState,
+        now_ms: u64,
+    ) -> Result<(), String> {
+        modify(self, session, now_ms, |record| {
+            record.manifest.state.transition_to(state)
+        })
+    }
+
+    /// Record that the session's init segment was received
+    fn mark_init_segment_uploaded(
+        &mut self,
+        session: &SessionId,
+        now_ms: u64,
+    ) -> Result<(), String> {
+        modify(self, session, now_ms, |record| {
+            record.uploads.mark_init_segment_uploaded();
+            Ok(())
+        })
+    }
+
+    /// Record that a chunk listed in the manifest was received
+    fn mark_chunk_uploaded(&mut self, chunk: &ChunkId, now_ms: u64) -> Result<(), String> {
+        modify(self, &chunk.session, now_ms, |record| {
+            record.uploads.complete(chunk)
+        })
+    }
+
+    /// Record that receiving a chunk failed for good
+    fn mark_chunk_failed(
+        &mut self,
+        chunk: &ChunkId,
+        error: &str,
+        now_ms: u64,
+    ) -> Result<(), String> {
+        modify(self, &chunk.session, now_ms, |record| {
+            record.uploads.fail(chunk, error, 0).map(|_| ())
+        })
+    }
+
+    /// Upload progress of a session
+    fn progress(&self, session: &SessionId) -> Option<UploadProgress> {
+        self.get(session).map(|record| record.uploads.progress())
+    }
+
+    /// Apply `policy` at `now_ms`, returning what happened to which session
+    fn expire(&mut self, policy: &ExpiryPolicy, now_ms: u64) -> Vec<(SessionId, ExpiryAction)> {
+        let mut expired = Vec::new();
+        for session in self.list() {
+            let Some(mut record) = self.get(&session) else {
+                continue;
+            };
+            let idle_ms = now_ms.saturating_sub(record.updated_at);
+            if record.state().is_terminal() {
+                let retention = RecordingMetadata::retention_status(
+                    record.manifest.metadata.as_ref(),
+                    policy.retention_ms,
+                    record.updated_at,
+                    now_ms,
+                );
+                if retention.purgeable {
+                    self.remove(&session);
+                    expired.push((session, ExpiryAction::Removed));
+                }
+            } else if policy
+                .idle_timeout_ms
+                .is_some_and(|timeout| idle_ms >= timeout)
+            {
+                record
+                    .manifest
+                    .state
+                    .transition_to(SessionState::Interrupted)
+                    .expect("non-terminal sessions can be interrupted");
+                record.updated_at = now_ms;
+                self.put(record);
+                expired.push((session, ExpiryAction::Interrupted));
+            }
+        }
+        expired
+    }
+
+    /// Copy every record into a snapshot
+    fn snapshot(&self) -> RegistrySnapshot {
+        RegistrySnapshot {
+            version: REGISTRY_SNAPSHOT_VERSION,
+            sessions: self
+                .list()
+                .iter()
+                .filter_map(|session| self.get(session))
+                .collect(),
+        }
+    }
+
+    /// Load the records of a snapshot, replacing records of the same sessions
+    fn restore(&mut self, snapshot: RegistrySnapshot) -> Result<(), String> {
+        if snapshot.version != REGISTRY_SNAPSHOT_VERSION {
+            return Err(format!(
+                "Unsupported registry snapshot version: {}",
+                snapshot.version
+            ));
+        }
+        for record in snapshot.sessions {
+            self.put(record);
+        }
+        Ok(())
+    }
+}
+
+/// Apply a change to a session's record and bump its update time
+///
+/// Nothing is written if the change fails.
+fn modify<R: SessionRegistry + ?Sized, T>(
+    registry: &mut R,
+    session: &SessionId,
+    now_ms: u64,
+    change: impl FnOnce(&mut SessionRecord) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut record = registry
+        .get(session)
+        .ok_or_else(|| format!("Session {} is not registered", session))?;
+    record.updated_at = now_ms;
+    let result = change(&mut record)?;
+    registry.put(record);
+    Ok(result)
+}
+
+/// Registry keeping every record in memory
+#[derive(Debug, Clone, Default)]
+pub struct MemorySessionRegistry {
+    sessions: BTreeMap<SessionId, SessionRecord>,
+}
+
+impl MemorySessionRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a registry holding the records of a snapshot
+    pub fn from_snapshot(snapshot: RegistrySnapshot) -> Result<Self, String> {
+        let mut registry = Self::new();
+        registry.restore(snapshot)?;
+        Ok(registry)
+    }
+}
+
+impl SessionRegistry for MemorySessionRegistry {
+    fn get(&self, session: &SessionId) -> Option<SessionRecord> {
+        self.sessions.get(session).cloned()
+    }
+
+    fn put(&mut self, record: SessionRecord) {
+        self.sessions.insert(record.session_id().clone(), record);
+    }
+
+    fn remove(&mut self, session: &SessionId) -> Option<SessionRecord> {
+        self.sessions.remove(session)
+    }
+
+    fn list(&self) -> Vec<SessionId> {
+        self.sessions.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{ChunkMetadata, TrackKind};
+    use crate::metadata::RetentionPolicy;
+
+    fn manifest(session: &SessionId, state: SessionState, chunks: u64) -> ChunkManifest {
+        let mut manifest = ChunkManifest::new(session.clone());
+        manifest.state = state;
+        for sequence in 0..chunks {
+            manifest
+                .add_chunk(ChunkMetadata {
+                    chunk_id: ChunkId::new(session.clone(), TrackKind::Muxed, sequence),
+                    timestamp_us: sequence * 2_000_000,
+                    size: 1024,
+                    hash: None,
+                    has_keyframe: Some(true),
+                    created_at: 0,
+                })
+                .unwrap();
+        }
+        manifest
+    }
+
+    #[test]
+    fn test_registry_tracks_manifest_and_uploads() {
+        let session = SessionId::from("s1");
+        let mut registry = MemorySessionRegistry::new();
+        registry.create(session.clone(), 1_000).unwrap();
+        assert!(registry.create(session.clone(), 1_000).is_err());
+
+        registry
+            .update_manifest(&manifest(&session, SessionState::Recording, 2), 2_000)
+            .unwrap();
+        let record = registry
+            .update_manifest(&manifest(&session, SessionState::Recording, 3), 3_000)
+            .unwrap();
+        assert_eq!(record.manifest.chunk_count(), 3);
+        assert_eq!(record.updated_at, 3_000);
+
+        // Invalid transitions leave the record untouched
+        assert!(registry
+            .update_manifest(&manifest(&session, SessionState::Synced, 3), 4_000)
+            .is_err());
+        assert_eq!(registry.get(&session).unwrap().updated_at, 3_000);
+
+        registry
+            .mark_init_segment_uploaded(&session, 5_000)
+            .unwrap();
+        let first = ChunkId::new(session.clone(), TrackKind::Muxed, 0);
+        let second = first.next();
+        registry.mark_chunk_uploaded(&first, 5_000).unwrap();
+        registry
+            .mark_chunk_failed(&second, "hash mismatch", 5_000)
+            .unwrap();
+        let unknown = ChunkId::new(session.clone(), TrackKind::Audio, 0);
+        assert!(registry.mark_chunk_uploaded(&unknown, 5_000).is_err());
+
+        let progress = registry.progress(&session).unwrap();
+        assert_eq!(
+            (progress.uploaded, progress.failed, progress.pending),
+            (1, 1, 1)
+        );
+
+        let json = serde_json::to_string(&registry.snapshot()).unwrap();
+        let restored =
+            MemorySessionRegistry::from_snapshot(serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(restored.get(&session), registry.get(&session));
+    }
+
+    #[test]
+    fn test_registry_expiry_policy() {
+        let policy = ExpiryPolicy {
+            idle_timeout_ms: Some(60_000),
+            retention_ms: Some(3_600_000),
+        };
+        let mut registry = MemorySessionRegistry::new();
+        let idle = SessionId::from("idle");
+        let active = SessionId::from("active");
+        let synced = SessionId::from("synced");
+        for session in [&idle, &active, &synced] {
+            registry.create(session.clone(), 0).unwrap();
+            registry
+                .transition(session, SessionState::Recording, 0)
+                .unwrap();
+        }
+        registry
+            .transition(&synced, SessionState::Finalizing, 0)
+            .unwrap();
+        registry
+            .transition(&synced, SessionState::Synced, 0)
+            .unwrap();
+        registry
+            .transition(&active, SessionState::Finalizing, 50_000)
+            .unwrap();
+
+        assert_eq!(
+            registry.expire(&policy, 60_000),
+            vec![(idle.clone(), ExpiryAction::Interrupted)]
+        );
+        assert_eq!(
+            registry.get(&idle).unwrap().state(),
+            SessionState::Interrupted
+        );
+
+        assert_eq!(
+            registry.expire(&policy, 3_600_000),
+            vec![
+                (active.clone(), ExpiryAction::Interrupted),
+                (synced.clone(), ExpiryAction::Removed),
+            ]
+        );
+        assert_eq!(registry.list(), vec![active.clone(), idle.clone()]);
+        assert!(registry
+            .expire(&ExpiryPolicy::default(), u64::MAX)
+            .is_empty());
+
+        // A recording's own retention overrides the policy; a hold keeps it
+        for (session, retention) in [
+            (
+                &active,
+                RetentionPolicy {
+                    expire_after_ms: Some(1_000),
+                    legal_hold: false,
+                },
+            ),
+            (
+                &idle,
+                RetentionPolicy {
+                    expire_after_ms: Some(0),
+                    legal_hold: true,
+                },
+            ),
+        ] {
+            let mut record = registry.get(session).unwrap();
+            record.manifest.metadata = Some(RecordingMetadata {
+                retention: Some(retention),
+                ..Default::default()
+            });
+            registry.put(record);
+        }
+        assert_eq!(
+            registry.expire(&ExpiryPolicy::default(), 3_601_000),
+            vec![(active, ExpiryAction::Removed)]
+        );
+        assert_eq!(registry.list(), vec![idle]);
+    }
+}
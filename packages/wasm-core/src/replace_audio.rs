@@ -0,0 +1,227 @@
+//! Replacing the audio track of a finished recording without transcoding.
+//!
+//! A better version of a recording's audio (noise-suppressed, or encoded
+//! again at another bitrate) is often produced after the recording ends, as
+//! a separate audio MP4 of the same duration and timescale.
+//! `replace_audio_track` remuxes the recording with those samples: every
+//! fragment keeps its video samples byte for byte, with their timing, flags
+//! and auxiliary information, and takes the new audio samples covering the
+//! same time (see `Refragmenter::replace_audio`). The new track's trak box
+//! takes the old one's place in the moov, so its sample description and any
+//! gapless edit list come with it. A replacement whose duration is off by
+//! more than one audio frame is refused, as it would not line up with the
+//! video.
+
+use crate::merge::{read_tracks, renumber_trak};
+use crate::muxide_muxer::{build_box, find_box, parse_boxes, trace_segment, Refragmenter};
+
+/// Build `recording` again with the audio samples of `audio`
+///
+/// `recording` is an init segment with one video and one audio track
+/// followed by its fragments; `audio` is a recording whose audio track has
+/// the same timescale and duration, give or take one frame. Boxes other than moof/mdat between fragments (prft,
+/// fragment checksums) are not carried over.
+pub fn replace_audio_track(recording: &[u8], audio: &[u8]) -> Result<Vec<u8>, String> {
+    let tracks = read_tracks(recording)?;
+    let mut audio_tracks = tracks.iter().filter(|t| t.is_audio);
+    let old = audio_tracks.next().ok_or("Recording has no audio track")?;
+    if audio_tracks.next().is_some() {
+        return Err("Recording has more than one audio track".to_string());
+    }
+    let new = read_tracks(audio)?
+        .into_iter()
+        .find(|t| t.is_audio)
+        .ok_or("Replacement has no audio track")?;
+    let mut refragmenter = Refragmenter::new(recording, 1)?;
+    refragmenter.replace_audio(audio)?;
+    let (duration, samples) = track_duration(recording, old.track_id)?;
+    let (new_duration, _) = track_duration(audio, new.track_id)?;
+    let frame = duration / samples.max(1) as u64;
+    if duration.abs_diff(new_duration) > frame {
+        return Err(format!(
+            "Replacement audio lasts {} ticks, the recording's {}",
+            new_duration, duration
+        ));
+    }
+
+    // The init segment, with the new trak in place of the old one
+    let trak = renumber_trak(&new.trak, old.track_id)?;
+    let moov = find_box(recording, b"moov")?.ok_or("Init segment has no moov box")?;
+    let mut moov_payload = Vec::with_capacity(moov.payload.len() + trak.len());
+    for b in parse_boxes(moov.payload)? {
+        let raw = &moov.payload[b.offset..b.offset + 8 + b.payload.len()];
+        if raw == old.trak.as_slice() {
+            moov_payload.extend_from_slice(&trak);
+        } else {
+            moov_payload.extend_from_slice(raw);
+        }
+    }
+
+    let boxes = parse_boxes(recording)?;
+    let mut out = Vec::with_capacity(recording.len());
+    let mut fragments = Vec::new();
+    let mut fragment_start = None;
+    for b in &boxes {
+        let end = b.offset + 8 + b.payload.len();
+        match &b.typ {
+            b"moov" => out.extend_from_slice(&build_box(b"moov", &moov_payload)),
+            b"moof" => fragment_start = Some(b.offset),
+            b"mdat" => {
+                let start = fragment_start
+                    .take()
+                    .ok_or_else(|| format!("mdat at offset {} has no moof", b.offset))?;
+                fragments.push(&recording[start..end]);
+            }
+            _ if fragments.is_empty() && fragment_start.is_none() => {
+                out.extend_from_slice(&recording[b.offset..end]);
+            }
+            _ => {}
+        }
+    }
+
+    let Some((last, fragments)) = fragments.split_last() else {
+        return Err("Recording has no fragments".to_string());
+    };
+    for fragment in fragments {
+        if let Some(fragment) = refragmenter.push_segment(fragment)? {
+            out.extend_from_slice(&fragment);
+        }
+    }
+    if let Some(fragment) = refragmenter.push_last_segment(last)? {
+        out.extend_from_slice(&fragment);
+    }
+    Ok(out)
+}
+
+/// Total sample duration (in track timescale units) and sample count of a
+/// track over every fragment of `mp4`
+fn track_duration(mp4: &[u8], track_id: u32) -> Result<(u64, u32), String> {
+    let traces = trace_segment(mp4)?;
+    let traces = traces.iter().filter(|t| t.track_id == track_id);
+    Ok(traces.fold((0, 0), |(duration, samples), t| {
+        (duration + t.duration, samples + t.sample_count)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::extract_track;
+    use crate::muxide_muxer::{trace_segment, MuxideConfig, MuxideMuxerState};
+
+    fn config() -> MuxideConfig {
+        MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        }
+    }
+
+    fn record(config: MuxideConfig, video: bool, audio_byte: u8) -> Vec<u8> {
+        record_frames(config, video, audio_byte, 140)
+    }
+
+    fn record_frames(config: MuxideConfig, video: bool, audio_byte: u8, frames: u64) -> Vec<u8> {
+        let mut muxer = MuxideMuxerState::new(config);
+        muxer.init().unwrap();
+        let mut recording = muxer.get_init_segment().unwrap();
+        for i in 0..frames {
+            if video && i < 90 {
+                muxer
+                    .push_video_chunk(&[0, 0, 0, 2, 0x65, i as u8], i * 33_333, i % 30 == 0)
+                    .unwrap();
+            }
+            muxer
+                .push_audio_chunk(&[audio_byte; 8], i * 21_333, 21_333)
+                .unwrap();
+        }
+        muxer.force_flush().unwrap();
+        for segment in muxer.get_pending_segments() {
+            recording.extend_from_slice(&segment);
+        }
+        recording
+    }
+
+    #[test]
+    fn test_replace_audio_track() {
+        let recording = record(config(), true, 1);
+        let cleaned = record(
+            MuxideConfig {
+                sps: None,
+                pps: None,
+                ..config()
+            },
+            false,
+            2,
+        );
+
+        let replaced = replace_audio_track(&recording, &cleaned).unwrap();
+        // Video is untouched
+        assert_eq!(
+            extract_track(&replaced, 1).unwrap(),
+            extract_track(&recording, 1).unwrap()
+        );
+        // Audio is the replacement's, every sample of it, at the same times
+        let audio = extract_track(&replaced, 2).unwrap();
+        let samples = |mp4: &[u8]| -> (usize, u64) {
+            let start = find_box(mp4, b"moov").unwrap().unwrap();
+            let media = &mp4[start.offset + 8 + start.payload.len()..];
+            let traces = trace_segment(media).unwrap();
+            (
+                traces.iter().map(|t| t.sample_count as usize).sum(),
+                traces[0].base_decode_time,
+            )
+        };
+        assert_eq!(
+            samples(&audio),
+            samples(&extract_track(&cleaned, 1).unwrap())
+        );
+        let mdat = parse_boxes(&audio)
+            .unwrap()
+            .into_iter()
+            .find(|b| &b.typ == b"mdat")
+            .unwrap();
+        assert!(mdat.payload.iter().all(|&b| b == 2));
+        // Fragments still start with a keyframe, one per second
+        assert_eq!(
+            parse_boxes(&replaced)
+                .unwrap()
+                .iter()
+                .filter(|b| &b.typ == b"moof")
+                .count(),
+            3
+        );
+
+        let error = replace_audio_track(
+            &recording,
+            &record(
+                MuxideConfig {
+                    audio_sample_rate: Some(44100),
+                    ..config()
+                },
+                false,
+                2,
+            ),
+        )
+        .unwrap_err();
+        assert!(error.contains("timescale"), "{}", error);
+
+        // One audio frame more or less still lines up, two do not
+        let audio_only = MuxideConfig {
+            sps: None,
+            pps: None,
+            ..config()
+        };
+        assert!(replace_audio_track(
+            &recording,
+            &record_frames(audio_only.clone(), false, 2, 139)
+        )
+        .is_ok());
+        let error =
+            replace_audio_track(&recording, &record_frames(audio_only, false, 2, 138)).unwrap_err();
+        assert!(error.contains("lasts"), "{}", error);
+    }
+}
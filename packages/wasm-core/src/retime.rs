@@ -0,0 +1,257 @@
+//! Rewriting the timing of existing media segments.
+//!
+//! Segments of a resumed session come from a fresh muxer: their sequence
+//! numbers restart at 1 and their decode times at 0. `SegmentRetimerState`
+//! rewrites each moof in place so they follow on from the segments before
+//! them, and can shift everything by a constant offset to line a recording
+//! up with another source. Only mfhd and tfdt change; sample durations and
+//! data are untouched, so every sample time implied by the trun moves with
+//! its tfdt.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::compat::read_init_tracks;
+use crate::muxide_muxer::{parse_boxes, read_trun, read_u32, read_u64};
+use crate::timebase::{Rounding, TimeBase};
+
+/// How segments are retimed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetimeOptions {
+    /// Start each track's fragment where its previous one ended, closing
+    /// gaps and resets; otherwise original decode times are kept
+    pub continuous: bool,
+    /// Constant shift applied to every decode time, in microseconds
+    pub offset_us: i64,
+    /// mfhd sequence number of the first fragment written
+    pub first_sequence: u32,
+}
+
+impl Default for RetimeOptions {
+    fn default() -> Self {
+        Self {
+            continuous: true,
+            offset_us: 0,
+            first_sequence: 1,
+        }
+    }
+}
+
+/// Retimes the media segments of one stream, in order
+#[derive(Debug, Clone)]
+pub struct SegmentRetimerState {
+    options: RetimeOptions,
+    /// Timescale of each track of the init segment
+    timescales: BTreeMap<u32, u32>,
+    next_sequence: u32,
+    /// Output decode time where each track's next fragment starts
+    next_decode_time: BTreeMap<u32, u64>,
+}
+
+impl SegmentRetimerState {
+    /// Create a retimer for segments described by `init_segment`
+    pub fn new(init_segment: &[u8], options: RetimeOptions) -> Result<Self, String> {
+        let timescales = read_init_tracks(init_segment)?
+            .into_iter()
+            .map(|(track_id, track)| (track_id, track.timescale))
+            .collect();
+        Ok(Self {
+            options,
+            timescales,
+            next_sequence: options.first_sequence,
+            next_decode_time: BTreeMap::new(),
+        })
+    }
+
+    /// Sequence number the next fragment will get
+    pub fn next_sequence(&self) -> u32 {
+        self.next_sequence
+    }
+
+    /// Rewrite the next segment's sequence numbers and decode times
+    pub fn retime(&mut self, segment: &[u8]) -> Result<Vec<u8>, String> {
+        let mut data = segment.to_vec();
+        let mut pos = 0;
+        while pos < segment.len() {
+            let size = read_u32(segment, pos)? as usize;
+            if size < 8 || pos + size > segment.len() {
+                return Err(format!("Invalid box size {} at offset {}", size, pos));
+            }
+            if &segment[pos + 4..pos + 8] == b"moof" {
+                self.retime_moof(&mut data, pos + 8, pos + size)?;
+            }
+            pos += size;
+        }
+        Ok(data)
+    }
+
+    fn retime_moof(&mut self, data: &mut [u8], start: usize, end: usize) -> Result<(), String> {
+        let children: Vec<_> = parse_boxes(&data[start..end])?
+            .iter()
+            .map(|b| (b.typ, start + b.offset + 8, b.payload.len()))
+            .collect();
+        for (typ, payload, len) in children {
+            match &typ {
+                b"mfhd" => {
+                    write_u32(data, payload + 4, self.next_sequence)?;
+                    self.next_sequence += 1;
+                }
+                b"traf" => self.retime_traf(data, payload, payload + len)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn retime_traf(&mut self, data: &mut [u8], start: usize, end: usize) -> Result<(), String> {
+        let mut track_id = None;
+        let mut tfdt = None;
+        let mut duration = 0;
+        for child in parse_boxes(&data[start..end])? {
+            let payload = start + child.offset + 8;
+            match &child.typ {
+                b"tfhd" => track_id = Some(read_u32(child.payload, 4)?),
+                b"tfdt" => tfdt = Some(payload),
+                b"trun" => {
+                    duration += read_trun(child.payload)?
+                        .entries
+                        .iter()
+                        .map(|e| e.duration as u64)
+                        .sum::<u64>()
+                }
+                _ => {}
+            }
+        }
+        let track_id = track_id.ok_or("traf has no tfhd box")?;
+        let tfdt = tfdt.ok_or("traf has no tfdt box")?;
+        let timescale = *self
+            .timescales
+            .get(&track_id)
+            .ok_or_else(|| format!("Segment references unknown track {}", track_id))?;
+
+        let version = data[tfdt];
+        let original = if version == 1 {
+            read_u64(data, tfdt + 4)?
+        } else {
+            read_u32(data, tfdt + 4)? as u64
+        };
+        let base = match self.next_decode_time.get(&track_id) {
+            Some(&next) if self.options.continuous => next,
+            _ => original,
+        };
+        self.next_decode_time.insert(track_id, base + duration);
+
+        let shift = TimeBase::new(timescale as u64)
+            .from_us(self.options.offset_us.unsigned_abs(), Rounding::Nearest);
+        let time = if self.options.offset_us < 0 {
+            base.checked_sub(shift).ok_or_else(|| {
+                format!(
+                    "Offset moves track {} before time zero (decode time {})",
+                    track_id, base
+                )
+            })?
+        } else {
+            base + shift
+        };
+        if version == 1 {
+            data[tfdt + 4..tfdt + 12].copy_from_slice(&time.to_be_bytes());
+        } else {
+            let time = u32::try_from(time)
+                .map_err(|_| "Retimed decode time does not fit a version 0 tfdt")?;
+            write_u32(data, tfdt + 4, time)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_u32(data: &mut [u8], pos: usize, value: u32) -> Result<(), String> {
+    data.get_mut(pos..pos + 4)
+        .ok_or("Unexpected end of box")?
+        .copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::muxide_muxer::{trace_segment, MuxideConfig, MuxideMuxerState};
+
+    /// A run of a fresh muxer: 3 s of 30fps video and 48 kHz audio
+    fn run(config: &MuxideConfig) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let mut muxer = MuxideMuxerState::new(config.clone());
+        muxer.init().unwrap();
+        for i in 0..90u64 {
+            muxer
+                .push_video_chunk(&[0, 0, 0, 1, 0x65], i * 33_333, i % 30 == 0)
+                .unwrap();
+            muxer.push_audio_chunk(&[0; 8], i * 21_333, 21_333).unwrap();
+        }
+        muxer.force_flush().unwrap();
+        (
+            muxer.get_init_segment().unwrap(),
+            muxer.get_pending_segments(),
+        )
+    }
+
+    #[test]
+    fn test_stitches_resumed_session() {
+        let config = MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        };
+        let (init, first) = run(&config);
+        let (_, resumed) = run(&config);
+
+        let mut retimer = SegmentRetimerState::new(&init, RetimeOptions::default()).unwrap();
+        let mut traces = Vec::new();
+        for segment in first.iter().chain(&resumed) {
+            traces.extend(trace_segment(&retimer.retime(segment).unwrap()).unwrap());
+        }
+        assert_eq!(retimer.next_sequence(), 7);
+        for track_id in [1, 2] {
+            let track: Vec<_> = traces.iter().filter(|t| t.track_id == track_id).collect();
+            assert_eq!(track.len(), 6);
+            for pair in track.windows(2) {
+                assert_eq!(
+                    pair[1].base_decode_time,
+                    pair[0].base_decode_time + pair[0].duration
+                );
+                assert_eq!(pair[1].sequence, pair[0].sequence + 1);
+            }
+        }
+
+        // Shifted 1.5 s later, original times kept
+        let options = RetimeOptions {
+            continuous: false,
+            offset_us: 1_500_000,
+            first_sequence: 10,
+        };
+        let mut retimer = SegmentRetimerState::new(&init, options).unwrap();
+        let shifted = trace_segment(&retimer.retime(&resumed[0]).unwrap()).unwrap();
+        let original = trace_segment(&resumed[0]).unwrap();
+        assert_eq!(shifted[0].sequence, 10);
+        assert_eq!(
+            shifted[0].base_decode_time,
+            original[0].base_decode_time + 135_000
+        );
+        assert_eq!(
+            shifted[1].base_decode_time,
+            original[1].base_decode_time + 72_000
+        );
+
+        let options = RetimeOptions {
+            offset_us: -1_000_000,
+            ..Default::default()
+        };
+        let mut retimer = SegmentRetimerState::new(&init, options).unwrap();
+        assert!(retimer.retime(&resumed[0]).is_err());
+    }
+}
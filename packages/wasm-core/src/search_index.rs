@@ -0,0 +1,284 @@
+//! Compact search index of a finished session.
+//!
+//! Built from the manifest at finalize so a library of local recordings can
+//! be searched offline without opening the files: chapter titles (labeled
+//! bookmarks), caption text (the other labeled markers, timed like
+//! subtitle cues), silence and talk ranges, and the lowercase words of all
+//! of it. Mirrors `SessionIndex` in `@maycast/common-types`; the recorder
+//! can also embed it in the file's session metadata.
+//!
+//! Times are on the timeline of the assembled file, like subtitle cues.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::manifest::ChunkManifest;
+use crate::session::SessionId;
+use crate::silence::{SILENCE_END_LABEL, SILENCE_START_LABEL};
+use crate::subtitles::{file_origin, label_cues, INTERNAL_LABELS};
+
+/// Format version of `SessionIndex`
+pub const SEARCH_INDEX_VERSION: u32 = 1;
+
+/// Searchable summary of one recording
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionIndex {
+    pub version: u32,
+    pub session_id: SessionId,
+    /// Display name of the recording
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    pub participant: Option<String>,
+    /// Length of the file in microseconds, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    pub duration_us: Option<u64>,
+    /// Labeled bookmarks, each running until the next one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chapters: Vec<IndexEntry>,
+    /// Other labeled markers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub captions: Vec<IndexEntry>,
+    /// Ranges silence detection reported
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub silence: Vec<IndexRange>,
+    /// The rest of the file, when its duration is known
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub talk: Vec<IndexRange>,
+    /// Sorted, distinct lowercase words of the title, participant, chapters
+    /// and captions
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub terms: Vec<String>,
+}
+
+/// Timed text of a `SessionIndex`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexEntry {
+    pub start_us: u64,
+    pub end_us: u64,
+    pub text: String,
+}
+
+/// Time range of a `SessionIndex`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexRange {
+    pub start_us: u64,
+    pub end_us: u64,
+}
+
+impl SessionIndex {
+    /// Index of a session's manifest
+    ///
+    /// `end_us` is the end of the recording in session time, like marker
+    /// timestamps; without it the metadata's duration is used, and without
+    /// either the last chapter ends where it starts and there are no talk
+    /// ranges.
+    pub fn from_manifest(manifest: &ChunkManifest, end_us: Option<u64>) -> Self {
+        let origin = file_origin(manifest);
+        let metadata = manifest.metadata.as_ref();
+        let duration_us = end_us
+            .map(|end| end.saturating_sub(origin))
+            .or_else(|| metadata.and_then(|metadata| metadata.duration_us));
+
+        let mut chapter_starts = Vec::new();
+        let mut caption_starts = Vec::new();
+        let mut silence = Vec::new();
+        let mut silent_since = None;
+        for marker in &manifest.markers {
+            let at = marker.timestamp_us.saturating_sub(origin);
+            match marker.label.as_deref() {
+                Some(SILENCE_START_LABEL) => silent_since = Some(at),
+                Some(SILENCE_END_LABEL) => {
+                    if let Some(start_us) = silent_since.take() {
+                        silence.push(IndexRange {
+                            start_us,
+                            end_us: at,
+                        });
+                    }
+                }
+                Some(label) if !label.is_empty() && !INTERNAL_LABELS.contains(&label) => {
+                    if marker.bookmark.is_some() {
+                        chapter_starts.push((at, label));
+                    } else {
+                        caption_starts.push((at, label));
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Still silent when the recording stopped
+        if let (Some(start_us), Some(end_us)) = (silent_since, duration_us) {
+            if start_us < end_us {
+                silence.push(IndexRange { start_us, end_us });
+            }
+        }
+
+        let chapters: Vec<IndexEntry> = (chapter_starts.iter().enumerate())
+            .map(|(i, &(start_us, text))| IndexEntry {
+                start_us,
+                end_us: (chapter_starts.get(i + 1).map(|&(next, _)| next))
+                    .or(duration_us)
+                    .unwrap_or(start_us)
+                    .max(start_us),
+                text: text.to_string(),
+            })
+            .collect();
+        let captions: Vec<IndexEntry> = label_cues(&caption_starts, duration_us)
+            .into_iter()
+            .map(|cue| IndexEntry {
+                start_us: cue.start_us,
+                end_us: cue.end_us,
+                text: cue.text,
+            })
+            .collect();
+
+        let mut talk = Vec::new();
+        if let Some(end_us) = duration_us {
+            let mut from = 0;
+            for range in &silence {
+                if range.start_us > from {
+                    talk.push(IndexRange {
+                        start_us: from,
+                        end_us: range.start_us.min(end_us),
+                    });
+                }
+                from = from.max(range.end_us);
+            }
+            if from < end_us {
+                talk.push(IndexRange {
+                    start_us: from,
+                    end_us,
+                });
+            }
+        }
+
+        let title = metadata.and_then(|metadata| metadata.display_name.clone());
+        let participant = metadata.and_then(|metadata| metadata.participant_name.clone());
+        let mut terms: Vec<String> = (title.iter().chain(&participant))
+            .map(String::as_str)
+            .chain(
+                chapters
+                    .iter()
+                    .chain(&captions)
+                    .map(|entry| entry.text.as_str()),
+            )
+            .flat_map(search_terms)
+            .collect();
+        terms.sort_unstable();
+        terms.dedup();
+
+        Self {
+            version: SEARCH_INDEX_VERSION,
+            session_id: manifest.session_id.clone(),
+            title,
+            participant,
+            duration_us,
+            chapters,
+            captions,
+            silence,
+            talk,
+            terms,
+        }
+    }
+
+    /// Whether every word of `query` is part of one of the terms; an empty
+    /// query matches everything
+    pub fn matches(&self, query: &str) -> bool {
+        search_terms(query).all(|word| self.terms.iter().any(|term| term.contains(&word)))
+    }
+}
+
+/// Lowercase words of a text, split at anything but letters and digits
+pub fn search_terms(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{ChunkId, ChunkMetadata, TrackKind};
+    use crate::manifest::{Bookmark, Marker};
+    use crate::metadata::RecordingMetadata;
+
+    fn marker(timestamp_us: u64, label: &str, bookmark: bool) -> Marker {
+        Marker {
+            timestamp_us,
+            label: Some(label.to_string()),
+            bookmark: bookmark.then(Bookmark::default),
+        }
+    }
+
+    #[test]
+    fn test_session_index_from_manifest() {
+        let session = SessionId::from("5f0c7d7e-2a61-4b8e-9d55-0c8f1c1e2b3a");
+        let mut manifest = ChunkManifest::new(session.clone());
+        manifest
+            .add_chunk(ChunkMetadata {
+                chunk_id: ChunkId::new(session, TrackKind::Video, 0),
+                timestamp_us: 1_000_000,
+                size: 100,
+                hash: None,
+                has_keyframe: Some(true),
+                created_at: 0,
+            })
+            .unwrap();
+        manifest.metadata = Some(RecordingMetadata {
+            display_name: Some("Weekly Sync".into()),
+            participant_name: Some("Aiko".into()),
+            ..Default::default()
+        });
+        manifest.markers = vec![
+            marker(1_000_000, "Intro", true),
+            marker(2_000_000, SILENCE_START_LABEL, false),
+            marker(3_000_000, SILENCE_END_LABEL, false),
+            marker(4_000_000, "Budget: Q3 review", false),
+            marker(5_000_000, "Roadmap", true),
+            marker(9_000_000, SILENCE_START_LABEL, false),
+        ];
+
+        let index = SessionIndex::from_manifest(&manifest, Some(11_000_000));
+        assert_eq!(index.duration_us, Some(10_000_000));
+        let chapters: Vec<_> = (index.chapters.iter())
+            .map(|c| (c.start_us, c.end_us, c.text.as_str()))
+            .collect();
+        assert_eq!(
+            chapters,
+            [(0, 4_000_000, "Intro"), (4_000_000, 10_000_000, "Roadmap")]
+        );
+        assert_eq!(index.captions.len(), 1);
+        assert_eq!(index.captions[0].start_us, 3_000_000);
+        assert_eq!(index.captions[0].end_us, 6_000_000);
+        let ranges = |ranges: &[IndexRange]| -> Vec<(u64, u64)> {
+            ranges.iter().map(|r| (r.start_us, r.end_us)).collect()
+        };
+        assert_eq!(
+            ranges(&index.silence),
+            [(1_000_000, 2_000_000), (8_000_000, 10_000_000)]
+        );
+        assert_eq!(
+            ranges(&index.talk),
+            [(0, 1_000_000), (2_000_000, 8_000_000)]
+        );
+        assert_eq!(
+            index.terms,
+            ["aiko", "budget", "intro", "q3", "review", "roadmap", "sync", "weekly"]
+        );
+
+        assert!(index.matches("road budg"));
+        assert!(index.matches("  "));
+        assert!(!index.matches("roadmap retro"));
+
+        let json = serde_json::to_value(&index).unwrap();
+        assert_eq!(json["chapters"][1]["startUs"], 4_000_000);
+        assert_eq!(serde_json::from_value::<SessionIndex>(json).unwrap(), index);
+    }
+}
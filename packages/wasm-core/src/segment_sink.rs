@@ -0,0 +1,669 @@
+//! Output destinations for muxed fMP4.
+//!
+//! `MuxideMuxerState` hands its init segment and every finished media segment
+//! (moof + mdat) to a `SegmentSink` as soon as they are built. `BufferedSink`
+//! keeps them in memory until taken (`get_pending_segments`,
+//! `take_pending_chunks`); the other backends pass them on without another
+//! copy: `CallbackSink` to a closure, `WritableStreamSink` to a WHATWG
+//! `WritableStream` and `OpfsSink` to a file in the Origin Private File System.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use js_sys::{Object, Promise, Reflect, Uint8Array};
+use wasm_bindgen::prelude::Closure;
+use wasm_bindgen::JsValue;
+use web_sys::{WritableStream, WritableStreamDefaultWriter};
+
+use crate::clock::now_ms;
+use crate::logging::{log_event, LogLevel};
+use crate::session::SessionId;
+use crate::storage::{js_error, OpfsStore};
+
+/// A finished media segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaSegment {
+    /// Position among all segments of the muxer, from 0
+    pub sequence: u64,
+    /// Input timestamp (microseconds) of the first sample pushed into it
+    pub start_us: u64,
+    /// Whether it contains a video sync sample
+    pub has_keyframe: bool,
+    /// moof + mdat
+    pub data: Vec<u8>,
+}
+
+/// Destination of the muxer output
+///
+/// Calls come in order: `write_init` once when the muxer is initialized, then
+/// `write_segment` for each segment, then `finalize` once from
+/// `MuxideMuxerState::finish`. An error fails the push or flush that produced
+/// the segment; the segment is not retried.
+///
+/// `write_init` comes again after `MuxideMuxerState::change_audio_config`,
+/// with an init segment that replaces the first one and still describes all
+/// segments written before.
+pub trait SegmentSink {
+    /// Take the init segment (ftyp + moov), or a replacement for it
+    fn write_init(&mut self, init: &[u8]) -> Result<(), String>;
+
+    /// Take a finished media segment
+    fn write_segment(&mut self, segment: MediaSegment) -> Result<(), String>;
+
+    /// End of output: no more segments follow
+    fn finalize(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Segments written but still held by the sink, for `MuxerStats`
+    fn buffered_segments(&self) -> usize {
+        0
+    }
+
+    /// Bytes of those segments, for telemetry
+    fn buffered_bytes(&self) -> u64 {
+        0
+    }
+
+    /// Position in the output file of the next segment, written into its
+    /// tfhd under `DataOffsetMode::Absolute`
+    ///
+    /// `init_len` and `segments_len` are the sizes of the init segment and
+    /// of all segments written so far. The default places them back to back,
+    /// as `get_complete_file` does; sinks that lay the file out differently
+    /// override it.
+    fn segment_offset(&self, init_len: u64, segments_len: u64) -> u64 {
+        init_len + segments_len
+    }
+
+    /// Whether a segment is done with once `write_segment` returns, for the
+    /// muxer's latency report
+    ///
+    /// Sinks that finish writes later report them through
+    /// `take_acknowledged`; `BufferedSink` segments are acknowledged when
+    /// taken.
+    fn acknowledges_on_write(&self) -> bool {
+        true
+    }
+
+    /// Sequences of the segments whose write completed since the last call,
+    /// with the Unix time (ms) it completed
+    fn take_acknowledged(&mut self) -> Vec<(u64, u64)> {
+        Vec::new()
+    }
+}
+
+impl<S: SegmentSink + ?Sized> SegmentSink for Box<S> {
+    fn write_init(&mut self, init: &[u8]) -> Result<(), String> {
+        (**self).write_init(init)
+    }
+
+    fn write_segment(&mut self, segment: MediaSegment) -> Result<(), String> {
+        (**self).write_segment(segment)
+    }
+
+    fn finalize(&mut self) -> Result<(), String> {
+        (**self).finalize()
+    }
+
+    fn buffered_segments(&self) -> usize {
+        (**self).buffered_segments()
+    }
+
+    fn buffered_bytes(&self) -> u64 {
+        (**self).buffered_bytes()
+    }
+
+    fn segment_offset(&self, init_len: u64, segments_len: u64) -> u64 {
+        (**self).segment_offset(init_len, segments_len)
+    }
+
+    fn acknowledges_on_write(&self) -> bool {
+        (**self).acknowledges_on_write()
+    }
+
+    fn take_acknowledged(&mut self) -> Vec<(u64, u64)> {
+        (**self).take_acknowledged()
+    }
+}
+
+/// Keeps segments in memory until they are taken
+///
+/// The init segment is not copied: the muxer keeps its own.
+#[derive(Debug, Default)]
+pub struct BufferedSink {
+    segments: Vec<MediaSegment>,
+    bytes: u64,
+}
+
+impl BufferedSink {
+    /// Segments not taken yet, oldest first
+    pub fn segments(&self) -> &[MediaSegment] {
+        &self.segments
+    }
+
+    /// Take all buffered segments
+    pub fn take(&mut self) -> Vec<MediaSegment> {
+        self.bytes = 0;
+        std::mem::take(&mut self.segments)
+    }
+}
+
+impl SegmentSink for BufferedSink {
+    fn write_init(&mut self, _init: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn write_segment(&mut self, segment: MediaSegment) -> Result<(), String> {
+        self.bytes += segment.data.len() as u64;
+        self.segments.push(segment);
+        Ok(())
+    }
+
+    fn buffered_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    fn buffered_bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    fn acknowledges_on_write(&self) -> bool {
+        false
+    }
+}
+
+/// Output passed to a `CallbackSink`
+#[derive(Debug)]
+pub enum SinkOutput<'a> {
+    Init(&'a [u8]),
+    Segment(MediaSegment),
+    Finalize,
+}
+
+/// Hands every output to a closure
+pub struct CallbackSink<F> {
+    callback: F,
+}
+
+impl<F> CallbackSink<F>
+where
+    F: FnMut(SinkOutput<'_>) -> Result<(), String>,
+{
+    pub fn new(callback: F) -> Self {
+        Self { callback }
+    }
+}
+
+impl<F> SegmentSink for CallbackSink<F>
+where
+    F: FnMut(SinkOutput<'_>) -> Result<(), String>,
+{
+    fn write_init(&mut self, init: &[u8]) -> Result<(), String> {
+        (self.callback)(SinkOutput::Init(init))
+    }
+
+    fn write_segment(&mut self, segment: MediaSegment) -> Result<(), String> {
+        (self.callback)(SinkOutput::Segment(segment))
+    }
+
+    fn finalize(&mut self) -> Result<(), String> {
+        (self.callback)(SinkOutput::Finalize)
+    }
+}
+
+/// Writes the output as one byte stream to a WHATWG `WritableStream`
+///
+/// Writes are queued by the stream in call order without awaiting each one,
+/// so the muxer never blocks; `finalize` closes the stream. The first write
+/// that fails is reported by the next call, which fails with it, and by
+/// `closed()`. The init segment heads the stream and cannot be replaced once
+/// written. Segments are acknowledged when the stream reports them written,
+/// which it does in order; until then they count as buffered.
+///
+/// The stream's queue is not bounded by the sink: once its `desiredSize`
+/// drops to zero or below the stream applies backpressure, and the host
+/// should wait for the writer's `ready` before pushing more.
+pub struct WritableStreamSink {
+    writer: WritableStreamDefaultWriter,
+    init_written: bool,
+    acks: Rc<RefCell<StreamAcks>>,
+    on_written: Closure<dyn FnMut(JsValue)>,
+    on_failed: Closure<dyn FnMut(JsValue)>,
+    /// Whether the last segment was written under backpressure
+    backpressure: bool,
+}
+
+/// Segment writes of a `WritableStreamSink` in flight and completed
+#[derive(Default)]
+struct StreamAcks {
+    /// Sequence and size of each segment write in flight
+    in_flight: VecDeque<(u64, u64)>,
+    in_flight_bytes: u64,
+    written: Vec<(u64, u64)>,
+    /// First write that failed
+    error: Option<String>,
+}
+
+impl WritableStreamSink {
+    /// Lock `stream` for writing
+    pub fn new(stream: &WritableStream) -> Result<Self, String> {
+        let writer = stream.get_writer().map_err(js_error)?;
+        let acks = Rc::new(RefCell::new(StreamAcks::default()));
+        let on_written = {
+            let acks = acks.clone();
+            Closure::new(move |_| {
+                let mut acks = acks.borrow_mut();
+                if let Some((sequence, len)) = acks.in_flight.pop_front() {
+                    acks.in_flight_bytes -= len;
+                    acks.written.push((sequence, now_ms()));
+                }
+            })
+        };
+        let on_failed = {
+            let acks = acks.clone();
+            Closure::new(move |error| {
+                let mut acks = acks.borrow_mut();
+                if acks.error.is_none() {
+                    acks.error = Some(js_error(error));
+                }
+            })
+        };
+        Ok(Self {
+            writer,
+            init_written: false,
+            acks,
+            on_written,
+            on_failed,
+            backpressure: false,
+        })
+    }
+
+    /// Resolves once the stream is closed, rejects if it errors
+    pub fn closed(&self) -> Promise {
+        self.writer.closed()
+    }
+
+    /// Writer locking the stream, for its `ready` and `desiredSize`
+    pub fn writer(&self) -> WritableStreamDefaultWriter {
+        self.writer.clone()
+    }
+
+    /// Room left in the stream's queue (its `desiredSize`); zero or less
+    /// under backpressure, None once the stream errored
+    fn desired_size(&self) -> Option<f64> {
+        self.writer.desired_size().ok().flatten()
+    }
+
+    /// Fail with the first write that failed, if any
+    fn check(&self) -> Result<(), String> {
+        match &self.acks.borrow().error {
+            Some(error) => Err(format!("Stream write failed: {}", error)),
+            None => Ok(()),
+        }
+    }
+
+    /// Record a failure of `write` for the next call to report
+    fn watch(&self, write: &Promise) {
+        let _ = write.catch(&self.on_failed);
+    }
+
+    fn write(&mut self, data: &[u8]) -> Promise {
+        let chunk: JsValue = Uint8Array::from(data).into();
+        self.writer.write_with_chunk(&chunk)
+    }
+
+    /// Queue a `FileSystemWritableFileStream` command, e.g. a `write` at a
+    /// position or a `seek`
+    fn command(&mut self, params: &[(&str, JsValue)]) -> Promise {
+        let chunk = Object::new();
+        for (key, value) in params {
+            let _ = Reflect::set(&chunk, &JsValue::from_str(key), value);
+        }
+        self.writer.write_with_chunk(&chunk)
+    }
+}
+
+impl SegmentSink for WritableStreamSink {
+    fn write_init(&mut self, init: &[u8]) -> Result<(), String> {
+        self.check()?;
+        if self.init_written {
+            return Err("The init segment of a stream cannot be replaced".to_string());
+        }
+        self.init_written = true;
+        let write = self.write(init);
+        self.watch(&write);
+        Ok(())
+    }
+
+    fn write_segment(&mut self, segment: MediaSegment) -> Result<(), String> {
+        self.check()?;
+        let backpressure = self.desired_size().is_some_and(|size| size <= 0.0);
+        if backpressure && !self.backpressure {
+            log_event!(
+                LogLevel::Warn,
+                "Stream is applying backpressure",
+                sequence = segment.sequence,
+                in_flight_bytes = self.acks.borrow().in_flight_bytes,
+            );
+        }
+        self.backpressure = backpressure;
+        let len = segment.data.len() as u64;
+        let write = self.write(&segment.data);
+        {
+            let mut acks = self.acks.borrow_mut();
+            acks.in_flight.push_back((segment.sequence, len));
+            acks.in_flight_bytes += len;
+        }
+        let _ = write.then2(&self.on_written, &self.on_failed);
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<(), String> {
+        self.check()?;
+        let close = self.writer.close();
+        self.watch(&close);
+        Ok(())
+    }
+
+    fn buffered_segments(&self) -> usize {
+        self.acks.borrow().in_flight.len()
+    }
+
+    fn buffered_bytes(&self) -> u64 {
+        self.acks.borrow().in_flight_bytes
+    }
+
+    fn acknowledges_on_write(&self) -> bool {
+        false
+    }
+
+    fn take_acknowledged(&mut self) -> Vec<(u64, u64)> {
+        std::mem::take(&mut self.acks.borrow_mut().written)
+    }
+}
+
+/// Streams the output into one file of a session directory in OPFS
+///
+/// The file is written through a writable file stream, so it only replaces
+/// any previous file of that name once `finalize` closes the stream. A new
+/// init segment of the same size (see `MuxideConfig::moov_reserved_size`)
+/// is written over the old one in place.
+pub struct OpfsSink {
+    stream: WritableStreamSink,
+    /// Size of the init segment written first
+    init_len: Option<usize>,
+    /// Bytes written so far
+    len: u64,
+}
+
+impl OpfsSink {
+    /// Create (or truncate) `name` in the directory of `session`
+    pub async fn create(
+        store: &OpfsStore,
+        session: &SessionId,
+        name: &str,
+    ) -> Result<Self, String> {
+        let writable = store.create_writable(session, name).await?;
+        Ok(Self {
+            stream: WritableStreamSink::new(&writable)?,
+            init_len: None,
+            len: 0,
+        })
+    }
+
+    /// Resolves once the file is written, rejects if a write failed
+    pub fn closed(&self) -> Promise {
+        self.stream.closed()
+    }
+
+    /// Writer of the file stream, for its backpressure
+    pub fn writer(&self) -> WritableStreamDefaultWriter {
+        self.stream.writer()
+    }
+}
+
+impl SegmentSink for OpfsSink {
+    fn write_init(&mut self, init: &[u8]) -> Result<(), String> {
+        match self.init_len {
+            None => {
+                self.init_len = Some(init.len());
+                self.len += init.len() as u64;
+                self.stream.write_init(init)
+            }
+            Some(len) if len == init.len() => {
+                self.stream.check()?;
+                let data: JsValue = Uint8Array::from(init).into();
+                let write = self.stream.command(&[
+                    ("type", "write".into()),
+                    ("position", 0.into()),
+                    ("data", data),
+                ]);
+                self.stream.watch(&write);
+                // Segments continue at the end of the file
+                let seek = self.stream.command(&[
+                    ("type", "seek".into()),
+                    ("position", (self.len as f64).into()),
+                ]);
+                self.stream.watch(&seek);
+                Ok(())
+            }
+            Some(len) => Err(format!(
+                "The init segment grew from {} to {} bytes and cannot be rewritten in place",
+                len,
+                init.len()
+            )),
+        }
+    }
+
+    fn write_segment(&mut self, segment: MediaSegment) -> Result<(), String> {
+        let len = segment.data.len() as u64;
+        self.stream.write_segment(segment)?;
+        self.len += len;
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<(), String> {
+        self.stream.finalize()
+    }
+
+    fn buffered_segments(&self) -> usize {
+        self.stream.buffered_segments()
+    }
+
+    fn buffered_bytes(&self) -> u64 {
+        self.stream.buffered_bytes()
+    }
+
+    fn acknowledges_on_write(&self) -> bool {
+        false
+    }
+
+    fn take_acknowledged(&mut self) -> Vec<(u64, u64)> {
+        self.stream.take_acknowledged()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ClockHandle, ManualClock};
+    use crate::muxide_muxer::{MuxideConfig, MuxideMuxerState};
+
+    fn config() -> MuxideConfig {
+        MuxideConfig {
+            sps: Some(vec![
+                0x67, 0x42, 0xC0, 0x1E, 0xD9, 0x00, 0x50, 0x05, 0xBA, 0x10,
+            ]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            ..Default::default()
+        }
+    }
+
+    fn push_frames<S: SegmentSink>(muxer: &mut MuxideMuxerState<S>) -> Result<(), String> {
+        for i in 0..150u64 {
+            let data = [0x00, 0x00, 0x00, 0x01, 0x41];
+            muxer.push_video_chunk(&data, i * 33333, i % 30 == 0)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_callback_sink_matches_buffered_output() {
+        let mut buffered = MuxideMuxerState::new(config());
+        buffered.init().unwrap();
+        push_frames(&mut buffered).unwrap();
+        let expected = buffered.get_complete_file().unwrap();
+
+        let mut output = Vec::new();
+        let mut sequences = Vec::new();
+        let mut finalized = false;
+        let sink = CallbackSink::new(|out: SinkOutput<'_>| {
+            match out {
+                SinkOutput::Init(data) => output.extend_from_slice(data),
+                SinkOutput::Segment(segment) => {
+                    sequences.push(segment.sequence);
+                    output.extend(segment.data);
+                }
+                SinkOutput::Finalize => finalized = true,
+            }
+            Ok(())
+        });
+        let mut muxer = MuxideMuxerState::with_sink(config(), sink);
+        muxer.init().unwrap();
+        push_frames(&mut muxer).unwrap();
+        assert_eq!(muxer.stats().pending_segment_count, 0);
+        muxer.finish().unwrap();
+        drop(muxer);
+
+        assert!(finalized);
+        assert_eq!(sequences, [0, 1, 2]);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_sink_error_fails_the_push() {
+        let sink = CallbackSink::new(|out: SinkOutput<'_>| match out {
+            SinkOutput::Segment(_) => Err("disk full".to_string()),
+            _ => Ok(()),
+        });
+        let mut muxer = MuxideMuxerState::with_sink(config(), sink);
+        muxer.init().unwrap();
+        let err = push_frames(&mut muxer).unwrap_err();
+        assert!(err.contains("disk full"), "{}", err);
+        assert_eq!(muxer.stats().segment_count, 1);
+    }
+
+    #[test]
+    fn test_failed_init_rewrite_changes_nothing() {
+        let mut inits = 0;
+        let sink = CallbackSink::new(|out: SinkOutput<'_>| match out {
+            SinkOutput::Init(_) => {
+                inits += 1;
+                if inits > 1 {
+                    Err("disk full".to_string())
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        });
+        let mut muxer = MuxideMuxerState::with_sink(config(), sink);
+        muxer.init().unwrap();
+        let init = muxer.get_init_segment().unwrap();
+
+        let err = muxer
+            .set_session_metadata(Some("{}".to_string()))
+            .unwrap_err();
+        assert!(err.contains("disk full"), "{}", err);
+        assert_eq!(muxer.config().session_metadata, None);
+        assert_eq!(muxer.get_init_segment().unwrap(), init);
+
+        let mut inits = 0;
+        let sink = CallbackSink::new(|out: SinkOutput<'_>| match out {
+            SinkOutput::Init(_) => {
+                inits += 1;
+                if inits > 1 {
+                    Err("disk full".to_string())
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        });
+        let audio = MuxideConfig {
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            ..config()
+        };
+        let mut muxer = MuxideMuxerState::with_sink(audio, sink);
+        muxer.init().unwrap();
+        let init = muxer.get_init_segment().unwrap();
+        let err = muxer.change_audio_config(44100, 1, None).unwrap_err();
+        assert!(err.contains("disk full"), "{}", err);
+        assert_eq!(muxer.config().audio_sample_rate, Some(48000));
+        assert!(muxer.config().previous_audio_configs.is_empty());
+        assert_eq!(muxer.get_init_segment().unwrap(), init);
+    }
+
+    #[test]
+    fn test_segments_acknowledged_on_write_or_take() {
+        let clock = ManualClock::new(1_700_000_000_000);
+        let sink = CallbackSink::new(|_: SinkOutput<'_>| Ok(()));
+        let mut streaming = MuxideMuxerState::with_sink(config(), sink);
+        streaming.set_clock(ClockHandle::new(clock.clone()));
+        let mut buffered = MuxideMuxerState::new(config());
+        buffered.set_clock(ClockHandle::new(clock.clone()));
+        streaming.init().unwrap();
+        buffered.init().unwrap();
+        for i in 0..150u64 {
+            let data = [0x00, 0x00, 0x00, 0x01, 0x41];
+            clock.set(1_700_000_000_000 + i * 33333 / 1000);
+            streaming
+                .push_video_chunk(&data, i * 33333, i % 30 == 0)
+                .unwrap();
+            buffered
+                .push_video_chunk(&data, i * 33333, i % 30 == 0)
+                .unwrap();
+        }
+
+        let report = streaming.latency_report();
+        assert_eq!((report.emit.count, report.ack.count), (2, 2));
+        assert_eq!(report.unacknowledged, 0);
+        // Emitted with its last frame, which arrives when it starts
+        assert_eq!(report.segments[0].end_us, 2_066_644);
+        assert_eq!(report.segments[0].emit_latency_ms, -33);
+
+        clock.advance(500);
+        let report = buffered.latency_report();
+        assert_eq!(report.unacknowledged, 2);
+        assert_eq!(report.oldest_unacknowledged_ms, Some(3_433));
+        buffered.get_pending_segments();
+        let report = buffered.latency_report();
+        assert_eq!((report.ack.count, report.unacknowledged), (2, 0));
+        assert_eq!(report.segments[1].ack_latency_ms, Some(1_333));
+    }
+
+    #[test]
+    fn test_buffered_sink_keeps_sequences_across_restore() {
+        let mut muxer = MuxideMuxerState::new(config());
+        muxer.init().unwrap();
+        push_frames(&mut muxer).unwrap();
+        muxer.get_pending_segments();
+        muxer.force_flush().unwrap();
+
+        let restored = MuxideMuxerState::restore_state(&muxer.serialize_state().unwrap()).unwrap();
+        let sequences: Vec<u64> = restored
+            .sink()
+            .segments()
+            .iter()
+            .map(|s| s.sequence)
+            .collect();
+        assert_eq!(sequences, [2]);
+        assert_eq!(
+            restored.sink().buffered_bytes(),
+            muxer.sink().buffered_bytes()
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Session identity and lifecycle shared by chunks, manifests and storage.
+//!
+//! A session corresponds to one recording on the TypeScript side
+//! (`RecordingId` / `RecordingState` in `@maycast/common-types`).
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+/// Unique identifier for a recording session (UUID string)
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Tsify,
+)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(transparent)]
+pub struct SessionId(String);
+
+impl SessionId {
+    /// Create a SessionId from an existing identifier string
+    pub fn new(id: impl Into<String>) -> Self {
+        Self(id.into())
+    }
+
+    /// Get the identifier as a string slice
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns true if no identifier has been assigned (legacy data)
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for SessionId {
+    fn from(id: String) -> Self {
+        Self(id)
+    }
+}
+
+impl From<&str> for SessionId {
+    fn from(id: &str) -> Self {
+        Self(id.to_string())
+    }
+}
+
+/// Session lifecycle state
+///
+/// Mirrors `RecordingState` in `@maycast/common-types`:
+/// `standby → recording → finalizing → synced`, plus `interrupted` from any
+/// non-terminal state for crash recovery.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionState {
+    #[default]
+    Standby,
+    Recording,
+    Finalizing,
+    Synced,
+    Interrupted,
+}
+
+impl SessionState {
+    /// Returns true if no further transitions are allowed
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, SessionState::Synced | SessionState::Interrupted)
+    }
+
+    /// Check whether moving from this state to `next` is a valid transition
+    pub fn can_transition_to(&self, next: SessionState) -> bool {
+        match next {
+            SessionState::Standby => false,
+            SessionState::Recording => *self == SessionState::Standby,
+            SessionState::Finalizing => *self == SessionState::Recording,
+            SessionState::Synced => *self == SessionState::Finalizing,
+            SessionState::Interrupted => !self.is_terminal(),
+        }
+    }
+
+    /// Move to `next`, failing if the transition is not allowed
+    pub fn transition_to(&mut self, next: SessionState) -> Result<(), String> {
+        if !self.can_transition_to(next) {
+            return Err(format!(
+                "Invalid session state transition: {} -> {}",
+                self, next
+            ));
+        }
+        *self = next;
+        Ok(())
+    }
+}
+
+impl fmt::Display for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SessionState::Standby => "standby",
+            SessionState::Recording => "recording",
+            SessionState::Finalizing => "finalizing",
+            SessionState::Synced => "synced",
+            SessionState::Interrupted => "interrupted",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_state_happy_path() {
+        let mut state = SessionState::default();
+        state.transition_to(SessionState::Recording).unwrap();
+        state.transition_to(SessionState::Finalizing).unwrap();
+        state.transition_to(SessionState::Synced).unwrap();
+        assert!(state.is_terminal());
+    }
+
+    #[test]
+    fn test_session_state_invalid_transitions() {
+        let mut state = SessionState::Standby;
+        let result = state.transition_to(SessionState::Synced);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .contains("Invalid session state transition: standby -> synced"));
+
+        assert!(SessionState::Recording.can_transition_to(SessionState::Interrupted));
+        assert!(!SessionState::Synced.can_transition_to(SessionState::Interrupted));
+        assert!(!SessionState::Interrupted.can_transition_to(SessionState::Recording));
+    }
+
+    #[test]
+    fn test_session_state_serde_matches_typescript() {
+        let json = serde_json::to_string(&SessionState::Finalizing).unwrap();
+        assert_eq!(json, r#""finalizing""#);
+        let state: SessionState = serde_json::from_str(r#""interrupted""#).unwrap();
+        assert_eq!(state, SessionState::Interrupted);
+    }
+}
@@ -0,0 +1,259 @@
+//! Sustained silence detection.
+//!
+//! Audio is classified frame by frame, either from a measured level (RMS in
+//! dBFS, e.g. from `LoudnessMeter.levels()`) or, without access to PCM, from
+//! the size of the encoded AAC frame: encoders spend only a handful of bytes
+//! on digital silence. Silence is reported once it has lasted
+//! `min_duration_ms`, so the UI can warn about a muted microphone, and again
+//! with its full range when sound comes back. The recorder stores finished
+//! ranges as `silence-start` / `silence-end` markers, which lets playback
+//! jump to where talking starts.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+/// Marker label at the start of a silent range
+pub const SILENCE_START_LABEL: &str = "silence-start";
+/// Marker label where sound resumes after a silent range
+pub const SILENCE_END_LABEL: &str = "silence-end";
+
+/// Silence detection settings
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SilenceConfig {
+    /// Frames at or below this level (dBFS) are silent
+    pub threshold_db: f64,
+    /// Shortest silence worth reporting
+    pub min_duration_ms: u32,
+    /// Encoded AAC frames up to this size count as silent
+    pub max_silent_aac_frame_bytes: u32,
+    /// Classify encoded AAC frames pushed to the recorder; disable when
+    /// levels are pushed from PCM instead
+    pub from_aac_frames: bool,
+}
+
+impl Default for SilenceConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db: -50.0,
+            min_duration_ms: 2000,
+            max_silent_aac_frame_bytes: 16,
+            from_aac_frames: true,
+        }
+    }
+}
+
+/// A silent stretch of the timeline (microseconds)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct SilenceRange {
+    pub start_us: u64,
+    pub end_us: u64,
+}
+
+/// Change in the silence state of the audio
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SilenceChange {
+    /// Audio has been silent since `start_us` for at least the minimum duration
+    Started { start_us: u64 },
+    /// Sound resumed after a reported silence
+    Ended(SilenceRange),
+}
+
+/// Classifies audio frames and tracks silent ranges
+#[derive(Debug, Clone)]
+pub struct SilenceDetector {
+    config: SilenceConfig,
+    silent_since_us: Option<u64>,
+    reported: bool,
+    ranges: Vec<SilenceRange>,
+    first_sound_us: Option<u64>,
+}
+
+impl SilenceDetector {
+    /// Create a detector
+    pub fn new(config: SilenceConfig) -> Self {
+        Self {
+            config,
+            silent_since_us: None,
+            reported: false,
+            ranges: Vec::new(),
+            first_sound_us: None,
+        }
+    }
+
+    /// Settings in use
+    pub fn config(&self) -> &SilenceConfig {
+        &self.config
+    }
+
+    /// Add a frame with a measured level (dBFS)
+    pub fn push_level(
+        &mut self,
+        timestamp_us: u64,
+        duration_us: u64,
+        level_db: f64,
+    ) -> Option<SilenceChange> {
+        let silent = level_db <= self.config.threshold_db;
+        self.push_frame(timestamp_us, duration_us, silent)
+    }
+
+    /// Add an encoded AAC frame, judging silence by its size
+    pub fn push_aac_frame(
+        &mut self,
+        timestamp_us: u64,
+        duration_us: u64,
+        size: usize,
+    ) -> Option<SilenceChange> {
+        let silent = size <= self.config.max_silent_aac_frame_bytes as usize;
+        self.push_frame(timestamp_us, duration_us, silent)
+    }
+
+    /// Close a silence still open at the end of the recording
+    pub fn finish(&mut self, end_us: u64) -> Option<SilenceChange> {
+        let start_us = self.silent_since_us.take()?;
+        if !std::mem::replace(&mut self.reported, false) {
+            // Too short to have been reported; still long enough at the end?
+            if end_us.saturating_sub(start_us) < self.min_duration_us() {
+                return None;
+            }
+        }
+        Some(self.close(start_us, end_us))
+    }
+
+    /// Whether a reported silence is in progress
+    pub fn is_silent(&self) -> bool {
+        self.reported
+    }
+
+    /// Finished silent ranges, in timeline order
+    pub fn ranges(&self) -> &[SilenceRange] {
+        &self.ranges
+    }
+
+    /// Timestamp of the first frame that was not silent
+    pub fn first_sound_us(&self) -> Option<u64> {
+        self.first_sound_us
+    }
+
+    fn push_frame(
+        &mut self,
+        timestamp_us: u64,
+        duration_us: u64,
+        silent: bool,
+    ) -> Option<SilenceChange> {
+        if silent {
+            let start_us = *self.silent_since_us.get_or_insert(timestamp_us);
+            let end_us = timestamp_us + duration_us;
+            if !self.reported && end_us.saturating_sub(start_us) >= self.min_duration_us() {
+                self.reported = true;
+                return Some(SilenceChange::Started { start_us });
+            }
+            return None;
+        }
+
+        self.first_sound_us.get_or_insert(timestamp_us);
+        let start_us = self.silent_since_us.take()?;
+        if std::mem::replace(&mut self.reported, false) {
+            Some(self.close(start_us, timestamp_us))
+        } else {
+            None
+        }
+    }
+
+    fn close(&mut self, start_us: u64, end_us: u64) -> SilenceChange {
+        let range = SilenceRange { start_us, end_us };
+        self.ranges.push(range);
+        SilenceChange::Ended(range)
+    }
+
+    fn min_duration_us(&self) -> u64 {
+        self.config.min_duration_ms as u64 * 1000
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_US: u64 = 21_333;
+
+    #[test]
+    fn test_detects_sustained_silence() {
+        let mut detector = SilenceDetector::new(SilenceConfig {
+            min_duration_ms: 100,
+            ..Default::default()
+        });
+        let mut changes = Vec::new();
+        // 3 silent frames (too short), 2 loud, 10 silent, 1 loud, 6 silent
+        let levels = [
+            [-90.0; 3].as_slice(),
+            &[-10.0; 2],
+            &[-90.0; 10],
+            &[-10.0],
+            &[-90.0; 6],
+        ];
+        let mut ts = 0;
+        for level in levels.concat() {
+            changes.extend(detector.push_level(ts, FRAME_US, level));
+            ts += FRAME_US;
+        }
+        changes.extend(detector.finish(ts));
+
+        let first = SilenceRange {
+            start_us: 5 * FRAME_US,
+            end_us: 15 * FRAME_US,
+        };
+        let last = SilenceRange {
+            start_us: 16 * FRAME_US,
+            end_us: 22 * FRAME_US,
+        };
+        assert_eq!(
+            changes,
+            vec![
+                SilenceChange::Started {
+                    start_us: first.start_us
+                },
+                SilenceChange::Ended(first),
+                SilenceChange::Started {
+                    start_us: last.start_us
+                },
+                SilenceChange::Ended(last),
+            ]
+        );
+        assert_eq!(detector.ranges(), &[first, last]);
+        assert_eq!(detector.first_sound_us(), Some(3 * FRAME_US));
+        assert!(!detector.is_silent());
+    }
+
+    #[test]
+    fn test_aac_frame_heuristic() {
+        let mut detector = SilenceDetector::new(SilenceConfig {
+            min_duration_ms: 50,
+            ..Default::default()
+        });
+        assert_eq!(detector.push_aac_frame(0, FRAME_US, 6), None);
+        assert_eq!(detector.push_aac_frame(FRAME_US, FRAME_US, 6), None,);
+        assert_eq!(
+            detector.push_aac_frame(2 * FRAME_US, FRAME_US, 8),
+            Some(SilenceChange::Started { start_us: 0 })
+        );
+        assert!(detector.is_silent());
+        assert_eq!(
+            detector.push_aac_frame(3 * FRAME_US, FRAME_US, 300),
+            Some(SilenceChange::Ended(SilenceRange {
+                start_us: 0,
+                end_us: 3 * FRAME_US
+            }))
+        );
+        // A silence shorter than the minimum at the end is not reported
+        detector.push_aac_frame(4 * FRAME_US, FRAME_US, 6);
+        assert_eq!(detector.finish(5 * FRAME_US), None);
+
+        let config: SilenceConfig = serde_json::from_str(r#"{"minDurationMs":500}"#).unwrap();
+        assert_eq!(config.min_duration_ms, 500);
+        assert_eq!(config.threshold_db, -50.0);
+    }
+}
@@ -0,0 +1,611 @@
+//! Synthetic recordings for load-testing the recorder pipeline.
+//!
+//! `SyntheticStream` generates encoded-looking frames (AVCC video with
+//! IDR/non-IDR NAL headers, raw AAC-sized audio) on a configurable
+//! schedule: frame rate, keyframe interval, bitrates, timestamp jitter and
+//! periods without frames. The frames carry no decodable picture or sound,
+//! only the sizes, timestamps and keyframe flags the recorder acts on.
+//! `Simulator` feeds them through a `RecorderState` on a `ManualClock` that
+//! follows the media time, so fragmentation, hashing, the stall watchdog,
+//! WAL batches and whatever consumes the events (upload queues, sinks) run
+//! as they would behind real encoders, in a native test or a WASM build.
+//!
+//! Everything is seeded, so a configuration always produces the same
+//! recording.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::chunk::TrackKind;
+use crate::clock::{ClockHandle, ManualClock};
+use crate::metadata::QualityReport;
+use crate::muxide_muxer::MuxideConfig;
+use crate::recorder::{RecorderEvent, RecorderState, RecorderStatus};
+use crate::session::SessionId;
+
+/// AAC frame length in samples
+const AAC_FRAME_SAMPLES: u64 = 1024;
+
+/// Baseline 3.0 parameter sets the synthetic video claims to use
+const SYNTHETIC_SPS: [u8; 8] = [0x67, 0x42, 0xC0, 0x1E, 0xD9, 0x00, 0x50, 0x05];
+const SYNTHETIC_PPS: [u8; 4] = [0x68, 0xCE, 0x3C, 0x80];
+
+/// What the synthetic encoders produce
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SimulationConfig {
+    /// Length of the recording in media time
+    pub duration_ms: u64,
+    pub video: bool,
+    pub frame_rate: f64,
+    /// Frames from one keyframe to the next
+    pub keyframe_interval: u32,
+    /// Also encode a keyframe whenever `RecorderState::keyframe_due` asks
+    /// for one, as the web client does
+    pub follow_keyframe_requests: bool,
+    pub video_bitrate_bps: u32,
+    /// Size of a keyframe relative to the other frames of its GOP
+    pub keyframe_size_ratio: f64,
+    /// Random variation of every frame size, as a fraction (0.2 = ±20%)
+    pub size_variation: f64,
+    pub audio: bool,
+    pub audio_sample_rate: u32,
+    pub audio_bitrate_bps: u32,
+    /// Largest random offset of a video timestamp from its schedule
+    pub jitter_us: u32,
+    /// Periods in which a track delivers no frames
+    pub gaps: Vec<SimulatedGap>,
+    pub fragment_duration_ms: u32,
+    /// Wall-clock time (Unix ms) at media time 0
+    pub start_ms: u64,
+    pub seed: u64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            duration_ms: 10_000,
+            video: true,
+            frame_rate: 30.0,
+            keyframe_interval: 60,
+            follow_keyframe_requests: false,
+            video_bitrate_bps: 2_000_000,
+            keyframe_size_ratio: 4.0,
+            size_variation: 0.2,
+            audio: true,
+            audio_sample_rate: 48_000,
+            audio_bitrate_bps: 128_000,
+            jitter_us: 0,
+            gaps: Vec::new(),
+            fragment_duration_ms: 2000,
+            start_ms: 1_700_000_000_000,
+            seed: 1,
+        }
+    }
+}
+
+impl SimulationConfig {
+    /// Muxer configuration matching the synthetic streams
+    pub fn muxer_config(&self) -> MuxideConfig {
+        MuxideConfig {
+            fragment_duration_ms: self.fragment_duration_ms,
+            sps: self.video.then(|| SYNTHETIC_SPS.to_vec()),
+            pps: self.video.then(|| SYNTHETIC_PPS.to_vec()),
+            audio_sample_rate: self.audio.then_some(self.audio_sample_rate),
+            audio_channels: self.audio.then_some(2),
+            ..Default::default()
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !self.video && !self.audio {
+            return Err("Simulation needs a video or an audio track".to_string());
+        }
+        let frame_rate_valid = self.frame_rate > 0.0 && self.frame_rate.is_finite();
+        if self.video && (!frame_rate_valid || self.keyframe_interval == 0) {
+            return Err("Frame rate and keyframe interval must be positive".to_string());
+        }
+        if self.audio && self.audio_sample_rate == 0 {
+            return Err("Audio sample rate must be positive".to_string());
+        }
+        // Range checks, so NaN fails too
+        let ratio_valid = (1.0..=f64::MAX).contains(&self.keyframe_size_ratio);
+        if !(0.0..1.0).contains(&self.size_variation) || !ratio_valid {
+            return Err("Invalid frame size variation or keyframe size ratio".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A period without frames (media time); `Muxed` silences both tracks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedGap {
+    pub track: TrackKind,
+    pub start_ms: u64,
+    pub duration_ms: u64,
+}
+
+impl SimulatedGap {
+    fn covers(&self, track: TrackKind, timestamp_us: u64) -> bool {
+        (self.track == track || self.track == TrackKind::Muxed)
+            && (self.start_ms * 1000..(self.start_ms + self.duration_ms) * 1000)
+                .contains(&timestamp_us)
+    }
+}
+
+/// One generated frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntheticFrame {
+    /// `Video` or `Audio`
+    pub track: TrackKind,
+    /// Presentation time, jitter included
+    pub timestamp_us: u64,
+    /// Scheduled time, when the encoder delivers the frame
+    pub scheduled_us: u64,
+    pub duration_us: u32,
+    pub is_keyframe: bool,
+    pub data: Vec<u8>,
+}
+
+/// Deterministic SplitMix64 generator
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [-1, 1)
+    fn signed_unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+}
+
+/// Frames of both tracks in delivery order
+#[derive(Debug, Clone)]
+pub struct SyntheticStream {
+    config: SimulationConfig,
+    rng: Rng,
+    video_index: u64,
+    audio_index: u64,
+}
+
+impl SyntheticStream {
+    pub fn new(config: SimulationConfig) -> Result<Self, String> {
+        config.validate()?;
+        Ok(Self {
+            rng: Rng(config.seed),
+            config,
+            video_index: 0,
+            audio_index: 0,
+        })
+    }
+
+    fn video_time_us(&self, index: u64) -> u64 {
+        (index as f64 * 1_000_000.0 / self.config.frame_rate).round() as u64
+    }
+
+    fn audio_time_us(&self, index: u64) -> u64 {
+        index * AAC_FRAME_SAMPLES * 1_000_000 / self.config.audio_sample_rate as u64
+    }
+
+    /// Bytes of a frame averaging `mean`, with the configured variation
+    fn frame_size(&mut self, mean: f64) -> usize {
+        let varied = mean * (1.0 + self.config.size_variation * self.rng.signed_unit());
+        varied.round().max(1.0) as usize
+    }
+
+    fn video_frame(&mut self, index: u64) -> SyntheticFrame {
+        let config = &self.config;
+        let scheduled_us = self.video_time_us(index);
+        let duration_us = (self.video_time_us(index + 1) - scheduled_us) as u32;
+        let is_keyframe = index.is_multiple_of(config.keyframe_interval as u64);
+        // Keyframes take `keyframe_size_ratio` shares of the GOP's bytes
+        let gop_bytes = config.video_bitrate_bps as f64 / 8.0 / config.frame_rate
+            * config.keyframe_interval as f64;
+        let share =
+            gop_bytes / (config.keyframe_size_ratio + config.keyframe_interval as f64 - 1.0);
+        let ratio = if is_keyframe {
+            config.keyframe_size_ratio
+        } else {
+            1.0
+        };
+        let jitter = config.jitter_us as f64;
+        let timestamp_us = if index == 0 {
+            0
+        } else {
+            (scheduled_us as f64 + jitter * self.rng.signed_unit()).max(0.0) as u64
+        };
+
+        let nal_len = self.frame_size(share * ratio).max(2);
+        let mut data = Vec::with_capacity(4 + nal_len);
+        data.extend_from_slice(&(nal_len as u32).to_be_bytes());
+        data.push(if is_keyframe { 0x65 } else { 0x41 });
+        self.fill(&mut data, nal_len - 1);
+        SyntheticFrame {
+            track: TrackKind::Video,
+            timestamp_us,
+            scheduled_us,
+            duration_us,
+            is_keyframe,
+            data,
+        }
+    }
+
+    fn audio_frame(&mut self, index: u64) -> SyntheticFrame {
+        let timestamp_us = self.audio_time_us(index);
+        let duration_us = (self.audio_time_us(index + 1) - timestamp_us) as u32;
+        let mean = self.config.audio_bitrate_bps as f64 / 8.0 * duration_us as f64 / 1_000_000.0;
+        let len = self.frame_size(mean);
+        let mut data = Vec::with_capacity(len);
+        self.fill(&mut data, len);
+        SyntheticFrame {
+            track: TrackKind::Audio,
+            timestamp_us,
+            scheduled_us: timestamp_us,
+            duration_us,
+            is_keyframe: true,
+            data,
+        }
+    }
+
+    fn fill(&mut self, data: &mut Vec<u8>, len: usize) {
+        let end = data.len() + len;
+        while data.len() < end {
+            let bytes = self.rng.next_u64().to_le_bytes();
+            let take = (end - data.len()).min(bytes.len());
+            data.extend_from_slice(&bytes[..take]);
+        }
+    }
+
+    fn in_gap(&self, track: TrackKind, timestamp_us: u64) -> bool {
+        self.config
+            .gaps
+            .iter()
+            .any(|gap| gap.covers(track, timestamp_us))
+    }
+}
+
+impl Iterator for SyntheticStream {
+    type Item = SyntheticFrame;
+
+    fn next(&mut self) -> Option<SyntheticFrame> {
+        let end_us = self.config.duration_ms * 1000;
+        loop {
+            let video_us = self
+                .config
+                .video
+                .then(|| self.video_time_us(self.video_index))
+                .filter(|&us| us < end_us);
+            let audio_us = self
+                .config
+                .audio
+                .then(|| self.audio_time_us(self.audio_index))
+                .filter(|&us| us < end_us);
+            let frame = match (video_us, audio_us) {
+                (Some(video), Some(audio)) if video <= audio => self.next_video(),
+                (Some(_), None) => self.next_video(),
+                (_, Some(_)) => self.next_audio(),
+                (None, None) => return None,
+            };
+            // Frames are generated even in gaps, so sizes and jitter do not
+            // depend on where the gaps are
+            if !self.in_gap(frame.track, frame.scheduled_us) {
+                return Some(frame);
+            }
+        }
+    }
+}
+
+impl SyntheticStream {
+    fn next_video(&mut self) -> SyntheticFrame {
+        self.video_index += 1;
+        self.video_frame(self.video_index - 1)
+    }
+
+    fn next_audio(&mut self) -> SyntheticFrame {
+        self.audio_index += 1;
+        self.audio_frame(self.audio_index - 1)
+    }
+}
+
+/// What a simulated recording produced
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationReport {
+    pub video_frames: u32,
+    pub audio_frames: u32,
+    /// Frames the recorder took (not dropped or skipped)
+    pub accepted_frames: u32,
+    pub init_segment_bytes: u64,
+    pub chunk_count: u32,
+    /// Bytes of all chunks
+    pub chunk_bytes: u64,
+    pub min_chunk_bytes: u64,
+    pub max_chunk_bytes: u64,
+    /// Longest time between the starts of consecutive chunks
+    pub max_chunk_interval_us: u64,
+    /// Chunks starting with a video keyframe
+    pub keyframe_chunks: u32,
+    pub stalls: u32,
+    pub wal_batches: u32,
+    pub quality: QualityReport,
+}
+
+impl SimulationReport {
+    fn note(&mut self, event: &RecorderEvent, last_chunk_us: &mut Option<u64>) {
+        match event {
+            RecorderEvent::ChunkReady(chunk) => {
+                let size = chunk.data.len() as u64;
+                self.min_chunk_bytes = if self.chunk_count == 0 {
+                    size
+                } else {
+                    self.min_chunk_bytes.min(size)
+                };
+                self.chunk_count += 1;
+                self.chunk_bytes += size;
+                self.max_chunk_bytes = self.max_chunk_bytes.max(size);
+                if chunk.metadata.has_keyframe == Some(true) {
+                    self.keyframe_chunks += 1;
+                }
+                let start = chunk.metadata.timestamp_us;
+                if let Some(last) = last_chunk_us.replace(start) {
+                    self.max_chunk_interval_us =
+                        self.max_chunk_interval_us.max(start.saturating_sub(last));
+                }
+            }
+            RecorderEvent::StreamStalled { .. } => self.stalls += 1,
+            RecorderEvent::WalBatch(_) => self.wal_batches += 1,
+            RecorderEvent::AudioConfigChanged { init_segment, .. }
+            | RecorderEvent::InitSegmentChanged(init_segment) => {
+                self.init_segment_bytes = init_segment.len() as u64;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Drives a `RecorderState` with a `SyntheticStream`
+///
+/// Configure the recorder through `recorder_mut` before the first `step`
+/// (WAL, watchdog, hashing, encryption...); `step` starts it, pushes one
+/// frame at a time with the clock at the frame's delivery time and stops it
+/// after the last one.
+pub struct Simulator {
+    recorder: RecorderState,
+    stream: SyntheticStream,
+    clock: ManualClock,
+    start_ms: u64,
+    follow_keyframe_requests: bool,
+    report: SimulationReport,
+    last_chunk_us: Option<u64>,
+    #[cfg(feature = "fault-injection")]
+    timestamp_faults: Option<crate::fault::TimestampFaults>,
+}
+
+impl Simulator {
+    pub fn new(session_id: SessionId, config: SimulationConfig) -> Result<Self, String> {
+        let muxer_config = config.muxer_config();
+        let clock = ManualClock::new(config.start_ms);
+        let mut recorder = RecorderState::new(session_id, muxer_config);
+        recorder.set_clock(ClockHandle::new(clock.clone()));
+        Ok(Self {
+            recorder,
+            start_ms: config.start_ms,
+            follow_keyframe_requests: config.follow_keyframe_requests,
+            stream: SyntheticStream::new(config)?,
+            clock,
+            report: SimulationReport::default(),
+            last_chunk_us: None,
+            #[cfg(feature = "fault-injection")]
+            timestamp_faults: None,
+        })
+    }
+
+    pub fn recorder(&self) -> &RecorderState {
+        &self.recorder
+    }
+
+    pub fn recorder_mut(&mut self) -> &mut RecorderState {
+        &mut self.recorder
+    }
+
+    /// Make the timestamps of the frames still to come jump
+    #[cfg(feature = "fault-injection")]
+    pub fn inject_timestamp_faults(&mut self, faults: crate::fault::TimestampFaults) {
+        self.timestamp_faults = Some(faults);
+    }
+
+    /// The recorder's clock, to move it between steps (e.g. a frozen tab)
+    pub fn clock(&self) -> ManualClock {
+        self.clock.clone()
+    }
+
+    /// Feed the next frame, returning the events it caused; `None` once the
+    /// recorder has stopped
+    pub fn step(&mut self) -> Result<Option<Vec<RecorderEvent>>, String> {
+        match self.recorder.status() {
+            RecorderStatus::Stopped => return Ok(None),
+            RecorderStatus::Idle => {
+                self.report.init_segment_bytes = self.recorder.start()?.len() as u64;
+            }
+            _ => {}
+        }
+        match self.stream.next() {
+            Some(mut frame) => {
+                #[cfg(feature = "fault-injection")]
+                if let Some(faults) = self.timestamp_faults.as_mut() {
+                    frame.timestamp_us = faults.apply(frame.track, frame.timestamp_us);
+                }
+                if frame.track == TrackKind::Video
+                    && self.recorder.keyframe_due(frame.timestamp_us)
+                    && self.follow_keyframe_requests
+                {
+                    frame.is_keyframe = true;
+                    frame.data[4] = 0x65;
+                }
+                self.clock.set(self.start_ms + frame.scheduled_us / 1000);
+                self.recorder.check_stalls();
+                let accepted = match frame.track {
+                    TrackKind::Video => {
+                        self.report.video_frames += 1;
+                        self.recorder.push_video(
+                            &frame.data,
+                            frame.timestamp_us,
+                            frame.is_keyframe,
+                        )?
+                    }
+                    _ => {
+                        self.report.audio_frames += 1;
+                        self.recorder.push_audio(
+                            &frame.data,
+                            frame.timestamp_us,
+                            frame.duration_us,
+                        )?
+                    }
+                };
+                if accepted {
+                    self.report.accepted_frames += 1;
+                }
+            }
+            None => self.recorder.stop()?,
+        }
+        let events = self.recorder.take_events();
+        for event in &events {
+            self.report.note(event, &mut self.last_chunk_us);
+        }
+        Ok(Some(events))
+    }
+
+    /// Run the rest of the recording
+    pub fn run(&mut self) -> Result<SimulationReport, String> {
+        while self.step()?.is_some() {}
+        Ok(self.report())
+    }
+
+    /// What the recording has produced so far
+    pub fn report(&self) -> SimulationReport {
+        SimulationReport {
+            quality: self.recorder.quality_report(),
+            ..self.report.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+
+    #[test]
+    fn test_steady_stream() {
+        let config = SimulationConfig::default();
+        let frames: Vec<SyntheticFrame> = SyntheticStream::new(config.clone()).unwrap().collect();
+        assert_eq!(
+            frames,
+            SyntheticStream::new(config.clone())
+                .unwrap()
+                .collect::<Vec<_>>()
+        );
+        assert!(frames
+            .windows(2)
+            .all(|w| w[0].scheduled_us <= w[1].scheduled_us));
+        let video: Vec<&SyntheticFrame> = frames
+            .iter()
+            .filter(|f| f.track == TrackKind::Video)
+            .collect();
+        assert_eq!(video.len(), 300);
+        assert_eq!(video.iter().filter(|f| f.is_keyframe).count(), 5);
+        let video_bytes: usize = video.iter().map(|f| f.data.len()).sum();
+        assert!(
+            (2_300_000..2_700_000).contains(&video_bytes),
+            "{}",
+            video_bytes
+        );
+
+        for frame_rate in [0.0, f64::NAN, f64::INFINITY] {
+            let config = SimulationConfig {
+                frame_rate,
+                ..config.clone()
+            };
+            assert!(SyntheticStream::new(config).is_err(), "{}", frame_rate);
+        }
+        let config_nan_ratio = SimulationConfig {
+            keyframe_size_ratio: f64::NAN,
+            ..config.clone()
+        };
+        assert!(SyntheticStream::new(config_nan_ratio).is_err());
+
+        let mut simulator = Simulator::new(SessionId::from("sim"), config).unwrap();
+        let report = simulator.run().unwrap();
+        assert_eq!(report.video_frames, 300);
+        assert_eq!(
+            report.accepted_frames,
+            report.video_frames + report.audio_frames
+        );
+        assert_eq!(report.chunk_count, 5);
+        // Fragments are cut on audio frames and drift past the fixed GOPs
+        assert_eq!(report.keyframe_chunks, 4);
+        assert_eq!(report.max_chunk_interval_us, 2_048_000);
+        assert_eq!(
+            report.chunk_bytes,
+            simulator.recorder().manifest().total_size()
+        );
+        assert!(report.quality.gaps.is_empty());
+        assert!(simulator.step().unwrap().is_none());
+
+        // Keyframes on request start every chunk
+        let config = SimulationConfig {
+            follow_keyframe_requests: true,
+            ..Default::default()
+        };
+        let report = Simulator::new(SessionId::from("sim"), config)
+            .unwrap()
+            .run()
+            .unwrap();
+        assert_eq!(report.keyframe_chunks, report.chunk_count);
+    }
+
+    #[test]
+    fn test_jitter_gaps_and_stalls() {
+        let config = SimulationConfig {
+            jitter_us: 5_000,
+            gaps: vec![SimulatedGap {
+                track: TrackKind::Muxed,
+                start_ms: 4_000,
+                duration_ms: 3_000,
+            }],
+            ..Default::default()
+        };
+        let mut simulator = Simulator::new(SessionId::from("sim"), config).unwrap();
+        simulator
+            .recorder_mut()
+            .enable_stall_watchdog(1_000)
+            .unwrap();
+        simulator.recorder_mut().enable_wal(500).unwrap();
+        let report = simulator.run().unwrap();
+
+        // Both tracks stall in the gap, which ends up in the quality report
+        assert_eq!(report.stalls, 2);
+        assert!(report.wal_batches > 0);
+        assert_eq!(report.video_frames, 210);
+        assert!(report
+            .quality
+            .gaps
+            .iter()
+            .any(|gap| gap.start_us <= 4_100_000 && gap.end_us >= 6_900_000));
+        assert_eq!(simulator.clock().now_ms(), 1_700_000_009_984);
+        assert_eq!(
+            report.chunk_bytes,
+            simulator.recorder().manifest().total_size()
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! Multi-rendition (simulcast) recording.
+//!
+//! `SimulcastState` drives one `RecorderState` per rendition (e.g. the full
+//! quality recording plus a 360p proxy for instant playback) from
+//! per-rendition encoded inputs. All renditions share one timestamp base, so
+//! the first frame of any rendition is time zero for every rendition, and
+//! their chunks are collected into one session manifest with the rendition
+//! name in each `ChunkId`.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::manifest::ChunkManifest;
+use crate::muxide_muxer::MuxideConfig;
+use crate::recorder::{RecorderEvent, RecorderState, RecorderStatus};
+use crate::session::{SessionId, SessionState};
+
+/// A rendition's name and muxer configuration
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct RenditionConfig {
+    /// Label used in chunk IDs and file names, e.g. "1080p"
+    pub name: String,
+    pub config: MuxideConfig,
+}
+
+struct Rendition {
+    name: String,
+    recorder: RecorderState,
+    init_segment: Option<Vec<u8>>,
+}
+
+/// Coordinates the recorders of all renditions of one session
+pub struct SimulcastState {
+    manifest: ChunkManifest,
+    renditions: Vec<Rendition>,
+    base_us: Option<u64>,
+    status: RecorderStatus,
+    events: Vec<RecorderEvent>,
+}
+
+impl SimulcastState {
+    /// Create a coordinator with no renditions
+    pub fn new(session_id: SessionId) -> Self {
+        Self {
+            manifest: ChunkManifest::new(session_id),
+            renditions: Vec::new(),
+            base_us: None,
+            status: RecorderStatus::Idle,
+            events: Vec::new(),
+        }
+    }
+
+    /// Add a rendition; must be called before `start()`
+    pub fn add_rendition(&mut self, rendition: RenditionConfig) -> Result<(), String> {
+        if self.status != RecorderStatus::Idle {
+            return Err(format!(
+                "Cannot add rendition in state: {}",
+                self.status.as_str()
+            ));
+        }
+        let name = rendition.name;
+        if name.is_empty() || name.contains(['/', ':']) {
+            return Err(format!("Invalid rendition name: '{}'", name));
+        }
+        if self.renditions.iter().any(|r| r.name == name) {
+            return Err(format!("Duplicate rendition: {}", name));
+        }
+        self.renditions.push(Rendition {
+            name,
+            recorder: RecorderState::new(self.manifest.session_id.clone(), rendition.config),
+            init_segment: None,
+        });
+        Ok(())
+    }
+
+    /// Names of all renditions, in the order they were added
+    pub fn rendition_names(&self) -> Vec<String> {
+        self.renditions.iter().map(|r| r.name.clone()).collect()
+    }
+
+    /// Current lifecycle state, shared by all renditions
+    pub fn status(&self) -> RecorderStatus {
+        self.status
+    }
+
+    /// Session manifest with the chunks of every rendition
+    pub fn manifest(&self) -> &ChunkManifest {
+        &self.manifest
+    }
+
+    /// Init segment of a rendition, available after `start()`
+    pub fn init_segment(&self, rendition: &str) -> Result<&[u8], String> {
+        self.rendition(rendition)?
+            .init_segment
+            .as_deref()
+            .ok_or_else(|| format!("Rendition {} has not started", rendition))
+    }
+
+    /// Take all queued events, oldest first
+    pub fn take_events(&mut self) -> Vec<RecorderEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Start every rendition
+    pub fn start(&mut self) -> Result<(), String> {
+        if self.renditions.is_empty() {
+            return Err("Simulcast needs at least one rendition".to_string());
+        }
+        if self.status != RecorderStatus::Idle {
+            return Err(format!(
+                "Cannot start simulcast in state: {}",
+                self.status.as_str()
+            ));
+        }
+        for rendition in &mut self.renditions {
+            rendition.init_segment = Some(rendition.recorder.start()?);
+        }
+        self.manifest.state.transition_to(SessionState::Recording)?;
+        self.collect_events()?;
+        self.set_status(RecorderStatus::Recording);
+        Ok(())
+    }
+
+    /// Pause every rendition
+    pub fn pause(&mut self) -> Result<(), String> {
+        for rendition in &mut self.renditions {
+            rendition.recorder.pause()?;
+        }
+        self.collect_events()?;
+        self.set_status(RecorderStatus::Paused);
+        Ok(())
+    }
+
+    /// Resume every rendition
+    pub fn resume(&mut self) -> Result<(), String> {
+        for rendition in &mut self.renditions {
+            rendition.recorder.resume()?;
+        }
+        self.collect_events()?;
+        self.set_status(RecorderStatus::Recording);
+        Ok(())
+    }
+
+    /// Stop every rendition, emitting their remaining chunks before the state change
+    pub fn stop(&mut self) -> Result<(), String> {
+        for rendition in &mut self.renditions {
+            rendition.recorder.stop()?;
+        }
+        self.collect_events()?;
+        self.manifest
+            .state
+            .transition_to(SessionState::Finalizing)?;
+        self.set_status(RecorderStatus::Stopped);
+        Ok(())
+    }
+
+    /// Whether the next frame handed to a rendition's encoder must be a keyframe
+    pub fn keyframe_due(&mut self, rendition: &str, timestamp_us: u64) -> Result<bool, String> {
+        let timestamp_us = self.rebase(timestamp_us);
+        Ok(self.rendition_mut(rendition)?.keyframe_due(timestamp_us))
+    }
+
+    /// Push an encoded video frame to a rendition
+    ///
+    /// Returns false if the frame was dropped.
+    pub fn push_video(
+        &mut self,
+        rendition: &str,
+        data: &[u8],
+        timestamp_us: u64,
+        is_keyframe: bool,
+    ) -> Result<bool, String> {
+        let timestamp_us = self.rebase(timestamp_us);
+        let pushed = self
+            .rendition_mut(rendition)?
+            .push_video(data, timestamp_us, is_keyframe)?;
+        self.collect_events()?;
+        Ok(pushed)
+    }
+
+    /// Push an encoded audio frame to a rendition
+    ///
+    /// Returns false if the frame was dropped.
+    pub fn push_audio(
+        &mut self,
+        rendition: &str,
+        data: &[u8],
+        timestamp_us: u64,
+        duration_us: u32,
+    ) -> Result<bool, String> {
+        let timestamp_us = self.rebase(timestamp_us);
+        let pushed = self
+            .rendition_mut(rendition)?
+            .push_audio(data, timestamp_us, duration_us)?;
+        self.collect_events()?;
+        Ok(pushed)
+    }
+
+    /// Map a timestamp onto the shared base set by the first frame of any rendition
+    fn rebase(&mut self, timestamp_us: u64) -> u64 {
+        if self.status != RecorderStatus::Recording {
+            return timestamp_us;
+        }
+        let base = *self.base_us.get_or_insert(timestamp_us);
+        timestamp_us.saturating_sub(base)
+    }
+
+    fn rendition(&self, name: &str) -> Result<&Rendition, String> {
+        self.renditions
+            .iter()
+            .find(|r| r.name == name)
+            .ok_or_else(|| format!("Unknown rendition: {}", name))
+    }
+
+    fn rendition_mut(&mut self, name: &str) -> Result<&mut RecorderState, String> {
+        self.renditions
+            .iter_mut()
+            .find(|r| r.name == name)
+            .map(|r| &mut r.recorder)
+            .ok_or_else(|| format!("Unknown rendition: {}", name))
+    }
+
+    /// Move chunks from the rendition recorders into the shared manifest
+    ///
+    /// Per-rendition state changes are folded into the coordinator's own.
+    fn collect_events(&mut self) -> Result<(), String> {
+        for rendition in &mut self.renditions {
+            for event in rendition.recorder.take_events() {
+                if let RecorderEvent::ChunkReady(mut chunk) = event {
+                    chunk.metadata.chunk_id = chunk
+                        .metadata
+                        .chunk_id
+                        .with_rendition(rendition.name.as_str());
+                    self.manifest.add_chunk(chunk.metadata.clone())?;
+                    self.events.push(RecorderEvent::ChunkReady(chunk));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_status(&mut self, to: RecorderStatus) {
+        let from = self.status;
+        self.status = to;
+        self.events.push(RecorderEvent::StateChanged { from, to });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::TrackKind;
+
+    fn rendition(name: &str) -> RenditionConfig {
+        RenditionConfig {
+            name: name.to_string(),
+            config: MuxideConfig {
+                sps: Some(vec![0x67, 0x42, 0xC0, 0x1E, 0xD9, 0x00, 0x50, 0x05]),
+                pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_simulcast_shares_manifest_and_timestamp_base() {
+        let mut simulcast = SimulcastState::new(SessionId::from("s1"));
+        simulcast.add_rendition(rendition("1080p")).unwrap();
+        simulcast.add_rendition(rendition("360p")).unwrap();
+        assert!(simulcast.add_rendition(rendition("360p")).is_err());
+        assert!(simulcast.add_rendition(rendition("a/b")).is_err());
+
+        simulcast.start().unwrap();
+        assert!(simulcast.init_segment("360p").is_ok());
+        assert!(simulcast.add_rendition(rendition("720p")).is_err());
+
+        // Capture clock starts at 10s; the proxy encoder delivers a frame later
+        let frame = [0x00, 0x00, 0x00, 0x04, 0x65, 0x88, 0x84, 0x00];
+        for i in 0..90u64 {
+            let ts = 10_000_000 + i * 33_333;
+            simulcast
+                .push_video("1080p", &frame, ts, i % 30 == 0)
+                .unwrap();
+            if i > 0 {
+                simulcast
+                    .push_video("360p", &frame, ts, i % 30 == 1)
+                    .unwrap();
+            }
+        }
+        assert!(simulcast.push_video("720p", &frame, 0, true).is_err());
+        simulcast.stop().unwrap();
+
+        let manifest = simulcast.manifest();
+        assert_eq!(manifest.state, SessionState::Finalizing);
+        let ids: Vec<String> = manifest
+            .chunks_for_track(TrackKind::Muxed)
+            .iter()
+            .map(|c| c.chunk_id.to_string())
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                "s1/muxed:1080p/0",
+                "s1/muxed:360p/0",
+                "s1/muxed:1080p/1",
+                "s1/muxed:360p/1",
+            ]
+        );
+        let first = |name: &str| {
+            manifest
+                .chunks
+                .iter()
+                .find(|c| c.chunk_id.rendition.as_deref() == Some(name))
+                .unwrap()
+                .timestamp_us
+        };
+        assert_eq!(first("1080p"), 0);
+        assert_eq!(first("360p"), 33_333);
+
+        let events = simulcast.take_events();
+        assert_eq!(
+            events.last(),
+            Some(&RecorderEvent::StateChanged {
+                from: RecorderStatus::Recording,
+                to: RecorderStatus::Stopped,
+            })
+        );
+    }
+}
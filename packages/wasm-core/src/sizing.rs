@@ -0,0 +1,229 @@
+//! Bandwidth-adaptive chunk sizing.
+//!
+//! Small chunks keep uploads resumable at a fine grain: a dropped connection
+//! costs at most one small request. Large chunks spend less on per-request
+//! overhead. `ChunkSizePolicyState` picks a fragment duration in between from
+//! what the `UploadTracker` reports: chunks sized to upload in about
+//! `target_upload_ms` at the measured throughput, halved while the upload
+//! backlog would take longer than `max_backlog_ms` to drain.
+//!
+//! The muxer cuts fragments by duration, so the byte target is converted
+//! using the recording's own bitrate, measured from the chunks it produced.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::chunk::ChunkMetadata;
+use crate::upload::UploadTracker;
+
+/// Weight of a new measurement in the smoothed rates
+const SMOOTHING: f64 = 0.3;
+/// Fragment durations are rounded to this step
+const STEP_MS: u32 = 100;
+
+/// Chunk sizing settings
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ChunkSizingConfig {
+    /// Shortest fragment duration the policy will pick
+    pub min_fragment_ms: u32,
+    /// Longest fragment duration the policy will pick
+    pub max_fragment_ms: u32,
+    /// Time one chunk upload should take at the measured throughput
+    pub target_upload_ms: u32,
+    /// Backlog drain time above which chunks are shrunk further
+    pub max_backlog_ms: u32,
+    /// Relative change below which the current duration is kept
+    pub hysteresis: f64,
+}
+
+impl Default for ChunkSizingConfig {
+    fn default() -> Self {
+        Self {
+            min_fragment_ms: 1000,
+            max_fragment_ms: 10_000,
+            target_upload_ms: 2000,
+            max_backlog_ms: 10_000,
+            hysteresis: 0.2,
+        }
+    }
+}
+
+/// Adjusts the fragment duration to upload conditions
+#[derive(Debug, Clone)]
+pub struct ChunkSizePolicyState {
+    config: ChunkSizingConfig,
+    fragment_ms: u32,
+    /// Smoothed per-request upload throughput (bytes/s)
+    throughput: Option<f64>,
+    /// Smoothed recording bitrate (bytes/s)
+    media_rate: Option<f64>,
+    /// Start timestamp (us) and size of the last chunk observed
+    last_chunk: Option<(u64, u64)>,
+    target_bytes: Option<u64>,
+}
+
+impl ChunkSizePolicyState {
+    /// Create a policy starting from the muxer's current fragment duration
+    pub fn new(config: ChunkSizingConfig, fragment_duration_ms: u32) -> Self {
+        Self {
+            config,
+            fragment_ms: fragment_duration_ms,
+            throughput: None,
+            media_rate: None,
+            last_chunk: None,
+            target_bytes: None,
+        }
+    }
+
+    /// Fragment duration currently recommended
+    pub fn fragment_duration_ms(&self) -> u32 {
+        self.fragment_ms
+    }
+
+    /// Smoothed upload throughput in bytes per second
+    pub fn throughput(&self) -> Option<f64> {
+        self.throughput
+    }
+
+    /// Chunk size aimed for at the last update
+    pub fn target_chunk_bytes(&self) -> Option<u64> {
+        self.target_bytes
+    }
+
+    /// Record a finished upload of `bytes` that took `elapsed_ms`
+    pub fn observe_upload(&mut self, bytes: u64, elapsed_ms: u64) {
+        if bytes == 0 {
+            return;
+        }
+        let rate = bytes as f64 * 1000.0 / elapsed_ms.max(1) as f64;
+        self.throughput = Some(smooth(self.throughput, rate));
+    }
+
+    /// Record a chunk produced by the recorder, in timeline order
+    ///
+    /// The bitrate is measured from one chunk's start to the next.
+    pub fn observe_chunk(&mut self, chunk: &ChunkMetadata) {
+        if let Some((start_us, size)) = self.last_chunk {
+            if chunk.timestamp_us > start_us {
+                let rate = size as f64 * 1_000_000.0 / (chunk.timestamp_us - start_us) as f64;
+                self.media_rate = Some(smooth(self.media_rate, rate));
+            }
+        }
+        self.last_chunk = Some((chunk.timestamp_us, chunk.size));
+    }
+
+    /// Take the tracker's finished uploads and update for its backlog
+    pub fn update_from_tracker(&mut self, tracker: &mut UploadTracker) -> Option<u32> {
+        for sample in tracker.take_upload_samples() {
+            self.observe_upload(sample.bytes, sample.elapsed_ms);
+        }
+        self.update(tracker.backlog_bytes())
+    }
+
+    /// Recompute the fragment duration for `backlog_bytes` still to upload
+    ///
+    /// Returns the new duration when it changed enough to apply, None while
+    /// throughput or bitrate have not been measured yet.
+    pub fn update(&mut self, backlog_bytes: u64) -> Option<u32> {
+        let throughput = self.throughput?;
+        let media_rate = self.media_rate?;
+
+        let mut target = throughput * self.config.target_upload_ms as f64 / 1000.0;
+        let backlog_ms = backlog_bytes as f64 * 1000.0 / throughput;
+        if backlog_ms > self.config.max_backlog_ms as f64 {
+            target /= 2.0;
+        }
+        self.target_bytes = Some(target as u64);
+
+        let min = self.config.min_fragment_ms.max(STEP_MS);
+        let max = self.config.max_fragment_ms.max(min);
+        let ms = (target * 1000.0 / media_rate).clamp(min as f64, max as f64) as u32;
+        let ms = (ms + STEP_MS / 2) / STEP_MS * STEP_MS;
+
+        let change = (ms as f64 - self.fragment_ms as f64).abs() / self.fragment_ms.max(1) as f64;
+        if ms == self.fragment_ms || change < self.config.hysteresis {
+            return None;
+        }
+        self.fragment_ms = ms;
+        Some(ms)
+    }
+}
+
+fn smooth(current: Option<f64>, sample: f64) -> f64 {
+    match current {
+        Some(current) => current + SMOOTHING * (sample - current),
+        None => sample,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{ChunkId, TrackKind};
+    use crate::manifest::ChunkManifest;
+    use crate::session::SessionId;
+
+    fn chunk(sequence: u64, size: u64) -> ChunkMetadata {
+        ChunkMetadata {
+            chunk_id: ChunkId::new(SessionId::from("s1"), TrackKind::Muxed, sequence),
+            timestamp_us: sequence * 2_000_000,
+            size,
+            hash: None,
+            has_keyframe: Some(true),
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_adapts_to_throughput_and_backlog() {
+        let mut policy = ChunkSizePolicyState::new(ChunkSizingConfig::default(), 2000);
+        // 2 s chunks of 500 kB: 250 kB/s of media
+        policy.observe_chunk(&chunk(0, 500_000));
+        policy.observe_chunk(&chunk(1, 500_000));
+        assert_eq!(policy.update(0), None);
+
+        // Fast network: 2 MB/s allows 4 MB chunks, capped at 10 s
+        policy.observe_upload(500_000, 250);
+        assert_eq!(policy.update(0), Some(10_000));
+        assert_eq!(policy.target_chunk_bytes(), Some(4_000_000));
+        // Small fluctuations are ignored
+        policy.observe_upload(500_000, 260);
+        assert_eq!(policy.update(0), None);
+
+        // Slow network with a backlog: 100 kB/s, 5 MB waiting
+        let mut policy = ChunkSizePolicyState::new(ChunkSizingConfig::default(), 2000);
+        policy.observe_chunk(&chunk(0, 200_000));
+        policy.observe_chunk(&chunk(1, 200_000));
+        policy.observe_upload(200_000, 2000);
+        assert_eq!(policy.update(0), None);
+        assert_eq!(policy.update(5_000_000), Some(1000));
+        assert_eq!(policy.target_chunk_bytes(), Some(100_000));
+    }
+
+    #[test]
+    fn test_fed_by_upload_tracker() {
+        let session = SessionId::from("s1");
+        let mut manifest = ChunkManifest::new(session.clone());
+        for sequence in 0..3 {
+            manifest.add_chunk(chunk(sequence, 400_000)).unwrap();
+        }
+        let mut tracker = UploadTracker::new(session);
+        tracker.sync_manifest(&manifest).unwrap();
+        let mut policy = ChunkSizePolicyState::new(ChunkSizingConfig::default(), 2000);
+        for metadata in &manifest.chunks {
+            policy.observe_chunk(metadata);
+        }
+
+        let ids = tracker.pending();
+        tracker.start_at(&ids[0], 1_000).unwrap();
+        tracker.complete_at(&ids[0], 1_400).unwrap();
+        assert_eq!(tracker.backlog_bytes(), 800_000);
+
+        // 1 MB/s: 2 MB chunks, 10 s of media at 200 kB/s
+        assert_eq!(policy.update_from_tracker(&mut tracker), Some(10_000));
+        assert!(tracker.take_upload_samples().is_empty());
+        assert_eq!(policy.throughput(), Some(1_000_000.0));
+    }
+}
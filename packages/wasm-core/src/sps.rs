@@ -0,0 +1,443 @@
+//! Timing information of an H.264 sequence parameter set.
+//!
+//! Frames carry their own timestamps, so the muxer only needs a frame rate
+//! where a duration cannot be derived from the next frame: a fragment
+//! holding a single frame, or the frame a gap is measured from. Encoders
+//! that know the rate write it into the SPS's VUI parameters
+//! (`num_units_in_tick`, `time_scale`, ITU-T H.264 E.1.1);
+//! `parse_sps_timing` reads them so that default follows the stream
+//! instead of assuming 30 fps.
+//!
+//! `check_keyframe` reads just enough of a pre-encoded keyframe's slice
+//! headers to tell whether it decodes with a session's parameter sets.
+
+/// VUI timing of an SPS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpsTiming {
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+    /// Whether the encoder promises a constant frame rate
+    pub fixed_frame_rate: bool,
+}
+
+impl SpsTiming {
+    /// Frames per second; a frame lasts two ticks (one per field)
+    pub fn frame_rate(&self) -> f64 {
+        self.time_scale as f64 / (2.0 * self.num_units_in_tick as f64)
+    }
+
+    /// Duration of one frame in units of `timescale`, rounded to nearest
+    pub fn frame_duration(&self, timescale: u32) -> u32 {
+        let ticks = 2 * self.num_units_in_tick as u64 * timescale as u64;
+        ((ticks + self.time_scale as u64 / 2) / self.time_scale as u64) as u32
+    }
+}
+
+/// profile_idc values whose SPS carries chroma format and bit depth fields
+const HIGH_PROFILES: [u8; 13] = [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134, 135];
+
+/// aspect_ratio_idc of an explicit sample aspect ratio
+const EXTENDED_SAR: u32 = 255;
+
+/// VUI timing of an SPS NAL unit (with its header byte, without start
+/// code), or None if it has none
+///
+/// Timing with a zero tick or time scale, or a rate outside 1-1000 fps, is
+/// treated as absent: some encoders write placeholders there.
+pub fn parse_sps_timing(sps: &[u8]) -> Result<Option<SpsTiming>, String> {
+    if sps.first().map(|header| header & 0x1F) != Some(7) {
+        return Err("Not an SPS NAL unit".to_string());
+    }
+    let rbsp = unescape_rbsp(&sps[1..]);
+    let mut bits = BitReader::new(&rbsp);
+
+    let profile_idc = bits.read(8)? as u8;
+    bits.skip(16)?; // Constraint flags, level_idc
+    bits.read_ue()?; // seq_parameter_set_id
+    if HIGH_PROFILES.contains(&profile_idc) {
+        let chroma_format_idc = bits.read_ue()?;
+        if chroma_format_idc == 3 {
+            bits.skip(1)?; // separate_colour_plane_flag
+        }
+        bits.read_ue()?; // bit_depth_luma_minus8
+        bits.read_ue()?; // bit_depth_chroma_minus8
+        bits.skip(1)?; // qpprime_y_zero_transform_bypass_flag
+        if bits.read_flag()? {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if bits.read_flag()? {
+                    skip_scaling_list(&mut bits, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+    bits.read_ue()?; // log2_max_frame_num_minus4
+    match bits.read_ue()? {
+        0 => {
+            bits.read_ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            bits.skip(1)?; // delta_pic_order_always_zero_flag
+            bits.read_se()?; // offset_for_non_ref_pic
+            bits.read_se()?; // offset_for_top_to_bottom_field
+            for _ in 0..bits.read_ue()? {
+                bits.read_se()?; // offset_for_ref_frame
+            }
+        }
+        _ => {}
+    }
+    bits.read_ue()?; // max_num_ref_frames
+    bits.skip(1)?; // gaps_in_frame_num_value_allowed_flag
+    bits.read_ue()?; // pic_width_in_mbs_minus1
+    bits.read_ue()?; // pic_height_in_map_units_minus1
+    if !bits.read_flag()? {
+        bits.skip(1)?; // mb_adaptive_frame_field_flag
+    }
+    bits.skip(1)?; // direct_8x8_inference_flag
+    if bits.read_flag()? {
+        for _ in 0..4 {
+            bits.read_ue()?; // frame_crop_*_offset
+        }
+    }
+    if !bits.read_flag()? {
+        return Ok(None); // No VUI parameters
+    }
+
+    if bits.read_flag()? && bits.read(8)? == EXTENDED_SAR {
+        bits.skip(32)?; // sar_width, sar_height
+    }
+    if bits.read_flag()? {
+        bits.skip(1)?; // overscan_appropriate_flag
+    }
+    if bits.read_flag()? {
+        bits.skip(4)?; // video_format, video_full_range_flag
+        if bits.read_flag()? {
+            bits.skip(24)?; // Colour primaries, transfer, matrix
+        }
+    }
+    if bits.read_flag()? {
+        bits.read_ue()?; // chroma_sample_loc_type_top_field
+        bits.read_ue()?; // chroma_sample_loc_type_bottom_field
+    }
+    if !bits.read_flag()? {
+        return Ok(None); // No timing info
+    }
+    let timing = SpsTiming {
+        num_units_in_tick: bits.read(32)?,
+        time_scale: bits.read(32)?,
+        fixed_frame_rate: bits.read_flag()?,
+    };
+    if timing.num_units_in_tick == 0 || timing.time_scale == 0 {
+        return Ok(None);
+    }
+    let rate = timing.frame_rate();
+    Ok((1.0..=1000.0).contains(&rate).then_some(timing))
+}
+
+/// Check that `sample` is a keyframe in AVCC layout (4-byte NAL lengths)
+/// that decodes with the session's `sps` and `pps` (NAL units with their
+/// header byte)
+///
+/// Every NAL unit must fit the sample, at least one must be an IDR slice and
+/// none a non-IDR slice; slices must refer to the session PPS by ID, and
+/// in-band parameter sets must be the session's own.
+pub fn check_keyframe(sample: &[u8], sps: &[u8], pps: &[u8]) -> Result<(), String> {
+    if pps.first().map(|header| header & 0x1F) != Some(8) {
+        return Err("Not a PPS NAL unit".to_string());
+    }
+    let pps_id = BitReader::new(&unescape_rbsp(&pps[1..])).read_ue()?;
+    let mut idr = false;
+    let mut pos = 0;
+    while pos < sample.len() {
+        let len = sample
+            .get(pos..pos + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or("Keyframe is not in AVCC layout: truncated NAL length")?;
+        let nal = pos
+            .checked_add(4 + len)
+            .and_then(|end| sample.get(pos + 4..end))
+            .filter(|nal| !nal.is_empty())
+            .ok_or_else(|| format!("Keyframe is not in AVCC layout: bad NAL length {}", len))?;
+        match nal[0] & 0x1F {
+            1 => return Err("Keyframe holds a non-IDR slice".to_string()),
+            5 => {
+                let mut bits = BitReader::new(&nal[1..]);
+                bits.read_ue()?; // first_mb_in_slice
+                bits.read_ue()?; // slice_type
+                let slice_pps_id = bits.read_ue()?;
+                if slice_pps_id != pps_id {
+                    return Err(format!(
+                        "Keyframe refers to PPS {}, not the session's PPS {}",
+                        slice_pps_id, pps_id
+                    ));
+                }
+                idr = true;
+            }
+            7 if nal != sps => return Err("Keyframe carries a different SPS".to_string()),
+            8 if nal != pps => return Err("Keyframe carries a different PPS".to_string()),
+            _ => {}
+        }
+        pos += 4 + len;
+    }
+    if !idr {
+        return Err("Keyframe holds no IDR slice".to_string());
+    }
+    Ok(())
+}
+
+/// Skip a scaling_list() of `size` coefficients
+fn skip_scaling_list(bits: &mut BitReader, size: usize) -> Result<(), String> {
+    let mut last_scale = 8i64;
+    let mut next_scale = 8i64;
+    for _ in 0..size {
+        if next_scale != 0 {
+            next_scale = (last_scale + bits.read_se()? + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+    Ok(())
+}
+
+/// Remove emulation prevention bytes (the 0x03 of 0x000003)
+fn unescape_rbsp(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+/// Reads big-endian bit fields and Exp-Golomb codes
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn read(&mut self, count: usize) -> Result<u32, String> {
+        let mut value = 0u64;
+        for _ in 0..count {
+            let byte = self
+                .bytes
+                .get(self.pos / 8)
+                .ok_or("NAL unit is truncated")?;
+            value = (value << 1) | ((byte >> (7 - self.pos % 8)) & 1) as u64;
+            self.pos += 1;
+        }
+        Ok(value as u32)
+    }
+
+    fn read_flag(&mut self) -> Result<bool, String> {
+        Ok(self.read(1)? == 1)
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), String> {
+        self.read(count).map(|_| ())
+    }
+
+    /// Unsigned Exp-Golomb code
+    fn read_ue(&mut self) -> Result<u32, String> {
+        let mut leading_zeros = 0;
+        while !self.read_flag()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err("Invalid Exp-Golomb code in NAL unit".to_string());
+            }
+        }
+        Ok(((1u64 << leading_zeros) - 1 + self.read(leading_zeros)? as u64) as u32)
+    }
+
+    /// Signed Exp-Golomb code
+    fn read_se(&mut self) -> Result<i64, String> {
+        let code = self.read_ue()? as i64;
+        Ok(if code % 2 == 1 {
+            (code + 1) / 2
+        } else {
+            -(code / 2)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::muxide_muxer::{trace_segment, MuxideConfig, MuxideMuxerState};
+
+    /// Writes bit fields and Exp-Golomb codes
+    #[derive(Default)]
+    struct BitWriter {
+        bits: Vec<bool>,
+    }
+
+    impl BitWriter {
+        fn put(&mut self, value: u32, count: usize) {
+            for i in (0..count).rev() {
+                self.bits.push(value >> i & 1 == 1);
+            }
+        }
+
+        fn ue(&mut self, value: u32) {
+            let code = value as u64 + 1;
+            let len = 64 - code.leading_zeros() as usize;
+            self.put(0, len - 1);
+            self.put(code as u32, len);
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            self.bits.push(true); // rbsp_stop_one_bit
+            while !self.bits.len().is_multiple_of(8) {
+                self.bits.push(false);
+            }
+            self.bits
+                .chunks(8)
+                .map(|byte| byte.iter().fold(0u8, |acc, &bit| acc << 1 | bit as u8))
+                .collect()
+        }
+    }
+
+    /// A High profile 1280x720 SPS, with VUI timing of `timing`
+    fn sps(timing: Option<(u32, u32)>) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.put(100, 8); // profile_idc: High
+        w.put(0, 8);
+        w.put(31, 8); // level_idc
+        w.ue(0); // seq_parameter_set_id
+        w.ue(1); // chroma_format_idc: 4:2:0
+        w.ue(0);
+        w.ue(0);
+        w.put(0, 1);
+        w.put(0, 1); // No scaling matrix
+        w.ue(0); // log2_max_frame_num_minus4
+        w.ue(0); // pic_order_cnt_type
+        w.ue(2);
+        w.ue(4); // max_num_ref_frames
+        w.put(0, 1);
+        w.ue(79); // 80 macroblocks wide
+        w.ue(44); // 45 high
+        w.put(1, 1); // frame_mbs_only_flag
+        w.put(1, 1); // direct_8x8_inference_flag
+        w.put(0, 1); // No cropping
+        w.put(1, 1); // vui_parameters_present_flag
+        w.put(1, 1); // aspect_ratio_info_present_flag
+        w.put(1, 8); // 1:1
+        w.put(0, 1);
+        w.put(1, 1); // video_signal_type_present_flag
+        w.put(5, 3);
+        w.put(0, 1);
+        w.put(1, 1);
+        w.put(0x010101, 24); // BT.709
+        w.put(0, 1);
+        match timing {
+            Some((num_units_in_tick, time_scale)) => {
+                w.put(1, 1);
+                w.put(num_units_in_tick, 32);
+                w.put(time_scale, 32);
+                w.put(1, 1);
+            }
+            None => w.put(0, 1),
+        }
+        w.put(0, 5); // No HRD, pic_struct, bitstream restriction
+        let mut nal = vec![0x67];
+        nal.extend(w.finish());
+        nal
+    }
+
+    #[test]
+    fn test_parse_sps_timing() {
+        // 29.97 fps as NTSC encoders write it
+        let timing = parse_sps_timing(&sps(Some((1001, 60000))))
+            .unwrap()
+            .unwrap();
+        assert!((timing.frame_rate() - 29.97).abs() < 0.001);
+        assert!(timing.fixed_frame_rate);
+        assert_eq!(timing.frame_duration(90000), 3003);
+        let timing = parse_sps_timing(&sps(Some((1, 50)))).unwrap().unwrap();
+        assert_eq!(timing.frame_duration(90000), 3600);
+        assert_eq!(timing.frame_duration(1000), 40);
+
+        assert_eq!(parse_sps_timing(&sps(None)).unwrap(), None);
+        assert_eq!(parse_sps_timing(&sps(Some((0, 60000)))).unwrap(), None);
+        assert_eq!(parse_sps_timing(&sps(Some((1, 1_000_000)))).unwrap(), None);
+        assert!(parse_sps_timing(&[0x68, 0xCE]).is_err());
+        assert!(parse_sps_timing(&sps(Some((1, 50)))[..8]).is_err());
+
+        // Emulation prevention bytes are dropped before parsing
+        assert_eq!(
+            unescape_rbsp(&[0, 0, 3, 1, 0, 0, 3, 0, 0, 3]),
+            vec![0, 0, 1, 0, 0, 0, 0]
+        );
+        assert_eq!(unescape_rbsp(&[0, 3, 0, 1]), vec![0, 3, 0, 1]);
+    }
+
+    #[test]
+    fn test_lone_frame_takes_sps_frame_duration() {
+        let lone_frame_duration = |sps: Vec<u8>| {
+            let mut muxer = MuxideMuxerState::new(MuxideConfig {
+                sps: Some(sps),
+                pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+                ..Default::default()
+            });
+            muxer.init().unwrap();
+            muxer
+                .push_video_chunk(&[0, 0, 0, 1, 0x65], 0, true)
+                .unwrap();
+            muxer.force_flush().unwrap();
+            let segment = muxer.get_pending_segments().remove(0);
+            trace_segment(&segment).unwrap()[0].duration
+        };
+        assert_eq!(lone_frame_duration(sps(Some((1, 50)))), 3600);
+        // 30 fps without timing
+        assert_eq!(lone_frame_duration(sps(None)), 3000);
+    }
+
+    #[test]
+    fn test_check_keyframe() {
+        let sps = sps(None);
+        let pps = vec![0x68, 0xCE, 0x3C, 0x80]; // pic_parameter_set_id 0
+        let slice = |nal_type: u8, pps_id: u32| {
+            let mut w = BitWriter::default();
+            w.ue(0); // first_mb_in_slice
+            w.ue(7); // slice_type: I
+            w.ue(pps_id);
+            let mut nal = vec![0x60 | nal_type];
+            nal.extend(w.finish());
+            nal
+        };
+        let avcc = |nals: &[&[u8]]| -> Vec<u8> {
+            nals.iter()
+                .flat_map(|nal| [&(nal.len() as u32).to_be_bytes()[..], nal].concat())
+                .collect()
+        };
+
+        let idr = slice(5, 0);
+        assert!(check_keyframe(&avcc(&[&idr]), &sps, &pps).is_ok());
+        assert!(check_keyframe(&avcc(&[&sps, &pps, &idr, &idr]), &sps, &pps).is_ok());
+
+        // Not a keyframe, or not one of this session
+        assert!(check_keyframe(&avcc(&[&slice(1, 0)]), &sps, &pps).is_err());
+        assert!(check_keyframe(&avcc(&[&[0x06, 0x05]]), &sps, &pps).is_err());
+        assert!(check_keyframe(&avcc(&[&slice(5, 1)]), &sps, &pps).is_err());
+        let other_pps = [0x68, 0xEE, 0x3C, 0x80];
+        assert!(check_keyframe(&avcc(&[&other_pps, &idr]), &sps, &pps).is_err());
+
+        // Annex B or a bad length
+        let annex_b = [&[0, 0, 0, 1][..], &idr].concat();
+        assert!(check_keyframe(&annex_b, &sps, &pps).is_err());
+        let mut truncated = avcc(&[&idr]);
+        truncated.pop();
+        assert!(check_keyframe(&truncated, &sps, &pps).is_err());
+        assert!(check_keyframe(&[], &sps, &pps).is_err());
+    }
+}
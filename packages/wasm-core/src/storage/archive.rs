@@ -0,0 +1,342 @@
+//! Single-file session archives, for "download everything" and re-import.
+//!
+//! `export_session` packs a stored session into one ZIP file:
+//!
+//! ```text
+//! recording.mp4          init segment + muxed chunks, playable as is
+//! chunks/video-….fmp4    every other chunk (per-track, simulcast renditions)
+//! manifest.json          the session's ChunkManifest
+//! markers.json           its markers, for tools that only want those
+//! captions.vtt           marker subtitles (see `subtitles`), if any
+//! captions.srt
+//! events.json            LogRecords the host kept for the session, if any
+//! ```
+//!
+//! `import_session` writes such an archive back into a store: it cuts the
+//! muxed chunks out of `recording.mp4` using the manifest's chunk sizes, the
+//! init segment being what precedes them, and checks every chunk against
+//! its hash before anything is written. Chunks must belong to the
+//! manifest's session and carry a hash.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use super::{chunk_file_name, ChunkStore};
+use crate::chunk::{ChunkId, TrackKind};
+use crate::compress::DEFAULT_COMPRESSION_LEVEL;
+use crate::logging::LogRecord;
+use crate::manifest::ChunkManifest;
+use crate::session::SessionId;
+use crate::subtitles::{export_subtitles, marker_cues, SubtitleFormat};
+use crate::zip::{read_zip, ZipWriter};
+
+/// Archive entry holding the playable recording
+pub const ARCHIVE_RECORDING_FILE: &str = "recording.mp4";
+
+/// Archive entry holding the manifest
+pub const ARCHIVE_MANIFEST_FILE: &str = "manifest.json";
+
+/// Archive entry holding the markers
+pub const ARCHIVE_MARKERS_FILE: &str = "markers.json";
+
+/// Archive entry holding the event log
+pub const ARCHIVE_EVENTS_FILE: &str = "events.json";
+
+/// Archive directory of the chunks not in the recording
+pub const ARCHIVE_CHUNKS_DIR: &str = "chunks/";
+
+/// Base name of the caption entries, followed by the format's extension
+const CAPTIONS_STEM: &str = "captions";
+
+/// A session written back into a store by `import_session`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedSession {
+    pub manifest: ChunkManifest,
+    /// Event log the archive was exported with
+    pub events: Vec<LogRecord>,
+}
+
+/// Whether a chunk goes into `recording.mp4` rather than `chunks/`
+fn in_recording(id: &ChunkId) -> bool {
+    id.track == TrackKind::Muxed && id.rendition.is_none()
+}
+
+/// Pack a stored session and its event log into a ZIP archive
+///
+/// Fails if the session has no manifest or a chunk it lists is missing.
+pub async fn export_session(
+    store: &impl ChunkStore,
+    session: &SessionId,
+    events: &[LogRecord],
+) -> Result<Vec<u8>, String> {
+    let manifest = store
+        .get_manifest(session)
+        .await?
+        .ok_or_else(|| format!("No manifest for session {}", session))?;
+
+    let mut recording = store.get_init_segment(session).await?;
+    let mut zip = ZipWriter::default();
+    for chunk in &manifest.chunks {
+        let id = &chunk.chunk_id;
+        let data = store.get_chunk(id).await?;
+        if in_recording(id) {
+            recording.extend_from_slice(&data);
+        } else {
+            let name = format!("{}{}", ARCHIVE_CHUNKS_DIR, chunk_file_name(id));
+            zip.add(&name, &data, None)?;
+        }
+    }
+    zip.add(ARCHIVE_RECORDING_FILE, &recording, None)?;
+
+    for (name, json) in [
+        (ARCHIVE_MANIFEST_FILE, to_json(&manifest)?),
+        (ARCHIVE_MARKERS_FILE, to_json(&manifest.markers)?),
+    ] {
+        zip.add(name, &json, Some(DEFAULT_COMPRESSION_LEVEL))?;
+    }
+    if !marker_cues(&manifest, None).is_empty() {
+        for format in [SubtitleFormat::WebVtt, SubtitleFormat::Srt] {
+            let name = format!("{}.{}", CAPTIONS_STEM, format.extension());
+            let captions = export_subtitles(&manifest, None, format);
+            zip.add(&name, captions.as_bytes(), Some(DEFAULT_COMPRESSION_LEVEL))?;
+        }
+    }
+    if !events.is_empty() {
+        zip.add(
+            ARCHIVE_EVENTS_FILE,
+            &to_json(&events)?,
+            Some(DEFAULT_COMPRESSION_LEVEL),
+        )?;
+    }
+    zip.finish()
+}
+
+/// Write the session packed in an archive into `store`
+///
+/// Fails without writing anything if the session already exists in the
+/// store, an entry is corrupt, or a chunk belongs to another session, has
+/// no hash or does not match it. Markers
+/// and captions are not read back: the manifest already holds the markers.
+pub async fn import_session(
+    store: &impl ChunkStore,
+    archive: &[u8],
+) -> Result<ImportedSession, String> {
+    let mut manifest = None;
+    let mut recording = None;
+    let mut events = Vec::new();
+    let mut files = Vec::new();
+    for entry in read_zip(archive)? {
+        match entry.name.as_str() {
+            ARCHIVE_MANIFEST_FILE => {
+                let parsed: ChunkManifest = serde_json::from_slice(&entry.data)
+                    .map_err(|e| format!("Invalid manifest in archive: {}", e))?;
+                manifest = Some(parsed);
+            }
+            ARCHIVE_RECORDING_FILE => recording = Some(entry.data),
+            ARCHIVE_EVENTS_FILE => {
+                events = serde_json::from_slice(&entry.data)
+                    .map_err(|e| format!("Invalid event log in archive: {}", e))?;
+            }
+            _ => files.push(entry),
+        }
+    }
+    let manifest = manifest.ok_or("Archive has no manifest")?;
+    let recording = recording.ok_or("Archive has no recording")?;
+
+    let mut recorded = 0usize;
+    for chunk in &manifest.chunks {
+        let id = &chunk.chunk_id;
+        if id.session != manifest.session_id {
+            return Err(format!(
+                "Chunk {} does not belong to session {}",
+                id, manifest.session_id
+            ));
+        }
+        if in_recording(id) {
+            recorded = usize::try_from(chunk.size)
+                .ok()
+                .and_then(|size| recorded.checked_add(size))
+                .ok_or("Recording is shorter than its chunks")?;
+        }
+    }
+    let init_len = recording
+        .len()
+        .checked_sub(recorded)
+        .ok_or("Recording is shorter than its chunks")?;
+
+    let mut chunks = Vec::with_capacity(manifest.chunks.len());
+    let mut pos = init_len;
+    for chunk in &manifest.chunks {
+        let id = &chunk.chunk_id;
+        let hash = chunk
+            .hash
+            .as_deref()
+            .ok_or_else(|| format!("Chunk {} has no hash", id))?;
+        let data = if in_recording(id) {
+            // Sizes were summed without overflow and fit the recording
+            let end = pos + chunk.size as usize;
+            let data = &recording[pos..end];
+            pos = end;
+            data
+        } else {
+            let name = format!("{}{}", ARCHIVE_CHUNKS_DIR, chunk_file_name(id));
+            files
+                .iter()
+                .find(|entry| entry.name == name)
+                .map(|entry| entry.data.as_slice())
+                .ok_or_else(|| format!("Archive is missing chunk {}", id))?
+        };
+        if data.len() as u64 != chunk.size || hash != blake3::hash(data).to_hex().as_str() {
+            return Err(format!("Chunk {} does not match the manifest", id));
+        }
+        chunks.push((id, data));
+    }
+
+    store
+        .put_new_session(&manifest, &recording[..init_len], &chunks)
+        .await?;
+    Ok(ImportedSession { manifest, events })
+}
+
+fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, String> {
+    serde_json::to_vec_pretty(value).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use crate::chunk::ChunkMetadata;
+    use crate::logging::LogLevel;
+    use crate::manifest::Marker;
+    use crate::storage::memory::MemoryStore;
+
+    fn chunk(session: &SessionId, track: TrackKind, sequence: u64, data: &[u8]) -> ChunkMetadata {
+        ChunkMetadata {
+            chunk_id: ChunkId::new(session.clone(), track, sequence),
+            timestamp_us: sequence * 1_000_000,
+            size: data.len() as u64,
+            hash: Some(blake3::hash(data).to_hex().to_string()),
+            has_keyframe: Some(true),
+            created_at: 1_000 + sequence,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_import() {
+        let session = SessionId::from("s1");
+        let source = MemoryStore::default();
+        let mut manifest = ChunkManifest::new(session.clone());
+        source
+            .put_init_segment(&session, b"ftypmoov")
+            .await
+            .unwrap();
+        for (track, sequence, data) in [
+            (TrackKind::Muxed, 0, &b"moof-0"[..]),
+            (TrackKind::Audio, 0, b"audio-0"),
+            (TrackKind::Muxed, 1, b"moof-1!"),
+        ] {
+            let metadata = chunk(&session, track, sequence, data);
+            source.put_chunk(&metadata.chunk_id, data).await.unwrap();
+            manifest.add_chunk(metadata).unwrap();
+        }
+        manifest.add_marker(Marker {
+            timestamp_us: 500_000,
+            label: Some("Intro".to_string()),
+            bookmark: None,
+        });
+        source.put_manifest(&manifest).await.unwrap();
+        let events = vec![LogRecord {
+            module: "recorder".to_string(),
+            level: LogLevel::Info,
+            message: "Recording started".to_string(),
+            fields: BTreeMap::new(),
+            timestamp: 1_000,
+        }];
+
+        let archive = export_session(&source, &session, &events).await.unwrap();
+        let entries = read_zip(&archive).unwrap();
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "chunks/audio-00000000.fmp4",
+                "recording.mp4",
+                "manifest.json",
+                "markers.json",
+                "captions.vtt",
+                "captions.srt",
+                "events.json",
+            ]
+        );
+        assert_eq!(entries[1].data, b"ftypmoovmoof-0moof-1!");
+        assert!(String::from_utf8_lossy(&entries[4].data).contains("Intro"));
+
+        let target = MemoryStore::default();
+        let imported = import_session(&target, &archive).await.unwrap();
+        assert_eq!(imported.manifest, manifest);
+        assert_eq!(imported.events, events);
+        assert_eq!(
+            target.get_init_segment(&session).await.unwrap(),
+            b"ftypmoov"
+        );
+        for metadata in &manifest.chunks {
+            assert_eq!(
+                target.get_chunk(&metadata.chunk_id).await.unwrap(),
+                source.get_chunk(&metadata.chunk_id).await.unwrap()
+            );
+        }
+        assert_eq!(
+            target.get_manifest(&session).await.unwrap(),
+            Some(manifest.clone())
+        );
+
+        // Importing over an existing session is refused
+        let err = import_session(&target, &archive).await.unwrap_err();
+        assert!(err.contains("already exists"));
+
+        // A chunk that does not match its hash fails the import
+        let mut zip = ZipWriter::default();
+        for entry in &entries {
+            let data = match entry.name.as_str() {
+                ARCHIVE_RECORDING_FILE => b"ftypmoovmoof-0moof-2!".to_vec(),
+                _ => entry.data.clone(),
+            };
+            zip.add(&entry.name, &data, None).unwrap();
+        }
+        let tampered = zip.finish().unwrap();
+        let empty = MemoryStore::default();
+        let err = import_session(&empty, &tampered).await.unwrap_err();
+        assert!(err.contains("does not match"));
+        assert!(empty.list_sessions().await.unwrap().is_empty());
+
+        // Chunks of another session, without a hash or with an impossible
+        // size are refused too
+        let repack = |edit: &dyn Fn(&mut ChunkManifest)| {
+            let mut manifest = manifest.clone();
+            edit(&mut manifest);
+            let mut zip = ZipWriter::default();
+            for entry in &entries {
+                let data = match entry.name.as_str() {
+                    ARCHIVE_MANIFEST_FILE => serde_json::to_vec(&manifest).unwrap(),
+                    _ => entry.data.clone(),
+                };
+                zip.add(&entry.name, &data, None).unwrap();
+            }
+            zip.finish().unwrap()
+        };
+        let foreign = repack(&|m| m.chunks[1].chunk_id.session = SessionId::from("s2"));
+        let err = import_session(&empty, &foreign).await.unwrap_err();
+        assert!(err.contains("does not belong"));
+        let unhashed = repack(&|m| m.chunks[0].hash = None);
+        let err = import_session(&empty, &unhashed).await.unwrap_err();
+        assert!(err.contains("has no hash"));
+        let oversized = repack(&|m| m.chunks[2].size = u64::MAX);
+        let err = import_session(&empty, &oversized).await.unwrap_err();
+        assert!(err.contains("shorter than its chunks"));
+        assert!(empty.list_sessions().await.unwrap().is_empty());
+    }
+}
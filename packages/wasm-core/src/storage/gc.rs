@@ -0,0 +1,340 @@
+//! Reclaiming local storage from finished sessions.
+//!
+//! Recordings stay in local storage until the server has confirmed every
+//! chunk; `mark_synced` records that confirmation by moving the persisted
+//! manifest to `synced`. `collect_garbage` only ever deletes such synced
+//! sessions: once they are older than the retention period, and then least
+//! recently recorded first while the store is over its byte budget. Sessions
+//! in any other state, and so every chunk not yet confirmed, are never
+//! touched, even if that leaves the store over budget; neither are synced
+//! sessions whose recording is under a legal hold.
+//!
+//! Sizes and ages come from the manifests: a session's size is the total of
+//! its chunk sizes, and it was last used when its newest chunk was created.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use super::ChunkStore;
+use crate::logging::{log_event, LogLevel};
+use crate::session::{SessionId, SessionState};
+
+/// When `collect_garbage` deletes synced sessions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GcPolicy {
+    /// Delete a synced session this long after its last chunk was recorded;
+    /// 0 deletes it as soon as it is synced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub synced_retention_ms: Option<u64>,
+    /// Total bytes the store may hold before synced sessions are evicted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget_bytes: Option<u64>,
+}
+
+/// Why a session was reclaimed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub enum GcReason {
+    /// Synced and older than the retention period
+    Retention,
+    /// Evicted to bring the store under its budget
+    Budget,
+}
+
+/// One reclaimed session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct GcReclaim {
+    pub session_id: SessionId,
+    pub bytes: u64,
+    pub reason: GcReason,
+}
+
+/// What a garbage collection run reclaimed, or would reclaim in a dry run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct GcReport {
+    /// Nothing was deleted
+    pub dry_run: bool,
+    /// Sessions in the order they were reclaimed
+    pub reclaimed: Vec<GcReclaim>,
+    pub reclaimed_bytes: u64,
+    /// Bytes left in the store afterwards
+    pub used_bytes: u64,
+    /// Bytes of sessions that are not synced and were left alone
+    pub unsynced_bytes: u64,
+    /// Bytes of synced sessions under a legal hold, left alone as well
+    #[serde(default)]
+    pub held_bytes: u64,
+    /// Whether the store is still over budget because of unsynced or held
+    /// sessions
+    pub over_budget: bool,
+}
+
+/// A stored session as seen by the collector
+struct StoredSession {
+    session_id: SessionId,
+    bytes: u64,
+    last_used_ms: u64,
+    synced: bool,
+    /// Under a legal hold (`RetentionPolicy::legal_hold`)
+    held: bool,
+}
+
+/// Record that the server has confirmed every chunk of a finished session
+///
+/// Moves the persisted manifest from `finalizing` to `synced`, which makes
+/// the session eligible for `collect_garbage`.
+pub async fn mark_synced<S: ChunkStore>(store: &S, session: &SessionId) -> Result<(), String> {
+    let mut manifest = store
+        .get_manifest(session)
+        .await?
+        .ok_or_else(|| format!("No manifest persisted for session {}", session))?;
+    if manifest.state == SessionState::Synced {
+        return Ok(());
+    }
+    manifest.state.transition_to(SessionState::Synced)?;
+    store.put_manifest(&manifest).await
+}
+
+/// Apply `policy` to every session in `store` at `now_ms` (Unix ms)
+///
+/// With `dry_run` set, only reports what would be reclaimed.
+pub async fn collect_garbage<S: ChunkStore>(
+    store: &S,
+    policy: &GcPolicy,
+    now_ms: u64,
+    dry_run: bool,
+) -> Result<GcReport, String> {
+    let mut sessions = Vec::new();
+    for session_id in store.list_sessions().await? {
+        let manifest = store.get_manifest(&session_id).await?;
+        let held = manifest
+            .as_ref()
+            .and_then(|manifest| manifest.metadata.as_ref())
+            .and_then(|metadata| metadata.retention)
+            .is_some_and(|retention| retention.legal_hold);
+        let (bytes, last_used_ms, synced) = match &manifest {
+            Some(manifest) => (
+                manifest.total_size(),
+                manifest
+                    .chunks
+                    .iter()
+                    .map(|c| c.created_at)
+                    .max()
+                    .unwrap_or(0),
+                manifest.state == SessionState::Synced,
+            ),
+            None => (0, 0, false),
+        };
+        sessions.push(StoredSession {
+            session_id,
+            bytes,
+            last_used_ms,
+            synced,
+            held,
+        });
+    }
+    // Least recently used first
+    sessions.sort_by_key(|s| s.last_used_ms);
+
+    let mut used_bytes: u64 = sessions.iter().map(|s| s.bytes).sum();
+    let unsynced_bytes = sessions.iter().filter(|s| !s.synced).map(|s| s.bytes).sum();
+    let held_bytes = sessions
+        .iter()
+        .filter(|s| s.synced && s.held)
+        .map(|s| s.bytes)
+        .sum();
+    let mut reclaimed = Vec::new();
+    let mut kept = Vec::new();
+    for session in sessions.into_iter().filter(|s| s.synced && !s.held) {
+        let expired = policy
+            .synced_retention_ms
+            .is_some_and(|retention| now_ms.saturating_sub(session.last_used_ms) >= retention);
+        if expired {
+            used_bytes -= session.bytes;
+            reclaimed.push(GcReclaim {
+                session_id: session.session_id,
+                bytes: session.bytes,
+                reason: GcReason::Retention,
+            });
+        } else {
+            kept.push(session);
+        }
+    }
+    if let Some(budget) = policy.budget_bytes {
+        for session in kept {
+            if used_bytes <= budget {
+                break;
+            }
+            used_bytes -= session.bytes;
+            reclaimed.push(GcReclaim {
+                session_id: session.session_id,
+                bytes: session.bytes,
+                reason: GcReason::Budget,
+            });
+        }
+    }
+
+    if !dry_run {
+        for reclaim in &reclaimed {
+            store.delete_session(&reclaim.session_id).await?;
+        }
+    }
+    let report = GcReport {
+        dry_run,
+        reclaimed_bytes: reclaimed.iter().map(|r| r.bytes).sum(),
+        reclaimed,
+        used_bytes,
+        unsynced_bytes,
+        held_bytes,
+        over_budget: policy
+            .budget_bytes
+            .is_some_and(|budget| used_bytes > budget),
+    };
+    log_event!(
+        LogLevel::Info,
+        "Storage garbage collected",
+        dry_run = dry_run,
+        sessions = report.reclaimed.len(),
+        reclaimed_bytes = report.reclaimed_bytes,
+        used_bytes = report.used_bytes,
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{ChunkId, ChunkMetadata, TrackKind};
+    use crate::manifest::ChunkManifest;
+    use crate::metadata::{RecordingMetadata, RetentionPolicy};
+    use crate::storage::memory::MemoryStore;
+
+    /// Store a finished session of `size` bytes recorded at `created_at`
+    async fn session(store: &MemoryStore, id: &str, size: u64, created_at: u64, synced: bool) {
+        let session_id = SessionId::from(id);
+        let chunk_id = ChunkId::new(session_id.clone(), TrackKind::Muxed, 0);
+        store
+            .put_chunk(&chunk_id, &vec![0; size as usize])
+            .await
+            .unwrap();
+        let mut manifest = ChunkManifest::new(session_id.clone());
+        manifest.state = SessionState::Finalizing;
+        manifest
+            .add_chunk(ChunkMetadata {
+                chunk_id,
+                timestamp_us: 0,
+                size,
+                hash: None,
+                has_keyframe: Some(true),
+                created_at,
+            })
+            .unwrap();
+        store.put_manifest(&manifest).await.unwrap();
+        if synced {
+            mark_synced(store, &session_id).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_collect_garbage() {
+        let store = MemoryStore::default();
+        session(&store, "old", 400, 1_000, true).await;
+        session(&store, "recent", 300, 5_000, true).await;
+        session(&store, "newest", 200, 6_000, true).await;
+        session(&store, "pending", 500, 2_000, false).await;
+
+        // Retention removes old synced sessions, the budget evicts LRU next
+        let policy = GcPolicy {
+            synced_retention_ms: Some(8_000),
+            budget_bytes: Some(800),
+        };
+        let report = collect_garbage(&store, &policy, 10_000, true)
+            .await
+            .unwrap();
+        let reclaimed: Vec<(&str, GcReason)> = report
+            .reclaimed
+            .iter()
+            .map(|r| (r.session_id.as_str(), r.reason))
+            .collect();
+        assert_eq!(
+            reclaimed,
+            vec![("old", GcReason::Retention), ("recent", GcReason::Budget)]
+        );
+        assert_eq!(report.reclaimed_bytes, 700);
+        assert_eq!(report.used_bytes, 700);
+        assert_eq!(report.unsynced_bytes, 500);
+        assert!(!report.over_budget);
+        // A dry run deletes nothing
+        assert_eq!(store.list_sessions().await.unwrap().len(), 4);
+
+        collect_garbage(&store, &policy, 10_000, false)
+            .await
+            .unwrap();
+        let left: Vec<SessionId> = store.list_sessions().await.unwrap();
+        assert_eq!(
+            left,
+            vec![SessionId::from("newest"), SessionId::from("pending")]
+        );
+
+        // Unsynced sessions are kept even over budget
+        let policy = GcPolicy {
+            synced_retention_ms: None,
+            budget_bytes: Some(100),
+        };
+        let report = collect_garbage(&store, &policy, 10_000, false)
+            .await
+            .unwrap();
+        assert_eq!(report.reclaimed.len(), 1);
+        assert_eq!(report.used_bytes, 500);
+        assert!(report.over_budget);
+        assert_eq!(
+            store.list_sessions().await.unwrap(),
+            vec![SessionId::from("pending")]
+        );
+
+        // Only finished sessions can be marked synced
+        session(&store, "live", 10, 0, false).await;
+        let mut manifest = store.get_manifest(&SessionId::from("live")).await.unwrap();
+        manifest.as_mut().unwrap().state = SessionState::Recording;
+        store.put_manifest(&manifest.unwrap()).await.unwrap();
+        assert!(mark_synced(&store, &SessionId::from("live")).await.is_err());
+        assert!(mark_synced(&store, &SessionId::from("gone")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_legal_hold_is_never_collected() {
+        let store = MemoryStore::default();
+        session(&store, "held", 400, 1_000, true).await;
+        session(&store, "free", 300, 2_000, true).await;
+        let held = SessionId::from("held");
+        let mut manifest = store.get_manifest(&held).await.unwrap().unwrap();
+        manifest.metadata = Some(RecordingMetadata {
+            retention: Some(RetentionPolicy {
+                expire_after_ms: None,
+                legal_hold: true,
+            }),
+            ..Default::default()
+        });
+        store.put_manifest(&manifest).await.unwrap();
+
+        let policy = GcPolicy {
+            synced_retention_ms: Some(0),
+            budget_bytes: Some(0),
+        };
+        let report = collect_garbage(&store, &policy, 10_000, false)
+            .await
+            .unwrap();
+        assert_eq!(report.reclaimed.len(), 1);
+        assert_eq!(report.reclaimed[0].session_id, SessionId::from("free"));
+        assert_eq!((report.used_bytes, report.held_bytes), (400, 400));
+        assert!(report.over_budget);
+        assert_eq!(store.list_sessions().await.unwrap(), vec![held]);
+    }
+}
@@ -0,0 +1,190 @@
+//! IndexedDB backend, for browsers with missing or unreliable OPFS support.
+//!
+//! All files live in a single object store keyed by `[sessionId, fileName]`,
+//! so a session's files form one contiguous key range that can be listed or
+//! deleted in a single request.
+
+use js_sys::{Array, Function, Promise, Uint8Array};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    IdbDatabase, IdbKeyRange, IdbObjectStore, IdbRequest, IdbTransaction, IdbTransactionMode,
+};
+
+use super::{global_scope, js_error, ChunkStore, GlobalScope};
+use crate::session::SessionId;
+
+/// Database name, separate from the web client's metadata database
+const DB_NAME: &str = "maycast-chunk-store";
+const DB_VERSION: u32 = 1;
+const FILES_STORE: &str = "files";
+
+/// Highest code unit, used as the upper bound of a session's key range
+const KEY_MAX: &str = "\u{ffff}";
+
+/// Chunk storage backed by an IndexedDB object store
+#[derive(Clone)]
+pub struct IndexedDbStore {
+    db: IdbDatabase,
+}
+
+impl IndexedDbStore {
+    /// Open (and create or upgrade if needed) the chunk database
+    pub async fn open() -> Result<Self, String> {
+        let factory = match global_scope()? {
+            GlobalScope::Window(window) => window.indexed_db(),
+            GlobalScope::Worker(worker) => worker.indexed_db(),
+        }
+        .map_err(js_error)?
+        .ok_or("IndexedDB is not available in this context")?;
+
+        let request = factory
+            .open_with_u32(DB_NAME, DB_VERSION)
+            .map_err(js_error)?;
+
+        let upgrade_request = request.clone();
+        let on_upgrade = Closure::once_into_js(move || {
+            if let Ok(db) = upgrade_request.result() {
+                let db: IdbDatabase = db.unchecked_into();
+                if !db.object_store_names().contains(FILES_STORE) {
+                    let _ = db.create_object_store(FILES_STORE);
+                }
+            }
+        });
+        request.set_onupgradeneeded(Some(on_upgrade.unchecked_ref()));
+
+        let db = await_request(&request).await?;
+        Ok(Self {
+            db: db.unchecked_into(),
+        })
+    }
+
+    fn files(&self, mode: IdbTransactionMode) -> Result<(IdbTransaction, IdbObjectStore), String> {
+        let transaction = self
+            .db
+            .transaction_with_str_and_mode(FILES_STORE, mode)
+            .map_err(js_error)?;
+        let store = transaction.object_store(FILES_STORE).map_err(js_error)?;
+        Ok((transaction, store))
+    }
+}
+
+impl ChunkStore for IndexedDbStore {
+    async fn put_file(&self, session: &SessionId, name: &str, data: &[u8]) -> Result<(), String> {
+        let (transaction, store) = self.files(IdbTransactionMode::Readwrite)?;
+        let done = transaction_done(&transaction);
+        store
+            .put_with_key(&Uint8Array::from(data), &file_key(session, name))
+            .map_err(js_error)?;
+        JsFuture::from(done).await.map(|_| ()).map_err(js_error)
+    }
+
+    async fn get_file(&self, session: &SessionId, name: &str) -> Result<Option<Vec<u8>>, String> {
+        let (_, store) = self.files(IdbTransactionMode::Readonly)?;
+        let request = store.get(&file_key(session, name)).map_err(js_error)?;
+        let value = await_request(&request).await?;
+        if value.is_undefined() {
+            return Ok(None);
+        }
+        Ok(Some(Uint8Array::new(&value).to_vec()))
+    }
+
+    async fn delete_file(&self, session: &SessionId, name: &str) -> Result<(), String> {
+        let (transaction, store) = self.files(IdbTransactionMode::Readwrite)?;
+        let done = transaction_done(&transaction);
+        store.delete(&file_key(session, name)).map_err(js_error)?;
+        JsFuture::from(done).await.map(|_| ()).map_err(js_error)
+    }
+
+    async fn list_files(&self, session: &SessionId) -> Result<Vec<String>, String> {
+        let (_, store) = self.files(IdbTransactionMode::Readonly)?;
+        let request = store
+            .get_all_keys_with_key(&session_range(session)?)
+            .map_err(js_error)?;
+        let keys: Array = await_request(&request).await?.unchecked_into();
+        Ok(keys
+            .iter()
+            .filter_map(|key| Array::from(&key).get(1).as_string())
+            .collect())
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<SessionId>, String> {
+        let (_, store) = self.files(IdbTransactionMode::Readonly)?;
+        let request = store.get_all_keys().map_err(js_error)?;
+        let keys: Array = await_request(&request).await?.unchecked_into();
+        let mut sessions: Vec<SessionId> = keys
+            .iter()
+            .filter_map(|key| Array::from(&key).get(0).as_string())
+            .map(SessionId::new)
+            .collect();
+        // Keys come back sorted, so duplicates are adjacent
+        sessions.dedup();
+        Ok(sessions)
+    }
+
+    async fn delete_session(&self, session: &SessionId) -> Result<(), String> {
+        let (transaction, store) = self.files(IdbTransactionMode::Readwrite)?;
+        let done = transaction_done(&transaction);
+        store.delete(&session_range(session)?).map_err(js_error)?;
+        JsFuture::from(done).await.map(|_| ()).map_err(js_error)
+    }
+}
+
+fn file_key(session: &SessionId, name: &str) -> JsValue {
+    Array::of2(&session.as_str().into(), &name.into()).into()
+}
+
+/// Key range covering every file of one session
+fn session_range(session: &SessionId) -> Result<JsValue, String> {
+    IdbKeyRange::bound(&file_key(session, ""), &file_key(session, KEY_MAX))
+        .map(JsValue::from)
+        .map_err(js_error)
+}
+
+/// Resolve with a request's result once it succeeds
+async fn await_request(request: &IdbRequest) -> Result<JsValue, String> {
+    let promise = Promise::new(&mut |resolve: Function, reject: Function| {
+        let success_request = request.clone();
+        let on_success = Closure::once_into_js(move || {
+            let _ = resolve.call1(
+                &JsValue::NULL,
+                &success_request.result().unwrap_or(JsValue::UNDEFINED),
+            );
+        });
+        let error_request = request.clone();
+        let on_error = Closure::once_into_js(move || {
+            let error = error_request
+                .error()
+                .ok()
+                .flatten()
+                .map(JsValue::from)
+                .unwrap_or(JsValue::UNDEFINED);
+            let _ = reject.call1(&JsValue::NULL, &error);
+        });
+        request.set_onsuccess(Some(on_success.unchecked_ref()));
+        request.set_onerror(Some(on_error.unchecked_ref()));
+    });
+    JsFuture::from(promise).await.map_err(js_error)
+}
+
+/// Resolve once a transaction commits, reject if it fails or is aborted
+///
+/// Must be called before the transaction's requests are issued.
+fn transaction_done(transaction: &IdbTransaction) -> Promise {
+    Promise::new(&mut |resolve: Function, reject: Function| {
+        let on_complete = Closure::once_into_js(move || {
+            let _ = resolve.call0(&JsValue::NULL);
+        });
+        let abort_reject = reject.clone();
+        let on_error = Closure::once_into_js(move || {
+            let _ = reject.call1(&JsValue::NULL, &"IndexedDB transaction failed".into());
+        });
+        let on_abort = Closure::once_into_js(move || {
+            let _ = abort_reject.call1(&JsValue::NULL, &"IndexedDB transaction aborted".into());
+        });
+        transaction.set_oncomplete(Some(on_complete.unchecked_ref()));
+        transaction.set_onerror(Some(on_error.unchecked_ref()));
+        transaction.set_onabort(Some(on_abort.unchecked_ref()));
+    })
+}
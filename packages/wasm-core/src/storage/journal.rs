@@ -0,0 +1,414 @@
+//! Append-only journal of a session's manifest.
+//!
+//! Rewriting the whole manifest after every chunk costs more the longer a
+//! recording runs, and a write torn by a crash leaves nothing readable.
+//! Instead every update is a new, small file: the changes since the previous
+//! entry (chunks, fragments, key periods and muted ranges appended, markers,
+//! metadata, state and tail replaced). Every `JOURNAL_COMPACT_INTERVAL`
+//! entries, or when the manifest changed in a way that is not an append,
+//! the whole manifest is written instead and older entries are deleted.
+//!
+//! Entry layout (little endian, 10-byte header, 8-byte trailer):
+//!
+//! ```text
+//! "MJNL" | version u8 | kind u8 | len u32 | payload[len] | checksum[8]
+//! ```
+//!
+//! The payload is JSON, gzip-compressed when that is smaller; the checksum
+//! is the start of the BLAKE3 hash of everything before it. Replay starts at
+//! the last readable full entry; an entry that is cut short or fails its
+//! checksum ends the changes applied, so a torn write loses only itself.
+
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::ChunkMetadata;
+use crate::compress::{compress_if_smaller, decompress_if_compressed, FAST_COMPRESSION_LEVEL};
+use crate::manifest::{ChunkManifest, FragmentRange, KeyPeriod, Marker, MutedRange, TailMarker};
+use crate::metadata::RecordingMetadata;
+use crate::session::SessionState;
+
+/// Change entries written between two full ones
+pub const JOURNAL_COMPACT_INTERVAL: u32 = 64;
+
+const MAGIC: &[u8; 4] = b"MJNL";
+const VERSION: u8 = 1;
+const KIND_FULL: u8 = 1;
+const KIND_CHANGES: u8 = 2;
+const HEADER_LEN: usize = 10;
+const CHECKSUM_LEN: usize = 8;
+
+/// Get the file name of a manifest journal entry
+pub fn journal_file_name(index: u64) -> String {
+    format!("manifest-{:08}.jnl", index)
+}
+
+/// Parse a manifest journal file name back into its index
+pub fn parse_journal_file_name(name: &str) -> Option<u64> {
+    name.strip_prefix("manifest-")?
+        .strip_suffix(".jnl")?
+        .parse()
+        .ok()
+}
+
+/// Items added to the end of a list
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Appended<T> {
+    /// Length of the list before them
+    from: usize,
+    items: Vec<T>,
+}
+
+impl<T: Clone + PartialEq> Appended<T> {
+    /// What was appended to `old` to get `new`; Err if `new` does not start
+    /// with `old`
+    fn diff(old: &[T], new: &[T]) -> Result<Option<Self>, ()> {
+        if new.len() < old.len() || new[..old.len()] != *old {
+            return Err(());
+        }
+        Ok((new.len() > old.len()).then(|| Self {
+            from: old.len(),
+            items: new[old.len()..].to_vec(),
+        }))
+    }
+
+    fn apply(self, list: &mut Vec<T>) -> Result<(), String> {
+        if self.from > list.len() {
+            return Err(format!(
+                "Journal entry appends at {} to a list of {}",
+                self.from,
+                list.len()
+            ));
+        }
+        list.truncate(self.from);
+        list.extend(self.items);
+        Ok(())
+    }
+}
+
+/// Payload of a change entry
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestChanges {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state: Option<SessionState>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<RecordingMetadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chunks: Option<Appended<ChunkMetadata>>,
+    /// All markers, as bookmarks are updated in place
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    markers: Option<Vec<Marker>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fragments: Option<Appended<FragmentRange>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keys: Option<Appended<KeyPeriod>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    muted: Option<Appended<MutedRange>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tail: Option<TailMarker>,
+}
+
+impl ManifestChanges {
+    /// Changes from `old` to `new`; None if they cannot be expressed as
+    /// changes (session, metadata or tail removed, a list not appended to)
+    fn diff(old: &ChunkManifest, new: &ChunkManifest) -> Option<Self> {
+        if old.session_id != new.session_id
+            || (old.metadata.is_some() && new.metadata.is_none())
+            || (old.tail.is_some() && new.tail.is_none())
+        {
+            return None;
+        }
+        Some(Self {
+            state: (old.state != new.state).then_some(new.state),
+            metadata: new
+                .metadata
+                .clone()
+                .filter(|_| old.metadata != new.metadata),
+            chunks: Appended::diff(&old.chunks, &new.chunks).ok()?,
+            markers: (old.markers != new.markers).then(|| new.markers.clone()),
+            fragments: Appended::diff(&old.fragments, &new.fragments).ok()?,
+            keys: Appended::diff(&old.keys, &new.keys).ok()?,
+            muted: Appended::diff(&old.muted, &new.muted).ok()?,
+            tail: new.tail.clone().filter(|_| old.tail != new.tail),
+        })
+    }
+
+    fn apply(self, manifest: &mut ChunkManifest) -> Result<(), String> {
+        if let Some(state) = self.state {
+            manifest.state = state;
+        }
+        if let Some(metadata) = self.metadata {
+            manifest.metadata = Some(metadata);
+        }
+        if let Some(chunks) = self.chunks {
+            chunks.apply(&mut manifest.chunks)?;
+        }
+        if let Some(markers) = self.markers {
+            manifest.markers = markers;
+        }
+        if let Some(fragments) = self.fragments {
+            fragments.apply(&mut manifest.fragments)?;
+        }
+        if let Some(keys) = self.keys {
+            keys.apply(&mut manifest.keys)?;
+        }
+        if let Some(muted) = self.muted {
+            muted.apply(&mut manifest.muted)?;
+        }
+        if let Some(tail) = self.tail {
+            manifest.tail = Some(tail);
+        }
+        Ok(())
+    }
+}
+
+/// A journal entry ready to be written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub index: u64,
+    /// Holds the whole manifest, making older entries obsolete
+    pub full: bool,
+    pub data: Vec<u8>,
+}
+
+/// Writes the journal of one session
+///
+/// Keeps the manifest as of the last entry written to diff the next one
+/// against, so only one writer may update a session's journal at a time.
+/// A fresh writer starts with a full entry.
+#[derive(Debug, Clone, Default)]
+pub struct ManifestJournal {
+    /// Index of the next entry; None until the existing entries are known
+    next_index: Option<u64>,
+    /// Manifest as of the last entry written
+    persisted: Option<ChunkManifest>,
+    /// Change entries since the last full one
+    changes: u32,
+}
+
+impl ManifestJournal {
+    /// Whether `start` was called
+    pub fn started(&self) -> bool {
+        self.next_index.is_some()
+    }
+
+    /// Continue after the session's existing entries, the last being
+    /// `last_index`
+    pub fn start(&mut self, last_index: Option<u64>) {
+        self.next_index = Some(last_index.map_or(0, |index| index + 1));
+    }
+
+    /// Entry bringing the journal to `manifest`; call `written` once it is
+    /// stored
+    pub fn entry(&self, manifest: &ChunkManifest) -> Result<JournalEntry, String> {
+        let index = self.next_index.ok_or("Manifest journal not started")?;
+        let changes = (self.persisted.as_ref())
+            .filter(|_| self.changes < JOURNAL_COMPACT_INTERVAL)
+            .and_then(|persisted| ManifestChanges::diff(persisted, manifest));
+        let (kind, json) = match changes {
+            Some(changes) => (KIND_CHANGES, serde_json::to_vec(&changes)),
+            None => (KIND_FULL, serde_json::to_vec(manifest)),
+        };
+        let json = json.map_err(|e| e.to_string())?;
+        Ok(JournalEntry {
+            index,
+            full: kind == KIND_FULL,
+            data: encode_entry(kind, &compress_if_smaller(&json, FAST_COMPRESSION_LEVEL)),
+        })
+    }
+
+    /// Record that `entry`, made from `manifest`, was stored
+    pub fn written(&mut self, entry: &JournalEntry, manifest: &ChunkManifest) {
+        self.next_index = Some(entry.index + 1);
+        self.persisted = Some(manifest.clone());
+        self.changes = if entry.full { 0 } else { self.changes + 1 };
+    }
+}
+
+fn encode_entry(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_LEN + payload.len() + CHECKSUM_LEN);
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.push(kind);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    let checksum = blake3::hash(&out);
+    out.extend_from_slice(&checksum.as_bytes()[..CHECKSUM_LEN]);
+    out
+}
+
+/// Contents of a stored entry
+enum Entry {
+    Full(Box<ChunkManifest>),
+    Changes(Box<ManifestChanges>),
+}
+
+fn decode_entry(data: &[u8]) -> Result<Entry, String> {
+    if data.len() < HEADER_LEN + CHECKSUM_LEN || &data[..4] != MAGIC {
+        return Err("Not a manifest journal entry".to_string());
+    }
+    if data[4] != VERSION {
+        return Err(format!("Unsupported manifest journal version {}", data[4]));
+    }
+    let len = u32::from_le_bytes(data[6..10].try_into().unwrap()) as usize;
+    if data.len() != HEADER_LEN + len + CHECKSUM_LEN {
+        return Err("Manifest journal entry cut short".to_string());
+    }
+    let (body, checksum) = data.split_at(HEADER_LEN + len);
+    if blake3::hash(body).as_bytes()[..CHECKSUM_LEN] != *checksum {
+        return Err("Manifest journal entry checksum mismatch".to_string());
+    }
+    let json = decompress_if_compressed(&body[HEADER_LEN..])?;
+    let invalid = |e: serde_json::Error| format!("Invalid manifest journal entry: {}", e);
+    match data[5] {
+        KIND_FULL => Ok(Entry::Full(serde_json::from_slice(&json).map_err(invalid)?)),
+        KIND_CHANGES => Ok(Entry::Changes(
+            serde_json::from_slice(&json).map_err(invalid)?,
+        )),
+        kind => Err(format!("Unknown manifest journal entry kind {}", kind)),
+    }
+}
+
+/// Replay journal entries, in index order, into the manifest they describe
+///
+/// Starts from the last full entry that reads back; changes after an entry
+/// that does not read or apply are dropped, up to the next full entry.
+/// Returns the manifest, or None without a readable full entry, and the
+/// errors met.
+pub fn replay_journal<'a>(
+    entries: impl IntoIterator<Item = (u64, &'a [u8])>,
+) -> (Option<ChunkManifest>, Vec<String>) {
+    let mut manifest: Option<ChunkManifest> = None;
+    let mut broken = false;
+    let mut errors = Vec::new();
+    for (index, data) in entries {
+        let applied = match decode_entry(data) {
+            Ok(Entry::Full(full)) => {
+                manifest = Some(*full);
+                broken = false;
+                Ok(())
+            }
+            Ok(Entry::Changes(_)) if broken => continue,
+            Ok(Entry::Changes(changes)) => match manifest.as_mut() {
+                Some(manifest) => {
+                    // Apply to a copy so a failing entry leaves no trace
+                    let mut updated = manifest.clone();
+                    changes.apply(&mut updated).map(|()| *manifest = updated)
+                }
+                None => continue,
+            },
+            Err(e) => Err(e),
+        };
+        if let Err(e) = applied {
+            errors.push(format!("Entry {}: {}", index, e));
+            broken = true;
+        }
+    }
+    (manifest, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{ChunkId, TrackKind};
+    use crate::session::SessionId;
+
+    fn add_chunk(manifest: &mut ChunkManifest, sequence: u64) {
+        let chunk_id = ChunkId::new(manifest.session_id.clone(), TrackKind::Muxed, sequence);
+        manifest
+            .add_chunk(ChunkMetadata {
+                chunk_id,
+                timestamp_us: sequence * 2_000_000,
+                size: 250_000,
+                hash: Some("ab".repeat(32)),
+                has_keyframe: Some(true),
+                created_at: 1_700_000_000_000,
+            })
+            .unwrap();
+    }
+
+    fn write(
+        journal: &mut ManifestJournal,
+        manifest: &ChunkManifest,
+        files: &mut Vec<(u64, Vec<u8>)>,
+    ) -> JournalEntry {
+        let entry = journal.entry(manifest).unwrap();
+        journal.written(&entry, manifest);
+        files.push((entry.index, entry.data.clone()));
+        entry
+    }
+
+    #[test]
+    fn test_journal_replays_changes_and_survives_torn_writes() {
+        let mut manifest = ChunkManifest::new(SessionId::from("s1"));
+        let mut journal = ManifestJournal::default();
+        journal.start(Some(6));
+        let mut files = Vec::new();
+
+        assert!(write(&mut journal, &manifest, &mut files).full);
+        for sequence in 0..3 {
+            add_chunk(&mut manifest, sequence);
+            let entry = write(&mut journal, &manifest, &mut files);
+            assert!(!entry.full);
+            assert!(entry.data.len() < 400);
+        }
+        manifest.add_marker(Marker {
+            timestamp_us: 1_000_000,
+            label: Some("Intro".to_string()),
+            bookmark: None,
+        });
+        manifest.state = SessionState::Finalizing;
+        write(&mut journal, &manifest, &mut files);
+        assert_eq!(files.first().unwrap().0, 7);
+
+        let replay = |files: &[(u64, Vec<u8>)]| {
+            replay_journal(files.iter().map(|(index, data)| (*index, data.as_slice())))
+        };
+        assert_eq!(replay(&files), (Some(manifest.clone()), vec![]));
+
+        // A torn last write loses only that entry
+        let mut torn = files.clone();
+        let last = torn.last_mut().unwrap();
+        last.1.truncate(last.1.len() / 2);
+        let (replayed, errors) = replay(&torn);
+        assert_eq!(replayed.unwrap().chunks.len(), 3);
+        assert_eq!(errors.len(), 1);
+
+        // A corrupt entry in the middle stops the changes after it
+        let mut corrupt = files.clone();
+        corrupt[2].1[12] ^= 0xFF;
+        let (replayed, errors) = replay(&corrupt);
+        assert_eq!(replayed.unwrap().chunks.len(), 1);
+        assert_eq!(
+            errors,
+            ["Entry 9: Manifest journal entry checksum mismatch"]
+        );
+
+        // Removing a chunk is not an append: the whole manifest is written
+        manifest.chunks.pop();
+        assert!(write(&mut journal, &manifest, &mut files).full);
+        assert_eq!(replay(&files).0, Some(manifest));
+
+        assert_eq!(journal_file_name(12), "manifest-00000012.jnl");
+        assert_eq!(parse_journal_file_name("manifest-00000012.jnl"), Some(12));
+        assert_eq!(parse_journal_file_name("manifest.json"), None);
+    }
+
+    #[test]
+    fn test_journal_compacts_periodically() {
+        let mut manifest = ChunkManifest::new(SessionId::from("s1"));
+        let mut journal = ManifestJournal::default();
+        journal.start(None);
+        let mut files = Vec::new();
+        let mut full = Vec::new();
+        for sequence in 0..=JOURNAL_COMPACT_INTERVAL as u64 + 1 {
+            let entry = write(&mut journal, &manifest, &mut files);
+            if entry.full {
+                full.push(entry.index);
+            }
+            add_chunk(&mut manifest, sequence);
+        }
+        assert_eq!(full, [0, JOURNAL_COMPACT_INTERVAL as u64 + 1]);
+    }
+}
@@ -0,0 +1,59 @@
+//! In-memory `ChunkStore` for tests.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use super::ChunkStore;
+use crate::session::SessionId;
+
+/// Keeps every file in a map keyed by session and file name
+#[derive(Default)]
+pub struct MemoryStore {
+    files: RefCell<BTreeMap<(SessionId, String), Vec<u8>>>,
+}
+
+impl ChunkStore for MemoryStore {
+    async fn put_file(&self, session: &SessionId, name: &str, data: &[u8]) -> Result<(), String> {
+        self.files
+            .borrow_mut()
+            .insert((session.clone(), name.to_string()), data.to_vec());
+        Ok(())
+    }
+
+    async fn get_file(&self, session: &SessionId, name: &str) -> Result<Option<Vec<u8>>, String> {
+        Ok(self
+            .files
+            .borrow()
+            .get(&(session.clone(), name.to_string()))
+            .cloned())
+    }
+
+    async fn delete_file(&self, session: &SessionId, name: &str) -> Result<(), String> {
+        self.files
+            .borrow_mut()
+            .remove(&(session.clone(), name.to_string()));
+        Ok(())
+    }
+
+    async fn list_files(&self, session: &SessionId) -> Result<Vec<String>, String> {
+        Ok(self
+            .files
+            .borrow()
+            .keys()
+            .filter(|(s, _)| s == session)
+            .map(|(_, name)| name.clone())
+            .collect())
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<SessionId>, String> {
+        let mut sessions: Vec<SessionId> =
+            self.files.borrow().keys().map(|(s, _)| s.clone()).collect();
+        sessions.dedup();
+        Ok(sessions)
+    }
+
+    async fn delete_session(&self, session: &SessionId) -> Result<(), String> {
+        self.files.borrow_mut().retain(|(s, _), _| s != session);
+        Ok(())
+    }
+}
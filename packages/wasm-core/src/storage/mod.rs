@@ -0,0 +1,648 @@
+//! Persistent chunk storage.
+//!
+//! Each session is stored under its `SessionId` as a set of named files:
+//!
+//! ```text
+//! {session_id}/
+//!   init.mp4               fMP4 init segment (ftyp + moov)
+//!   chunk-00000000.fmp4    muxed media segments (same names as the web client)
+//!   video-00000000.fmp4    per-track segments
+//!   audio-00000000.fmp4
+//!   manifest-00000000.jnl  ChunkManifest journal entries (see `journal`)
+//!   manifest.json          serialized ChunkManifest (sessions from before
+//!                          the journal)
+//!   waveform.bin           audio waveform peaks (see `waveform`)
+//!   wal-00000000.bin       write-ahead log batches (only while recording)
+//!   recorder.bin           recorder snapshot for resuming (only while recording)
+//! ```
+//!
+//! Journal entries and WAL batches are written gzip-compressed when that
+//! makes them smaller (see `compress`); reading accepts either form.
+//!
+//! Backends only implement the file primitives of `ChunkStore`; chunk,
+//! init segment and manifest handling is shared. `ChunkStorage` picks a
+//! backend at runtime so everything above it has a single code path.
+
+mod archive;
+mod gc;
+mod indexed_db;
+mod journal;
+#[cfg(test)]
+pub(crate) mod memory;
+mod opfs;
+
+pub use archive::{
+    export_session, import_session, ImportedSession, ARCHIVE_CHUNKS_DIR, ARCHIVE_EVENTS_FILE,
+    ARCHIVE_MANIFEST_FILE, ARCHIVE_MARKERS_FILE, ARCHIVE_RECORDING_FILE,
+};
+pub use gc::{collect_garbage, mark_synced, GcPolicy, GcReason, GcReclaim, GcReport};
+pub use indexed_db::IndexedDbStore;
+pub use journal::{
+    journal_file_name, parse_journal_file_name, replay_journal, JournalEntry, ManifestJournal,
+    JOURNAL_COMPACT_INTERVAL,
+};
+pub use opfs::OpfsStore;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+use wasm_bindgen::{JsCast, JsValue};
+
+use crate::chunk::{ChunkId, TrackKind};
+use crate::compress::{
+    compress_if_smaller, decompress_if_compressed, DEFAULT_COMPRESSION_LEVEL,
+    FAST_COMPRESSION_LEVEL,
+};
+use crate::logging::{log_event, LogLevel};
+use crate::manifest::ChunkManifest;
+use crate::session::SessionId;
+use crate::wal::WalBatch;
+use crate::waveform::Waveform;
+
+/// File name of the init segment inside a session directory
+pub const INIT_SEGMENT_FILE: &str = "init.mp4";
+
+/// File name of the manifest of sessions stored before the journal
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// File name of the waveform peaks inside a session directory
+pub const WAVEFORM_FILE: &str = "waveform.bin";
+
+/// File name of the recorder snapshot inside a session directory
+pub const SNAPSHOT_FILE: &str = "recorder.bin";
+
+/// Get the file name a chunk is stored under inside its session directory
+///
+/// Muxed chunks keep the `chunk-{sequence}.fmp4` naming used by the web client,
+/// so sessions written from Rust stay readable by the existing TypeScript code.
+/// Simulcast chunks insert the rendition: `chunk-{rendition}-{sequence}.fmp4`.
+pub fn chunk_file_name(id: &ChunkId) -> String {
+    let prefix = match id.track {
+        TrackKind::Muxed => "chunk",
+        TrackKind::Video => "video",
+        TrackKind::Audio => "audio",
+    };
+    match &id.rendition {
+        Some(rendition) => format!("{}-{}-{:08}.fmp4", prefix, rendition, id.sequence),
+        None => format!("{}-{:08}.fmp4", prefix, id.sequence),
+    }
+}
+
+/// Get the file name of a write-ahead log batch
+pub fn wal_file_name(index: u64) -> String {
+    format!("wal-{:08}.bin", index)
+}
+
+/// Parse a write-ahead log batch file name back into its index
+pub fn parse_wal_file_name(name: &str) -> Option<u64> {
+    name.strip_prefix("wal-")?
+        .strip_suffix(".bin")?
+        .parse()
+        .ok()
+}
+
+/// Parse a chunk file name of `session` back into its chunk ID
+pub fn parse_chunk_file_name(session: &SessionId, name: &str) -> Option<ChunkId> {
+    let stem = name.strip_suffix(".fmp4")?;
+    let (prefix, rest) = stem.split_once('-')?;
+    let track = match prefix {
+        "chunk" => TrackKind::Muxed,
+        "video" => TrackKind::Video,
+        "audio" => TrackKind::Audio,
+        _ => return None,
+    };
+    let (rendition, sequence) = match rest.rsplit_once('-') {
+        Some((rendition, sequence)) => (Some(rendition), sequence),
+        None => (None, rest),
+    };
+    let id = ChunkId::new(session.clone(), track, sequence.parse().ok()?);
+    Some(match rendition {
+        Some(rendition) => id.with_rendition(rendition),
+        None => id,
+    })
+}
+
+/// Persistent storage for session chunks
+///
+/// Implementors provide per-session file access; the chunk-level methods are
+/// built on top of those.
+#[allow(async_fn_in_trait)]
+pub trait ChunkStore {
+    /// Write a file, replacing any previous contents
+    async fn put_file(&self, session: &SessionId, name: &str, data: &[u8]) -> Result<(), String>;
+
+    /// Read a file, or None if it does not exist
+    async fn get_file(&self, session: &SessionId, name: &str) -> Result<Option<Vec<u8>>, String>;
+
+    /// Delete a file; deleting a missing file is not an error
+    async fn delete_file(&self, session: &SessionId, name: &str) -> Result<(), String>;
+
+    /// List the file names stored for a session
+    async fn list_files(&self, session: &SessionId) -> Result<Vec<String>, String>;
+
+    /// List the IDs of all stored sessions, sorted
+    async fn list_sessions(&self) -> Result<Vec<SessionId>, String>;
+
+    /// Delete a session and everything stored for it
+    ///
+    /// Deleting a session that does not exist is not an error.
+    async fn delete_session(&self, session: &SessionId) -> Result<(), String>;
+
+    /// Write the init segment of a session
+    async fn put_init_segment(&self, session: &SessionId, data: &[u8]) -> Result<(), String> {
+        self.put_file(session, INIT_SEGMENT_FILE, data).await
+    }
+
+    /// Read the init segment of a session
+    async fn get_init_segment(&self, session: &SessionId) -> Result<Vec<u8>, String> {
+        self.get_file(session, INIT_SEGMENT_FILE)
+            .await?
+            .ok_or_else(|| format!("Init segment not found for session {}", session))
+    }
+
+    /// Write a chunk's data
+    async fn put_chunk(&self, id: &ChunkId, data: &[u8]) -> Result<(), String> {
+        self.put_file(&id.session, &chunk_file_name(id), data).await
+    }
+
+    /// Read a chunk's data
+    async fn get_chunk(&self, id: &ChunkId) -> Result<Vec<u8>, String> {
+        self.get_file(&id.session, &chunk_file_name(id))
+            .await?
+            .ok_or_else(|| format!("Chunk not found: {}", id))
+    }
+
+    /// List the chunks stored for a session, in ID order
+    ///
+    /// Includes chunks written after the last persisted manifest.
+    async fn list(&self, session: &SessionId) -> Result<Vec<ChunkId>, String> {
+        let mut chunks: Vec<ChunkId> = self
+            .list_files(session)
+            .await?
+            .iter()
+            .filter_map(|name| parse_chunk_file_name(session, name))
+            .collect();
+        chunks.sort();
+        Ok(chunks)
+    }
+
+    /// Persist a session's whole manifest, replacing its journal
+    async fn put_manifest(&self, manifest: &ChunkManifest) -> Result<(), String> {
+        self.append_manifest(&mut ManifestJournal::default(), manifest)
+            .await
+    }
+
+    /// Bring the session's manifest journal up to `manifest`
+    ///
+    /// Writes the changes since the entry `journal` wrote last, or the whole
+    /// manifest, after which the entries (and legacy manifest file) it
+    /// replaces are deleted.
+    async fn append_manifest(
+        &self,
+        journal: &mut ManifestJournal,
+        manifest: &ChunkManifest,
+    ) -> Result<(), String> {
+        let session = &manifest.session_id;
+        if !journal.started() {
+            let files = self.list_files(session).await?;
+            journal.start(
+                files
+                    .iter()
+                    .filter_map(|name| parse_journal_file_name(name))
+                    .max(),
+            );
+        }
+        let entry = journal.entry(manifest)?;
+        self.put_file(session, &journal_file_name(entry.index), &entry.data)
+            .await?;
+        journal.written(&entry, manifest);
+        if entry.full {
+            for name in self.list_files(session).await? {
+                let obsolete = name == MANIFEST_FILE
+                    || parse_journal_file_name(&name).is_some_and(|index| index < entry.index);
+                if obsolete {
+                    self.delete_file(session, &name).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a complete session that is not stored yet: the init segment,
+    /// the chunks, then the manifest
+    ///
+    /// Fails without writing anything if the session already has files.
+    async fn put_new_session(
+        &self,
+        manifest: &ChunkManifest,
+        init_segment: &[u8],
+        chunks: &[(&ChunkId, &[u8])],
+    ) -> Result<(), String> {
+        let session = &manifest.session_id;
+        if !self.list_files(session).await?.is_empty() {
+            return Err(format!("Session {} already exists", session));
+        }
+        self.put_init_segment(session, init_segment).await?;
+        for (id, data) in chunks {
+            self.put_chunk(id, data).await?;
+        }
+        self.put_manifest(manifest).await
+    }
+
+    /// Persist a session's waveform peaks, compressed
+    async fn put_waveform(&self, session: &SessionId, waveform: &Waveform) -> Result<(), String> {
+        let bytes = waveform.to_bytes();
+        let data = compress_if_smaller(&bytes, DEFAULT_COMPRESSION_LEVEL);
+        self.put_file(session, WAVEFORM_FILE, &data).await
+    }
+
+    /// Read a session's waveform peaks, or None if none were persisted
+    async fn get_waveform(&self, session: &SessionId) -> Result<Option<Waveform>, String> {
+        match self.get_file(session, WAVEFORM_FILE).await? {
+            Some(data) => {
+                let bytes = decompress_if_compressed(&data)
+                    .map_err(|e| format!("Invalid waveform for session {}: {}", session, e))?;
+                Waveform::from_bytes(&bytes).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Persist one batch of the session's write-ahead log, compressed
+    async fn put_wal_batch(&self, session: &SessionId, batch: &WalBatch) -> Result<(), String> {
+        let data = compress_if_smaller(&batch.data, FAST_COMPRESSION_LEVEL);
+        self.put_file(session, &wal_file_name(batch.index), &data)
+            .await
+    }
+
+    /// Read the session's whole write-ahead log, batches concatenated in order
+    ///
+    /// A batch that does not decompress ends the log, like a record cut
+    /// short by a crash.
+    async fn get_wal(&self, session: &SessionId) -> Result<Vec<u8>, String> {
+        let mut indices: Vec<u64> = self
+            .list_files(session)
+            .await?
+            .iter()
+            .filter_map(|name| parse_wal_file_name(name))
+            .collect();
+        indices.sort_unstable();
+
+        let mut wal = Vec::new();
+        for index in indices {
+            if let Some(data) = self.get_file(session, &wal_file_name(index)).await? {
+                match decompress_if_compressed(&data) {
+                    Ok(batch) => wal.extend_from_slice(&batch),
+                    Err(e) => {
+                        log_event!(
+                            LogLevel::Warn,
+                            "Ignoring the rest of an unreadable write-ahead log",
+                            session = session,
+                            batch = index,
+                            error = e,
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(wal)
+    }
+
+    /// Delete the session's write-ahead log once it is no longer needed
+    async fn delete_wal(&self, session: &SessionId) -> Result<(), String> {
+        for name in self.list_files(session).await? {
+            if parse_wal_file_name(&name).is_some() {
+                self.delete_file(session, &name).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Persist the snapshot a recorder resumes the session from
+    async fn put_snapshot(&self, session: &SessionId, data: &[u8]) -> Result<(), String> {
+        self.put_file(session, SNAPSHOT_FILE, data).await
+    }
+
+    /// Read the session's recorder snapshot, or None if none was persisted
+    async fn get_snapshot(&self, session: &SessionId) -> Result<Option<Vec<u8>>, String> {
+        self.get_file(session, SNAPSHOT_FILE).await
+    }
+
+    /// Delete the session's recorder snapshot once it can no longer be resumed
+    async fn delete_snapshot(&self, session: &SessionId) -> Result<(), String> {
+        self.delete_file(session, SNAPSHOT_FILE).await
+    }
+
+    /// Read a session's manifest, or None if none was persisted
+    ///
+    /// Replays the journal, skipping entries that do not read back (see
+    /// `replay_journal`); sessions without one read the legacy manifest file.
+    async fn get_manifest(&self, session: &SessionId) -> Result<Option<ChunkManifest>, String> {
+        let mut indices: Vec<u64> = self
+            .list_files(session)
+            .await?
+            .iter()
+            .filter_map(|name| parse_journal_file_name(name))
+            .collect();
+        indices.sort_unstable();
+        let mut entries = Vec::new();
+        for index in indices {
+            if let Some(data) = self.get_file(session, &journal_file_name(index)).await? {
+                entries.push((index, data));
+            }
+        }
+        let (manifest, errors) = replay_journal(
+            entries
+                .iter()
+                .map(|(index, data)| (*index, data.as_slice())),
+        );
+        for error in errors {
+            log_event!(
+                LogLevel::Warn,
+                "Skipping unreadable manifest journal entries",
+                session = session,
+                error = error,
+            );
+        }
+        if manifest.is_some() {
+            return Ok(manifest);
+        }
+        match self.get_file(session, MANIFEST_FILE).await? {
+            Some(data) => {
+                let json = decompress_if_compressed(&data)
+                    .map_err(|e| format!("Invalid manifest for session {}: {}", session, e))?;
+                serde_json::from_slice(&json)
+                    .map(Some)
+                    .map_err(|e| format!("Invalid manifest for session {}: {}", session, e))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Storage backend selectable from JS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Origin Private File System
+    Opfs,
+    /// IndexedDB, for browsers with missing or unreliable OPFS support
+    IndexedDb,
+}
+
+/// A `ChunkStore` whose backend is chosen at runtime
+#[derive(Clone)]
+pub enum ChunkStorage {
+    Opfs(OpfsStore),
+    IndexedDb(IndexedDbStore),
+}
+
+impl ChunkStorage {
+    /// Open the given backend
+    pub async fn open(backend: StorageBackend) -> Result<Self, String> {
+        match backend {
+            StorageBackend::Opfs => Ok(ChunkStorage::Opfs(OpfsStore::open().await?)),
+            StorageBackend::IndexedDb => Ok(ChunkStorage::IndexedDb(IndexedDbStore::open().await?)),
+        }
+    }
+
+    /// Open OPFS if it is usable, otherwise fall back to IndexedDB
+    pub async fn open_preferred() -> Result<Self, String> {
+        match OpfsStore::open().await {
+            Ok(store) => Ok(ChunkStorage::Opfs(store)),
+            Err(_) => Self::open(StorageBackend::IndexedDb).await,
+        }
+    }
+
+    /// Backend this storage writes to
+    pub fn backend(&self) -> StorageBackend {
+        match self {
+            ChunkStorage::Opfs(_) => StorageBackend::Opfs,
+            ChunkStorage::IndexedDb(_) => StorageBackend::IndexedDb,
+        }
+    }
+}
+
+impl ChunkStore for ChunkStorage {
+    async fn put_file(&self, session: &SessionId, name: &str, data: &[u8]) -> Result<(), String> {
+        match self {
+            ChunkStorage::Opfs(store) => store.put_file(session, name, data).await,
+            ChunkStorage::IndexedDb(store) => store.put_file(session, name, data).await,
+        }
+    }
+
+    async fn get_file(&self, session: &SessionId, name: &str) -> Result<Option<Vec<u8>>, String> {
+        match self {
+            ChunkStorage::Opfs(store) => store.get_file(session, name).await,
+            ChunkStorage::IndexedDb(store) => store.get_file(session, name).await,
+        }
+    }
+
+    async fn delete_file(&self, session: &SessionId, name: &str) -> Result<(), String> {
+        match self {
+            ChunkStorage::Opfs(store) => store.delete_file(session, name).await,
+            ChunkStorage::IndexedDb(store) => store.delete_file(session, name).await,
+        }
+    }
+
+    async fn list_files(&self, session: &SessionId) -> Result<Vec<String>, String> {
+        match self {
+            ChunkStorage::Opfs(store) => store.list_files(session).await,
+            ChunkStorage::IndexedDb(store) => store.list_files(session).await,
+        }
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<SessionId>, String> {
+        match self {
+            ChunkStorage::Opfs(store) => store.list_sessions().await,
+            ChunkStorage::IndexedDb(store) => store.list_sessions().await,
+        }
+    }
+
+    async fn delete_session(&self, session: &SessionId) -> Result<(), String> {
+        match self {
+            ChunkStorage::Opfs(store) => store.delete_session(session).await,
+            ChunkStorage::IndexedDb(store) => store.delete_session(session).await,
+        }
+    }
+}
+
+/// Get the global scope of the current window or worker
+fn global_scope() -> Result<GlobalScope, String> {
+    let global = js_sys::global();
+    if let Some(window) = global.dyn_ref::<web_sys::Window>() {
+        return Ok(GlobalScope::Window(window.clone()));
+    }
+    if let Some(worker) = global.dyn_ref::<web_sys::WorkerGlobalScope>() {
+        return Ok(GlobalScope::Worker(worker.clone()));
+    }
+    Err("Storage is not available in this context".to_string())
+}
+
+enum GlobalScope {
+    Window(web_sys::Window),
+    Worker(web_sys::WorkerGlobalScope),
+}
+
+fn is_not_found(error: &JsValue) -> bool {
+    error
+        .dyn_ref::<web_sys::DomException>()
+        .is_some_and(|e| e.name() == "NotFoundError")
+}
+
+pub(crate) fn js_error(error: JsValue) -> String {
+    if let Some(e) = error.dyn_ref::<js_sys::Error>() {
+        return String::from(e.message());
+    }
+    error.as_string().unwrap_or_else(|| format!("{:?}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_file_names() {
+        let session = SessionId::from("s1");
+        let muxed = ChunkId::new(session.clone(), TrackKind::Muxed, 1);
+        let audio = ChunkId::new(session.clone(), TrackKind::Audio, 12);
+        let rendition = ChunkId::new(session.clone(), TrackKind::Muxed, 4).with_rendition("360p");
+
+        assert_eq!(chunk_file_name(&muxed), "chunk-00000001.fmp4");
+        assert_eq!(chunk_file_name(&audio), "audio-00000012.fmp4");
+        assert_eq!(chunk_file_name(&rendition), "chunk-360p-00000004.fmp4");
+        assert_eq!(chunk_file_name(&ChunkId::legacy(3)), "chunk-00000003.fmp4");
+
+        for id in [&muxed, &audio, &rendition] {
+            assert_eq!(
+                parse_chunk_file_name(&session, &chunk_file_name(id)).as_ref(),
+                Some(id)
+            );
+        }
+        let parse = |name| parse_chunk_file_name(&session, name);
+        assert_eq!(parse("init.mp4"), None);
+        assert_eq!(parse("manifest.json"), None);
+        assert_eq!(parse("chunk-abc.fmp4"), None);
+        assert_eq!(parse("wal-00000002.bin"), None);
+
+        assert_eq!(wal_file_name(2), "wal-00000002.bin");
+        assert_eq!(parse_wal_file_name("wal-00000002.bin"), Some(2));
+        assert_eq!(parse_wal_file_name("chunk-00000002.fmp4"), None);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_files_are_compressed() {
+        let store = memory::MemoryStore::default();
+        let session = SessionId::from("s1");
+        let mut manifest = ChunkManifest::new(session.clone());
+        for sequence in 0..100 {
+            manifest
+                .add_chunk(crate::chunk::ChunkMetadata {
+                    chunk_id: ChunkId::new(session.clone(), TrackKind::Muxed, sequence),
+                    timestamp_us: sequence * 2_000_000,
+                    size: 250_000,
+                    hash: Some("ab".repeat(32)),
+                    has_keyframe: Some(true),
+                    created_at: 1_700_000_000_000,
+                })
+                .unwrap();
+        }
+        store.put_manifest(&manifest).await.unwrap();
+        let stored = store
+            .get_file(&session, &journal_file_name(0))
+            .await
+            .unwrap()
+            .unwrap();
+        let json = serde_json::to_vec(&manifest).unwrap();
+        assert!(stored.len() * 5 < json.len());
+        assert_eq!(store.get_manifest(&session).await.unwrap(), Some(manifest));
+
+        // Manifests written before compression still read
+        let legacy = ChunkManifest::new(SessionId::from("s2"));
+        let plain = serde_json::to_vec(&legacy).unwrap();
+        store
+            .put_file(&legacy.session_id, MANIFEST_FILE, &plain)
+            .await
+            .unwrap();
+        let read = store.get_manifest(&legacy.session_id).await.unwrap();
+        assert_eq!(read, Some(legacy));
+
+        // WAL batches read back concatenated; an unreadable one ends the log
+        let records = [1u8, 0, 0, 0, 0, 0, 0, 0, 0, 0].repeat(50);
+        for index in 0..2 {
+            let batch = WalBatch {
+                index,
+                data: records.clone(),
+            };
+            store.put_wal_batch(&session, &batch).await.unwrap();
+        }
+        let mut broken = crate::compress::compress(&records, 1);
+        broken[12] ^= 0xFF;
+        store
+            .put_file(&session, &wal_file_name(2), &broken)
+            .await
+            .unwrap();
+        assert_eq!(store.get_wal(&session).await.unwrap(), records.repeat(2));
+    }
+
+    #[tokio::test]
+    async fn test_manifest_journal_replaces_rewrites() {
+        let store = memory::MemoryStore::default();
+        let session = SessionId::from("s1");
+        let mut manifest = ChunkManifest::new(session.clone());
+        let legacy = serde_json::to_vec(&manifest).unwrap();
+        store
+            .put_file(&session, MANIFEST_FILE, &legacy)
+            .await
+            .unwrap();
+
+        // A writer resuming the session starts with the whole manifest,
+        // replacing the legacy file, then appends changes
+        let mut journal = ManifestJournal::default();
+        for sequence in 0..3 {
+            store
+                .append_manifest(&mut journal, &manifest)
+                .await
+                .unwrap();
+            manifest
+                .add_chunk(crate::chunk::ChunkMetadata {
+                    chunk_id: ChunkId::new(session.clone(), TrackKind::Muxed, sequence),
+                    timestamp_us: sequence * 2_000_000,
+                    size: 250_000,
+                    hash: Some("ab".repeat(32)),
+                    has_keyframe: Some(true),
+                    created_at: 1_700_000_000_000,
+                })
+                .unwrap();
+        }
+        store
+            .append_manifest(&mut journal, &manifest)
+            .await
+            .unwrap();
+        let mut files = store.list_files(&session).await.unwrap();
+        files.sort();
+        assert_eq!(files, (0..4).map(journal_file_name).collect::<Vec<_>>());
+        assert_eq!(
+            store.get_manifest(&session).await.unwrap().as_ref(),
+            Some(&manifest)
+        );
+
+        // A write torn by a crash loses only itself
+        let last = store.get_file(&session, &files[3]).await.unwrap().unwrap();
+        store
+            .put_file(&session, &files[3], &last[..last.len() - 3])
+            .await
+            .unwrap();
+        let recovered = store.get_manifest(&session).await.unwrap().unwrap();
+        assert_eq!(recovered.chunks, manifest.chunks[..2]);
+
+        // A whole manifest written anew drops the older entries
+        store.put_manifest(&manifest).await.unwrap();
+        assert_eq!(
+            store.list_files(&session).await.unwrap(),
+            [journal_file_name(4)]
+        );
+        assert_eq!(store.get_manifest(&session).await.unwrap(), Some(manifest));
+    }
+}
@@ -0,0 +1,189 @@
+//! Origin Private File System (OPFS) backend.
+//!
+//! Uses the async `FileSystemDirectoryHandle` API, which is available both on
+//! the main thread and in workers. Each session is a directory under the OPFS
+//! root. Every write goes through a temporary writable stream, so a file is
+//! either fully replaced or left untouched.
+
+use js_sys::{Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    FileSystemDirectoryHandle, FileSystemFileHandle, FileSystemGetDirectoryOptions,
+    FileSystemGetFileOptions, FileSystemHandle, FileSystemRemoveOptions,
+    FileSystemWritableFileStream,
+};
+
+use super::{global_scope, is_not_found, js_error, ChunkStore, GlobalScope};
+use crate::session::SessionId;
+
+/// Chunk storage rooted at the OPFS root directory
+#[derive(Clone)]
+pub struct OpfsStore {
+    root: FileSystemDirectoryHandle,
+}
+
+impl OpfsStore {
+    /// Open the OPFS root of the current window or worker
+    pub async fn open() -> Result<Self, String> {
+        let storage = match global_scope()? {
+            GlobalScope::Window(window) => window.navigator().storage(),
+            GlobalScope::Worker(worker) => worker.navigator().storage(),
+        };
+        let root = await_as::<FileSystemDirectoryHandle>(storage.get_directory()).await?;
+        Ok(Self { root })
+    }
+
+    /// Get a session directory, or None if it doesn't exist and `create` is false
+    async fn session_dir(
+        &self,
+        session: &SessionId,
+        create: bool,
+    ) -> Result<Option<FileSystemDirectoryHandle>, String> {
+        if session.is_empty() {
+            return Err("Cannot store chunks without a session ID".to_string());
+        }
+        let options = FileSystemGetDirectoryOptions::new();
+        options.set_create(create);
+        let promise = self
+            .root
+            .get_directory_handle_with_options(session.as_str(), &options);
+        match JsFuture::from(promise).await {
+            Ok(dir) => Ok(Some(dir.unchecked_into())),
+            Err(e) if is_not_found(&e) => Ok(None),
+            Err(e) => Err(js_error(e)),
+        }
+    }
+
+    /// Open a temporary writable stream that replaces `name` of `session`
+    /// when closed, creating the session directory and file as needed
+    pub(crate) async fn create_writable(
+        &self,
+        session: &SessionId,
+        name: &str,
+    ) -> Result<FileSystemWritableFileStream, String> {
+        let dir = self
+            .session_dir(session, true)
+            .await?
+            .ok_or_else(|| format!("Failed to create directory for session {}", session))?;
+
+        let options = FileSystemGetFileOptions::new();
+        options.set_create(true);
+        let file: FileSystemFileHandle =
+            await_as(dir.get_file_handle_with_options(name, &options)).await?;
+        await_as(file.create_writable()).await
+    }
+}
+
+impl ChunkStore for OpfsStore {
+    async fn put_file(&self, session: &SessionId, name: &str, data: &[u8]) -> Result<(), String> {
+        let writable = self.create_writable(session, name).await?;
+
+        let written = match writable.write_with_u8_array(data) {
+            Ok(promise) => JsFuture::from(promise).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        match written {
+            Ok(()) => JsFuture::from(writable.close())
+                .await
+                .map(|_| ())
+                .map_err(js_error),
+            Err(e) => {
+                // Discard the temporary file so the previous contents survive
+                let _ = JsFuture::from(writable.abort()).await;
+                Err(js_error(e))
+            }
+        }
+    }
+
+    async fn get_file(&self, session: &SessionId, name: &str) -> Result<Option<Vec<u8>>, String> {
+        let Some(dir) = self.session_dir(session, false).await? else {
+            return Ok(None);
+        };
+        let handle: FileSystemFileHandle = match JsFuture::from(dir.get_file_handle(name)).await {
+            Ok(handle) => handle.unchecked_into(),
+            Err(e) if is_not_found(&e) => return Ok(None),
+            Err(e) => return Err(js_error(e)),
+        };
+        let file: web_sys::File = await_as(handle.get_file()).await?;
+        let buffer = JsFuture::from(file.array_buffer())
+            .await
+            .map_err(js_error)?;
+        Ok(Some(Uint8Array::new(&buffer).to_vec()))
+    }
+
+    async fn delete_file(&self, session: &SessionId, name: &str) -> Result<(), String> {
+        let Some(dir) = self.session_dir(session, false).await? else {
+            return Ok(());
+        };
+        match JsFuture::from(dir.remove_entry(name)).await {
+            Ok(_) => Ok(()),
+            Err(e) if is_not_found(&e) => Ok(()),
+            Err(e) => Err(js_error(e)),
+        }
+    }
+
+    async fn list_files(&self, session: &SessionId) -> Result<Vec<String>, String> {
+        let Some(dir) = self.session_dir(session, false).await? else {
+            return Ok(Vec::new());
+        };
+        Ok(list_entries(&dir)
+            .await?
+            .into_iter()
+            .filter(|entry| entry.is_instance_of::<FileSystemFileHandle>())
+            .map(|entry| entry.unchecked_into::<FileSystemHandle>().name())
+            .collect())
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<SessionId>, String> {
+        let mut sessions: Vec<SessionId> = list_entries(&self.root)
+            .await?
+            .into_iter()
+            .filter(|entry| entry.is_instance_of::<FileSystemDirectoryHandle>())
+            .map(|entry| SessionId::new(entry.unchecked_into::<FileSystemHandle>().name()))
+            .collect();
+        sessions.sort();
+        Ok(sessions)
+    }
+
+    async fn delete_session(&self, session: &SessionId) -> Result<(), String> {
+        let options = FileSystemRemoveOptions::new();
+        options.set_recursive(true);
+        let promise = self
+            .root
+            .remove_entry_with_options(session.as_str(), &options);
+        match JsFuture::from(promise).await {
+            Ok(_) => Ok(()),
+            Err(e) if is_not_found(&e) => Ok(()),
+            Err(e) => Err(js_error(e)),
+        }
+    }
+}
+
+/// Collect all entries of a directory handle
+async fn list_entries(dir: &FileSystemDirectoryHandle) -> Result<Vec<JsValue>, String> {
+    let iter = dir.values();
+    let mut entries = Vec::new();
+    loop {
+        let next = JsFuture::from(iter.next().map_err(js_error)?)
+            .await
+            .map_err(js_error)?;
+        if get_property(&next, "done")?.as_bool().unwrap_or(true) {
+            break;
+        }
+        entries.push(get_property(&next, "value")?);
+    }
+    Ok(entries)
+}
+
+/// Await a promise and cast its result to a concrete JS type
+async fn await_as<T: JsCast>(promise: js_sys::Promise) -> Result<T, String> {
+    let value = JsFuture::from(promise).await.map_err(js_error)?;
+    value
+        .dyn_into::<T>()
+        .map_err(|v| format!("Unexpected OPFS value: {:?}", v))
+}
+
+fn get_property(target: &JsValue, key: &str) -> Result<JsValue, String> {
+    Reflect::get(target, &JsValue::from_str(key)).map_err(js_error)
+}
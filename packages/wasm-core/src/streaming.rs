@@ -0,0 +1,1003 @@
+//! Live segment streaming over a message-based connection (WebSocket).
+//!
+//! The recorder's init segment and chunks are sent as binary frames while
+//! they are also written to local storage. Each frame is one message:
+//!
+//! ```text
+//! [type: u8][payload]
+//!   0x01 Hello     [version: u8][session ID, UTF-8]
+//!   0x02 Init      [init segment]
+//!   0x03 Segment   [sequence: u64][metadata length: u32][ChunkMetadata JSON][data]
+//!   0x04 End       [segment count: u64]
+//!   0x05 Repair    [chunk ID length: u32][chunk ID, UTF-8][data]
+//!   0x10 Ack       [next expected sequence: u64]
+//!   0x11 Finished
+//!   0x12 Resend    [reason: u8][chunk IDs, UTF-8, newline-separated]
+//! ```
+//!
+//! Integers are big-endian. Segment sequence numbers are assigned by the
+//! sender and are contiguous per session, independent of chunk IDs.
+//! `SegmentSender` keeps segments until they are acknowledged, limits how
+//! many are unacknowledged on the wire, and resends them after a reconnect.
+//! `SegmentReceiver` puts segments back in order, persists them to a
+//! `ChunkStore` with the session manifest, and acknowledges what is stored.
+//! Its position is the manifest's chunk count, so a restarted receiver
+//! resumes from what it persisted.
+//!
+//! A receiver that finds a chunk corrupt or missing asks for it again with
+//! `Resend`, instead of the whole session being uploaded again. A segment
+//! that fails verification on arrival is still unacknowledged, so the sender
+//! sends it again from memory. Chunks acknowledged earlier are answered with
+//! `Repair` frames, served from local storage by the application through
+//! `SegmentSender::take_repair_requests` and `push_repair`. A receiver asks
+//! again for outstanding chunks on every reconnect and only reports the
+//! stream finished once all of them are repaired.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+use crate::chunk::{ChunkId, ChunkMetadata};
+use crate::compat::compare_init_segments;
+use crate::logging::{log_event, LogLevel};
+use crate::manifest::ChunkManifest;
+use crate::session::SessionId;
+use crate::storage::{chunk_file_name, ChunkStore, INIT_SEGMENT_FILE};
+
+/// Protocol version sent in `Hello`
+pub const STREAM_PROTOCOL_VERSION: u8 = 1;
+
+const HELLO: u8 = 0x01;
+const INIT: u8 = 0x02;
+const SEGMENT: u8 = 0x03;
+const END: u8 = 0x04;
+const REPAIR: u8 = 0x05;
+const ACK: u8 = 0x10;
+const FINISHED: u8 = 0x11;
+const RESEND: u8 = 0x12;
+
+/// Why a receiver asks for chunks again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ResendReason {
+    /// The stored data does not match the chunk's size or hash
+    HashMismatch = 1,
+    /// The chunk is listed in the manifest but not stored
+    Missing = 2,
+}
+
+impl fmt::Display for ResendReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ResendReason::HashMismatch => "hash mismatch",
+            ResendReason::Missing => "missing",
+        })
+    }
+}
+
+/// One protocol message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamFrame {
+    /// Sender → receiver, first frame on every connection
+    Hello { session_id: SessionId },
+    /// Sender → receiver, the session's init segment
+    Init { data: Vec<u8> },
+    /// Sender → receiver, one chunk
+    Segment {
+        sequence: u64,
+        metadata: ChunkMetadata,
+        data: Vec<u8>,
+    },
+    /// Sender → receiver, no segments follow the first `segment_count`
+    End { segment_count: u64 },
+    /// Sender → receiver, the data of a chunk asked for with `Resend`
+    Repair { chunk_id: ChunkId, data: Vec<u8> },
+    /// Receiver → sender, every segment before `next_sequence` is persisted
+    Ack { next_sequence: u64 },
+    /// Receiver → sender, every segment up to `End` is persisted
+    Finished,
+    /// Receiver → sender, send these chunks again
+    Resend {
+        reason: ResendReason,
+        chunk_ids: Vec<ChunkId>,
+    },
+}
+
+impl StreamFrame {
+    /// Encode the frame as one binary message
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        match self {
+            StreamFrame::Hello { session_id } => {
+                out.push(HELLO);
+                out.push(STREAM_PROTOCOL_VERSION);
+                out.extend_from_slice(session_id.as_str().as_bytes());
+            }
+            StreamFrame::Init { data } => {
+                out.push(INIT);
+                out.extend_from_slice(data);
+            }
+            StreamFrame::Segment {
+                sequence,
+                metadata,
+                data,
+            } => {
+                let metadata = serde_json::to_vec(metadata).map_err(|e| e.to_string())?;
+                out.reserve(13 + metadata.len() + data.len());
+                out.push(SEGMENT);
+                out.extend_from_slice(&sequence.to_be_bytes());
+                out.extend_from_slice(&(metadata.len() as u32).to_be_bytes());
+                out.extend_from_slice(&metadata);
+                out.extend_from_slice(data);
+            }
+            StreamFrame::End { segment_count } => {
+                out.push(END);
+                out.extend_from_slice(&segment_count.to_be_bytes());
+            }
+            StreamFrame::Repair { chunk_id, data } => {
+                let chunk_id = chunk_id.to_string();
+                out.reserve(5 + chunk_id.len() + data.len());
+                out.push(REPAIR);
+                out.extend_from_slice(&(chunk_id.len() as u32).to_be_bytes());
+                out.extend_from_slice(chunk_id.as_bytes());
+                out.extend_from_slice(data);
+            }
+            StreamFrame::Ack { next_sequence } => {
+                out.push(ACK);
+                out.extend_from_slice(&next_sequence.to_be_bytes());
+            }
+            StreamFrame::Finished => out.push(FINISHED),
+            StreamFrame::Resend { reason, chunk_ids } => {
+                out.push(RESEND);
+                out.push(*reason as u8);
+                let ids: Vec<String> = chunk_ids.iter().map(ChunkId::to_string).collect();
+                out.extend_from_slice(ids.join("\n").as_bytes());
+            }
+        }
+        Ok(out)
+    }
+
+    /// Decode one binary message
+    pub fn decode(message: &[u8]) -> Result<Self, String> {
+        let (&kind, payload) = message
+            .split_first()
+            .ok_or_else(|| "Empty stream frame".to_string())?;
+        match kind {
+            HELLO => {
+                let (&version, session) = payload
+                    .split_first()
+                    .ok_or_else(|| "Truncated hello frame".to_string())?;
+                if version != STREAM_PROTOCOL_VERSION {
+                    return Err(format!("Unsupported stream protocol version: {}", version));
+                }
+                let session = std::str::from_utf8(session)
+                    .map_err(|_| "Invalid session ID in hello frame".to_string())?;
+                Ok(StreamFrame::Hello {
+                    session_id: SessionId::from(session),
+                })
+            }
+            INIT => Ok(StreamFrame::Init {
+                data: payload.to_vec(),
+            }),
+            SEGMENT => {
+                let sequence = read_u64(payload, 0)?;
+                let length = read_u32(payload, 8)? as usize;
+                let end = 12usize
+                    .checked_add(length)
+                    .filter(|&end| end <= payload.len())
+                    .ok_or_else(|| "Truncated segment frame".to_string())?;
+                let metadata = serde_json::from_slice(&payload[12..end])
+                    .map_err(|e| format!("Invalid segment metadata: {}", e))?;
+                Ok(StreamFrame::Segment {
+                    sequence,
+                    metadata,
+                    data: payload[end..].to_vec(),
+                })
+            }
+            END => Ok(StreamFrame::End {
+                segment_count: read_u64(payload, 0)?,
+            }),
+            REPAIR => {
+                let length = read_u32(payload, 0)? as usize;
+                let end = 4usize
+                    .checked_add(length)
+                    .filter(|&end| end <= payload.len())
+                    .ok_or_else(|| "Invalid chunk ID in repair frame".to_string())?;
+                let chunk_id = std::str::from_utf8(&payload[4..end])
+                    .map_err(|_| "Invalid chunk ID in repair frame".to_string())?;
+                Ok(StreamFrame::Repair {
+                    chunk_id: chunk_id.parse()?,
+                    data: payload[end..].to_vec(),
+                })
+            }
+            ACK => Ok(StreamFrame::Ack {
+                next_sequence: read_u64(payload, 0)?,
+            }),
+            FINISHED => Ok(StreamFrame::Finished),
+            RESEND => {
+                let (&reason, ids) = payload
+                    .split_first()
+                    .ok_or_else(|| "Truncated resend frame".to_string())?;
+                let reason = match reason {
+                    1 => ResendReason::HashMismatch,
+                    2 => ResendReason::Missing,
+                    other => return Err(format!("Unknown resend reason: {}", other)),
+                };
+                let ids = std::str::from_utf8(ids)
+                    .map_err(|_| "Invalid chunk IDs in resend frame".to_string())?;
+                let chunk_ids = ids
+                    .split('\n')
+                    .filter(|id| !id.is_empty())
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?;
+                Ok(StreamFrame::Resend { reason, chunk_ids })
+            }
+            other => Err(format!("Unknown stream frame type: {:#04x}", other)),
+        }
+    }
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, String> {
+    data.get(offset..offset + 8)
+        .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| "Truncated stream frame".to_string())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| "Truncated stream frame".to_string())
+}
+
+struct PendingSegment {
+    sequence: u64,
+    metadata: ChunkMetadata,
+    data: Vec<u8>,
+}
+
+/// Sending end: queues segments and decides which frames go on the wire
+///
+/// Every method returns the encoded frames to send now, in order.
+pub struct SegmentSender {
+    session_id: SessionId,
+    max_in_flight: usize,
+    init_segment: Option<Vec<u8>>,
+    /// Segments not acknowledged yet, oldest first
+    unacked: VecDeque<PendingSegment>,
+    /// How many of `unacked` were sent on the current connection
+    in_flight: usize,
+    next_sequence: u64,
+    segment_count: Option<u64>,
+    end_sent: bool,
+    connected: bool,
+    finished: bool,
+    /// Acknowledged chunks the receiver asked for again
+    repair_requests: Vec<ChunkId>,
+}
+
+impl SegmentSender {
+    /// Create a sender allowing `max_in_flight` unacknowledged segments
+    pub fn new(session_id: SessionId, max_in_flight: u32) -> Self {
+        Self {
+            session_id,
+            max_in_flight: max_in_flight.max(1) as usize,
+            init_segment: None,
+            unacked: VecDeque::new(),
+            in_flight: 0,
+            next_sequence: 0,
+            segment_count: None,
+            end_sent: false,
+            connected: false,
+            finished: false,
+            repair_requests: Vec::new(),
+        }
+    }
+
+    /// Start a connection: hello, the init segment, then unacknowledged segments
+    pub fn connect(&mut self) -> Result<Vec<Vec<u8>>, String> {
+        self.connected = true;
+        self.in_flight = 0;
+        self.end_sent = false;
+        let mut frames = vec![StreamFrame::Hello {
+            session_id: self.session_id.clone(),
+        }
+        .encode()?];
+        if let Some(data) = &self.init_segment {
+            frames.push(StreamFrame::Init { data: data.clone() }.encode()?);
+        }
+        self.pump(&mut frames)?;
+        Ok(frames)
+    }
+
+    /// The connection was lost; nothing is sent until `connect()`
+    pub fn disconnect(&mut self) {
+        self.connected = false;
+    }
+
+    /// Set the init segment, sent first on every connection
+    pub fn push_init_segment(&mut self, data: Vec<u8>) -> Result<Vec<Vec<u8>>, String> {
+        let mut frames = Vec::new();
+        if self.connected {
+            frames.push(StreamFrame::Init { data: data.clone() }.encode()?);
+        }
+        self.init_segment = Some(data);
+        Ok(frames)
+    }
+
+    /// Queue a chunk for sending
+    pub fn push_segment(
+        &mut self,
+        metadata: ChunkMetadata,
+        data: Vec<u8>,
+    ) -> Result<Vec<Vec<u8>>, String> {
+        if self.segment_count.is_some() {
+            return Err("Cannot push segments after end".to_string());
+        }
+        self.unacked.push_back(PendingSegment {
+            sequence: self.next_sequence,
+            metadata,
+            data,
+        });
+        self.next_sequence += 1;
+        let mut frames = Vec::new();
+        self.pump(&mut frames)?;
+        Ok(frames)
+    }
+
+    /// Mark the stream complete; `End` is sent once every segment is on the wire
+    pub fn end(&mut self) -> Result<Vec<Vec<u8>>, String> {
+        self.segment_count = Some(self.next_sequence);
+        let mut frames = Vec::new();
+        self.pump(&mut frames)?;
+        Ok(frames)
+    }
+
+    /// Handle a frame from the receiver
+    pub fn receive(&mut self, message: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        match StreamFrame::decode(message)? {
+            StreamFrame::Ack { next_sequence } => {
+                while self
+                    .unacked
+                    .front()
+                    .is_some_and(|segment| segment.sequence < next_sequence)
+                {
+                    self.unacked.pop_front();
+                    self.in_flight = self.in_flight.saturating_sub(1);
+                }
+                let mut frames = Vec::new();
+                self.pump(&mut frames)?;
+                Ok(frames)
+            }
+            StreamFrame::Finished => {
+                self.finished = true;
+                Ok(Vec::new())
+            }
+            StreamFrame::Resend { reason, chunk_ids } => {
+                log_event!(
+                    LogLevel::Warn,
+                    "Receiver asked for chunks again",
+                    session = self.session_id,
+                    reason = reason,
+                    chunks = chunk_ids.len(),
+                );
+                self.finished = false;
+                let mut frames = Vec::new();
+                for chunk_id in chunk_ids {
+                    let queued = self
+                        .unacked
+                        .iter()
+                        .position(|segment| segment.metadata.chunk_id == chunk_id);
+                    match queued {
+                        // Segments not sent yet go out in order anyway
+                        Some(index) if index >= self.in_flight => {}
+                        Some(index) => {
+                            if self.connected {
+                                let segment = &self.unacked[index];
+                                frames.push(
+                                    StreamFrame::Segment {
+                                        sequence: segment.sequence,
+                                        metadata: segment.metadata.clone(),
+                                        data: segment.data.clone(),
+                                    }
+                                    .encode()?,
+                                );
+                            }
+                        }
+                        None => {
+                            if !self.repair_requests.contains(&chunk_id) {
+                                self.repair_requests.push(chunk_id);
+                            }
+                        }
+                    }
+                }
+                Ok(frames)
+            }
+            other => Err(format!("Unexpected frame from receiver: {:?}", other)),
+        }
+    }
+
+    /// Take the acknowledged chunks the receiver asked for again
+    ///
+    /// They are no longer held in memory; read each one from local storage
+    /// and pass it to `push_repair`.
+    pub fn take_repair_requests(&mut self) -> Vec<ChunkId> {
+        std::mem::take(&mut self.repair_requests)
+    }
+
+    /// Send the data of a chunk taken from `take_repair_requests`
+    ///
+    /// Nothing is sent while disconnected; the receiver asks again for
+    /// chunks it is still missing when the connection is back.
+    pub fn push_repair(
+        &mut self,
+        chunk_id: ChunkId,
+        data: Vec<u8>,
+    ) -> Result<Vec<Vec<u8>>, String> {
+        if !self.connected {
+            return Ok(Vec::new());
+        }
+        Ok(vec![StreamFrame::Repair { chunk_id, data }.encode()?])
+    }
+
+    /// Number of segments not acknowledged yet
+    pub fn pending(&self) -> usize {
+        self.unacked.len()
+    }
+
+    /// Whether the receiver confirmed the whole stream
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn pump(&mut self, frames: &mut Vec<Vec<u8>>) -> Result<(), String> {
+        if !self.connected {
+            return Ok(());
+        }
+        while self.in_flight < self.max_in_flight.min(self.unacked.len()) {
+            let segment = &self.unacked[self.in_flight];
+            frames.push(
+                StreamFrame::Segment {
+                    sequence: segment.sequence,
+                    metadata: segment.metadata.clone(),
+                    data: segment.data.clone(),
+                }
+                .encode()?,
+            );
+            self.in_flight += 1;
+        }
+        if let Some(segment_count) = self.segment_count {
+            if !self.end_sent && self.in_flight == self.unacked.len() {
+                frames.push(StreamFrame::End { segment_count }.encode()?);
+                self.end_sent = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Receiving end: reorders segments and persists them to a `ChunkStore`
+///
+/// Handles one session per connection. Returns the frames to send back
+/// after every message; acknowledgements are only sent for segments that
+/// have been written.
+pub struct SegmentReceiver {
+    max_buffered: usize,
+    manifest: Option<ChunkManifest>,
+    buffered: BTreeMap<u64, (ChunkMetadata, Vec<u8>)>,
+    segment_count: Option<u64>,
+    /// Persisted chunks asked for again and not repaired yet
+    requested: BTreeMap<ChunkId, ResendReason>,
+}
+
+impl SegmentReceiver {
+    /// Create a receiver holding at most `max_buffered` out-of-order segments
+    pub fn new(max_buffered: u32) -> Self {
+        Self {
+            max_buffered: max_buffered.max(1) as usize,
+            manifest: None,
+            buffered: BTreeMap::new(),
+            segment_count: None,
+            requested: BTreeMap::new(),
+        }
+    }
+
+    /// Session being received, once the sender said hello
+    pub fn session_id(&self) -> Option<&SessionId> {
+        self.manifest.as_ref().map(|manifest| &manifest.session_id)
+    }
+
+    /// Manifest of the persisted segments
+    pub fn manifest(&self) -> Option<&ChunkManifest> {
+        self.manifest.as_ref()
+    }
+
+    /// Whether every segment up to `End` has been persisted and every chunk
+    /// asked for again has been repaired
+    pub fn is_finished(&self) -> bool {
+        self.segment_count.is_some()
+            && self.segment_count == Some(self.next_sequence())
+            && self.requested.is_empty()
+    }
+
+    /// Chunks asked for again and not repaired yet
+    pub fn outstanding_repairs(&self) -> Vec<ChunkId> {
+        self.requested.keys().cloned().collect()
+    }
+
+    /// Ask the sender for persisted chunks again, e.g. after the server
+    /// found them corrupt
+    pub fn request_resend(
+        &mut self,
+        chunk_ids: &[ChunkId],
+        reason: ResendReason,
+    ) -> Result<Vec<Vec<u8>>, String> {
+        let manifest = self
+            .manifest
+            .as_ref()
+            .ok_or_else(|| "Stream frame before hello".to_string())?;
+        for chunk_id in chunk_ids {
+            if !manifest.chunks.iter().any(|c| &c.chunk_id == chunk_id) {
+                return Err(format!(
+                    "Chunk {} is not persisted for session {}",
+                    chunk_id, manifest.session_id
+                ));
+            }
+        }
+        for chunk_id in chunk_ids {
+            self.requested.insert(chunk_id.clone(), reason);
+        }
+        resend_frames(chunk_ids.iter().map(|chunk_id| (chunk_id, &reason)))
+    }
+
+    /// Check every persisted chunk against the manifest and ask for the
+    /// missing or corrupt ones again
+    pub async fn audit<S: ChunkStore>(&mut self, store: &S) -> Result<Vec<Vec<u8>>, String> {
+        let manifest = self
+            .manifest
+            .as_ref()
+            .ok_or_else(|| "Stream frame before hello".to_string())?;
+        let mut found = Vec::new();
+        for metadata in &manifest.chunks {
+            let id = &metadata.chunk_id;
+            match store.get_file(&id.session, &chunk_file_name(id)).await? {
+                None => found.push((id.clone(), ResendReason::Missing)),
+                Some(data) if !is_intact(metadata, &data) => {
+                    found.push((id.clone(), ResendReason::HashMismatch))
+                }
+                Some(_) => {}
+            }
+        }
+        if !found.is_empty() {
+            log_event!(
+                LogLevel::Warn,
+                "Stored chunks failed audit",
+                session = manifest.session_id,
+                chunks = found.len(),
+            );
+        }
+        self.requested.extend(found.iter().cloned());
+        resend_frames(found.iter().map(|(chunk_id, reason)| (chunk_id, reason)))
+    }
+
+    /// Handle a frame from the sender
+    pub async fn receive<S: ChunkStore>(
+        &mut self,
+        store: &S,
+        message: &[u8],
+    ) -> Result<Vec<Vec<u8>>, String> {
+        match StreamFrame::decode(message)? {
+            StreamFrame::Hello { session_id } => {
+                if let Some(current) = self.session_id() {
+                    if current != &session_id {
+                        return Err(format!(
+                            "Connection is streaming session {}, got hello for {}",
+                            current, session_id
+                        ));
+                    }
+                }
+                let manifest = store
+                    .get_manifest(&session_id)
+                    .await?
+                    .unwrap_or_else(|| ChunkManifest::new(session_id.clone()));
+                log_event!(
+                    LogLevel::Info,
+                    "Stream connected",
+                    session = session_id,
+                    next_sequence = manifest.chunk_count(),
+                );
+                self.manifest = Some(manifest);
+                self.buffered.clear();
+                let mut frames = self.acknowledge()?;
+                frames.extend(resend_frames(self.requested.iter())?);
+                Ok(frames)
+            }
+            StreamFrame::Init { data } => {
+                let session = self.require_session()?.clone();
+                // Segments already stored must stay playable behind the new one
+                if self.next_sequence() > 0 {
+                    if let Some(existing) = store.get_file(&session, INIT_SEGMENT_FILE).await? {
+                        if existing != data {
+                            compare_init_segments(&existing, &data)?.ensure()?;
+                        }
+                    }
+                }
+                store.put_init_segment(&session, &data).await?;
+                Ok(Vec::new())
+            }
+            StreamFrame::Segment {
+                sequence,
+                metadata,
+                data,
+            } => {
+                self.require_session()?;
+                let next = self.next_sequence();
+                if sequence > next && !self.buffered.contains_key(&sequence) {
+                    if self.buffered.len() >= self.max_buffered {
+                        return Err(format!(
+                            "Too many out-of-order segments waiting for segment {}",
+                            next
+                        ));
+                    }
+                    self.buffered.insert(sequence, (metadata, data));
+                } else if sequence == next {
+                    self.buffered.insert(sequence, (metadata, data));
+                    // Still unacknowledged, so the sender has it in memory
+                    if let Some(corrupt) = self.persist_ready(store).await? {
+                        let mut frames = self.acknowledge()?;
+                        frames.push(
+                            StreamFrame::Resend {
+                                reason: ResendReason::HashMismatch,
+                                chunk_ids: vec![corrupt],
+                            }
+                            .encode()?,
+                        );
+                        return Ok(frames);
+                    }
+                }
+                self.acknowledge()
+            }
+            StreamFrame::Repair { chunk_id, data } => {
+                let session = self.require_session()?.clone();
+                if !self.requested.contains_key(&chunk_id) {
+                    log_event!(
+                        LogLevel::Debug,
+                        "Ignoring repair of a chunk not asked for",
+                        chunk = chunk_id,
+                    );
+                    return Ok(Vec::new());
+                }
+                let manifest = self.manifest.as_ref().expect("session checked above");
+                let metadata = manifest
+                    .chunks
+                    .iter()
+                    .find(|c| c.chunk_id == chunk_id)
+                    .ok_or_else(|| {
+                        format!(
+                            "Chunk {} is not persisted for session {}",
+                            chunk_id, session
+                        )
+                    })?;
+                if !is_intact(metadata, &data) {
+                    return Ok(vec![StreamFrame::Resend {
+                        reason: ResendReason::HashMismatch,
+                        chunk_ids: vec![chunk_id],
+                    }
+                    .encode()?]);
+                }
+                store.put_chunk(&chunk_id, &data).await?;
+                self.requested.remove(&chunk_id);
+                log_event!(LogLevel::Info, "Chunk repaired", chunk = chunk_id);
+                self.acknowledge()
+            }
+            StreamFrame::End { segment_count } => {
+                self.require_session()?;
+                self.segment_count = Some(segment_count);
+                self.acknowledge()
+            }
+            other => Err(format!("Unexpected frame from sender: {:?}", other)),
+        }
+    }
+
+    /// Write buffered segments that continue the persisted sequence
+    ///
+    /// Stops at a segment whose data does not match its metadata, dropping
+    /// it and returning its chunk ID.
+    async fn persist_ready<S: ChunkStore>(&mut self, store: &S) -> Result<Option<ChunkId>, String> {
+        let mut manifest = self.manifest.take().expect("session checked by caller");
+        let result = async {
+            let mut corrupt = None;
+            while let Some((metadata, data)) =
+                self.buffered.remove(&(manifest.chunk_count() as u64))
+            {
+                verify(&manifest, &metadata)?;
+                if !is_intact(&metadata, &data) {
+                    log_event!(
+                        LogLevel::Warn,
+                        "Segment failed verification",
+                        chunk = metadata.chunk_id,
+                        size = data.len(),
+                    );
+                    corrupt = Some(metadata.chunk_id);
+                    break;
+                }
+                store.put_chunk(&metadata.chunk_id, &data).await?;
+                manifest.add_chunk(metadata)?;
+            }
+            store.put_manifest(&manifest).await?;
+            Ok(corrupt)
+        }
+        .await;
+        self.manifest = Some(manifest);
+        result
+    }
+
+    fn acknowledge(&self) -> Result<Vec<Vec<u8>>, String> {
+        let mut frames = vec![StreamFrame::Ack {
+            next_sequence: self.next_sequence(),
+        }
+        .encode()?];
+        if self.is_finished() {
+            frames.push(StreamFrame::Finished.encode()?);
+        }
+        Ok(frames)
+    }
+
+    fn next_sequence(&self) -> u64 {
+        self.manifest
+            .as_ref()
+            .map_or(0, |manifest| manifest.chunk_count() as u64)
+    }
+
+    fn require_session(&self) -> Result<&SessionId, String> {
+        self.session_id()
+            .ok_or_else(|| "Stream frame before hello".to_string())
+    }
+}
+
+/// Check that a received segment belongs to the session being received
+fn verify(manifest: &ChunkManifest, metadata: &ChunkMetadata) -> Result<(), String> {
+    let id = &metadata.chunk_id;
+    if id.session != manifest.session_id {
+        return Err(format!(
+            "Segment {} does not belong to session {}",
+            id, manifest.session_id
+        ));
+    }
+    Ok(())
+}
+
+/// Whether chunk data matches the size and hash in its metadata
+fn is_intact(metadata: &ChunkMetadata, data: &[u8]) -> bool {
+    metadata.size == data.len() as u64
+        && metadata
+            .hash
+            .as_ref()
+            .is_none_or(|expected| blake3::hash(data).to_hex().as_str() == expected)
+}
+
+/// Encode `Resend` frames for chunk IDs, one frame per reason
+fn resend_frames<'a>(
+    chunks: impl Iterator<Item = (&'a ChunkId, &'a ResendReason)>,
+) -> Result<Vec<Vec<u8>>, String> {
+    let mut by_reason: BTreeMap<ResendReason, Vec<ChunkId>> = BTreeMap::new();
+    for (chunk_id, reason) in chunks {
+        by_reason.entry(*reason).or_default().push(chunk_id.clone());
+    }
+    by_reason
+        .into_iter()
+        .map(|(reason, chunk_ids)| StreamFrame::Resend { reason, chunk_ids }.encode())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{ChunkId, TrackKind};
+    use crate::storage::memory::MemoryStore;
+
+    fn segment(sequence: u64) -> (ChunkMetadata, Vec<u8>) {
+        let data = vec![sequence as u8; 16];
+        let metadata = ChunkMetadata {
+            chunk_id: ChunkId::new(SessionId::from("s1"), TrackKind::Muxed, sequence),
+            timestamp_us: sequence * 2_000_000,
+            size: data.len() as u64,
+            hash: Some(blake3::hash(&data).to_hex().to_string()),
+            has_keyframe: Some(true),
+            created_at: 0,
+        };
+        (metadata, data)
+    }
+
+    async fn deliver(
+        receiver: &mut SegmentReceiver,
+        store: &MemoryStore,
+        frames: Vec<Vec<u8>>,
+    ) -> Vec<Vec<u8>> {
+        let mut replies = Vec::new();
+        for frame in frames {
+            replies.extend(receiver.receive(store, &frame).await.unwrap());
+        }
+        replies
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let (metadata, data) = segment(3);
+        let frames = [
+            StreamFrame::Hello {
+                session_id: SessionId::from("s1"),
+            },
+            StreamFrame::Init {
+                data: vec![1, 2, 3],
+            },
+            StreamFrame::Segment {
+                sequence: 7,
+                metadata,
+                data,
+            },
+            StreamFrame::End { segment_count: 8 },
+            StreamFrame::Repair {
+                chunk_id: segment(1).0.chunk_id,
+                data: vec![4, 5],
+            },
+            StreamFrame::Ack { next_sequence: 8 },
+            StreamFrame::Finished,
+            StreamFrame::Resend {
+                reason: ResendReason::Missing,
+                chunk_ids: vec![segment(1).0.chunk_id, segment(2).0.chunk_id],
+            },
+        ];
+        for frame in frames {
+            assert_eq!(
+                StreamFrame::decode(&frame.encode().unwrap()).unwrap(),
+                frame
+            );
+        }
+        assert!(StreamFrame::decode(&[]).is_err());
+        assert!(StreamFrame::decode(&[SEGMENT, 0, 0]).is_err());
+        assert!(StreamFrame::decode(&[HELLO, 2]).is_err());
+        assert!(StreamFrame::decode(&[RESEND, 9]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream_reorders_and_resumes() {
+        let store = MemoryStore::default();
+        let mut sender = SegmentSender::new(SessionId::from("s1"), 2);
+        let mut receiver = SegmentReceiver::new(4);
+
+        assert!(sender.push_init_segment(vec![0xAA]).unwrap().is_empty());
+        let mut frames = sender.connect().unwrap();
+        for sequence in 0..3 {
+            let (metadata, data) = segment(sequence);
+            frames.extend(sender.push_segment(metadata, data).unwrap());
+        }
+        // Hello, init and a window of two segments; deliver them reversed
+        assert_eq!(frames.len(), 4);
+        let segments = frames.split_off(2);
+        let mut replies = deliver(&mut receiver, &store, frames).await;
+        replies.extend(deliver(&mut receiver, &store, segments.into_iter().rev().collect()).await);
+        assert_eq!(receiver.manifest().unwrap().chunk_count(), 2);
+
+        // Acks release the third segment; the connection drops before its ack
+        let mut third = Vec::new();
+        for reply in replies {
+            third.extend(sender.receive(&reply).unwrap());
+        }
+        assert_eq!(third.len(), 1);
+        assert_eq!(sender.pending(), 1);
+        sender.disconnect();
+        let (metadata, data) = segment(3);
+        assert!(sender.push_segment(metadata, data).unwrap().is_empty());
+        assert!(sender.end().unwrap().is_empty());
+
+        // A fresh receiver resumes from the persisted manifest
+        let mut receiver = SegmentReceiver::new(4);
+        let mut frames = sender.connect().unwrap();
+        frames.insert(2, third.remove(0));
+        let replies = deliver(&mut receiver, &store, frames).await;
+        for reply in replies {
+            assert!(sender.receive(&reply).unwrap().is_empty());
+        }
+
+        assert!(receiver.is_finished());
+        assert!(sender.is_finished());
+        assert_eq!(sender.pending(), 0);
+        let session = SessionId::from("s1");
+        let manifest = store.get_manifest(&session).await.unwrap().unwrap();
+        assert_eq!(manifest.chunk_count(), 4);
+        assert_eq!(store.get_init_segment(&session).await.unwrap(), vec![0xAA]);
+        let (metadata, data) = segment(3);
+        assert_eq!(store.get_chunk(&metadata.chunk_id).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn test_resend_corrupt_and_missing_chunks() {
+        let store = MemoryStore::default();
+        let session = SessionId::from("s1");
+        let mut sender = SegmentSender::new(session.clone(), 4);
+        let mut receiver = SegmentReceiver::new(4);
+
+        // A segment corrupted on the wire is resent from memory
+        let mut frames = sender.connect().unwrap();
+        for sequence in 0..3 {
+            let (metadata, data) = segment(sequence);
+            frames.extend(sender.push_segment(metadata, data).unwrap());
+        }
+        *frames.last_mut().unwrap().last_mut().unwrap() ^= 0xFF;
+        let replies = deliver(&mut receiver, &store, frames).await;
+        assert_eq!(
+            StreamFrame::decode(replies.last().unwrap()).unwrap(),
+            StreamFrame::Resend {
+                reason: ResendReason::HashMismatch,
+                chunk_ids: vec![segment(2).0.chunk_id],
+            }
+        );
+        let mut frames = Vec::new();
+        for reply in replies {
+            frames.extend(sender.receive(&reply).unwrap());
+        }
+        frames.extend(sender.end().unwrap());
+        let replies = deliver(&mut receiver, &store, frames).await;
+        for reply in replies {
+            assert!(sender.receive(&reply).unwrap().is_empty());
+        }
+        assert!(sender.is_finished());
+        assert_eq!(sender.pending(), 0);
+
+        // Chunks damaged after being acknowledged are found by an audit
+        let (first, _) = segment(0);
+        let (second, _) = segment(1);
+        store.put_chunk(&first.chunk_id, &[9; 16]).await.unwrap();
+        store
+            .delete_file(&session, &chunk_file_name(&second.chunk_id))
+            .await
+            .unwrap();
+        let requests = receiver.audit(&store).await.unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(!receiver.is_finished());
+        for request in requests {
+            assert!(sender.receive(&request).unwrap().is_empty());
+        }
+        assert!(!sender.is_finished());
+        assert_eq!(
+            sender.take_repair_requests(),
+            vec![first.chunk_id.clone(), second.chunk_id.clone()]
+        );
+
+        // Repairs sent while disconnected are lost; the receiver asks again
+        sender.disconnect();
+        assert!(sender
+            .push_repair(first.chunk_id.clone(), segment(0).1)
+            .unwrap()
+            .is_empty());
+        let replies = deliver(&mut receiver, &store, sender.connect().unwrap()).await;
+        for reply in replies {
+            assert!(sender.receive(&reply).unwrap().is_empty());
+        }
+        let requested = sender.take_repair_requests();
+        assert_eq!(requested.len(), 2);
+        assert_eq!(receiver.outstanding_repairs(), requested);
+
+        // Served from local storage
+        let mut frames = Vec::new();
+        for chunk_id in requested {
+            let data = segment(chunk_id.sequence).1;
+            frames.extend(sender.push_repair(chunk_id, data).unwrap());
+        }
+        let replies = deliver(&mut receiver, &store, frames).await;
+        for reply in replies {
+            assert!(sender.receive(&reply).unwrap().is_empty());
+        }
+        assert!(receiver.is_finished());
+        assert!(sender.is_finished());
+        assert_eq!(
+            store.get_chunk(&second.chunk_id).await.unwrap(),
+            segment(1).1
+        );
+
+        assert!(receiver
+            .request_resend(
+                &[ChunkId::new(session, TrackKind::Muxed, 9)],
+                ResendReason::Missing
+            )
+            .is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Sidecar subtitles from a session's markers.
+//!
+//! Players that cannot read timed metadata still load a `.vtt` or `.srt`
+//! file next to the video. Every labeled marker becomes a cue showing its
+//! label, from the marker's position until the next cue starts, at most
+//! `DEFAULT_CUE_DURATION_US` later and never past the end of the recording.
+//! Markers the recorder adds for itself (silence, audio config changes) and
+//! markers without a label are left out.
+//!
+//! Cue times are on the timeline of the assembled file, which starts with
+//! the first chunk: marker timestamps are shifted back by its timestamp.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::manifest::ChunkManifest;
+use crate::recorder::{AUDIO_CONFIG_LABEL, SLATE_END_LABEL, SLATE_START_LABEL};
+use crate::silence::{SILENCE_END_LABEL, SILENCE_START_LABEL};
+use crate::timebase::{Rounding, TimeBase};
+
+/// How long a cue stays up when no later cue replaces it
+pub const DEFAULT_CUE_DURATION_US: u64 = 3_000_000;
+
+/// Marker labels the recorder writes itself
+pub(crate) const INTERNAL_LABELS: [&str; 5] = [
+    SILENCE_START_LABEL,
+    SILENCE_END_LABEL,
+    AUDIO_CONFIG_LABEL,
+    SLATE_START_LABEL,
+    SLATE_END_LABEL,
+];
+
+/// Sidecar subtitle file format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleFormat {
+    /// WebVTT (`.vtt`), for `<track>` elements
+    #[default]
+    WebVtt,
+    /// SubRip (`.srt`)
+    Srt,
+}
+
+impl SubtitleFormat {
+    /// File extension, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            SubtitleFormat::WebVtt => "vtt",
+            SubtitleFormat::Srt => "srt",
+        }
+    }
+}
+
+/// One subtitle cue, in microseconds on the file timeline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct SubtitleCue {
+    #[serde(rename = "start")]
+    pub start_us: u64,
+    #[serde(rename = "end")]
+    pub end_us: u64,
+    pub text: String,
+}
+
+/// Cues for the labeled markers of a manifest, in order
+///
+/// `end_us` is the end of the recording in session time (like marker
+/// timestamps), if known; cues are cut there and markers at or after it are
+/// dropped.
+pub fn marker_cues(manifest: &ChunkManifest, end_us: Option<u64>) -> Vec<SubtitleCue> {
+    let origin = file_origin(manifest);
+    let labeled: Vec<(u64, &str)> = manifest
+        .markers
+        .iter()
+        .filter_map(|marker| {
+            let label = marker.label.as_deref()?.trim();
+            (!label.is_empty() && !INTERNAL_LABELS.contains(&label))
+                .then(|| (marker.timestamp_us.saturating_sub(origin), label))
+        })
+        .collect();
+    label_cues(&labeled, end_us.map(|end| end.saturating_sub(origin)))
+}
+
+/// Session time of the start of the assembled file: the first chunk's
+/// timestamp
+pub(crate) fn file_origin(manifest: &ChunkManifest) -> u64 {
+    (manifest.chunks.iter())
+        .map(|chunk| chunk.timestamp_us)
+        .min()
+        .unwrap_or(0)
+}
+
+/// Cues for labels at sorted file times, as in `marker_cues`; `end_us` is
+/// the end of the file, if known
+pub(crate) fn label_cues(labeled: &[(u64, &str)], end_us: Option<u64>) -> Vec<SubtitleCue> {
+    let mut cues = Vec::new();
+    for (i, &(start_us, label)) in labeled.iter().enumerate() {
+        let mut cue_end = start_us + DEFAULT_CUE_DURATION_US;
+        if let Some(&(next, _)) = labeled.get(i + 1) {
+            cue_end = cue_end.min(next);
+        }
+        if let Some(end_us) = end_us {
+            cue_end = cue_end.min(end_us);
+        }
+        if cue_end <= start_us {
+            continue;
+        }
+        cues.push(SubtitleCue {
+            start_us,
+            end_us: cue_end,
+            text: label.to_string(),
+        });
+    }
+    cues
+}
+
+/// Render cues as a WebVTT file
+pub fn write_webvtt(cues: &[SubtitleCue]) -> String {
+    let mut out = String::from("WEBVTT\n");
+    for cue in cues {
+        let text = cue_lines(&cue.text)
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+        out.push_str(&format!(
+            "\n{} --> {}\n{}\n",
+            timestamp(cue.start_us, '.'),
+            timestamp(cue.end_us, '.'),
+            text
+        ));
+    }
+    out
+}
+
+/// Render cues as a SubRip file
+pub fn write_srt(cues: &[SubtitleCue]) -> String {
+    let mut out = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n",
+            i + 1,
+            timestamp(cue.start_us, ','),
+            timestamp(cue.end_us, ','),
+            cue_lines(&cue.text)
+        ));
+    }
+    out
+}
+
+/// Render the marker subtitles of a manifest (see `marker_cues`)
+pub fn export_subtitles(
+    manifest: &ChunkManifest,
+    end_us: Option<u64>,
+    format: SubtitleFormat,
+) -> String {
+    let cues = marker_cues(manifest, end_us);
+    match format {
+        SubtitleFormat::WebVtt => write_webvtt(&cues),
+        SubtitleFormat::Srt => write_srt(&cues),
+    }
+}
+
+/// Cue text without blank lines, which would end the cue
+fn cue_lines(text: &str) -> String {
+    text.lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `HH:MM:SS.mmm`, with `separator` before the milliseconds
+fn timestamp(us: u64, separator: char) -> String {
+    let ms = TimeBase::MICROS.convert(us, TimeBase::MILLIS, Rounding::Nearest);
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{ChunkId, ChunkMetadata, TrackKind};
+    use crate::manifest::Marker;
+    use crate::session::SessionId;
+
+    fn marker(timestamp_us: u64, label: Option<&str>) -> Marker {
+        Marker {
+            timestamp_us,
+            label: label.map(str::to_string),
+            bookmark: None,
+        }
+    }
+
+    #[test]
+    fn test_marker_subtitles() {
+        let session = SessionId::from("5f0c7d7e-2a61-4b8e-9d55-0c8f1c1e2b3a");
+        let mut manifest = ChunkManifest::new(session.clone());
+        manifest
+            .add_chunk(ChunkMetadata {
+                chunk_id: ChunkId::new(session, TrackKind::Video, 0),
+                timestamp_us: 400_000,
+                size: 100,
+                hash: None,
+                has_keyframe: Some(true),
+                created_at: 0,
+            })
+            .unwrap();
+        manifest.markers = vec![
+            marker(1_400_000, Some("Intro")),
+            marker(2_000_000, Some(SILENCE_START_LABEL)),
+            marker(2_400_000, None),
+            marker(3_400_000, Some("Q & A <live>\n\nSecond line")),
+            marker(3_661_400_123, Some("Outro")),
+            marker(3_700_000_000, Some("Past the end")),
+        ];
+
+        let cues = marker_cues(&manifest, Some(3_661_900_000));
+        assert_eq!(cues.len(), 3);
+        // The unlabeled and silence markers do not cut the first cue short
+        assert_eq!((cues[0].start_us, cues[0].end_us), (1_000_000, 3_000_000));
+        assert_eq!((cues[1].start_us, cues[1].end_us), (3_000_000, 6_000_000));
+        assert_eq!(
+            (cues[2].start_us, cues[2].end_us),
+            (3_661_000_123, 3_661_500_000)
+        );
+
+        assert_eq!(
+            export_subtitles(&manifest, Some(3_661_900_000), SubtitleFormat::WebVtt),
+            "WEBVTT\n\
+             \n00:00:01.000 --> 00:00:03.000\nIntro\n\
+             \n00:00:03.000 --> 00:00:06.000\nQ &amp; A &lt;live&gt;\nSecond line\n\
+             \n01:01:01.000 --> 01:01:01.500\nOutro\n"
+        );
+        assert_eq!(
+            export_subtitles(&manifest, Some(3_661_900_000), SubtitleFormat::Srt),
+            "1\n00:00:01,000 --> 00:00:03,000\nIntro\n\
+             \n2\n00:00:03,000 --> 00:00:06,000\nQ & A <live>\nSecond line\n\
+             \n3\n01:01:01,000 --> 01:01:01,500\nOutro\n"
+        );
+        assert_eq!(SubtitleFormat::Srt.extension(), "srt");
+        assert_eq!(
+            export_subtitles(
+                &ChunkManifest::new("s".into()),
+                None,
+                SubtitleFormat::WebVtt
+            ),
+            "WEBVTT\n"
+        );
+    }
+}
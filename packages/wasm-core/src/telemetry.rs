@@ -0,0 +1,192 @@
+//! Internal telemetry counters.
+//!
+//! The muxer bumps a handful of counters as frames go in and segments come
+//! out; `snapshot()` reads them all at once so the host app can scrape them
+//! on its own schedule. Counters are plain `Cell`s in a thread-local, so the
+//! hot path pays an addition and no locking or allocation. Only the error
+//! counts, touched on failure paths, live in a map.
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::clock::ClockHandle;
+
+/// Counter values at one point in time
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct TelemetrySnapshot {
+    /// Frames accepted by muxers
+    pub frames_in: u64,
+    /// Frames written into media segments
+    pub frames_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Media segments produced
+    pub flushes: u64,
+    /// Errors by code, e.g. `not_initialized`
+    pub errors: BTreeMap<String, u64>,
+    /// Most bytes held by a muxer at once (buffered samples + pending segments)
+    pub buffered_bytes_high_water: u64,
+    /// Unix timestamp in milliseconds
+    pub timestamp: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    frames_in: Cell<u64>,
+    frames_out: Cell<u64>,
+    bytes_in: Cell<u64>,
+    bytes_out: Cell<u64>,
+    flushes: Cell<u64>,
+    buffered_bytes_high_water: Cell<u64>,
+    errors: RefCell<BTreeMap<&'static str, u64>>,
+    clock: RefCell<ClockHandle>,
+}
+
+thread_local! {
+    static COUNTERS: Counters = Counters::default();
+}
+
+fn add(counter: &Cell<u64>, value: u64) {
+    counter.set(counter.get() + value);
+}
+
+/// Count a frame of `bytes` accepted by a muxer
+pub(crate) fn frame_in(bytes: usize) {
+    COUNTERS.with(|c| {
+        add(&c.frames_in, 1);
+        add(&c.bytes_in, bytes as u64);
+    });
+}
+
+/// Count a media segment of `frames` frames and `bytes` bytes
+pub(crate) fn segment_out(frames: usize, bytes: usize) {
+    COUNTERS.with(|c| {
+        add(&c.flushes, 1);
+        add(&c.frames_out, frames as u64);
+        add(&c.bytes_out, bytes as u64);
+    });
+}
+
+/// Report the bytes a muxer currently holds
+pub(crate) fn buffered_bytes(bytes: u64) {
+    COUNTERS.with(|c| {
+        if bytes > c.buffered_bytes_high_water.get() {
+            c.buffered_bytes_high_water.set(bytes);
+        }
+    });
+}
+
+/// Count an error under `code` and pass its message through
+pub(crate) fn error(code: &'static str, message: impl Into<String>) -> String {
+    COUNTERS.with(|c| *c.errors.borrow_mut().entry(code).or_default() += 1);
+    message.into()
+}
+
+/// Stamp snapshots with the time of `clock` instead of the system clock
+pub fn set_clock(clock: ClockHandle) {
+    COUNTERS.with(|c| *c.clock.borrow_mut() = clock);
+}
+
+/// Read every counter
+pub fn snapshot() -> TelemetrySnapshot {
+    COUNTERS.with(|c| TelemetrySnapshot {
+        frames_in: c.frames_in.get(),
+        frames_out: c.frames_out.get(),
+        bytes_in: c.bytes_in.get(),
+        bytes_out: c.bytes_out.get(),
+        flushes: c.flushes.get(),
+        errors: c
+            .errors
+            .borrow()
+            .iter()
+            .map(|(code, count)| (code.to_string(), *count))
+            .collect(),
+        buffered_bytes_high_water: c.buffered_bytes_high_water.get(),
+        timestamp: c.clock.borrow().now_ms(),
+    })
+}
+
+/// Read every counter as a JSON object
+pub fn snapshot_json() -> String {
+    serde_json::to_string(&snapshot()).unwrap_or_default()
+}
+
+/// Set every counter back to zero
+pub fn reset() {
+    COUNTERS.with(|c| {
+        for counter in [
+            &c.frames_in,
+            &c.frames_out,
+            &c.bytes_in,
+            &c.bytes_out,
+            &c.flushes,
+            &c.buffered_bytes_high_water,
+        ] {
+            counter.set(0);
+        }
+        c.errors.borrow_mut().clear();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::muxide_muxer::{MuxideConfig, MuxideMuxerState};
+
+    #[test]
+    fn test_muxer_feeds_counters() {
+        reset();
+        let mut muxer = MuxideMuxerState::new(MuxideConfig {
+            video_width: None,
+            video_height: None,
+            sps: None,
+            pps: None,
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(1),
+            ..Default::default()
+        });
+        assert!(muxer.push_audio_chunk(&[1, 2, 3, 4], 0, 21_333).is_err());
+        muxer.init().unwrap();
+        for i in 0..10u64 {
+            muxer
+                .push_audio_chunk(&[1, 2, 3, 4], i * 21_333, 21_333)
+                .unwrap();
+        }
+        muxer.force_flush().unwrap();
+        let segment_bytes = muxer.stats().segment_bytes;
+        muxer.get_pending_segments();
+
+        let counters = snapshot();
+        assert_eq!(counters.frames_in, 10);
+        assert_eq!(counters.bytes_in, 40);
+        assert_eq!(counters.frames_out, 10);
+        assert_eq!(counters.flushes, 1);
+        assert_eq!(counters.bytes_out, segment_bytes);
+        assert_eq!(counters.buffered_bytes_high_water, segment_bytes);
+        assert_eq!(counters.errors.get("not_initialized"), Some(&1));
+
+        let json: serde_json::Value = serde_json::from_str(&snapshot_json()).unwrap();
+        assert_eq!(json["framesIn"], 10);
+        assert_eq!(json["errors"]["not_initialized"], 1);
+
+        reset();
+        assert_eq!(snapshot().frames_in, 0);
+        assert!(snapshot().errors.is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_uses_injected_clock() {
+        let clock = ManualClock::new(5_000);
+        set_clock(ClockHandle::new(clock.clone()));
+        assert_eq!(snapshot().timestamp, 5_000);
+        clock.advance(40);
+        assert_eq!(snapshot().timestamp, 5_040);
+        set_clock(ClockHandle::default());
+    }
+}
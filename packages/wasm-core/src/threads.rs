@@ -0,0 +1,174 @@
+//! Finishing segments on a thread pool (`wasm-threads` feature).
+//!
+//! When the page is cross-origin isolated, `SharedArrayBuffer` lets a build
+//! with atomics share its memory with Web Workers, each running one thread
+//! of a rayon pool. Under `HashStrategy::Background` the recorder hands
+//! every finished segment to the pool as a `SegmentJob`, which encrypts and
+//! hashes it while pushes go on, so a push only muxes and maps its frame.
+//! Chunks are still emitted in order, each once its job is done.
+//!
+//! The pool is started once per module instance with `start_pool`; the host
+//! starts a worker per thread, instantiating the module on the shared memory
+//! and running the thread it is handed (`run_pool_thread` in the bindings).
+//! Waiting for a job blocks, which the browser only allows off the main
+//! thread: the recorder must then run in a worker itself. Without the
+//! feature, or before the pool is started, no job is ever spawned and
+//! segments are finished inline.
+
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use crate::encryption::SegmentEncryptorState;
+
+#[cfg(feature = "wasm-threads")]
+static POOL: std::sync::OnceLock<rayon_core::ThreadPool> = std::sync::OnceLock::new();
+
+/// Start the thread pool with `num_threads` threads, `spawn` starting each
+/// one (`ThreadBuilder::run` on a new thread)
+///
+/// Does nothing if the pool is already running.
+#[cfg(feature = "wasm-threads")]
+pub fn start_pool<F>(num_threads: usize, spawn: F) -> Result<(), String>
+where
+    F: FnMut(rayon_core::ThreadBuilder) -> std::io::Result<()>,
+{
+    if num_threads == 0 {
+        return Err("Thread pool needs at least one thread".to_string());
+    }
+    if POOL.get().is_some() {
+        return Ok(());
+    }
+    let pool = rayon_core::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .thread_name(|i| format!("maycast-pool-{i}"))
+        .spawn_handler(spawn)
+        .build()
+        .map_err(|e| format!("Cannot start thread pool: {e}"))?;
+    let _ = POOL.set(pool);
+    Ok(())
+}
+
+/// Whether segments can be finished on the thread pool
+pub fn pool_ready() -> bool {
+    #[cfg(feature = "wasm-threads")]
+    return POOL.get().is_some();
+    #[cfg(not(feature = "wasm-threads"))]
+    false
+}
+
+/// Data and hash of a segment finished on the pool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinishedSegment {
+    /// Segment as emitted (encrypted when encryption is on)
+    pub data: Vec<u8>,
+    /// BLAKE3 hash (hex) of `data`
+    pub hash: String,
+}
+
+/// A segment being encrypted and hashed on the thread pool
+#[derive(Debug)]
+pub struct SegmentJob {
+    receiver: Receiver<FinishedSegment>,
+}
+
+impl SegmentJob {
+    /// Encrypt (with `encryptor`, as chunk `sequence`) and hash `data` on
+    /// the pool; `data` is handed back when the pool is not running
+    pub fn spawn(
+        data: Vec<u8>,
+        encryptor: Option<SegmentEncryptorState>,
+        sequence: u64,
+    ) -> Result<Self, Vec<u8>> {
+        if !pool_ready() {
+            return Err(data);
+        }
+        let (sender, receiver) = mpsc::channel();
+        let job = move || {
+            let data = match &encryptor {
+                Some(encryptor) => encryptor.encrypt(sequence, &data),
+                None => data,
+            };
+            let hash = hash_on_pool(&data);
+            // The recorder may be gone
+            let _ = sender.send(FinishedSegment { data, hash });
+        };
+        #[cfg(feature = "wasm-threads")]
+        if let Some(pool) = POOL.get() {
+            pool.spawn(job);
+        }
+        #[cfg(not(feature = "wasm-threads"))]
+        let _ = job;
+        Ok(Self { receiver })
+    }
+
+    /// The finished segment, if the job is done
+    pub fn try_finish(&self) -> Result<Option<FinishedSegment>, String> {
+        match self.receiver.try_recv() {
+            Ok(segment) => Ok(Some(segment)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err("Segment job failed".to_string()),
+        }
+    }
+
+    /// Wait for the job to finish (not on the browser's main thread)
+    pub fn finish(self) -> Result<FinishedSegment, String> {
+        self.receiver
+            .recv()
+            .map_err(|_| "Segment job failed".to_string())
+    }
+}
+
+/// Hash (hex) of `data` from a pool thread, large inputs spread across the
+/// pool
+fn hash_on_pool(data: &[u8]) -> String {
+    #[cfg(feature = "wasm-threads")]
+    if data.len() >= crate::hashing::PARALLEL_HASH_MIN_BYTES {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_rayon(data);
+        return hasher.finalize().to_hex().to_string();
+    }
+    blake3::hash(data).to_hex().to_string()
+}
+
+#[cfg(all(test, feature = "wasm-threads"))]
+mod tests {
+    use super::*;
+    use crate::encryption::{decrypt_segment, SegmentKey};
+
+    #[test]
+    fn test_segment_jobs() {
+        assert!(start_pool(0, |_| Ok(())).is_err());
+        start_pool(2, |thread| {
+            std::thread::Builder::new()
+                .name(thread.name().unwrap_or_default().to_string())
+                .spawn(|| thread.run())
+                .map(drop)
+        })
+        .unwrap();
+        assert!(pool_ready());
+
+        let data: Vec<u8> = (0..400_000u32).map(|i| (i * 7) as u8).collect();
+        let job = SegmentJob::spawn(data.clone(), None, 0).unwrap();
+        let finished = job.finish().unwrap();
+        assert_eq!(finished.data, data);
+        assert_eq!(finished.hash, blake3::hash(&data).to_hex().to_string());
+
+        let key = [7u8; 16];
+        let encryptor = SegmentEncryptorState::new(SegmentKey {
+            key: key.to_vec(),
+            uri: "key-1".into(),
+        })
+        .unwrap();
+        let job = SegmentJob::spawn(data.clone(), Some(encryptor), 3).unwrap();
+        let finished = loop {
+            if let Some(finished) = job.try_finish().unwrap() {
+                break finished;
+            }
+            std::thread::yield_now();
+        };
+        assert_eq!(decrypt_segment(&key, 3, &finished.data).unwrap(), data);
+        assert_eq!(
+            finished.hash,
+            blake3::hash(&finished.data).to_hex().to_string()
+        );
+    }
+}
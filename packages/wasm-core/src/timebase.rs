@@ -0,0 +1,154 @@
+//! Exact time conversions between clocks.
+//!
+//! Every timestamp or duration that crosses from one clock to another
+//! (microseconds from WebCodecs, track timescales, sample rates) goes through
+//! `TimeBase::convert`, which multiplies before dividing in 128-bit integers
+//! and rounds the way the caller asks. A series of durations is converted
+//! with a `TickCarry`, which carries each rounding error into the next value
+//! so the sum never drifts from the exact total by more than half a tick.
+
+/// How a conversion rounds results that fall between two ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Toward zero
+    Floor,
+    /// To the nearest tick, halves up
+    Nearest,
+    /// Away from zero
+    Ceil,
+}
+
+/// A clock counting `ticks_per_second` ticks per second
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeBase {
+    ticks_per_second: u64,
+}
+
+impl TimeBase {
+    /// Microseconds, the unit of WebCodecs timestamps
+    pub const MICROS: TimeBase = TimeBase::new(1_000_000);
+    /// Milliseconds
+    pub const MILLIS: TimeBase = TimeBase::new(1000);
+
+    /// Create a time base; zero is treated as one tick per second
+    pub const fn new(ticks_per_second: u64) -> Self {
+        Self {
+            ticks_per_second: if ticks_per_second == 0 {
+                1
+            } else {
+                ticks_per_second
+            },
+        }
+    }
+
+    /// Ticks per second
+    pub fn rate(self) -> u64 {
+        self.ticks_per_second
+    }
+
+    /// Convert `value` ticks of this clock to ticks of `to`
+    pub fn convert(self, value: u64, to: TimeBase, rounding: Rounding) -> u64 {
+        if self == to {
+            return value;
+        }
+        divide(
+            value as u128 * to.ticks_per_second as u128,
+            self.ticks_per_second as u128,
+            rounding,
+        ) as u64
+    }
+
+    /// Convert microseconds to ticks of this clock
+    pub fn from_us(self, us: u64, rounding: Rounding) -> u64 {
+        TimeBase::MICROS.convert(us, self, rounding)
+    }
+
+    /// Convert ticks of this clock to microseconds
+    pub fn to_us(self, ticks: u64, rounding: Rounding) -> u64 {
+        self.convert(ticks, TimeBase::MICROS, rounding)
+    }
+
+    /// Convert ticks of this clock to milliseconds
+    pub fn to_ms(self, ticks: u64, rounding: Rounding) -> u64 {
+        self.convert(ticks, TimeBase::MILLIS, rounding)
+    }
+}
+
+/// Converts a series of durations, carrying each rounding error forward
+///
+/// Every result is rounded to the nearest tick including the error left by
+/// the previous ones, so after any number of values the total is the exact
+/// total rounded to the nearest tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickCarry {
+    from: TimeBase,
+    to: TimeBase,
+    /// Error carried so far, in 1 / `from` ticks of `to`
+    remainder: i64,
+}
+
+impl TickCarry {
+    /// Convert durations from `from` ticks to `to` ticks
+    pub fn new(from: TimeBase, to: TimeBase) -> Self {
+        Self {
+            from,
+            to,
+            remainder: 0,
+        }
+    }
+
+    /// Convert the next duration
+    pub fn push(&mut self, value: u64) -> u64 {
+        let unit = self.from.ticks_per_second as i128;
+        let total = self.remainder as i128 + value as i128 * self.to.ticks_per_second as i128;
+        let ticks = (total + unit / 2).div_euclid(unit).max(0);
+        self.remainder = (total - ticks * unit) as i64;
+        ticks as u64
+    }
+
+    /// Rounding error carried so far, for checkpointing
+    pub fn remainder(&self) -> i64 {
+        self.remainder
+    }
+
+    /// Resume from a checkpointed `remainder`
+    pub fn set_remainder(&mut self, remainder: i64) {
+        self.remainder = remainder;
+    }
+}
+
+fn divide(num: u128, den: u128, rounding: Rounding) -> u128 {
+    match rounding {
+        Rounding::Floor => num / den,
+        Rounding::Nearest => (num + den / 2) / den,
+        Rounding::Ceil => num.div_ceil(den),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_and_carry() {
+        let audio = TimeBase::new(44100);
+        let video = TimeBase::new(90000);
+        assert_eq!(video.from_us(33_333, Rounding::Floor), 2999);
+        assert_eq!(video.from_us(33_333, Rounding::Nearest), 3000);
+        assert_eq!(video.from_us(33_334, Rounding::Ceil), 3001);
+        assert_eq!(video.to_ms(135_000, Rounding::Floor), 1500);
+        // Ten hours of 90 kHz ticks to microseconds does not overflow
+        assert_eq!(video.to_us(3_240_000_000, Rounding::Floor), 36_000_000_000);
+
+        // 1024-sample frames: 2089.8 ticks each
+        let mut carry = TickCarry::new(audio, video);
+        let ticks: Vec<u64> = (0..5).map(|_| carry.push(1024)).collect();
+        assert_eq!(ticks, vec![2090, 2090, 2089, 2090, 2090]);
+        let total: u64 = (0..995).map(|_| carry.push(1024)).sum::<u64>() + 10_449;
+        assert_eq!(total, audio.convert(1_024_000, video, Rounding::Nearest));
+
+        let mut resumed = TickCarry::new(audio, video);
+        resumed.set_remainder(carry.remainder());
+        assert_eq!(resumed.push(1024), carry.push(1024));
+    }
+}
@@ -0,0 +1,706 @@
+//! Replayable traces of muxer input.
+//!
+//! With tracing enabled, the muxer logs every push (timestamp, size, flags),
+//! flush, fragment duration change, audio config change and attached
+//! auxiliary information (size only) to a compact binary trace, without the media bytes. `replay_trace` drives a dry-run muxer with the same calls, so
+//! a user-reported sync bug can be reproduced from the trace alone.
+//!
+//! Layout (little endian): `MXTR` magic, version byte, length-prefixed JSON
+//! config, then one record per call: a tag byte and fixed-width fields. Video
+//! frames also record how many bytes form complete NAL units, so input
+//! validation sees the same anomalies on replay.
+//!
+//! A `SessionFingerprint` condenses a session's output to its config, a hash
+//! of the init segment, and the structure and moof hash of every fragment.
+//! `check_trace` replays a trace against a stored fingerprint, so a library
+//! of captured sessions can check that a muxer change still produces the
+//! same output.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::muxide_muxer::{
+    trace_segment, FragmentTrace, MuxerStats, MuxideConfig, MuxideMuxerState, SampleFlags,
+};
+
+const TRACE_MAGIC: &[u8] = b"MXTR";
+const TRACE_VERSION: u8 = 1;
+
+const TAG_VIDEO: u8 = 1;
+const TAG_AUDIO: u8 = 2;
+const TAG_FLUSH: u8 = 3;
+const TAG_FRAGMENT_DURATION: u8 = 4;
+const TAG_VIDEO_FULL: u8 = 5;
+const TAG_VIDEO_WITH_FLAGS: u8 = 6;
+const TAG_AUDIO_CONFIG: u8 = 7;
+const TAG_VIDEO_AUX: u8 = 8;
+
+/// One traced muxer call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceRecord {
+    Video {
+        timestamp_us: u64,
+        size: u32,
+        /// Bytes of the frame that form complete AVCC NAL units
+        valid_len: u32,
+        is_keyframe: bool,
+    },
+    /// A `push_video_chunk_full` call; a zero duration means none was given
+    VideoFull {
+        pts_us: u64,
+        dts_us: u64,
+        duration_us: u32,
+        size: u32,
+        valid_len: u32,
+        flags: u32,
+    },
+    /// A `push_video_chunk_with_flags` call, with the trun sample flag bits
+    VideoWithFlags {
+        pts_us: u64,
+        dts_us: u64,
+        duration_us: u32,
+        size: u32,
+        valid_len: u32,
+        sample_flags: u32,
+    },
+    Audio {
+        timestamp_us: u64,
+        duration_us: u32,
+        size: u32,
+    },
+    Flush,
+    FragmentDuration(u32),
+    /// A `change_audio_config` call; replayed with a generated
+    /// AudioSpecificConfig
+    AudioConfig {
+        sample_rate: u32,
+        channels: u16,
+    },
+    /// A `set_next_video_aux` call; replayed with zeros of the traced size
+    VideoAux {
+        size: u32,
+    },
+}
+
+/// Appends records to a trace
+#[derive(Debug, Clone)]
+pub struct TraceWriter {
+    bytes: Vec<u8>,
+}
+
+impl TraceWriter {
+    /// Start a trace of a muxer with `config`
+    pub fn new(config: &MuxideConfig) -> Result<Self, String> {
+        let config = serde_json::to_vec(config).map_err(|e| e.to_string())?;
+        let mut bytes = Vec::with_capacity(TRACE_MAGIC.len() + 5 + config.len());
+        bytes.extend_from_slice(TRACE_MAGIC);
+        bytes.push(TRACE_VERSION);
+        bytes.extend_from_slice(&(config.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&config);
+        Ok(Self { bytes })
+    }
+
+    /// Append a record
+    pub fn push(&mut self, record: TraceRecord) {
+        let out = &mut self.bytes;
+        match record {
+            TraceRecord::Video {
+                timestamp_us,
+                size,
+                valid_len,
+                is_keyframe,
+            } => {
+                out.push(TAG_VIDEO);
+                out.extend_from_slice(&timestamp_us.to_le_bytes());
+                out.extend_from_slice(&size.to_le_bytes());
+                out.extend_from_slice(&valid_len.to_le_bytes());
+                out.push(is_keyframe as u8);
+            }
+            TraceRecord::VideoFull {
+                pts_us,
+                dts_us,
+                duration_us,
+                size,
+                valid_len,
+                flags,
+            }
+            | TraceRecord::VideoWithFlags {
+                pts_us,
+                dts_us,
+                duration_us,
+                size,
+                valid_len,
+                sample_flags: flags,
+            } => {
+                out.push(match record {
+                    TraceRecord::VideoFull { .. } => TAG_VIDEO_FULL,
+                    _ => TAG_VIDEO_WITH_FLAGS,
+                });
+                out.extend_from_slice(&pts_us.to_le_bytes());
+                out.extend_from_slice(&dts_us.to_le_bytes());
+                for value in [duration_us, size, valid_len, flags] {
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            TraceRecord::Audio {
+                timestamp_us,
+                duration_us,
+                size,
+            } => {
+                out.push(TAG_AUDIO);
+                out.extend_from_slice(&timestamp_us.to_le_bytes());
+                out.extend_from_slice(&duration_us.to_le_bytes());
+                out.extend_from_slice(&size.to_le_bytes());
+            }
+            TraceRecord::Flush => out.push(TAG_FLUSH),
+            TraceRecord::FragmentDuration(ms) => {
+                out.push(TAG_FRAGMENT_DURATION);
+                out.extend_from_slice(&ms.to_le_bytes());
+            }
+            TraceRecord::AudioConfig {
+                sample_rate,
+                channels,
+            } => {
+                out.push(TAG_AUDIO_CONFIG);
+                out.extend_from_slice(&sample_rate.to_le_bytes());
+                out.extend_from_slice(&channels.to_le_bytes());
+            }
+            TraceRecord::VideoAux { size } => {
+                out.push(TAG_VIDEO_AUX);
+                out.extend_from_slice(&size.to_le_bytes());
+            }
+        }
+    }
+
+    /// The trace so far
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Parse a trace into the traced muxer's config and its records
+pub fn read_trace(bytes: &[u8]) -> Result<(MuxideConfig, Vec<TraceRecord>), String> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.take(TRACE_MAGIC.len())? != TRACE_MAGIC {
+        return Err("Invalid trace: bad magic".to_string());
+    }
+    let version = reader.take(1)?[0];
+    if version != TRACE_VERSION {
+        return Err(format!("Unsupported trace version: {}", version));
+    }
+    let config_len = reader.u32()? as usize;
+    let config: MuxideConfig = serde_json::from_slice(reader.take(config_len)?)
+        .map_err(|e| format!("Invalid trace config: {}", e))?;
+
+    let mut records = Vec::new();
+    while reader.pos < bytes.len() {
+        let record = match reader.take(1)?[0] {
+            TAG_VIDEO => TraceRecord::Video {
+                timestamp_us: reader.u64()?,
+                size: reader.u32()?,
+                valid_len: reader.u32()?,
+                is_keyframe: reader.take(1)?[0] != 0,
+            },
+            TAG_VIDEO_FULL => TraceRecord::VideoFull {
+                pts_us: reader.u64()?,
+                dts_us: reader.u64()?,
+                duration_us: reader.u32()?,
+                size: reader.u32()?,
+                valid_len: reader.u32()?,
+                flags: reader.u32()?,
+            },
+            TAG_VIDEO_WITH_FLAGS => TraceRecord::VideoWithFlags {
+                pts_us: reader.u64()?,
+                dts_us: reader.u64()?,
+                duration_us: reader.u32()?,
+                size: reader.u32()?,
+                valid_len: reader.u32()?,
+                sample_flags: reader.u32()?,
+            },
+            TAG_AUDIO => TraceRecord::Audio {
+                timestamp_us: reader.u64()?,
+                duration_us: reader.u32()?,
+                size: reader.u32()?,
+            },
+            TAG_FLUSH => TraceRecord::Flush,
+            TAG_FRAGMENT_DURATION => TraceRecord::FragmentDuration(reader.u32()?),
+            TAG_AUDIO_CONFIG => TraceRecord::AudioConfig {
+                sample_rate: reader.u32()?,
+                channels: u16::from_le_bytes(reader.take(2)?.try_into().unwrap()),
+            },
+            TAG_VIDEO_AUX => TraceRecord::VideoAux {
+                size: reader.u32()?,
+            },
+            tag => return Err(format!("Invalid trace: unknown record tag {}", tag)),
+        };
+        records.push(record);
+    }
+    Ok((config, records))
+}
+
+/// A traced call that failed on replay
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayError {
+    /// Index of the record in the trace
+    pub index: u32,
+    pub message: String,
+}
+
+/// Outcome of replaying a trace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayResult {
+    /// Structure of every segment produced, in order
+    pub fragments: Vec<FragmentTrace>,
+    pub stats: MuxerStats,
+    pub errors: Vec<ReplayError>,
+}
+
+/// Re-drive a dry-run muxer with the calls of a trace
+///
+/// Frames are stand-ins of the traced sizes; the final partial segment is
+/// flushed at the end so every traced frame shows up in `fragments`.
+pub fn replay_trace(bytes: &[u8]) -> Result<ReplayResult, String> {
+    let replay = replay(bytes)?;
+    let mut fragments = Vec::new();
+    for segment in &replay.segments {
+        fragments.extend(trace_segment(segment)?);
+    }
+    Ok(ReplayResult {
+        fragments,
+        stats: replay.muxer.stats(),
+        errors: replay.errors,
+    })
+}
+
+/// A replayed trace: the muxer, its dry-run segments and the failed calls
+struct Replay {
+    config: MuxideConfig,
+    muxer: MuxideMuxerState,
+    segments: Vec<Vec<u8>>,
+    errors: Vec<ReplayError>,
+}
+
+fn replay(bytes: &[u8]) -> Result<Replay, String> {
+    let (config, records) = read_trace(bytes)?;
+    let mut muxer = MuxideMuxerState::new(MuxideConfig {
+        dry_run: true,
+        ..config.clone()
+    });
+    muxer.init()?;
+    // Frames past the size limit are refused all the same, so stand-ins
+    // never need to be larger than one byte over it
+    let limit = config.max_frame_size_or_default().saturating_add(1);
+
+    let mut segments = Vec::new();
+    let mut errors = Vec::new();
+    for (index, record) in records.into_iter().enumerate() {
+        let result = match record {
+            TraceRecord::Video {
+                timestamp_us,
+                size,
+                valid_len,
+                is_keyframe,
+            } => muxer.push_video_chunk(
+                &stand_in_avcc(size.min(limit), valid_len),
+                timestamp_us,
+                is_keyframe,
+            ),
+            TraceRecord::VideoFull {
+                pts_us,
+                dts_us,
+                duration_us,
+                size,
+                valid_len,
+                flags,
+            } => muxer.push_video_chunk_full(
+                &stand_in_avcc(size.min(limit), valid_len),
+                pts_us,
+                dts_us,
+                Some(duration_us),
+                flags,
+            ),
+            TraceRecord::VideoWithFlags {
+                pts_us,
+                dts_us,
+                duration_us,
+                size,
+                valid_len,
+                sample_flags,
+            } => muxer.push_video_chunk_with_flags(
+                &stand_in_avcc(size.min(limit), valid_len),
+                pts_us,
+                dts_us,
+                Some(duration_us),
+                SampleFlags::from_bits(sample_flags),
+            ),
+            TraceRecord::Audio {
+                timestamp_us,
+                duration_us,
+                size,
+            } => muxer.push_audio_chunk(
+                &vec![0; size.min(limit) as usize],
+                timestamp_us,
+                duration_us,
+            ),
+            TraceRecord::Flush => muxer.force_flush(),
+            TraceRecord::FragmentDuration(ms) => {
+                muxer.set_fragment_duration_ms(ms);
+                Ok(())
+            }
+            TraceRecord::AudioConfig {
+                sample_rate,
+                channels,
+            } => muxer
+                .change_audio_config(sample_rate, channels, None)
+                .map(|_| ()),
+            TraceRecord::VideoAux { size } => {
+                muxer.set_next_video_aux(&vec![0; size.min(limit) as usize])
+            }
+        };
+        if let Err(message) = result {
+            errors.push(ReplayError {
+                index: index as u32,
+                message,
+            });
+        }
+        segments.extend(muxer.get_pending_segments());
+    }
+    muxer.force_flush()?;
+    segments.extend(muxer.get_pending_segments());
+    Ok(Replay {
+        config,
+        muxer,
+        segments,
+        errors,
+    })
+}
+
+/// Compact description of a session's output, for regression checks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionFingerprint {
+    pub config: MuxideConfig,
+    /// BLAKE3 hash of the init segment, hex encoded
+    pub init_hash: String,
+    pub segments: Vec<SegmentFingerprint>,
+}
+
+/// Structure and hash of one media segment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentFingerprint {
+    pub fragments: Vec<FragmentTrace>,
+    /// BLAKE3 hash of the segment's moof boxes, hex encoded. Sample data is
+    /// left out, so real and dry-run output of the same pushes hash alike.
+    pub moof_hash: String,
+}
+
+/// Outcome of checking a session against its fingerprint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct FingerprintCheck {
+    /// Init segment and every moof are byte-identical
+    pub identical: bool,
+    /// Every fragment has the same timing, sample counts, sync samples and
+    /// sizes, even if box layout differs
+    pub equivalent: bool,
+    /// Human-readable description of each mismatch
+    pub differences: Vec<String>,
+}
+
+/// Fingerprint the output of a muxer with `config`
+///
+/// `segments` are the media segments in order; a segment may hold several
+/// moof + mdat pairs.
+pub fn fingerprint_output(
+    config: &MuxideConfig,
+    init_segment: &[u8],
+    segments: &[Vec<u8>],
+) -> Result<SessionFingerprint, String> {
+    let segments = segments
+        .iter()
+        .map(|segment| {
+            Ok(SegmentFingerprint {
+                fragments: trace_segment(segment)?,
+                moof_hash: moof_hash(segment)?,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    Ok(SessionFingerprint {
+        config: MuxideConfig {
+            dry_run: false,
+            ..config.clone()
+        },
+        init_hash: blake3::hash(init_segment).to_hex().to_string(),
+        segments,
+    })
+}
+
+/// Fingerprint the output a trace replays to
+pub fn fingerprint_trace(bytes: &[u8]) -> Result<SessionFingerprint, String> {
+    let replay = replay(bytes)?;
+    let init_segment = replay.muxer.get_init_segment()?;
+    fingerprint_output(&replay.config, &init_segment, &replay.segments)
+}
+
+/// Replay a trace and compare its output with an expected fingerprint
+pub fn check_trace(
+    bytes: &[u8],
+    expected: &SessionFingerprint,
+) -> Result<FingerprintCheck, String> {
+    Ok(expected.compare(&fingerprint_trace(bytes)?))
+}
+
+impl SessionFingerprint {
+    /// Compare `actual` output against this fingerprint
+    pub fn compare(&self, actual: &SessionFingerprint) -> FingerprintCheck {
+        let mut differences = Vec::new();
+        let mut identical = true;
+        let mut equivalent = true;
+
+        if self.config != actual.config {
+            differences.push("config differs".to_string());
+            equivalent = false;
+        }
+        if self.init_hash != actual.init_hash {
+            differences.push("init segment differs".to_string());
+            identical = false;
+        }
+        if self.segments.len() != actual.segments.len() {
+            differences.push(format!(
+                "expected {} segments, got {}",
+                self.segments.len(),
+                actual.segments.len()
+            ));
+            equivalent = false;
+        }
+        for (index, (expected, actual)) in self.segments.iter().zip(&actual.segments).enumerate() {
+            if expected.fragments != actual.fragments {
+                equivalent = false;
+                if expected.fragments.len() != actual.fragments.len() {
+                    differences.push(format!(
+                        "segment {}: expected {} track fragments, got {}",
+                        index,
+                        expected.fragments.len(),
+                        actual.fragments.len()
+                    ));
+                }
+                for (e, a) in expected.fragments.iter().zip(&actual.fragments) {
+                    if e != a {
+                        differences.push(format!("segment {}: expected {}, got {}", index, e, a));
+                    }
+                }
+            }
+            if expected.moof_hash != actual.moof_hash {
+                identical = false;
+                if expected.fragments == actual.fragments {
+                    differences.push(format!("segment {}: moof bytes differ", index));
+                }
+            }
+        }
+        FingerprintCheck {
+            identical: identical && equivalent,
+            equivalent,
+            differences,
+        }
+    }
+}
+
+/// BLAKE3 over the moof boxes of a segment, skipping mdat payloads
+fn moof_hash(segment: &[u8]) -> Result<String, String> {
+    let mut hasher = blake3::Hasher::new();
+    let mut pos = 0;
+    while pos < segment.len() {
+        let header = segment
+            .get(pos..pos + 8)
+            .ok_or_else(|| format!("Truncated box header at offset {}", pos))?;
+        let size = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        if size < 8 {
+            return Err(format!("Invalid box size {} at offset {}", size, pos));
+        }
+        if &header[4..] == b"moof" {
+            hasher.update(segment.get(pos..pos + size).ok_or("Truncated moof box")?);
+        }
+        pos += size;
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// AVCC data of `size` bytes whose first `valid_len` bytes (at most
+/// `size`) are complete NAL units and whose rest is not
+fn stand_in_avcc(size: u32, valid_len: u32) -> Vec<u8> {
+    let valid_len = valid_len.min(size);
+    let mut data = Vec::with_capacity(size as usize);
+    if valid_len >= 5 {
+        data.extend_from_slice(&(valid_len - 4).to_be_bytes());
+        data.resize(valid_len as usize, 0);
+    }
+    if (size as usize).saturating_sub(data.len()) >= 4 {
+        // A length prefix running past the end of the frame
+        data.extend_from_slice(&u32::MAX.to_be_bytes());
+    }
+    data.resize(size as usize, 0);
+    data
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.pos < len {
+            return Err("Invalid trace: unexpected end of data".to_string());
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::muxide_muxer::ValidationMode;
+
+    fn config(validation: ValidationMode) -> MuxideConfig {
+        MuxideConfig {
+            sps: Some(vec![0x67, 0x42, 0xC0, 0x1E]),
+            pps: Some(vec![0x68, 0xCE, 0x3C, 0x80]),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            validation,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_replay_reproduces_segments() {
+        let mut muxer = MuxideMuxerState::new(config(ValidationMode::Warn));
+        muxer.enable_trace().unwrap();
+        muxer.init().unwrap();
+        let frame = [0, 0, 0, 3, 0x41, 1, 2];
+        for i in 0..100u64 {
+            // A jittery camera with one frame whose NAL sizes are off
+            let ts = i * 33_333 + (i % 3) * 4_000;
+            if i == 40 {
+                muxer
+                    .push_video_chunk(&[0, 0, 0, 3, 0x41, 1, 2, 9, 9], ts, false)
+                    .unwrap();
+            } else {
+                muxer.push_video_chunk(&frame, ts, i % 30 == 0).unwrap();
+            }
+            muxer
+                .push_audio_chunk(&[0; 90], i * 21_333, 21_333)
+                .unwrap();
+            if i == 70 {
+                muxer.set_fragment_duration_ms(500);
+            }
+        }
+        muxer.force_flush().unwrap();
+        let mut expected = Vec::new();
+        for segment in muxer.get_pending_segments() {
+            expected.extend(trace_segment(&segment).unwrap());
+        }
+
+        let trace = muxer.trace().unwrap().to_vec();
+        // 200 pushes: 18 bytes per video record, 17 per audio record
+        assert!(trace.len() < 100 * 18 + 100 * 17 + 1024);
+        let result = replay_trace(&trace).unwrap();
+        assert_eq!(result.fragments, expected);
+        assert_eq!(result.stats.anomaly_count, 1);
+        assert_eq!(result.stats.video_frame_count, 100);
+        assert!(result.errors.is_empty());
+    }
+
+    #[test]
+    fn test_fingerprint_check() {
+        let mut muxer = MuxideMuxerState::new(config(ValidationMode::Warn));
+        muxer.enable_trace().unwrap();
+        muxer.init().unwrap();
+        for i in 0..90u64 {
+            muxer
+                .push_video_chunk(&[0, 0, 0, 3, 0x41, 1, 2], i * 33_333, i % 30 == 0)
+                .unwrap();
+            muxer
+                .push_audio_chunk(&[0; 90], i * 21_333, 21_333)
+                .unwrap();
+        }
+        muxer.force_flush().unwrap();
+        let segments = muxer.get_pending_segments();
+        let init = muxer.get_init_segment().unwrap();
+        let captured = fingerprint_output(&config(ValidationMode::Warn), &init, &segments).unwrap();
+        assert!(captured.segments.len() > 1);
+
+        // Replaying the trace reproduces the real output byte for byte
+        let trace = muxer.trace().unwrap().to_vec();
+        assert_eq!(fingerprint_trace(&trace).unwrap(), captured);
+        let check = check_trace(&trace, &captured).unwrap();
+        assert!(check.identical && check.equivalent);
+        assert!(check.differences.is_empty());
+
+        // A fingerprint survives a JSON round trip
+        let json = serde_json::to_string(&captured).unwrap();
+        let stored: SessionFingerprint = serde_json::from_str(&json).unwrap();
+        assert!(check_trace(&trace, &stored).unwrap().identical);
+
+        // A changed moof with the same structure is equivalent only
+        let mut layout = captured.clone();
+        layout.segments[1].moof_hash = "00".repeat(32);
+        let check = check_trace(&trace, &layout).unwrap();
+        assert!(!check.identical && check.equivalent);
+        assert_eq!(check.differences, vec!["segment 1: moof bytes differ"]);
+
+        // Different timing is neither
+        let mut timing = captured;
+        timing.segments[0].fragments[0].duration += 1;
+        timing.segments.pop();
+        let check = check_trace(&trace, &timing).unwrap();
+        assert!(!check.identical && !check.equivalent);
+        assert_eq!(check.differences.len(), 2);
+        assert!(check.differences[1].starts_with("segment 0: expected #1 track 1"));
+    }
+
+    #[test]
+    fn test_replay_reports_errors() {
+        let mut muxer = MuxideMuxerState::new(config(ValidationMode::Strict));
+        muxer.enable_trace().unwrap();
+        muxer.init().unwrap();
+        muxer
+            .push_video_chunk(&[0, 0, 0, 1, 0x65], 66_666, true)
+            .unwrap();
+        assert!(muxer
+            .push_video_chunk(&[0, 0, 0, 1, 0x41], 33_333, false)
+            .is_err());
+        assert!(muxer
+            .push_video_chunk(&[0, 0, 0, 9], 99_999, false)
+            .is_err());
+
+        let result = replay_trace(muxer.trace().unwrap()).unwrap();
+        let indices: Vec<u32> = result.errors.iter().map(|e| e.index).collect();
+        assert_eq!(indices, vec![1, 2]);
+        assert!(result.errors[0].message.contains("Non-monotonic"));
+
+        assert!(replay_trace(b"MXTR").is_err());
+        assert!(read_trace(b"nope").is_err());
+
+        // Stand-ins of forged records neither underflow nor grow unbounded
+        assert_eq!(stand_in_avcc(2, 9), vec![0, 0]);
+        assert_eq!(stand_in_avcc(6, 6), vec![0, 0, 0, 2, 0, 0]);
+        assert_eq!(stand_in_avcc(5, 0), vec![0xFF, 0xFF, 0xFF, 0xFF, 0]);
+    }
+}
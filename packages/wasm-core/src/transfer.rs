@@ -0,0 +1,118 @@
+//! Hand-off of an in-progress recording to another worker.
+//!
+//! A `RecorderTransfer` packs everything `RecorderState::resume_transfer`
+//! needs to continue a recording elsewhere: the muxer config, the manifest
+//! and the recorder snapshot, whose muxer state holds the frames and segments
+//! not yet emitted as chunks. Encoded, it is a single byte buffer, so it can
+//! be posted to another worker (or a SharedWorker) with its `ArrayBuffer` in
+//! the transfer list instead of being copied.
+//!
+//! Layout (little endian):
+//!
+//! ```text
+//! "RCTX" | version u8 | flags u8 | config JSON | manifest JSON | snapshot
+//! ```
+//!
+//! with every variable-length part prefixed by its u32 length.
+
+use crate::manifest::ChunkManifest;
+use crate::muxide_muxer::{put_bytes, MuxideConfig, StateReader};
+use crate::session::SessionId;
+
+const TRANSFER_MAGIC: &[u8] = b"RCTX";
+const TRANSFER_VERSION: u8 = 1;
+const FLAG_PAUSED: u8 = 0x01;
+
+/// An in-progress recording packed for another worker
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecorderTransfer {
+    /// Muxer config in effect, to create the receiving recorder with
+    pub config: MuxideConfig,
+    pub manifest: ChunkManifest,
+    /// `RecorderState::snapshot` taken together with `manifest`
+    pub snapshot: Vec<u8>,
+    /// The recording was paused and continues paused
+    pub paused: bool,
+}
+
+impl RecorderTransfer {
+    /// Session being transferred
+    pub fn session_id(&self) -> &SessionId {
+        &self.manifest.session_id
+    }
+
+    /// Encode into one buffer
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let config = serde_json::to_vec(&self.config).map_err(|e| e.to_string())?;
+        let manifest = serde_json::to_vec(&self.manifest).map_err(|e| e.to_string())?;
+        let mut out = Vec::with_capacity(
+            TRANSFER_MAGIC.len() + 14 + config.len() + manifest.len() + self.snapshot.len(),
+        );
+        out.extend_from_slice(TRANSFER_MAGIC);
+        out.push(TRANSFER_VERSION);
+        out.push(if self.paused { FLAG_PAUSED } else { 0 });
+        put_bytes(&mut out, &config);
+        put_bytes(&mut out, &manifest);
+        put_bytes(&mut out, &self.snapshot);
+        Ok(out)
+    }
+
+    /// Decode a buffer produced by `encode`
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = StateReader { bytes, pos: 0 };
+        if reader.take(TRANSFER_MAGIC.len())? != TRANSFER_MAGIC {
+            return Err("Invalid recorder transfer: bad magic".to_string());
+        }
+        let version = reader.u8()?;
+        if version != TRANSFER_VERSION {
+            return Err(format!(
+                "Unsupported recorder transfer version: {}",
+                version
+            ));
+        }
+        let flags = reader.u8()?;
+        let config = serde_json::from_slice(reader.bytes()?)
+            .map_err(|e| format!("Invalid recorder transfer config: {}", e))?;
+        let manifest = serde_json::from_slice(reader.bytes()?)
+            .map_err(|e| format!("Invalid recorder transfer manifest: {}", e))?;
+        let snapshot = reader.bytes()?.to_vec();
+        if reader.pos != bytes.len() {
+            return Err("Invalid recorder transfer: trailing bytes".to_string());
+        }
+        Ok(Self {
+            config,
+            manifest,
+            snapshot,
+            paused: flags & FLAG_PAUSED != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_round_trip() {
+        let transfer = RecorderTransfer {
+            config: MuxideConfig::default(),
+            manifest: ChunkManifest::new(SessionId::from("s1")),
+            snapshot: vec![1, 2, 3],
+            paused: true,
+        };
+        let bytes = transfer.encode().unwrap();
+        assert_eq!(RecorderTransfer::decode(&bytes).unwrap(), transfer);
+
+        assert!(RecorderTransfer::decode(&bytes[..bytes.len() - 1]).is_err());
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(RecorderTransfer::decode(&trailing)
+            .unwrap_err()
+            .contains("trailing"));
+        let mut bad = bytes;
+        bad[0] = b'X';
+        assert!(RecorderTransfer::decode(&bad)
+            .unwrap_err()
+            .contains("bad magic"));
+    }
+}
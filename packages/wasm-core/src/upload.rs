@@ -0,0 +1,431 @@
+//! Per-chunk upload bookkeeping.
+//!
+//! `UploadTracker` mirrors the web client's `upload_states` store: every chunk
+//! of a session's manifest is `pending`, `uploading`, `uploaded` or `failed`,
+//! with a retry count and the last error. The tracker serializes to JSON, so
+//! an uploader can persist it and resume after a restart; chunks that were in
+//! flight at that point go back to `pending`.
+//!
+//! Each completed upload is also kept as an `UploadSample` (bytes and time
+//! taken) until taken by a consumer such as `ChunkSizePolicyState`.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::chunk::ChunkId;
+use crate::clock::ClockHandle;
+use crate::manifest::ChunkManifest;
+use crate::session::SessionId;
+use crate::streaming::ResendReason;
+
+/// Upload state of one chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadState {
+    Pending,
+    Uploading,
+    Uploaded,
+    Failed,
+}
+
+/// Upload status of one chunk, as in the web client's `ChunkUploadStatus`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkUploadStatus {
+    pub chunk_id: ChunkId,
+    pub state: UploadState,
+    pub retry_count: u32,
+    /// Unix timestamp (ms) of the last upload attempt, 0 if never attempted
+    pub last_attempt: u64,
+    /// BLAKE3 hash of the chunk data (hex), from the manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Chunk size in bytes, from the manifest
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Chunk counts per upload state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadProgress {
+    pub uploaded: u32,
+    pub total: u32,
+    pub pending: u32,
+    pub uploading: u32,
+    pub failed: u32,
+}
+
+/// One finished chunk upload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadSample {
+    pub bytes: u64,
+    /// Time from `start` to `complete`
+    pub elapsed_ms: u64,
+}
+
+/// Upload status of every chunk of one session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadTracker {
+    session_id: SessionId,
+    #[serde(default)]
+    init_segment_uploaded: bool,
+    /// Sorted by chunk ID
+    #[serde(default)]
+    chunks: Vec<ChunkUploadStatus>,
+    /// Completed uploads not taken yet
+    #[serde(skip)]
+    samples: Vec<UploadSample>,
+    /// Time source of `start` and `complete`
+    #[serde(skip)]
+    clock: ClockHandle,
+}
+
+impl UploadTracker {
+    /// Create an empty tracker for a session
+    pub fn new(session_id: SessionId) -> Self {
+        Self {
+            session_id,
+            init_segment_uploaded: false,
+            chunks: Vec::new(),
+            samples: Vec::new(),
+            clock: ClockHandle::default(),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn set_clock(&mut self, clock: ClockHandle) {
+        self.clock = clock;
+    }
+
+    /// Restore a persisted tracker
+    ///
+    /// Chunks that were uploading when it was saved are pending again.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let mut tracker: Self =
+            serde_json::from_str(json).map_err(|e| format!("Invalid upload tracker: {}", e))?;
+        tracker.chunks.sort_by(|a, b| a.chunk_id.cmp(&b.chunk_id));
+        for status in &mut tracker.chunks {
+            if status.state == UploadState::Uploading {
+                status.state = UploadState::Pending;
+            }
+        }
+        Ok(tracker)
+    }
+
+    /// Serialize the tracker for persistence
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string(self).map_err(|e| e.to_string())
+    }
+
+    /// Session being tracked
+    pub fn session_id(&self) -> &SessionId {
+        &self.session_id
+    }
+
+    /// Add the manifest's chunks that are not tracked yet as pending
+    ///
+    /// Returns the number of chunks added.
+    pub fn sync_manifest(&mut self, manifest: &ChunkManifest) -> Result<usize, String> {
+        if manifest.session_id != self.session_id {
+            return Err(format!(
+                "Manifest of session {} does not match tracker of session {}",
+                manifest.session_id, self.session_id
+            ));
+        }
+        let mut added = 0;
+        for chunk in &manifest.chunks {
+            if let Err(index) = self.position(&chunk.chunk_id) {
+                self.chunks.insert(
+                    index,
+                    ChunkUploadStatus {
+                        chunk_id: chunk.chunk_id.clone(),
+                        state: UploadState::Pending,
+                        retry_count: 0,
+                        last_attempt: 0,
+                        hash: chunk.hash.clone(),
+                        size: chunk.size,
+                        error: None,
+                    },
+                );
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    /// Whether the session's init segment has been uploaded
+    pub fn init_segment_uploaded(&self) -> bool {
+        self.init_segment_uploaded
+    }
+
+    /// Record that the session's init segment has been uploaded
+    pub fn mark_init_segment_uploaded(&mut self) {
+        self.init_segment_uploaded = true;
+    }
+
+    /// Look up the status of a chunk
+    pub fn status(&self, id: &ChunkId) -> Option<&ChunkUploadStatus> {
+        self.position(id).ok().map(|index| &self.chunks[index])
+    }
+
+    /// Statuses of all tracked chunks, in ID order
+    pub fn statuses(&self) -> &[ChunkUploadStatus] {
+        &self.chunks
+    }
+
+    /// IDs of the chunks waiting to be uploaded, in ID order
+    pub fn pending(&self) -> Vec<ChunkId> {
+        self.chunks
+            .iter()
+            .filter(|status| status.state == UploadState::Pending)
+            .map(|status| status.chunk_id.clone())
+            .collect()
+    }
+
+    /// Mark a pending chunk as uploading
+    pub fn start(&mut self, id: &ChunkId) -> Result<(), String> {
+        self.start_at(id, self.clock.now_ms())
+    }
+
+    /// Mark a pending chunk as uploading at `now_ms` (Unix time in milliseconds)
+    pub fn start_at(&mut self, id: &ChunkId, now_ms: u64) -> Result<(), String> {
+        let status = self.status_mut(id)?;
+        if status.state != UploadState::Pending {
+            return Err(format!(
+                "Cannot upload chunk {} in state {:?}",
+                id, status.state
+            ));
+        }
+        status.state = UploadState::Uploading;
+        status.last_attempt = now_ms;
+        Ok(())
+    }
+
+    /// Mark an uploading chunk as uploaded
+    pub fn complete(&mut self, id: &ChunkId) -> Result<(), String> {
+        self.complete_at(id, self.clock.now_ms())
+    }
+
+    /// Mark an uploading chunk as uploaded at `now_ms` (Unix time in milliseconds)
+    pub fn complete_at(&mut self, id: &ChunkId, now_ms: u64) -> Result<(), String> {
+        let status = self.status_mut(id)?;
+        let sample = (status.state == UploadState::Uploading).then(|| UploadSample {
+            bytes: status.size,
+            elapsed_ms: now_ms.saturating_sub(status.last_attempt),
+        });
+        status.state = UploadState::Uploaded;
+        status.error = None;
+        self.samples.extend(sample);
+        Ok(())
+    }
+
+    /// Take the uploads completed since the last call, oldest first
+    pub fn take_upload_samples(&mut self) -> Vec<UploadSample> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// Bytes of the chunks not uploaded yet, in flight ones included
+    pub fn backlog_bytes(&self) -> u64 {
+        self.chunks
+            .iter()
+            .filter(|status| status.state != UploadState::Uploaded)
+            .map(|status| status.size)
+            .sum()
+    }
+
+    /// Record a failed attempt
+    ///
+    /// The chunk goes back to pending while it has retries left, and is
+    /// marked failed after `max_retries` retries. Returns the new state.
+    pub fn fail(
+        &mut self,
+        id: &ChunkId,
+        error: &str,
+        max_retries: u32,
+    ) -> Result<UploadState, String> {
+        let status = self.status_mut(id)?;
+        status.error = Some(error.to_string());
+        if status.retry_count < max_retries {
+            status.retry_count += 1;
+            status.state = UploadState::Pending;
+        } else {
+            status.state = UploadState::Failed;
+        }
+        Ok(status.state)
+    }
+
+    /// Make failed chunks pending again with a fresh retry budget
+    pub fn retry_failed(&mut self) -> usize {
+        let mut count = 0;
+        for status in &mut self.chunks {
+            if status.state == UploadState::Failed {
+                status.state = UploadState::Pending;
+                status.retry_count = 0;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Upload chunks again that the server found corrupt or missing
+    ///
+    /// Uploaded and failed chunks go back to pending with a fresh retry
+    /// budget, so the uploader reads them from local storage again; chunks
+    /// still pending or uploading are left alone. Returns how many chunks
+    /// were reset.
+    pub fn request_reupload(
+        &mut self,
+        ids: &[ChunkId],
+        reason: ResendReason,
+    ) -> Result<usize, String> {
+        let mut count = 0;
+        for id in ids {
+            let status = self.status_mut(id)?;
+            if matches!(status.state, UploadState::Uploaded | UploadState::Failed) {
+                status.state = UploadState::Pending;
+                status.retry_count = 0;
+                status.error = Some(format!("Requested again by the server: {}", reason));
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Count chunks per state
+    pub fn progress(&self) -> UploadProgress {
+        let mut progress = UploadProgress {
+            total: self.chunks.len() as u32,
+            ..Default::default()
+        };
+        for status in &self.chunks {
+            match status.state {
+                UploadState::Pending => progress.pending += 1,
+                UploadState::Uploading => progress.uploading += 1,
+                UploadState::Uploaded => progress.uploaded += 1,
+                UploadState::Failed => progress.failed += 1,
+            }
+        }
+        progress
+    }
+
+    /// Whether the init segment and every tracked chunk have been uploaded
+    pub fn is_complete(&self) -> bool {
+        self.init_segment_uploaded
+            && self
+                .chunks
+                .iter()
+                .all(|status| status.state == UploadState::Uploaded)
+    }
+
+    fn position(&self, id: &ChunkId) -> Result<usize, usize> {
+        self.chunks
+            .binary_search_by(|status| status.chunk_id.cmp(id))
+    }
+
+    fn status_mut(&mut self, id: &ChunkId) -> Result<&mut ChunkUploadStatus, String> {
+        match self.position(id) {
+            Ok(index) => Ok(&mut self.chunks[index]),
+            Err(_) => Err(format!("Chunk {} is not tracked", id)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{ChunkMetadata, TrackKind};
+    use crate::clock::ManualClock;
+
+    fn manifest(count: u64) -> ChunkManifest {
+        let session = SessionId::from("s1");
+        let mut manifest = ChunkManifest::new(session.clone());
+        for sequence in 0..count {
+            manifest
+                .add_chunk(ChunkMetadata {
+                    chunk_id: ChunkId::new(session.clone(), TrackKind::Muxed, sequence),
+                    timestamp_us: sequence * 2_000_000,
+                    size: 4,
+                    hash: Some(format!("hash{}", sequence)),
+                    has_keyframe: Some(true),
+                    created_at: 0,
+                })
+                .unwrap();
+        }
+        manifest
+    }
+
+    #[test]
+    fn test_tracker_retries_and_resumes() {
+        let mut tracker = UploadTracker::new(SessionId::from("s1"));
+        assert_eq!(tracker.sync_manifest(&manifest(2)).unwrap(), 2);
+        assert_eq!(tracker.sync_manifest(&manifest(3)).unwrap(), 1);
+        assert!(tracker
+            .sync_manifest(&ChunkManifest::new(SessionId::from("s2")))
+            .is_err());
+
+        let clock = ManualClock::new(1_700_000_000_000);
+        tracker.set_clock(ClockHandle::new(clock.clone()));
+        let ids = tracker.pending();
+        tracker.start(&ids[0]).unwrap();
+        assert!(tracker.start(&ids[0]).is_err());
+        clock.advance(250);
+        tracker.complete(&ids[0]).unwrap();
+        assert_eq!(tracker.take_upload_samples()[0].elapsed_ms, 250);
+
+        tracker.start(&ids[1]).unwrap();
+        assert_eq!(
+            tracker.fail(&ids[1], "timeout", 1).unwrap(),
+            UploadState::Pending
+        );
+        tracker.start(&ids[1]).unwrap();
+        assert_eq!(
+            tracker.fail(&ids[1], "timeout", 1).unwrap(),
+            UploadState::Failed
+        );
+        tracker.start(&ids[2]).unwrap();
+
+        assert_eq!(
+            tracker.progress(),
+            UploadProgress {
+                uploaded: 1,
+                total: 3,
+                pending: 0,
+                uploading: 1,
+                failed: 1,
+            }
+        );
+
+        // A restored tracker picks up in-flight chunks again
+        let mut restored = UploadTracker::from_json(&tracker.to_json().unwrap()).unwrap();
+        assert_eq!(restored.pending(), vec![ids[2].clone()]);
+        assert_eq!(restored.retry_failed(), 1);
+        assert_eq!(restored.pending(), vec![ids[1].clone(), ids[2].clone()]);
+        assert_eq!(
+            restored.status(&ids[1]).unwrap().hash.as_deref(),
+            Some("hash1")
+        );
+        assert!(!restored.is_complete());
+
+        // Chunks the server re-requests are uploaded again
+        assert_eq!(
+            restored
+                .request_reupload(&[ids[0].clone(), ids[2].clone()], ResendReason::Missing)
+                .unwrap(),
+            1
+        );
+        assert_eq!(restored.pending(), ids);
+        assert!(restored
+            .request_reupload(
+                &[ChunkId::new(SessionId::from("s1"), TrackKind::Muxed, 9)],
+                ResendReason::Missing
+            )
+            .is_err());
+    }
+}
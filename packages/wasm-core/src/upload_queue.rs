@@ -0,0 +1,688 @@
+//! Prioritized, rate-limited scheduling of uploads.
+//!
+//! `UploadQueueState` decides what to upload next across the sessions whose
+//! `UploadTracker`s it holds; the caller does the transfers and reports back
+//! with `complete` or `fail`. It never performs I/O, so the web client (via
+//! `UploadQueue`) and native uploaders share it.
+//!
+//! Ordering, from first to last:
+//!
+//! - a session's init segment, before any of its chunks (which wait for it)
+//! - chunks holding a keyframe, which a player or server can start from
+//! - other chunks, in chunk ID order
+//!
+//! Each session belongs to a lane: `Live` for a recording in progress whose
+//! chunks are uploaded as they are produced, `Archival` for backfilling
+//! finished ones. Lanes share the concurrency and bandwidth caps by weight
+//! (deficit round-robin over the bytes started), so neither starves the
+//! other, and a lane coming back from idle does not make up for the time it
+//! had no work. The queue can be paused by hand or by network changes from
+//! JS: offline pauses everything, a metered connection pauses the archival
+//! lane unless allowed.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::chunk::ChunkId;
+use crate::clock::ClockHandle;
+use crate::manifest::ChunkManifest;
+use crate::session::SessionId;
+use crate::upload::{UploadState, UploadTracker};
+
+/// Class of a session's uploads
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Tsify,
+)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadLane {
+    /// Chunks of a recording in progress
+    Live,
+    /// Backfill of finished recordings
+    #[default]
+    Archival,
+}
+
+/// Upload queue caps and lane weights
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UploadQueueConfig {
+    /// Uploads in flight at once, init segments included
+    pub max_concurrent_uploads: u32,
+    /// Bytes started per second, unlimited if unset; up to one second's
+    /// worth can be started in a burst
+    #[tsify(optional)]
+    pub max_bytes_per_second: Option<u64>,
+    /// Share of the bytes for the live lane while both lanes have work
+    pub live_weight: u32,
+    /// Share of the bytes for the archival lane while both lanes have work
+    pub archival_weight: u32,
+    /// Keep backfilling on a metered connection
+    pub archival_on_metered: bool,
+}
+
+impl Default for UploadQueueConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_uploads: 5,
+            max_bytes_per_second: None,
+            live_weight: 3,
+            archival_weight: 1,
+            archival_on_metered: false,
+        }
+    }
+}
+
+/// Network conditions reported by the browser (`navigator.onLine`,
+/// `navigator.connection`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkStatus {
+    pub online: bool,
+    /// Connection billed by volume (`saveData` or a cellular type)
+    #[serde(default)]
+    #[tsify(optional)]
+    pub metered: bool,
+}
+
+/// One upload to perform, handed out by `next_job`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum UploadJob {
+    #[serde(rename_all = "camelCase")]
+    InitSegment {
+        session_id: SessionId,
+        lane: UploadLane,
+    },
+    #[serde(rename_all = "camelCase")]
+    Chunk {
+        chunk_id: ChunkId,
+        lane: UploadLane,
+        size: u64,
+        /// BLAKE3 hash from the manifest (hex)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hash: Option<String>,
+    },
+}
+
+impl UploadJob {
+    pub fn session_id(&self) -> &SessionId {
+        match self {
+            Self::InitSegment { session_id, .. } => session_id,
+            Self::Chunk { chunk_id, .. } => &chunk_id.session,
+        }
+    }
+
+    /// Bytes to transfer, 0 for an init segment (not known in advance)
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::InitSegment { .. } => 0,
+            Self::Chunk { size, .. } => *size,
+        }
+    }
+
+    pub fn lane(&self) -> UploadLane {
+        match self {
+            Self::InitSegment { lane, .. } | Self::Chunk { lane, .. } => *lane,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct QueuedSession {
+    tracker: UploadTracker,
+    lane: UploadLane,
+    init_in_flight: bool,
+    /// Chunks of the manifest holding a keyframe
+    keyframes: BTreeSet<ChunkId>,
+}
+
+/// Candidate job ranking: lower goes first
+type Rank = (u8, usize);
+
+/// Schedules the uploads of several sessions
+#[derive(Debug)]
+pub struct UploadQueueState {
+    config: UploadQueueConfig,
+    /// In the order they were added
+    sessions: Vec<QueuedSession>,
+    in_flight: u32,
+    paused: bool,
+    network: NetworkStatus,
+    /// Deficit round-robin: bytes each lane with work may start before the
+    /// next round, and the lane served last
+    deficits: BTreeMap<UploadLane, u64>,
+    last_lane: Option<UploadLane>,
+    /// Token bucket of `max_bytes_per_second`: bytes that may start now
+    /// and when it was last refilled
+    budget: u64,
+    budget_at_ms: Option<u64>,
+    /// Bytes the next job needs from the bucket, while it does not hold them
+    blocked_bytes: Option<u64>,
+    clock: ClockHandle,
+}
+
+impl UploadQueueState {
+    pub fn new(config: UploadQueueConfig) -> Self {
+        Self {
+            config,
+            sessions: Vec::new(),
+            in_flight: 0,
+            paused: false,
+            network: NetworkStatus {
+                online: true,
+                metered: false,
+            },
+            deficits: BTreeMap::new(),
+            last_lane: None,
+            budget: config.max_bytes_per_second.unwrap_or(0),
+            budget_at_ms: None,
+            blocked_bytes: None,
+            clock: ClockHandle::default(),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn set_clock(&mut self, clock: ClockHandle) {
+        self.clock = clock;
+    }
+
+    pub fn config(&self) -> &UploadQueueConfig {
+        &self.config
+    }
+
+    /// Add a session's tracker, e.g. restored with `UploadTracker::from_json`
+    ///
+    /// Call `sync_manifest` afterwards so keyframe chunks are known.
+    pub fn add_session(&mut self, tracker: UploadTracker, lane: UploadLane) -> Result<(), String> {
+        if self.position(tracker.session_id()).is_some() {
+            return Err(format!(
+                "Session {} is already queued",
+                tracker.session_id()
+            ));
+        }
+        self.sessions.push(QueuedSession {
+            tracker,
+            lane,
+            init_in_flight: false,
+            keyframes: BTreeSet::new(),
+        });
+        Ok(())
+    }
+
+    /// Take a session out of the queue, returning its tracker
+    ///
+    /// Uploads still in flight for it no longer count against the cap.
+    pub fn remove_session(&mut self, session: &SessionId) -> Option<UploadTracker> {
+        let index = self.position(session)?;
+        let removed = self.sessions.remove(index);
+        let in_flight = removed.tracker.progress().uploading + u32::from(removed.init_in_flight);
+        self.in_flight = self.in_flight.saturating_sub(in_flight);
+        Some(removed.tracker)
+    }
+
+    pub fn tracker(&self, session: &SessionId) -> Option<&UploadTracker> {
+        self.position(session).map(|i| &self.sessions[i].tracker)
+    }
+
+    /// Move a session to another lane, e.g. to archival once recording stops
+    pub fn set_lane(&mut self, session: &SessionId, lane: UploadLane) -> Result<(), String> {
+        self.session_mut(session)?.lane = lane;
+        Ok(())
+    }
+
+    /// Queue the manifest's new chunks; returns how many were added
+    pub fn sync_manifest(&mut self, manifest: &ChunkManifest) -> Result<usize, String> {
+        let session = self.session_mut(&manifest.session_id)?;
+        let added = session.tracker.sync_manifest(manifest)?;
+        session.keyframes.extend(
+            manifest
+                .chunks
+                .iter()
+                .filter(|chunk| chunk.has_keyframe == Some(true))
+                .map(|chunk| chunk.chunk_id.clone()),
+        );
+        Ok(added)
+    }
+
+    /// Stop handing out jobs; uploads in flight still finish
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Apply a network change: offline pauses every lane and a metered
+    /// connection the archival one, until a later change lifts it
+    pub fn set_network(&mut self, network: NetworkStatus) {
+        self.network = network;
+    }
+
+    /// Whether `lane` may start uploads now
+    pub fn lane_active(&self, lane: UploadLane) -> bool {
+        !self.paused
+            && self.network.online
+            && (lane == UploadLane::Live
+                || !self.network.metered
+                || self.config.archival_on_metered)
+    }
+
+    /// Uploads handed out and not reported back yet
+    pub fn in_flight(&self) -> u32 {
+        self.in_flight
+    }
+
+    /// Next upload to start, if the caps and pauses allow one
+    ///
+    /// The job is marked as in flight (its chunk as uploading in the
+    /// tracker) until passed to `complete` or `fail`.
+    pub fn next_job(&mut self) -> Result<Option<UploadJob>, String> {
+        self.next_job_at(self.clock.now_ms())
+    }
+
+    /// `next_job` at `now_ms` (Unix time in milliseconds)
+    pub fn next_job_at(&mut self, now_ms: u64) -> Result<Option<UploadJob>, String> {
+        if self.in_flight >= self.config.max_concurrent_uploads.max(1) {
+            return Ok(None);
+        }
+        self.refill(now_ms);
+
+        let mut best: BTreeMap<UploadLane, (Rank, UploadJob)> = BTreeMap::new();
+        for (index, session) in self.sessions.iter().enumerate() {
+            if !self.lane_active(session.lane) {
+                continue;
+            }
+            if let Some(candidate) = Self::candidate(index, session) {
+                match best.get(&session.lane) {
+                    Some(current) if current.0 <= candidate.0 => {}
+                    _ => {
+                        best.insert(session.lane, candidate);
+                    }
+                }
+            }
+        }
+        let Some((lane, deficits)) = self.pick_lane(&best) else {
+            self.blocked_bytes = None;
+            return Ok(None);
+        };
+        let Some((_, job)) = best.remove(&lane) else {
+            return Ok(None);
+        };
+
+        // A chunk larger than a second's worth starts on a full bucket
+        if let Some(rate) = self.config.max_bytes_per_second {
+            let needed = job.size().min(rate);
+            if self.budget < needed {
+                self.blocked_bytes = Some(needed);
+                return Ok(None);
+            }
+        }
+        self.blocked_bytes = None;
+        self.deficits = deficits;
+        self.last_lane = Some(lane);
+        self.start(&job, now_ms)?;
+        Ok(Some(job))
+    }
+
+    /// Lane whose job goes next by deficit round-robin, with the lanes'
+    /// deficits once the job has started
+    ///
+    /// Every round each lane with work earns its weight in bytes, and a lane
+    /// starts its job once its deficit covers the job's size; the rounds
+    /// until the first lane can are counted rather than visited one by one.
+    /// A lane without work loses its deficit, so it cannot save up while
+    /// idle. A zero weight only gets what the other lane leaves.
+    fn pick_lane(
+        &self,
+        best: &BTreeMap<UploadLane, (Rank, UploadJob)>,
+    ) -> Option<(UploadLane, BTreeMap<UploadLane, u64>)> {
+        let weighted: Vec<UploadLane> = best
+            .keys()
+            .copied()
+            .filter(|&lane| self.weight(lane) > 0)
+            .collect();
+        if weighted.is_empty() {
+            return best.keys().next().map(|&lane| (lane, BTreeMap::new()));
+        }
+        let mut deficits: BTreeMap<UploadLane, u64> = weighted
+            .iter()
+            .map(|&lane| (lane, self.deficits.get(&lane).copied().unwrap_or(0)))
+            .collect();
+        let size = |lane: UploadLane| best.get(&lane).map_or(0, |(_, job)| job.size());
+        // Ties go to a lane after the one served last
+        let (lane, rounds) = weighted
+            .iter()
+            .map(|&lane| {
+                let missing = size(lane).saturating_sub(deficits[&lane]);
+                (lane, missing.div_ceil(self.weight(lane) as u64))
+            })
+            .min_by_key(|&(lane, rounds)| (rounds, Some(lane) <= self.last_lane))?;
+        for (&other, deficit) in deficits.iter_mut() {
+            *deficit = deficit.saturating_add(rounds.saturating_mul(self.weight(other) as u64));
+        }
+        let deficit = deficits.get_mut(&lane)?;
+        *deficit = deficit.saturating_sub(size(lane));
+        Some((lane, deficits))
+    }
+
+    fn weight(&self, lane: UploadLane) -> u32 {
+        match lane {
+            UploadLane::Live => self.config.live_weight,
+            UploadLane::Archival => self.config.archival_weight,
+        }
+    }
+
+    /// Best job of one session with its rank
+    fn candidate(index: usize, session: &QueuedSession) -> Option<(Rank, UploadJob)> {
+        let tracker = &session.tracker;
+        if !tracker.init_segment_uploaded() {
+            // Chunks wait until the server has the init segment
+            return (!session.init_in_flight).then(|| {
+                let job = UploadJob::InitSegment {
+                    session_id: tracker.session_id().clone(),
+                    lane: session.lane,
+                };
+                ((0, index), job)
+            });
+        }
+        let (status, keyframe) = tracker
+            .statuses()
+            .iter()
+            .filter(|status| status.state == UploadState::Pending)
+            .map(|status| (status, session.keyframes.contains(&status.chunk_id)))
+            .min_by_key(|&(_, keyframe)| !keyframe)?;
+        let rank = if keyframe { 1 } else { 2 };
+        let job = UploadJob::Chunk {
+            chunk_id: status.chunk_id.clone(),
+            lane: session.lane,
+            size: status.size,
+            hash: status.hash.clone(),
+        };
+        Some(((rank, index), job))
+    }
+
+    fn start(&mut self, job: &UploadJob, now_ms: u64) -> Result<(), String> {
+        let session = self.session_mut(job.session_id())?;
+        let bytes = match job {
+            UploadJob::InitSegment { .. } => {
+                session.init_in_flight = true;
+                0
+            }
+            UploadJob::Chunk { chunk_id, size, .. } => {
+                session.tracker.start_at(chunk_id, now_ms)?;
+                *size
+            }
+        };
+        self.budget = self.budget.saturating_sub(bytes);
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    fn refill(&mut self, now_ms: u64) {
+        let Some(rate) = self.config.max_bytes_per_second else {
+            return;
+        };
+        let elapsed = now_ms.saturating_sub(self.budget_at_ms.unwrap_or(now_ms));
+        let earned = (elapsed as u128 * rate as u128 / 1_000) as u64;
+        self.budget = self.budget.saturating_add(earned).min(rate);
+        self.budget_at_ms = Some(now_ms);
+    }
+
+    /// When the bandwidth cap allows the next upload (Unix time in
+    /// milliseconds), if it held back the last `next_job`
+    pub fn ready_at_ms(&self) -> Option<u64> {
+        let rate = self.config.max_bytes_per_second.filter(|&r| r > 0)?;
+        let missing = self.blocked_bytes?.saturating_sub(self.budget);
+        Some(self.budget_at_ms? + (missing * 1_000).div_ceil(rate))
+    }
+
+    /// Report a job as done
+    pub fn complete(&mut self, job: &UploadJob) -> Result<(), String> {
+        let now_ms = self.clock.now_ms();
+        let session = self.session_mut(job.session_id())?;
+        match job {
+            UploadJob::InitSegment { .. } => {
+                session.init_in_flight = false;
+                session.tracker.mark_init_segment_uploaded();
+            }
+            UploadJob::Chunk { chunk_id, .. } => session.tracker.complete_at(chunk_id, now_ms)?,
+        }
+        self.in_flight = self.in_flight.saturating_sub(1);
+        Ok(())
+    }
+
+    /// Report a failed job
+    ///
+    /// A chunk goes back to the queue while it has retries left (see
+    /// `UploadTracker::fail`); an init segment is always offered again.
+    /// Returns the job's new state.
+    pub fn fail(
+        &mut self,
+        job: &UploadJob,
+        error: &str,
+        max_retries: u32,
+    ) -> Result<UploadState, String> {
+        let session = self.session_mut(job.session_id())?;
+        let state = match job {
+            UploadJob::InitSegment { .. } => {
+                session.init_in_flight = false;
+                UploadState::Pending
+            }
+            UploadJob::Chunk { chunk_id, .. } => {
+                session.tracker.fail(chunk_id, error, max_retries)?
+            }
+        };
+        self.in_flight = self.in_flight.saturating_sub(1);
+        Ok(state)
+    }
+
+    fn position(&self, session: &SessionId) -> Option<usize> {
+        self.sessions
+            .iter()
+            .position(|queued| queued.tracker.session_id() == session)
+    }
+
+    fn session_mut(&mut self, session: &SessionId) -> Result<&mut QueuedSession, String> {
+        match self.position(session) {
+            Some(index) => Ok(&mut self.sessions[index]),
+            None => Err(format!("Session {} is not queued", session)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{ChunkMetadata, TrackKind};
+    use crate::clock::{Clock, ManualClock};
+
+    fn manifest(session: &str, sizes: &[(u64, bool)]) -> ChunkManifest {
+        let session = SessionId::from(session);
+        let mut manifest = ChunkManifest::new(session.clone());
+        for (sequence, &(size, keyframe)) in sizes.iter().enumerate() {
+            manifest
+                .add_chunk(ChunkMetadata {
+                    chunk_id: ChunkId::new(session.clone(), TrackKind::Video, sequence as u64),
+                    timestamp_us: sequence as u64 * 1_000_000,
+                    size,
+                    hash: None,
+                    has_keyframe: Some(keyframe),
+                    created_at: 0,
+                })
+                .unwrap();
+        }
+        manifest
+    }
+
+    fn queue(
+        config: UploadQueueConfig,
+        sessions: &[(&ChunkManifest, UploadLane)],
+    ) -> UploadQueueState {
+        let mut queue = UploadQueueState::new(config);
+        for (manifest, lane) in sessions {
+            queue
+                .add_session(UploadTracker::new(manifest.session_id.clone()), *lane)
+                .unwrap();
+            queue.sync_manifest(manifest).unwrap();
+        }
+        queue
+    }
+
+    fn chunk_of(job: &UploadJob) -> (&str, u64) {
+        match job {
+            UploadJob::Chunk { chunk_id, .. } => (chunk_id.session.as_str(), chunk_id.sequence),
+            other => panic!("expected a chunk, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_init_and_keyframes_first() {
+        let live = manifest("live", &[(100, false), (100, true), (100, false)]);
+        let mut queue = queue(
+            UploadQueueConfig {
+                max_concurrent_uploads: 2,
+                ..Default::default()
+            },
+            &[(&live, UploadLane::Live)],
+        );
+        assert!(queue
+            .add_session(
+                UploadTracker::new(SessionId::from("live")),
+                UploadLane::Live
+            )
+            .is_err());
+
+        // Nothing but the init segment until it is uploaded
+        let init = queue.next_job_at(0).unwrap().unwrap();
+        assert!(matches!(init, UploadJob::InitSegment { .. }));
+        assert_eq!(queue.next_job_at(0).unwrap(), None);
+        assert_eq!(
+            queue.fail(&init, "offline", 3).unwrap(),
+            UploadState::Pending
+        );
+        let init = queue.next_job_at(0).unwrap().unwrap();
+        queue.complete(&init).unwrap();
+
+        let first = queue.next_job_at(0).unwrap().unwrap();
+        assert_eq!(chunk_of(&first), ("live", 1));
+        let second = queue.next_job_at(0).unwrap().unwrap();
+        assert_eq!(chunk_of(&second), ("live", 0));
+        // Concurrency cap
+        assert_eq!(queue.next_job_at(0).unwrap(), None);
+        queue.complete(&first).unwrap();
+        assert_eq!(
+            chunk_of(&queue.next_job_at(0).unwrap().unwrap()),
+            ("live", 2)
+        );
+        assert_eq!(queue.in_flight(), 2);
+    }
+
+    #[test]
+    fn test_lanes_share_bandwidth_and_pause() {
+        let live = manifest("live", &[(1_000, true); 8]);
+        let archive = manifest("archive", &[(1_000, true); 8]);
+        let mut queue = queue(
+            UploadQueueConfig {
+                max_concurrent_uploads: 100,
+                max_bytes_per_second: Some(4_000),
+                ..Default::default()
+            },
+            &[(&live, UploadLane::Live), (&archive, UploadLane::Archival)],
+        );
+        let clock = ManualClock::new(1_000_000);
+        queue.set_clock(ClockHandle::new(clock.clone()));
+
+        let mut started = Vec::new();
+        for second in 0..4 {
+            while let Some(job) = queue.next_job().unwrap() {
+                if matches!(job, UploadJob::InitSegment { .. }) {
+                    queue.complete(&job).unwrap();
+                } else {
+                    started.push(chunk_of(&job).0.to_string());
+                }
+            }
+            // Four chunks a second, then wait for the bucket to refill
+            assert_eq!(started.len(), 4 * (second + 1));
+            let ready_at = (second < 3).then(|| clock.now_ms() + 250);
+            assert_eq!(queue.ready_at_ms(), ready_at);
+            clock.advance(1_000);
+        }
+        // 3:1 while both have work, then the archive catches up
+        let live_in_first_eight = started[..8].iter().filter(|s| *s == "live").count();
+        assert_eq!(live_in_first_eight, 6);
+        assert!(started[12..].iter().all(|s| s == "archive"));
+
+        // A lane that was idle while the other one uploaded gets its share
+        // from then on, not everything until it has caught up
+        let history = manifest("live", &[(1_000, true); 40]);
+        let mut queue = self::queue(
+            UploadQueueConfig {
+                max_concurrent_uploads: 100,
+                ..Default::default()
+            },
+            &[(&history, UploadLane::Live)],
+        );
+        for _ in 0..31 {
+            let job = queue.next_job_at(0).unwrap().unwrap();
+            queue.complete(&job).unwrap();
+        }
+        queue
+            .add_session(
+                UploadTracker::new(archive.session_id.clone()),
+                UploadLane::Archival,
+            )
+            .unwrap();
+        queue.sync_manifest(&archive).unwrap();
+        let init = queue.next_job_at(0).unwrap().unwrap();
+        assert!(matches!(init, UploadJob::InitSegment { .. }));
+        queue.complete(&init).unwrap();
+        let lanes: Vec<UploadLane> = (0..8)
+            .map(|_| queue.next_job_at(0).unwrap().unwrap().lane())
+            .collect();
+        let archival = lanes.iter().filter(|&&l| l == UploadLane::Archival).count();
+        assert_eq!(archival, 2);
+
+        let mut queue = self::queue(
+            UploadQueueConfig::default(),
+            &[(&live, UploadLane::Live), (&archive, UploadLane::Archival)],
+        );
+        queue.set_network(NetworkStatus {
+            online: false,
+            metered: false,
+        });
+        assert_eq!(queue.next_job_at(0).unwrap(), None);
+        queue.set_network(NetworkStatus {
+            online: true,
+            metered: true,
+        });
+        for _ in 0..2 {
+            let job = queue.next_job_at(0).unwrap().unwrap();
+            assert_eq!(job.lane(), UploadLane::Live);
+            queue.complete(&job).unwrap();
+        }
+        queue.remove_session(&SessionId::from("live")).unwrap();
+        assert_eq!(queue.next_job_at(0).unwrap(), None);
+        queue.set_network(NetworkStatus {
+            online: true,
+            metered: false,
+        });
+        queue.pause();
+        assert_eq!(queue.next_job_at(0).unwrap(), None);
+        queue.resume();
+        assert_eq!(
+            queue.next_job_at(0).unwrap().unwrap().lane(),
+            UploadLane::Archival
+        );
+    }
+}
@@ -0,0 +1,354 @@
+//! Resumable chunk uploads for native apps (`native` feature).
+//!
+//! `ChunkUploader` uploads a session from a `ChunkStore` as described by its
+//! manifest: the init segment first, then every chunk the `UploadTracker`
+//! still lists as pending, with a limit on concurrent uploads and a fixed
+//! number of retries per chunk. The tracker is updated as uploads finish, so
+//! persisting it lets a later run continue where this one stopped.
+//!
+//! The HTTP side is behind `UploadTransport`; `HttpTransport` (`http-upload`
+//! feature) talks to the recording server's proxy upload endpoints.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use futures_util::stream::{FuturesUnordered, StreamExt};
+
+use crate::chunk::ChunkId;
+use crate::logging::{log_event, LogLevel};
+use crate::manifest::ChunkManifest;
+use crate::session::SessionId;
+use crate::storage::ChunkStore;
+use crate::upload::{UploadProgress, UploadState, UploadTracker};
+
+/// Destination of uploaded sessions
+#[allow(async_fn_in_trait)]
+pub trait UploadTransport {
+    /// Upload the init segment of a session
+    async fn put_init_segment(&self, session: &SessionId, data: &[u8]) -> Result<(), String>;
+
+    /// Upload one chunk along with its BLAKE3 hash (hex)
+    async fn put_chunk(&self, id: &ChunkId, data: &[u8], hash: &str) -> Result<(), String>;
+}
+
+/// Concurrency and retry settings, with the web client's defaults
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadOptions {
+    pub max_concurrent_uploads: usize,
+    /// Retries per chunk before it is marked failed
+    pub max_retries: u32,
+    /// Wait before a retry, multiplied by the retry count
+    pub retry_delay: Duration,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        Self {
+            max_concurrent_uploads: 5,
+            max_retries: 3,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Uploads stored sessions through an `UploadTransport`
+pub struct ChunkUploader<T> {
+    transport: T,
+    options: UploadOptions,
+}
+
+impl<T: UploadTransport> ChunkUploader<T> {
+    /// Create an uploader with the default options
+    pub fn new(transport: T) -> Self {
+        Self::with_options(transport, UploadOptions::default())
+    }
+
+    /// Create an uploader with custom concurrency and retry settings
+    pub fn with_options(transport: T, options: UploadOptions) -> Self {
+        Self { transport, options }
+    }
+
+    /// Upload everything in `manifest` that `tracker` does not list as uploaded
+    ///
+    /// Chunks that run out of retries are left failed and do not stop the
+    /// other uploads; only a failed init segment aborts the run. Returns the
+    /// tracker's progress afterwards.
+    pub async fn run<S: ChunkStore>(
+        &self,
+        store: &S,
+        manifest: &ChunkManifest,
+        tracker: &mut UploadTracker,
+    ) -> Result<UploadProgress, String> {
+        tracker.sync_manifest(manifest)?;
+        let session = &manifest.session_id;
+        if !tracker.init_segment_uploaded() {
+            self.upload_init_segment(store, session).await?;
+            tracker.mark_init_segment_uploaded();
+        }
+
+        let mut queue: VecDeque<ChunkId> = tracker.pending().into();
+        let mut in_flight = FuturesUnordered::new();
+        loop {
+            while in_flight.len() < self.options.max_concurrent_uploads.max(1) {
+                let Some(id) = queue.pop_front() else {
+                    break;
+                };
+                tracker.start(&id)?;
+                let status = tracker.status(&id).expect("started chunk is tracked");
+                let hash = status.hash.clone();
+                let delay = self.options.retry_delay * status.retry_count;
+                in_flight.push(async move {
+                    let result = self.upload_chunk(store, &id, hash, delay).await;
+                    (id, result)
+                });
+            }
+            let Some((id, result)) = in_flight.next().await else {
+                break;
+            };
+            match result {
+                Ok(()) => tracker.complete(&id)?,
+                Err(error) => {
+                    let state = tracker.fail(&id, &error, self.options.max_retries)?;
+                    log_event!(
+                        LogLevel::Warn,
+                        "Chunk upload failed",
+                        chunk = id,
+                        error = error,
+                        retrying = state == UploadState::Pending,
+                    );
+                    if state == UploadState::Pending {
+                        queue.push_back(id);
+                    }
+                }
+            }
+        }
+
+        let progress = tracker.progress();
+        log_event!(
+            LogLevel::Info,
+            "Upload finished",
+            session = session,
+            uploaded = progress.uploaded,
+            failed = progress.failed,
+        );
+        Ok(progress)
+    }
+
+    async fn upload_init_segment<S: ChunkStore>(
+        &self,
+        store: &S,
+        session: &SessionId,
+    ) -> Result<(), String> {
+        let data = store.get_init_segment(session).await?;
+        let mut retry_count = 0;
+        loop {
+            match self.transport.put_init_segment(session, &data).await {
+                Ok(()) => return Ok(()),
+                Err(error) if retry_count < self.options.max_retries => {
+                    retry_count += 1;
+                    log_event!(
+                        LogLevel::Warn,
+                        "Init segment upload failed",
+                        session = session,
+                        error = error,
+                    );
+                    sleep(self.options.retry_delay * retry_count).await;
+                }
+                Err(error) => {
+                    return Err(format!(
+                        "Init segment upload failed after {} retries: {}",
+                        retry_count, error
+                    ))
+                }
+            }
+        }
+    }
+
+    async fn upload_chunk<S: ChunkStore>(
+        &self,
+        store: &S,
+        id: &ChunkId,
+        hash: Option<String>,
+        delay: Duration,
+    ) -> Result<(), String> {
+        sleep(delay).await;
+        let data = store.get_chunk(id).await?;
+        let hash = hash.unwrap_or_else(|| blake3::hash(&data).to_hex().to_string());
+        self.transport.put_chunk(id, &data, &hash).await
+    }
+}
+
+async fn sleep(duration: Duration) {
+    if !duration.is_zero() {
+        tokio::time::sleep(duration).await;
+    }
+}
+
+/// Transport for the recording server's proxy upload API
+///
+/// Only muxed, single-rendition chunks can be addressed by the server's
+/// numeric `chunk_id`; other chunks fail to upload.
+#[cfg(feature = "http-upload")]
+pub struct HttpTransport {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+#[cfg(feature = "http-upload")]
+impl HttpTransport {
+    /// Create a transport for the server at `base_url`, e.g. `http://localhost:3000`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_client(reqwest::Client::new(), base_url)
+    }
+
+    /// Create a transport using a preconfigured client (timeouts, proxies)
+    pub fn with_client(client: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Self {
+            client,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    async fn post(&self, url: String, data: &[u8], hash: Option<&str>) -> Result<(), String> {
+        let mut request = self
+            .client
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .body(data.to_vec());
+        if let Some(hash) = hash {
+            request = request.header("X-Chunk-Hash", hash);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("POST {} failed: {}", url, e))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(format!("POST {} failed: {}", url, status));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "http-upload")]
+impl UploadTransport for HttpTransport {
+    async fn put_init_segment(&self, session: &SessionId, data: &[u8]) -> Result<(), String> {
+        let url = format!("{}/api/recordings/{}/init-segment", self.base_url, session);
+        self.post(url, data, None).await
+    }
+
+    async fn put_chunk(&self, id: &ChunkId, data: &[u8], hash: &str) -> Result<(), String> {
+        if id.track != crate::chunk::TrackKind::Muxed || id.rendition.is_some() {
+            return Err(format!("Chunk {} cannot be uploaded over HTTP", id));
+        }
+        let url = format!(
+            "{}/api/recordings/{}/chunks?chunk_id={}",
+            self.base_url, id.session, id.sequence
+        );
+        self.post(url, data, Some(hash)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{ChunkMetadata, TrackKind};
+    use crate::storage::memory::MemoryStore;
+    use std::cell::{Cell, RefCell};
+    use std::collections::BTreeMap;
+
+    /// Transport that fails the first attempts of chosen chunks
+    #[derive(Default)]
+    struct FlakyTransport {
+        failures: RefCell<BTreeMap<u64, u32>>,
+        uploaded: RefCell<Vec<(u64, String)>>,
+        active: Cell<usize>,
+        max_active: Cell<usize>,
+    }
+
+    impl UploadTransport for FlakyTransport {
+        async fn put_init_segment(&self, _: &SessionId, _: &[u8]) -> Result<(), String> {
+            Ok(())
+        }
+
+        async fn put_chunk(&self, id: &ChunkId, _: &[u8], hash: &str) -> Result<(), String> {
+            self.active.set(self.active.get() + 1);
+            self.max_active
+                .set(self.max_active.get().max(self.active.get()));
+            tokio::task::yield_now().await;
+            self.active.set(self.active.get() - 1);
+
+            if let Some(left) = self.failures.borrow_mut().get_mut(&id.sequence) {
+                if *left > 0 {
+                    *left -= 1;
+                    return Err("503 Service Unavailable".to_string());
+                }
+            }
+            self.uploaded
+                .borrow_mut()
+                .push((id.sequence, hash.to_string()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_uploads_with_retries_and_resumes() {
+        let session = SessionId::from("s1");
+        let store = MemoryStore::default();
+        store.put_init_segment(&session, b"init").await.unwrap();
+        let mut manifest = ChunkManifest::new(session.clone());
+        for sequence in 0..6u64 {
+            let id = ChunkId::new(session.clone(), TrackKind::Muxed, sequence);
+            let data = vec![sequence as u8; 8];
+            store.put_chunk(&id, &data).await.unwrap();
+            manifest
+                .add_chunk(ChunkMetadata {
+                    chunk_id: id,
+                    timestamp_us: sequence * 2_000_000,
+                    size: 8,
+                    hash: Some(blake3::hash(&data).to_hex().to_string()),
+                    has_keyframe: Some(true),
+                    created_at: 0,
+                })
+                .unwrap();
+        }
+
+        let transport = FlakyTransport::default();
+        transport.failures.borrow_mut().insert(1, 1);
+        transport.failures.borrow_mut().insert(4, 5);
+        let uploader = ChunkUploader::with_options(
+            transport,
+            UploadOptions {
+                max_concurrent_uploads: 2,
+                max_retries: 2,
+                retry_delay: Duration::ZERO,
+            },
+        );
+
+        let mut tracker = UploadTracker::new(session.clone());
+        let progress = uploader.run(&store, &manifest, &mut tracker).await.unwrap();
+        assert_eq!(progress.uploaded, 5);
+        assert_eq!(progress.failed, 1);
+        assert_eq!(uploader.transport.max_active.get(), 2);
+        let failed = tracker.status(&manifest.chunks[4].chunk_id).unwrap();
+        assert_eq!(failed.retry_count, 2);
+        assert_eq!(failed.error.as_deref(), Some("503 Service Unavailable"));
+        let (_, hash) = uploader.transport.uploaded.borrow()[0].clone();
+        assert_eq!(Some(hash), manifest.chunks[0].hash);
+
+        // Resuming only uploads what is left
+        let mut tracker = UploadTracker::from_json(&tracker.to_json().unwrap()).unwrap();
+        tracker.retry_failed();
+        uploader.transport.uploaded.borrow_mut().clear();
+        uploader.run(&store, &manifest, &mut tracker).await.unwrap();
+        let uploaded: Vec<u64> = uploader
+            .transport
+            .uploaded
+            .borrow()
+            .iter()
+            .map(|(sequence, _)| *sequence)
+            .collect();
+        assert_eq!(uploaded, vec![4]);
+        assert!(tracker.is_complete());
+    }
+}
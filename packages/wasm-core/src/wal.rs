@@ -0,0 +1,350 @@
+//! Write-ahead sample log for crash recovery.
+//!
+//! Every frame accepted by the recorder is appended here before it is muxed.
+//! Records are buffered and handed out in batches, each of which is persisted
+//! as its own file next to the session's chunks. After a crash the batches
+//! are concatenated and replayed into a fresh muxer, which loses at most one
+//! batch interval instead of a whole fragment.
+//!
+//! Record layout (little endian, 18-byte header):
+//!
+//! ```text
+//! kind u8 | flags u8 | timestamp_us u64 | duration_us u32 | len u32 | data[len]
+//! ```
+//!
+//! An audio config change is logged as a record of its own, with the sample
+//! rate in `duration_us` and the channel count (u16) followed by the
+//! AudioSpecificConfig, if any, as data. A slate is logged as one record
+//! holding its keyframe, with its length in `duration_us`.
+//!
+//! A record cut short at the end of the log (crash mid-write) is ignored.
+
+use std::borrow::Cow;
+
+const KIND_VIDEO: u8 = 1;
+const KIND_AUDIO: u8 = 2;
+const KIND_AUDIO_CONFIG: u8 = 3;
+const KIND_SLATE: u8 = 4;
+const FLAG_KEYFRAME: u8 = 0x01;
+const HEADER_LEN: usize = 18;
+
+/// A frame as accepted by the recorder, in output time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalFrame {
+    Video {
+        timestamp_us: u64,
+        is_keyframe: bool,
+        data: Vec<u8>,
+    },
+    Audio {
+        timestamp_us: u64,
+        duration_us: u32,
+        data: Vec<u8>,
+    },
+    /// The audio encoder switched configuration (see
+    /// `MuxideMuxerState::change_audio_config`)
+    AudioConfig {
+        timestamp_us: u64,
+        sample_rate: u32,
+        channels: u16,
+        audio_specific_config: Option<Vec<u8>>,
+    },
+    /// A still picture shown from `timestamp_us` (see
+    /// `RecorderState::insert_slate`)
+    Slate {
+        timestamp_us: u64,
+        duration_us: u32,
+        keyframe: Vec<u8>,
+    },
+}
+
+impl WalFrame {
+    /// Timestamp of the frame in microseconds
+    pub fn timestamp_us(&self) -> u64 {
+        match self {
+            WalFrame::Video { timestamp_us, .. }
+            | WalFrame::Audio { timestamp_us, .. }
+            | WalFrame::AudioConfig { timestamp_us, .. }
+            | WalFrame::Slate { timestamp_us, .. } => *timestamp_us,
+        }
+    }
+
+    /// Append the encoded record to `out`
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        let (kind, flags, timestamp_us, duration_us, data) = match self {
+            WalFrame::Video {
+                timestamp_us,
+                is_keyframe,
+                data,
+            } => (
+                KIND_VIDEO,
+                if *is_keyframe { FLAG_KEYFRAME } else { 0 },
+                *timestamp_us,
+                0,
+                Cow::Borrowed(data),
+            ),
+            WalFrame::Audio {
+                timestamp_us,
+                duration_us,
+                data,
+            } => (
+                KIND_AUDIO,
+                0,
+                *timestamp_us,
+                *duration_us,
+                Cow::Borrowed(data),
+            ),
+            WalFrame::AudioConfig {
+                timestamp_us,
+                sample_rate,
+                channels,
+                audio_specific_config,
+            } => {
+                let mut data = channels.to_le_bytes().to_vec();
+                data.extend(audio_specific_config.iter().flatten());
+                (
+                    KIND_AUDIO_CONFIG,
+                    0,
+                    *timestamp_us,
+                    *sample_rate,
+                    Cow::Owned(data),
+                )
+            }
+            WalFrame::Slate {
+                timestamp_us,
+                duration_us,
+                keyframe,
+            } => (
+                KIND_SLATE,
+                0,
+                *timestamp_us,
+                *duration_us,
+                Cow::Borrowed(keyframe),
+            ),
+        };
+        out.reserve(HEADER_LEN + data.len());
+        out.push(kind);
+        out.push(flags);
+        out.extend_from_slice(&timestamp_us.to_le_bytes());
+        out.extend_from_slice(&duration_us.to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&data);
+    }
+}
+
+/// Frames decoded from a write-ahead log
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalLog {
+    pub frames: Vec<WalFrame>,
+    /// True if the log ended in a partially written record
+    pub truncated: bool,
+}
+
+/// Decode a (possibly truncated) write-ahead log
+pub fn decode(bytes: &[u8]) -> Result<WalLog, String> {
+    let mut log = WalLog::default();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        if bytes.len() - pos < HEADER_LEN {
+            log.truncated = true;
+            break;
+        }
+        let header = &bytes[pos..pos + HEADER_LEN];
+        let kind = header[0];
+        let flags = header[1];
+        let timestamp_us = u64::from_le_bytes(header[2..10].try_into().unwrap());
+        let duration_us = u32::from_le_bytes(header[10..14].try_into().unwrap());
+        let len = u32::from_le_bytes(header[14..18].try_into().unwrap()) as usize;
+
+        let start = pos + HEADER_LEN;
+        if bytes.len() - start < len {
+            log.truncated = true;
+            break;
+        }
+        let data = bytes[start..start + len].to_vec();
+
+        log.frames.push(match kind {
+            KIND_VIDEO => WalFrame::Video {
+                timestamp_us,
+                is_keyframe: flags & FLAG_KEYFRAME != 0,
+                data,
+            },
+            KIND_AUDIO => WalFrame::Audio {
+                timestamp_us,
+                duration_us,
+                data,
+            },
+            KIND_AUDIO_CONFIG if data.len() >= 2 => WalFrame::AudioConfig {
+                timestamp_us,
+                sample_rate: duration_us,
+                channels: u16::from_le_bytes([data[0], data[1]]),
+                audio_specific_config: Some(data[2..].to_vec()).filter(|asc| !asc.is_empty()),
+            },
+            KIND_SLATE => WalFrame::Slate {
+                timestamp_us,
+                duration_us,
+                keyframe: data,
+            },
+            KIND_AUDIO_CONFIG => {
+                return Err(format!(
+                    "Corrupt write-ahead log: short audio config record at offset {}",
+                    pos
+                ))
+            }
+            _ => {
+                return Err(format!(
+                    "Corrupt write-ahead log: unknown record kind {} at offset {}",
+                    kind, pos
+                ))
+            }
+        });
+        pos = start + len;
+    }
+
+    Ok(log)
+}
+
+/// A batch of encoded records ready to be persisted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalBatch {
+    /// Position of the batch in the log, starting at 0
+    pub index: u64,
+    pub data: Vec<u8>,
+}
+
+/// Buffers log records and cuts them into batches by media time
+pub struct WalWriter {
+    flush_interval_us: u64,
+    buffer: Vec<u8>,
+    batch_start_us: Option<u64>,
+    next_index: u64,
+}
+
+impl WalWriter {
+    /// Create a writer that emits a batch every `flush_interval_ms` of media time
+    pub fn new(flush_interval_ms: u32) -> Self {
+        Self {
+            flush_interval_us: flush_interval_ms as u64 * 1000,
+            buffer: Vec::new(),
+            batch_start_us: None,
+            next_index: 0,
+        }
+    }
+
+    /// Append a frame, returning a batch if the flush interval has elapsed
+    pub fn append(&mut self, frame: &WalFrame) -> Option<WalBatch> {
+        let ts = frame.timestamp_us();
+        let start = *self.batch_start_us.get_or_insert(ts);
+        frame.encode_into(&mut self.buffer);
+
+        if ts.saturating_sub(start) >= self.flush_interval_us {
+            self.flush()
+        } else {
+            None
+        }
+    }
+
+    /// Take everything buffered as a batch, if anything is buffered
+    pub fn flush(&mut self) -> Option<WalBatch> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let batch = WalBatch {
+            index: self.next_index,
+            data: std::mem::take(&mut self.buffer),
+        };
+        self.next_index += 1;
+        self.batch_start_us = None;
+        Some(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(ts: u64, key: bool) -> WalFrame {
+        WalFrame::Video {
+            timestamp_us: ts,
+            is_keyframe: key,
+            data: vec![0, 0, 0, 1, 0x65],
+        }
+    }
+
+    fn audio(ts: u64) -> WalFrame {
+        WalFrame::Audio {
+            timestamp_us: ts,
+            duration_us: 21_333,
+            data: vec![0x21, 0x10],
+        }
+    }
+
+    #[test]
+    fn test_wal_roundtrip() {
+        let frames = vec![
+            video(0, true),
+            audio(0),
+            video(33_333, false),
+            WalFrame::AudioConfig {
+                timestamp_us: 21_333,
+                sample_rate: 44_100,
+                channels: 1,
+                audio_specific_config: Some(vec![0x12, 0x08]),
+            },
+            audio(21_333),
+            WalFrame::Slate {
+                timestamp_us: 66_666,
+                duration_us: 3_000_000,
+                keyframe: vec![0, 0, 0, 2, 0x65, 0x88],
+            },
+        ];
+        let mut bytes = Vec::new();
+        for frame in &frames {
+            frame.encode_into(&mut bytes);
+        }
+
+        let log = decode(&bytes).unwrap();
+        assert_eq!(log.frames, frames);
+        assert!(!log.truncated);
+    }
+
+    #[test]
+    fn test_wal_ignores_truncated_tail() {
+        let mut bytes = Vec::new();
+        video(0, true).encode_into(&mut bytes);
+        let complete = bytes.len();
+        video(33_333, false).encode_into(&mut bytes);
+
+        // Cut inside the second record's data, then inside its header
+        for cut in [bytes.len() - 1, complete + 3] {
+            let log = decode(&bytes[..cut]).unwrap();
+            assert_eq!(log.frames, vec![video(0, true)]);
+            assert!(log.truncated);
+        }
+    }
+
+    #[test]
+    fn test_wal_rejects_unknown_record() {
+        let mut bytes = Vec::new();
+        video(0, true).encode_into(&mut bytes);
+        bytes[0] = 9;
+        assert!(decode(&bytes).unwrap_err().contains("unknown record kind"));
+    }
+
+    #[test]
+    fn test_wal_writer_batches_by_media_time() {
+        let mut writer = WalWriter::new(100);
+        assert!(writer.append(&video(0, true)).is_none());
+        assert!(writer.append(&video(50_000, false)).is_none());
+
+        let batch = writer.append(&video(100_000, false)).unwrap();
+        assert_eq!(batch.index, 0);
+        assert_eq!(decode(&batch.data).unwrap().frames.len(), 3);
+
+        assert!(writer.append(&video(133_333, false)).is_none());
+        let batch = writer.flush().unwrap();
+        assert_eq!(batch.index, 1);
+        assert!(writer.flush().is_none());
+    }
+}
@@ -0,0 +1,133 @@
+//! Encoder stall detection.
+//!
+//! Tracks the wall-clock time of the last push per stream. A stream that has
+//! not delivered a frame for longer than the threshold is reported once as
+//! stalled, and once more when frames arrive again, so the UI can warn the
+//! user instead of silently producing a file with a hole.
+
+use crate::chunk::TrackKind;
+
+/// Change in a stream's stall state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallChange {
+    Stalled { track: TrackKind, stalled_ms: u64 },
+    Recovered { track: TrackKind, stalled_ms: u64 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct StreamWatch {
+    last_push_ms: u64,
+    stalled: bool,
+}
+
+/// Per-stream stall tracker driven by explicit timestamps (ms)
+#[derive(Debug, Clone)]
+pub struct StallWatchdog {
+    threshold_ms: u64,
+    video: Option<StreamWatch>,
+    audio: Option<StreamWatch>,
+}
+
+impl StallWatchdog {
+    /// Create a watchdog for the configured streams
+    pub fn new(threshold_ms: u32, has_video: bool, has_audio: bool) -> Self {
+        let watch = |enabled: bool| {
+            enabled.then_some(StreamWatch {
+                last_push_ms: 0,
+                stalled: false,
+            })
+        };
+        Self {
+            threshold_ms: threshold_ms as u64,
+            video: watch(has_video),
+            audio: watch(has_audio),
+        }
+    }
+
+    /// Restart all timers, e.g. on start or resume
+    pub fn reset(&mut self, now_ms: u64) {
+        for watch in [&mut self.video, &mut self.audio].into_iter().flatten() {
+            watch.last_push_ms = now_ms;
+            watch.stalled = false;
+        }
+    }
+
+    /// Record a push on `track`, reporting recovery if it was stalled
+    pub fn note_push(&mut self, track: TrackKind, now_ms: u64) -> Option<StallChange> {
+        let watch = self.watch_mut(track)?;
+        let stalled_ms = now_ms.saturating_sub(watch.last_push_ms);
+        let was_stalled = std::mem::replace(&mut watch.stalled, false);
+        watch.last_push_ms = now_ms;
+        was_stalled.then_some(StallChange::Recovered { track, stalled_ms })
+    }
+
+    /// Report streams that just crossed the stall threshold
+    pub fn check(&mut self, now_ms: u64) -> Vec<StallChange> {
+        let threshold_ms = self.threshold_ms;
+        let mut changes = Vec::new();
+        for (track, watch) in [
+            (TrackKind::Video, &mut self.video),
+            (TrackKind::Audio, &mut self.audio),
+        ] {
+            let Some(watch) = watch else { continue };
+            let stalled_ms = now_ms.saturating_sub(watch.last_push_ms);
+            if !watch.stalled && stalled_ms > threshold_ms {
+                watch.stalled = true;
+                changes.push(StallChange::Stalled { track, stalled_ms });
+            }
+        }
+        changes
+    }
+
+    fn watch_mut(&mut self, track: TrackKind) -> Option<&mut StreamWatch> {
+        match track {
+            TrackKind::Video => self.video.as_mut(),
+            TrackKind::Audio => self.audio.as_mut(),
+            TrackKind::Muxed => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_reports_stall_once_and_recovery() {
+        let mut watchdog = StallWatchdog::new(1000, true, true);
+        watchdog.reset(10_000);
+
+        watchdog.note_push(TrackKind::Video, 10_500);
+        watchdog.note_push(TrackKind::Audio, 10_500);
+        assert!(watchdog.check(11_000).is_empty());
+
+        // Audio keeps flowing, video stops
+        watchdog.note_push(TrackKind::Audio, 11_600);
+        assert_eq!(
+            watchdog.check(11_600),
+            vec![StallChange::Stalled {
+                track: TrackKind::Video,
+                stalled_ms: 1100,
+            }]
+        );
+        assert!(watchdog.check(12_000).is_empty());
+
+        assert_eq!(
+            watchdog.note_push(TrackKind::Video, 13_000),
+            Some(StallChange::Recovered {
+                track: TrackKind::Video,
+                stalled_ms: 2500,
+            })
+        );
+        assert_eq!(watchdog.note_push(TrackKind::Video, 13_033), None);
+    }
+
+    #[test]
+    fn test_watchdog_ignores_unconfigured_streams() {
+        let mut watchdog = StallWatchdog::new(1000, false, true);
+        watchdog.reset(0);
+        watchdog.note_push(TrackKind::Audio, 5_000);
+        assert!(watchdog.check(5_500).is_empty());
+        assert_eq!(watchdog.note_push(TrackKind::Video, 5_500), None);
+    }
+}
@@ -0,0 +1,213 @@
+//! Waveform peaks computed while recording.
+//!
+//! `WaveformBuilderState` takes the same interleaved PCM as the loudness
+//! meter and keeps one peak per `1 / peaks_per_second` of audio: the largest
+//! absolute sample over all channels, scaled to 0-255. An hour at the
+//! default 100 peaks per second is 360 KB, small enough to store next to
+//! the manifest (`waveform.bin`) so the playback UI draws the waveform
+//! without decoding the recording. Peaks start with the first sample
+//! pushed, which is the start of the audio track.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+/// Peaks per second of audio unless configured otherwise
+pub const DEFAULT_PEAKS_PER_SECOND: u32 = 100;
+
+/// First bytes of a serialized waveform
+const WAVEFORM_MAGIC: &[u8] = b"MWAV";
+const WAVEFORM_VERSION: u8 = 1;
+const HEADER_LEN: usize = 9;
+
+/// Peaks of a recording's audio
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct Waveform {
+    pub peaks_per_second: u32,
+    /// Largest absolute sample of each interval, 255 being full scale
+    pub peaks: Vec<u8>,
+}
+
+impl Waveform {
+    /// Serialize for storage: magic, version, peaks per second, peaks
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.peaks.len());
+        out.extend_from_slice(WAVEFORM_MAGIC);
+        out.push(WAVEFORM_VERSION);
+        out.extend_from_slice(&self.peaks_per_second.to_le_bytes());
+        out.extend_from_slice(&self.peaks);
+        out
+    }
+
+    /// Parse what `to_bytes` wrote
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() < HEADER_LEN || &data[..4] != WAVEFORM_MAGIC {
+            return Err("Not a waveform".to_string());
+        }
+        if data[4] != WAVEFORM_VERSION {
+            return Err(format!("Unsupported waveform version {}", data[4]));
+        }
+        let peaks_per_second = u32::from_le_bytes([data[5], data[6], data[7], data[8]]);
+        if peaks_per_second == 0 {
+            return Err("Waveform has 0 peaks per second".to_string());
+        }
+        Ok(Self {
+            peaks_per_second,
+            peaks: data[HEADER_LEN..].to_vec(),
+        })
+    }
+
+    /// Duration covered by the peaks, in microseconds
+    pub fn duration_us(&self) -> u64 {
+        self.peaks.len() as u64 * 1_000_000 / self.peaks_per_second as u64
+    }
+}
+
+/// Computes waveform peaks from PCM as it is recorded
+#[derive(Debug, Clone)]
+pub struct WaveformBuilderState {
+    sample_rate: u32,
+    channels: usize,
+    waveform: Waveform,
+    /// Frames pushed so far
+    frames: u64,
+    /// Frame at which the current interval ends
+    interval_end: u64,
+    /// Peak of the current interval so far
+    peak: f32,
+    /// Peaks already returned by `take_peaks`
+    taken: usize,
+}
+
+impl WaveformBuilderState {
+    /// Create a builder for the input's sample rate and channel count
+    pub fn new(sample_rate: u32, channels: u16, peaks_per_second: u32) -> Result<Self, String> {
+        if sample_rate == 0 || channels == 0 {
+            return Err("Sample rate and channel count must be at least 1".to_string());
+        }
+        if peaks_per_second == 0 || peaks_per_second > sample_rate {
+            return Err(format!(
+                "Peaks per second must be between 1 and the sample rate, got {}",
+                peaks_per_second
+            ));
+        }
+        let mut builder = Self {
+            sample_rate,
+            channels: channels as usize,
+            waveform: Waveform {
+                peaks_per_second,
+                peaks: Vec::new(),
+            },
+            frames: 0,
+            interval_end: 0,
+            peak: 0.0,
+            taken: 0,
+        };
+        builder.interval_end = builder.interval_end_frame(0);
+        Ok(builder)
+    }
+
+    /// Frame at which interval `index` ends, rounded so intervals never drift
+    fn interval_end_frame(&self, index: usize) -> u64 {
+        (index as u64 + 1) * self.sample_rate as u64 / self.waveform.peaks_per_second as u64
+    }
+
+    /// Add interleaved samples in [-1, 1]
+    pub fn push_interleaved(&mut self, samples: &[f32]) -> Result<(), String> {
+        if !samples.len().is_multiple_of(self.channels) {
+            return Err(format!(
+                "Sample count {} is not a multiple of {} channels",
+                samples.len(),
+                self.channels
+            ));
+        }
+        for frame in samples.chunks_exact(self.channels) {
+            for sample in frame {
+                // NaN from a broken source counts as silence
+                self.peak = self.peak.max(sample.abs());
+            }
+            self.frames += 1;
+            if self.frames == self.interval_end {
+                self.close_interval();
+            }
+        }
+        Ok(())
+    }
+
+    fn close_interval(&mut self) {
+        let peak = (self.peak.min(1.0) * 255.0).round() as u8;
+        self.waveform.peaks.push(peak);
+        self.peak = 0.0;
+        self.interval_end = self.interval_end_frame(self.waveform.peaks.len());
+    }
+
+    /// Peaks completed since the previous call, for drawing while recording
+    pub fn take_peaks(&mut self) -> Vec<u8> {
+        let peaks = self.waveform.peaks[self.taken..].to_vec();
+        self.taken = self.waveform.peaks.len();
+        peaks
+    }
+
+    /// Every completed peak so far
+    pub fn waveform(&self) -> &Waveform {
+        &self.waveform
+    }
+
+    /// Close the partial last interval and return the whole waveform
+    ///
+    /// For when recording stops: samples pushed afterwards start a new
+    /// interval after the shortened one.
+    pub fn finish(&mut self) -> Waveform {
+        let index = self.waveform.peaks.len();
+        let interval_start = match index {
+            0 => 0,
+            _ => self.interval_end_frame(index - 1),
+        };
+        if self.frames > interval_start {
+            self.close_interval();
+        }
+        self.waveform.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_waveform_peaks() {
+        let mut builder = WaveformBuilderState::new(44_100, 2, DEFAULT_PEAKS_PER_SECOND).unwrap();
+        // 1.5 s of stereo: loud left channel in the first second, then quiet
+        let samples: Vec<f32> = (0..66_150)
+            .flat_map(|i| {
+                let level = if i < 44_100 { 0.5 } else { 0.1 };
+                let sample = if i % 2 == 0 { level } else { -level };
+                [sample, 0.0]
+            })
+            .collect();
+        for block in samples.chunks(256) {
+            builder.push_interleaved(block).unwrap();
+        }
+        assert!(builder.push_interleaved(&[0.0; 3]).is_err());
+
+        let live = builder.take_peaks();
+        assert_eq!(live.len(), 150);
+        assert!(live[..100].iter().all(|&p| p == 128));
+        assert!(live[100..].iter().all(|&p| p == 26));
+        assert!(builder.take_peaks().is_empty());
+
+        // A partial interval is only kept by finish
+        builder.push_interleaved(&[1.5, -2.0, 0.0, 0.0]).unwrap();
+        assert_eq!(builder.waveform().peaks.len(), 150);
+        let waveform = builder.finish();
+        assert_eq!(waveform.peaks.len(), 151);
+        assert_eq!(waveform.peaks[150], 255);
+        assert_eq!(waveform.duration_us(), 1_510_000);
+
+        let bytes = waveform.to_bytes();
+        assert_eq!(Waveform::from_bytes(&bytes).unwrap(), waveform);
+        assert!(Waveform::from_bytes(&bytes[..4]).is_err());
+        assert!(WaveformBuilderState::new(48_000, 2, 0).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+//! Minimal ZIP archives (PKWARE APPNOTE 6.3) for session exports.
+//!
+//! Only what a session archive needs: entries are stored or raw-deflated
+//! with `miniz_oxide`, names are UTF-8, and there is no ZIP64, so an archive
+//! and each of its entries stay below 4 GiB. Every system opens such a file
+//! without extra software, and reading back only relies on the central
+//! directory, so archives re-zipped by other tools import too as long as
+//! they keep to stored or deflated entries.
+
+use crate::compress::crc32;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4B50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4B50;
+const END_OF_DIRECTORY_SIGNATURE: u32 = 0x0605_4B50;
+const LOCAL_HEADER_LEN: usize = 30;
+const CENTRAL_HEADER_LEN: usize = 46;
+const END_OF_DIRECTORY_LEN: usize = 22;
+/// Version needed to extract: 2.0, for deflate
+const VERSION: u16 = 20;
+/// General purpose flag bit 11: names are UTF-8
+const FLAG_UTF8: u16 = 0x0800;
+/// General purpose flag bit 0: encrypted
+const FLAG_ENCRYPTED: u16 = 0x0001;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+/// MS-DOS date of 1980-01-01, the earliest a ZIP entry can carry
+const DOS_EPOCH_DATE: u16 = 0x0021;
+
+/// One file of an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ZipEntry {
+    pub(crate) name: String,
+    pub(crate) data: Vec<u8>,
+}
+
+/// Builds an archive in memory, entry by entry
+#[derive(Debug, Default)]
+pub(crate) struct ZipWriter {
+    out: Vec<u8>,
+    directory: Vec<u8>,
+    entries: u16,
+}
+
+impl ZipWriter {
+    /// Add an entry, deflated at `level` (0-10) or stored if `level` is None
+    /// or deflating does not make it smaller
+    pub(crate) fn add(&mut self, name: &str, data: &[u8], level: Option<u8>) -> Result<(), String> {
+        if self.entries == u16::MAX {
+            return Err("Too many files for a ZIP archive".to_string());
+        }
+        let name_len = u16::try_from(name.len())
+            .map_err(|_| format!("File name too long for a ZIP archive: {}", name))?;
+        let size = u32::try_from(data.len())
+            .map_err(|_| format!("{} is too large for a ZIP archive", name))?;
+        let offset =
+            u32::try_from(self.out.len()).map_err(|_| "Archive exceeds 4 GiB".to_string())?;
+
+        let deflated = level
+            .map(|level| miniz_oxide::deflate::compress_to_vec(data, level))
+            .filter(|deflated| deflated.len() < data.len());
+        let (method, stored) = match &deflated {
+            Some(deflated) => (METHOD_DEFLATE, deflated.as_slice()),
+            None => (METHOD_STORED, data),
+        };
+        let crc = crc32(data);
+        let compressed_size = stored.len() as u32;
+
+        self.out
+            .extend_from_slice(&LOCAL_HEADER_SIGNATURE.to_le_bytes());
+        self.out.extend_from_slice(&VERSION.to_le_bytes());
+        write_common(&mut self.out, method, crc, compressed_size, size, name_len);
+        self.out.extend_from_slice(&0u16.to_le_bytes()); // Extra field length
+        self.out.extend_from_slice(name.as_bytes());
+        self.out.extend_from_slice(stored);
+
+        self.directory
+            .extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+        self.directory.extend_from_slice(&VERSION.to_le_bytes()); // Made by
+        self.directory.extend_from_slice(&VERSION.to_le_bytes());
+        write_common(
+            &mut self.directory,
+            method,
+            crc,
+            compressed_size,
+            size,
+            name_len,
+        );
+        self.directory.extend_from_slice(&[0; 12]); // Extra, comment, disk, attributes
+        self.directory.extend_from_slice(&offset.to_le_bytes());
+        self.directory.extend_from_slice(name.as_bytes());
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Append the central directory and return the archive
+    pub(crate) fn finish(mut self) -> Result<Vec<u8>, String> {
+        let offset =
+            u32::try_from(self.out.len()).map_err(|_| "Archive exceeds 4 GiB".to_string())?;
+        let size = self.directory.len() as u32;
+        self.out.extend_from_slice(&self.directory);
+        self.out
+            .extend_from_slice(&END_OF_DIRECTORY_SIGNATURE.to_le_bytes());
+        self.out.extend_from_slice(&[0; 4]); // Disk numbers
+        self.out.extend_from_slice(&self.entries.to_le_bytes());
+        self.out.extend_from_slice(&self.entries.to_le_bytes());
+        self.out.extend_from_slice(&size.to_le_bytes());
+        self.out.extend_from_slice(&offset.to_le_bytes());
+        self.out.extend_from_slice(&0u16.to_le_bytes()); // Comment length
+        Ok(self.out)
+    }
+}
+
+/// Header fields shared by local and central headers, from the flags on
+fn write_common(
+    out: &mut Vec<u8>,
+    method: u16,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    name_len: u16,
+) {
+    out.extend_from_slice(&FLAG_UTF8.to_le_bytes());
+    out.extend_from_slice(&method.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // Time
+    out.extend_from_slice(&DOS_EPOCH_DATE.to_le_bytes());
+    out.extend_from_slice(&crc.to_le_bytes());
+    out.extend_from_slice(&compressed_size.to_le_bytes());
+    out.extend_from_slice(&size.to_le_bytes());
+    out.extend_from_slice(&name_len.to_le_bytes());
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16, String> {
+    data.get(pos..pos + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| "Truncated ZIP archive".to_string())
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32, String> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| "Truncated ZIP archive".to_string())
+}
+
+/// Read every file of an archive, in directory order, checking CRCs
+///
+/// Directory entries (names ending in `/`) are skipped.
+pub(crate) fn read_zip(data: &[u8]) -> Result<Vec<ZipEntry>, String> {
+    // The end of directory record is followed by a comment of up to 64 KiB
+    let search_from = data
+        .len()
+        .saturating_sub(END_OF_DIRECTORY_LEN + u16::MAX as usize);
+    let end = (search_from..=data.len().saturating_sub(END_OF_DIRECTORY_LEN))
+        .rev()
+        .find(|&pos| u32_at(data, pos) == Ok(END_OF_DIRECTORY_SIGNATURE))
+        .ok_or("Not a ZIP archive")?;
+    let count = u16_at(data, end + 10)? as usize;
+    let mut pos = u32_at(data, end + 16)? as usize;
+
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        if u32_at(data, pos)? != CENTRAL_HEADER_SIGNATURE {
+            return Err("Corrupt ZIP central directory".to_string());
+        }
+        let flags = u16_at(data, pos + 8)?;
+        let method = u16_at(data, pos + 10)?;
+        let crc = u32_at(data, pos + 16)?;
+        let compressed_size = u32_at(data, pos + 20)? as usize;
+        let size = u32_at(data, pos + 24)? as usize;
+        let name_len = u16_at(data, pos + 28)? as usize;
+        let extra_len = u16_at(data, pos + 30)? as usize;
+        let comment_len = u16_at(data, pos + 32)? as usize;
+        let offset = u32_at(data, pos + 42)? as usize;
+        let name_start = pos + CENTRAL_HEADER_LEN;
+        let name = data
+            .get(name_start..name_start + name_len)
+            .ok_or("Truncated ZIP archive")?;
+        let name = String::from_utf8(name.to_vec())
+            .map_err(|_| "ZIP entry name is not UTF-8".to_string())?;
+        pos = name_start + name_len + extra_len + comment_len;
+
+        if name.ends_with('/') {
+            continue;
+        }
+        if flags & FLAG_ENCRYPTED != 0 {
+            return Err(format!("{} is encrypted", name));
+        }
+        if u32_at(data, offset)? != LOCAL_HEADER_SIGNATURE {
+            return Err(format!("Corrupt ZIP entry {}", name));
+        }
+        let data_start = offset
+            + LOCAL_HEADER_LEN
+            + u16_at(data, offset + 26)? as usize
+            + u16_at(data, offset + 28)? as usize;
+        let stored = data
+            .get(data_start..data_start + compressed_size)
+            .ok_or("Truncated ZIP archive")?;
+        let contents = match method {
+            METHOD_STORED => stored.to_vec(),
+            METHOD_DEFLATE => miniz_oxide::inflate::decompress_to_vec_with_limit(stored, size)
+                .map_err(|e| format!("Corrupt ZIP entry {}: {:?}", name, e.status))?,
+            _ => return Err(format!("{} uses unsupported compression {}", name, method)),
+        };
+        if contents.len() != size || crc32(&contents) != crc {
+            return Err(format!("{} does not match its checksum", name));
+        }
+        entries.push(ZipEntry {
+            name,
+            data: contents,
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = "WEBVTT\n\n".repeat(200);
+        let media: Vec<u8> = (0..5_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        let mut writer = ZipWriter::default();
+        writer
+            .add("captions.vtt", text.as_bytes(), Some(6))
+            .unwrap();
+        writer.add("recording.mp4", &media, None).unwrap();
+        writer.add("empty.json", b"", Some(6)).unwrap();
+        let archive = writer.finish().unwrap();
+        assert!(archive.len() < text.len() + media.len());
+
+        let entries = read_zip(&archive).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].name, "captions.vtt");
+        assert_eq!(entries[0].data, text.as_bytes());
+        assert_eq!(entries[1].data, media);
+        assert!(entries[2].data.is_empty());
+
+        // Flipping a stored byte breaks the checksum
+        let mut corrupt = archive.clone();
+        let at = corrupt.windows(4).position(|w| w == &media[..4]).unwrap();
+        corrupt[at] ^= 0xFF;
+        assert!(read_zip(&corrupt).unwrap_err().contains("checksum"));
+        assert!(read_zip(&archive[..archive.len() - 1]).is_err());
+        assert_eq!(read_zip(b"plain").unwrap_err(), "Not a ZIP archive");
+    }
+}
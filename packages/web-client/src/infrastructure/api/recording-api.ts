@@ -8,6 +8,8 @@ import type {
   RecordingMetadata,
   CreateRecordingResponse,
   DownloadUrlsResponse,
+  IngestHandshakeRequest,
+  IngestHandshakeResponse,
   UploadUrlResponse,
 } from '@maycast/common-types';
 
@@ -73,6 +75,30 @@ export class RecordingAPIClient {
     return data;
   }
 
+  /**
+   * Ingestハンドシェイク
+   * recordingIdを付けると、サーバーは交渉結果をその録画のアップロードに適用する
+   * @returns ハンドシェイク未対応のサーバーの場合はnull
+   */
+  async negotiateIngest(request: IngestHandshakeRequest): Promise<IngestHandshakeResponse | null> {
+    const response = await fetch(`${this.baseUrl}/api/ingest/handshake`, {
+      method: 'POST',
+      headers: {
+        'Content-Type': 'application/json',
+      },
+      body: JSON.stringify(request),
+    });
+
+    if (response.status === 404) {
+      return null;
+    }
+    if (!response.ok) {
+      throw new Error(`Failed to negotiate ingest: ${response.statusText}`);
+    }
+
+    return response.json();
+  }
+
   /**
    * Recording情報を取得
    */
@@ -1,5 +1,18 @@
 import { RecordingAPIClient } from '../../infrastructure/api/recording-api';
-import type { RecordingMetadata, RecordingState } from '@maycast/common-types';
+import { INGEST_PROTOCOL_VERSION, MIN_INGEST_PROTOCOL_VERSION } from '@maycast/common-types';
+import type { IngestCapabilities, RecordingMetadata, RecordingState } from '@maycast/common-types';
+
+/**
+ * このクライアントのアップロード対応範囲（Ingestハンドシェイクで送信）
+ */
+export const CLIENT_INGEST_CAPABILITIES: IngestCapabilities = {
+  protocolVersion: INGEST_PROTOCOL_VERSION,
+  minProtocolVersion: MIN_INGEST_PROTOCOL_VERSION,
+  codecs: ['avc1', 'mp4a'],
+  containers: ['fmp4'],
+  features: ['chunk-hash', 'direct-upload'],
+  agent: 'maycast-web-client',
+};
 
 /**
  * RecordingManager
@@ -9,6 +22,8 @@ export class RecordingManager {
   private recordingId: string | null = null;
   private currentState: RecordingState = 'standby';
   private apiClient: RecordingAPIClient;
+  // null=ハンドシェイク未対応のサーバー（従来どおりアップロード）
+  private ingestFeatures: string[] | null = null;
 
   constructor(serverUrl: string) {
     this.apiClient = new RecordingAPIClient(serverUrl);
@@ -22,7 +37,8 @@ export class RecordingManager {
   }
 
   /**
-   * 新しいRecordingを作成
+   * 新しいRecordingを作成し、Ingestハンドシェイクを行う
+   * 交渉が成立しない場合はアップロードできないためエラーにする
    * @param roomId Optional Room ID for Guest Mode recordings
    */
   async createRecording(roomId?: string): Promise<string> {
@@ -31,9 +47,26 @@ export class RecordingManager {
     this.recordingId = response.recording_id;
     this.currentState = 'standby'; // 初期状態
     console.log(`📝 [RecordingManager] Recording created: ${this.recordingId}`);
+
+    const handshake = await this.apiClient.negotiateIngest({
+      ...CLIENT_INGEST_CAPABILITIES,
+      recordingId: this.recordingId,
+    });
+    if (handshake && !handshake.negotiation.accepted) {
+      throw new Error(`Ingest handshake rejected: ${handshake.negotiation.message}`);
+    }
+    this.ingestFeatures = handshake?.negotiation.accepted ? handshake.negotiation.features : null;
+    console.log('🤝 [RecordingManager] Ingest features:', this.ingestFeatures ?? '(legacy server)');
     return this.recordingId;
   }
 
+  /**
+   * ハンドシェイクで交渉した機能（ハンドシェイク未対応のサーバーではnull）
+   */
+  getIngestFeatures(): string[] | null {
+    return this.ingestFeatures;
+  }
+
   /**
    * Recordingメタデータをアップロード
    */
@@ -43,6 +43,8 @@ export class ChunkUploader {
   private processingPromise: Promise<void> | null = null;
   // null=未チェック, true=直接アップロード対応, false=プロキシ方式
   private directUploadSupported: boolean | null = null;
+  // Ingestハンドシェイクで交渉した機能（null=ハンドシェイクなし）
+  private ingestFeatures: string[] | null;
 
   constructor(
     recordingId: string,
@@ -50,6 +52,7 @@ export class ChunkUploader {
     options: {
       maxConcurrentUploads?: number;
       maxRetries?: number;
+      ingestFeatures?: string[] | null;
     } = {}
   ) {
     this.recordingId = recordingId;
@@ -57,6 +60,7 @@ export class ChunkUploader {
     // 並列数を5に設定（安定性とパフォーマンスのバランス）
     this.maxConcurrentUploads = options.maxConcurrentUploads ?? 5;
     this.maxRetries = options.maxRetries ?? 3;
+    this.ingestFeatures = options.ingestFeatures ?? null;
   }
 
   /**
@@ -159,6 +163,12 @@ export class ChunkUploader {
       return this.directUploadSupported;
     }
 
+    // 直接アップロードを交渉していない場合はプロキシ方式
+    if (this.ingestFeatures && !this.ingestFeatures.includes('direct-upload')) {
+      this.directUploadSupported = false;
+      return this.directUploadSupported;
+    }
+
     try {
       const response = await this.apiClient.getChunkUploadUrl(this.recordingId, '0');
       this.directUploadSupported = response.directUpload;
@@ -72,7 +72,9 @@ export class RemoteStorageStrategy implements IStorageStrategy {
 
       // ChunkUploader初期化（リモートIDを使用）
       const apiClient = recordingManager.getAPIClient();
-      const chunkUploader = new ChunkUploader(remoteRecordingId, apiClient);
+      const chunkUploader = new ChunkUploader(remoteRecordingId, apiClient, {
+        ingestFeatures: recordingManager.getIngestFeatures(),
+      });
       this.chunkUploaderMap.set(localRecordingId, chunkUploader);
 
       console.log(`✅ Remote recording session initialized: local=${localRecordingId}, remote=${remoteRecordingId}`);